@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+
+pub(crate) trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub(crate) struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+pub(crate) struct FixedClock(pub(crate) DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+pub(crate) fn clock_from_env() -> Box<dyn Clock> {
+    match source_date_epoch() {
+        Some(at) => Box::new(FixedClock(at)),
+        None => Box::new(SystemClock),
+    }
+}
+
+fn source_date_epoch() -> Option<DateTime<Utc>> {
+    let value = std::env::var("SOURCE_DATE_EPOCH").ok()?;
+    let seconds = value.trim().parse::<i64>().ok()?;
+    DateTime::from_timestamp(seconds, 0)
+}
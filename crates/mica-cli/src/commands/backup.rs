@@ -0,0 +1,382 @@
+use crate::commands::{CliCommand, CommandContext};
+use crate::{config_dir, expand_tilde, load_config_or_default, BackupCommand, CliError};
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use mica_core::state::MICA_VERSION;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+
+const MANIFEST_NAME: &str = "mica-backup.toml";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum BackupSection {
+    Config,
+    Profile,
+    Generations,
+    Presets,
+    Index,
+}
+
+const SECTIONS: [BackupSection; 5] = [
+    BackupSection::Config,
+    BackupSection::Profile,
+    BackupSection::Generations,
+    BackupSection::Presets,
+    BackupSection::Index,
+];
+
+impl BackupSection {
+    fn name(self) -> &'static str {
+        match self {
+            BackupSection::Config => "config",
+            BackupSection::Profile => "profile",
+            BackupSection::Generations => "generations",
+            BackupSection::Presets => "presets",
+            BackupSection::Index => "index",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<BackupSection> {
+        SECTIONS.into_iter().find(|section| section.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BackupManifest {
+    mica_version: String,
+    created: DateTime<Utc>,
+    sections: Vec<String>,
+    #[serde(default)]
+    preset_dirs: BTreeMap<String, String>,
+}
+
+struct BackupEntry {
+    section: BackupSection,
+    name: String,
+    path: PathBuf,
+}
+
+impl CliCommand for BackupCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        match self {
+            BackupCommand::Create {
+                archive,
+                with_index,
+            } => create(ctx, &archive, with_index),
+            BackupCommand::Restore {
+                archive,
+                only,
+                force,
+            } => restore(ctx, &archive, &only, force),
+        }
+    }
+}
+
+fn backup_entries(
+    root: &Path,
+    sections: &[BackupSection],
+    preset_dirs: &BTreeMap<String, String>,
+) -> Result<Vec<BackupEntry>, CliError> {
+    let mut entries = Vec::new();
+    for section in sections {
+        let names: Vec<(String, PathBuf)> = match section {
+            BackupSection::Config => vec![
+                ("config.toml".to_string(), root.join("config.toml")),
+                ("projects.toml".to_string(), root.join("projects.toml")),
+            ],
+            BackupSection::Profile => vec![
+                ("profile.toml".to_string(), root.join("profile.toml")),
+                ("profile.nix".to_string(), root.join("profile.nix")),
+            ],
+            BackupSection::Generations => {
+                vec![("generations".to_string(), root.join("generations"))]
+            }
+            BackupSection::Index => vec![
+                (
+                    "cache/index.db".to_string(),
+                    root.join("cache").join("index.db"),
+                ),
+                (
+                    "cache/versions.db".to_string(),
+                    root.join("cache").join("versions.db"),
+                ),
+            ],
+            BackupSection::Presets => {
+                let mut names = Vec::new();
+                for (name, dir) in preset_dirs {
+                    names.push((name.clone(), expand_tilde(dir)?));
+                }
+                names
+            }
+        };
+        for (name, path) in names {
+            entries.push(BackupEntry {
+                section: *section,
+                name,
+                path,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+fn configured_preset_dirs() -> Result<BTreeMap<String, String>, CliError> {
+    let config = load_config_or_default()?;
+    Ok(config
+        .presets
+        .extra_dirs
+        .iter()
+        .enumerate()
+        .map(|(idx, dir)| (format!("presets/{}", idx), dir.clone()))
+        .collect())
+}
+
+fn create(ctx: &CommandContext, archive: &Path, with_index: bool) -> Result<(), CliError> {
+    let root = config_dir()?;
+    let preset_dirs = configured_preset_dirs()?;
+    let sections: Vec<BackupSection> = SECTIONS
+        .into_iter()
+        .filter(|section| with_index || *section != BackupSection::Index)
+        .collect();
+    let entries: Vec<BackupEntry> = backup_entries(&root, &sections, &preset_dirs)?
+        .into_iter()
+        .filter(|entry| entry.path.exists())
+        .collect();
+    if entries.is_empty() {
+        ctx.output
+            .info(format!("nothing to back up in {}", root.display()));
+        return Ok(());
+    }
+    if ctx.dry_run {
+        ctx.output
+            .info(format!("dry-run: would write {}", archive.display()));
+        for entry in &entries {
+            ctx.output.info(format!(
+                "  {} ({})",
+                entry.path.display(),
+                entry.section.name()
+            ));
+        }
+        return Ok(());
+    }
+
+    let created = ctx.clock.now();
+    let manifest = BackupManifest {
+        mica_version: MICA_VERSION.to_string(),
+        created,
+        sections: sections
+            .iter()
+            .map(|section| section.name().to_string())
+            .collect(),
+        preset_dirs,
+    };
+    let manifest = toml::to_string_pretty(&manifest)
+        .map_err(|err| CliError::InvalidBackup(err.to_string()))?;
+
+    let file = File::create(archive).map_err(CliError::BackupIo)?;
+    let mut builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(created.timestamp().max(0) as u64);
+    header.set_cksum();
+    builder
+        .append_data(&mut header, MANIFEST_NAME, manifest.as_bytes())
+        .map_err(CliError::BackupIo)?;
+    for entry in &entries {
+        if entry.path.is_dir() {
+            builder.append_dir_all(&entry.name, &entry.path)
+        } else {
+            builder.append_path_with_name(&entry.path, &entry.name)
+        }
+        .map_err(CliError::BackupIo)?;
+    }
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .map_err(CliError::BackupIo)?;
+    ctx.output.info(format!(
+        "backed up {} item(s) to {}",
+        entries.len(),
+        archive.display()
+    ));
+    Ok(())
+}
+
+fn open_archive(archive: &Path) -> Result<tar::Archive<GzDecoder<File>>, CliError> {
+    let file = File::open(archive).map_err(CliError::BackupIo)?;
+    Ok(tar::Archive::new(GzDecoder::new(file)))
+}
+
+fn read_manifest(archive: &Path) -> Result<BackupManifest, CliError> {
+    let mut reader = open_archive(archive)?;
+    let mut entries = reader.entries().map_err(CliError::BackupIo)?;
+    let mut first = match entries.next() {
+        Some(entry) => entry.map_err(CliError::BackupIo)?,
+        None => return Err(CliError::InvalidBackup("archive is empty".to_string())),
+    };
+    let name = first.path().map_err(CliError::BackupIo)?.into_owned();
+    if name != Path::new(MANIFEST_NAME) {
+        return Err(CliError::InvalidBackup(format!(
+            "missing {}, not a mica backup",
+            MANIFEST_NAME
+        )));
+    }
+    let mut content = String::new();
+    first
+        .read_to_string(&mut content)
+        .map_err(CliError::BackupIo)?;
+    toml::from_str(&content).map_err(|err| CliError::InvalidBackup(err.to_string()))
+}
+
+fn restore_target<'a>(
+    entries: &'a [BackupEntry],
+    name: &Path,
+) -> Option<(&'a BackupEntry, PathBuf)> {
+    if name
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    entries.iter().find_map(|entry| {
+        name.strip_prefix(&entry.name).ok().map(|rest| {
+            if rest.as_os_str().is_empty() {
+                (entry, entry.path.clone())
+            } else {
+                (entry, entry.path.join(rest))
+            }
+        })
+    })
+}
+
+/// Finds a symlink between the entry's base path and the restore target,
+/// since unpacking would follow it and write outside the intended directory.
+fn symlink_on_path(entry: &BackupEntry, target: &Path) -> Option<PathBuf> {
+    let is_symlink = |path: &Path| {
+        std::fs::symlink_metadata(path)
+            .map(|meta| meta.file_type().is_symlink())
+            .unwrap_or(false)
+    };
+    if is_symlink(target) {
+        return Some(target.to_path_buf());
+    }
+    target
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| ancestor.starts_with(&entry.path) && *ancestor != entry.path)
+        .find(|ancestor| is_symlink(ancestor))
+        .map(Path::to_path_buf)
+}
+
+fn restore_planned(entries: &[BackupEntry], name: &Path) -> Result<Option<PathBuf>, CliError> {
+    let Some((entry, target)) = restore_target(entries, name) else {
+        return Ok(None);
+    };
+    if let Some(link) = symlink_on_path(entry, &target) {
+        return Err(CliError::BackupSymlink(link));
+    }
+    Ok(Some(target))
+}
+
+fn restore(
+    ctx: &CommandContext,
+    archive: &Path,
+    only: &[BackupSection],
+    force: bool,
+) -> Result<(), CliError> {
+    let manifest = read_manifest(archive)?;
+    let available: Vec<BackupSection> = manifest
+        .sections
+        .iter()
+        .filter_map(|name| BackupSection::from_name(name))
+        .collect();
+    for section in only {
+        if !available.contains(section) {
+            ctx.output.warn(format!(
+                "warning: backup does not contain the {} section",
+                section.name()
+            ));
+        }
+    }
+    let selected: Vec<BackupSection> = available
+        .into_iter()
+        .filter(|section| only.is_empty() || only.contains(section))
+        .collect();
+    // Preset targets come from this machine's config, never from the archive,
+    // so a foreign manifest cannot direct writes outside known directories.
+    let preset_dirs = configured_preset_dirs()?;
+    if selected.contains(&BackupSection::Presets) {
+        for (name, dir) in &manifest.preset_dirs {
+            if !preset_dirs.contains_key(name) {
+                ctx.output.warn(format!(
+                    "warning: skipping {} (was {}), add it to presets.extra_dirs and restore --only presets",
+                    name, dir
+                ));
+            }
+        }
+    }
+    let root = config_dir()?;
+    let entries = backup_entries(&root, &selected, &preset_dirs)?;
+
+    let mut planned = Vec::new();
+    let mut reader = open_archive(archive)?;
+    for entry in reader.entries().map_err(CliError::BackupIo)? {
+        let entry = entry.map_err(CliError::BackupIo)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path().map_err(CliError::BackupIo)?.into_owned();
+        if let Some(target) = restore_planned(&entries, &name)? {
+            planned.push(target);
+        }
+    }
+    if planned.is_empty() {
+        ctx.output.info("nothing to restore");
+        return Ok(());
+    }
+    let existing = planned.iter().filter(|target| target.exists()).count();
+    if ctx.dry_run {
+        ctx.output
+            .info(format!("dry-run: would restore from {}", archive.display()));
+        for target in &planned {
+            let note = if target.exists() { " (overwrite)" } else { "" };
+            ctx.output.info(format!("  {}{}", target.display(), note));
+        }
+        return Ok(());
+    }
+    if existing > 0 && !force {
+        return Err(CliError::BackupConflict(existing));
+    }
+
+    let mut reader = open_archive(archive)?;
+    let mut restored = 0;
+    for entry in reader.entries().map_err(CliError::BackupIo)? {
+        let mut entry = entry.map_err(CliError::BackupIo)?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path().map_err(CliError::BackupIo)?.into_owned();
+        let Some(target) = restore_planned(&entries, &name)? else {
+            continue;
+        };
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).map_err(CliError::BackupIo)?;
+        }
+        entry.unpack(&target).map_err(CliError::BackupIo)?;
+        restored += 1;
+    }
+    let names: Vec<&str> = selected.iter().map(|section| section.name()).collect();
+    ctx.output.info(format!(
+        "restored {} file(s) from {} ({})",
+        restored,
+        archive.display(),
+        names.join(", ")
+    ));
+    Ok(())
+}
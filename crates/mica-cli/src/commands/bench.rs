@@ -0,0 +1,107 @@
+use crate::commands::CommandContext;
+use crate::timing::format_duration;
+use crate::CliError;
+use mica_core::diff::diff_lines;
+use mica_core::fixtures::{
+    synthetic_merged, synthetic_nix_edit, synthetic_project_nix, synthetic_project_state,
+};
+use mica_core::nixgen::generate_project_nix;
+use mica_core::nixparse::parse_project_state_from_nix;
+use mica_index::fixtures::synthetic_index;
+use mica_index::generate::{search_packages_with_mode, SearchMode};
+use std::time::{Duration, Instant};
+
+const STATE_PACKAGES: usize = 2_000;
+const INDEX_PACKAGES: usize = 20_000;
+const DIFF_PACKAGES: usize = 5_000;
+
+const GENERATE_BUDGET: Duration = Duration::from_millis(10);
+const PARSE_BUDGET: Duration = Duration::from_millis(20);
+const SEARCH_BUDGET: Duration = Duration::from_millis(20);
+const DIFF_BUDGET: Duration = Duration::from_millis(500);
+
+pub(crate) fn run(ctx: &CommandContext, iterations: usize) -> Result<(), CliError> {
+    let iterations = iterations.max(1);
+    let state = synthetic_project_state(STATE_PACKAGES);
+    let merged = synthetic_merged(&state);
+    let content = synthetic_project_nix(STATE_PACKAGES);
+    let diff_old = synthetic_project_nix(DIFF_PACKAGES);
+    let diff_new = synthetic_nix_edit(&diff_old);
+    let conn = synthetic_index(INDEX_PACKAGES)?;
+
+    let mut results = vec![
+        (
+            format!("generate {} packages", STATE_PACKAGES),
+            GENERATE_BUDGET,
+            median(iterations, || {
+                generate_project_nix(&state, &merged, "bench", ctx.clock.now());
+                Ok(())
+            })?,
+        ),
+        (
+            format!("parse {} packages", STATE_PACKAGES),
+            PARSE_BUDGET,
+            median(iterations, || {
+                parse_project_state_from_nix(&content).map_err(CliError::NixStateParse)?;
+                Ok(())
+            })?,
+        ),
+        (
+            format!("diff {} lines", diff_old.lines().count()),
+            DIFF_BUDGET,
+            median(iterations, || {
+                diff_lines(&diff_old, &diff_new);
+                Ok(())
+            })?,
+        ),
+    ];
+    for (label, query, mode) in [
+        ("all", "editor", SearchMode::All),
+        ("name", "rust", SearchMode::Name),
+        ("binary", "shell-git", SearchMode::Binary),
+    ] {
+        results.push((
+            format!("search {} ({} packages)", label, INDEX_PACKAGES),
+            SEARCH_BUDGET,
+            median(iterations, || {
+                search_packages_with_mode(&conn, query, 50, mode)?;
+                Ok(())
+            })?,
+        ));
+    }
+
+    let mut over = 0;
+    for (label, budget, elapsed) in &results {
+        let status = if elapsed > budget {
+            over += 1;
+            "over budget"
+        } else {
+            "ok"
+        };
+        ctx.output.info(format!(
+            "{}: {} (budget {}) {}",
+            label,
+            format_duration(*elapsed),
+            format_duration(*budget),
+            status
+        ));
+    }
+    if over > 0 {
+        return Err(CliError::BenchBudgetExceeded(over));
+    }
+    Ok(())
+}
+
+fn median(
+    iterations: usize,
+    mut action: impl FnMut() -> Result<(), CliError>,
+) -> Result<Duration, CliError> {
+    let mut samples = Vec::with_capacity(iterations);
+    for _ in 0..iterations {
+        let start = Instant::now();
+        action()?;
+        samples.push(start.elapsed());
+    }
+    samples.sort();
+    Ok(samples[samples.len() / 2])
+}
@@ -0,0 +1,73 @@
+use crate::commands::{CliCommand, CommandContext};
+use crate::daemon::server::serve;
+use crate::daemon::{send_request, DaemonRequest, DaemonResponse};
+use crate::{daemon_socket_path, CliError, DaemonCommand};
+
+impl CliCommand for DaemonCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        let socket = daemon_socket_path()?;
+        match self {
+            DaemonCommand::Run => {
+                if ctx.dry_run {
+                    ctx.output.info("dry-run: skipping daemon start");
+                    return Ok(());
+                }
+                serve(ctx.output, ctx.clock)
+            }
+            DaemonCommand::Status => {
+                if !socket.exists() {
+                    return Err(CliError::DaemonNotRunning(socket));
+                }
+                match send_request(&socket, &DaemonRequest::Status)? {
+                    DaemonResponse::Status(status) => {
+                        ctx.output.info(format!("pid: {}", status.pid));
+                        ctx.output
+                            .info(format!("started: {}", status.started.to_rfc3339()));
+                        ctx.output.info(format!("requests: {}", status.requests));
+                        ctx.output.info(format!(
+                            "index: {} ({}, {} loads)",
+                            status.index_path.display(),
+                            if status.index_loaded {
+                                "loaded"
+                            } else {
+                                "missing"
+                            },
+                            status.index_reloads
+                        ));
+                        ctx.output.info(format!("presets: {}", status.presets));
+                        ctx.output
+                            .info(format!("projects: {}", status.projects.len()));
+                        for project in status.projects {
+                            ctx.output.info(format!("  {}", project.display()));
+                        }
+                        Ok(())
+                    }
+                    other => Err(unexpected_response(other)),
+                }
+            }
+            DaemonCommand::Stop => {
+                if !socket.exists() {
+                    return Err(CliError::DaemonNotRunning(socket));
+                }
+                if ctx.dry_run {
+                    ctx.output.info("dry-run: skipping daemon stop");
+                    return Ok(());
+                }
+                match send_request(&socket, &DaemonRequest::Stop)? {
+                    DaemonResponse::Stopping => {
+                        ctx.output.info("daemon stopping");
+                        Ok(())
+                    }
+                    other => Err(unexpected_response(other)),
+                }
+            }
+        }
+    }
+}
+
+fn unexpected_response(response: DaemonResponse) -> CliError {
+    match response {
+        DaemonResponse::Error { message } => CliError::DaemonProtocol(message),
+        other => CliError::DaemonProtocol(format!("unexpected response: {:?}", other)),
+    }
+}
@@ -0,0 +1,191 @@
+use crate::commands::{CliCommand, CommandContext};
+use crate::indexops::{collision_winner, preferred_priority, project_collisions};
+use crate::{
+    apply_project_changes, load_project_state, project_selected_packages, update_project_modified,
+    BuildEnvCommand, CliError, CollisionsArg, EnvCommand, Output, ProjectPaths, ShellCommand,
+};
+use mica_core::state::{BuildEnvState, ProjectState};
+
+impl CliCommand for EnvCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        if ctx.global {
+            ctx.output
+                .info("env is only supported in project mode for now");
+        } else {
+            let paths = ctx.project_paths()?;
+            let mut state = load_project_state(ctx.clock, paths)?;
+            match self {
+                EnvCommand::Set { key, value } => {
+                    state.env.insert(key, value);
+                }
+                EnvCommand::Unset { key } => {
+                    state.env.remove(&key);
+                }
+            }
+            update_project_modified(ctx.clock, &mut state);
+            apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+        }
+        Ok(())
+    }
+}
+
+impl CliCommand for ShellCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        if ctx.global {
+            ctx.output
+                .info("shell hook is only supported in project mode for now");
+        } else {
+            let paths = ctx.project_paths()?;
+            let mut state = load_project_state(ctx.clock, paths)?;
+            match self {
+                ShellCommand::Set { content } => {
+                    state.shell.hook = Some(content);
+                }
+                ShellCommand::Clear => {
+                    state.shell.hook = None;
+                }
+            }
+            update_project_modified(ctx.clock, &mut state);
+            apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+        }
+        Ok(())
+    }
+}
+
+impl CliCommand for BuildEnvCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        if ctx.global {
+            ctx.output
+                .info("buildEnv settings are only supported in project mode for now");
+            return Ok(());
+        }
+        let paths = ctx.project_paths()?;
+        let mut state = load_project_state(ctx.clock, paths)?;
+        match self {
+            BuildEnvCommand::Show => {
+                show_build_env(ctx.output, &state.build_env);
+                show_package_priorities(ctx, paths, &state)?;
+                return Ok(());
+            }
+            BuildEnvCommand::Name { name, clear } => {
+                if clear {
+                    state.build_env.name = None;
+                } else if let Some(name) = name {
+                    state.build_env.name = Some(name);
+                } else {
+                    show_build_env(ctx.output, &state.build_env);
+                    return Ok(());
+                }
+            }
+            BuildEnvCommand::Prefer {
+                package,
+                priority,
+                clear,
+            } => {
+                if clear {
+                    state.packages.priorities.remove(&package);
+                } else {
+                    let attrs = project_selected_packages(ctx.clock, paths, &state)?;
+                    if !attrs.contains(&package) {
+                        return Err(CliError::PackageNotSelected(package));
+                    }
+                    let priority = match priority {
+                        Some(priority) => priority,
+                        None => {
+                            let collisions = project_collisions(&attrs)?;
+                            preferred_priority(&package, &collisions, &state.packages.priorities)
+                        }
+                    };
+                    ctx.output
+                        .info(format!("{} now has priority {}", package, priority));
+                    state.packages.priorities.insert(package, priority);
+                }
+            }
+            BuildEnvCommand::AddOutput { outputs } => {
+                for output in outputs {
+                    if !state.build_env.extra_outputs.contains(&output) {
+                        state.build_env.extra_outputs.push(output);
+                    }
+                }
+            }
+            BuildEnvCommand::RemoveOutput { outputs } => {
+                state
+                    .build_env
+                    .extra_outputs
+                    .retain(|output| !outputs.contains(output));
+            }
+            BuildEnvCommand::Collisions { mode } => {
+                state.build_env.ignore_collisions = matches!(mode, CollisionsArg::Ignore);
+            }
+            BuildEnvCommand::Priority { value, clear } => {
+                if clear {
+                    state.build_env.priority = None;
+                } else if let Some(value) = value {
+                    state.build_env.priority = Some(value);
+                } else {
+                    show_build_env(ctx.output, &state.build_env);
+                    return Ok(());
+                }
+            }
+        }
+        update_project_modified(ctx.clock, &mut state);
+        apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+        Ok(())
+    }
+}
+
+fn show_package_priorities(
+    ctx: &CommandContext,
+    paths: &ProjectPaths,
+    state: &ProjectState,
+) -> Result<(), CliError> {
+    let output = ctx.output;
+    for (package, priority) in &state.packages.priorities {
+        output.info(format!("package priority: {} = {}", package, priority));
+    }
+    let attrs = project_selected_packages(ctx.clock, paths, state)?;
+    for collision in project_collisions(&attrs)? {
+        let status = if state.build_env.ignore_collisions {
+            "ignored".to_string()
+        } else {
+            match collision_winner(&collision, &state.packages.priorities) {
+                Some(winner) => format!("{} wins", winner),
+                None => "unresolved".to_string(),
+            }
+        };
+        output.info(format!(
+            "collision: {} from {} ({})",
+            collision.binary,
+            collision.packages.join(", "),
+            status
+        ));
+    }
+    Ok(())
+}
+
+fn show_build_env(output: &Output, settings: &BuildEnvState) {
+    output.info(format!(
+        "name: {}",
+        settings.name.as_deref().unwrap_or("(project dir name)")
+    ));
+    if settings.extra_outputs.is_empty() {
+        output.info("extra outputs: none");
+    } else {
+        output.info(format!(
+            "extra outputs: {}",
+            settings.extra_outputs.join(", ")
+        ));
+    }
+    output.info(format!(
+        "collisions: {}",
+        if settings.ignore_collisions {
+            "ignore"
+        } else {
+            "error"
+        }
+    ));
+    match settings.priority {
+        Some(priority) => output.info(format!("priority: {}", priority)),
+        None => output.info("priority: default"),
+    }
+}
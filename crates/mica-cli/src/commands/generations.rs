@@ -0,0 +1,23 @@
+use crate::commands::{CliCommand, CommandContext};
+use crate::nixops::generations::{list_generations, rollback_generation};
+use crate::{load_profile_state, CliError, GenerationsCommand};
+
+impl CliCommand for GenerationsCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        if !ctx.global {
+            ctx.output
+                .info("generations are only available in global mode");
+            return Ok(());
+        }
+        match self {
+            GenerationsCommand::List => {
+                let state = load_profile_state()?;
+                list_generations(ctx.output, &state)?;
+            }
+            GenerationsCommand::Rollback { id } => {
+                rollback_generation(ctx.output, ctx.clock, id, ctx.dry_run)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,343 @@
+use crate::commands::{CliCommand, CommandContext};
+use crate::indexops::compat::{cache_issues, CacheKind};
+use crate::indexops::fallback::{bundled_fallback, write_fallback_list};
+use crate::indexops::remote::{
+    primary_pin_rev, try_fetch_remote_index, try_fetch_remote_index_for_pins,
+};
+use crate::indexops::{
+    collect_index_pins, collect_index_pins_profile, rebuild_index_for_pin, rebuild_index_from_json,
+    rebuild_index_from_local_repo_with_spinner, rebuild_index_from_pins_with_spinner,
+    warn_index_coverage, IndexPin,
+};
+use crate::multiuser::ensure_index_writable;
+use crate::{
+    index_db_path, load_config_or_default, load_profile_state, load_project_state,
+    run_with_spinner, versions_db_path, CliError, IndexCommand, VersionsCommand,
+};
+use mica_index::generate::{get_meta, open_db, IndexError};
+use mica_index::versions::{export_versions, import_versions, init_versions_db, open_versions_db};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+
+impl CliCommand for IndexCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        match self {
+            IndexCommand::Status => {
+                let index_path = index_db_path()?;
+                if !index_path.exists() {
+                    return Err(CliError::MissingIndex(index_path));
+                }
+                let conn = open_db(&index_path)?;
+                let meta = get_meta(&conn)?;
+                if meta.is_empty() {
+                    ctx.output.info(format!("index: {}", index_path.display()));
+                    ctx.output.info("meta: empty");
+                } else {
+                    ctx.output.info(format!("index: {}", index_path.display()));
+                    for (key, value) in meta {
+                        ctx.output.info(format!("{}: {}", key, value));
+                    }
+                }
+                if let Some(pins) = current_index_pins(ctx) {
+                    warn_index_coverage(ctx.output, &conn, &pins);
+                }
+            }
+            IndexCommand::Rebuild {
+                inputs,
+                output: output_path_override,
+                fresh,
+                from_pin,
+            } => {
+                if ctx.dry_run {
+                    ctx.output.info("dry-run: skipping index rebuild");
+                    return Ok(());
+                }
+                let output_path = output_path_override.unwrap_or(index_db_path()?);
+                ensure_index_writable(&output_path)?;
+                if let Some(name) = from_pin {
+                    return rebuild_single_pin(ctx, &output_path, &name);
+                }
+                let pin = if ctx.global {
+                    load_profile_state().ok().map(|state| state.pin)
+                } else {
+                    ctx.project_paths.and_then(|paths| {
+                        load_project_state(ctx.clock, paths)
+                            .ok()
+                            .map(|state| state.pin)
+                    })
+                };
+                let count = rebuild_index_from_json(
+                    ctx.git,
+                    ctx.clock,
+                    ctx.output,
+                    &inputs,
+                    &output_path,
+                    pin.as_ref(),
+                    fresh,
+                )?;
+                ctx.output.info(format!("indexed {} packages", count));
+            }
+            IndexCommand::RebuildLocal {
+                repo,
+                output: output_path_override,
+                skip_attr,
+                show_trace,
+            } => {
+                if ctx.dry_run {
+                    ctx.output.info("dry-run: skipping local index rebuild");
+                    return Ok(());
+                }
+                let output_path = output_path_override.unwrap_or(index_db_path()?);
+                ensure_index_writable(&output_path)?;
+                let count = rebuild_index_from_local_repo_with_spinner(
+                    ctx.output,
+                    ctx.clock,
+                    &repo,
+                    &output_path,
+                    &skip_attr,
+                    show_trace,
+                )?;
+                ctx.output.info(format!("indexed {} packages", count));
+            }
+            IndexCommand::Versions { command } => versions(ctx, command)?,
+            IndexCommand::Fallback { file, attrs } => fallback(ctx, &file, attrs.as_deref())?,
+            IndexCommand::Fetch => {
+                if ctx.dry_run {
+                    ctx.output.info("dry-run: skipping index fetch");
+                    return Ok(());
+                }
+                let config = load_config_or_default()?;
+                if config.index.remote_url.trim().is_empty() {
+                    return Err(CliError::MissingRemoteIndex);
+                }
+                let index_path = index_db_path()?;
+                ensure_index_writable(&index_path)?;
+                let pins = current_index_pins(ctx);
+                let fetched = try_fetch_remote_index(
+                    ctx.output,
+                    &config.index.remote_url,
+                    &index_path,
+                    pins.as_ref().and_then(|entries| primary_pin_rev(entries)),
+                )?;
+                if !fetched {
+                    let Some(pins) = pins.as_ref() else {
+                        return Err(CliError::RemoteIndexFailed(
+                            reqwest::StatusCode::NOT_FOUND,
+                            "no remote index found and no local state available for rebuild"
+                                .to_string(),
+                        ));
+                    };
+                    ctx.output
+                        .status("remote index unavailable, rebuilding locally");
+                    let count = rebuild_index_from_pins_with_spinner(
+                        ctx.git,
+                        ctx.clock,
+                        ctx.output,
+                        &index_path,
+                        pins,
+                    )?;
+                    ctx.output.info(format!("indexed {} packages", count));
+                }
+                ctx.output
+                    .info(format!("index fetched to {}", index_path.display()));
+                if let Ok(conn) = open_db(&index_path) {
+                    if let Ok(meta) = get_meta(&conn) {
+                        for (key, value) in meta {
+                            ctx.output.info(format!("{}: {}", key, value));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn current_index_pins(ctx: &CommandContext) -> Option<Vec<IndexPin>> {
+    if ctx.global {
+        load_profile_state()
+            .ok()
+            .map(|state| collect_index_pins_profile(&state))
+    } else {
+        ctx.project_paths.and_then(|paths| {
+            load_project_state(ctx.clock, paths)
+                .ok()
+                .map(|state| collect_index_pins(&state))
+        })
+    }
+}
+
+pub(crate) fn migrate_caches(ctx: &CommandContext) -> Result<(), CliError> {
+    let issues = cache_issues()?;
+    for kind in [CacheKind::Index, CacheKind::Versions] {
+        let path = kind.path()?;
+        if !path.exists() {
+            ctx.output.info(format!(
+                "{} does not exist, nothing to migrate",
+                path.display()
+            ));
+            continue;
+        }
+        let Some(issue) = issues.iter().find(|issue| issue.kind == kind) else {
+            if !ctx.dry_run {
+                ensure_index_writable(&path)?;
+                match kind {
+                    CacheKind::Index => drop(open_db(&path)?),
+                    CacheKind::Versions => drop(open_versions_db(&path)?),
+                }
+            }
+            ctx.output.info(format!("{} is compatible", path.display()));
+            continue;
+        };
+        if ctx.dry_run {
+            ctx.output.info(format!(
+                "dry-run: would refresh {} ({})",
+                path.display(),
+                issue.reason
+            ));
+            continue;
+        }
+        ensure_index_writable(&path)?;
+        let backup = path.with_extension("db.bak");
+        std::fs::rename(&path, &backup).map_err(CliError::WriteNix)?;
+        ctx.output.info(format!(
+            "moved {} to {} ({})",
+            path.display(),
+            backup.display(),
+            issue.reason
+        ));
+        match kind {
+            CacheKind::Index => refresh_index(ctx, &path)?,
+            CacheKind::Versions => {
+                drop(init_versions_db(&path)?);
+                ctx.output.info(
+                    "started a new versions database, restore history with `mica index versions import`",
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn refresh_index(ctx: &CommandContext, path: &Path) -> Result<(), CliError> {
+    let Some(pins) = current_index_pins(ctx) else {
+        ctx.output.info(
+            "run `mica index fetch` or `mica index rebuild` in a project to build a new index",
+        );
+        return Ok(());
+    };
+    let config = load_config_or_default()?;
+    if try_fetch_remote_index_for_pins(ctx.output, ctx.clock, Some(&config), path, &pins)? {
+        ctx.output.info("fetched a fresh index");
+        return Ok(());
+    }
+    let count = rebuild_index_from_pins_with_spinner(ctx.git, ctx.clock, ctx.output, path, &pins)?;
+    ctx.output
+        .info(format!("rebuilt the index, {} packages", count));
+    Ok(())
+}
+
+fn rebuild_single_pin(
+    ctx: &CommandContext,
+    output_path: &Path,
+    name: &str,
+) -> Result<(), CliError> {
+    let pins = if ctx.global {
+        collect_index_pins_profile(&load_profile_state()?)
+    } else {
+        collect_index_pins(&load_project_state(ctx.clock, ctx.project_paths()?)?)
+    };
+    let count = run_with_spinner(ctx.output, "rebuilding pin", || {
+        rebuild_index_for_pin(ctx.git, ctx.clock, ctx.output, output_path, &pins, name)
+    })?;
+    ctx.output
+        .info(format!("re-indexed {}, index has {} packages", name, count));
+    Ok(())
+}
+
+fn fallback(ctx: &CommandContext, file: &Path, attrs: Option<&Path>) -> Result<(), CliError> {
+    let index_path = index_db_path()?;
+    if !index_path.exists() {
+        return Err(CliError::MissingIndex(index_path));
+    }
+    let attrs: Vec<String> = match attrs {
+        Some(path) => std::fs::read_to_string(path)
+            .map_err(IndexError::Read)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect(),
+        None => bundled_fallback()
+            .packages
+            .into_iter()
+            .map(|pkg| pkg.attr_path)
+            .collect(),
+    };
+    let conn = open_db(&index_path)?;
+    let missing = if file == Path::new("-") {
+        let mut stdout = std::io::stdout().lock();
+        write_fallback_list(&conn, &index_path, &attrs, &mut stdout)?
+    } else {
+        let mut writer = BufWriter::new(File::create(file).map_err(IndexError::Write)?);
+        let missing = write_fallback_list(&conn, &index_path, &attrs, &mut writer)?;
+        writer.flush().map_err(IndexError::Write)?;
+        missing
+    };
+    for attr in &missing {
+        ctx.output
+            .warn(format!("warning: {} is not in the index, left out", attr));
+    }
+    ctx.output
+        .status(format!("wrote {} package(s)", attrs.len() - missing.len()));
+    Ok(())
+}
+
+fn versions(ctx: &CommandContext, command: VersionsCommand) -> Result<(), CliError> {
+    let versions_path = versions_db_path()?;
+    match command {
+        VersionsCommand::Export { file } => {
+            if !versions_path.exists() {
+                return Err(CliError::MissingIndex(versions_path));
+            }
+            let conn = open_versions_db(&versions_path)?;
+            let count = if file == Path::new("-") {
+                let mut stdout = std::io::stdout().lock();
+                export_versions(&conn, &mut stdout)?
+            } else {
+                let mut writer = BufWriter::new(File::create(&file).map_err(IndexError::Write)?);
+                let count = export_versions(&conn, &mut writer)?;
+                writer.flush().map_err(IndexError::Write)?;
+                count
+            };
+            ctx.output.status(format!("exported {} record(s)", count));
+        }
+        VersionsCommand::Import { file } => {
+            let mut reader: Box<dyn std::io::BufRead> = if file == Path::new("-") {
+                Box::new(std::io::stdin().lock())
+            } else {
+                Box::new(BufReader::new(File::open(&file).map_err(IndexError::Read)?))
+            };
+            let mut conn = if ctx.dry_run && !versions_path.exists() {
+                init_versions_db(Path::new(":memory:"))?
+            } else {
+                if let Some(parent) = versions_path.parent() {
+                    std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+                }
+                init_versions_db(&versions_path)?
+            };
+            let summary = import_versions(&mut conn, &mut reader, !ctx.dry_run)?;
+            let prefix = if ctx.dry_run {
+                "dry-run: would import"
+            } else {
+                "imported"
+            };
+            ctx.output.info(format!(
+                "{} {} commit(s), {} version(s), {} commit date(s); {} already present",
+                prefix, summary.commits, summary.versions, summary.commit_dates, summary.skipped
+            ));
+        }
+    }
+    Ok(())
+}
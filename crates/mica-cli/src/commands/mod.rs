@@ -0,0 +1,112 @@
+use crate::clock::Clock;
+use crate::commands::pin::UpdateRequest;
+use crate::github::GitProvider;
+use crate::{
+    BuildEnvCommand, CliError, Command, DaemonCommand, MigrateCommand, Output, ProjectPaths,
+};
+
+pub(crate) mod backup;
+#[cfg(feature = "bench")]
+pub(crate) mod bench;
+pub(crate) mod daemon;
+pub(crate) mod env;
+pub(crate) mod generations;
+pub(crate) mod index;
+pub(crate) mod outdated;
+pub(crate) mod packages;
+pub(crate) mod pin;
+pub(crate) mod project;
+pub(crate) mod state;
+pub(crate) mod tui;
+
+pub(crate) struct CommandContext<'a> {
+    pub(crate) output: &'a Output,
+    pub(crate) global: bool,
+    pub(crate) dry_run: bool,
+    pub(crate) project_paths: Option<&'a ProjectPaths>,
+    pub(crate) git: &'a dyn GitProvider,
+    pub(crate) clock: &'a dyn Clock,
+}
+
+impl CommandContext<'_> {
+    pub(crate) fn project_paths(&self) -> Result<&ProjectPaths, CliError> {
+        self.project_paths.ok_or(CliError::MissingProjectPaths)
+    }
+}
+
+pub(crate) trait CliCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError>;
+}
+
+impl CliCommand for Command {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        match self {
+            Command::Tui => {
+                if ctx.dry_run {
+                    ctx.output.info("dry-run ignored for TUI");
+                }
+                tui::run_tui(ctx)
+            }
+            Command::Init { repo, adopt } => project::init(ctx, repo, adopt),
+            Command::Add { packages } => packages::add(ctx, packages),
+            Command::Remove { packages } => packages::remove(ctx, packages),
+            Command::Try { packages, run } => packages::try_packages(ctx, packages, run),
+            Command::Search { query, mode } => packages::search(ctx, query, mode),
+            Command::Env { command } => command.run(ctx),
+            Command::Shell { command } => command.run(ctx),
+            Command::Apply { presets } => packages::apply(ctx, presets),
+            Command::Unapply { presets } => packages::unapply(ctx, presets),
+            Command::List => packages::list(ctx),
+            Command::Presets => packages::presets(ctx),
+            Command::Update {
+                package,
+                url,
+                latest,
+                rev,
+                sha256,
+                branch,
+                channel,
+            } => UpdateRequest {
+                package,
+                url,
+                latest,
+                rev,
+                sha256,
+                branch,
+                channel,
+            }
+            .run(ctx),
+            Command::Extends { path, clear } => pin::extends(ctx, path, clear),
+            Command::Describe {
+                description,
+                owner,
+                clear,
+            } => project::describe(ctx, description, owner, clear),
+            Command::Migrate {
+                command: Some(MigrateCommand::Caches),
+                ..
+            } => index::migrate_caches(ctx),
+            Command::Migrate { all_projects, .. } => project::migrate(ctx, all_projects),
+            Command::Outdated { all_projects } => outdated::outdated(ctx, all_projects),
+            Command::BuildEnv { command } => command.unwrap_or(BuildEnvCommand::Show).run(ctx),
+            Command::Pin { command } => command.run(ctx),
+            Command::Backup { command } => command.run(ctx),
+            Command::State { command } => command.run(ctx),
+            Command::Generations { command } => command.run(ctx),
+            Command::Daemon { command } => command.unwrap_or(DaemonCommand::Run).run(ctx),
+            Command::Export { footer, check } => project::export(ctx, footer, check),
+            Command::Index { command } => command.run(ctx),
+            Command::Sync { from_nix } => project::sync(ctx, from_nix),
+            Command::Eval => project::eval(ctx),
+            Command::Diff { target } => project::diff(ctx, target),
+            Command::Completion { shell } => project::completion(shell),
+            Command::TuiSnapshot {
+                screen,
+                width,
+                height,
+            } => tui::snapshot(screen, width, height),
+            #[cfg(feature = "bench")]
+            Command::Bench { iterations } => bench::run(ctx, iterations),
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::commands::CommandContext;
+use crate::github::NIXOS_CHANNELS_URL;
+use crate::indexops::pin_source_label;
+use crate::{
+    index_db_path, load_profile_state, load_project_state, registry_path, versions_db_path,
+    CliError, ProjectPaths,
+};
+use mica_core::registry::ProjectRegistry;
+use mica_core::state::{PackagesState, Pin};
+use mica_index::generate::{get_package, open_db};
+use mica_index::versions::{open_versions_db, version_for_commit};
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PackageUpdate {
+    pub(crate) attr: String,
+    pub(crate) current: String,
+    pub(crate) latest: String,
+}
+
+struct OutdatedCheck<'a> {
+    ctx: &'a CommandContext<'a>,
+    index: Option<Connection>,
+    versions: Option<Connection>,
+    upstream: BTreeMap<(String, String), Option<String>>,
+}
+
+impl OutdatedCheck<'_> {
+    fn upstream_rev(&mut self, pin: &Pin) -> Option<String> {
+        let key = match &pin.channel {
+            Some(channel) => (NIXOS_CHANNELS_URL.to_string(), channel.name.clone()),
+            None => (pin.url.clone(), pin.branch.clone()),
+        };
+        if let Some(rev) = self.upstream.get(&key) {
+            return rev.clone();
+        }
+        let latest = match &pin.channel {
+            Some(channel) => self
+                .ctx
+                .git
+                .channel_release(&channel.name)
+                .map(|release| release.rev),
+            None => self.ctx.git.latest_rev(&pin.url, &pin.branch),
+        };
+        let rev = match latest {
+            Ok(rev) => Some(rev),
+            Err(err) => {
+                self.ctx.output.warn(format!(
+                    "warning: could not check {} ({}): {}",
+                    key.0, key.1, err
+                ));
+                None
+            }
+        };
+        self.upstream.insert(key, rev.clone());
+        rev
+    }
+
+    fn report(
+        &mut self,
+        label: &str,
+        pin: &Pin,
+        packages: &PackagesState,
+    ) -> Result<bool, CliError> {
+        let output = self.ctx.output;
+        let latest_rev = self.upstream_rev(pin).filter(|rev| rev != &pin.rev);
+        let updates = match &self.index {
+            Some(index) => package_updates(index, self.versions.as_ref(), pin, packages)?,
+            None => Vec::new(),
+        };
+        if latest_rev.is_none() && updates.is_empty() {
+            output.info(format!("{}: up to date", label));
+            return Ok(false);
+        }
+        output.info(format!("{}:", label));
+        if let Some(rev) = latest_rev {
+            output.info(format!(
+                "  pin {} -> {} (pinned {})",
+                short_rev(&pin.rev),
+                short_rev(&rev),
+                pin.updated
+            ));
+        }
+        for update in &updates {
+            output.info(format!(
+                "  {} {} -> {}",
+                update.attr, update.current, update.latest
+            ));
+        }
+        Ok(true)
+    }
+}
+
+pub(crate) fn outdated(ctx: &CommandContext, all_projects: bool) -> Result<(), CliError> {
+    let index_path = index_db_path()?;
+    let index = if index_path.exists() {
+        Some(open_db(&index_path)?)
+    } else {
+        ctx.output
+            .warn("warning: index missing, only checking pins (run `mica index fetch`)");
+        None
+    };
+    let versions_path = versions_db_path()?;
+    let versions = if versions_path.exists() {
+        Some(open_versions_db(&versions_path).map_err(CliError::Index)?)
+    } else {
+        None
+    };
+    let mut check = OutdatedCheck {
+        ctx,
+        index,
+        versions,
+        upstream: BTreeMap::new(),
+    };
+
+    if !all_projects {
+        if ctx.global {
+            let state = load_profile_state()?;
+            check.report("global profile", &state.pin, &state.packages)?;
+        } else {
+            let paths = ctx.project_paths()?;
+            let state = load_project_state(ctx.clock, paths)?;
+            let label = paths.nix_path.display().to_string();
+            check.report(&label, &state.pin, &state.packages)?;
+        }
+        return Ok(());
+    }
+
+    let registry = ProjectRegistry::load_from_path(&registry_path()?)?;
+    if registry.projects.is_empty() {
+        ctx.output.info("no known projects to check");
+        return Ok(());
+    }
+    let mut checked = 0;
+    let mut stale = 0;
+    for path in registry.projects.keys() {
+        if !path.exists() {
+            ctx.output.verbose(format!(
+                "skipping {}: file no longer exists",
+                path.display()
+            ));
+            continue;
+        }
+        match check_project(&mut check, path) {
+            Ok(outdated) => {
+                checked += 1;
+                if outdated {
+                    stale += 1;
+                }
+            }
+            Err(err) => ctx
+                .output
+                .warn(format!("failed to check {}: {}", path.display(), err)),
+        }
+    }
+    ctx.output.info(format!(
+        "{} of {} project(s) have updates available",
+        stale, checked
+    ));
+    Ok(())
+}
+
+fn check_project(check: &mut OutdatedCheck, nix_path: &Path) -> Result<bool, CliError> {
+    let paths = ProjectPaths {
+        nix_path: nix_path.to_path_buf(),
+        root_dir: nix_path.parent().map(Path::to_path_buf).unwrap_or_default(),
+    };
+    let state = load_project_state(check.ctx.clock, &paths)?;
+    check.report(&nix_path.display().to_string(), &state.pin, &state.packages)
+}
+
+pub(crate) fn package_updates(
+    index: &Connection,
+    versions: Option<&Connection>,
+    pin: &Pin,
+    packages: &PackagesState,
+) -> Result<Vec<PackageUpdate>, CliError> {
+    let source = pin_source_label(pin);
+    let mut current: BTreeMap<&str, String> = BTreeMap::new();
+    if let Some(versions) = versions {
+        for attr in &packages.added {
+            if let Some(entry) =
+                version_for_commit(versions, attr, &source, &pin.rev).map_err(CliError::Index)?
+            {
+                current.insert(attr, entry.version);
+            }
+        }
+    }
+    for (attr, pinned) in &packages.pinned {
+        current.insert(attr, pinned.version.clone());
+    }
+
+    let mut updates = Vec::new();
+    for (attr, version) in current {
+        let Some(latest) = get_package(index, attr)?.and_then(|pkg| pkg.version) else {
+            continue;
+        };
+        if latest != version {
+            updates.push(PackageUpdate {
+                attr: attr.to_string(),
+                current: version,
+                latest,
+            });
+        }
+    }
+    Ok(updates)
+}
+
+fn short_rev(rev: &str) -> String {
+    rev.chars().take(12).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::outdated::{package_updates, PackageUpdate};
+    use crate::indexops::pin_source_label;
+    use chrono::NaiveDate;
+    use mica_core::state::{PackagesState, Pin, PinnedPackage};
+    use mica_index::generate::{ingest_packages, init_db, NixPackage};
+    use mica_index::versions::{init_versions_db, record_versions, VersionSource};
+    use std::path::Path;
+
+    fn package(attr: &str, version: &str) -> NixPackage {
+        NixPackage {
+            attr_path: attr.to_string(),
+            name: format!("{}-{}", attr, version),
+            version: Some(version.to_string()),
+            description: None,
+            homepage: None,
+            license: None,
+            platforms: None,
+            main_program: None,
+            position: None,
+            broken: None,
+            insecure: None,
+            maintainers: None,
+            last_updated: None,
+        }
+    }
+
+    #[test]
+    fn package_updates_compare_pinned_versions_with_the_index() {
+        let pin = Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: "old".to_string(),
+            sha256: "sha".to_string(),
+            branch: "nixpkgs-unstable".to_string(),
+            updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            channel: None,
+        };
+        let mut index = init_db(Path::new(":memory:")).unwrap();
+        ingest_packages(
+            &mut index,
+            &[
+                package("ripgrep", "14.1.1"),
+                package("jq", "1.7.1"),
+                package("nodejs", "22.0.0"),
+            ],
+        )
+        .unwrap();
+        let mut versions = init_versions_db(Path::new(":memory:")).unwrap();
+        record_versions(
+            &mut versions,
+            &VersionSource {
+                source: pin_source_label(&pin),
+                url: pin.url.clone(),
+                branch: pin.branch.clone(),
+                commit: pin.rev.clone(),
+                commit_date: "2026-01-01T00:00:00Z".to_string(),
+                indexed_at: "2026-01-01T00:00:00Z".to_string(),
+            },
+            &[package("ripgrep", "14.0.0"), package("jq", "1.7.1")],
+        )
+        .unwrap();
+
+        let mut packages = PackagesState {
+            added: vec!["ripgrep".to_string(), "jq".to_string(), "gone".to_string()],
+            ..PackagesState::default()
+        };
+        packages.pinned.insert(
+            "nodejs".to_string(),
+            PinnedPackage {
+                version: "20.1.0".to_string(),
+                pin: pin.clone(),
+            },
+        );
+
+        let updates = package_updates(&index, Some(&versions), &pin, &packages).unwrap();
+        assert_eq!(
+            updates,
+            vec![
+                PackageUpdate {
+                    attr: "nodejs".to_string(),
+                    current: "20.1.0".to_string(),
+                    latest: "22.0.0".to_string(),
+                },
+                PackageUpdate {
+                    attr: "ripgrep".to_string(),
+                    current: "14.0.0".to_string(),
+                    latest: "14.1.1".to_string(),
+                },
+            ]
+        );
+    }
+}
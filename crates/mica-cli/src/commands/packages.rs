@@ -0,0 +1,392 @@
+use crate::commands::index::current_index_pins;
+use crate::commands::tui::{env_value_for_editor, env_value_mode_from_stored};
+use crate::commands::CommandContext;
+use crate::daemon::{try_request, DaemonPackage, DaemonPreset, DaemonRequest, DaemonResponse};
+use crate::indexops::aliases::warn_alias_packages;
+use crate::indexops::{
+    normalize_attr_path, report_index_coverage, warn_flagged_packages, warn_index_coverage,
+    warn_package_collisions,
+};
+use crate::nixops::ephemeral::{
+    ephemeral_shell_expression, resolve_try_packages, run_ephemeral_shell,
+};
+use crate::nixops::url_rewrites;
+use crate::{
+    apply_profile_changes, apply_project_changes, current_host, has_local_presets, index_db_path,
+    inherited_packages, load_all_presets, load_config_or_default, load_profile_state,
+    load_project_state, project_selected_packages, to_index_search_mode, tui,
+    update_profile_modified, update_project_modified, CliError, Output, ProjectPaths,
+    SearchModeArg,
+};
+use mica_core::state::{GlobalProfileState, ProjectState};
+use mica_index::generate::{open_db, search_packages_with_mode};
+use std::collections::BTreeMap;
+
+pub(crate) fn add(ctx: &CommandContext, packages: Vec<String>) -> Result<(), CliError> {
+    if ctx.global {
+        let mut state = load_profile_state()?;
+        for pkg in &packages {
+            if !state.packages.added.contains(pkg) {
+                state.packages.added.push(pkg.clone());
+            }
+            state.packages.removed.retain(|item| item != pkg);
+        }
+        warn_flagged_packages(ctx.output, &packages);
+        warn_alias_packages(ctx.output, &packages);
+        update_profile_modified(ctx.clock, &mut state);
+        apply_profile_changes(ctx.output, ctx.clock, ctx.dry_run, &state)?;
+    } else {
+        let paths = ctx.project_paths()?;
+        let mut state = load_project_state(ctx.clock, paths)?;
+        let inherited = inherited_packages(ctx.clock, paths, &state)?;
+        for pkg in &packages {
+            state.packages.removed.retain(|item| item != pkg);
+            if inherited.contains(pkg) {
+                ctx.output.info(format!(
+                    "{} is already provided by parent {}; not listing it again",
+                    pkg,
+                    state.extends.as_deref().unwrap_or_default()
+                ));
+                continue;
+            }
+            if !state.packages.added.contains(pkg) {
+                state.packages.added.push(pkg.clone());
+            }
+        }
+        warn_flagged_packages(ctx.output, &packages);
+        warn_alias_packages(ctx.output, &packages);
+        warn_project_collisions(ctx, paths, &state);
+        update_project_modified(ctx.clock, &mut state);
+        apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn remove(ctx: &CommandContext, packages: Vec<String>) -> Result<(), CliError> {
+    if ctx.global {
+        let mut state = load_profile_state()?;
+        for pkg in packages {
+            if !state.packages.removed.contains(&pkg) {
+                state.packages.removed.push(pkg.clone());
+            }
+            state.packages.added.retain(|item| item != &pkg);
+        }
+        update_profile_modified(ctx.clock, &mut state);
+        apply_profile_changes(ctx.output, ctx.clock, ctx.dry_run, &state)?;
+    } else {
+        let paths = ctx.project_paths()?;
+        let mut state = load_project_state(ctx.clock, paths)?;
+        for pkg in packages {
+            if !state.packages.removed.contains(&pkg) {
+                state.packages.removed.push(pkg.clone());
+            }
+            state.packages.added.retain(|item| item != &pkg);
+            state.packages.priorities.remove(&pkg);
+            state.packages.overrides.remove(&pkg);
+        }
+        update_project_modified(ctx.clock, &mut state);
+        apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn try_packages(
+    ctx: &CommandContext,
+    packages: Vec<String>,
+    run: Option<String>,
+) -> Result<(), CliError> {
+    let (pin, pins, pinned) = if ctx.global {
+        let state = load_profile_state()?;
+        (state.pin, BTreeMap::new(), state.packages.pinned)
+    } else {
+        let state = load_project_state(ctx.clock, ctx.project_paths()?)?;
+        (state.pin, state.pins, state.packages.pinned)
+    };
+    let resolved = resolve_try_packages(&packages, &pin, &pins, &pinned);
+    let attrs: Vec<String> = resolved.iter().map(|(attr, _)| attr.clone()).collect();
+    warn_flagged_packages(ctx.output, &attrs);
+    let expression = ephemeral_shell_expression(&pin, &resolved, &url_rewrites()?);
+    if ctx.dry_run {
+        ctx.output
+            .info(format!("dry-run: nix-shell -E '{}'", expression));
+        return Ok(());
+    }
+    ctx.output.status(format!(
+        "entering ephemeral shell with {} (state unchanged)",
+        attrs.join(", ")
+    ));
+    run_ephemeral_shell(&expression, run.as_deref())
+}
+
+pub(crate) fn search(
+    ctx: &CommandContext,
+    query: String,
+    mode: Option<SearchModeArg>,
+) -> Result<(), CliError> {
+    let config = load_config_or_default()?;
+    let search_mode = mode
+        .map(|mode| mode.to_search_mode())
+        .unwrap_or(config.tui.search_mode);
+    let request = DaemonRequest::Search {
+        query: query.clone(),
+        mode: search_mode.clone(),
+        limit: 25,
+    };
+    let results = match try_request(&request) {
+        Some(DaemonResponse::Packages { packages, meta }) => {
+            ctx.output.verbose("search served by daemon");
+            if let Some(pins) = current_index_pins(ctx) {
+                report_index_coverage(ctx.output, &meta, &pins);
+            }
+            packages
+        }
+        _ => {
+            let index_path = index_db_path()?;
+            if !index_path.exists() {
+                return Err(CliError::MissingIndex(index_path));
+            }
+            let conn = open_db(&index_path)?;
+            if let Some(pins) = current_index_pins(ctx) {
+                warn_index_coverage(ctx.output, &conn, &pins);
+            }
+            search_packages_with_mode(&conn, &query, 25, to_index_search_mode(&search_mode))?
+                .into_iter()
+                .map(|pkg| DaemonPackage {
+                    attr_path: pkg.attr_path,
+                    version: pkg.version,
+                    description: pkg.description,
+                })
+                .collect()
+        }
+    };
+    for pkg in results {
+        let version = pkg.version.unwrap_or_else(|| "-".to_string());
+        let description = pkg.description.unwrap_or_default();
+        ctx.output.info(format!(
+            "{} {} {}",
+            normalize_attr_path(&pkg.attr_path),
+            version,
+            description
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn apply(ctx: &CommandContext, presets: Vec<String>) -> Result<(), CliError> {
+    if ctx.global {
+        let mut state = load_profile_state()?;
+        for preset in presets {
+            if !state.presets.active.contains(&preset) {
+                state.presets.active.push(preset);
+            }
+        }
+        update_profile_modified(ctx.clock, &mut state);
+        apply_profile_changes(ctx.output, ctx.clock, ctx.dry_run, &state)?;
+    } else {
+        let paths = ctx.project_paths()?;
+        let mut state = load_project_state(ctx.clock, paths)?;
+        for preset in presets {
+            if !state.presets.active.contains(&preset) {
+                state.presets.active.push(preset);
+            }
+        }
+        warn_project_collisions(ctx, paths, &state);
+        update_project_modified(ctx.clock, &mut state);
+        apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+    }
+    Ok(())
+}
+
+fn warn_project_collisions(ctx: &CommandContext, paths: &ProjectPaths, state: &ProjectState) {
+    let output = ctx.output;
+    match project_selected_packages(ctx.clock, paths, state) {
+        Ok(attrs) => warn_package_collisions(output, state, &attrs),
+        Err(err) => output.verbose(format!("skipping collision check: {}", err)),
+    }
+}
+
+pub(crate) fn unapply(ctx: &CommandContext, presets: Vec<String>) -> Result<(), CliError> {
+    if ctx.global {
+        let mut state = load_profile_state()?;
+        state
+            .presets
+            .active
+            .retain(|preset| !presets.contains(preset));
+        update_profile_modified(ctx.clock, &mut state);
+        apply_profile_changes(ctx.output, ctx.clock, ctx.dry_run, &state)?;
+    } else {
+        let paths = ctx.project_paths()?;
+        let mut state = load_project_state(ctx.clock, paths)?;
+        state
+            .presets
+            .active
+            .retain(|preset| !presets.contains(preset));
+        update_project_modified(ctx.clock, &mut state);
+        apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn list(ctx: &CommandContext) -> Result<(), CliError> {
+    if ctx.global {
+        let state = load_profile_state()?;
+        print_profile_state(ctx.output, &state);
+    } else {
+        let paths = ctx.project_paths()?;
+        let state = load_project_state(ctx.clock, paths)?;
+        print_project_state(ctx.output, &state);
+    }
+    Ok(())
+}
+
+pub(crate) fn presets(ctx: &CommandContext) -> Result<(), CliError> {
+    // The daemon only knows presets that do not depend on the working
+    // directory, so a local presets/ dir is always read directly.
+    let daemon = if has_local_presets() {
+        None
+    } else {
+        try_request(&DaemonRequest::Presets)
+    };
+    let mut presets = match daemon {
+        Some(DaemonResponse::Presets { presets }) => {
+            ctx.output.verbose("presets served by daemon");
+            presets
+        }
+        _ => load_all_presets()?
+            .into_iter()
+            .map(|preset| DaemonPreset {
+                required: preset.packages_required.len(),
+                optional: preset.packages_optional.len(),
+                name: preset.name,
+                description: preset.description,
+                order: preset.order,
+                source: preset.source,
+            })
+            .collect(),
+    };
+    presets.sort_by(|left, right| {
+        left.order
+            .cmp(&right.order)
+            .then_with(|| left.name.cmp(&right.name))
+    });
+
+    for preset in presets {
+        let description = preset.description.trim();
+        if description.is_empty() {
+            ctx.output.info(format!(
+                "{} [order:{} req:{} opt:{}] {}",
+                preset.name,
+                preset.order,
+                preset.required,
+                preset.optional,
+                preset.source.display()
+            ));
+        } else {
+            ctx.output.info(format!(
+                "{} [order:{} req:{} opt:{}] {} - {}",
+                preset.name,
+                preset.order,
+                preset.required,
+                preset.optional,
+                preset.source.display(),
+                description
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn print_project_state(output: &Output, state: &ProjectState) {
+    output.info("mode: project");
+    if let Some(description) = &state.manifest.description {
+        output.info(format!("description: {}", description));
+    }
+    if let Some(owner) = &state.manifest.owner {
+        output.info(format!("owner: {}", owner));
+    }
+    output.info(format!("pin: {} @ {}", state.pin.url, state.pin.rev));
+    if let Some(parent) = &state.extends {
+        output.info(format!("extends: {}", parent));
+    }
+    if !state.pins.is_empty() {
+        output.info("pins:");
+        for (name, pin) in &state.pins {
+            output.info(format!("  {} -> {} ({})", name, pin.url, pin.rev));
+        }
+    }
+    output.info(format!("presets: {}", state.presets.active.join(", ")));
+    output.info(format!(
+        "packages (added): {}",
+        state.packages.added.join(", ")
+    ));
+    output.info(format!(
+        "packages (removed): {}",
+        state.packages.removed.join(", ")
+    ));
+    if !state.packages.pinned.is_empty() {
+        output.info("packages (pinned):");
+        for (name, pinned) in &state.packages.pinned {
+            output.info(format!(
+                "  {} -> {} ({})",
+                name, pinned.version, pinned.pin.rev
+            ));
+        }
+    }
+    if !state.env.is_empty() {
+        output.info("env:");
+        for (key, value) in &state.env {
+            let display = env_value_for_editor(value);
+            let suffix =
+                if env_value_mode_from_stored(value) == tui::app::EnvValueMode::NixExpression {
+                    " [expr]"
+                } else {
+                    ""
+                };
+            output.info(format!("  {}={}{}", key, display, suffix));
+        }
+    }
+    if let Some(hook) = &state.shell.hook {
+        output.info("shellHook:");
+        output.info(hook);
+    }
+}
+
+fn print_profile_state(output: &Output, state: &GlobalProfileState) {
+    output.info("mode: global");
+    output.info(format!("pin: {} @ {}", state.pin.url, state.pin.rev));
+    output.info(format!("presets: {}", state.presets.active.join(", ")));
+    output.info(format!(
+        "packages (added): {}",
+        state.packages.added.join(", ")
+    ));
+    output.info(format!(
+        "packages (removed): {}",
+        state.packages.removed.join(", ")
+    ));
+    if !state.packages.pinned.is_empty() {
+        output.info("packages (pinned):");
+        for (name, pinned) in &state.packages.pinned {
+            output.info(format!(
+                "  {} -> {} ({})",
+                name, pinned.version, pinned.pin.rev
+            ));
+        }
+    }
+    if !state.hosts.is_empty() {
+        let host = current_host();
+        output.info("packages (per host):");
+        for entry in &state.hosts {
+            let marker = if entry.matches(&host) {
+                " (this host)"
+            } else {
+                ""
+            };
+            output.info(format!(
+                "  {}{}: {}",
+                entry.label(),
+                marker,
+                entry.packages.join(", ")
+            ));
+        }
+    }
+}
@@ -0,0 +1,430 @@
+use crate::commands::{CliCommand, CommandContext};
+use crate::indexops::missing::report_missing_packages;
+use crate::nixops::generations::profile_installed_packages;
+use crate::nixops::warm::spawn_warm_cache;
+use crate::nixops::{head_nix_tarball, pin_tarball_url, prefetch_nix_sha256, url_rewrites};
+use crate::{
+    add_extra_pin, apply_profile_changes, apply_project_changes, is_valid_extends_path,
+    load_config_or_default, load_profile_state, load_project_state, project_selected_packages,
+    resolve_channel_pin, resolve_update_rev_and_sha, run_with_spinner, update_channel,
+    update_profile_pin_stub, update_project_modified, update_project_pin_stub, AddPinRequest,
+    CliError, PinCommand, ResolvedPin,
+};
+use mica_core::config::UrlRewrite;
+use mica_core::state::{PackagesState, Pin};
+use std::collections::BTreeMap;
+
+pub(crate) struct UpdateRequest {
+    pub(crate) package: Option<String>,
+    pub(crate) url: Option<String>,
+    pub(crate) latest: bool,
+    pub(crate) rev: Option<String>,
+    pub(crate) sha256: Option<String>,
+    pub(crate) branch: Option<String>,
+    pub(crate) channel: Option<String>,
+}
+
+impl CliCommand for UpdateRequest {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        let UpdateRequest {
+            package,
+            url,
+            latest,
+            rev,
+            sha256,
+            branch,
+            channel,
+        } = self;
+        let check_missing = package.is_none() && !ctx.dry_run;
+        if ctx.global {
+            let mut state = load_profile_state()?;
+            let base_pin = match package.as_deref() {
+                Some(name) => state
+                    .packages
+                    .pinned
+                    .get(name)
+                    .map(|pinned| &pinned.pin)
+                    .unwrap_or(&state.pin),
+                None => &state.pin,
+            };
+            let (resolved, branch) =
+                resolve_update(ctx, base_pin, &url, branch, rev, sha256, latest, channel)?;
+            update_profile_pin_stub(ctx.clock, &mut state, package, url, resolved, branch)?;
+            apply_profile_changes(ctx.output, ctx.clock, ctx.dry_run, &state)?;
+            if check_missing {
+                let mut attrs = profile_installed_packages(&state)?;
+                attrs.retain(|attr| !state.packages.pinned.contains_key(attr));
+                report_missing_packages(ctx.output, &state.pin, &attrs);
+            }
+        } else {
+            let paths = ctx.project_paths()?;
+            let mut state = load_project_state(ctx.clock, paths)?;
+            let base_pin = match package.as_deref() {
+                Some(name) => state
+                    .packages
+                    .pinned
+                    .get(name)
+                    .map(|pinned| &pinned.pin)
+                    .unwrap_or(&state.pin),
+                None => &state.pin,
+            };
+            let (resolved, branch) =
+                resolve_update(ctx, base_pin, &url, branch, rev, sha256, latest, channel)?;
+            update_project_pin_stub(ctx.clock, &mut state, package, url, resolved, branch)?;
+            apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+            if check_missing {
+                let mut attrs = project_selected_packages(ctx.clock, paths, &state)?;
+                attrs.retain(|attr| !state.packages.pinned.contains_key(attr));
+                report_missing_packages(ctx.output, &state.pin, &attrs);
+            }
+            if !ctx.dry_run {
+                let hooks = load_config_or_default()?.hooks;
+                spawn_warm_cache(ctx.output, &hooks, &paths.nix_path);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Resolves an update from `--channel` or the pin's tracked channel, falling
+/// back to the url, branch and revision flags. Channel updates also record the
+/// channel as the pin's branch.
+#[allow(clippy::too_many_arguments)]
+fn resolve_update(
+    ctx: &CommandContext,
+    base_pin: &Pin,
+    url: &Option<String>,
+    branch: Option<String>,
+    rev: Option<String>,
+    sha256: Option<String>,
+    latest: bool,
+    channel: Option<String>,
+) -> Result<(ResolvedPin, Option<String>), CliError> {
+    match update_channel(base_pin, channel, url, &branch, latest) {
+        Some(channel) => Ok((
+            resolve_channel_pin(ctx.git, &channel, sha256)?,
+            Some(channel.trim().to_string()),
+        )),
+        None => Ok((
+            resolve_update_rev_and_sha(ctx.git, base_pin, url, &branch, rev, sha256, latest)?,
+            branch,
+        )),
+    }
+}
+
+pub(crate) fn extends(
+    ctx: &CommandContext,
+    path: Option<String>,
+    clear: bool,
+) -> Result<(), CliError> {
+    if ctx.global {
+        ctx.output.info("extends is only supported in project mode");
+        return Ok(());
+    }
+    let paths = ctx.project_paths()?;
+    let mut state = load_project_state(ctx.clock, paths)?;
+    if clear {
+        state.extends = None;
+    } else if let Some(path) = path {
+        let path = path.trim().to_string();
+        if !is_valid_extends_path(&path) {
+            return Err(CliError::InvalidExtendsPath(path));
+        }
+        let parent_path = paths.root_dir.join(&path);
+        if !parent_path.exists() {
+            return Err(CliError::MissingDefaultNix(parent_path));
+        }
+        state.extends = Some(path);
+    } else {
+        match &state.extends {
+            Some(parent) => ctx.output.info(parent),
+            None => ctx.output.info("not extending a parent"),
+        }
+        return Ok(());
+    }
+    update_project_modified(ctx.clock, &mut state);
+    apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+    Ok(())
+}
+
+impl CliCommand for PinCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        if let PinCommand::Verify { quick } = self {
+            return verify(ctx, quick);
+        }
+        if ctx.global {
+            ctx.output
+                .info("pins are only supported in project mode for now");
+        } else {
+            let paths = ctx.project_paths()?;
+            let mut state = load_project_state(ctx.clock, paths)?;
+            match self {
+                PinCommand::Add {
+                    name,
+                    url,
+                    branch,
+                    tarball_name,
+                    latest,
+                    rev,
+                    sha256,
+                    channel,
+                } => {
+                    let branch = channel
+                        .as_ref()
+                        .map(|channel| channel.trim().to_string())
+                        .or(branch);
+                    add_extra_pin(
+                        ctx.git,
+                        ctx.clock,
+                        &mut state,
+                        AddPinRequest {
+                            name,
+                            url,
+                            branch,
+                            tarball_name,
+                            rev,
+                            sha256,
+                            latest,
+                            channel,
+                        },
+                    )?;
+                    apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+                }
+                PinCommand::Remove { name } => {
+                    if state.pins.remove(&name).is_none() {
+                        return Err(CliError::PinNotFound(name));
+                    }
+                    update_project_modified(ctx.clock, &mut state);
+                    apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+                }
+                PinCommand::Verify { .. } => {}
+                PinCommand::List => {
+                    if state.pins.is_empty() {
+                        ctx.output.info("no extra pins configured");
+                    } else {
+                        for (name, pin) in &state.pins {
+                            ctx.output
+                                .info(format!("{} -> {} @ {}", name, pin.url, pin.rev));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PinCheck {
+    Ok(Option<u64>),
+    Mismatch(String),
+    Failed(String),
+}
+
+pub(crate) fn pins_to_verify(
+    pin: &Pin,
+    extra: &BTreeMap<String, Pin>,
+    packages: &PackagesState,
+) -> Vec<(String, Pin)> {
+    let mut targets = vec![("nixpkgs".to_string(), pin.clone())];
+    for (name, pin) in extra {
+        targets.push((format!("pin {}", name), pin.clone()));
+    }
+    for (attr, pinned) in &packages.pinned {
+        targets.push((format!("package {}", attr), pinned.pin.clone()));
+    }
+    targets
+}
+
+pub(crate) fn check_pins(
+    targets: &[(String, Pin)],
+    check: &mut dyn FnMut(&Pin) -> PinCheck,
+) -> Vec<PinCheck> {
+    let mut seen: BTreeMap<(&str, &str, &str), PinCheck> = BTreeMap::new();
+    targets
+        .iter()
+        .map(|(_, pin)| {
+            seen.entry((&pin.url, &pin.rev, &pin.sha256))
+                .or_insert_with(|| check(pin))
+                .clone()
+        })
+        .collect()
+}
+
+/// Checks the tarball `fetchTarball` would download for `pin`: the channel
+/// release for channel pins, the GitHub archive otherwise.
+pub(crate) fn check_pin(
+    pin: &Pin,
+    quick: bool,
+    rewrites: &[UrlRewrite],
+    head: &mut dyn FnMut(&str) -> Result<Option<u64>, CliError>,
+    prefetch: &mut dyn FnMut(&str) -> Result<String, CliError>,
+) -> PinCheck {
+    let url = pin_tarball_url(pin, rewrites);
+    if quick {
+        match head(&url) {
+            Ok(size) => PinCheck::Ok(size),
+            Err(err) => PinCheck::Failed(err.to_string()),
+        }
+    } else {
+        match prefetch(&url) {
+            Ok(sha256) if sha256 == pin.sha256 => PinCheck::Ok(None),
+            Ok(sha256) => PinCheck::Mismatch(sha256),
+            Err(err) => PinCheck::Failed(err.to_string()),
+        }
+    }
+}
+
+fn verify(ctx: &CommandContext, quick: bool) -> Result<(), CliError> {
+    let targets = if ctx.global {
+        let state = load_profile_state()?;
+        pins_to_verify(&state.pin, &BTreeMap::new(), &state.packages)
+    } else {
+        let state = load_project_state(ctx.clock, ctx.project_paths()?)?;
+        pins_to_verify(&state.pin, &state.pins, &state.packages)
+    };
+    let label = if quick {
+        "checking pin tarballs"
+    } else {
+        "prefetching pin tarballs"
+    };
+    let rewrites = url_rewrites()?;
+    let results = run_with_spinner(ctx.output, label, || {
+        Ok::<_, CliError>(check_pins(&targets, &mut |pin| {
+            check_pin(
+                pin,
+                quick,
+                &rewrites,
+                &mut head_nix_tarball,
+                &mut prefetch_nix_sha256,
+            )
+        }))
+    })?;
+
+    let mut failed = 0;
+    for ((name, pin), result) in targets.iter().zip(&results) {
+        let source = format!("{} @ {}", pin.url, pin.rev);
+        match result {
+            PinCheck::Ok(Some(size)) => ctx
+                .output
+                .info(format!("ok        {} ({}, {} bytes)", name, source, size)),
+            PinCheck::Ok(None) => ctx.output.info(format!("ok        {} ({})", name, source)),
+            PinCheck::Mismatch(sha256) => {
+                failed += 1;
+                ctx.output.info(format!(
+                    "mismatch  {} ({}): stored {}, tarball is {}",
+                    name, source, pin.sha256, sha256
+                ));
+            }
+            PinCheck::Failed(err) => {
+                failed += 1;
+                ctx.output
+                    .info(format!("failed    {} ({}): {}", name, source, err));
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(CliError::PinVerifyFailed(failed));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::pin::{check_pin, check_pins, pins_to_verify, PinCheck};
+    use chrono::NaiveDate;
+    use mica_core::config::UrlRewrite;
+    use mica_core::state::{PackagesState, Pin, PinChannel, PinnedPackage};
+    use std::collections::BTreeMap;
+
+    fn pin(rev: &str, sha256: &str) -> Pin {
+        Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: rev.to_string(),
+            sha256: sha256.to_string(),
+            branch: "nixpkgs-unstable".to_string(),
+            updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            channel: None,
+        }
+    }
+
+    #[test]
+    fn pin_checks_cover_every_pin_once() {
+        let mut extra = BTreeMap::new();
+        extra.insert("old".to_string(), pin("old", "sha-old"));
+        let mut packages = PackagesState::default();
+        packages.pinned.insert(
+            "nodejs".to_string(),
+            PinnedPackage {
+                version: "20.1.0".to_string(),
+                pin: pin("main", "sha-main"),
+            },
+        );
+        let targets = pins_to_verify(&pin("main", "sha-main"), &extra, &packages);
+        let names: Vec<&str> = targets.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["nixpkgs", "pin old", "package nodejs"]);
+
+        let mut calls = 0;
+        let results = check_pins(&targets, &mut |pin| {
+            calls += 1;
+            if pin.rev == "old" {
+                PinCheck::Mismatch("sha-new".to_string())
+            } else {
+                PinCheck::Ok(None)
+            }
+        });
+        assert_eq!(calls, 2);
+        assert_eq!(
+            results,
+            vec![
+                PinCheck::Ok(None),
+                PinCheck::Mismatch("sha-new".to_string()),
+                PinCheck::Ok(None)
+            ]
+        );
+    }
+
+    #[test]
+    fn quick_check_heads_the_channel_tarball_for_channel_pins() {
+        let tarball = "https://releases.nixos.org/nixos/24.11/nixos-24.11.1/nixexprs.tar.xz";
+        let mut channel_pin = pin("abc", "sha-channel");
+        channel_pin.channel = Some(PinChannel {
+            name: "nixos-24.11".to_string(),
+            tarball: tarball.to_string(),
+        });
+        let rewrites = vec![UrlRewrite {
+            from: "https://releases.nixos.org/".to_string(),
+            to: "https://mirror.internal/releases/".to_string(),
+        }];
+        let mut headed = Vec::new();
+        let mut prefetched = Vec::new();
+
+        let quick = check_pin(
+            &channel_pin,
+            true,
+            &rewrites,
+            &mut |url| {
+                headed.push(url.to_string());
+                Ok(Some(42))
+            },
+            &mut |_| unreachable!("quick mode only sends a HEAD request"),
+        );
+        let full = check_pin(
+            &channel_pin,
+            false,
+            &rewrites,
+            &mut |_| unreachable!("full mode prefetches the tarball"),
+            &mut |url| {
+                prefetched.push(url.to_string());
+                Ok("sha-channel".to_string())
+            },
+        );
+
+        let expected = "https://mirror.internal/releases/nixos/24.11/nixos-24.11.1/nixexprs.tar.xz";
+        assert_eq!(quick, PinCheck::Ok(Some(42)));
+        assert_eq!(full, PinCheck::Ok(None));
+        assert_eq!(headed, vec![expected.to_string()]);
+        assert_eq!(prefetched, vec![expected.to_string()]);
+    }
+}
@@ -0,0 +1,345 @@
+use crate::commands::CommandContext;
+use crate::indexops::aliases::warn_alias_packages;
+use crate::indexops::missing::report_preset_package_issues;
+use crate::nixops::adopt::{
+    deprioritize_nix_env_elements, list_nix_env_elements, plan_adoption, report_adoption,
+    uninstall_nix_env_elements,
+};
+use crate::nixops::drift::diff_installed_profile;
+use crate::nixops::generations::profile_installed_packages;
+use crate::nixops::{eval_nix_contents, sync_and_install_profile};
+use crate::{
+    apply_profile_changes, apply_project_changes, build_initial_profile_state,
+    build_initial_project_state, build_profile_nix, build_project_nix, diff_profile, diff_project,
+    format_mica_nix, index_db_path, init_profile_state, init_project_state, load_all_presets,
+    load_config_or_default, load_profile_state, load_project_state, profile_nix_drift,
+    profile_state_path, project_selected_packages, registry_path, update_profile_state_from_nix,
+    update_project_modified, update_project_state_from_nix, Cli, CliError, DiffTarget,
+};
+use clap::CommandFactory;
+use clap_complete::{generate, Shell};
+use mica_core::config::ProfileBackend;
+use mica_core::export::{render_export, ExportFooter};
+use mica_core::migrate::migrate_project_nix;
+use mica_core::nixparse::parse_nix_file;
+use mica_core::registry::ProjectRegistry;
+use mica_core::state::ManifestState;
+use mica_index::generate::open_db;
+use std::io;
+use std::io::Write;
+use std::path::Path;
+
+pub(crate) fn init(
+    ctx: &CommandContext,
+    repo: Option<String>,
+    adopt: bool,
+) -> Result<(), CliError> {
+    if adopt {
+        if !ctx.global {
+            return Err(CliError::AdoptRequiresGlobal);
+        }
+        return init_adopted_profile(ctx, repo);
+    }
+    if ctx.global {
+        if ctx.dry_run {
+            let state = build_initial_profile_state(ctx.git, ctx.clock, repo)?;
+            ctx.output.info(format!(
+                "dry-run: would initialize {}",
+                profile_state_path()?.display()
+            ));
+            if ctx.output.verbose {
+                ctx.output.info(build_profile_nix(ctx.clock, &state)?);
+            }
+        } else {
+            init_profile_state(ctx.git, ctx.clock, repo)?;
+            let state = load_profile_state()?;
+            sync_and_install_profile(ctx.output, ctx.clock, &state)?;
+        }
+    } else {
+        let paths = ctx.project_paths()?;
+        if ctx.dry_run {
+            if paths.nix_path.exists() {
+                return Err(CliError::StateExists(paths.nix_path.to_path_buf()));
+            }
+            let state = build_initial_project_state(ctx.git, ctx.clock, repo)?;
+            ctx.output.info(format!(
+                "dry-run: would initialize {}",
+                paths.nix_path.display()
+            ));
+            if ctx.output.verbose {
+                ctx.output
+                    .info(build_project_nix(ctx.clock, paths, &state)?);
+            }
+        } else {
+            init_project_state(ctx.git, ctx.clock, paths, repo)?;
+        }
+    }
+    Ok(())
+}
+
+fn init_adopted_profile(ctx: &CommandContext, repo: Option<String>) -> Result<(), CliError> {
+    let index_path = index_db_path()?;
+    if !index_path.exists() {
+        return Err(CliError::MissingIndex(index_path));
+    }
+    let conn = open_db(&index_path)?;
+    let mut state = build_initial_profile_state(ctx.git, ctx.clock, repo)?;
+    let elements = list_nix_env_elements()?;
+    let plan = plan_adoption(&conn, &elements)?;
+    state.packages.added = plan.attrs();
+
+    if ctx.dry_run {
+        ctx.output.info(format!(
+            "dry-run: would initialize {} with {} package(s) from nix-env:",
+            profile_state_path()?.display(),
+            plan.adopted.len()
+        ));
+        report_adoption(ctx.output, &plan);
+        return Ok(());
+    }
+    state
+        .save_to_path(&profile_state_path()?)
+        .map_err(CliError::State)?;
+    ctx.output.info(format!(
+        "adopted {} package(s) from nix-env:",
+        plan.adopted.len()
+    ));
+    report_adoption(ctx.output, &plan);
+    let names: Vec<String> = if load_config_or_default()?.profile.backend == ProfileBackend::NixEnv
+    {
+        plan.adopted.iter().map(|(name, _)| name.clone()).collect()
+    } else {
+        Vec::new()
+    };
+    // Install the mica profile before removing anything, so a failed eval or
+    // install leaves the existing nix-env packages in place.
+    deprioritize_nix_env_elements(&names)?;
+    if let Err(err) = sync_and_install_profile(ctx.output, ctx.clock, &state) {
+        if !names.is_empty() {
+            ctx.output.warn(
+                "warning: install failed, existing nix-env packages were kept, run `nix-env --rollback` to restore their priority",
+            );
+        }
+        return Err(err);
+    }
+    if let Err(err) = uninstall_nix_env_elements(&names) {
+        ctx.output.warn(format!(
+            "warning: mica profile installed but old packages were not removed ({}), remove them with `nix-env -e {}`",
+            err,
+            names.join(" ")
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn export(ctx: &CommandContext, footer: bool, check: bool) -> Result<(), CliError> {
+    let (formatted, footer) = if ctx.global {
+        let state = load_profile_state()?;
+        let formatted = format_mica_nix(&build_profile_nix(ctx.clock, &state)?);
+        let footer = (footer || check)
+            .then(|| ExportFooter::new(&state, &state.pin))
+            .transpose()?;
+        (formatted, footer)
+    } else {
+        let paths = ctx.project_paths()?;
+        let state = load_project_state(ctx.clock, paths)?;
+        let formatted = format_mica_nix(&build_project_nix(ctx.clock, paths, &state)?);
+        let footer = (footer || check)
+            .then(|| ExportFooter::new(&state, &state.pin))
+            .transpose()?;
+        (formatted, footer)
+    };
+    let rendered = match footer {
+        Some(footer) => render_export(&formatted, &footer, check),
+        None => formatted,
+    };
+    io::stdout()
+        .write_all(rendered.as_bytes())
+        .map_err(CliError::ExportIo)?;
+    Ok(())
+}
+
+pub(crate) fn sync(ctx: &CommandContext, from_nix: bool) -> Result<(), CliError> {
+    if ctx.global {
+        let mut state = load_profile_state()?;
+        if from_nix {
+            update_profile_state_from_nix(ctx.clock, &mut state)?;
+        }
+        apply_profile_changes(ctx.output, ctx.clock, ctx.dry_run, &state)?;
+    } else {
+        let paths = ctx.project_paths()?;
+        let mut state = load_project_state(ctx.clock, paths)?;
+        if from_nix {
+            update_project_state_from_nix(ctx.clock, paths, &mut state)?;
+        }
+        apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn eval(ctx: &CommandContext) -> Result<(), CliError> {
+    if ctx.global {
+        let state = load_profile_state()?;
+        let generated = build_profile_nix(ctx.clock, &state)?;
+        warn_alias_packages(ctx.output, &profile_installed_packages(&state)?);
+        eval_nix_contents(ctx.output, &generated)?;
+    } else {
+        let paths = ctx.project_paths()?;
+        let state = load_project_state(ctx.clock, paths)?;
+        let generated = build_project_nix(ctx.clock, paths, &state)?;
+        warn_alias_packages(
+            ctx.output,
+            &project_selected_packages(ctx.clock, paths, &state)?,
+        );
+        report_preset_package_issues(ctx.output, &state, &load_all_presets()?);
+        eval_nix_contents(ctx.output, &generated)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn describe(
+    ctx: &CommandContext,
+    description: Option<String>,
+    owner: Option<String>,
+    clear: bool,
+) -> Result<(), CliError> {
+    if ctx.global {
+        ctx.output
+            .info("describe is only supported in project mode");
+        return Ok(());
+    }
+    let paths = ctx.project_paths()?;
+    let mut state = load_project_state(ctx.clock, paths)?;
+    if clear {
+        state.manifest = ManifestState::default();
+    } else if description.is_some() || owner.is_some() {
+        if let Some(description) = description {
+            state.manifest.description = non_empty(&description);
+        }
+        if let Some(owner) = owner {
+            state.manifest.owner = non_empty(&owner);
+        }
+    } else {
+        if state.manifest.is_default() {
+            ctx.output.info("no description set");
+        }
+        if let Some(description) = &state.manifest.description {
+            ctx.output.info(format!("description: {}", description));
+        }
+        if let Some(owner) = &state.manifest.owner {
+            ctx.output.info(format!("owner: {}", owner));
+        }
+        return Ok(());
+    }
+    update_project_modified(ctx.clock, &mut state);
+    apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+    Ok(())
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!value.is_empty()).then_some(value)
+}
+
+pub(crate) fn diff(ctx: &CommandContext, target: DiffTarget) -> Result<(), CliError> {
+    if !ctx.global {
+        if target != DiffTarget::Nix {
+            ctx.output
+                .info("projects have no installed profile, comparing state and nix file");
+        }
+        let paths = ctx.project_paths()?;
+        let state = load_project_state(ctx.clock, paths)?;
+        return diff_project(ctx.output, ctx.clock, paths, &state);
+    }
+    let state = load_profile_state()?;
+    match target {
+        DiffTarget::Nix => diff_profile(ctx.output, ctx.clock, &state)?,
+        DiffTarget::Installed => {
+            diff_installed_profile(ctx.output, &state)?;
+        }
+        DiffTarget::All => {
+            let (pins_changed, paths_changed) = profile_nix_drift(ctx.clock, &state)?;
+            let changed: Vec<&str> = [("pins", pins_changed), ("paths", paths_changed)]
+                .into_iter()
+                .filter(|(_, changed)| *changed)
+                .map(|(name, _)| name)
+                .collect();
+            if changed.is_empty() {
+                ctx.output.info("state -> profile.nix: ok");
+            } else {
+                ctx.output.info(format!(
+                    "state -> profile.nix: changed ({}), run `mica --global sync`",
+                    changed.join(", ")
+                ));
+            }
+            diff_installed_profile(ctx.output, &state)?;
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn completion(shell: Shell) -> Result<(), CliError> {
+    let mut cmd = Cli::command();
+    generate(shell, &mut cmd, "mica", &mut io::stdout());
+    Ok(())
+}
+
+pub(crate) fn migrate(ctx: &CommandContext, all_projects: bool) -> Result<(), CliError> {
+    if !all_projects {
+        if ctx.global {
+            ctx.output
+                .info("the global profile has no layout to migrate, use --all-projects");
+            return Ok(());
+        }
+        return migrate_nix_file(ctx, &ctx.project_paths()?.nix_path);
+    }
+    let registry = ProjectRegistry::load_from_path(&registry_path()?)?;
+    if registry.projects.is_empty() {
+        ctx.output.info("no known projects to migrate");
+    }
+    for path in registry.projects.keys() {
+        if !path.exists() {
+            ctx.output.verbose(format!(
+                "skipping {}: file no longer exists",
+                path.display()
+            ));
+            continue;
+        }
+        if let Err(err) = migrate_nix_file(ctx, path) {
+            ctx.output
+                .warn(format!("failed to migrate {}: {}", path.display(), err));
+        }
+    }
+    Ok(())
+}
+
+fn migrate_nix_file(ctx: &CommandContext, path: &Path) -> Result<(), CliError> {
+    let content = std::fs::read_to_string(path).map_err(CliError::ReadNix)?;
+    let migration = migrate_project_nix(&content)?;
+    if !migration.changed() {
+        ctx.output.info(format!(
+            "{} is already at layout {}",
+            path.display(),
+            migration.to
+        ));
+        return Ok(());
+    }
+    parse_nix_file(&migration.content).map_err(CliError::NixParse)?;
+    if ctx.dry_run {
+        ctx.output.info(format!(
+            "dry-run: would migrate {} from layout {} to {}",
+            path.display(),
+            migration.from,
+            migration.to
+        ));
+        return Ok(());
+    }
+    std::fs::write(path, &migration.content).map_err(CliError::WriteNix)?;
+    ctx.output.info(format!(
+        "migrated {} from layout {} to {}",
+        path.display(),
+        migration.from,
+        migration.to
+    ));
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::commands::{CliCommand, CommandContext};
+use crate::{
+    apply_project_changes, ensure_state_writable, load_project_state, CliError, StateCommand,
+    StateFormat,
+};
+use mica_core::state::ProjectState;
+use std::io::{Read, Write};
+use std::path::Path;
+
+impl CliCommand for StateCommand {
+    fn run(self, ctx: &CommandContext) -> Result<(), CliError> {
+        if ctx.global {
+            ctx.output
+                .info("state dump/load is only supported in project mode");
+            return Ok(());
+        }
+        match self {
+            StateCommand::Dump { file, format } => dump(ctx, &file, format),
+            StateCommand::Load { file } => load(ctx, &file),
+        }
+    }
+}
+
+pub(crate) fn render_state(state: &ProjectState, format: StateFormat) -> Result<String, CliError> {
+    match format {
+        StateFormat::Toml => {
+            toml::to_string_pretty(state).map_err(|err| CliError::InvalidStateDump(err.to_string()))
+        }
+        StateFormat::Json => serde_json::to_string_pretty(state)
+            .map(|json| format!("{}\n", json))
+            .map_err(|err| CliError::InvalidStateDump(err.to_string())),
+    }
+}
+
+pub(crate) fn parse_state(content: &str) -> Result<ProjectState, CliError> {
+    if content.trim_start().starts_with('{') {
+        serde_json::from_str(content).map_err(|err| CliError::InvalidStateDump(err.to_string()))
+    } else {
+        toml::from_str(content).map_err(|err| CliError::InvalidStateDump(err.to_string()))
+    }
+}
+
+fn dump(ctx: &CommandContext, file: &Path, format: StateFormat) -> Result<(), CliError> {
+    let state = load_project_state(ctx.clock, ctx.project_paths()?)?;
+    let rendered = render_state(&state, format)?;
+    if file == Path::new("-") {
+        std::io::stdout()
+            .write_all(rendered.as_bytes())
+            .map_err(CliError::StateDumpIo)?;
+    } else {
+        std::fs::write(file, rendered).map_err(CliError::StateDumpIo)?;
+        ctx.output
+            .status(format!("wrote state to {}", file.display()));
+    }
+    Ok(())
+}
+
+fn load(ctx: &CommandContext, file: &Path) -> Result<(), CliError> {
+    let mut content = String::new();
+    if file == Path::new("-") {
+        std::io::stdin()
+            .read_to_string(&mut content)
+            .map_err(CliError::StateDumpIo)?;
+    } else {
+        content = std::fs::read_to_string(file).map_err(CliError::StateDumpIo)?;
+    }
+    let state = parse_state(&content)?;
+    let paths = ctx.project_paths()?;
+    ensure_state_writable(&state.mica, &paths.nix_path)?;
+    apply_project_changes(ctx.output, ctx.clock, paths, ctx.dry_run, &state)?;
+    if !ctx.dry_run {
+        ctx.output
+            .info(format!("regenerated {}", paths.nix_path.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::commands::state::{parse_state, render_state};
+    use crate::StateFormat;
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use mica_core::state::{MicaMetadata, Pin, ProjectState};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn state_dumps_round_trip_in_both_formats() {
+        let mut state = ProjectState {
+            mica: MicaMetadata {
+                version: "0.1.0".to_string(),
+                created: Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap(),
+                modified: Utc.with_ymd_and_hms(2026, 1, 2, 0, 0, 0).unwrap(),
+            },
+            pin: Pin {
+                name: None,
+                url: "https://github.com/NixOS/nixpkgs".to_string(),
+                rev: "abc".to_string(),
+                sha256: "sha".to_string(),
+                branch: "nixpkgs-unstable".to_string(),
+                updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+                channel: None,
+            },
+            pins: BTreeMap::new(),
+            presets: Default::default(),
+            packages: Default::default(),
+            env: BTreeMap::new(),
+            shell: Default::default(),
+            nix: Default::default(),
+            extends: None,
+            build_env: Default::default(),
+            manifest: Default::default(),
+        };
+        state.packages.added = vec!["jq".to_string(), "ripgrep".to_string()];
+        state
+            .env
+            .insert("RUST_LOG".to_string(), "debug".to_string());
+
+        for format in [StateFormat::Toml, StateFormat::Json] {
+            let rendered = render_state(&state, format).unwrap();
+            assert_eq!(parse_state(&rendered).unwrap(), state);
+        }
+        assert!(parse_state("pin = 3").is_err());
+    }
+}
@@ -0,0 +1,4433 @@
+use crate::clock::Clock;
+use crate::commands::CommandContext;
+use crate::github::CommitSummary;
+use crate::indexops::compat::check_index_cache;
+use crate::indexops::fallback::{open_fallback_index, spawn_full_index_build};
+use crate::indexops::missing::{index_matches_pin, missing_packages};
+use crate::indexops::remote::{
+    fetch_remote_index_with_progress, primary_pin_rev, should_check_remote_index,
+    try_fetch_remote_index_for_pins, write_index_check_time,
+};
+use crate::indexops::{
+    collect_index_pins, collect_index_pins_profile, flagged_packages, index_coverage_issues,
+    index_has_descriptions, meta_has_key, normalize_attr_path, pin_source_label,
+    preferred_priority, rebuild_index_from_pins_with_spinner, IndexPin,
+};
+use crate::nixops::ephemeral::{
+    ephemeral_shell_expression, resolve_try_packages, run_ephemeral_shell,
+};
+use crate::nixops::storepath::preview_store_paths;
+use crate::nixops::warm::{run_warm_cache, warm_cache_command};
+use crate::nixops::{
+    fetch_nix_sha256, fetch_nix_sha256_cancellable, sync_and_install_profile, url_rewrites,
+};
+use crate::{
+    add_extra_pin, build_project_nix, check_pin_name, config_path, current_host, ensure_config_dir,
+    format_mica_nix, index_db_path, init_profile_state, init_project_state, load_all_presets,
+    load_config_or_default, load_profile_state, load_project_state, profile_nix_path,
+    profile_state_path, resolve_channel_pin, run_with_spinner, save_profile_state,
+    save_project_state, sync_profile_nix, to_index_search_mode, tui, update_profile_modified,
+    update_profile_state_from_nix, update_project_modified, update_project_state_from_nix,
+    versions_db_path, AddPinRequest, CliError, Output, ProjectPaths,
+};
+use chrono::{DateTime, Utc};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use mica_core::config::Config;
+use mica_core::diff::{diff_lines, diff_lines_changes_only};
+use mica_core::merge::{merge_states, plan_merge, MergeChoice, SectionMerge};
+use mica_core::nixgen::generate_profile_nix;
+use mica_core::preset::merge_profile_presets;
+use mica_core::state::{
+    BuildEnvState, GlobalProfileState, MicaMetadata, Pin, PinChannel, PinnedPackage, ProjectState,
+    VersionCheck, NIX_EXPR_PREFIX,
+};
+use mica_index::generate::{
+    find_binary_collisions, get_meta, get_package, list_packages, open_db,
+    search_packages_with_mode, PackageInfo,
+};
+use mica_index::versions::{
+    latest_version_for_source, list_versions, open_versions_db, version_for_commit,
+};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+pub(crate) fn run_tui(ctx: &CommandContext) -> Result<(), CliError> {
+    if ctx.global {
+        run_tui_global(ctx)
+    } else {
+        run_tui_project(ctx.project_paths()?, ctx)
+    }
+}
+
+pub(crate) fn snapshot(
+    screen: Option<tui::snapshot::Screen>,
+    width: Option<u16>,
+    height: Option<u16>,
+) -> Result<(), CliError> {
+    let screens = match screen {
+        Some(screen) => vec![screen],
+        None => tui::snapshot::SCREENS.to_vec(),
+    };
+    let mut stdout = std::io::stdout();
+    for screen in screens {
+        let (default_width, default_height) = screen.size();
+        let rendered = tui::snapshot::render_screen(
+            screen,
+            width.unwrap_or(default_width),
+            height.unwrap_or(default_height),
+        )
+        .map_err(CliError::WriteNix)?;
+        writeln!(stdout, "# {}", screen.name()).map_err(CliError::WriteNix)?;
+        stdout
+            .write_all(rendered.as_bytes())
+            .map_err(CliError::WriteNix)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn run_tui_project(paths: &ProjectPaths, ctx: &CommandContext) -> Result<(), CliError> {
+    use crossterm::terminal::{
+        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+    };
+    use ratatui::backend::CrosstermBackend;
+    use ratatui::Terminal;
+    use tui::app::App;
+
+    let output = ctx.output;
+    let project_path = &paths.nix_path;
+    if !project_path.exists() {
+        output.status(format!(
+            "default.nix missing at {}, initializing",
+            project_path.display()
+        ));
+        init_project_state(ctx.git, ctx.clock, paths, None)?;
+    }
+    let mut state = load_project_state(ctx.clock, paths)?;
+    let config = load_config_or_default().ok();
+    let index_path = index_db_path()?;
+    let pins = collect_index_pins(&state);
+    let incompatible = index_path
+        .exists()
+        .then(|| check_index_cache(&index_path))
+        .flatten();
+    let fallback = !index_path.exists() || incompatible.is_some();
+    let (mut conn, meta) = if fallback {
+        if incompatible.is_none() {
+            output.status(format!(
+                "index missing at {}, starting with the bundled package list while the full index builds",
+                index_path.display()
+            ));
+        }
+        let conn = open_fallback_index()?;
+        let meta = get_meta(&conn).unwrap_or_default();
+        (conn, meta)
+    } else {
+        open_tui_index(ctx, config.as_ref(), &index_path, &pins)?
+    };
+
+    let presets = load_tui_presets(&state.pins)?;
+    let mut app = App::new(Vec::new(), presets);
+    app.mode = tui::app::AppMode::Project;
+    app.project_dir = Some(paths.root_dir.to_string_lossy().to_string());
+    if let Some(config) = &config {
+        apply_columns_from_config(&mut app, config);
+        apply_search_mode_from_config(&mut app, config);
+        apply_filter_chips_from_config(&mut app, config);
+        apply_show_details_from_config(&mut app, config);
+    }
+    app.index_info = index_info_with_pin_fallback(index_info_from_meta(meta), &pins);
+    if let Some(reason) = incompatible {
+        warn_incompatible_index(&mut app, &reason);
+    } else if fallback {
+        spawn_full_index_build(&mut app, ctx.clock, config.as_ref(), &index_path, &pins);
+    } else if let Some(config) = &config {
+        spawn_index_refresh(
+            &mut app,
+            ctx.clock,
+            config,
+            &index_path,
+            primary_pin_rev(&pins),
+        )?;
+    }
+    apply_state_to_app(&mut app, &state);
+    warn_read_only_state(&mut app, &state.mica);
+    warn_index_coverage_toast(&mut app, &conn, &pins);
+    update_search_results(&conn, &mut app)?;
+    app.refresh_preset_filter();
+
+    enable_raw_mode().map_err(CliError::WriteNix)?;
+    let mut stdout = std::io::stdout();
+    crossterm::execute!(stdout, EnterAlternateScreen).map_err(CliError::WriteNix)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend).map_err(CliError::WriteNix)?;
+
+    let result = run_tui_loop_project(
+        &mut terminal,
+        &mut app,
+        &mut state,
+        paths,
+        &index_path,
+        &mut conn,
+        ctx,
+    );
+
+    disable_raw_mode().map_err(CliError::WriteNix)?;
+    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen)
+        .map_err(CliError::WriteNix)?;
+    terminal.show_cursor().map_err(CliError::WriteNix)?;
+    drop_pending_pins(&mut app, output);
+    result
+}
+
+pub(crate) fn run_tui_global(ctx: &CommandContext) -> Result<(), CliError> {
+    use crossterm::terminal::{
+        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+    };
+    use ratatui::backend::CrosstermBackend;
+    use ratatui::Terminal;
+    use tui::app::App;
+
+    let output = ctx.output;
+    let profile_state = profile_state_path()?;
+    if !profile_state.exists() {
+        output.status(format!(
+            "global profile missing at {}, initializing",
+            profile_state.display()
+        ));
+        init_profile_state(ctx.git, ctx.clock, None)?;
+        let state = load_profile_state()?;
+        sync_and_install_profile(output, ctx.clock, &state)?;
+    }
+    let mut state = load_profile_state()?;
+    let profile_nix = profile_nix_path()?;
+    if !profile_nix.exists() {
+        sync_profile_nix(ctx.clock, &state)?;
+    }
+
+    let config = load_config_or_default().ok();
+    let index_path = index_db_path()?;
+    let pins = collect_index_pins_profile(&state);
+    let incompatible = index_path
+        .exists()
+        .then(|| check_index_cache(&index_path))
+        .flatten();
+    let fallback = !index_path.exists() || incompatible.is_some();
+    let (mut conn, meta) = if fallback {
+        if incompatible.is_none() {
+            output.status(format!(
+                "index missing at {}, starting with the bundled package list while the full index builds",
+                index_path.display()
+            ));
+        }
+        let conn = open_fallback_index()?;
+        let meta = get_meta(&conn).unwrap_or_default();
+        (conn, meta)
+    } else {
+        open_tui_index(ctx, config.as_ref(), &index_path, &pins)?
+    };
+
+    let presets = load_tui_presets(&BTreeMap::new())?;
+    let mut app = App::new(Vec::new(), presets);
+    app.mode = tui::app::AppMode::Global;
+    if let Some(config) = &config {
+        apply_columns_from_config(&mut app, config);
+        apply_search_mode_from_config(&mut app, config);
+        apply_filter_chips_from_config(&mut app, config);
+        apply_show_details_from_config(&mut app, config);
+    }
+    app.index_info = index_info_with_pin_fallback(index_info_from_meta(meta), &pins);
+    if let Some(reason) = incompatible {
+        warn_incompatible_index(&mut app, &reason);
+    } else if fallback {
+        spawn_full_index_build(&mut app, ctx.clock, config.as_ref(), &index_path, &pins);
+    } else if let Some(config) = &config {
+        spawn_index_refresh(
+            &mut app,
+            ctx.clock,
+            config,
+            &index_path,
+            primary_pin_rev(&pins),
+        )?;
+    }
+    apply_profile_state_to_app(&mut app, &state);
+    warn_read_only_state(&mut app, &state.mica);
+    warn_index_coverage_toast(&mut app, &conn, &pins);
+    update_search_results(&conn, &mut app)?;
+    app.refresh_preset_filter();
+
+    enable_raw_mode().map_err(CliError::WriteNix)?;
+    let mut stdout = std::io::stdout();
+    crossterm::execute!(stdout, EnterAlternateScreen).map_err(CliError::WriteNix)?;
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend).map_err(CliError::WriteNix)?;
+
+    let result = run_tui_loop_global(
+        &mut terminal,
+        &mut app,
+        &mut state,
+        &index_path,
+        &mut conn,
+        ctx,
+    );
+
+    disable_raw_mode().map_err(CliError::WriteNix)?;
+    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen)
+        .map_err(CliError::WriteNix)?;
+    terminal.show_cursor().map_err(CliError::WriteNix)?;
+    drop_pending_pins(&mut app, output);
+    result
+}
+
+const COMMIT_PICKER_LIMIT: usize = 30;
+
+pub(crate) fn run_tui_loop_project(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &mut ProjectState,
+    paths: &ProjectPaths,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+) -> Result<(), CliError> {
+    use crossterm::event::{self, Event};
+
+    loop {
+        app.clear_expired_toast();
+        terminal
+            .draw(|frame| tui::ui::render(frame, app))
+            .map_err(CliError::WriteNix)?;
+
+        if event::poll(Duration::from_millis(200)).map_err(CliError::WriteNix)? {
+            if let Event::Key(key) = event::read().map_err(CliError::WriteNix)? {
+                if app.overlay.is_some() {
+                    if let Err(err) =
+                        handle_overlay_key(key, terminal, app, state, paths, index_path, conn, ctx)
+                    {
+                        app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+                    }
+                } else if let Err(err) =
+                    handle_main_key(key, terminal, app, state, paths, index_path, conn, ctx)
+                {
+                    app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+                }
+            }
+        }
+
+        if poll_jobs(app, ctx.clock) {
+            let pins = collect_index_pins(state);
+            if let Err(err) = reload_index(app, index_path, conn, &pins) {
+                app.notify(tui::app::ToastLevel::Error, err.to_string());
+            }
+        }
+        refresh_collisions(conn, app);
+
+        if app.should_quit {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn run_tui_loop_global(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &mut GlobalProfileState,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+) -> Result<(), CliError> {
+    use crossterm::event::{self, Event};
+
+    loop {
+        app.clear_expired_toast();
+        terminal
+            .draw(|frame| tui::ui::render(frame, app))
+            .map_err(CliError::WriteNix)?;
+
+        if event::poll(Duration::from_millis(200)).map_err(CliError::WriteNix)? {
+            if let Event::Key(key) = event::read().map_err(CliError::WriteNix)? {
+                if app.overlay.is_some() {
+                    if let Err(err) =
+                        handle_overlay_key_global(key, terminal, app, state, index_path, conn, ctx)
+                    {
+                        app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+                    }
+                } else if let Err(err) =
+                    handle_main_key_global(key, terminal, app, state, index_path, conn, ctx)
+                {
+                    app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+                }
+            }
+        }
+
+        if poll_jobs(app, ctx.clock) {
+            let pins = collect_index_pins_profile(state);
+            if let Err(err) = reload_index(app, index_path, conn, &pins) {
+                app.notify(tui::app::ToastLevel::Error, err.to_string());
+            }
+        }
+        refresh_collisions(conn, app);
+
+        if app.should_quit {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+fn open_tui_index(
+    ctx: &CommandContext,
+    config: Option<&Config>,
+    index_path: &Path,
+    pins: &[IndexPin],
+) -> Result<(rusqlite::Connection, Vec<(String, String)>), CliError> {
+    let output = ctx.output;
+    let mut conn = open_db(index_path)?;
+    let mut meta = get_meta(&conn).unwrap_or_default();
+    let mut has_meta = meta_has_key(&meta, "index_meta");
+    if has_meta && !index_has_descriptions(&conn)? {
+        has_meta = false;
+    }
+    if !has_meta {
+        let fetched = try_fetch_remote_index_for_pins(output, ctx.clock, config, index_path, pins)?;
+        if fetched {
+            conn = open_db(index_path)?;
+            meta = get_meta(&conn).unwrap_or_default();
+            has_meta = meta_has_key(&meta, "index_meta");
+            if has_meta && !index_has_descriptions(&conn)? {
+                has_meta = false;
+            }
+        }
+        if !has_meta {
+            output.status("index missing metadata, rebuilding from nix-env -qaP --json --meta");
+            let count =
+                rebuild_index_from_pins_with_spinner(ctx.git, ctx.clock, output, index_path, pins)?;
+            output.status(format!("index ready, {} packages", count));
+            conn = open_db(index_path)?;
+            meta = get_meta(&conn).unwrap_or_default();
+        }
+    }
+    Ok((conn, meta))
+}
+
+fn spawn_index_refresh(
+    app: &mut tui::app::App,
+    clock: &dyn Clock,
+    config: &Config,
+    index_path: &Path,
+    commit: Option<&str>,
+) -> Result<(), CliError> {
+    if !should_check_remote_index(clock, config)? {
+        return Ok(());
+    }
+    let checked_at = clock.now();
+    let remote_url = config.index.remote_url.clone();
+    let index_path = index_path.to_path_buf();
+    let commit = commit.map(str::to_string);
+    app.jobs.spawn(
+        tui::app::JobKind::IndexRefresh,
+        "Refreshing remote index",
+        move |job| {
+            let fetched = fetch_remote_index_with_progress(
+                &remote_url,
+                &index_path,
+                commit.as_deref(),
+                &mut |done, total| {
+                    job.progress(done, total);
+                    !job.is_cancelled()
+                },
+            );
+            let _ = write_index_check_time(checked_at);
+            match fetched {
+                Ok(true) => Ok(Some("Index refreshed".to_string())),
+                Ok(false) => Ok(None),
+                Err(err) => Err(err.to_string()),
+            }
+        },
+    );
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+fn update_project_base_pin(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &mut ProjectState,
+    paths: &ProjectPaths,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+    rev: Option<String>,
+) -> Result<(), CliError> {
+    let resolved = with_tui_suspended(terminal, || resolve_base_pin_update(ctx, &state.pin, rev))?;
+    let mut ours = state.clone();
+    apply_app_to_state(&mut ours, app);
+    ours.pin.rev = resolved.rev;
+    ours.pin.sha256 = resolved.sha256;
+    ours.pin.channel = resolved.channel;
+    ours.pin.updated = ctx.clock.now().date_naive();
+    if !save_project_edit(ctx.clock, conn, paths, state, app, ours)? {
+        // The merge dialog saves the pin and refreshes the index once resolved.
+        return Ok(());
+    }
+    refresh_project_index(terminal, app, state, index_path, conn, ctx)?;
+    show_missing_after_pin_update(conn, app, &state.pin);
+    spawn_warm_cache_job(app, &paths.nix_path)
+}
+
+/// Fetches or rebuilds the index for the project's pins and reopens it.
+fn refresh_project_index(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &ProjectState,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+) -> Result<(), CliError> {
+    let output = ctx.output;
+    let pins = collect_index_pins(state);
+    with_tui_suspended(terminal, || {
+        let config = load_config_or_default().ok();
+        let fetched =
+            try_fetch_remote_index_for_pins(output, ctx.clock, config.as_ref(), index_path, &pins)?;
+        if !fetched {
+            rebuild_index_from_pins_with_spinner(ctx.git, ctx.clock, output, index_path, &pins)?;
+        }
+        Ok(())
+    })?;
+    reload_index(app, index_path, conn, &pins)
+}
+
+struct BasePinUpdate {
+    rev: String,
+    sha256: String,
+    channel: Option<PinChannel>,
+}
+
+/// Resolves a base pin update. Without an explicit revision a channel pin
+/// follows its channel; picking a revision drops the channel source.
+fn resolve_base_pin_update(
+    ctx: &CommandContext,
+    pin: &Pin,
+    rev: Option<String>,
+) -> Result<BasePinUpdate, CliError> {
+    let output = ctx.output;
+    if let (None, Some(channel)) = (&rev, &pin.channel) {
+        let resolved = run_with_spinner(output, "resolving nixos channel release", || {
+            resolve_channel_pin(ctx.git, &channel.name, None)
+        })?;
+        return Ok(BasePinUpdate {
+            rev: resolved.rev.ok_or(CliError::IncompletePin)?,
+            sha256: resolved.sha256.ok_or(CliError::IncompletePin)?,
+            channel: resolved.channel,
+        });
+    }
+    let rev = match rev {
+        Some(rev) => rev,
+        None => run_with_spinner(output, "fetching latest nixpkgs revision", || {
+            ctx.git.latest_rev(&pin.url, &pin.branch)
+        })?,
+    };
+    let sha256 = run_with_spinner(output, "prefetching nixpkgs tarball", || {
+        fetch_nix_sha256(&pin.url, &rev)
+    })?;
+    Ok(BasePinUpdate {
+        rev,
+        sha256,
+        channel: None,
+    })
+}
+
+fn update_profile_base_pin(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &mut GlobalProfileState,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+    rev: Option<String>,
+) -> Result<(), CliError> {
+    let output = ctx.output;
+    with_tui_suspended(terminal, || {
+        let resolved = resolve_base_pin_update(ctx, &state.pin, rev)?;
+        state.pin.rev = resolved.rev;
+        state.pin.sha256 = resolved.sha256;
+        state.pin.channel = resolved.channel;
+        state.pin.updated = ctx.clock.now().date_naive();
+        update_profile_modified(ctx.clock, state);
+        save_profile_state(state)?;
+        sync_and_install_profile(output, ctx.clock, state)?;
+        let pins = collect_index_pins_profile(state);
+        let config = load_config_or_default().ok();
+        let fetched =
+            try_fetch_remote_index_for_pins(output, ctx.clock, config.as_ref(), index_path, &pins)?;
+        if !fetched {
+            rebuild_index_from_pins_with_spinner(ctx.git, ctx.clock, output, index_path, &pins)?;
+        }
+        Ok(())
+    })?;
+    reload_index(app, index_path, conn, &collect_index_pins_profile(state))?;
+    show_missing_after_pin_update(conn, app, &state.pin);
+    Ok(())
+}
+
+fn open_commit_picker(app: &mut tui::app::App, pin: &Pin, commits: Vec<CommitSummary>) {
+    if commits.is_empty() {
+        app.push_toast(
+            tui::app::ToastLevel::Info,
+            format!("No recent commits found on {}", pin.branch),
+        );
+        return;
+    }
+    let cursor = commits
+        .iter()
+        .position(|commit| commit.rev == pin.rev)
+        .unwrap_or(0);
+    app.overlay = Some(tui::app::Overlay::CommitPicker(
+        tui::app::CommitPickerState {
+            entries: commits
+                .into_iter()
+                .map(|commit| tui::app::CommitPickerEntry {
+                    rev: commit.rev,
+                    date: commit.date,
+                    message: commit.message,
+                })
+                .collect(),
+            cursor,
+            branch: pin.branch.clone(),
+            current: pin.rev.clone(),
+        },
+    ));
+}
+
+pub(crate) fn handle_commit_picker_key(
+    key: KeyEvent,
+    app: &mut tui::app::App,
+    mut picker: tui::app::CommitPickerState,
+) -> Option<String> {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => return None,
+        KeyCode::Up => picker.cursor = picker.cursor.saturating_sub(1),
+        KeyCode::Down => {
+            picker.cursor = (picker.cursor + 1).min(picker.entries.len().saturating_sub(1))
+        }
+        KeyCode::Enter => {
+            if let Some(entry) = picker.entries.get(picker.cursor) {
+                if entry.rev != picker.current {
+                    return Some(entry.rev.clone());
+                }
+                app.push_toast(tui::app::ToastLevel::Info, "Already pinned to that commit");
+            }
+        }
+        _ => {}
+    }
+    app.overlay = Some(tui::app::Overlay::CommitPicker(picker));
+    None
+}
+
+fn spawn_warm_cache_job(app: &mut tui::app::App, nix_path: &Path) -> Result<(), CliError> {
+    let hooks = load_config_or_default()?.hooks;
+    let Some(command) = warm_cache_command(&hooks, nix_path) else {
+        return Ok(());
+    };
+    app.jobs.spawn(
+        tui::app::JobKind::WarmCache,
+        "Warming binary cache",
+        move |job| {
+            run_warm_cache(command, &|| job.is_cancelled()).map_err(|err| err.to_string())?;
+            Ok(Some("Binary cache warmed".to_string()))
+        },
+    );
+    Ok(())
+}
+
+fn poll_jobs(app: &mut tui::app::App, clock: &dyn Clock) -> bool {
+    use tui::app::{JobKind, JobStatus, ToastLevel};
+
+    let mut index_refreshed = false;
+    for job in app.jobs.poll() {
+        let pending = app.pending_pins.remove(&job.id);
+        match job.status {
+            JobStatus::Done(Some(sha256)) if job.kind == JobKind::Prefetch => {
+                if let Some((package, entry)) = pending {
+                    let message = format!("Pinned {} to {}", package, entry.version);
+                    finish_version_selection(app, &package, entry, sha256, clock.now());
+                    app.notify(ToastLevel::Info, message);
+                }
+            }
+            JobStatus::Done(Some(message)) => {
+                index_refreshed |= job.kind == JobKind::IndexRefresh;
+                app.notify(ToastLevel::Info, message);
+            }
+            JobStatus::Failed(err) => app.notify(
+                ToastLevel::Warning,
+                format!("{} failed: {}", job.label, err),
+            ),
+            JobStatus::Done(None) | JobStatus::Running | JobStatus::Cancelled => {}
+        }
+    }
+    let jobs = &app.jobs;
+    app.pending_pins.retain(|id, _| jobs.is_job_running(*id));
+    index_refreshed
+}
+
+fn reload_index(
+    app: &mut tui::app::App,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    pins: &[IndexPin],
+) -> Result<(), CliError> {
+    *conn = open_db(index_path)?;
+    app.index_info = index_info_with_pin_fallback(
+        index_info_from_meta(get_meta(conn).unwrap_or_default()),
+        pins,
+    );
+    update_search_results(conn, app)
+}
+
+pub(crate) fn handle_jobs_key(key: KeyEvent, app: &mut tui::app::App) {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('J') => return,
+        KeyCode::Up => app.jobs.cursor = app.jobs.cursor.saturating_sub(1),
+        KeyCode::Down => {
+            app.jobs.cursor = (app.jobs.cursor + 1).min(app.jobs.entries.len().saturating_sub(1))
+        }
+        KeyCode::Char('c') | KeyCode::Char('x') => {
+            if let Some(job) = app.jobs.entries.get(app.jobs.cursor) {
+                let (id, label) = (job.id, job.label.clone());
+                if app.jobs.cancel(id) {
+                    app.notify(tui::app::ToastLevel::Info, format!("Cancelled {}", label));
+                }
+            }
+        }
+        _ => {}
+    }
+    app.overlay = Some(tui::app::Overlay::Jobs);
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn handle_main_key(
+    key: KeyEvent,
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &mut ProjectState,
+    paths: &ProjectPaths,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+) -> Result<(), CliError> {
+    use tui::app::{FilterKind, Focus, Overlay};
+    use tui::input::{map_key, InputAction};
+
+    let output = ctx.output;
+
+    match map_key(key) {
+        InputAction::Quit => app.should_quit = true,
+        InputAction::Help => app.overlay = Some(Overlay::Help),
+        InputAction::OpenJobs => app.overlay = Some(Overlay::Jobs),
+        InputAction::UpdatePin | InputAction::RebuildIndex | InputAction::OpenCommitPicker
+            if app.jobs.is_running(tui::app::JobKind::IndexRefresh) =>
+        {
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                "Index refresh still running, press J to view or cancel it",
+            );
+        }
+        InputAction::Toggle if app.focus == Focus::Changes => open_change_actions(app),
+        InputAction::Toggle => app.toggle_current(),
+        InputAction::AddAndPin => add_and_pick_version(app),
+        InputAction::ToggleFocus => app.toggle_focus(),
+        InputAction::Next => app.next(),
+        InputAction::Prev => app.prev(),
+        InputAction::Save => {
+            save_project_from_tui(ctx.clock, conn, paths, state, app)?;
+            warn_pending_pins(app);
+        }
+        InputAction::OpenEnv => open_env_overlay(app),
+        InputAction::OpenShell => open_shell_overlay(app),
+        InputAction::OpenBuildEnv => open_build_env_overlay(app),
+        InputAction::PreferPackage => prefer_current_package(app),
+        InputAction::EditOverride => open_override_overlay(app),
+        InputAction::TryPackage => try_current_package(terminal, app, &state.pin, output)?,
+        InputAction::PreviewStorePaths => {
+            open_store_paths_overlay(terminal, app, &state.pin, output)?
+        }
+        InputAction::ToggleBroken => {
+            app.filters.show_broken = !app.filters.show_broken;
+            update_search_results(conn, app)?;
+        }
+        InputAction::ToggleInsecure => {
+            app.filters.show_insecure = !app.filters.show_insecure;
+            update_search_results(conn, app)?;
+        }
+        InputAction::ToggleInstalled => {
+            app.filters.show_installed_only = !app.filters.show_installed_only;
+            update_search_results(conn, app)?;
+        }
+        InputAction::CyclePinFilter => cycle_pin_filter(conn, app)?,
+        InputAction::FilterChip(slot) => select_filter_chip(conn, app, slot)?,
+        InputAction::ToggleSearchMode => {
+            app.cycle_search_mode();
+            if let Err(err) = save_search_mode_to_config(&app.search_mode) {
+                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+            }
+            update_search_results(conn, app)?;
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                format!("Search mode: {}", app.search_mode_label()),
+            );
+        }
+        InputAction::ExpandDetails => toggle_details_expanded(app),
+        InputAction::ScrollDetailsUp => {
+            app.details_scroll = app.details_scroll.saturating_sub(DETAILS_SCROLL_STEP);
+        }
+        InputAction::ScrollDetailsDown => {
+            app.details_scroll = app.details_scroll.saturating_add(DETAILS_SCROLL_STEP);
+        }
+        InputAction::ToggleDetails => {
+            app.show_details = !app.show_details;
+            if let Err(err) = save_show_details_to_config(app.show_details) {
+                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+            }
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                format!(
+                    "Details panel: {}",
+                    if app.show_details { "on" } else { "off" }
+                ),
+            );
+        }
+        InputAction::EditLicenseFilter => open_filter_overlay(app, FilterKind::License),
+        InputAction::EditPlatformFilter => open_filter_overlay(app, FilterKind::Platform),
+        InputAction::GoTo => {
+            app.overlay = Some(Overlay::GoTo(tui::app::GoToState::default()));
+        }
+        InputAction::PreviewDiff => {
+            app.overlay = Some(build_diff_overlay(ctx.clock, paths, state, app)?);
+        }
+        InputAction::ShowPackageInfo => {
+            if app.focus != Focus::Packages {
+                app.push_toast(tui::app::ToastLevel::Info, "Focus packages to view info");
+            } else if let Some(overlay) = build_package_info_overlay(app, state) {
+                app.overlay = Some(overlay);
+            } else {
+                app.push_toast(tui::app::ToastLevel::Info, "No package selected");
+            }
+        }
+        InputAction::OpenVersionPicker => open_version_picker(app),
+        InputAction::UpdatePin => {
+            update_project_base_pin(terminal, app, state, paths, index_path, conn, ctx, None)?;
+        }
+        InputAction::OpenCommitPicker => {
+            let pin = state.pin.clone();
+            let commits = with_tui_suspended(terminal, || {
+                run_with_spinner(output, "fetching recent commits", || {
+                    ctx.git
+                        .recent_commits(&pin.url, &pin.branch, COMMIT_PICKER_LIMIT)
+                })
+            })?;
+            open_commit_picker(app, &pin, commits);
+        }
+        InputAction::AddPin => {
+            app.overlay = Some(tui::app::Overlay::PinEditor(tui::app::PinEditorState::new(
+                state.pin.url.clone(),
+                state.pin.branch.clone(),
+            )));
+        }
+        InputAction::TogglePresets => {
+            app.presets_collapsed = !app.presets_collapsed;
+            if app.presets_collapsed {
+                app.focus = Focus::Packages;
+            }
+        }
+        InputAction::ToggleChanges => {
+            app.changes_collapsed = !app.changes_collapsed;
+        }
+        InputAction::OpenColumns => {
+            app.overlay = Some(tui::app::Overlay::Columns(tui::app::ColumnsEditorState {
+                cursor: 0,
+            }));
+        }
+        InputAction::RebuildIndex => {
+            with_tui_suspended(terminal, || {
+                let pins = collect_index_pins(state);
+                let config = load_config_or_default().ok();
+                let fetched = try_fetch_remote_index_for_pins(
+                    output,
+                    ctx.clock,
+                    config.as_ref(),
+                    index_path,
+                    &pins,
+                )?;
+                if !fetched {
+                    rebuild_index_from_pins_with_spinner(
+                        ctx.git, ctx.clock, output, index_path, &pins,
+                    )?;
+                }
+                Ok(())
+            })?;
+            *conn = open_db(index_path)?;
+            let pins = collect_index_pins(state);
+            app.index_info = index_info_with_pin_fallback(
+                index_info_from_meta(get_meta(conn).unwrap_or_default()),
+                &pins,
+            );
+            update_search_results(conn, app)?;
+            app.push_toast(tui::app::ToastLevel::Info, "Index rebuilt");
+        }
+        InputAction::Sync => {
+            update_project_state_from_nix(ctx.clock, paths, state)?;
+            apply_state_to_app(app, state);
+            update_search_results(conn, app)?;
+            app.refresh_preset_filter();
+            app.push_toast(tui::app::ToastLevel::Info, "Reloaded from nix");
+        }
+        InputAction::Backspace => match app.focus {
+            Focus::Packages => {
+                app.query.pop();
+                update_search_results(conn, app)?;
+            }
+            Focus::Presets => {
+                app.preset_query.pop();
+                app.refresh_preset_filter();
+            }
+            Focus::Changes => {}
+        },
+        InputAction::Clear => match app.focus {
+            Focus::Packages => {
+                app.query.clear();
+                update_search_results(conn, app)?;
+            }
+            Focus::Presets => {
+                app.preset_query.clear();
+                app.refresh_preset_filter();
+            }
+            Focus::Changes => {}
+        },
+        InputAction::Insert(ch) => match app.focus {
+            Focus::Packages => {
+                app.query.push(ch);
+                update_search_results(conn, app)?;
+            }
+            Focus::Presets => {
+                app.preset_query.push(ch);
+                app.refresh_preset_filter();
+            }
+            Focus::Changes => {}
+        },
+        InputAction::None => {}
+    }
+
+    Ok(())
+}
+
+pub(crate) fn handle_main_key_global(
+    key: KeyEvent,
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &mut GlobalProfileState,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+) -> Result<(), CliError> {
+    use tui::app::{FilterKind, Focus, Overlay};
+    use tui::input::{map_key, InputAction};
+
+    let output = ctx.output;
+
+    match map_key(key) {
+        InputAction::Quit => app.should_quit = true,
+        InputAction::Help => app.overlay = Some(Overlay::Help),
+        InputAction::OpenJobs => app.overlay = Some(Overlay::Jobs),
+        InputAction::UpdatePin | InputAction::RebuildIndex | InputAction::OpenCommitPicker
+            if app.jobs.is_running(tui::app::JobKind::IndexRefresh) =>
+        {
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                "Index refresh still running, press J to view or cancel it",
+            );
+        }
+        InputAction::Toggle if app.focus == Focus::Changes => open_change_actions(app),
+        InputAction::Toggle => app.toggle_current(),
+        InputAction::AddAndPin => add_and_pick_version(app),
+        InputAction::ToggleFocus => app.toggle_focus(),
+        InputAction::Next => app.next(),
+        InputAction::Prev => app.prev(),
+        InputAction::Save => {
+            with_tui_suspended(terminal, || {
+                save_profile_tui_selection(output, ctx.clock, state, app)
+            })?;
+            push_save_toast(conn, app, "Saved and installed");
+            warn_pending_pins(app);
+        }
+        InputAction::OpenEnv => {
+            app.push_toast(tui::app::ToastLevel::Info, "Env is project-only");
+        }
+        InputAction::OpenShell => {
+            app.push_toast(tui::app::ToastLevel::Info, "Shell hook is project-only");
+        }
+        InputAction::OpenBuildEnv => {
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                "buildEnv settings are project-only",
+            );
+        }
+        InputAction::PreferPackage => {
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                "Package priorities are project-only",
+            );
+        }
+        InputAction::EditOverride => {
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                "Package overrides are project-only",
+            );
+        }
+        InputAction::TryPackage => try_current_package(terminal, app, &state.pin, output)?,
+        InputAction::PreviewStorePaths => {
+            open_store_paths_overlay(terminal, app, &state.pin, output)?
+        }
+        InputAction::ToggleBroken => {
+            app.filters.show_broken = !app.filters.show_broken;
+            update_search_results(conn, app)?;
+        }
+        InputAction::ToggleInsecure => {
+            app.filters.show_insecure = !app.filters.show_insecure;
+            update_search_results(conn, app)?;
+        }
+        InputAction::ToggleInstalled => {
+            app.filters.show_installed_only = !app.filters.show_installed_only;
+            update_search_results(conn, app)?;
+        }
+        InputAction::CyclePinFilter => cycle_pin_filter(conn, app)?,
+        InputAction::FilterChip(slot) => select_filter_chip(conn, app, slot)?,
+        InputAction::ToggleSearchMode => {
+            app.cycle_search_mode();
+            if let Err(err) = save_search_mode_to_config(&app.search_mode) {
+                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+            }
+            update_search_results(conn, app)?;
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                format!("Search mode: {}", app.search_mode_label()),
+            );
+        }
+        InputAction::ExpandDetails => toggle_details_expanded(app),
+        InputAction::ScrollDetailsUp => {
+            app.details_scroll = app.details_scroll.saturating_sub(DETAILS_SCROLL_STEP);
+        }
+        InputAction::ScrollDetailsDown => {
+            app.details_scroll = app.details_scroll.saturating_add(DETAILS_SCROLL_STEP);
+        }
+        InputAction::ToggleDetails => {
+            app.show_details = !app.show_details;
+            if let Err(err) = save_show_details_to_config(app.show_details) {
+                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+            }
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                format!(
+                    "Details panel: {}",
+                    if app.show_details { "on" } else { "off" }
+                ),
+            );
+        }
+        InputAction::EditLicenseFilter => open_filter_overlay(app, FilterKind::License),
+        InputAction::EditPlatformFilter => open_filter_overlay(app, FilterKind::Platform),
+        InputAction::GoTo => {
+            app.overlay = Some(Overlay::GoTo(tui::app::GoToState::default()));
+        }
+        InputAction::PreviewDiff => {
+            app.overlay = Some(build_diff_overlay_profile(ctx.clock, state, app)?);
+        }
+        InputAction::ShowPackageInfo => {
+            if app.focus != Focus::Packages {
+                app.push_toast(tui::app::ToastLevel::Info, "Focus packages to view info");
+            } else {
+                let pins = collect_index_pins_profile(state);
+                if let Some(overlay) = build_package_info_overlay_with_pins(app, &pins) {
+                    app.overlay = Some(overlay);
+                } else {
+                    app.push_toast(tui::app::ToastLevel::Info, "No package selected");
+                }
+            }
+        }
+        InputAction::OpenVersionPicker => open_version_picker(app),
+        InputAction::UpdatePin => {
+            update_profile_base_pin(terminal, app, state, index_path, conn, ctx, None)?;
+        }
+        InputAction::OpenCommitPicker => {
+            let pin = state.pin.clone();
+            let commits = with_tui_suspended(terminal, || {
+                run_with_spinner(output, "fetching recent commits", || {
+                    ctx.git
+                        .recent_commits(&pin.url, &pin.branch, COMMIT_PICKER_LIMIT)
+                })
+            })?;
+            open_commit_picker(app, &pin, commits);
+        }
+        InputAction::AddPin => {
+            app.push_toast(tui::app::ToastLevel::Info, "Extra pins are project-only");
+        }
+        InputAction::TogglePresets => {
+            app.presets_collapsed = !app.presets_collapsed;
+            if app.presets_collapsed {
+                app.focus = Focus::Packages;
+            }
+        }
+        InputAction::ToggleChanges => {
+            app.changes_collapsed = !app.changes_collapsed;
+        }
+        InputAction::OpenColumns => {
+            app.overlay = Some(tui::app::Overlay::Columns(tui::app::ColumnsEditorState {
+                cursor: 0,
+            }));
+        }
+        InputAction::RebuildIndex => {
+            with_tui_suspended(terminal, || {
+                let pins = collect_index_pins_profile(state);
+                let config = load_config_or_default().ok();
+                let fetched = try_fetch_remote_index_for_pins(
+                    output,
+                    ctx.clock,
+                    config.as_ref(),
+                    index_path,
+                    &pins,
+                )?;
+                if !fetched {
+                    rebuild_index_from_pins_with_spinner(
+                        ctx.git, ctx.clock, output, index_path, &pins,
+                    )?;
+                }
+                Ok(())
+            })?;
+            *conn = open_db(index_path)?;
+            let pins = collect_index_pins_profile(state);
+            app.index_info = index_info_with_pin_fallback(
+                index_info_from_meta(get_meta(conn).unwrap_or_default()),
+                &pins,
+            );
+            update_search_results(conn, app)?;
+            app.push_toast(tui::app::ToastLevel::Info, "Index rebuilt");
+        }
+        InputAction::Sync => {
+            update_profile_state_from_nix(ctx.clock, state)?;
+            apply_profile_state_to_app(app, state);
+            update_search_results(conn, app)?;
+            app.refresh_preset_filter();
+            app.push_toast(tui::app::ToastLevel::Info, "Reloaded from nix");
+        }
+        InputAction::Backspace => match app.focus {
+            Focus::Packages => {
+                app.query.pop();
+                update_search_results(conn, app)?;
+            }
+            Focus::Presets => {
+                app.preset_query.pop();
+                app.refresh_preset_filter();
+            }
+            Focus::Changes => {}
+        },
+        InputAction::Clear => match app.focus {
+            Focus::Packages => {
+                app.query.clear();
+                update_search_results(conn, app)?;
+            }
+            Focus::Presets => {
+                app.preset_query.clear();
+                app.refresh_preset_filter();
+            }
+            Focus::Changes => {}
+        },
+        InputAction::Insert(ch) => match app.focus {
+            Focus::Packages => {
+                app.query.push(ch);
+                update_search_results(conn, app)?;
+            }
+            Focus::Presets => {
+                app.preset_query.push(ch);
+                app.refresh_preset_filter();
+            }
+            Focus::Changes => {}
+        },
+        InputAction::None => {}
+    }
+
+    Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn handle_overlay_key(
+    key: KeyEvent,
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &mut ProjectState,
+    paths: &ProjectPaths,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+) -> Result<(), CliError> {
+    use tui::app::{EnvEditMode, EnvValueMode, Overlay};
+
+    let overlay = match app.overlay.take() {
+        Some(overlay) => overlay,
+        None => return Ok(()),
+    };
+
+    match overlay {
+        Overlay::Help => {
+            let close = matches!(
+                key.code,
+                KeyCode::Esc | KeyCode::Char('?') | KeyCode::Char('q') | KeyCode::Enter
+            );
+            if !close {
+                app.overlay = Some(Overlay::Help);
+            }
+        }
+        Overlay::Jobs => handle_jobs_key(key, app),
+        Overlay::CommitPicker(picker) => {
+            if let Some(rev) = handle_commit_picker_key(key, app, picker) {
+                update_project_base_pin(
+                    terminal,
+                    app,
+                    state,
+                    paths,
+                    index_path,
+                    conn,
+                    ctx,
+                    Some(rev),
+                )?;
+            }
+        }
+        Overlay::PackageInfo(mut state) => {
+            let mut close = false;
+            let max_scroll = state.lines.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Enter => close = true,
+                KeyCode::Up => state.scroll = state.scroll.saturating_sub(1),
+                KeyCode::Down => state.scroll = (state.scroll + 1).min(max_scroll),
+                KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
+                KeyCode::PageDown => state.scroll = (state.scroll + 10).min(max_scroll),
+                KeyCode::Home => state.scroll = 0,
+                KeyCode::End => state.scroll = max_scroll,
+                KeyCode::Char('i') if key.modifiers.contains(KeyModifiers::CONTROL) => close = true,
+                _ => {}
+            }
+            if !close {
+                app.overlay = Some(Overlay::PackageInfo(state));
+            }
+        }
+        Overlay::VersionPicker(mut state) => {
+            let mut close = false;
+            let max = state.entries.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Esc => close = true,
+                KeyCode::Up if state.cursor > 0 => {
+                    state.cursor -= 1;
+                }
+                KeyCode::Down => {
+                    state.cursor = (state.cursor + 1).min(max);
+                }
+                KeyCode::Enter => {
+                    if let Some(entry) = state.entries.get(state.cursor).cloned() {
+                        let package = state.package.clone();
+                        apply_version_selection(app, &package, entry);
+                        close = true;
+                    }
+                }
+                _ => {}
+            }
+            if !close {
+                app.overlay = Some(Overlay::VersionPicker(state));
+            }
+        }
+        Overlay::PinEditor(mut editor) => {
+            let mut close = false;
+            match key.code {
+                KeyCode::Esc => close = true,
+                KeyCode::Tab | KeyCode::Down => {
+                    pin_editor_next_field(&mut editor);
+                    editor.error = None;
+                }
+                KeyCode::BackTab | KeyCode::Up => {
+                    pin_editor_prev_field(&mut editor);
+                    editor.error = None;
+                }
+                KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    editor.use_latest = !editor.use_latest;
+                    editor.error = None;
+                }
+                KeyCode::Enter => {
+                    if submit_pin_editor(
+                        terminal,
+                        app,
+                        &mut editor,
+                        state,
+                        paths,
+                        index_path,
+                        conn,
+                        ctx,
+                    ) {
+                        close = true;
+                    } else {
+                        app.overlay = Some(Overlay::PinEditor(editor));
+                        return Ok(());
+                    }
+                }
+                KeyCode::Backspace => {
+                    let (value, cursor) = pin_editor_active_input(&mut editor);
+                    if *cursor > 0 {
+                        *cursor -= 1;
+                        value.remove(*cursor);
+                    }
+                    editor.error = None;
+                }
+                KeyCode::Left => {
+                    let (_, cursor) = pin_editor_active_input(&mut editor);
+                    if *cursor > 0 {
+                        *cursor -= 1;
+                    }
+                }
+                KeyCode::Right => {
+                    let (value, cursor) = pin_editor_active_input(&mut editor);
+                    if *cursor < value.len() {
+                        *cursor += 1;
+                    }
+                }
+                KeyCode::Home => {
+                    let (_, cursor) = pin_editor_active_input(&mut editor);
+                    *cursor = 0;
+                }
+                KeyCode::End => {
+                    let (value, cursor) = pin_editor_active_input(&mut editor);
+                    *cursor = value.len();
+                }
+                KeyCode::Char(ch)
+                    if !key.modifiers.contains(KeyModifiers::CONTROL)
+                        && !key.modifiers.contains(KeyModifiers::ALT) =>
+                {
+                    let (value, cursor) = pin_editor_active_input(&mut editor);
+                    value.insert(*cursor, ch);
+                    *cursor += 1;
+                    editor.error = None;
+                }
+                _ => {}
+            }
+            if !close {
+                app.overlay = Some(Overlay::PinEditor(editor));
+            }
+        }
+        Overlay::MissingPackages(state) => handle_missing_packages_key(key, app, state),
+        Overlay::Merge(merge) => {
+            if let Some(merged) = handle_merge_key(key, app, merge) {
+                let pins_changed = merged.pin != state.pin || merged.pins != state.pins;
+                save_merged_state(ctx.clock, conn, paths, state, app, merged)?;
+                if pins_changed {
+                    refresh_project_index(terminal, app, state, index_path, conn, ctx)?;
+                }
+            }
+        }
+        Overlay::GoTo(state) => handle_goto_key(conn, key, app, state)?,
+        Overlay::ChangeActions(state) => handle_change_actions_key(conn, key, app, state)?,
+        Overlay::StorePaths(state) => handle_store_paths_key(key, app, state),
+        Overlay::Columns(mut state) => {
+            let mut close = false;
+            let max = tui::app::COLUMN_OPTIONS.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Esc => close = true,
+                KeyCode::Up if state.cursor > 0 => {
+                    state.cursor -= 1;
+                }
+                KeyCode::Down => {
+                    state.cursor = (state.cursor + 1).min(max);
+                }
+                KeyCode::Enter | KeyCode::Char(' ') => {
+                    if let Some(option) = tui::app::COLUMN_OPTIONS.get(state.cursor) {
+                        toggle_column_setting(app, option.kind);
+                    }
+                }
+                _ => {}
+            }
+            if !close {
+                app.overlay = Some(Overlay::Columns(state));
+            }
+        }
+        Overlay::Filter(mut state) => match key.code {
+            KeyCode::Esc => {}
+            KeyCode::Enter => {
+                let value = state.input.trim().to_string();
+                match state.kind {
+                    tui::app::FilterKind::License => app.filters.license = value,
+                    tui::app::FilterKind::Platform => app.filters.platform = value,
+                }
+                update_search_results(conn, app)?;
+            }
+            KeyCode::Backspace => {
+                if state.cursor > 0 {
+                    state.cursor -= 1;
+                    state.input.remove(state.cursor);
+                }
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::Left => {
+                if state.cursor > 0 {
+                    state.cursor -= 1;
+                }
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::Right => {
+                if state.cursor < state.input.len() {
+                    state.cursor += 1;
+                }
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::Home => {
+                state.cursor = 0;
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::End => {
+                state.cursor = state.input.len();
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::Char(ch)
+                if !key.modifiers.contains(KeyModifiers::CONTROL)
+                    && !key.modifiers.contains(KeyModifiers::ALT) =>
+            {
+                state.input.insert(state.cursor, ch);
+                state.cursor += 1;
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            _ => {
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+        },
+        Overlay::Env(mut state) => {
+            let mut close = false;
+            if matches!(state.mode, EnvEditMode::List) {
+                match key.code {
+                    KeyCode::Esc | KeyCode::Char('q') => close = true,
+                    KeyCode::Up if state.cursor > 0 => {
+                        state.cursor -= 1;
+                    }
+                    KeyCode::Down if !state.entries.is_empty() => {
+                        state.cursor = (state.cursor + 1).min(state.entries.len() - 1);
+                    }
+                    KeyCode::Char('a') => {
+                        state.mode = EnvEditMode::Edit {
+                            original_key: None,
+                            value_mode: EnvValueMode::String,
+                        };
+                        state.input.clear();
+                        state.input_cursor = 0;
+                        state.error = None;
+                    }
+                    KeyCode::Enter => {
+                        if let Some(entry) = state.entries.get(state.cursor) {
+                            state.input =
+                                format!("{}={}", entry.key, env_value_for_editor(&entry.value));
+                            state.input_cursor = state.input.len();
+                            state.mode = EnvEditMode::Edit {
+                                original_key: Some(entry.key.clone()),
+                                value_mode: env_value_mode_from_stored(&entry.value),
+                            };
+                            state.error = None;
+                        }
+                    }
+                    KeyCode::Char('d') if !state.entries.is_empty() => {
+                        state.entries.remove(state.cursor);
+                        if state.cursor >= state.entries.len() && state.cursor > 0 {
+                            state.cursor -= 1;
+                        }
+                    }
+                    _ => {}
+                }
+            } else {
+                match key.code {
+                    KeyCode::Esc => {
+                        state.mode = EnvEditMode::List;
+                        state.input.clear();
+                        state.input_cursor = 0;
+                        state.error = None;
+                    }
+                    KeyCode::Enter => match apply_env_input(&mut state) {
+                        Ok(()) => {}
+                        Err(err) => state.error = Some(err),
+                    },
+                    KeyCode::Tab => {
+                        if let EnvEditMode::Edit { value_mode, .. } = &mut state.mode {
+                            *value_mode = value_mode.toggle();
+                            state.error = None;
+                        }
+                    }
+                    KeyCode::Backspace if state.input_cursor > 0 => {
+                        state.input_cursor -= 1;
+                        state.input.remove(state.input_cursor);
+                    }
+                    KeyCode::Left if state.input_cursor > 0 => {
+                        state.input_cursor -= 1;
+                    }
+                    KeyCode::Right if state.input_cursor < state.input.len() => {
+                        state.input_cursor += 1;
+                    }
+                    KeyCode::Home => state.input_cursor = 0,
+                    KeyCode::End => state.input_cursor = state.input.len(),
+                    KeyCode::Char(ch)
+                        if !key.modifiers.contains(KeyModifiers::CONTROL)
+                            && !key.modifiers.contains(KeyModifiers::ALT) =>
+                    {
+                        state.input.insert(state.input_cursor, ch);
+                        state.input_cursor += 1;
+                    }
+                    _ => {}
+                }
+            }
+
+            if close {
+                apply_env_overlay(app, state);
+            } else {
+                app.overlay = Some(Overlay::Env(state));
+            }
+        }
+        Overlay::BuildEnv(mut state) => {
+            let field = tui::app::BUILD_ENV_FIELDS[state.cursor];
+            let mut close = false;
+            if !state.editing {
+                match key.code {
+                    KeyCode::Esc => close = true,
+                    KeyCode::Up if state.cursor > 0 => state.cursor -= 1,
+                    KeyCode::Down if state.cursor + 1 < tui::app::BUILD_ENV_FIELDS.len() => {
+                        state.cursor += 1;
+                    }
+                    KeyCode::Enter | KeyCode::Char(' ')
+                        if field == tui::app::BuildEnvField::Collisions =>
+                    {
+                        app.build_env.ignore_collisions = !app.build_env.ignore_collisions;
+                        app.update_dirty();
+                    }
+                    KeyCode::Enter => {
+                        state.input = field.value(&app.build_env);
+                        state.input_cursor = state.input.len();
+                        state.editing = true;
+                        state.error = None;
+                    }
+                    _ => {}
+                }
+            } else {
+                match key.code {
+                    KeyCode::Esc => {
+                        state.editing = false;
+                        state.error = None;
+                    }
+                    KeyCode::Enter => {
+                        match apply_build_env_input(&mut app.build_env, field, &state.input) {
+                            Ok(()) => {
+                                state.editing = false;
+                                state.error = None;
+                                app.update_dirty();
+                            }
+                            Err(err) => state.error = Some(err),
+                        }
+                    }
+                    KeyCode::Backspace if state.input_cursor > 0 => {
+                        state.input_cursor -= 1;
+                        state.input.remove(state.input_cursor);
+                    }
+                    KeyCode::Left if state.input_cursor > 0 => state.input_cursor -= 1,
+                    KeyCode::Right if state.input_cursor < state.input.len() => {
+                        state.input_cursor += 1;
+                    }
+                    KeyCode::Home => state.input_cursor = 0,
+                    KeyCode::End => state.input_cursor = state.input.len(),
+                    KeyCode::Char(ch)
+                        if !key.modifiers.contains(KeyModifiers::CONTROL)
+                            && !key.modifiers.contains(KeyModifiers::ALT) =>
+                    {
+                        state.input.insert(state.input_cursor, ch);
+                        state.input_cursor += 1;
+                    }
+                    _ => {}
+                }
+            }
+            if !close {
+                app.overlay = Some(Overlay::BuildEnv(state));
+            }
+        }
+        Overlay::Override(state) => handle_override_key(key, app, state),
+        Overlay::Shell(mut state) => {
+            let mut close = false;
+            let mut cancel = false;
+            match key.code {
+                KeyCode::Esc => close = true,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    close = true;
+                    cancel = true;
+                }
+                _ => edit_text_lines(&mut state, key),
+            }
+
+            if close {
+                if cancel {
+                    apply_shell_overlay(app, &state.original);
+                } else {
+                    apply_shell_overlay(app, &state.lines);
+                }
+            } else {
+                app.overlay = Some(Overlay::Shell(state));
+            }
+        }
+        Overlay::Diff(mut state) => {
+            let current_lines = if state.show_full {
+                &state.full_lines
+            } else {
+                &state.change_lines
+            };
+            let max_scroll = current_lines.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('q') => {}
+                KeyCode::Up => state.scroll = state.scroll.saturating_sub(1),
+                KeyCode::Down => state.scroll = (state.scroll + 1).min(max_scroll),
+                KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
+                KeyCode::PageDown => state.scroll = (state.scroll + 10).min(max_scroll),
+                KeyCode::Home => state.scroll = 0,
+                KeyCode::End => state.scroll = max_scroll,
+                KeyCode::Char('t') | KeyCode::Char('T') => {
+                    state.show_full = !state.show_full;
+                    let new_max = if state.show_full {
+                        state.full_lines.len().saturating_sub(1)
+                    } else {
+                        state.change_lines.len().saturating_sub(1)
+                    };
+                    if state.scroll > new_max {
+                        state.scroll = new_max;
+                    }
+                }
+                _ => {}
+            }
+            if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+                return Ok(());
+            }
+            app.overlay = Some(Overlay::Diff(state));
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn handle_overlay_key_global(
+    key: KeyEvent,
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    state: &mut GlobalProfileState,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+) -> Result<(), CliError> {
+    use tui::app::Overlay;
+
+    let overlay = match app.overlay.take() {
+        Some(overlay) => overlay,
+        None => return Ok(()),
+    };
+
+    match overlay {
+        Overlay::Help => {
+            let close = matches!(
+                key.code,
+                KeyCode::Esc | KeyCode::Char('?') | KeyCode::Char('q') | KeyCode::Enter
+            );
+            if !close {
+                app.overlay = Some(Overlay::Help);
+            }
+        }
+        Overlay::Jobs => handle_jobs_key(key, app),
+        Overlay::CommitPicker(picker) => {
+            if let Some(rev) = handle_commit_picker_key(key, app, picker) {
+                update_profile_base_pin(terminal, app, state, index_path, conn, ctx, Some(rev))?;
+            }
+        }
+        Overlay::PackageInfo(mut state) => {
+            let mut close = false;
+            let max_scroll = state.lines.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Enter => close = true,
+                KeyCode::Up => state.scroll = state.scroll.saturating_sub(1),
+                KeyCode::Down => state.scroll = (state.scroll + 1).min(max_scroll),
+                KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
+                KeyCode::PageDown => state.scroll = (state.scroll + 10).min(max_scroll),
+                KeyCode::Home => state.scroll = 0,
+                KeyCode::End => state.scroll = max_scroll,
+                KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => close = true,
+                KeyCode::Char('i') if key.modifiers.contains(KeyModifiers::CONTROL) => close = true,
+                _ => {}
+            }
+            if !close {
+                app.overlay = Some(Overlay::PackageInfo(state));
+            }
+        }
+        Overlay::VersionPicker(mut state) => {
+            let mut close = false;
+            let max = state.entries.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Esc => close = true,
+                KeyCode::Up if state.cursor > 0 => {
+                    state.cursor -= 1;
+                }
+                KeyCode::Down => {
+                    state.cursor = (state.cursor + 1).min(max);
+                }
+                KeyCode::Enter => {
+                    if let Some(entry) = state.entries.get(state.cursor).cloned() {
+                        let package = state.package.clone();
+                        apply_version_selection(app, &package, entry);
+                        close = true;
+                    }
+                }
+                _ => {}
+            }
+            if !close {
+                app.overlay = Some(Overlay::VersionPicker(state));
+            }
+        }
+        Overlay::MissingPackages(state) => handle_missing_packages_key(key, app, state),
+        Overlay::Merge(_) => {}
+        Overlay::GoTo(state) => handle_goto_key(conn, key, app, state)?,
+        Overlay::ChangeActions(state) => handle_change_actions_key(conn, key, app, state)?,
+        Overlay::StorePaths(state) => handle_store_paths_key(key, app, state),
+        Overlay::Columns(mut state) => {
+            let mut close = false;
+            let max = tui::app::COLUMN_OPTIONS.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Esc => close = true,
+                KeyCode::Up if state.cursor > 0 => {
+                    state.cursor -= 1;
+                }
+                KeyCode::Down => {
+                    state.cursor = (state.cursor + 1).min(max);
+                }
+                KeyCode::Enter | KeyCode::Char(' ') => {
+                    if let Some(option) = tui::app::COLUMN_OPTIONS.get(state.cursor) {
+                        toggle_column_setting(app, option.kind);
+                    }
+                }
+                _ => {}
+            }
+            if !close {
+                app.overlay = Some(Overlay::Columns(state));
+            }
+        }
+        Overlay::Filter(mut state) => match key.code {
+            KeyCode::Esc => {}
+            KeyCode::Enter => {
+                let value = state.input.trim().to_string();
+                match state.kind {
+                    tui::app::FilterKind::License => app.filters.license = value,
+                    tui::app::FilterKind::Platform => app.filters.platform = value,
+                }
+                update_search_results(conn, app)?;
+            }
+            KeyCode::Backspace => {
+                if state.cursor > 0 {
+                    state.cursor -= 1;
+                    state.input.remove(state.cursor);
+                }
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::Left => {
+                if state.cursor > 0 {
+                    state.cursor -= 1;
+                }
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::Right => {
+                if state.cursor < state.input.len() {
+                    state.cursor += 1;
+                }
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::Home => {
+                state.cursor = 0;
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::End => {
+                state.cursor = state.input.len();
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            KeyCode::Char(ch)
+                if !key.modifiers.contains(KeyModifiers::CONTROL)
+                    && !key.modifiers.contains(KeyModifiers::ALT) =>
+            {
+                state.input.insert(state.cursor, ch);
+                state.cursor += 1;
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+            _ => {
+                app.overlay = Some(Overlay::Filter(state));
+                return Ok(());
+            }
+        },
+        Overlay::Diff(mut state) => {
+            let current_lines = if state.show_full {
+                &state.full_lines
+            } else {
+                &state.change_lines
+            };
+            let max_scroll = current_lines.len().saturating_sub(1);
+            match key.code {
+                KeyCode::Esc | KeyCode::Char('q') => {}
+                KeyCode::Up => state.scroll = state.scroll.saturating_sub(1),
+                KeyCode::Down => state.scroll = (state.scroll + 1).min(max_scroll),
+                KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
+                KeyCode::PageDown => state.scroll = (state.scroll + 10).min(max_scroll),
+                KeyCode::Home => state.scroll = 0,
+                KeyCode::End => state.scroll = max_scroll,
+                KeyCode::Char('t') | KeyCode::Char('T') => {
+                    state.show_full = !state.show_full;
+                    let new_max = if state.show_full {
+                        state.full_lines.len().saturating_sub(1)
+                    } else {
+                        state.change_lines.len().saturating_sub(1)
+                    };
+                    if state.scroll > new_max {
+                        state.scroll = new_max;
+                    }
+                }
+                _ => {}
+            }
+            if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
+                return Ok(());
+            }
+            app.overlay = Some(Overlay::Diff(state));
+        }
+        Overlay::Env(_)
+        | Overlay::Shell(_)
+        | Overlay::Override(_)
+        | Overlay::BuildEnv(_)
+        | Overlay::PinEditor(_) => {
+            app.push_toast(tui::app::ToastLevel::Info, "Not available in global mode");
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn pin_editor_active_input(
+    editor: &mut tui::app::PinEditorState,
+) -> (&mut String, &mut usize) {
+    let (value, cursor) = match editor.active {
+        tui::app::PinField::Name => (&mut editor.name, &mut editor.name_cursor),
+        tui::app::PinField::Url => (&mut editor.url, &mut editor.url_cursor),
+        tui::app::PinField::Branch => (&mut editor.branch, &mut editor.branch_cursor),
+        tui::app::PinField::Rev => (&mut editor.rev, &mut editor.rev_cursor),
+        tui::app::PinField::Sha256 => (&mut editor.sha256, &mut editor.sha256_cursor),
+        tui::app::PinField::TarballName => {
+            (&mut editor.tarball_name, &mut editor.tarball_name_cursor)
+        }
+    };
+    if *cursor > value.len() {
+        *cursor = value.len();
+    }
+    (value, cursor)
+}
+
+pub(crate) fn pin_editor_next_field(editor: &mut tui::app::PinEditorState) {
+    let idx = tui::app::PIN_FIELDS
+        .iter()
+        .position(|field| *field == editor.active)
+        .unwrap_or(0);
+    let next = (idx + 1) % tui::app::PIN_FIELDS.len();
+    editor.active = tui::app::PIN_FIELDS[next];
+}
+
+pub(crate) fn pin_editor_prev_field(editor: &mut tui::app::PinEditorState) {
+    let idx = tui::app::PIN_FIELDS
+        .iter()
+        .position(|field| *field == editor.active)
+        .unwrap_or(0);
+    let prev = if idx == 0 {
+        tui::app::PIN_FIELDS.len() - 1
+    } else {
+        idx - 1
+    };
+    editor.active = tui::app::PIN_FIELDS[prev];
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn submit_pin_editor(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    editor: &mut tui::app::PinEditorState,
+    state: &mut ProjectState,
+    paths: &ProjectPaths,
+    index_path: &Path,
+    conn: &mut rusqlite::Connection,
+    ctx: &CommandContext,
+) -> bool {
+    editor.error = None;
+
+    let name = editor.name.trim();
+    if name.is_empty() {
+        editor.error = Some("Name is required".to_string());
+        return false;
+    }
+    match check_pin_name(name, &state.pins) {
+        Ok(()) => {}
+        Err(CliError::InvalidPinName(_)) => {
+            editor.error = Some("Name must be a valid identifier".to_string());
+            return false;
+        }
+        Err(CliError::ReservedPinName(_, suggestion)) => {
+            editor.error = Some(format!(
+                "{} is reserved in the generated nix, try {}",
+                name, suggestion
+            ));
+            return false;
+        }
+        Err(err) => {
+            editor.error = Some(err.to_string());
+            return false;
+        }
+    }
+    let url = editor.url.trim();
+    if url.is_empty() {
+        editor.error = Some("URL is required".to_string());
+        return false;
+    }
+    if !editor.use_latest && editor.rev.trim().is_empty() {
+        editor.error = Some("Revision is required when latest is off".to_string());
+        return false;
+    }
+
+    let name = name.to_string();
+    let url = url.to_string();
+    let branch = {
+        let value = editor.branch.trim();
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    };
+    let use_latest = editor.use_latest;
+    let rev = if use_latest {
+        None
+    } else {
+        Some(editor.rev.trim().to_string())
+    };
+    let sha256 = {
+        let value = editor.sha256.trim();
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    };
+    let tarball_name = {
+        let value = editor.tarball_name.trim();
+        if value.is_empty() {
+            None
+        } else {
+            Some(value.to_string())
+        }
+    };
+
+    let mut ours = state.clone();
+    apply_app_to_state(&mut ours, app);
+    if let Err(err) = with_tui_suspended(terminal, || {
+        add_extra_pin(
+            ctx.git,
+            ctx.clock,
+            &mut ours,
+            AddPinRequest {
+                name,
+                url,
+                branch,
+                tarball_name,
+                rev,
+                sha256,
+                latest: use_latest,
+                channel: None,
+            },
+        )
+    }) {
+        editor.error = Some(err.to_string());
+        return false;
+    }
+    match save_project_edit(ctx.clock, conn, paths, state, app, ours) {
+        Ok(true) => {}
+        // The merge dialog saves the pin and refreshes the index once resolved.
+        Ok(false) => return true,
+        Err(err) => {
+            editor.error = Some(err.to_string());
+            return false;
+        }
+    }
+    if let Err(err) = refresh_project_index(terminal, app, state, index_path, conn, ctx) {
+        app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+        return true;
+    }
+
+    app.push_toast(tui::app::ToastLevel::Info, "Pin added");
+    true
+}
+
+pub(crate) fn with_tui_suspended<T>(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    action: impl FnOnce() -> Result<T, CliError>,
+) -> Result<T, CliError> {
+    use crossterm::terminal::{
+        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+    };
+
+    disable_raw_mode().map_err(CliError::WriteNix)?;
+    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen)
+        .map_err(CliError::WriteNix)?;
+    terminal.show_cursor().map_err(CliError::WriteNix)?;
+
+    let result = action();
+
+    crossterm::execute!(terminal.backend_mut(), EnterAlternateScreen)
+        .map_err(CliError::WriteNix)?;
+    enable_raw_mode().map_err(CliError::WriteNix)?;
+    let _ = terminal.hide_cursor();
+    terminal.clear().map_err(CliError::WriteNix)?;
+    result
+}
+
+pub(crate) fn update_search_results(
+    conn: &rusqlite::Connection,
+    app: &mut tui::app::App,
+) -> Result<(), CliError> {
+    let limit = 1000usize;
+    let query = match &app.filters.pin {
+        Some(pin) => format!("pin:{} {}", pin, app.query.trim()),
+        None => app.query.trim().to_string(),
+    };
+    let packages = if query.is_empty() {
+        list_packages(conn, limit + 1)?
+    } else {
+        search_packages_with_mode(
+            conn,
+            &query,
+            limit + 1,
+            to_index_search_mode(&app.search_mode),
+        )?
+    };
+
+    let total_fetched = packages.len();
+    let entries: Vec<tui::app::PackageEntry> = packages
+        .into_iter()
+        .take(limit)
+        .map(package_entry)
+        .filter(|pkg| {
+            app.filters.matches(pkg)
+                && (!app.filters.show_installed_only || app.is_installed(&pkg.name))
+        })
+        .collect();
+
+    let display_total = if total_fetched > limit {
+        Some(limit + 1)
+    } else {
+        Some(total_fetched)
+    };
+
+    app.packages = entries;
+    app.index_info.displayed_count = display_total;
+    app.cursor = 0;
+    app.details_scroll = 0;
+    if app.packages.is_empty() {
+        app.packages_state.select(None);
+    } else {
+        app.packages_state.select(Some(0));
+    }
+    Ok(())
+}
+
+fn package_entry(pkg: PackageInfo) -> tui::app::PackageEntry {
+    tui::app::PackageEntry {
+        attr_path: pkg.attr_path.clone(),
+        name: normalize_attr_path(&pkg.attr_path),
+        version: pkg.version,
+        description: pkg.description,
+        homepage: pkg.homepage,
+        license: pkg.license,
+        platforms: pkg.platforms,
+        main_program: pkg.main_program,
+        position: pkg.position,
+        broken: pkg.broken,
+        insecure: pkg.insecure,
+        maintainers: pkg.maintainers,
+        last_updated: pkg.last_updated,
+    }
+}
+
+pub(crate) fn go_to_attr(
+    conn: &rusqlite::Connection,
+    app: &mut tui::app::App,
+    attr: &str,
+) -> Result<bool, CliError> {
+    let Some(pkg) = get_package(conn, attr)? else {
+        return Ok(false);
+    };
+    app.focus = tui::app::Focus::Packages;
+    app.query = attr.to_string();
+    update_search_results(conn, app)?;
+    let index = match app
+        .packages
+        .iter()
+        .position(|entry| entry.attr_path == attr)
+    {
+        Some(index) => index,
+        None => {
+            app.packages.insert(0, package_entry(pkg));
+            0
+        }
+    };
+    app.cursor = index;
+    app.packages_state.select(Some(index));
+    Ok(true)
+}
+
+pub(crate) fn open_change_actions(app: &mut tui::app::App) {
+    let Some(change) = app.current_change() else {
+        app.push_toast(tui::app::ToastLevel::Info, "No pending changes");
+        return;
+    };
+    let actions = app.change_actions(&change);
+    app.overlay = Some(tui::app::Overlay::ChangeActions(
+        tui::app::ChangeActionsState {
+            change,
+            actions,
+            cursor: 0,
+        },
+    ));
+}
+
+pub(crate) fn handle_change_actions_key(
+    conn: &rusqlite::Connection,
+    key: KeyEvent,
+    app: &mut tui::app::App,
+    mut state: tui::app::ChangeActionsState,
+) -> Result<(), CliError> {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => return Ok(()),
+        KeyCode::Up => state.cursor = state.cursor.saturating_sub(1),
+        KeyCode::Down => {
+            state.cursor = (state.cursor + 1).min(state.actions.len().saturating_sub(1))
+        }
+        KeyCode::Enter => {
+            if let Some(action) = state.actions.get(state.cursor).copied() {
+                run_change_action(conn, app, &state.change, action)?;
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+    app.overlay = Some(tui::app::Overlay::ChangeActions(state));
+    Ok(())
+}
+
+pub(crate) fn run_change_action(
+    conn: &rusqlite::Connection,
+    app: &mut tui::app::App,
+    change: &tui::app::PendingChange,
+    action: tui::app::ChangeAction,
+) -> Result<(), CliError> {
+    use tui::app::{ChangeAction, ChangeSection};
+
+    match (action, change.section) {
+        (ChangeAction::Unstage, _) => {
+            app.unstage(change);
+            update_search_results(conn, app)?;
+            let label = match change.section {
+                ChangeSection::BuildEnv => "buildEnv settings".to_string(),
+                ChangeSection::ShellHook => "shell hook".to_string(),
+                _ => change.target.clone(),
+            };
+            app.push_toast(tui::app::ToastLevel::Info, format!("Unstaged {}", label));
+        }
+        (ChangeAction::JumpTo, ChangeSection::Templates) => app.jump_to_preset(&change.target),
+        (ChangeAction::JumpTo | ChangeAction::PickVersion, _) => {
+            if !go_to_attr(conn, app, &change.target)? {
+                app.push_toast(
+                    tui::app::ToastLevel::Info,
+                    format!("{} is not in the index", change.target),
+                );
+            } else if action == ChangeAction::PickVersion {
+                open_version_picker(app);
+            }
+        }
+        (ChangeAction::Edit, ChangeSection::Env) => open_env_overlay(app),
+        (ChangeAction::Edit, ChangeSection::BuildEnv) => open_build_env_overlay(app),
+        (ChangeAction::Edit, ChangeSection::ShellHook) => open_shell_overlay(app),
+        (ChangeAction::Edit, _) => {}
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_goto_key(
+    conn: &rusqlite::Connection,
+    key: KeyEvent,
+    app: &mut tui::app::App,
+    mut state: tui::app::GoToState,
+) -> Result<(), CliError> {
+    match key.code {
+        KeyCode::Esc => return Ok(()),
+        KeyCode::Enter => {
+            let attr = state.input.trim().to_string();
+            if attr.is_empty() {
+                return Ok(());
+            }
+            if state.missing.as_deref() == Some(attr.as_str()) {
+                app.add_attr(&attr);
+                app.push_toast(
+                    tui::app::ToastLevel::Info,
+                    format!("Added {} (not in index)", attr),
+                );
+                return Ok(());
+            }
+            if go_to_attr(conn, app, &attr)? {
+                app.push_toast(tui::app::ToastLevel::Info, format!("Jumped to {}", attr));
+                return Ok(());
+            }
+            state.missing = Some(attr);
+        }
+        KeyCode::Backspace if state.cursor > 0 => {
+            state.cursor -= 1;
+            state.input.remove(state.cursor);
+            state.missing = None;
+        }
+        KeyCode::Left => state.cursor = state.cursor.saturating_sub(1),
+        KeyCode::Right => state.cursor = (state.cursor + 1).min(state.input.len()),
+        KeyCode::Home => state.cursor = 0,
+        KeyCode::End => state.cursor = state.input.len(),
+        KeyCode::Char(ch)
+            if !key.modifiers.contains(KeyModifiers::CONTROL)
+                && !key.modifiers.contains(KeyModifiers::ALT) =>
+        {
+            state.input.insert(state.cursor, ch);
+            state.cursor += 1;
+            state.missing = None;
+        }
+        _ => {}
+    }
+    app.overlay = Some(tui::app::Overlay::GoTo(state));
+    Ok(())
+}
+
+pub(crate) fn cycle_pin_filter(
+    conn: &rusqlite::Connection,
+    app: &mut tui::app::App,
+) -> Result<(), CliError> {
+    if app.pin_map.is_empty() {
+        app.push_toast(tui::app::ToastLevel::Info, "No extra pins to filter by");
+        return Ok(());
+    }
+    app.cycle_pin_filter();
+    update_search_results(conn, app)?;
+    let label = app.filters.pin.as_deref().unwrap_or("all sources");
+    app.push_toast(tui::app::ToastLevel::Info, format!("Pin filter: {}", label));
+    Ok(())
+}
+
+pub(crate) fn select_filter_chip(
+    conn: &rusqlite::Connection,
+    app: &mut tui::app::App,
+    slot: usize,
+) -> Result<(), CliError> {
+    let message = if slot == 0 {
+        app.filters = tui::app::PackageFilters::default();
+        "Filters cleared".to_string()
+    } else {
+        match app.select_filter_chip(slot - 1) {
+            Some(name) if app.active_filter_chip() == Some(slot - 1) => format!("Filter: {}", name),
+            Some(name) => format!("Filter {} off", name),
+            None => {
+                app.push_toast(
+                    tui::app::ToastLevel::Info,
+                    format!("No filter chip {} (add [[tui.filters]] to config)", slot),
+                );
+                return Ok(());
+            }
+        }
+    };
+    update_search_results(conn, app)?;
+    app.push_toast(tui::app::ToastLevel::Info, message);
+    Ok(())
+}
+
+fn warn_incompatible_index(app: &mut tui::app::App, reason: &str) {
+    app.push_toast(
+        tui::app::ToastLevel::Warning,
+        format!(
+            "Index {}; showing the bundled package list until you run `mica migrate caches`",
+            reason
+        ),
+    );
+}
+
+fn warn_index_coverage_toast(
+    app: &mut tui::app::App,
+    conn: &rusqlite::Connection,
+    pins: &[IndexPin],
+) {
+    let meta = get_meta(conn).unwrap_or_default();
+    for issue in index_coverage_issues(&meta, pins) {
+        app.push_toast(tui::app::ToastLevel::Warning, issue);
+    }
+}
+
+fn warn_read_only_state(app: &mut tui::app::App, mica: &MicaMetadata) {
+    if mica.version_check() == VersionCheck::Newer {
+        app.push_toast(
+            tui::app::ToastLevel::Warning,
+            format!(
+                "Read-only: written by mica {}, saving will fail until you upgrade",
+                mica.version
+            ),
+        );
+    }
+}
+
+pub(crate) fn apply_state_to_app(app: &mut tui::app::App, state: &ProjectState) {
+    app.added = state.packages.added.iter().cloned().collect();
+    app.removed = state.packages.removed.iter().cloned().collect();
+    app.active_presets = state.presets.active.iter().cloned().collect();
+    app.pinned = state.packages.pinned.clone();
+    app.env = state.env.clone();
+    app.shell_hook = state.shell.hook.clone();
+    app.build_env = state.build_env.clone();
+    app.priorities = state.packages.priorities.clone();
+    app.overrides = state.packages.overrides.clone();
+    apply_pin_map_to_app(app, &collect_index_pins(state));
+    app.rebuild_preset_packages();
+    app.commit_baseline();
+}
+
+pub(crate) fn apply_profile_state_to_app(app: &mut tui::app::App, state: &GlobalProfileState) {
+    app.added = state.packages.added.iter().cloned().collect();
+    app.removed = state.packages.removed.iter().cloned().collect();
+    app.active_presets = state.presets.active.iter().cloned().collect();
+    app.pinned = state.packages.pinned.clone();
+    app.env.clear();
+    app.shell_hook = None;
+    apply_pin_map_to_app(app, &collect_index_pins_profile(state));
+    app.rebuild_preset_packages();
+    app.commit_baseline();
+}
+
+pub(crate) fn apply_columns_from_config(app: &mut tui::app::App, config: &Config) {
+    app.columns = tui::app::ColumnSettings {
+        show_version: config.tui.columns.version,
+        show_description: config.tui.columns.description,
+        show_license: config.tui.columns.license,
+        show_platforms: config.tui.columns.platforms,
+        show_main_program: config.tui.columns.main_program,
+    };
+}
+
+pub(crate) fn apply_filter_chips_from_config(app: &mut tui::app::App, config: &Config) {
+    app.filter_chips = config.tui.filters.clone();
+}
+
+pub(crate) fn apply_search_mode_from_config(app: &mut tui::app::App, config: &Config) {
+    app.search_mode = config.tui.search_mode.clone();
+}
+
+pub(crate) fn apply_show_details_from_config(app: &mut tui::app::App, config: &Config) {
+    app.show_details = config.tui.show_details;
+}
+
+pub(crate) const DETAILS_SCROLL_STEP: usize = 3;
+
+pub(crate) fn toggle_details_expanded(app: &mut tui::app::App) {
+    if !app.show_details {
+        app.show_details = true;
+    }
+    app.details_expanded = !app.details_expanded;
+    app.details_scroll = 0;
+    app.push_toast(
+        tui::app::ToastLevel::Info,
+        format!(
+            "Details panel: {}",
+            if app.details_expanded {
+                "expanded"
+            } else {
+                "compact"
+            }
+        ),
+    );
+}
+
+pub(crate) fn save_columns_to_config(columns: &tui::app::ColumnSettings) -> Result<(), CliError> {
+    ensure_config_dir()?;
+    let mut config = load_config_or_default()?;
+    config.tui.columns = mica_core::config::TuiColumns {
+        version: columns.show_version,
+        description: columns.show_description,
+        license: columns.show_license,
+        platforms: columns.show_platforms,
+        main_program: columns.show_main_program,
+    };
+    config
+        .save_to_path(&config_path()?)
+        .map_err(CliError::Config)
+}
+
+pub(crate) fn save_search_mode_to_config(
+    mode: &mica_core::config::SearchMode,
+) -> Result<(), CliError> {
+    ensure_config_dir()?;
+    let mut config = load_config_or_default()?;
+    config.tui.search_mode = mode.clone();
+    config
+        .save_to_path(&config_path()?)
+        .map_err(CliError::Config)
+}
+
+pub(crate) fn save_show_details_to_config(show_details: bool) -> Result<(), CliError> {
+    ensure_config_dir()?;
+    let mut config = load_config_or_default()?;
+    config.tui.show_details = show_details;
+    config
+        .save_to_path(&config_path()?)
+        .map_err(CliError::Config)
+}
+
+pub(crate) fn toggle_column_setting(app: &mut tui::app::App, column: tui::app::ColumnKind) {
+    app.toggle_column(column);
+    if let Err(err) = save_columns_to_config(&app.columns) {
+        app.push_toast(tui::app::ToastLevel::Error, err.to_string());
+    }
+}
+
+pub(crate) fn open_env_overlay(app: &mut tui::app::App) {
+    let mut entries: Vec<tui::app::EnvEntry> = app
+        .env
+        .iter()
+        .map(|(key, value)| tui::app::EnvEntry {
+            key: key.clone(),
+            value: value.clone(),
+        })
+        .collect();
+    entries.sort_by(|a, b| a.key.cmp(&b.key));
+    app.overlay = Some(tui::app::Overlay::Env(tui::app::EnvEditorState {
+        entries,
+        cursor: 0,
+        input: String::new(),
+        input_cursor: 0,
+        mode: tui::app::EnvEditMode::List,
+        error: None,
+    }));
+}
+
+pub(crate) fn open_shell_overlay(app: &mut tui::app::App) {
+    let lines: Vec<String> = app
+        .shell_hook
+        .as_deref()
+        .unwrap_or("")
+        .lines()
+        .map(|line| line.to_string())
+        .collect();
+    let lines = if lines.is_empty() {
+        vec![String::new()]
+    } else {
+        lines
+    };
+    app.overlay = Some(tui::app::Overlay::Shell(tui::app::ShellEditorState {
+        original: lines.clone(),
+        lines,
+        cursor_row: 0,
+        cursor_col: 0,
+    }));
+}
+
+pub(crate) fn refresh_collisions(conn: &rusqlite::Connection, app: &mut tui::app::App) {
+    let selected: Vec<String> = app.selected_packages().into_iter().collect();
+    if selected == app.collisions_for {
+        return;
+    }
+    app.collisions = find_binary_collisions(conn, &selected).unwrap_or_default();
+    app.collisions_for = selected;
+}
+
+pub(crate) fn selected_priorities(app: &tui::app::App) -> BTreeMap<String, i32> {
+    let selected = app.selected_packages();
+    app.priorities
+        .iter()
+        .filter(|(pkg, _)| selected.contains(*pkg))
+        .map(|(pkg, priority)| (pkg.clone(), *priority))
+        .collect()
+}
+
+pub(crate) fn selected_overrides(app: &tui::app::App) -> BTreeMap<String, String> {
+    let selected = app.selected_packages();
+    app.overrides
+        .iter()
+        .filter(|(pkg, _)| selected.contains(*pkg))
+        .map(|(pkg, snippet)| (pkg.clone(), snippet.clone()))
+        .collect()
+}
+
+pub(crate) fn open_override_overlay(app: &mut tui::app::App) {
+    let Some(entry) = app.current_package() else {
+        return;
+    };
+    let attr = app.base_attr_for(&entry.attr_path);
+    if !app.is_installed(&attr) {
+        app.push_toast(
+            tui::app::ToastLevel::Info,
+            format!("{} is not selected", attr),
+        );
+        return;
+    }
+    let lines: Vec<String> = match app.overrides.get(&attr) {
+        Some(snippet) => snippet.lines().map(str::to_string).collect(),
+        None => vec!["prev: {".to_string(), "  ".to_string(), "}".to_string()],
+    };
+    let (cursor_row, cursor_col) = if app.overrides.contains_key(&attr) {
+        (0, 0)
+    } else {
+        (1, 2)
+    };
+    app.overlay = Some(tui::app::Overlay::Override(tui::app::OverrideEditorState {
+        attr,
+        editor: tui::app::ShellEditorState {
+            original: lines.clone(),
+            lines,
+            cursor_row,
+            cursor_col,
+        },
+    }));
+}
+
+pub(crate) fn handle_override_key(
+    key: KeyEvent,
+    app: &mut tui::app::App,
+    mut state: tui::app::OverrideEditorState,
+) {
+    match key.code {
+        KeyCode::Esc if state.editor.lines == state.editor.original => {}
+        KeyCode::Esc => apply_override_overlay(app, &state.attr, &state.editor.lines),
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {}
+        _ => {
+            edit_text_lines(&mut state.editor, key);
+            app.overlay = Some(tui::app::Overlay::Override(state));
+        }
+    }
+}
+
+pub(crate) fn apply_override_overlay(app: &mut tui::app::App, attr: &str, lines: &[String]) {
+    let snippet = lines.join("\n");
+    let snippet = snippet.trim();
+    if snippet.is_empty() {
+        if app.overrides.remove(attr).is_some() {
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                format!("Cleared override for {}", attr),
+            );
+        }
+    } else {
+        app.overrides.insert(attr.to_string(), snippet.to_string());
+        app.push_toast(
+            tui::app::ToastLevel::Info,
+            format!("{} now uses overrideAttrs", attr),
+        );
+    }
+    app.update_dirty();
+}
+
+pub(crate) fn prefer_current_package(app: &mut tui::app::App) {
+    let Some(entry) = app.current_package() else {
+        return;
+    };
+    let base = app.base_attr_for(&entry.attr_path);
+    if app.priorities.remove(&base).is_some() {
+        app.update_dirty();
+        app.push_toast(
+            tui::app::ToastLevel::Info,
+            format!("Cleared priority for {}", base),
+        );
+        return;
+    }
+    if !app.is_installed(&base) {
+        app.push_toast(
+            tui::app::ToastLevel::Info,
+            format!("{} is not selected", base),
+        );
+        return;
+    }
+    let priority = preferred_priority(&base, &app.collisions, &app.priorities);
+    app.priorities.insert(base.clone(), priority);
+    app.update_dirty();
+    app.push_toast(
+        tui::app::ToastLevel::Info,
+        format!("{} now has priority {}", base, priority),
+    );
+}
+
+pub(crate) fn show_missing_after_pin_update(
+    conn: &rusqlite::Connection,
+    app: &mut tui::app::App,
+    pin: &Pin,
+) {
+    let mut attrs: Vec<String> = app.selected_packages().into_iter().collect();
+    attrs.retain(|attr| !app.pinned.contains_key(attr));
+    let missing = if index_matches_pin(conn, pin) {
+        missing_packages(conn, &attrs).unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    if missing.is_empty() {
+        app.push_toast(tui::app::ToastLevel::Info, "Pin updated");
+        return;
+    }
+    app.overlay = Some(tui::app::Overlay::MissingPackages(
+        tui::app::MissingPackagesState {
+            rev: pin.rev.clone(),
+            entries: missing
+                .into_iter()
+                .map(|entry| tui::app::MissingPackageEntry {
+                    attr: entry.attr,
+                    candidates: entry.candidates,
+                    choice: 0,
+                })
+                .collect(),
+            cursor: 0,
+        },
+    ));
+}
+
+pub(crate) fn handle_missing_packages_key(
+    key: KeyEvent,
+    app: &mut tui::app::App,
+    mut state: tui::app::MissingPackagesState,
+) {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => return,
+        KeyCode::Up => state.cursor = state.cursor.saturating_sub(1),
+        KeyCode::Down => state.cursor += 1,
+        KeyCode::Left | KeyCode::Right => {
+            if let Some(entry) = state.entries.get_mut(state.cursor) {
+                let count = entry.candidates.len();
+                if count > 0 {
+                    entry.choice = if key.code == KeyCode::Left {
+                        (entry.choice + count - 1) % count
+                    } else {
+                        (entry.choice + 1) % count
+                    };
+                }
+            }
+        }
+        KeyCode::Enter => {
+            if let Some(entry) = state.entries.get(state.cursor) {
+                match entry.candidates.get(entry.choice) {
+                    Some(candidate) => {
+                        app.replace_package(&entry.attr, candidate);
+                        app.push_toast(
+                            tui::app::ToastLevel::Info,
+                            format!("Replaced {} with {}", entry.attr, candidate),
+                        );
+                        state.entries.remove(state.cursor);
+                    }
+                    None => app.push_toast(
+                        tui::app::ToastLevel::Info,
+                        format!("No replacement for {}, press d to drop it", entry.attr),
+                    ),
+                }
+            }
+        }
+        KeyCode::Char('d') if state.cursor < state.entries.len() => {
+            let entry = state.entries.remove(state.cursor);
+            app.drop_package(&entry.attr);
+            app.push_toast(
+                tui::app::ToastLevel::Info,
+                format!("Dropped {}", entry.attr),
+            );
+        }
+        _ => {}
+    }
+    if state.entries.is_empty() {
+        return;
+    }
+    state.cursor = state.cursor.min(state.entries.len() - 1);
+    app.overlay = Some(tui::app::Overlay::MissingPackages(state));
+}
+
+pub(crate) fn try_current_package(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    pin: &Pin,
+    output: &Output,
+) -> Result<(), CliError> {
+    if app.focus != tui::app::Focus::Packages {
+        app.push_toast(tui::app::ToastLevel::Info, "Focus packages to try one");
+        return Ok(());
+    }
+    let Some(entry) = app.current_package() else {
+        app.push_toast(tui::app::ToastLevel::Info, "No package selected");
+        return Ok(());
+    };
+    let packages = vec![entry.attr_path.clone()];
+    let resolved = resolve_try_packages(&packages, pin, &app.pin_map, &app.pinned);
+    let Some((attr, _)) = resolved.first() else {
+        return Ok(());
+    };
+    let attr = attr.clone();
+    let expression = ephemeral_shell_expression(pin, &resolved, &url_rewrites()?);
+    with_tui_suspended(terminal, || {
+        output.status(format!(
+            "entering ephemeral shell with {}, exit to return to mica",
+            attr
+        ));
+        run_ephemeral_shell(&expression, None)
+    })?;
+    app.push_toast(
+        tui::app::ToastLevel::Info,
+        format!("Left ephemeral shell for {}", attr),
+    );
+    Ok(())
+}
+
+pub(crate) fn store_preview_packages(app: &tui::app::App) -> Vec<String> {
+    let mut packages = Vec::new();
+    if app.focus == tui::app::Focus::Packages {
+        if let Some(entry) = app.current_package() {
+            packages.push(entry.attr_path.clone());
+        }
+    }
+    for attr in app.added.difference(&app.base_added) {
+        if !packages.contains(attr) {
+            packages.push(attr.clone());
+        }
+    }
+    packages
+}
+
+pub(crate) fn open_store_paths_overlay(
+    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
+    app: &mut tui::app::App,
+    pin: &Pin,
+    output: &Output,
+) -> Result<(), CliError> {
+    let packages = store_preview_packages(app);
+    if packages.is_empty() {
+        app.push_toast(tui::app::ToastLevel::Info, "No package selected");
+        return Ok(());
+    }
+    let resolved = resolve_try_packages(&packages, pin, &app.pin_map, &app.pinned);
+    let substituters = load_config_or_default()
+        .map(|config| config.nixpkgs.substituters)
+        .unwrap_or_else(|_| Config::default().nixpkgs.substituters);
+    let result = with_tui_suspended(terminal, || {
+        run_with_spinner(output, "evaluating store paths", || {
+            preview_store_paths(&resolved, &substituters)
+        })
+    });
+    match result {
+        Ok(entries) => {
+            app.overlay = Some(tui::app::Overlay::StorePaths(tui::app::StorePathsState {
+                rev: pin.rev.clone(),
+                entries,
+                cursor: 0,
+            }));
+        }
+        Err(err) => app.push_toast(tui::app::ToastLevel::Error, err.to_string()),
+    }
+    Ok(())
+}
+
+pub(crate) fn handle_store_paths_key(
+    key: KeyEvent,
+    app: &mut tui::app::App,
+    mut state: tui::app::StorePathsState,
+) {
+    match key.code {
+        KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('W') => return,
+        KeyCode::Up => state.cursor = state.cursor.saturating_sub(1),
+        KeyCode::Down => {
+            state.cursor = (state.cursor + 1).min(state.entries.len().saturating_sub(1))
+        }
+        _ => {}
+    }
+    app.overlay = Some(tui::app::Overlay::StorePaths(state));
+}
+
+pub(crate) fn open_build_env_overlay(app: &mut tui::app::App) {
+    app.overlay = Some(tui::app::Overlay::BuildEnv(tui::app::BuildEnvEditorState {
+        cursor: 0,
+        editing: false,
+        input: String::new(),
+        input_cursor: 0,
+        error: None,
+    }));
+}
+
+pub(crate) fn apply_build_env_input(
+    settings: &mut BuildEnvState,
+    field: tui::app::BuildEnvField,
+    input: &str,
+) -> Result<(), String> {
+    let input = input.trim();
+    match field {
+        tui::app::BuildEnvField::Name => {
+            settings.name = (!input.is_empty()).then(|| input.to_string());
+        }
+        tui::app::BuildEnvField::Outputs => {
+            settings.extra_outputs = Vec::new();
+            for output in input.split(|ch: char| ch == ',' || ch.is_whitespace()) {
+                if !output.is_empty() && !settings.extra_outputs.iter().any(|o| o == output) {
+                    settings.extra_outputs.push(output.to_string());
+                }
+            }
+        }
+        tui::app::BuildEnvField::Collisions => {
+            settings.ignore_collisions = match input {
+                "ignore" => true,
+                "error" => false,
+                _ => return Err("collisions must be ignore or error".to_string()),
+            };
+        }
+        tui::app::BuildEnvField::Priority => {
+            settings.priority = if input.is_empty() {
+                None
+            } else {
+                Some(
+                    input
+                        .parse()
+                        .map_err(|_| "priority must be an integer".to_string())?,
+                )
+            };
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn open_filter_overlay(app: &mut tui::app::App, kind: tui::app::FilterKind) {
+    let input = match kind {
+        tui::app::FilterKind::License => app.filters.license.clone(),
+        tui::app::FilterKind::Platform => app.filters.platform.clone(),
+    };
+    app.overlay = Some(tui::app::Overlay::Filter(tui::app::FilterEditorState {
+        cursor: input.len(),
+        input,
+        kind,
+    }));
+}
+
+pub(crate) fn build_diff_overlay(
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    state: &ProjectState,
+    app: &tui::app::App,
+) -> Result<tui::app::Overlay, CliError> {
+    let mut temp_state = state.clone();
+    temp_state.packages.added = app.added.iter().cloned().collect();
+    temp_state.packages.removed = app.removed.iter().cloned().collect();
+    temp_state.packages.pinned = app.pinned.clone();
+    temp_state.presets.active = app.active_presets.iter().cloned().collect();
+    temp_state.env = app.env.clone();
+    temp_state.shell.hook = app.shell_hook.clone();
+    temp_state.build_env = app.build_env.clone();
+    temp_state.packages.priorities = selected_priorities(app);
+    temp_state.packages.overrides = selected_overrides(app);
+
+    let generated = format_mica_nix(&build_project_nix(clock, paths, &temp_state)?);
+    let existing = std::fs::read_to_string(&paths.nix_path).map_err(CliError::ReadNix)?;
+    let full_diff = diff_lines(&existing, &generated);
+    let mut changes_only = diff_lines_changes_only(&existing, &generated);
+    if changes_only.is_empty() {
+        changes_only.push("No changes".to_string());
+    }
+
+    Ok(tui::app::Overlay::Diff(tui::app::DiffViewerState {
+        full_lines: full_diff,
+        change_lines: changes_only,
+        show_full: false,
+        scroll: 0,
+    }))
+}
+
+pub(crate) fn build_diff_overlay_profile(
+    clock: &dyn Clock,
+    state: &GlobalProfileState,
+    app: &tui::app::App,
+) -> Result<tui::app::Overlay, CliError> {
+    let mut temp_state = state.clone();
+    temp_state.packages.added = app.added.iter().cloned().collect();
+    temp_state.packages.removed = app.removed.iter().cloned().collect();
+    temp_state.packages.pinned = app.pinned.clone();
+    temp_state.presets.active = app.active_presets.iter().cloned().collect();
+
+    let presets = load_all_presets()?;
+    let mut preset_map = BTreeMap::new();
+    for preset in presets {
+        preset_map.insert(preset.name.clone(), preset);
+    }
+    let mut active_presets = Vec::new();
+    for name in &temp_state.presets.active {
+        match preset_map.get(name) {
+            Some(preset) => active_presets.push(preset.clone()),
+            None => return Err(CliError::MissingPreset(name.clone())),
+        }
+    }
+    let merged = merge_profile_presets(&active_presets, &temp_state, &current_host());
+    let generated = generate_profile_nix(&temp_state, &merged, clock.now());
+    let generated = format_mica_nix(&generated);
+    let existing = std::fs::read_to_string(profile_nix_path()?).map_err(CliError::ReadNix)?;
+
+    let full_diff = diff_lines(&existing, &generated);
+    let mut changes_only = diff_lines_changes_only(&existing, &generated);
+    if changes_only.is_empty() {
+        changes_only.push("No changes".to_string());
+    }
+
+    Ok(tui::app::Overlay::Diff(tui::app::DiffViewerState {
+        full_lines: full_diff,
+        change_lines: changes_only,
+        show_full: false,
+        scroll: 0,
+    }))
+}
+
+pub(crate) fn build_package_info_overlay(
+    app: &tui::app::App,
+    state: &ProjectState,
+) -> Option<tui::app::Overlay> {
+    let pins = collect_index_pins(state);
+    build_package_info_overlay_with_pins(app, &pins)
+}
+
+pub(crate) fn build_package_info_overlay_with_pins(
+    app: &tui::app::App,
+    pins: &[IndexPin],
+) -> Option<tui::app::Overlay> {
+    let pkg = app.packages.get(app.cursor)?;
+    let mut lines = Vec::new();
+    lines.push(format!("Name: {}", pkg.name));
+    lines.push(format!("Attr path: {}", pkg.attr_path));
+    lines.push(format!(
+        "Version: {}",
+        pkg.version.as_deref().unwrap_or("unknown")
+    ));
+
+    let mut pin_label = "primary".to_string();
+    let mut pin_url = pins
+        .first()
+        .map(|pin| pin.pin.url.clone())
+        .unwrap_or_else(|| "unknown".to_string());
+    let mut pin_rev = pins
+        .first()
+        .map(|pin| pin.pin.rev.clone())
+        .unwrap_or_else(|| "unknown".to_string());
+    for pin in pins {
+        let Some(label) = pin.name.as_ref() else {
+            continue;
+        };
+        if pkg.attr_path.starts_with(&format!("{}.", label)) {
+            pin_label = label.clone();
+            pin_url = pin.pin.url.clone();
+            pin_rev = pin.pin.rev.clone();
+            break;
+        }
+    }
+
+    lines.push(format!("Pin: {}", pin_label));
+    lines.push(format!("Pin URL: {}", pin_url));
+    lines.push(format!("Pin rev: {}", pin_rev));
+    lines.push(format!(
+        "Source: {}",
+        pkg.position.as_deref().unwrap_or("unknown")
+    ));
+
+    if let Some(homepage) = pkg.homepage.as_deref().filter(|s| !s.trim().is_empty()) {
+        lines.push(format!("Homepage: {}", homepage));
+    }
+    if let Some(main_program) = pkg.main_program.as_deref().filter(|s| !s.trim().is_empty()) {
+        lines.push(format!("Main program: {}", main_program));
+    }
+    if let Some(license) = pkg.license.as_deref().filter(|s| !s.trim().is_empty()) {
+        lines.push(format!("License: {}", license));
+    }
+    if let Some(platforms) = pkg.platforms.as_deref().filter(|s| !s.trim().is_empty()) {
+        lines.push(format!("Platforms: {}", platforms));
+    }
+    if let Some(maintainers) = pkg.maintainers.as_deref().filter(|s| !s.trim().is_empty()) {
+        lines.push(format!("Maintainers: {}", maintainers));
+    }
+    if let Some(last_updated) = pkg.last_updated.as_deref().filter(|s| !s.trim().is_empty()) {
+        lines.push(format!("Last updated: {}", last_updated));
+    }
+    if pkg.broken || pkg.insecure {
+        let mut flags = Vec::new();
+        if pkg.broken {
+            flags.push("broken");
+        }
+        if pkg.insecure {
+            flags.push("insecure");
+        }
+        lines.push(format!("Flags: {}", flags.join(", ")));
+    }
+
+    if let Some(description) = pkg.description.as_deref().filter(|s| !s.trim().is_empty()) {
+        lines.push("Description:".to_string());
+        for line in description.lines() {
+            lines.push(format!("  {}", line));
+        }
+    }
+
+    Some(tui::app::Overlay::PackageInfo(tui::app::PackageInfoState {
+        lines,
+        scroll: 0,
+    }))
+}
+
+pub(crate) fn build_version_picker_overlay(
+    app: &tui::app::App,
+) -> Result<Option<tui::app::Overlay>, CliError> {
+    let pkg = match app.packages.get(app.cursor) {
+        Some(pkg) => pkg,
+        None => return Ok(None),
+    };
+    let base_attr = app.base_attr_for(&pkg.attr_path);
+    let versions_path = versions_db_path()?;
+    if !versions_path.exists() {
+        return Ok(None);
+    }
+    let conn = open_versions_db(&versions_path).map_err(CliError::Index)?;
+    let versions = list_versions(&conn, &base_attr, 200).map_err(CliError::Index)?;
+    if versions.is_empty() {
+        return Ok(None);
+    }
+    let entries = versions
+        .into_iter()
+        .map(|entry| tui::app::VersionPickerEntry {
+            source: entry.source,
+            version: entry.version,
+            commit: entry.commit,
+            commit_date: entry.commit_date,
+            branch: entry.branch,
+            url: entry.url,
+        })
+        .collect();
+
+    Ok(Some(tui::app::Overlay::VersionPicker(
+        tui::app::VersionPickerState {
+            entries,
+            cursor: 0,
+            package: base_attr,
+        },
+    )))
+}
+
+pub(crate) fn open_version_picker(app: &mut tui::app::App) {
+    if app.focus != tui::app::Focus::Packages {
+        app.push_toast(
+            tui::app::ToastLevel::Info,
+            "Focus packages to view versions",
+        );
+        return;
+    }
+    match build_version_picker_overlay(app) {
+        Ok(Some(overlay)) => app.overlay = Some(overlay),
+        Ok(None) => app.push_toast(
+            tui::app::ToastLevel::Info,
+            "No version history for selection",
+        ),
+        Err(err) => app.push_toast(tui::app::ToastLevel::Error, err.to_string()),
+    }
+}
+
+pub(crate) fn add_and_pick_version(app: &mut tui::app::App) {
+    if app.focus != tui::app::Focus::Packages {
+        app.push_toast(tui::app::ToastLevel::Info, "Focus packages to add and pin");
+        return;
+    }
+    app.add_current_package();
+    open_version_picker(app);
+}
+
+pub(crate) fn apply_version_selection(
+    app: &mut tui::app::App,
+    package: &str,
+    entry: tui::app::VersionPickerEntry,
+) {
+    let superseded: Vec<u64> = app
+        .pending_pins
+        .iter()
+        .filter(|(_, (pending, _))| pending == package)
+        .map(|(id, _)| *id)
+        .collect();
+    for id in superseded {
+        app.jobs.cancel(id);
+        app.pending_pins.remove(&id);
+    }
+    let url = entry.url.clone();
+    let commit = entry.commit.clone();
+    let id = app.jobs.spawn(
+        tui::app::JobKind::Prefetch,
+        format!("Prefetching {} {}", package, entry.version),
+        move |job| {
+            fetch_nix_sha256_cancellable(&url, &commit, &|| job.is_cancelled())
+                .map(Some)
+                .map_err(|err| err.to_string())
+        },
+    );
+    app.push_toast(
+        tui::app::ToastLevel::Info,
+        format!(
+            "Prefetching {} {} in the background",
+            package, entry.version
+        ),
+    );
+    app.pending_pins.insert(id, (package.to_string(), entry));
+}
+
+fn pending_pin_labels(app: &tui::app::App) -> Vec<String> {
+    app.pending_pins
+        .values()
+        .map(|(package, entry)| format!("{} {}", package, entry.version))
+        .collect()
+}
+
+/// Version pins still prefetching are not part of a save; they are applied
+/// once their job finishes and need another save.
+pub(crate) fn warn_pending_pins(app: &mut tui::app::App) {
+    let pending = pending_pin_labels(app);
+    if !pending.is_empty() {
+        app.notify(
+            tui::app::ToastLevel::Warning,
+            format!(
+                "Still prefetching, not saved yet: {}; save again once done",
+                pending.join(", ")
+            ),
+        );
+    }
+}
+
+/// Cancels prefetches still running at quit, waiting for their
+/// `nix-prefetch-url` to be killed, and names the pins lost with them.
+pub(crate) fn drop_pending_pins(app: &mut tui::app::App, output: &Output) {
+    let pending = pending_pin_labels(app);
+    if pending.is_empty() {
+        return;
+    }
+    let ids: Vec<u64> = app.pending_pins.keys().copied().collect();
+    for id in ids {
+        app.jobs.cancel_and_wait(id);
+    }
+    app.pending_pins.clear();
+    output.warn(format!(
+        "warning: quit while prefetching, dropped version pins: {}",
+        pending.join(", ")
+    ));
+}
+
+pub(crate) fn finish_version_selection(
+    app: &mut tui::app::App,
+    package: &str,
+    entry: tui::app::VersionPickerEntry,
+    sha256: String,
+    now: DateTime<Utc>,
+) {
+    let pin = Pin {
+        name: None,
+        url: entry.url,
+        rev: entry.commit,
+        sha256,
+        branch: entry.branch,
+        updated: now.date_naive(),
+        channel: None,
+    };
+    app.pinned.insert(
+        package.to_string(),
+        PinnedPackage {
+            version: entry.version,
+            pin,
+        },
+    );
+    app.added.remove(package);
+    app.removed.remove(package);
+    app.update_dirty();
+}
+
+pub(crate) fn resolve_pinned_version(package: &str, pin: &Pin) -> Result<Option<String>, CliError> {
+    let versions_path = versions_db_path()?;
+    if !versions_path.exists() {
+        return Ok(None);
+    }
+    let conn = open_versions_db(&versions_path).map_err(CliError::Index)?;
+    let source = pin_source_label(pin);
+    if let Some(entry) =
+        version_for_commit(&conn, package, &source, &pin.rev).map_err(CliError::Index)?
+    {
+        return Ok(Some(entry.version));
+    }
+    if let Some(entry) =
+        latest_version_for_source(&conn, package, &source).map_err(CliError::Index)?
+    {
+        return Ok(Some(entry.version));
+    }
+    Ok(None)
+}
+
+pub(crate) fn apply_env_input(state: &mut tui::app::EnvEditorState) -> Result<(), String> {
+    let input = state.input.trim();
+    if input.is_empty() {
+        return Err("entry cannot be empty".to_string());
+    }
+    let (raw_key, raw_value) = input
+        .split_once('=')
+        .ok_or_else(|| "use KEY=VALUE".to_string())?;
+    let key = raw_key.trim();
+    if key.is_empty() {
+        return Err("key cannot be empty".to_string());
+    }
+    let raw_value = raw_value.trim();
+
+    let (original, value_mode) = match &state.mode {
+        tui::app::EnvEditMode::Edit {
+            original_key,
+            value_mode,
+        } => (original_key.clone(), *value_mode),
+        _ => (None, tui::app::EnvValueMode::String),
+    };
+    let value = encode_env_editor_value(raw_value, value_mode)?;
+
+    if let Some(original_key) = &original {
+        if original_key != key && state.entries.iter().any(|entry| entry.key == key) {
+            return Err("key already exists".to_string());
+        }
+        if let Some(entry) = state
+            .entries
+            .iter_mut()
+            .find(|entry| &entry.key == original_key)
+        {
+            entry.key = key.to_string();
+            entry.value = value;
+        } else {
+            state.entries.push(tui::app::EnvEntry {
+                key: key.to_string(),
+                value,
+            });
+        }
+    } else {
+        if state.entries.iter().any(|entry| entry.key == key) {
+            return Err("key already exists".to_string());
+        }
+        state.entries.push(tui::app::EnvEntry {
+            key: key.to_string(),
+            value,
+        });
+    }
+
+    state.entries.sort_by(|a, b| a.key.cmp(&b.key));
+    state.cursor = state
+        .entries
+        .iter()
+        .position(|entry| entry.key == key)
+        .unwrap_or(0);
+    state.mode = tui::app::EnvEditMode::List;
+    state.input.clear();
+    state.input_cursor = 0;
+    state.error = None;
+    Ok(())
+}
+
+pub(crate) fn apply_env_overlay(app: &mut tui::app::App, state: tui::app::EnvEditorState) {
+    let mut env = BTreeMap::new();
+    for entry in state.entries {
+        let key = entry.key;
+        let value = entry.value;
+        if !key.trim().is_empty() {
+            env.insert(key, value);
+        }
+    }
+    app.env = env;
+    app.update_dirty();
+}
+
+pub(crate) fn env_value_mode_from_stored(value: &str) -> tui::app::EnvValueMode {
+    if value.starts_with(NIX_EXPR_PREFIX) || is_legacy_nix_expression_value(value) {
+        tui::app::EnvValueMode::NixExpression
+    } else {
+        tui::app::EnvValueMode::String
+    }
+}
+
+pub(crate) fn env_value_for_editor(value: &str) -> String {
+    value
+        .strip_prefix(NIX_EXPR_PREFIX)
+        .unwrap_or(value)
+        .to_string()
+}
+
+pub(crate) fn encode_env_editor_value(
+    raw: &str,
+    mode: tui::app::EnvValueMode,
+) -> Result<String, String> {
+    match mode {
+        tui::app::EnvValueMode::String => Ok(raw.to_string()),
+        tui::app::EnvValueMode::NixExpression => {
+            if raw.trim().is_empty() {
+                return Err("expression cannot be empty".to_string());
+            }
+            Ok(format!("{}{}", NIX_EXPR_PREFIX, raw.trim()))
+        }
+    }
+}
+
+pub(crate) fn is_legacy_nix_expression_value(value: &str) -> bool {
+    let trimmed = value.trim();
+    (trimmed.len() >= 2
+        && trimmed.starts_with('\"')
+        && trimmed.ends_with('\"')
+        && contains_unescaped_nix_interpolation(trimmed))
+        || (trimmed.len() >= 4 && trimmed.starts_with("''") && trimmed.ends_with("''"))
+}
+
+pub(crate) fn contains_unescaped_nix_interpolation(value: &str) -> bool {
+    let bytes = value.as_bytes();
+    let mut idx = 0usize;
+    while idx + 1 < bytes.len() {
+        if bytes[idx] == b'$' && bytes[idx + 1] == b'{' && (idx == 0 || bytes[idx - 1] != b'\\') {
+            return true;
+        }
+        idx += 1;
+    }
+    false
+}
+
+pub(crate) fn apply_shell_overlay(app: &mut tui::app::App, lines: &[String]) {
+    let combined = lines.join("\n");
+    if combined.trim().is_empty() {
+        app.shell_hook = None;
+    } else {
+        app.shell_hook = Some(combined);
+    }
+    app.update_dirty();
+}
+
+pub(crate) fn edit_text_lines(state: &mut tui::app::ShellEditorState, key: KeyEvent) {
+    match key.code {
+        KeyCode::Up if state.cursor_row > 0 => {
+            state.cursor_row -= 1;
+            let line_len = state.lines[state.cursor_row].len();
+            state.cursor_col = state.cursor_col.min(line_len);
+        }
+        KeyCode::Down if state.cursor_row + 1 < state.lines.len() => {
+            state.cursor_row += 1;
+            let line_len = state.lines[state.cursor_row].len();
+            state.cursor_col = state.cursor_col.min(line_len);
+        }
+        KeyCode::Left => {
+            if state.cursor_col > 0 {
+                state.cursor_col -= 1;
+            } else if state.cursor_row > 0 {
+                state.cursor_row -= 1;
+                state.cursor_col = state.lines[state.cursor_row].len();
+            }
+        }
+        KeyCode::Right => {
+            let line_len = state.lines[state.cursor_row].len();
+            if state.cursor_col < line_len {
+                state.cursor_col += 1;
+            } else if state.cursor_row + 1 < state.lines.len() {
+                state.cursor_row += 1;
+                state.cursor_col = 0;
+            }
+        }
+        KeyCode::Enter => {
+            ensure_shell_lines(state);
+            let current = state.lines.get_mut(state.cursor_row).unwrap();
+            let remainder = current.split_off(state.cursor_col);
+            state.cursor_row += 1;
+            state.cursor_col = 0;
+            state.lines.insert(state.cursor_row, remainder);
+        }
+        KeyCode::Backspace => {
+            ensure_shell_lines(state);
+            if state.cursor_col > 0 {
+                let current = state.lines.get_mut(state.cursor_row).unwrap();
+                current.remove(state.cursor_col - 1);
+                state.cursor_col -= 1;
+            } else if state.cursor_row > 0 {
+                let current = state.lines.remove(state.cursor_row);
+                state.cursor_row -= 1;
+                let prev = state.lines.get_mut(state.cursor_row).unwrap();
+                let prev_len = prev.len();
+                prev.push_str(&current);
+                state.cursor_col = prev_len;
+            }
+        }
+        KeyCode::Char(ch)
+            if !key.modifiers.contains(KeyModifiers::CONTROL)
+                && !key.modifiers.contains(KeyModifiers::ALT) =>
+        {
+            ensure_shell_lines(state);
+            let current = state.lines.get_mut(state.cursor_row).unwrap();
+            current.insert(state.cursor_col, ch);
+            state.cursor_col += 1;
+        }
+        _ => {}
+    }
+}
+
+pub(crate) fn ensure_shell_lines(state: &mut tui::app::ShellEditorState) {
+    if state.lines.is_empty() {
+        state.lines.push(String::new());
+    }
+    if state.cursor_row >= state.lines.len() {
+        state.cursor_row = state.lines.len().saturating_sub(1);
+    }
+    let line_len = state.lines[state.cursor_row].len();
+    if state.cursor_col > line_len {
+        state.cursor_col = line_len;
+    }
+}
+
+pub(crate) fn index_info_from_meta(meta: Vec<(String, String)>) -> tui::app::IndexInfo {
+    let mut info = tui::app::IndexInfo::default();
+    for (key, value) in meta {
+        match key.as_str() {
+            "nixpkgs_url" => info.url = value,
+            "nixpkgs_commit" => info.rev = value,
+            "package_count" => info.count = value.parse().ok(),
+            "generated_at" => info.generated_at = Some(value),
+            "fallback" => info.fallback = value == "true",
+            _ => {}
+        }
+    }
+    info
+}
+
+pub(crate) fn index_info_unknown(value: &str) -> bool {
+    let trimmed = value.trim();
+    trimmed.is_empty() || trimmed.eq_ignore_ascii_case("unknown")
+}
+
+pub(crate) fn index_info_with_pin_fallback(
+    mut info: tui::app::IndexInfo,
+    pins: &[IndexPin],
+) -> tui::app::IndexInfo {
+    let Some(primary) = pins.first() else {
+        return info;
+    };
+    if index_info_unknown(&info.url) {
+        info.url = primary.pin.url.clone();
+    }
+    if index_info_unknown(&info.rev) {
+        info.rev = primary.pin.rev.clone();
+    }
+    info
+}
+
+pub(crate) fn load_tui_presets(
+    pins: &BTreeMap<String, Pin>,
+) -> Result<Vec<tui::app::PresetEntry>, CliError> {
+    let mut presets: Vec<_> = load_all_presets()?
+        .into_iter()
+        .map(|preset| tui::app::PresetEntry {
+            packages_required: preset.required_packages(pins),
+            name: preset.name,
+            description: preset.description,
+            order: preset.order,
+            packages_optional: preset.packages_optional,
+        })
+        .collect();
+    presets.sort_by_key(|preset| preset.order);
+    Ok(presets)
+}
+
+pub(crate) fn push_save_toast(conn: &rusqlite::Connection, app: &mut tui::app::App, message: &str) {
+    let added: Vec<String> = app.added.iter().cloned().collect();
+    match flagged_packages(conn, &added) {
+        Ok(flagged) if !flagged.is_empty() => app.push_toast(
+            tui::app::ToastLevel::Warning,
+            format!("{}; broken/insecure: {}", message, flagged.join(", ")),
+        ),
+        _ => app.push_toast(tui::app::ToastLevel::Info, message),
+    }
+}
+
+fn apply_app_to_state(state: &mut ProjectState, app: &tui::app::App) {
+    state.packages.added = app.added.iter().cloned().collect();
+    state.packages.removed = app.removed.iter().cloned().collect();
+    state.packages.pinned = app.pinned.clone();
+    state.presets.active = app.active_presets.iter().cloned().collect();
+    state.env = app.env.clone();
+    state.shell.hook = app.shell_hook.clone();
+    state.build_env = app.build_env.clone();
+    state.packages.priorities = selected_priorities(app);
+    state.packages.overrides = selected_overrides(app);
+}
+
+pub(crate) fn save_project_from_tui(
+    clock: &dyn Clock,
+    conn: &rusqlite::Connection,
+    paths: &ProjectPaths,
+    state: &mut ProjectState,
+    app: &mut tui::app::App,
+) -> Result<(), CliError> {
+    let mut ours = state.clone();
+    apply_app_to_state(&mut ours, app);
+    save_project_edit(clock, conn, paths, state, app, ours)?;
+    Ok(())
+}
+
+/// Saves `ours`, the TUI's edit of `state`, merging any sections of
+/// `default.nix` edited since `state` was loaded. Returns false when both
+/// sides changed a section and the merge dialog was opened instead.
+fn save_project_edit(
+    clock: &dyn Clock,
+    conn: &rusqlite::Connection,
+    paths: &ProjectPaths,
+    state: &mut ProjectState,
+    app: &mut tui::app::App,
+    ours: ProjectState,
+) -> Result<bool, CliError> {
+    let theirs = match load_project_state(clock, paths) {
+        Ok(theirs) => theirs,
+        Err(_) => state.clone(),
+    };
+    let plan = plan_merge(state, &ours, &theirs);
+    if !plan.iter().any(|entry| entry.theirs_changed) {
+        *state = ours;
+        update_project_modified(clock, state);
+        save_project_state(clock, paths, state)?;
+        app.commit_baseline();
+        push_save_toast(conn, app, "Saved changes");
+        return Ok(true);
+    }
+    let merge = tui::app::MergeState {
+        entries: plan
+            .iter()
+            .map(|entry| tui::app::MergeEntry {
+                section: entry.section,
+                ours_changed: entry.ours_changed,
+                theirs_changed: entry.theirs_changed,
+                choice: entry.default_choice(),
+            })
+            .collect(),
+        cursor: 0,
+        ours: Box::new(ours),
+        theirs: Box::new(theirs),
+    };
+    if plan.iter().any(SectionMerge::is_conflict) {
+        app.overlay = Some(tui::app::Overlay::Merge(merge));
+        return Ok(false);
+    }
+    save_merged_state(clock, conn, paths, state, app, merged_state(&merge))?;
+    Ok(true)
+}
+
+pub(crate) fn merged_state(merge: &tui::app::MergeState) -> ProjectState {
+    merge_states(
+        &merge.ours,
+        &merge.theirs,
+        merge
+            .entries
+            .iter()
+            .map(|entry| (entry.section, entry.choice)),
+    )
+}
+
+fn save_merged_state(
+    clock: &dyn Clock,
+    conn: &rusqlite::Connection,
+    paths: &ProjectPaths,
+    state: &mut ProjectState,
+    app: &mut tui::app::App,
+    mut merged: ProjectState,
+) -> Result<(), CliError> {
+    update_project_modified(clock, &mut merged);
+    save_project_state(clock, paths, &merged)?;
+    *state = merged;
+    apply_state_to_app(app, state);
+    update_search_results(conn, app)?;
+    push_save_toast(conn, app, "Saved and merged changes from default.nix");
+    Ok(())
+}
+
+pub(crate) fn handle_merge_key(
+    key: KeyEvent,
+    app: &mut tui::app::App,
+    mut merge: tui::app::MergeState,
+) -> Option<ProjectState> {
+    let choice = match key.code {
+        KeyCode::Esc | KeyCode::Char('q') => {
+            app.push_toast(tui::app::ToastLevel::Info, "Save cancelled");
+            return None;
+        }
+        KeyCode::Enter => return Some(merged_state(&merge)),
+        KeyCode::Up => {
+            merge.cursor = merge.cursor.saturating_sub(1);
+            None
+        }
+        KeyCode::Down => {
+            merge.cursor = (merge.cursor + 1).min(merge.entries.len().saturating_sub(1));
+            None
+        }
+        KeyCode::Char('o') => Some(MergeChoice::Ours),
+        KeyCode::Char('t') => Some(MergeChoice::Theirs),
+        KeyCode::Char('b') => Some(MergeChoice::Both),
+        _ => None,
+    };
+    if let (Some(choice), Some(entry)) = (choice, merge.entries.get_mut(merge.cursor)) {
+        entry.choice = choice;
+    }
+    app.overlay = Some(tui::app::Overlay::Merge(merge));
+    None
+}
+
+pub(crate) fn save_profile_tui_selection(
+    output: &Output,
+    clock: &dyn Clock,
+    state: &mut GlobalProfileState,
+    app: &mut tui::app::App,
+) -> Result<(), CliError> {
+    state.packages.added = app.added.iter().cloned().collect();
+    state.packages.removed = app.removed.iter().cloned().collect();
+    state.packages.pinned = app.pinned.clone();
+    state.presets.active = app.active_presets.iter().cloned().collect();
+    update_profile_modified(clock, state);
+    save_profile_state(state)?;
+    sync_and_install_profile(output, clock, state)?;
+    app.commit_baseline();
+    Ok(())
+}
+
+pub(crate) fn apply_pin_map_to_app(app: &mut tui::app::App, pins: &[IndexPin]) {
+    app.pin_map.clear();
+    for pin in pins {
+        if let Some(label) = pin.name.as_ref() {
+            app.pin_map.insert(label.clone(), pin.pin.clone());
+        }
+    }
+    if let Some(pin) = &app.filters.pin {
+        if !app.pin_map.contains_key(pin) {
+            app.filters.pin = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::clock::{FixedClock, SystemClock};
+    use crate::commands::tui::{
+        apply_build_env_input, apply_pin_map_to_app, cycle_pin_filter, drop_pending_pins,
+        encode_env_editor_value, env_value_for_editor, env_value_mode_from_stored,
+        handle_change_actions_key, handle_commit_picker_key, handle_goto_key, handle_jobs_key,
+        handle_merge_key, handle_missing_packages_key, handle_override_key,
+        index_info_with_pin_fallback, open_change_actions, open_override_overlay, poll_jobs,
+        save_project_edit, select_filter_chip, selected_overrides, warn_pending_pins,
+    };
+    use crate::indexops::IndexPin;
+    use crate::tui::app::{
+        App, BuildEnvField, ChangeAction, ChangeKind, ChangeSection, CommitPickerEntry,
+        CommitPickerState, Focus, GoToState, JobKind, JobStatus, MergeEntry, MergeState,
+        MissingPackageEntry, MissingPackagesState, Overlay, PackageEntry, ToastLevel,
+        VersionPickerEntry,
+    };
+    use crate::tui::input::{map_key, InputAction};
+    use crate::{sync_project_nix, Output, ProjectPaths};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+    use mica_core::merge::{plan_merge, MergeChoice, MergeSection};
+    use mica_core::state::{BuildEnvState, MicaMetadata, Pin, ProjectState, NIX_EXPR_PREFIX};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn build_env_input_parses_outputs_and_priority() {
+        let mut settings = BuildEnvState::default();
+        apply_build_env_input(&mut settings, BuildEnvField::Outputs, "man, dev man")
+            .expect("outputs rejected");
+        assert_eq!(settings.extra_outputs, vec!["man", "dev"]);
+        apply_build_env_input(&mut settings, BuildEnvField::Priority, "3")
+            .expect("priority rejected");
+        assert_eq!(settings.priority, Some(3));
+        assert!(apply_build_env_input(&mut settings, BuildEnvField::Priority, "high").is_err());
+        apply_build_env_input(&mut settings, BuildEnvField::Name, "  ").expect("name rejected");
+        assert_eq!(settings.name, None);
+    }
+
+    #[test]
+    fn missing_package_overlay_replaces_and_drops() {
+        let mut app = App::new(Vec::new(), Vec::new());
+        app.added.insert("nodejs_16".to_string());
+        app.preset_packages.insert("exa".to_string());
+        let state = MissingPackagesState {
+            rev: "abc".to_string(),
+            entries: vec![
+                MissingPackageEntry {
+                    attr: "nodejs_16".to_string(),
+                    candidates: vec!["nodejs_20".to_string(), "nodejs_22".to_string()],
+                    choice: 0,
+                },
+                MissingPackageEntry {
+                    attr: "exa".to_string(),
+                    candidates: Vec::new(),
+                    choice: 0,
+                },
+            ],
+            cursor: 0,
+        };
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        handle_missing_packages_key(key(KeyCode::Right), &mut app, state);
+        let Some(Overlay::MissingPackages(state)) = app.overlay.take() else {
+            panic!("overlay closed early");
+        };
+        handle_missing_packages_key(key(KeyCode::Enter), &mut app, state);
+        assert!(app.added.contains("nodejs_22"));
+        assert!(!app.added.contains("nodejs_16"));
+
+        let Some(Overlay::MissingPackages(state)) = app.overlay.take() else {
+            panic!("overlay closed early");
+        };
+        handle_missing_packages_key(key(KeyCode::Char('d')), &mut app, state);
+        assert!(app.removed.contains("exa"));
+        assert!(app.overlay.is_none());
+        assert!(app.dirty);
+    }
+
+    #[test]
+    fn jobs_report_results_and_can_be_cancelled() {
+        let mut app = App::new(Vec::new(), Vec::new());
+        app.jobs.spawn(JobKind::IndexRefresh, "refresh", |job| {
+            job.progress(5, Some(10));
+            Ok(Some("Index refreshed".to_string()))
+        });
+        app.jobs.spawn(JobKind::IndexRefresh, "slow", |job| {
+            while !job.is_cancelled() {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+            Err("cancelled".to_string())
+        });
+        let mut refreshed = false;
+        for _ in 0..200 {
+            refreshed |= poll_jobs(&mut app, &SystemClock);
+            if app.jobs.entries[0].status != JobStatus::Running {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(refreshed);
+        assert_eq!(app.toast.as_ref().unwrap().message, "Index refreshed");
+        assert!(app.jobs.is_running(JobKind::IndexRefresh));
+
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        handle_jobs_key(key(KeyCode::Down), &mut app);
+        handle_jobs_key(key(KeyCode::Char('c')), &mut app);
+        assert_eq!(app.jobs.entries[1].status, JobStatus::Cancelled);
+        assert!(!app.jobs.is_running(JobKind::IndexRefresh));
+        assert!(matches!(app.overlay.take(), Some(Overlay::Jobs)));
+        handle_jobs_key(key(KeyCode::Esc), &mut app);
+        assert!(app.overlay.is_none());
+    }
+
+    #[test]
+    fn pending_prefetches_are_named_on_save_and_stopped_on_quit() {
+        let mut app = App::new(Vec::new(), Vec::new());
+        let id = app.jobs.spawn(JobKind::Prefetch, "Prefetching jq", |job| {
+            while !job.is_cancelled() {
+                std::thread::sleep(std::time::Duration::from_millis(5));
+            }
+            Err("cancelled".to_string())
+        });
+        let entry = VersionPickerEntry {
+            source: "nixpkgs".to_string(),
+            version: "1.6".to_string(),
+            commit: "abc".to_string(),
+            commit_date: "2026-01-01".to_string(),
+            branch: "nixpkgs-unstable".to_string(),
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+        };
+        app.pending_pins.insert(id, ("jq".to_string(), entry));
+
+        warn_pending_pins(&mut app);
+        let toast = app.toast.as_ref().unwrap();
+        assert!(matches!(toast.level, ToastLevel::Warning));
+        assert!(toast.message.contains("jq 1.6"), "{}", toast.message);
+
+        let output = Output {
+            quiet: true,
+            verbose: false,
+        };
+        drop_pending_pins(&mut app, &output);
+        assert!(app.pending_pins.is_empty());
+        assert_eq!(app.jobs.entries[0].status, JobStatus::Cancelled);
+    }
+
+    #[test]
+    fn finished_prefetch_jobs_pin_the_pending_version() {
+        let entry = |version: &str| VersionPickerEntry {
+            source: "nixpkgs".to_string(),
+            version: version.to_string(),
+            commit: "abc".to_string(),
+            commit_date: "2026-01-01".to_string(),
+            branch: "nixpkgs-unstable".to_string(),
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+        };
+        let mut app = App::new(Vec::new(), Vec::new());
+        app.added.insert("jq".to_string());
+        let ok = app.jobs.spawn(JobKind::Prefetch, "Prefetching jq", |_| {
+            Ok(Some("sha256-jq".to_string()))
+        });
+        app.pending_pins
+            .insert(ok, ("jq".to_string(), entry("1.6")));
+        let failed = app.jobs.spawn(JobKind::Prefetch, "Prefetching fd", |_| {
+            Err("offline".to_string())
+        });
+        app.pending_pins
+            .insert(failed, ("fd".to_string(), entry("9.0.0")));
+
+        let clock = FixedClock(Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap());
+        for _ in 0..200 {
+            poll_jobs(&mut app, &clock);
+            if app.jobs.running().next().is_none() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+        assert!(app.pending_pins.is_empty());
+        let pinned = app.pinned.get("jq").expect("jq not pinned");
+        assert_eq!(pinned.version, "1.6");
+        assert_eq!(pinned.pin.sha256, "sha256-jq");
+        assert_eq!(
+            pinned.pin.updated,
+            NaiveDate::from_ymd_opt(2026, 3, 1).unwrap()
+        );
+        assert!(!app.added.contains("jq"));
+        assert!(!app.pinned.contains_key("fd"));
+    }
+
+    #[test]
+    fn add_and_pin_keeps_the_package_selected() {
+        let package = |attr: &str| PackageEntry {
+            attr_path: attr.to_string(),
+            name: attr.to_string(),
+            version: Some("1.0".to_string()),
+            description: None,
+            homepage: None,
+            license: None,
+            platforms: None,
+            main_program: None,
+            position: None,
+            broken: false,
+            insecure: false,
+            maintainers: None,
+            last_updated: None,
+        };
+        let mut app = App::new(vec![package("ripgrep"), package("fd")], Vec::new());
+        for modifiers in [KeyModifiers::SHIFT, KeyModifiers::ALT] {
+            assert_eq!(
+                map_key(KeyEvent::new(KeyCode::Enter, modifiers)),
+                InputAction::AddAndPin
+            );
+        }
+
+        app.add_current_package();
+        app.add_current_package();
+        assert!(app.added.contains("ripgrep"));
+
+        app.cursor = 1;
+        app.preset_packages.insert("fd".to_string());
+        app.removed.insert("fd".to_string());
+        app.add_current_package();
+        assert!(!app.removed.contains("fd"));
+        assert!(!app.added.contains("fd"));
+        assert!(app.dirty);
+    }
+
+    #[test]
+    fn override_editor_stores_snippets_for_selected_packages() {
+        let mut app = App::new(
+            vec![PackageEntry {
+                attr_path: "ripgrep".to_string(),
+                name: "ripgrep".to_string(),
+                version: None,
+                description: None,
+                homepage: None,
+                license: None,
+                platforms: None,
+                main_program: None,
+                position: None,
+                broken: false,
+                insecure: false,
+                maintainers: None,
+                last_updated: None,
+            }],
+            Vec::new(),
+        );
+        assert_eq!(
+            map_key(KeyEvent::new(KeyCode::Char('A'), KeyModifiers::SHIFT)),
+            InputAction::EditOverride
+        );
+        open_override_overlay(&mut app);
+        assert!(app.overlay.is_none());
+
+        app.add_current_package();
+        app.commit_baseline();
+        let type_snippet = |app: &mut App, keys: &[KeyCode]| {
+            open_override_overlay(app);
+            for code in keys {
+                let Some(Overlay::Override(state)) = app.overlay.take() else {
+                    panic!("override editor closed");
+                };
+                handle_override_key(KeyEvent::new(*code, KeyModifiers::NONE), app, state);
+            }
+        };
+        let mut keys: Vec<KeyCode> = "doCheck = false;".chars().map(KeyCode::Char).collect();
+        keys.push(KeyCode::Esc);
+        type_snippet(&mut app, &keys);
+        assert!(app.overlay.is_none());
+        assert_eq!(
+            app.overrides.get("ripgrep").map(String::as_str),
+            Some("prev: {\n  doCheck = false;\n}")
+        );
+        assert!(app.dirty);
+
+        type_snippet(&mut app, &[KeyCode::Down, KeyCode::Esc]);
+        assert!(app.overrides.contains_key("ripgrep"));
+
+        app.toggle_current();
+        assert!(selected_overrides(&app).is_empty());
+        app.toggle_current();
+        assert_eq!(selected_overrides(&app).len(), 1);
+    }
+
+    #[test]
+    fn changes_panel_actions_jump_to_and_unstage_changes() {
+        let mut conn = mica_index::generate::init_db(std::path::Path::new(":memory:"))
+            .expect("db init failed");
+        let packages: Vec<mica_index::generate::NixPackage> = ["jq", "ripgrep"]
+            .iter()
+            .map(|attr| mica_index::generate::NixPackage {
+                attr_path: attr.to_string(),
+                name: attr.to_string(),
+                version: None,
+                description: None,
+                homepage: None,
+                license: None,
+                platforms: None,
+                main_program: None,
+                position: None,
+                broken: None,
+                insecure: None,
+                maintainers: None,
+                last_updated: None,
+            })
+            .collect();
+        mica_index::generate::ingest_packages(&mut conn, &packages).expect("ingest failed");
+
+        let mut app = App::new(Vec::new(), Vec::new());
+        app.added.insert("jq".to_string());
+        app.env.insert("A".to_string(), "1".to_string());
+        app.commit_baseline();
+        app.added.insert("ripgrep".to_string());
+        app.added.remove("jq");
+        app.env.insert("A".to_string(), "2".to_string());
+        app.update_dirty();
+
+        app.focus = Focus::Changes;
+        let changes = app.pending_changes();
+        assert_eq!(
+            changes
+                .iter()
+                .map(|change| (change.section, change.kind, change.target.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (ChangeSection::Packages, ChangeKind::Added, "ripgrep"),
+                (ChangeSection::Packages, ChangeKind::Removed, "jq"),
+                (ChangeSection::Env, ChangeKind::Changed, "A"),
+            ]
+        );
+        assert_eq!(
+            app.change_actions(&changes[0]),
+            vec![
+                ChangeAction::JumpTo,
+                ChangeAction::PickVersion,
+                ChangeAction::Unstage
+            ]
+        );
+        assert_eq!(app.affected_section(&changes[2]), "default.nix (mica:env)");
+
+        let press = |app: &mut App, code: KeyCode| {
+            let Some(Overlay::ChangeActions(state)) = app.overlay.take() else {
+                panic!("change actions closed");
+            };
+            handle_change_actions_key(&conn, KeyEvent::new(code, KeyModifiers::NONE), app, state)
+                .expect("change action failed");
+        };
+        app.next();
+        app.next();
+        app.next();
+        assert_eq!(app.changes_cursor, 2);
+        open_change_actions(&mut app);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.env.get("A").map(String::as_str), Some("1"));
+        assert_eq!(app.changes_cursor, 1);
+
+        open_change_actions(&mut app);
+        press(&mut app, KeyCode::Enter);
+        assert_eq!(app.focus, Focus::Packages);
+        assert_eq!(
+            app.current_package().map(|entry| entry.attr_path.as_str()),
+            Some("jq")
+        );
+
+        app.focus = Focus::Changes;
+        app.prev();
+        open_change_actions(&mut app);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Enter);
+        assert!(!app.added.contains("ripgrep"));
+        assert_eq!(app.pending_changes().len(), 1);
+        assert!(app.dirty);
+    }
+
+    #[test]
+    fn commit_picker_returns_the_selected_commit() {
+        let mut app = App::new(Vec::new(), Vec::new());
+        let entry = |rev: &str| CommitPickerEntry {
+            rev: rev.to_string(),
+            date: "2026-01-02T00:00:00Z".to_string(),
+            message: format!("commit {}", rev),
+        };
+        let picker = CommitPickerState {
+            entries: vec![entry("new"), entry("current")],
+            cursor: 1,
+            branch: "nixpkgs-unstable".to_string(),
+            current: "current".to_string(),
+        };
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+
+        assert_eq!(
+            handle_commit_picker_key(key(KeyCode::Enter), &mut app, picker),
+            None
+        );
+        let Some(Overlay::CommitPicker(picker)) = app.overlay.take() else {
+            panic!("picker closed on the current commit");
+        };
+        assert_eq!(
+            handle_commit_picker_key(key(KeyCode::Up), &mut app, picker),
+            None
+        );
+        let Some(Overlay::CommitPicker(picker)) = app.overlay.take() else {
+            panic!("picker closed early");
+        };
+        assert_eq!(
+            handle_commit_picker_key(key(KeyCode::Enter), &mut app, picker),
+            Some("new".to_string())
+        );
+        assert!(app.overlay.is_none());
+    }
+
+    #[test]
+    fn notifications_queue_behind_the_visible_toast() {
+        let mut app = App::new(Vec::new(), Vec::new());
+        app.notify(ToastLevel::Info, "first");
+        app.notify(ToastLevel::Info, "second");
+        app.notify(ToastLevel::Info, "second");
+        app.notify(ToastLevel::Warning, "first");
+        assert_eq!(app.toast.as_ref().unwrap().message, "first");
+        assert_eq!(app.notifications.len(), 1);
+
+        app.toast = None;
+        app.clear_expired_toast();
+        assert_eq!(app.toast.as_ref().unwrap().message, "second");
+        assert!(app.notifications.is_empty());
+    }
+
+    #[test]
+    fn index_info_falls_back_to_primary_pin_when_meta_is_unknown() {
+        let info = crate::tui::app::IndexInfo {
+            url: "unknown".to_string(),
+            rev: "unknown".to_string(),
+            count: None,
+            generated_at: None,
+            displayed_count: None,
+            fallback: false,
+        };
+        let pins = vec![IndexPin {
+            name: None,
+            pin: mica_core::state::Pin {
+                name: None,
+                url: "https://github.com/jpetrucciani/nix".to_string(),
+                rev: "004391ff727d67a4f2e41590b0e8430a306d6688".to_string(),
+                sha256: "sha256-test".to_string(),
+                branch: "main".to_string(),
+                updated: NaiveDate::from_ymd_opt(2026, 2, 8).expect("valid date"),
+                channel: None,
+            },
+        }];
+
+        let merged = index_info_with_pin_fallback(info, &pins);
+        assert_eq!(merged.url, "https://github.com/jpetrucciani/nix");
+        assert_eq!(merged.rev, "004391ff727d67a4f2e41590b0e8430a306d6688");
+    }
+
+    #[test]
+    fn pin_filter_cycles_through_extra_pins() {
+        let mut conn = mica_index::generate::init_db(std::path::Path::new(":memory:"))
+            .expect("db init failed");
+        let packages: Vec<mica_index::generate::NixPackage> = ["jq", "internal.jq", "old.jq"]
+            .iter()
+            .map(|attr| mica_index::generate::NixPackage {
+                attr_path: attr.to_string(),
+                name: attr.to_string(),
+                version: None,
+                description: None,
+                homepage: None,
+                license: None,
+                platforms: None,
+                main_program: None,
+                position: None,
+                broken: None,
+                insecure: None,
+                maintainers: None,
+                last_updated: None,
+            })
+            .collect();
+        mica_index::generate::ingest_packages(&mut conn, &packages).expect("ingest failed");
+        let pin = |name: &str| IndexPin {
+            name: Some(name.to_string()),
+            pin: mica_core::state::Pin {
+                name: None,
+                url: "https://github.com/NixOS/nixpkgs".to_string(),
+                rev: "abc".to_string(),
+                sha256: "sha256-test".to_string(),
+                branch: "main".to_string(),
+                updated: NaiveDate::from_ymd_opt(2026, 2, 8).expect("valid date"),
+                channel: None,
+            },
+        };
+
+        let mut app = App::new(Vec::new(), Vec::new());
+        cycle_pin_filter(&conn, &mut app).expect("cycle failed");
+        assert_eq!(app.filters.pin, None);
+
+        apply_pin_map_to_app(&mut app, &[pin("internal"), pin("old")]);
+        app.query = "jq".to_string();
+        let attrs = |app: &App| -> Vec<String> {
+            app.packages
+                .iter()
+                .map(|pkg| pkg.attr_path.clone())
+                .collect()
+        };
+        cycle_pin_filter(&conn, &mut app).expect("cycle failed");
+        assert_eq!(app.filters.pin.as_deref(), Some("internal"));
+        assert_eq!(attrs(&app), vec!["internal.jq"]);
+        cycle_pin_filter(&conn, &mut app).expect("cycle failed");
+        assert_eq!(attrs(&app), vec!["old.jq"]);
+        cycle_pin_filter(&conn, &mut app).expect("cycle failed");
+        assert_eq!(app.filters.pin, None);
+        assert_eq!(app.packages.len(), 3);
+
+        app.filters.pin = Some("old".to_string());
+        apply_pin_map_to_app(&mut app, &[pin("internal")]);
+        assert_eq!(app.filters.pin, None);
+    }
+
+    #[test]
+    fn filter_chips_toggle_from_alt_digits() {
+        let mut conn = mica_index::generate::init_db(std::path::Path::new(":memory:"))
+            .expect("db init failed");
+        let packages: Vec<mica_index::generate::NixPackage> =
+            [("jq", "MIT"), ("ripgrep", "Unlicense")]
+                .iter()
+                .map(|(attr, license)| mica_index::generate::NixPackage {
+                    attr_path: attr.to_string(),
+                    name: attr.to_string(),
+                    version: None,
+                    description: None,
+                    homepage: None,
+                    license: Some(serde_json::Value::String(license.to_string())),
+                    platforms: None,
+                    main_program: None,
+                    position: None,
+                    broken: None,
+                    insecure: None,
+                    maintainers: None,
+                    last_updated: None,
+                })
+                .collect();
+        mica_index::generate::ingest_packages(&mut conn, &packages).expect("ingest failed");
+
+        assert!(matches!(
+            map_key(KeyEvent::new(KeyCode::Char('1'), KeyModifiers::ALT)),
+            InputAction::FilterChip(1)
+        ));
+        assert!(matches!(
+            map_key(KeyEvent::new(KeyCode::Char('1'), KeyModifiers::NONE)),
+            InputAction::Insert('1')
+        ));
+
+        let mut app = App::new(Vec::new(), Vec::new());
+        app.filter_chips = vec![mica_core::config::FilterChip {
+            name: "mit".to_string(),
+            license: "mit".to_string(),
+            ..mica_core::config::FilterChip::default()
+        }];
+        let attrs = |app: &App| -> Vec<String> {
+            app.packages
+                .iter()
+                .map(|pkg| pkg.attr_path.clone())
+                .collect()
+        };
+
+        select_filter_chip(&conn, &mut app, 1).expect("chip failed");
+        assert_eq!(app.active_filter_chip(), Some(0));
+        assert_eq!(attrs(&app), vec!["jq"]);
+        select_filter_chip(&conn, &mut app, 1).expect("chip failed");
+        assert_eq!(app.active_filter_chip(), None);
+        assert_eq!(app.packages.len(), 2);
+
+        select_filter_chip(&conn, &mut app, 2).expect("chip failed");
+        assert_eq!(app.packages.len(), 2);
+        select_filter_chip(&conn, &mut app, 1).expect("chip failed");
+        select_filter_chip(&conn, &mut app, 0).expect("chip failed");
+        assert_eq!(app.filters.license, "");
+        assert_eq!(app.packages.len(), 2);
+    }
+
+    #[test]
+    fn goto_prompt_selects_filtered_attrs_and_adds_missing_ones() {
+        let mut conn = mica_index::generate::init_db(std::path::Path::new(":memory:"))
+            .expect("db init failed");
+        let packages: Vec<mica_index::generate::NixPackage> =
+            [("jq", "MIT"), ("ripgrep", "Unlicense")]
+                .iter()
+                .map(|(attr, license)| mica_index::generate::NixPackage {
+                    attr_path: attr.to_string(),
+                    name: attr.to_string(),
+                    version: None,
+                    description: None,
+                    homepage: None,
+                    license: Some(serde_json::Value::String(license.to_string())),
+                    platforms: None,
+                    main_program: None,
+                    position: None,
+                    broken: None,
+                    insecure: None,
+                    maintainers: None,
+                    last_updated: None,
+                })
+                .collect();
+        mica_index::generate::ingest_packages(&mut conn, &packages).expect("ingest failed");
+
+        assert!(matches!(
+            map_key(KeyEvent::new(KeyCode::Char('g'), KeyModifiers::CONTROL)),
+            InputAction::GoTo
+        ));
+
+        let mut app = App::new(Vec::new(), Vec::new());
+        app.filters.license = "mit".to_string();
+        let type_attr = |app: &mut App, attr: &str| {
+            let mut state = GoToState::default();
+            for ch in attr.chars() {
+                handle_goto_key(
+                    &conn,
+                    KeyEvent::new(KeyCode::Char(ch), KeyModifiers::NONE),
+                    app,
+                    state,
+                )
+                .expect("goto key failed");
+                let Some(Overlay::GoTo(next)) = app.overlay.take() else {
+                    panic!("goto overlay closed");
+                };
+                state = next;
+            }
+            handle_goto_key(
+                &conn,
+                KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE),
+                app,
+                state,
+            )
+            .expect("goto enter failed");
+        };
+
+        type_attr(&mut app, "ripgrep");
+        assert!(app.overlay.is_none());
+        assert_eq!(
+            app.current_package().map(|pkg| pkg.attr_path.as_str()),
+            Some("ripgrep")
+        );
+        assert_eq!(app.filters.license, "mit");
+
+        type_attr(&mut app, "python3Packages.mica");
+        let Some(Overlay::GoTo(state)) = app.overlay.take() else {
+            panic!("missing attr should keep the prompt open");
+        };
+        assert_eq!(state.missing.as_deref(), Some("python3Packages.mica"));
+        assert!(app.added.is_empty());
+        handle_goto_key(
+            &conn,
+            KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE),
+            &mut app,
+            state,
+        )
+        .expect("goto enter failed");
+        assert!(app.overlay.is_none());
+        assert!(app.added.contains("python3Packages.mica"));
+        assert!(app.dirty);
+    }
+
+    #[test]
+    fn env_expression_values_round_trip_through_editor_helpers() {
+        let stored = format!("{}${{pkgs.path}}/meme", NIX_EXPR_PREFIX);
+        assert_eq!(
+            env_value_mode_from_stored(&stored),
+            crate::tui::app::EnvValueMode::NixExpression
+        );
+        assert_eq!(env_value_for_editor(&stored), "${pkgs.path}/meme");
+    }
+
+    #[test]
+    fn encode_env_editor_value_marks_nix_expressions() {
+        let encoded = encode_env_editor_value(
+            "pkgs.path + \"/meme\"",
+            crate::tui::app::EnvValueMode::NixExpression,
+        )
+        .expect("encode should succeed");
+        assert_eq!(encoded, format!("{}pkgs.path + \"/meme\"", NIX_EXPR_PREFIX));
+    }
+
+    #[test]
+    fn encode_env_editor_value_rejects_empty_expression() {
+        let result = encode_env_editor_value("   ", crate::tui::app::EnvValueMode::NixExpression);
+        assert!(result.is_err());
+    }
+
+    fn test_state() -> ProjectState {
+        ProjectState {
+            mica: MicaMetadata {
+                version: "0.1.0".to_string(),
+                created: Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap(),
+                modified: Utc.with_ymd_and_hms(2026, 1, 1, 0, 0, 0).unwrap(),
+            },
+            pin: Pin {
+                name: None,
+                url: "https://github.com/NixOS/nixpkgs".to_string(),
+                rev: "abc".to_string(),
+                sha256: "sha".to_string(),
+                branch: "nixpkgs-unstable".to_string(),
+                updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+                channel: None,
+            },
+            pins: BTreeMap::new(),
+            presets: Default::default(),
+            packages: Default::default(),
+            env: BTreeMap::new(),
+            shell: Default::default(),
+            nix: Default::default(),
+            extends: None,
+            build_env: Default::default(),
+            manifest: Default::default(),
+        }
+    }
+
+    #[test]
+    fn merge_overlay_applies_section_choices() {
+        let mut base = test_state();
+        base.packages.added = vec!["jq".to_string()];
+        let mut ours = base.clone();
+        ours.packages.added.push("ripgrep".to_string());
+        ours.env.insert("A".to_string(), "ours".to_string());
+        let mut theirs = base.clone();
+        theirs.packages.added.push("fd".to_string());
+        theirs.env.insert("A".to_string(), "theirs".to_string());
+        theirs.pin.rev = "def".to_string();
+
+        let merge = MergeState {
+            entries: plan_merge(&base, &ours, &theirs)
+                .iter()
+                .map(|entry| MergeEntry {
+                    section: entry.section,
+                    ours_changed: entry.ours_changed,
+                    theirs_changed: entry.theirs_changed,
+                    choice: entry.default_choice(),
+                })
+                .collect(),
+            cursor: 0,
+            ours: Box::new(ours),
+            theirs: Box::new(theirs),
+        };
+        let key = |code| KeyEvent::new(code, KeyModifiers::NONE);
+        let mut app = App::new(Vec::new(), Vec::new());
+
+        assert_eq!(merge.entries[0].section, MergeSection::Pin);
+        assert_eq!(merge.entries[0].choice, MergeChoice::Theirs);
+        assert!(handle_merge_key(key(KeyCode::Down), &mut app, merge).is_none());
+        let Some(Overlay::Merge(merge)) = app.overlay.take() else {
+            panic!("overlay closed early");
+        };
+        assert!(handle_merge_key(key(KeyCode::Char('b')), &mut app, merge).is_none());
+        let Some(Overlay::Merge(merge)) = app.overlay.take() else {
+            panic!("overlay closed early");
+        };
+        assert!(handle_merge_key(key(KeyCode::Down), &mut app, merge).is_none());
+        let Some(Overlay::Merge(merge)) = app.overlay.take() else {
+            panic!("overlay closed early");
+        };
+        assert!(handle_merge_key(key(KeyCode::Char('t')), &mut app, merge).is_none());
+        let Some(Overlay::Merge(merge)) = app.overlay.take() else {
+            panic!("overlay closed early");
+        };
+        let merged = handle_merge_key(key(KeyCode::Enter), &mut app, merge).unwrap();
+        assert_eq!(merged.packages.added, vec!["jq", "fd", "ripgrep"]);
+        assert_eq!(merged.env.get("A").map(String::as_str), Some("theirs"));
+        assert_eq!(merged.pin.rev, "def");
+        assert!(app.overlay.is_none());
+    }
+
+    #[test]
+    fn pin_edits_open_the_merge_dialog_when_the_file_moved() {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("mica-tui-merge-{}", nanos));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths = ProjectPaths::new(Some(dir.join("default.nix")), None).unwrap();
+        let clock = FixedClock(Utc.with_ymd_and_hms(2026, 2, 1, 0, 0, 0).unwrap());
+        let conn = rusqlite::Connection::open_in_memory().unwrap();
+
+        let mut state = test_state();
+        let mut theirs = state.clone();
+        theirs.pin.rev = "theirs".to_string();
+        sync_project_nix(&clock, &paths, &theirs).unwrap();
+        let written = std::fs::read_to_string(&paths.nix_path).unwrap();
+
+        let mut ours = state.clone();
+        ours.pin.rev = "ours".to_string();
+        let mut app = App::new(Vec::new(), Vec::new());
+        let saved = save_project_edit(&clock, &conn, &paths, &mut state, &mut app, ours).unwrap();
+
+        assert!(!saved);
+        assert!(matches!(app.overlay, Some(Overlay::Merge(_))));
+        assert_eq!(state.pin.rev, "abc");
+        assert_eq!(std::fs::read_to_string(&paths.nix_path).unwrap(), written);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use crate::{daemon_socket_path, CliError};
+use chrono::{DateTime, Utc};
+use mica_core::config::SearchMode;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub(crate) mod server;
+
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "request", rename_all = "snake_case")]
+pub(crate) enum DaemonRequest {
+    Status,
+    Search {
+        query: String,
+        mode: SearchMode,
+        limit: usize,
+    },
+    Presets,
+    Stop,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub(crate) enum DaemonResponse {
+    Status(DaemonStatus),
+    Packages {
+        packages: Vec<DaemonPackage>,
+        /// Index meta, so clients can run the same coverage checks as a
+        /// local search.
+        #[serde(default)]
+        meta: Vec<(String, String)>,
+    },
+    Presets {
+        presets: Vec<DaemonPreset>,
+    },
+    Stopping,
+    Error {
+        message: String,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct DaemonStatus {
+    pub(crate) pid: u32,
+    pub(crate) started: DateTime<Utc>,
+    pub(crate) requests: u64,
+    pub(crate) index_path: PathBuf,
+    pub(crate) index_loaded: bool,
+    pub(crate) index_reloads: u64,
+    pub(crate) presets: usize,
+    pub(crate) projects: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct DaemonPackage {
+    pub(crate) attr_path: String,
+    pub(crate) version: Option<String>,
+    pub(crate) description: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct DaemonPreset {
+    pub(crate) name: String,
+    pub(crate) description: String,
+    pub(crate) order: i32,
+    pub(crate) required: usize,
+    pub(crate) optional: usize,
+    pub(crate) source: PathBuf,
+}
+
+pub(crate) fn send_request(
+    socket: &Path,
+    request: &DaemonRequest,
+) -> Result<DaemonResponse, CliError> {
+    let mut stream = UnixStream::connect(socket).map_err(CliError::DaemonIo)?;
+    stream
+        .set_read_timeout(Some(CLIENT_TIMEOUT))
+        .map_err(CliError::DaemonIo)?;
+    stream
+        .set_write_timeout(Some(CLIENT_TIMEOUT))
+        .map_err(CliError::DaemonIo)?;
+    write_message(&mut stream, request)?;
+    let mut reader = BufReader::new(stream);
+    read_message(&mut reader)?.ok_or_else(|| {
+        CliError::DaemonProtocol("daemon closed the connection without replying".to_string())
+    })
+}
+
+pub(crate) fn try_request(request: &DaemonRequest) -> Option<DaemonResponse> {
+    if std::env::var_os("MICA_NO_DAEMON").is_some() {
+        return None;
+    }
+    let socket = daemon_socket_path().ok()?;
+    if !socket.exists() {
+        return None;
+    }
+    match send_request(&socket, request).ok()? {
+        DaemonResponse::Error { .. } => None,
+        response => Some(response),
+    }
+}
+
+pub(crate) fn write_message<T: Serialize>(
+    writer: &mut impl Write,
+    message: &T,
+) -> Result<(), CliError> {
+    let mut line =
+        serde_json::to_string(message).map_err(|err| CliError::DaemonProtocol(err.to_string()))?;
+    line.push('\n');
+    writer
+        .write_all(line.as_bytes())
+        .map_err(CliError::DaemonIo)?;
+    writer.flush().map_err(CliError::DaemonIo)
+}
+
+pub(crate) fn read_message<T: for<'de> Deserialize<'de>>(
+    reader: &mut impl BufRead,
+) -> Result<Option<T>, CliError> {
+    let mut line = String::new();
+    let read = reader.read_line(&mut line).map_err(CliError::DaemonIo)?;
+    if read == 0 {
+        return Ok(None);
+    }
+    serde_json::from_str(line.trim_end())
+        .map(Some)
+        .map_err(|err| CliError::DaemonProtocol(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::daemon::{read_message, write_message, DaemonRequest, DaemonResponse};
+    use mica_core::config::SearchMode;
+    use std::io::Cursor;
+
+    #[test]
+    fn requests_round_trip_as_json_lines() {
+        let request = DaemonRequest::Search {
+            query: "ripgrep".to_string(),
+            mode: SearchMode::Binary,
+            limit: 25,
+        };
+        let mut buffer = Vec::new();
+        write_message(&mut buffer, &request).expect("write failed");
+        write_message(&mut buffer, &DaemonRequest::Stop).expect("write failed");
+        assert!(buffer.ends_with(b"{\"request\":\"stop\"}\n"));
+
+        let mut reader = Cursor::new(buffer);
+        let first: Option<DaemonRequest> = read_message(&mut reader).expect("read failed");
+        let second: Option<DaemonRequest> = read_message(&mut reader).expect("read failed");
+        let third: Option<DaemonRequest> = read_message(&mut reader).expect("read failed");
+        assert_eq!(first, Some(request));
+        assert_eq!(second, Some(DaemonRequest::Stop));
+        assert_eq!(third, None);
+    }
+
+    #[test]
+    fn packages_response_without_meta_still_parses() {
+        let mut reader = Cursor::new(b"{\"response\":\"packages\",\"packages\":[]}\n".to_vec());
+        let response: Option<DaemonResponse> = read_message(&mut reader).expect("read failed");
+        assert_eq!(
+            response,
+            Some(DaemonResponse::Packages {
+                packages: Vec::new(),
+                meta: Vec::new(),
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_response_is_a_protocol_error() {
+        let mut reader = Cursor::new(b"{\"response\":\"nope\"}\n".to_vec());
+        let result: Result<Option<DaemonResponse>, _> = read_message(&mut reader);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,244 @@
+use crate::clock::{Clock, SystemClock};
+use crate::daemon::{
+    read_message, send_request, write_message, DaemonPackage, DaemonPreset, DaemonRequest,
+    DaemonResponse, DaemonStatus,
+};
+use crate::indexops::collect_index_pins_profile;
+use crate::indexops::remote::{maybe_refresh_remote_index, primary_pin_rev};
+use crate::{
+    daemon_socket_path, index_db_path, load_config_or_default, load_profile_state,
+    load_shared_presets, registry_path, to_index_search_mode, CliError, Output,
+};
+use chrono::{DateTime, Utc};
+use mica_core::preset::Preset;
+use mica_core::registry::ProjectRegistry;
+use mica_index::generate::{get_meta, open_db, search_packages_with_mode};
+use rusqlite::Connection;
+use std::io::BufReader;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+const REMOTE_CHECK_INTERVAL: Duration = Duration::from_secs(300);
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(2);
+
+struct DaemonState {
+    started: DateTime<Utc>,
+    requests: u64,
+    index_path: PathBuf,
+    conn: Option<Connection>,
+    index_mtime: Option<SystemTime>,
+    index_reloads: u64,
+    presets: Vec<Preset>,
+    registry_path: PathBuf,
+    registry: ProjectRegistry,
+    registry_mtime: Option<SystemTime>,
+    last_refresh: Instant,
+}
+
+impl DaemonState {
+    fn load(
+        clock: &dyn Clock,
+        index_path: PathBuf,
+        registry_path: PathBuf,
+    ) -> Result<DaemonState, CliError> {
+        let mut state = DaemonState {
+            started: clock.now(),
+            requests: 0,
+            index_path,
+            conn: None,
+            index_mtime: None,
+            index_reloads: 0,
+            presets: load_shared_presets()?,
+            registry_path,
+            registry: ProjectRegistry::default(),
+            registry_mtime: None,
+            last_refresh: Instant::now(),
+        };
+        state.reload_index();
+        state.reload_registry();
+        Ok(state)
+    }
+
+    fn refresh(&mut self, output: &Output) {
+        if self.last_refresh.elapsed() < REFRESH_INTERVAL {
+            return;
+        }
+        self.last_refresh = Instant::now();
+        if modified_time(&self.index_path) != self.index_mtime {
+            output.verbose("daemon: index changed on disk, reopening");
+            self.reload_index();
+        }
+        if modified_time(&self.registry_path) != self.registry_mtime {
+            self.reload_registry();
+        }
+        match load_shared_presets() {
+            Ok(presets) => self.presets = presets,
+            Err(err) => output.warn(format!("warning: daemon failed to reload presets: {}", err)),
+        }
+    }
+
+    fn reload_index(&mut self) {
+        self.index_mtime = modified_time(&self.index_path);
+        self.conn = if self.index_mtime.is_some() {
+            open_db(&self.index_path).ok()
+        } else {
+            None
+        };
+        self.index_reloads += 1;
+    }
+
+    fn reload_registry(&mut self) {
+        self.registry_mtime = modified_time(&self.registry_path);
+        self.registry = ProjectRegistry::load_from_path(&self.registry_path).unwrap_or_default();
+    }
+
+    fn handle(&mut self, request: DaemonRequest) -> DaemonResponse {
+        self.requests += 1;
+        match request {
+            DaemonRequest::Status => DaemonResponse::Status(DaemonStatus {
+                pid: std::process::id(),
+                started: self.started,
+                requests: self.requests,
+                index_path: self.index_path.clone(),
+                index_loaded: self.conn.is_some(),
+                index_reloads: self.index_reloads,
+                presets: self.presets.len(),
+                projects: self.registry.projects.keys().cloned().collect(),
+            }),
+            DaemonRequest::Search { query, mode, limit } => {
+                let Some(conn) = &self.conn else {
+                    return DaemonResponse::Error {
+                        message: format!("missing index at {}", self.index_path.display()),
+                    };
+                };
+                match search_packages_with_mode(conn, &query, limit, to_index_search_mode(&mode)) {
+                    Ok(results) => DaemonResponse::Packages {
+                        meta: get_meta(conn).unwrap_or_default(),
+                        packages: results
+                            .into_iter()
+                            .map(|pkg| DaemonPackage {
+                                attr_path: pkg.attr_path,
+                                version: pkg.version,
+                                description: pkg.description,
+                            })
+                            .collect(),
+                    },
+                    Err(err) => DaemonResponse::Error {
+                        message: err.to_string(),
+                    },
+                }
+            }
+            DaemonRequest::Presets => DaemonResponse::Presets {
+                presets: self
+                    .presets
+                    .iter()
+                    .map(|preset| DaemonPreset {
+                        name: preset.name.clone(),
+                        description: preset.description.clone(),
+                        order: preset.order,
+                        required: preset.packages_required.len(),
+                        optional: preset.packages_optional.len(),
+                        source: preset.source.clone(),
+                    })
+                    .collect(),
+            },
+            DaemonRequest::Stop => DaemonResponse::Stopping,
+        }
+    }
+}
+
+pub(crate) fn serve(output: &Output, clock: &dyn Clock) -> Result<(), CliError> {
+    let socket = daemon_socket_path()?;
+    if socket.exists() {
+        if send_request(&socket, &DaemonRequest::Status).is_ok() {
+            return Err(CliError::DaemonRunning(socket));
+        }
+        std::fs::remove_file(&socket).map_err(CliError::DaemonIo)?;
+    }
+    if let Some(parent) = socket.parent() {
+        std::fs::create_dir_all(parent).map_err(CliError::DaemonIo)?;
+    }
+    let mut state = DaemonState::load(clock, index_db_path()?, registry_path()?)?;
+    let listener = UnixListener::bind(&socket).map_err(CliError::DaemonIo)?;
+    spawn_remote_refresh(*output, state.index_path.clone());
+    output.status(format!("mica daemon listening on {}", socket.display()));
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                output.warn(format!(
+                    "warning: daemon failed to accept connection: {}",
+                    err
+                ));
+                continue;
+            }
+        };
+        state.refresh(output);
+        match handle_connection(stream, &mut state) {
+            Ok(true) => break,
+            Ok(false) => {}
+            Err(err) => output.verbose(format!("daemon: dropped connection: {}", err)),
+        }
+    }
+
+    let _ = std::fs::remove_file(&socket);
+    output.status("mica daemon stopped");
+    Ok(())
+}
+
+fn handle_connection(stream: UnixStream, state: &mut DaemonState) -> Result<bool, CliError> {
+    stream
+        .set_read_timeout(Some(CONNECTION_TIMEOUT))
+        .map_err(CliError::DaemonIo)?;
+    let mut reader = BufReader::new(stream.try_clone().map_err(CliError::DaemonIo)?);
+    let mut writer = stream;
+    let request = match read_message::<DaemonRequest>(&mut reader) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(false),
+        Err(err) => {
+            let response = DaemonResponse::Error {
+                message: err.to_string(),
+            };
+            write_message(&mut writer, &response)?;
+            return Ok(false);
+        }
+    };
+    let stop = request == DaemonRequest::Stop;
+    let response = state.handle(request);
+    write_message(&mut writer, &response)?;
+    Ok(stop)
+}
+
+fn spawn_remote_refresh(output: Output, index_path: PathBuf) {
+    std::thread::spawn(move || loop {
+        if let Ok(config) = load_config_or_default() {
+            let pins = load_profile_state()
+                .map(|state| collect_index_pins_profile(&state))
+                .unwrap_or_default();
+            // The daemon outlives any one command, so it checks against
+            // the wall clock rather than a frozen SOURCE_DATE_EPOCH.
+            if let Err(err) = maybe_refresh_remote_index(
+                &output,
+                &SystemClock,
+                &config,
+                &index_path,
+                primary_pin_rev(&pins),
+            ) {
+                output.warn(format!(
+                    "warning: daemon remote index check failed: {}",
+                    err
+                ));
+            }
+        }
+        std::thread::sleep(REMOTE_CHECK_INTERVAL);
+    });
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
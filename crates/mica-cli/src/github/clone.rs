@@ -0,0 +1,234 @@
+use crate::github::{parse_github_repo, CommitSummary};
+use crate::{cache_dir, expand_tilde, load_config_or_default, CliError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+use std::process::Stdio;
+
+pub(crate) fn normalize_clone_url(url: &str) -> String {
+    let trimmed = url.trim().trim_end_matches('/');
+    trimmed.strip_suffix(".git").unwrap_or(trimmed).to_string()
+}
+
+pub(crate) fn local_clone_for_url(url: &str) -> Option<PathBuf> {
+    let config = load_config_or_default().ok()?;
+    let wanted = normalize_clone_url(url);
+    let configured = config
+        .nixpkgs
+        .local_clones
+        .iter()
+        .find(|(key, _)| normalize_clone_url(key) == wanted)
+        .and_then(|(_, path)| expand_tilde(path).ok());
+    if let Some(path) = configured {
+        if is_git_dir(&path) {
+            return Some(path);
+        }
+    }
+    if !config.nixpkgs.mirror_clones {
+        return None;
+    }
+    let (owner, repo) = parse_github_repo(url).ok()?;
+    let mirror = cache_dir()
+        .ok()?
+        .join("mirrors")
+        .join(format!("{}-{}.git", owner, repo));
+    if is_git_dir(&mirror) {
+        let _ = run_git(&mirror, &["fetch", "--quiet", "--prune", "origin"]);
+        return Some(mirror);
+    }
+    let parent = mirror.parent()?;
+    std::fs::create_dir_all(parent).ok()?;
+    let mirror_arg = mirror.to_string_lossy().to_string();
+    run_git(parent, &["clone", "--quiet", "--mirror", url, &mirror_arg]).ok()?;
+    Some(mirror)
+}
+
+pub(crate) fn is_git_dir(path: &Path) -> bool {
+    path.join(".git").exists() || (path.join("HEAD").is_file() && path.join("objects").is_dir())
+}
+
+pub(crate) fn run_git(dir: &Path, args: &[&str]) -> Result<String, CliError> {
+    let output = ProcessCommand::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingGit
+            } else {
+                CliError::GitIo(err)
+            }
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CliError::GitFailed(stderr.trim().to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Updates `origin/<branch>` so "latest" is not read from a stale ref.
+pub(crate) fn git_fetch_branch(clone: &Path, branch: &str) -> Result<(), CliError> {
+    run_git(clone, &["fetch", "--quiet", "origin", branch]).map(|_| ())
+}
+
+/// Whether `git archive` of `rev` can differ from GitHub's tarball.
+/// `export-ignore` and `export-subst` attributes change the archive contents,
+/// and GitHub may apply them differently, so the NAR hash would not match.
+pub(crate) fn git_archive_uses_export_attributes(
+    clone: &Path,
+    rev: &str,
+) -> Result<bool, CliError> {
+    let files = run_git(clone, &["ls-tree", "-r", "--name-only", rev])?;
+    for path in files
+        .lines()
+        .filter(|path| *path == ".gitattributes" || path.ends_with("/.gitattributes"))
+    {
+        let attributes = run_git(clone, &["show", &format!("{}:{}", rev, path)])?;
+        if attributes.contains("export-ignore") || attributes.contains("export-subst") {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+pub(crate) fn git_resolve_branch_rev(clone: &Path, branch: &str) -> Result<String, CliError> {
+    let candidates = [
+        format!("refs/remotes/origin/{}", branch),
+        format!("refs/heads/{}", branch),
+    ];
+    let mut last_err = CliError::GitFailed(format!("branch {} not found", branch));
+    for candidate in candidates {
+        let spec = format!("{}^{{commit}}", candidate);
+        match run_git(clone, &["rev-parse", "--verify", "--quiet", &spec]) {
+            Ok(rev) if !rev.is_empty() => return Ok(rev),
+            Ok(_) => {}
+            Err(err) => last_err = err,
+        }
+    }
+    Err(last_err)
+}
+
+pub(crate) fn git_recent_commits(
+    clone: &Path,
+    branch: &str,
+    limit: usize,
+) -> Result<Vec<CommitSummary>, CliError> {
+    let rev = git_resolve_branch_rev(clone, branch)?;
+    let count = format!("--max-count={}", limit);
+    let log = run_git(clone, &["log", &count, "--format=%H%x09%cI%x09%s", &rev])?;
+    Ok(parse_git_log(&log))
+}
+
+pub(crate) fn parse_git_log(log: &str) -> Vec<CommitSummary> {
+    log.lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            let rev = parts.next()?.trim();
+            let date = parts.next()?.trim();
+            if rev.is_empty() {
+                return None;
+            }
+            Some(CommitSummary {
+                rev: rev.to_string(),
+                date: date.to_string(),
+                message: parts.next().unwrap_or_default().trim().to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::github::clone::{
+        git_archive_uses_export_attributes, git_recent_commits, git_resolve_branch_rev,
+        normalize_clone_url,
+    };
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static NEXT_TEMP_REPO_ID: AtomicU64 = AtomicU64::new(0);
+
+    #[test]
+    fn normalize_clone_url_ignores_git_suffix_and_trailing_slash() {
+        assert_eq!(
+            normalize_clone_url("https://github.com/NixOS/nixpkgs.git/"),
+            "https://github.com/NixOS/nixpkgs"
+        );
+        assert_eq!(
+            normalize_clone_url("https://github.com/NixOS/nixpkgs"),
+            "https://github.com/NixOS/nixpkgs"
+        );
+    }
+
+    #[test]
+    fn git_resolve_branch_rev_reads_local_clone() {
+        let dir = std::env::temp_dir().join(format!(
+            "mica-clone-test-{}-{}",
+            std::process::id(),
+            NEXT_TEMP_REPO_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).expect("create temp repo");
+        let git = |args: &[&str]| {
+            std::process::Command::new("git")
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .output()
+        };
+        let Ok(init) = git(&["init", "--quiet", "--initial-branch=main"]) else {
+            let _ = std::fs::remove_dir_all(&dir);
+            return;
+        };
+        if !init.status.success() {
+            let _ = std::fs::remove_dir_all(&dir);
+            return;
+        }
+        std::fs::write(dir.join("default.nix"), "{}\n").expect("write file");
+        git(&["add", "."]).expect("git add");
+        git(&[
+            "-c",
+            "user.name=mica",
+            "-c",
+            "user.email=mica@example.com",
+            "commit",
+            "--quiet",
+            "-m",
+            "init",
+        ])
+        .expect("git commit");
+        let head = git(&["rev-parse", "HEAD"]).expect("rev-parse");
+        let head = String::from_utf8_lossy(&head.stdout).trim().to_string();
+
+        let rev = git_resolve_branch_rev(&dir, "main").expect("resolve failed");
+        assert_eq!(rev, head);
+        assert!(git_resolve_branch_rev(&dir, "missing").is_err());
+
+        let commits = git_recent_commits(&dir, "main", 10).expect("log failed");
+        assert_eq!(commits.len(), 1);
+        assert_eq!(commits[0].rev, head);
+        assert_eq!(commits[0].message, "init");
+        assert!(!git_archive_uses_export_attributes(&dir, &head).expect("attributes"));
+
+        std::fs::create_dir_all(dir.join("docs")).expect("create dir");
+        std::fs::write(dir.join("docs/.gitattributes"), "*.md export-ignore\n")
+            .expect("write attributes");
+        git(&["add", "."]).expect("git add");
+        git(&[
+            "-c",
+            "user.name=mica",
+            "-c",
+            "user.email=mica@example.com",
+            "commit",
+            "--quiet",
+            "-m",
+            "attributes",
+        ])
+        .expect("git commit");
+        let rev = git_resolve_branch_rev(&dir, "main").expect("resolve failed");
+        assert!(git_archive_uses_export_attributes(&dir, &rev).expect("attributes"));
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
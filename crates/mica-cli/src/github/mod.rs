@@ -0,0 +1,583 @@
+use crate::github::clone::{
+    git_fetch_branch, git_recent_commits, git_resolve_branch_rev, local_clone_for_url,
+};
+use crate::CliError;
+use mica_core::state::Pin;
+use reqwest::blocking::Client;
+use serde::Deserialize;
+use std::time::Duration;
+
+pub(crate) mod clone;
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GitHubCommit {
+    sha: String,
+    #[serde(default)]
+    commit: GitHubCommitInfo,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub(crate) struct GitHubCommitInfo {
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    author: Option<GitHubCommitAuthor>,
+    #[serde(default)]
+    committer: Option<GitHubCommitAuthor>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct GitHubCommitAuthor {
+    date: String,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub(crate) struct GitHubRepoInfo {
+    #[serde(default)]
+    default_branch: String,
+}
+
+/// A nixos channel release: its git revision and the `nixexprs.tar.xz` it
+/// ships.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ChannelRelease {
+    pub(crate) rev: String,
+    pub(crate) tarball: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CommitSummary {
+    pub(crate) rev: String,
+    pub(crate) date: String,
+    pub(crate) message: String,
+}
+
+pub(crate) trait GitProvider {
+    fn latest_rev(&self, url: &str, branch: &str) -> Result<String, CliError>;
+    fn commit_date(&self, url: &str, rev: &str) -> Result<String, CliError>;
+    fn channel_release(&self, channel: &str) -> Result<ChannelRelease, CliError>;
+    fn recent_commits(
+        &self,
+        url: &str,
+        branch: &str,
+        limit: usize,
+    ) -> Result<Vec<CommitSummary>, CliError>;
+}
+
+pub(crate) struct GitHubProvider;
+
+impl GitProvider for GitHubProvider {
+    fn latest_rev(&self, url: &str, branch: &str) -> Result<String, CliError> {
+        fetch_latest_github_rev(url, branch)
+    }
+
+    fn commit_date(&self, url: &str, rev: &str) -> Result<String, CliError> {
+        fetch_github_commit_date(url, rev)
+    }
+
+    fn channel_release(&self, channel: &str) -> Result<ChannelRelease, CliError> {
+        fetch_channel_release(channel)
+    }
+
+    fn recent_commits(
+        &self,
+        url: &str,
+        branch: &str,
+        limit: usize,
+    ) -> Result<Vec<CommitSummary>, CliError> {
+        fetch_recent_commits(url, branch, limit)
+    }
+}
+
+pub(crate) fn latest_rev_for_pin(
+    git: &dyn GitProvider,
+    url: &Option<String>,
+    branch: &Option<String>,
+    base_pin: &Pin,
+) -> Result<String, CliError> {
+    let effective_url = url.clone().unwrap_or_else(|| base_pin.url.clone());
+    let mut effective_branch = branch.clone().unwrap_or_else(|| base_pin.branch.clone());
+    if effective_branch.trim().is_empty() {
+        effective_branch = "main".to_string();
+    }
+    git.latest_rev(&effective_url, &effective_branch)
+}
+
+pub(crate) fn fetch_latest_github_rev(url: &str, branch: &str) -> Result<String, CliError> {
+    let requested_branch = if branch.trim().is_empty() {
+        "main"
+    } else {
+        branch.trim()
+    };
+    if let Some(clone) = local_clone_for_url(url) {
+        // An unfetched clone would report whatever origin/<branch> was at its
+        // last fetch, so fall back to the network when the fetch fails.
+        if git_fetch_branch(&clone, requested_branch).is_ok() {
+            if let Ok(rev) = git_resolve_branch_rev(&clone, requested_branch) {
+                return Ok(rev);
+            }
+        }
+    }
+    let (owner, repo) = parse_github_repo(url)?;
+    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+
+    match fetch_github_commit_sha(&client, &owner, &repo, requested_branch) {
+        Ok(rev) => Ok(rev),
+        Err(CliError::GitHubApiStatus(status, body))
+            if should_retry_default_branch_lookup(status, &body) =>
+        {
+            let default_branch = fetch_github_default_branch(&client, &owner, &repo)?;
+            if default_branch.trim().is_empty() || default_branch == requested_branch {
+                return Err(CliError::GitHubApiStatus(status, body));
+            }
+            fetch_github_commit_sha(&client, &owner, &repo, &default_branch)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) fn fetch_recent_commits(
+    url: &str,
+    branch: &str,
+    limit: usize,
+) -> Result<Vec<CommitSummary>, CliError> {
+    let branch = if branch.trim().is_empty() {
+        "main"
+    } else {
+        branch.trim()
+    };
+    if let Some(clone) = local_clone_for_url(url) {
+        if git_fetch_branch(&clone, branch).is_ok() {
+            if let Ok(commits) = git_recent_commits(&clone, branch, limit) {
+                return Ok(commits);
+            }
+        }
+    }
+    let (owner, repo) = parse_github_repo(url)?;
+    let api_url = format!(
+        "https://api.github.com/repos/{}/{}/commits?sha={}&per_page={}",
+        owner,
+        repo,
+        encode_github_ref(branch),
+        limit.clamp(1, 100)
+    );
+    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let response = client
+        .get(&api_url)
+        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
+        .header("Accept", "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", "2022-11-28")
+        .send()?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().unwrap_or_default();
+        return Err(CliError::GitHubApiStatus(status, body));
+    }
+
+    let commits: Vec<GitHubCommit> = response.json()?;
+    Ok(commits
+        .into_iter()
+        .map(|commit| {
+            let date = commit
+                .commit
+                .committer
+                .or(commit.commit.author)
+                .map(|author| author.date)
+                .unwrap_or_default();
+            CommitSummary {
+                rev: commit.sha,
+                date,
+                message: commit
+                    .commit
+                    .message
+                    .lines()
+                    .next()
+                    .unwrap_or_default()
+                    .to_string(),
+            }
+        })
+        .collect())
+}
+
+pub(crate) const NIXOS_CHANNELS_URL: &str = "https://channels.nixos.org";
+
+/// Resolves a channel to its current release. channels.nixos.org redirects
+/// `<channel>/nixexprs.tar.xz` to an immutable release directory, so the
+/// tarball and git revision are both read from where the redirect lands.
+pub(crate) fn fetch_channel_release(channel: &str) -> Result<ChannelRelease, CliError> {
+    let channel = channel.trim();
+    if !is_valid_channel_name(channel) {
+        return Err(CliError::InvalidChannel(channel.to_string()));
+    }
+    let base = std::env::var("MICA_CHANNELS_URL")
+        .ok()
+        .map(|value| value.trim().trim_end_matches('/').to_string())
+        .filter(|value| !value.is_empty())
+        .unwrap_or_else(|| NIXOS_CHANNELS_URL.to_string());
+    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let response = client
+        .head(format!("{}/{}/nixexprs.tar.xz", base, channel))
+        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
+        .send()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(CliError::ChannelStatus(channel.to_string(), status));
+    }
+    let tarball = response.url().to_string();
+
+    let response = client
+        .get(channel_revision_url(&tarball))
+        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
+        .send()?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(CliError::ChannelStatus(channel.to_string(), status));
+    }
+
+    let body = response.text()?;
+    let rev = parse_channel_revision(&body)
+        .ok_or_else(|| CliError::ChannelMissingRev(channel.to_string()))?;
+    Ok(ChannelRelease { rev, tarball })
+}
+
+pub(crate) fn channel_revision_url(tarball: &str) -> String {
+    let release = tarball
+        .strip_suffix("nixexprs.tar.xz")
+        .unwrap_or(tarball)
+        .trim_end_matches('/');
+    format!("{}/git-revision", release)
+}
+
+pub(crate) fn is_valid_channel_name(channel: &str) -> bool {
+    !channel.is_empty()
+        && channel
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '-' | '.' | '_'))
+}
+
+pub(crate) fn parse_channel_revision(body: &str) -> Option<String> {
+    let rev = body.trim();
+    if rev.len() == 40 && rev.chars().all(|ch| ch.is_ascii_hexdigit()) {
+        Some(rev.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
+pub(crate) fn fetch_github_commit_sha(
+    client: &Client,
+    owner: &str,
+    repo: &str,
+    reference: &str,
+) -> Result<String, CliError> {
+    let ref_encoded = encode_github_ref(reference);
+    let api_url = format!(
+        "https://api.github.com/repos/{}/{}/commits/{}",
+        owner, repo, ref_encoded
+    );
+    let response = client
+        .get(&api_url)
+        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
+        .header("Accept", "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", "2022-11-28")
+        .send()?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().unwrap_or_default();
+        return Err(CliError::GitHubApiStatus(status, body));
+    }
+
+    let commit: GitHubCommit = response.json()?;
+    if commit.sha.trim().is_empty() {
+        return Err(CliError::GitHubApiMissingSha);
+    }
+    Ok(commit.sha)
+}
+
+pub(crate) fn fetch_github_commit_date(url: &str, rev: &str) -> Result<String, CliError> {
+    let (owner, repo) = parse_github_repo(url)?;
+    let ref_encoded = encode_github_ref(rev);
+    let api_url = format!(
+        "https://api.github.com/repos/{}/{}/commits/{}",
+        owner, repo, ref_encoded
+    );
+    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let response = client
+        .get(&api_url)
+        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
+        .header("Accept", "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", "2022-11-28")
+        .send()?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().unwrap_or_default();
+        return Err(CliError::GitHubApiStatus(status, body));
+    }
+
+    let commit: GitHubCommit = response.json()?;
+    if let Some(committer) = commit.commit.committer {
+        if !committer.date.trim().is_empty() {
+            return Ok(committer.date);
+        }
+    }
+    if let Some(author) = commit.commit.author {
+        if !author.date.trim().is_empty() {
+            return Ok(author.date);
+        }
+    }
+
+    Err(CliError::GitHubApiMissingDate)
+}
+
+pub(crate) fn fetch_github_default_branch(
+    client: &Client,
+    owner: &str,
+    repo: &str,
+) -> Result<String, CliError> {
+    let api_url = format!("https://api.github.com/repos/{}/{}", owner, repo);
+    let response = client
+        .get(&api_url)
+        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
+        .header("Accept", "application/vnd.github+json")
+        .header("X-GitHub-Api-Version", "2022-11-28")
+        .send()?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().unwrap_or_default();
+        return Err(CliError::GitHubApiStatus(status, body));
+    }
+
+    let repo_info: GitHubRepoInfo = response.json()?;
+    if repo_info.default_branch.trim().is_empty() {
+        return Err(CliError::GitHubApiMissingDefaultBranch);
+    }
+    Ok(repo_info.default_branch)
+}
+
+pub(crate) fn should_retry_default_branch_lookup(status: reqwest::StatusCode, body: &str) -> bool {
+    status == reqwest::StatusCode::UNPROCESSABLE_ENTITY && body.contains("No commit found for SHA")
+}
+
+pub(crate) fn parse_github_repo(url: &str) -> Result<(String, String), CliError> {
+    let trimmed = url.trim();
+    if trimmed.is_empty() {
+        return Err(CliError::InvalidGitHubUrl(url.to_string()));
+    }
+
+    let rest = if let Some(rest) = trimmed.strip_prefix("https://github.com/") {
+        rest
+    } else if let Some(rest) = trimmed.strip_prefix("http://github.com/") {
+        rest
+    } else if let Some(rest) = trimmed.strip_prefix("git@github.com:") {
+        rest
+    } else if let Some(rest) = trimmed.strip_prefix("github.com/") {
+        rest
+    } else {
+        return Err(CliError::InvalidGitHubUrl(trimmed.to_string()));
+    };
+
+    let rest = rest.trim_end_matches('/');
+    let mut split = rest.split(['?', '#']);
+    let rest = match split.next() {
+        Some(value) => value,
+        None => rest,
+    };
+    let mut parts = rest.split('/').filter(|part| !part.is_empty());
+    let owner = parts
+        .next()
+        .ok_or_else(|| CliError::InvalidGitHubUrl(trimmed.to_string()))?;
+    let repo = parts
+        .next()
+        .ok_or_else(|| CliError::InvalidGitHubUrl(trimmed.to_string()))?;
+    if parts.next().is_some() {
+        return Err(CliError::InvalidGitHubUrl(trimmed.to_string()));
+    }
+    let repo = match repo.strip_suffix(".git") {
+        Some(stripped) => stripped,
+        None => repo,
+    };
+    if owner.is_empty() || repo.is_empty() {
+        return Err(CliError::InvalidGitHubUrl(trimmed.to_string()));
+    }
+
+    Ok((owner.to_string(), repo.to_string()))
+}
+
+pub(crate) fn encode_github_ref(reference: &str) -> String {
+    let mut out = String::new();
+    for byte in reference.as_bytes() {
+        match *byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                out.push(*byte as char)
+            }
+            _ => out.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::github::{
+        channel_revision_url, is_valid_channel_name, latest_rev_for_pin, parse_channel_revision,
+        parse_github_repo, should_retry_default_branch_lookup, ChannelRelease, CommitSummary,
+        GitProvider,
+    };
+    use crate::CliError;
+    use chrono::NaiveDate;
+    use mica_core::state::Pin;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct FakeGit {
+        requests: RefCell<Vec<(String, String)>>,
+    }
+
+    impl GitProvider for FakeGit {
+        fn latest_rev(&self, url: &str, branch: &str) -> Result<String, CliError> {
+            self.requests
+                .borrow_mut()
+                .push((url.to_string(), branch.to_string()));
+            Ok("a".repeat(40))
+        }
+
+        fn commit_date(&self, _url: &str, _rev: &str) -> Result<String, CliError> {
+            Ok("2024-01-01T00:00:00Z".to_string())
+        }
+
+        fn channel_release(&self, channel: &str) -> Result<ChannelRelease, CliError> {
+            Err(CliError::InvalidChannel(channel.to_string()))
+        }
+
+        fn recent_commits(
+            &self,
+            _url: &str,
+            _branch: &str,
+            _limit: usize,
+        ) -> Result<Vec<CommitSummary>, CliError> {
+            Ok(Vec::new())
+        }
+    }
+
+    fn base_pin() -> Pin {
+        Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: "b".repeat(40),
+            sha256: "sha".to_string(),
+            branch: String::new(),
+            updated: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            channel: None,
+        }
+    }
+
+    #[test]
+    fn latest_rev_for_pin_uses_provider_with_pin_defaults() {
+        let git = FakeGit::default();
+        let rev = latest_rev_for_pin(&git, &None, &None, &base_pin()).expect("rev");
+        assert_eq!(rev, "a".repeat(40));
+        assert_eq!(
+            git.requests.borrow().as_slice(),
+            &[(
+                "https://github.com/NixOS/nixpkgs".to_string(),
+                "main".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn latest_rev_for_pin_prefers_explicit_url_and_branch() {
+        let git = FakeGit::default();
+        latest_rev_for_pin(
+            &git,
+            &Some("https://github.com/jpetrucciani/nix".to_string()),
+            &Some("develop".to_string()),
+            &base_pin(),
+        )
+        .expect("rev");
+        assert_eq!(
+            git.requests.borrow().as_slice(),
+            &[(
+                "https://github.com/jpetrucciani/nix".to_string(),
+                "develop".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn parse_github_repo_https() {
+        let (owner, repo) =
+            parse_github_repo("https://github.com/jpetrucciani/nix").expect("parse failed");
+        assert_eq!(owner, "jpetrucciani");
+        assert_eq!(repo, "nix");
+    }
+
+    #[test]
+    fn parse_github_repo_git_ssh() {
+        let (owner, repo) =
+            parse_github_repo("git@github.com:jpetrucciani/nix.git").expect("parse failed");
+        assert_eq!(owner, "jpetrucciani");
+        assert_eq!(repo, "nix");
+    }
+
+    #[test]
+    fn parse_github_repo_rejects_non_github() {
+        let result = parse_github_repo("https://example.com/jpetrucciani/nix");
+        assert!(matches!(result, Err(CliError::InvalidGitHubUrl(_))));
+    }
+
+    #[test]
+    fn parse_channel_revision_accepts_git_sha() {
+        let rev = parse_channel_revision("B134951A4C9F3C995FD7BE05F3243F8ECD65D798\n");
+        assert_eq!(
+            rev.as_deref(),
+            Some("b134951a4c9f3c995fd7be05f3243f8ecd65d798")
+        );
+        assert!(parse_channel_revision("<html>not found</html>").is_none());
+        assert!(parse_channel_revision("").is_none());
+    }
+
+    #[test]
+    fn channel_revision_is_read_next_to_the_release_tarball() {
+        assert_eq!(
+            channel_revision_url(
+                "https://releases.nixos.org/nixos/24.05/nixos-24.05.7376.b134951a4c9f/nixexprs.tar.xz"
+            ),
+            "https://releases.nixos.org/nixos/24.05/nixos-24.05.7376.b134951a4c9f/git-revision"
+        );
+    }
+
+    #[test]
+    fn channel_names_reject_path_segments() {
+        assert!(is_valid_channel_name("nixos-24.05"));
+        assert!(is_valid_channel_name("nixpkgs-unstable"));
+        assert!(!is_valid_channel_name("../nixos-24.05"));
+        assert!(!is_valid_channel_name(""));
+    }
+
+    #[test]
+    fn retry_default_branch_lookup_when_commit_is_missing_for_sha() {
+        let body = r#"{"message":"No commit found for SHA: main"}"#;
+        assert!(should_retry_default_branch_lookup(
+            reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+            body
+        ));
+    }
+
+    #[test]
+    fn does_not_retry_default_branch_lookup_for_other_errors() {
+        let body = r#"{"message":"Validation Failed"}"#;
+        assert!(!should_retry_default_branch_lookup(
+            reqwest::StatusCode::UNPROCESSABLE_ENTITY,
+            body
+        ));
+        assert!(!should_retry_default_branch_lookup(
+            reqwest::StatusCode::NOT_FOUND,
+            r#"{"message":"Not Found"}"#
+        ));
+    }
+}
@@ -0,0 +1,454 @@
+use crate::github::clone::{local_clone_for_url, run_git};
+use crate::nixops::{nix_url_rewriter, pin_tarball_url, url_rewrites};
+use crate::{escape_nix_string, index_db_path, CliError, Output};
+use mica_core::config::UrlRewrite;
+use mica_core::state::Pin;
+use mica_index::generate::{find_aliases, open_db, PackageAlias};
+use rnix::ast::{self, HasEntry, InterpolPart};
+use rowan::ast::AstNode;
+use rusqlite::Connection;
+use std::io;
+use std::path::Path;
+use std::process::{Command as ProcessCommand, Stdio};
+
+pub(crate) const ALIASES_FILE: &str = "pkgs/top-level/aliases.nix";
+
+const ALIAS_SOURCE_PREFIXES: [&str; 3] = ["self.", "super.", "pkgs."];
+
+pub(crate) fn parse_aliases(source: &str) -> Vec<PackageAlias> {
+    let root = rnix::Root::parse(source).tree();
+    let Some(set) = root
+        .syntax()
+        .descendants()
+        .filter_map(ast::AttrSet::cast)
+        .max_by_key(|set| set.attrpath_values().count())
+    else {
+        return Vec::new();
+    };
+    let mut aliases = Vec::new();
+    for entry in set.attrpath_values() {
+        let Some(alias) = entry.attrpath().and_then(|path| single_attr_name(&path)) else {
+            continue;
+        };
+        let Some(value) = entry.value() else {
+            continue;
+        };
+        if let Some(parsed) = alias_from_value(&alias, value) {
+            aliases.push(parsed);
+        }
+    }
+    aliases
+}
+
+fn single_attr_name(path: &ast::Attrpath) -> Option<String> {
+    let mut attrs = path.attrs();
+    let attr = attrs.next()?;
+    if attrs.next().is_some() {
+        return None;
+    }
+    match attr {
+        ast::Attr::Ident(ident) => ident.ident_token().map(|token| token.text().to_string()),
+        ast::Attr::Str(value) => string_literal(&value),
+        ast::Attr::Dynamic(_) => None,
+    }
+}
+
+fn alias_from_value(alias: &str, value: ast::Expr) -> Option<PackageAlias> {
+    if let Some(target) = attr_path_expr(&value) {
+        return (target != alias).then(|| PackageAlias {
+            alias: alias.to_string(),
+            target: Some(target),
+            message: None,
+        });
+    }
+    let ast::Expr::Apply(apply) = unwrap_parens(value) else {
+        return None;
+    };
+    let mut args = Vec::new();
+    let mut head = ast::Expr::Apply(apply);
+    while let ast::Expr::Apply(inner) = head {
+        args.push(inner.argument()?);
+        head = unwrap_parens(inner.lambda()?);
+    }
+    args.reverse();
+    let message = args.iter().find_map(|arg| match arg {
+        ast::Expr::Str(value) => string_literal(value),
+        _ => None,
+    });
+    let is_throw = matches!(&head, ast::Expr::Ident(ident)
+        if ident.ident_token().is_some_and(|token| token.text() == "throw"));
+    let target = if is_throw {
+        message
+            .as_deref()
+            .and_then(|message| suggested_attr(message, alias))
+    } else {
+        args.last()
+            .and_then(attr_path_expr)
+            .filter(|target| target != alias)
+    };
+    if !is_throw && message.is_none() {
+        return None;
+    }
+    Some(PackageAlias {
+        alias: alias.to_string(),
+        target,
+        message,
+    })
+}
+
+fn unwrap_parens(expr: ast::Expr) -> ast::Expr {
+    match expr {
+        ast::Expr::Paren(paren) => match paren.expr() {
+            Some(inner) => unwrap_parens(inner),
+            None => ast::Expr::Paren(paren),
+        },
+        other => other,
+    }
+}
+
+fn attr_path_expr(expr: &ast::Expr) -> Option<String> {
+    let text = match unwrap_parens(expr.clone()) {
+        ast::Expr::Ident(ident) => ident.ident_token()?.text().to_string(),
+        ast::Expr::Select(select) if select.default_expr().is_none() => select.to_string(),
+        _ => return None,
+    };
+    let mut path = text.trim();
+    for prefix in ALIAS_SOURCE_PREFIXES {
+        path = path.strip_prefix(prefix).unwrap_or(path);
+    }
+    is_attr_path(path).then(|| path.to_string())
+}
+
+fn string_literal(value: &ast::Str) -> Option<String> {
+    let mut out = String::new();
+    for part in value.normalized_parts() {
+        match part {
+            InterpolPart::Literal(text) => out.push_str(&text),
+            InterpolPart::Interpolation(_) => out.push_str("..."),
+        }
+    }
+    let out = out.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!out.is_empty()).then_some(out)
+}
+
+fn suggested_attr(message: &str, alias: &str) -> Option<String> {
+    let quoted = message
+        .split('\'')
+        .skip(1)
+        .step_by(2)
+        .map(|token| token.trim_start_matches("pkgs."))
+        .filter(|token| is_attr_path(token) && *token != alias)
+        .last();
+    if let Some(token) = quoted {
+        return Some(token.to_string());
+    }
+    for marker in ["renamed to ", "replaced by ", "superseded by ", "use "] {
+        let Some(idx) = message
+            .to_ascii_lowercase()
+            .match_indices(marker)
+            .map(|(idx, _)| idx)
+            .find(|idx| *idx == 0 || message[..*idx].ends_with(' '))
+        else {
+            continue;
+        };
+        let token = message[idx + marker.len()..]
+            .split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches(['.', ',', ';', ')'])
+            .trim_start_matches("pkgs.");
+        if is_attr_path(token) && token != alias {
+            return Some(token.to_string());
+        }
+    }
+    None
+}
+
+fn is_attr_path(value: &str) -> bool {
+    !value.is_empty()
+        && !value.starts_with('.')
+        && !value.ends_with('.')
+        && value.chars().next().is_some_and(|ch| !ch.is_ascii_digit())
+        && value
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '_' | '-' | '.' | '\''))
+}
+
+pub(crate) fn aliases_source_expression(src: &str, rewrites: &[UrlRewrite]) -> String {
+    format!(
+        r#"let
+  src = {src};
+  rewriteUrl = {rewrite_url};
+  lockPath = src + "/flake.lock";
+  lock = if builtins.pathExists lockPath
+    then builtins.fromJSON (builtins.readFile lockPath)
+    else null;
+  locked = if lock != null && lock ? nodes && lock.nodes ? nixpkgs && lock.nodes.nixpkgs ? locked
+    then lock.nodes.nixpkgs.locked
+    else null;
+  nixpkgsSrc = if locked != null && locked ? owner && locked ? repo && locked ? rev && locked ? narHash
+    then builtins.fetchTarball {{
+      url = rewriteUrl "https://github.com/${{locked.owner}}/${{locked.repo}}/archive/${{locked.rev}}.tar.gz";
+      sha256 = locked.narHash;
+    }}
+    else src;
+  found = builtins.filter builtins.pathExists [
+    (src + "/{file}")
+    (nixpkgsSrc + "/{file}")
+  ];
+in if found == [ ] then "" else builtins.readFile (builtins.head found)
+"#,
+        src = src,
+        file = ALIASES_FILE,
+        rewrite_url = nix_url_rewriter(rewrites)
+    )
+}
+
+pub(crate) fn pin_source_expression(pin: &Pin, rewrites: &[UrlRewrite]) -> String {
+    let url = pin_tarball_url(pin, rewrites);
+    format!(
+        "builtins.fetchTarball {{ url = \"{}\"; sha256 = \"{}\"; }}",
+        escape_nix_string(&url),
+        escape_nix_string(&pin.sha256)
+    )
+}
+
+fn read_aliases_source(expression: &str) -> Result<String, CliError> {
+    let command_output = ProcessCommand::new("nix-instantiate")
+        .args(["--eval", "--json", "-E", expression])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixInstantiate
+            } else {
+                CliError::NixInstantiateFailed(err.to_string())
+            }
+        })?;
+    if !command_output.status.success() {
+        let stderr = String::from_utf8_lossy(&command_output.stderr);
+        return Err(CliError::NixInstantiateFailed(format!(
+            "status={}, stderr={}",
+            command_output.status,
+            stderr.trim()
+        )));
+    }
+    serde_json::from_slice(&command_output.stdout)
+        .map_err(|err| CliError::NixInstantiateFailed(err.to_string()))
+}
+
+fn finish_aliases(output: &Output, source: Result<String, CliError>) -> Vec<PackageAlias> {
+    match source {
+        Ok(source) => {
+            let aliases = parse_aliases(&source);
+            output.verbose(format!("loaded {} nixpkgs aliases", aliases.len()));
+            aliases
+        }
+        Err(err) => {
+            output.verbose(format!("skipping nixpkgs aliases: {}", err));
+            Vec::new()
+        }
+    }
+}
+
+pub(crate) fn load_aliases_from_pin(output: &Output, pin: &Pin) -> Vec<PackageAlias> {
+    let rewrites = match url_rewrites() {
+        Ok(rewrites) => rewrites,
+        Err(err) => return finish_aliases(output, Err(err)),
+    };
+    let expression = aliases_source_expression(&pin_source_expression(pin, &rewrites), &rewrites);
+    finish_aliases(output, read_aliases_source(&expression))
+}
+
+pub(crate) fn load_aliases_from_local_repo(output: &Output, repo_path: &Path) -> Vec<PackageAlias> {
+    let src = match std::fs::canonicalize(repo_path) {
+        Ok(path) => format!(
+            "builtins.toPath \"{}\"",
+            escape_nix_string(path.to_string_lossy().as_ref())
+        ),
+        Err(err) => return finish_aliases(output, Err(CliError::ReadNix(err))),
+    };
+    let rewrites = match url_rewrites() {
+        Ok(rewrites) => rewrites,
+        Err(err) => return finish_aliases(output, Err(err)),
+    };
+    finish_aliases(
+        output,
+        read_aliases_source(&aliases_source_expression(&src, &rewrites)),
+    )
+}
+
+pub(crate) fn load_aliases_from_local_clone(output: &Output, pin: &Pin) -> Vec<PackageAlias> {
+    let Some(clone) = local_clone_for_url(&pin.url) else {
+        output.verbose("skipping nixpkgs aliases: no local clone for the pin");
+        return Vec::new();
+    };
+    let object = format!("{}:{}", pin.rev, ALIASES_FILE);
+    finish_aliases(output, run_git(&clone, &["show", &object]))
+}
+
+pub(crate) fn format_alias(alias: &PackageAlias) -> String {
+    match (&alias.target, &alias.message) {
+        (Some(target), _) => format!("{} -> {}", alias.alias, target),
+        (None, Some(message)) => format!("{} ({})", alias.alias, message),
+        (None, None) => alias.alias.clone(),
+    }
+}
+
+pub(crate) fn report_alias_packages(output: &Output, conn: &Connection, attrs: &[String]) {
+    let aliases = match find_aliases(conn, attrs) {
+        Ok(aliases) => aliases,
+        Err(err) => {
+            output.verbose(format!("skipping alias check: {}", err));
+            return;
+        }
+    };
+    if aliases.is_empty() {
+        return;
+    }
+    output.warn(format!(
+        "warning: {} package(s) are deprecated nixpkgs aliases:",
+        aliases.len()
+    ));
+    for alias in &aliases {
+        output.warn(format!("  {}", format_alias(alias)));
+    }
+    for alias in &aliases {
+        if let Some(target) = &alias.target {
+            output.warn(format!(
+                "  fix: mica remove {} && mica add {}",
+                alias.alias, target
+            ));
+        }
+    }
+}
+
+pub(crate) fn warn_alias_packages(output: &Output, attrs: &[String]) {
+    let Ok(index_path) = index_db_path() else {
+        return;
+    };
+    if !index_path.exists() {
+        return;
+    }
+    match open_db(&index_path) {
+        Ok(conn) => report_alias_packages(output, &conn, attrs),
+        Err(err) => output.verbose(format!("skipping alias check: {}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indexops::aliases::{
+        aliases_source_expression, format_alias, parse_aliases, pin_source_expression,
+        suggested_attr,
+    };
+    use crate::indexops::nixenv::nix_env_expression;
+    use chrono::NaiveDate;
+    use mica_core::config::UrlRewrite;
+    use mica_core::state::Pin;
+    use mica_index::generate::PackageAlias;
+
+    #[test]
+    fn parses_renames_throws_and_warnings() {
+        let source = r#"
+lib: self: super:
+
+let
+  mapAliases = aliases: aliases;
+  helper = { a = 1; };
+in
+
+mapAliases ({
+  AusweisApp2 = ausweisapp; # Added 2023-11-08
+  exa = throw "'exa' has been removed because it is unmaintained upstream. Consider using 'eza' instead"; # Added 2023-09-07
+  nodejs-16_x = nodejs_16;
+  nodejs_14 = throw "nodejs_14 has been removed as it is EOL.";
+  "docker-compose_1" = self.docker-compose;
+  pyqt5 = python3Packages.pyqt5;
+  gnome-firmware-updater = lib.warnOnInstantiate "gnome-firmware-updater was renamed to gnome-firmware" gnome-firmware;
+  ripgrep = ripgrep;
+  inherit (lib) warn;
+})
+"#;
+        let aliases = parse_aliases(source);
+        let rename = |alias: &str, target: &str| PackageAlias {
+            alias: alias.to_string(),
+            target: Some(target.to_string()),
+            message: None,
+        };
+        assert_eq!(
+            aliases,
+            vec![
+                rename("AusweisApp2", "ausweisapp"),
+                PackageAlias {
+                    alias: "exa".to_string(),
+                    target: Some("eza".to_string()),
+                    message: Some(
+                        "'exa' has been removed because it is unmaintained upstream. Consider using 'eza' instead"
+                            .to_string()
+                    ),
+                },
+                rename("nodejs-16_x", "nodejs_16"),
+                PackageAlias {
+                    alias: "nodejs_14".to_string(),
+                    target: None,
+                    message: Some("nodejs_14 has been removed as it is EOL.".to_string()),
+                },
+                rename("docker-compose_1", "docker-compose"),
+                rename("pyqt5", "python3Packages.pyqt5"),
+                PackageAlias {
+                    alias: "gnome-firmware-updater".to_string(),
+                    target: Some("gnome-firmware".to_string()),
+                    message: Some(
+                        "gnome-firmware-updater was renamed to gnome-firmware".to_string()
+                    ),
+                },
+            ]
+        );
+        assert_eq!(format_alias(&aliases[0]), "AusweisApp2 -> ausweisapp");
+        assert_eq!(
+            format_alias(&aliases[3]),
+            "nodejs_14 (nodejs_14 has been removed as it is EOL.)"
+        );
+        assert_eq!(
+            suggested_attr("'foo' has been renamed to bar.", "foo"),
+            Some("bar".to_string())
+        );
+        assert_eq!(
+            suggested_attr("foo was removed because it is old", "foo"),
+            None
+        );
+        assert!(parse_aliases("not { valid nix").is_empty());
+    }
+
+    #[test]
+    fn index_expressions_rewrite_locked_nixpkgs_fetches() {
+        let pin = Pin {
+            name: None,
+            url: "https://github.com/jpetrucciani/nix".to_string(),
+            rev: "abc".to_string(),
+            sha256: "sha".to_string(),
+            branch: "main".to_string(),
+            updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            channel: None,
+        };
+        let rewrites = vec![UrlRewrite {
+            from: "https://github.com/".to_string(),
+            to: "https://mirror.internal/github/".to_string(),
+        }];
+
+        let index = nix_env_expression(&pin, &[], &rewrites);
+        let aliases = aliases_source_expression(&pin_source_expression(&pin, &rewrites), &rewrites);
+
+        for expression in [&index, &aliases] {
+            assert!(expression.contains(
+                "url = \"https://mirror.internal/github/jpetrucciani/nix/archive/abc.tar.gz\";"
+            ));
+            assert!(expression.contains(
+                "{ from = \"https://github.com/\"; to = \"https://mirror.internal/github/\"; }"
+            ));
+            assert!(expression.contains("url = rewriteUrl \"https://github.com/${"));
+        }
+    }
+}
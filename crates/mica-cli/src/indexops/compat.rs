@@ -0,0 +1,172 @@
+use crate::{index_db_path, versions_db_path, CliError, Output};
+use mica_core::state::{check_version, VersionCheck, MICA_VERSION};
+use mica_index::generate::get_meta;
+use mica_index::schema::INDEX_FORMAT;
+use rusqlite::{Connection, OpenFlags};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CacheKind {
+    Index,
+    Versions,
+}
+
+impl CacheKind {
+    pub(crate) fn path(self) -> Result<PathBuf, CliError> {
+        match self {
+            CacheKind::Index => index_db_path(),
+            CacheKind::Versions => versions_db_path(),
+        }
+    }
+
+    fn check(self, path: &Path) -> Option<String> {
+        match self {
+            CacheKind::Index => check_index_cache(path),
+            CacheKind::Versions => check_versions_cache(path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CacheIssue {
+    pub(crate) kind: CacheKind,
+    pub(crate) path: PathBuf,
+    pub(crate) reason: String,
+}
+
+pub(crate) fn index_meta_issue(meta: &[(String, String)]) -> Option<String> {
+    let value = |name: &str| {
+        meta.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+    if let Some(format) = value("index_format") {
+        match format.parse::<u32>() {
+            Ok(format) if format > INDEX_FORMAT => {
+                return Some(format!(
+                    "uses index format {}, this mica reads format {}",
+                    format, INDEX_FORMAT
+                ))
+            }
+            Ok(_) => {}
+            Err(_) => return Some(format!("has an unrecognized index format {:?}", format)),
+        }
+    }
+    match value("mica_version") {
+        Some(version) if check_version(version) == VersionCheck::Newer => Some(format!(
+            "was built by mica {} (this is {})",
+            version, MICA_VERSION
+        )),
+        _ => None,
+    }
+}
+
+fn open_read_only(path: &Path) -> Result<Connection, rusqlite::Error> {
+    Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+}
+
+pub(crate) fn check_index_cache(path: &Path) -> Option<String> {
+    let meta = open_read_only(path)
+        .map_err(mica_index::generate::IndexError::from)
+        .and_then(|conn| get_meta(&conn));
+    match meta {
+        Ok(meta) => index_meta_issue(&meta),
+        Err(err) => Some(format!("could not be read ({})", err)),
+    }
+}
+
+pub(crate) fn check_versions_cache(path: &Path) -> Option<String> {
+    let result = open_read_only(path).and_then(|conn| {
+        conn.query_row("SELECT COUNT(*) FROM indexed_commits", [], |row| {
+            row.get::<_, i64>(0)
+        })
+    });
+    result
+        .err()
+        .map(|err| format!("could not be read ({})", err))
+}
+
+pub(crate) fn cache_issues() -> Result<Vec<CacheIssue>, CliError> {
+    let mut issues = Vec::new();
+    for kind in [CacheKind::Index, CacheKind::Versions] {
+        let path = kind.path()?;
+        if !path.exists() {
+            continue;
+        }
+        if let Some(reason) = kind.check(&path) {
+            issues.push(CacheIssue { kind, path, reason });
+        }
+    }
+    Ok(issues)
+}
+
+pub(crate) fn warn_incompatible_caches(output: &Output) {
+    let issues = match cache_issues() {
+        Ok(issues) => issues,
+        Err(err) => {
+            output.verbose(format!("skipping cache compatibility check: {}", err));
+            return;
+        }
+    };
+    if issues.is_empty() {
+        return;
+    }
+    for issue in &issues {
+        output.warn(format!(
+            "warning: {} {}",
+            issue.path.display(),
+            issue.reason
+        ));
+    }
+    output.warn("warning: run `mica migrate caches` to refresh them");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indexops::compat::{check_index_cache, check_versions_cache, index_meta_issue};
+    use mica_core::state::MICA_VERSION;
+    use mica_index::generate::{init_db, set_meta};
+    use mica_index::versions::init_versions_db;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    #[test]
+    fn newer_or_unreadable_caches_are_reported() {
+        let meta = |format: &str, version: &str| {
+            vec![
+                ("index_format".to_string(), format.to_string()),
+                ("mica_version".to_string(), version.to_string()),
+            ]
+        };
+        assert_eq!(index_meta_issue(&meta("1", MICA_VERSION)), None);
+        assert_eq!(index_meta_issue(&[]), None);
+        assert!(index_meta_issue(&meta("99", MICA_VERSION))
+            .unwrap()
+            .contains("index format 99"));
+        assert!(index_meta_issue(&meta("1", "99.0.0"))
+            .unwrap()
+            .contains("built by mica 99.0.0"));
+
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir = std::env::temp_dir().join(format!("mica-compat-{}", nanos));
+        std::fs::create_dir_all(&dir).unwrap();
+        let index = dir.join("index.db");
+        let conn = init_db(&index).unwrap();
+        set_meta(&conn, "index_format", "1").unwrap();
+        drop(conn);
+        assert_eq!(check_index_cache(&index), None);
+        let versions = dir.join("versions.db");
+        drop(init_versions_db(&versions).unwrap());
+        assert_eq!(check_versions_cache(&versions), None);
+
+        let garbage = dir.join("garbage.db");
+        std::fs::write(&garbage, "not a database, just some text padding it out").unwrap();
+        assert!(check_index_cache(&garbage)
+            .unwrap()
+            .starts_with("could not be read"));
+        assert!(check_versions_cache(&garbage).is_some());
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::clock::{Clock, FixedClock};
+use crate::github::GitHubProvider;
+use crate::indexops::remote::{
+    fetch_remote_index_with_progress, primary_pin_rev, write_index_check_time,
+};
+use crate::indexops::{rebuild_index_from_pins, IndexPin};
+use crate::multiuser::ensure_index_writable;
+use crate::tui::app::{App, JobKind};
+use crate::{CliError, Output};
+use mica_core::config::Config;
+use mica_index::generate::{
+    get_meta, get_package, ingest_packages, init_db, set_meta, IndexError, NixPackage,
+};
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+const FALLBACK_PACKAGES: &str = include_str!("fallback.tsv");
+
+/// Header keys copied from the source index into `fallback.tsv` and back into the fallback meta.
+const FALLBACK_META_KEYS: [&str; 2] = ["nixpkgs_url", "nixpkgs_commit"];
+
+pub(crate) struct FallbackList {
+    pub(crate) meta: Vec<(String, String)>,
+    pub(crate) packages: Vec<NixPackage>,
+}
+
+/// Parses `# key value` header lines and `attr, version, mainProgram, description` rows.
+pub(crate) fn parse_fallback_list(content: &str) -> FallbackList {
+    let mut meta = Vec::new();
+    let mut packages = Vec::new();
+    for line in content.lines() {
+        if let Some(header) = line.strip_prefix('#') {
+            if let Some((key, value)) = header.trim().split_once(' ') {
+                if FALLBACK_META_KEYS.contains(&key) {
+                    meta.push((key.to_string(), value.trim().to_string()));
+                }
+            }
+            continue;
+        }
+        let mut fields = line.splitn(4, '\t');
+        let (Some(attr), Some(version), Some(main_program), Some(description)) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        let attr = attr.trim();
+        if attr.is_empty() {
+            continue;
+        }
+        packages.push(NixPackage {
+            attr_path: attr.to_string(),
+            name: attr.rsplit('.').next().unwrap_or(attr).to_string(),
+            version: non_empty(version),
+            description: non_empty(description),
+            homepage: None,
+            license: None,
+            platforms: None,
+            main_program: non_empty(main_program),
+            position: None,
+            broken: None,
+            insecure: None,
+            maintainers: None,
+            last_updated: None,
+        });
+    }
+    FallbackList { meta, packages }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    Some(value.trim().to_string()).filter(|value| !value.is_empty())
+}
+
+pub(crate) fn bundled_fallback() -> FallbackList {
+    parse_fallback_list(FALLBACK_PACKAGES)
+}
+
+pub(crate) fn open_fallback_index() -> Result<Connection, CliError> {
+    let mut conn = init_db(Path::new(":memory:"))?;
+    let fallback = bundled_fallback();
+    ingest_packages(&mut conn, &fallback.packages)?;
+    set_meta(&conn, "package_count", &fallback.packages.len().to_string())?;
+    set_meta(&conn, "fallback", "true")?;
+    for (key, value) in &fallback.meta {
+        set_meta(&conn, key, value)?;
+    }
+    Ok(conn)
+}
+
+/// Writes `attrs` as a fallback list taken from `conn`, returning the attrs the index lacks.
+pub(crate) fn write_fallback_list(
+    conn: &Connection,
+    index_path: &Path,
+    attrs: &[String],
+    writer: &mut dyn Write,
+) -> Result<Vec<String>, CliError> {
+    let meta: BTreeMap<String, String> = get_meta(conn)?.into_iter().collect();
+    let commit = meta
+        .get("nixpkgs_commit")
+        .filter(|commit| !commit.is_empty() && commit.as_str() != "unknown")
+        .ok_or_else(|| CliError::IndexMissingCommit(index_path.to_path_buf()))?;
+    let url = meta
+        .get("nixpkgs_url")
+        .map(String::as_str)
+        .unwrap_or("unknown");
+    let mut missing = Vec::new();
+    let mut out = String::new();
+    out.push_str("# generated by `mica index fallback`; regenerate instead of editing rows\n");
+    out.push_str(&format!("# nixpkgs_url {}\n", url));
+    out.push_str(&format!("# nixpkgs_commit {}\n", commit));
+    for attr in attrs {
+        let Some(pkg) = get_package(conn, attr)? else {
+            missing.push(attr.clone());
+            continue;
+        };
+        out.push_str(&format!(
+            "{}\t{}\t{}\t{}\n",
+            pkg.attr_path,
+            pkg.version.as_deref().unwrap_or_default(),
+            pkg.main_program.as_deref().unwrap_or_default(),
+            tsv_field(pkg.description.as_deref().unwrap_or_default())
+        ));
+    }
+    writer
+        .write_all(out.as_bytes())
+        .map_err(IndexError::Write)?;
+    Ok(missing)
+}
+
+fn tsv_field(value: &str) -> String {
+    value.replace(['\t', '\n', '\r'], " ")
+}
+
+pub(crate) fn spawn_full_index_build(
+    app: &mut App,
+    clock: &dyn Clock,
+    config: Option<&Config>,
+    index_path: &Path,
+    pins: &[IndexPin],
+) {
+    let remote_url = config
+        .map(|config| config.index.remote_url.trim().to_string())
+        .unwrap_or_default();
+    let index_path = index_path.to_path_buf();
+    let pins = pins.to_vec();
+    let checked_at = clock.now();
+    app.jobs
+        .spawn(JobKind::IndexRefresh, "Building full index", move |job| {
+            ensure_index_writable(&index_path).map_err(|err| err.to_string())?;
+            if !remote_url.is_empty() {
+                let commit = primary_pin_rev(&pins).map(str::to_string);
+                let fetched = fetch_remote_index_with_progress(
+                    &remote_url,
+                    &index_path,
+                    commit.as_deref(),
+                    &mut |done, total| {
+                        job.progress(done, total);
+                        !job.is_cancelled()
+                    },
+                );
+                let _ = write_index_check_time(checked_at);
+                match fetched {
+                    Ok(true) => return Ok(Some("Full index ready".to_string())),
+                    Ok(false) => {}
+                    Err(CliError::Cancelled) => return Err(CliError::Cancelled.to_string()),
+                    Err(_) => {}
+                }
+            }
+            if job.is_cancelled() {
+                return Err(CliError::Cancelled.to_string());
+            }
+            let output = Output {
+                quiet: true,
+                verbose: false,
+            };
+            let building = index_path.with_extension("building");
+            let count = rebuild_index_from_pins(
+                &GitHubProvider,
+                &FixedClock(checked_at),
+                &output,
+                &building,
+                &pins,
+            )
+            .map_err(|err| err.to_string())?;
+            std::fs::rename(&building, &index_path).map_err(|err| err.to_string())?;
+            Ok(Some(format!("Full index ready, {} packages", count)))
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indexops::fallback::{
+        bundled_fallback, open_fallback_index, parse_fallback_list, write_fallback_list,
+    };
+    use mica_index::generate::{get_meta, get_package, ingest_packages, init_db, set_meta};
+    use std::path::Path;
+
+    #[test]
+    fn fallback_index_covers_common_packages() {
+        let packages = bundled_fallback().packages;
+        assert!(packages.len() > 400);
+        let mut attrs: Vec<&str> = packages.iter().map(|pkg| pkg.attr_path.as_str()).collect();
+        attrs.sort();
+        attrs.dedup();
+        assert_eq!(attrs.len(), packages.len());
+
+        let conn = open_fallback_index().unwrap();
+        let ripgrep = get_package(&conn, "ripgrep").unwrap().unwrap();
+        assert_eq!(ripgrep.main_program.as_deref(), Some("rg"));
+        let requests = get_package(&conn, "python3Packages.requests")
+            .unwrap()
+            .unwrap();
+        assert_eq!(requests.name, "requests");
+        assert_eq!(requests.main_program, None);
+        let meta = get_meta(&conn).unwrap();
+        assert!(meta.contains(&("fallback".to_string(), "true".to_string())));
+        assert!(meta.iter().any(|(key, _)| key == "nixpkgs_commit"));
+    }
+
+    #[test]
+    fn fallback_list_round_trips_versions_and_commit() {
+        let mut source = init_db(Path::new(":memory:")).unwrap();
+        let mut packages = parse_fallback_list(
+            "ripgrep\t14.1.0\trg\tSearch tool\njq\t1.7.1\tjq\tJSON processor\n",
+        )
+        .packages;
+        packages[1].description = Some("JSON\tprocessor".to_string());
+        ingest_packages(&mut source, &packages).unwrap();
+        set_meta(&source, "nixpkgs_url", "https://github.com/NixOS/nixpkgs").unwrap();
+        set_meta(&source, "nixpkgs_commit", "abc123").unwrap();
+
+        let attrs = vec![
+            "jq".to_string(),
+            "ripgrep".to_string(),
+            "missing".to_string(),
+        ];
+        let mut written = Vec::new();
+        let missing =
+            write_fallback_list(&source, Path::new("index.db"), &attrs, &mut written).unwrap();
+        let list = parse_fallback_list(&String::from_utf8(written).unwrap());
+
+        assert_eq!(missing, vec!["missing".to_string()]);
+        assert!(list
+            .meta
+            .contains(&("nixpkgs_commit".to_string(), "abc123".to_string())));
+        assert_eq!(list.packages.len(), 2);
+        assert_eq!(list.packages[0].attr_path, "jq");
+        assert_eq!(
+            list.packages[0].description.as_deref(),
+            Some("JSON processor")
+        );
+        assert_eq!(list.packages[1].version.as_deref(), Some("14.1.0"));
+        assert_eq!(list.packages[1].main_program.as_deref(), Some("rg"));
+
+        let unpinned = init_db(Path::new(":memory:")).unwrap();
+        assert!(
+            write_fallback_list(&unpinned, Path::new("index.db"), &attrs, &mut Vec::new()).is_err()
+        );
+    }
+}
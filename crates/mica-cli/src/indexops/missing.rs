@@ -0,0 +1,407 @@
+use crate::indexops::aliases::report_alias_packages;
+use crate::indexops::collect_index_pins;
+use crate::{index_db_path, CliError, Output};
+use mica_core::preset::Preset;
+use mica_core::state::{Pin, ProjectState};
+use mica_index::generate::{
+    find_aliases, get_meta, get_package, open_db, search_packages_with_mode, SearchMode,
+};
+use rusqlite::Connection;
+use std::collections::BTreeMap;
+
+const MAX_RENAME_CANDIDATES: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct MissingPackage {
+    pub(crate) attr: String,
+    pub(crate) candidates: Vec<String>,
+}
+
+pub(crate) fn missing_packages(
+    conn: &Connection,
+    attrs: &[String],
+) -> Result<Vec<MissingPackage>, CliError> {
+    let mut missing = Vec::new();
+    for attr in attrs {
+        if get_package(conn, attr)?.is_some() {
+            continue;
+        }
+        let mut candidates: Vec<String> = find_aliases(conn, std::slice::from_ref(attr))?
+            .into_iter()
+            .filter_map(|alias| alias.target)
+            .collect();
+        for candidate in rename_candidates(conn, attr)? {
+            if !candidates.contains(&candidate) && candidates.len() < MAX_RENAME_CANDIDATES {
+                candidates.push(candidate);
+            }
+        }
+        missing.push(MissingPackage {
+            attr: attr.clone(),
+            candidates,
+        });
+    }
+    Ok(missing)
+}
+
+fn rename_candidates(conn: &Connection, attr: &str) -> Result<Vec<String>, CliError> {
+    let (prefix, leaf) = match attr.rsplit_once('.') {
+        Some((prefix, leaf)) => (Some(prefix), leaf),
+        None => (None, attr),
+    };
+    let stem = attr_stem(leaf);
+    if stem.len() < 2 {
+        return Ok(Vec::new());
+    }
+    let stem_lower = stem.to_lowercase();
+    let mut candidates = Vec::new();
+    for pkg in search_packages_with_mode(conn, stem, 50, SearchMode::Name)? {
+        let candidate_prefix = pkg.attr_path.rsplit_once('.').map(|(prefix, _)| prefix);
+        if pkg.attr_path == attr
+            || candidate_prefix != prefix
+            || !pkg.attr_path.to_lowercase().contains(&stem_lower)
+            || candidates.contains(&pkg.attr_path)
+        {
+            continue;
+        }
+        candidates.push(pkg.attr_path);
+        if candidates.len() == MAX_RENAME_CANDIDATES {
+            break;
+        }
+    }
+    Ok(candidates)
+}
+
+fn attr_stem(leaf: &str) -> &str {
+    leaf.trim_end_matches(|ch: char| ch.is_ascii_digit() || ch == '_' || ch == '-')
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum PresetPackageProblem {
+    UnknownPin { pin: String },
+    Missing { attr: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PresetPackageIssue {
+    pub(crate) preset: String,
+    pub(crate) package: String,
+    pub(crate) problem: PresetPackageProblem,
+}
+
+pub(crate) fn preset_package_issues(
+    conn: &Connection,
+    state: &ProjectState,
+    presets: &[Preset],
+) -> Result<Vec<PresetPackageIssue>, CliError> {
+    let index_pins = collect_index_pins(state);
+    let namespace_for = |pin: &Pin| -> Option<Option<String>> {
+        index_pins
+            .iter()
+            .find(|entry| {
+                entry.pin.url == pin.url
+                    && entry.pin.rev == pin.rev
+                    && entry.pin.sha256 == pin.sha256
+            })
+            .map(|entry| entry.name.clone())
+    };
+    let mut indexed_namespaces: BTreeMap<String, bool> = BTreeMap::new();
+    let mut issues = Vec::new();
+    for preset in presets
+        .iter()
+        .filter(|preset| state.presets.active.contains(&preset.name))
+    {
+        for pkg in &preset.packages_required {
+            let namespace = match preset.package_pins.get(pkg) {
+                Some(pin_name) => match state.pins.get(pin_name) {
+                    Some(pin) => namespace_for(pin).unwrap_or(Some(pin_name.clone())),
+                    None => {
+                        issues.push(PresetPackageIssue {
+                            preset: preset.name.clone(),
+                            package: pkg.clone(),
+                            problem: PresetPackageProblem::UnknownPin {
+                                pin: pin_name.clone(),
+                            },
+                        });
+                        None
+                    }
+                },
+                None => None,
+            };
+            let attr = match &namespace {
+                Some(label) => {
+                    let indexed = match indexed_namespaces.get(label) {
+                        Some(indexed) => *indexed,
+                        None => {
+                            let query = format!("pin:{}", label);
+                            let indexed =
+                                !search_packages_with_mode(conn, &query, 1, SearchMode::All)?
+                                    .is_empty();
+                            indexed_namespaces.insert(label.clone(), indexed);
+                            indexed
+                        }
+                    };
+                    if !indexed {
+                        continue;
+                    }
+                    format!("{}.{}", label, pkg)
+                }
+                None => pkg.clone(),
+            };
+            if get_package(conn, &attr)?.is_none() {
+                issues.push(PresetPackageIssue {
+                    preset: preset.name.clone(),
+                    package: pkg.clone(),
+                    problem: PresetPackageProblem::Missing { attr },
+                });
+            }
+        }
+    }
+    Ok(issues)
+}
+
+pub(crate) fn report_preset_package_issues(
+    output: &Output,
+    state: &ProjectState,
+    presets: &[Preset],
+) {
+    let Ok(index_path) = index_db_path() else {
+        return;
+    };
+    if !index_path.exists() {
+        return;
+    }
+    let issues = match open_db(&index_path)
+        .map_err(CliError::from)
+        .and_then(|conn| preset_package_issues(&conn, state, presets))
+    {
+        Ok(issues) => issues,
+        Err(err) => {
+            output.verbose(format!("skipping preset package check: {}", err));
+            return;
+        }
+    };
+    for issue in issues {
+        match issue.problem {
+            PresetPackageProblem::UnknownPin { pin } => output.warn(format!(
+                "warning: preset {} wants {} from pin {}, which this project does not define, using the base pin",
+                issue.preset, issue.package, pin
+            )),
+            PresetPackageProblem::Missing { attr } => output.warn(format!(
+                "warning: preset {}: {} not found in the index",
+                issue.preset, attr
+            )),
+        }
+    }
+}
+
+pub(crate) fn index_matches_pin(conn: &Connection, pin: &Pin) -> bool {
+    get_meta(conn)
+        .unwrap_or_default()
+        .iter()
+        .any(|(key, value)| key == "nixpkgs_commit" && value == &pin.rev)
+}
+
+pub(crate) fn format_missing_package(entry: &MissingPackage) -> String {
+    if entry.candidates.is_empty() {
+        entry.attr.clone()
+    } else {
+        format!("{} (maybe: {})", entry.attr, entry.candidates.join(", "))
+    }
+}
+
+pub(crate) fn report_missing_packages(output: &Output, pin: &Pin, attrs: &[String]) {
+    let Ok(index_path) = index_db_path() else {
+        return;
+    };
+    if !index_path.exists() {
+        return;
+    }
+    let conn = match open_db(&index_path) {
+        Ok(conn) => conn,
+        Err(err) => {
+            output.verbose(format!("skipping missing package check: {}", err));
+            return;
+        }
+    };
+    if !index_matches_pin(&conn, pin) {
+        output.info(
+            "index does not match the new pin yet, run `mica index fetch` or `mica index rebuild` to check for missing packages",
+        );
+        return;
+    }
+    let missing = match missing_packages(&conn, attrs) {
+        Ok(missing) => missing,
+        Err(err) => {
+            output.verbose(format!("skipping missing package check: {}", err));
+            return;
+        }
+    };
+    let present: Vec<String> = attrs
+        .iter()
+        .filter(|attr| !missing.iter().any(|entry| &entry.attr == *attr))
+        .cloned()
+        .collect();
+    report_alias_packages(output, &conn, &present);
+    if missing.is_empty() {
+        return;
+    }
+    output.warn(format!(
+        "warning: {} package(s) not found at {}:",
+        missing.len(),
+        pin.rev.chars().take(12).collect::<String>()
+    ));
+    for entry in &missing {
+        output.warn(format!("  {}", format_missing_package(entry)));
+    }
+    for entry in &missing {
+        match entry.candidates.first() {
+            Some(candidate) => output.warn(format!(
+                "  fix: mica remove {} && mica add {}",
+                entry.attr, candidate
+            )),
+            None => output.warn(format!("  fix: mica remove {}", entry.attr)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indexops::missing::{
+        attr_stem, missing_packages, preset_package_issues, MissingPackage, PresetPackageProblem,
+    };
+    use mica_core::preset::{Preset, PresetFile};
+    use mica_core::state::ProjectState;
+    use mica_index::generate::{
+        ingest_aliases, ingest_packages, init_db, NixPackage, PackageAlias,
+    };
+    use std::path::{Path, PathBuf};
+
+    fn package(attr: &str) -> NixPackage {
+        NixPackage {
+            attr_path: attr.to_string(),
+            name: attr.to_string(),
+            version: None,
+            description: None,
+            homepage: None,
+            license: None,
+            platforms: None,
+            main_program: None,
+            position: None,
+            broken: None,
+            insecure: None,
+            maintainers: None,
+            last_updated: None,
+        }
+    }
+
+    #[test]
+    fn preset_packages_are_checked_in_their_pin_namespace() {
+        let state: ProjectState = toml::from_str(
+            r#"
+[mica]
+version = "0.1.0"
+created = "2026-01-01T00:00:00Z"
+modified = "2026-01-01T00:00:00Z"
+
+[pin]
+url = "https://github.com/NixOS/nixpkgs"
+rev = "base"
+sha256 = "sha-base"
+branch = "nixpkgs-unstable"
+updated = "2026-01-01"
+
+[pins.stable]
+url = "https://github.com/NixOS/nixpkgs"
+rev = "stable"
+sha256 = "sha-stable"
+branch = "nixos-24.11"
+updated = "2026-01-01"
+
+[presets]
+active = ["node"]
+"#,
+        )
+        .unwrap();
+        let file: PresetFile = toml::from_str(
+            "[preset]\nname = \"node\"\n\n[packages]\nrequired = [\"nodejs\", \"yarn\", \"pnpm\"]\npins = { nodejs = \"stable\", yarn = \"stable\", pnpm = \"edge\" }\n",
+        )
+        .unwrap();
+        let preset = Preset::from_file(file, PathBuf::from("node.toml"));
+
+        let mut conn = init_db(Path::new(":memory:")).unwrap();
+        ingest_packages(
+            &mut conn,
+            &[package("nodejs"), package("pnpm"), package("stable.nodejs")],
+        )
+        .unwrap();
+
+        let issues = preset_package_issues(&conn, &state, &[preset]).unwrap();
+        assert_eq!(
+            issues
+                .into_iter()
+                .map(|issue| (issue.package, issue.problem))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "yarn".to_string(),
+                    PresetPackageProblem::Missing {
+                        attr: "stable.yarn".to_string()
+                    }
+                ),
+                (
+                    "pnpm".to_string(),
+                    PresetPackageProblem::UnknownPin {
+                        pin: "edge".to_string()
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_packages_suggest_renamed_attrs() {
+        let mut conn = init_db(Path::new(":memory:")).unwrap();
+        let packages: Vec<NixPackage> = [
+            "ripgrep",
+            "nodejs_20",
+            "nodejs_22",
+            "python3Packages.requests",
+            "eza",
+        ]
+        .iter()
+        .map(|attr| package(attr))
+        .collect();
+        ingest_packages(&mut conn, &packages).unwrap();
+        ingest_aliases(
+            &mut conn,
+            &[PackageAlias {
+                alias: "exa".to_string(),
+                target: Some("eza".to_string()),
+                message: Some("'exa' has been removed, use 'eza' instead".to_string()),
+            }],
+        )
+        .unwrap();
+
+        let attrs = vec![
+            "ripgrep".to_string(),
+            "nodejs_16".to_string(),
+            "exa".to_string(),
+        ];
+        let missing = missing_packages(&conn, &attrs).unwrap();
+        assert_eq!(
+            missing,
+            vec![
+                MissingPackage {
+                    attr: "nodejs_16".to_string(),
+                    candidates: vec!["nodejs_20".to_string(), "nodejs_22".to_string()],
+                },
+                MissingPackage {
+                    attr: "exa".to_string(),
+                    candidates: vec!["eza".to_string()],
+                },
+            ]
+        );
+        assert_eq!(attr_stem("python39"), "python");
+        assert_eq!(attr_stem("nodejs_16"), "nodejs");
+    }
+}
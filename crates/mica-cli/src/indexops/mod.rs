@@ -0,0 +1,1257 @@
+use crate::clock::Clock;
+use crate::github::clone::{local_clone_for_url, run_git};
+use crate::github::GitProvider;
+use crate::indexops::aliases::{
+    load_aliases_from_local_clone, load_aliases_from_local_repo, load_aliases_from_pin,
+};
+use crate::indexops::nixenv::{load_packages_from_local_repo, load_packages_from_pin};
+use crate::{
+    ensure_pin_complete, index_db_path, load_config_or_default, run_with_spinner, versions_db_path,
+    CliError, Output,
+};
+use chrono::Utc;
+use mica_core::state::{GlobalProfileState, Pin, ProjectState, MICA_VERSION};
+use mica_index::generate::{
+    find_binary_collisions, finish_chunked_ingest, get_meta, get_package, ingest_aliases,
+    ingest_package_chunk, ingest_packages, ingested_chunks, init_db, load_packages_from_json,
+    open_db, replace_packages_where, set_meta, BinaryCollision, IndexError, PackageAlias,
+};
+use mica_index::schema::INDEX_FORMAT;
+use mica_index::versions::{
+    cache_commit_date, cached_commit_date, init_versions_db, record_versions,
+    set_commit_package_count, VersionSource,
+};
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+pub(crate) mod aliases;
+pub(crate) mod compat;
+pub(crate) mod fallback;
+pub(crate) mod missing;
+pub(crate) mod nixenv;
+pub(crate) mod remote;
+
+#[derive(Debug, Clone)]
+pub(crate) struct IndexPin {
+    pub(crate) name: Option<String>,
+    pub(crate) pin: Pin,
+}
+
+pub(crate) fn collect_index_pins(state: &ProjectState) -> Vec<IndexPin> {
+    let mut pins = Vec::new();
+    pins.push(IndexPin {
+        name: None,
+        pin: state.pin.clone(),
+    });
+
+    let mut seen = BTreeSet::new();
+    seen.insert((
+        state.pin.url.clone(),
+        state.pin.rev.clone(),
+        state.pin.sha256.clone(),
+    ));
+
+    let mut used_labels = BTreeSet::new();
+    for (name, pin) in &state.pins {
+        let key = (pin.url.clone(), pin.rev.clone(), pin.sha256.clone());
+        if !seen.insert(key) {
+            continue;
+        }
+        let label = unique_pin_label(&sanitize_pin_label(name), &mut used_labels);
+        pins.push(IndexPin {
+            name: Some(label),
+            pin: pin.clone(),
+        });
+    }
+    for (pkg, pinned) in &state.packages.pinned {
+        let key = (
+            pinned.pin.url.clone(),
+            pinned.pin.rev.clone(),
+            pinned.pin.sha256.clone(),
+        );
+        if !seen.insert(key) {
+            continue;
+        }
+        let base_label = format!("pin-{}", sanitize_pin_label(pkg));
+        let label = unique_pin_label(&base_label, &mut used_labels);
+        pins.push(IndexPin {
+            name: Some(label),
+            pin: pinned.pin.clone(),
+        });
+    }
+
+    pins
+}
+
+pub(crate) fn collect_index_pins_profile(state: &GlobalProfileState) -> Vec<IndexPin> {
+    let mut pins = Vec::new();
+    pins.push(IndexPin {
+        name: None,
+        pin: state.pin.clone(),
+    });
+
+    let mut seen = BTreeSet::new();
+    seen.insert((
+        state.pin.url.clone(),
+        state.pin.rev.clone(),
+        state.pin.sha256.clone(),
+    ));
+
+    let mut used_labels = BTreeSet::new();
+    for (pkg, pinned) in &state.packages.pinned {
+        let key = (
+            pinned.pin.url.clone(),
+            pinned.pin.rev.clone(),
+            pinned.pin.sha256.clone(),
+        );
+        if !seen.insert(key) {
+            continue;
+        }
+        let base_label = format!("pin-{}", sanitize_pin_label(pkg));
+        let label = unique_pin_label(&base_label, &mut used_labels);
+        pins.push(IndexPin {
+            name: Some(label),
+            pin: pinned.pin.clone(),
+        });
+    }
+
+    pins
+}
+
+pub(crate) fn sanitize_pin_label(value: &str) -> String {
+    let mut out = String::new();
+    for ch in value.chars() {
+        if ch.is_ascii_alphanumeric() {
+            out.push(ch.to_ascii_lowercase());
+        } else {
+            out.push('_');
+        }
+    }
+    if out.is_empty() {
+        "pin".to_string()
+    } else {
+        out
+    }
+}
+
+pub(crate) fn unique_pin_label(base: &str, used: &mut BTreeSet<String>) -> String {
+    if used.insert(base.to_string()) {
+        return base.to_string();
+    }
+    let mut idx = 2;
+    loop {
+        let candidate = format!("{}-{}", base, idx);
+        if used.insert(candidate.clone()) {
+            return candidate;
+        }
+        idx += 1;
+    }
+}
+
+pub(crate) fn normalize_attr_paths(packages: &mut [mica_index::generate::NixPackage]) {
+    for pkg in packages {
+        pkg.attr_path = normalize_attr_path(&pkg.attr_path);
+    }
+}
+
+pub(crate) const NORMALIZED_ATTR_PREFIXES: [&str; 2] = ["nixos.", "pkgs."];
+
+pub(crate) fn normalize_attr_path(value: &str) -> String {
+    NORMALIZED_ATTR_PREFIXES
+        .iter()
+        .find_map(|prefix| value.strip_prefix(prefix))
+        .unwrap_or(value)
+        .to_string()
+}
+
+pub(crate) const LAST_UPDATED_COMMIT_LIMIT: &str = "5000";
+
+pub(crate) fn annotate_last_updated(
+    output: &Output,
+    repo: &Path,
+    rev: &str,
+    packages: &mut [mica_index::generate::NixPackage],
+) {
+    let log = match run_git(
+        repo,
+        &[
+            "log",
+            rev,
+            "--max-count",
+            LAST_UPDATED_COMMIT_LIMIT,
+            "--format=%x00%h %cs",
+            "--name-only",
+            "--",
+            "pkgs",
+        ],
+    ) {
+        Ok(log) => log,
+        Err(err) => {
+            output.verbose(format!("skipping last-updated info: {}", err));
+            return;
+        }
+    };
+    let touched = parse_last_updated_log(&log);
+    for pkg in packages.iter_mut().filter(|pkg| pkg.last_updated.is_none()) {
+        pkg.last_updated = pkg
+            .position
+            .as_deref()
+            .and_then(position_repo_path)
+            .and_then(|path| touched.get(path).cloned());
+    }
+}
+
+pub(crate) fn parse_last_updated_log(log: &str) -> HashMap<String, String> {
+    let mut touched = HashMap::new();
+    let mut current: Option<String> = None;
+    for line in log.lines() {
+        if let Some(header) = line.strip_prefix('\0') {
+            current = header
+                .split_once(' ')
+                .map(|(rev, date)| format!("{} ({})", date, rev));
+            continue;
+        }
+        let path = line.trim();
+        if path.is_empty() {
+            continue;
+        }
+        if let Some(label) = &current {
+            touched
+                .entry(path.to_string())
+                .or_insert_with(|| label.clone());
+        }
+    }
+    touched
+}
+
+pub(crate) fn position_repo_path(position: &str) -> Option<&str> {
+    let path = match position.rsplit_once(':') {
+        Some((path, line)) if line.chars().all(|ch| ch.is_ascii_digit()) => path,
+        _ => position,
+    };
+    if path.starts_with("pkgs/") {
+        return Some(path);
+    }
+    path.find("/pkgs/").map(|idx| &path[idx + 1..])
+}
+
+pub(crate) fn packages_have_meta(packages: &[mica_index::generate::NixPackage]) -> bool {
+    packages.iter().any(|pkg| {
+        pkg.description.is_some()
+            || pkg.homepage.is_some()
+            || pkg.license.is_some()
+            || pkg.platforms.is_some()
+            || pkg.main_program.is_some()
+            || pkg.broken.unwrap_or(false)
+            || pkg.insecure.unwrap_or(false)
+    })
+}
+
+pub(crate) fn rebuild_index_from_json(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    output: &Output,
+    inputs: &[PathBuf],
+    output_path: &Path,
+    pin: Option<&Pin>,
+    fresh: bool,
+) -> Result<usize, CliError> {
+    let staging = partial_index_path(output_path);
+    if fresh {
+        remove_partial_index(&staging)?;
+    }
+    if let Some(parent) = staging.parent() {
+        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+    }
+    let mut chunks = Vec::new();
+    for input in inputs {
+        chunks.push((input_chunk_name(input), input_fingerprint(input)?));
+    }
+
+    let mut conn = init_db(&staging)?;
+    let mut done = ingested_chunks(&conn)?;
+    let resumable = done.iter().all(|(name, fingerprint)| {
+        chunks
+            .iter()
+            .any(|(chunk, current)| chunk == name && current == fingerprint)
+    });
+    if !resumable {
+        output.warn("warning: inputs changed since the interrupted rebuild, starting over");
+        drop(conn);
+        remove_partial_index(&staging)?;
+        conn = init_db(&staging)?;
+        done.clear();
+    } else if !done.is_empty() {
+        output.info(format!(
+            "resuming rebuild: {} of {} input(s) already ingested",
+            done.len(),
+            chunks.len()
+        ));
+    }
+
+    let mut versions = match pin {
+        Some(pin) => {
+            let versions_path = versions_db_path()?;
+            if let Some(parent) = versions_path.parent() {
+                std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+            }
+            let versions_conn = init_versions_db(&versions_path)?;
+            let commit_date = CommitDates::new(git).resolve(output, &versions_conn, pin);
+            let version_source = VersionSource {
+                source: pin_source_label(pin),
+                url: pin.url.clone(),
+                branch: pin_branch_label(pin),
+                commit: pin.rev.clone(),
+                commit_date,
+                indexed_at: clock.now().to_rfc3339(),
+            };
+            Some((versions_conn, version_source))
+        }
+        None => None,
+    };
+
+    for (idx, (input, (chunk, fingerprint))) in inputs.iter().zip(&chunks).enumerate() {
+        if done.contains_key(chunk) {
+            continue;
+        }
+        let mut packages = load_packages_from_json(input)?;
+        normalize_attr_paths(&mut packages);
+        if packages_have_meta(&packages) {
+            set_meta(&conn, "index_meta", "true")?;
+        }
+        if let Some((versions_conn, version_source)) = &mut versions {
+            record_versions(versions_conn, version_source, &packages).map_err(CliError::Index)?;
+        }
+        ingest_package_chunk(&mut conn, chunk, fingerprint, &packages)?;
+        if inputs.len() > 1 {
+            output.info(format!(
+                "ingested {}/{}: {} ({} packages)",
+                idx + 1,
+                inputs.len(),
+                input.display(),
+                packages.len()
+            ));
+        }
+    }
+
+    let index_has_meta = get_meta(&conn)?
+        .iter()
+        .any(|(key, value)| key == "index_meta" && value == "true");
+    let count = finish_chunked_ingest(&conn)?;
+    if let Some((versions_conn, version_source)) = &versions {
+        set_commit_package_count(
+            versions_conn,
+            &version_source.source,
+            &version_source.commit,
+            count,
+        )
+        .map_err(CliError::Index)?;
+    }
+    let aliases = pin
+        .map(|pin| load_aliases_from_local_clone(output, pin))
+        .unwrap_or_default();
+    ingest_aliases(&mut conn, &aliases)?;
+    write_index_meta(&conn, clock, count, pin, index_has_meta)?;
+    let indexed: Vec<IndexedPin> = pin
+        .map(|pin| IndexedPin::primary(&pin.url, &pin.rev))
+        .into_iter()
+        .collect();
+    write_indexed_pins(&conn, &indexed)?;
+    drop(conn);
+    std::fs::rename(&staging, output_path).map_err(CliError::WriteNix)?;
+    Ok(count)
+}
+
+pub(crate) fn partial_index_path(output_path: &Path) -> PathBuf {
+    let mut name = output_path.as_os_str().to_owned();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+fn remove_partial_index(staging: &Path) -> Result<(), CliError> {
+    match std::fs::remove_file(staging) {
+        Ok(()) => Ok(()),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(CliError::WriteNix(err)),
+    }
+}
+
+fn input_chunk_name(input: &Path) -> String {
+    input
+        .canonicalize()
+        .unwrap_or_else(|_| input.to_path_buf())
+        .display()
+        .to_string()
+}
+
+fn input_fingerprint(input: &Path) -> Result<String, CliError> {
+    let meta = std::fs::metadata(input).map_err(IndexError::Read)?;
+    let modified = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default();
+    Ok(format!("{}:{}", meta.len(), modified))
+}
+
+pub(crate) fn rebuild_index_from_local_repo(
+    output: &Output,
+    clock: &dyn Clock,
+    repo_path: &Path,
+    output_path: &Path,
+    extra_skip: &[String],
+    show_trace: bool,
+) -> Result<usize, CliError> {
+    let mut packages = load_packages_from_local_repo(output, repo_path, extra_skip, show_trace)?;
+    normalize_attr_paths(&mut packages);
+    annotate_last_updated(output, repo_path, "HEAD", &mut packages);
+    let index_has_meta = packages_have_meta(&packages);
+    let aliases = load_aliases_from_local_repo(output, repo_path);
+    let indexed = [IndexedPin::primary(
+        &repo_path.display().to_string(),
+        "HEAD",
+    )];
+    rebuild_index_with_packages(
+        clock,
+        output_path,
+        &packages,
+        &aliases,
+        None,
+        &indexed,
+        index_has_meta,
+    )
+}
+
+pub(crate) fn rebuild_index_from_pins(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    output: &Output,
+    output_path: &Path,
+    pins: &[IndexPin],
+) -> Result<usize, CliError> {
+    let mut loader = PinLoader::new(git, clock)?;
+    let mut packages = Vec::new();
+    let mut aliases = Vec::new();
+    for (idx, index_pin) in pins.iter().enumerate() {
+        if idx == 0 {
+            ensure_pin_complete(&index_pin.pin)?;
+        } else if ensure_pin_complete(&index_pin.pin).is_err() {
+            continue;
+        }
+        let pin_packages = match loader.load(output, index_pin, idx) {
+            Ok(packages) => packages,
+            Err(err) if idx > 0 => {
+                output.warn(format!(
+                    "warning: skipping supplemental pin '{}' ({}@{}): {}",
+                    index_pin_label(index_pin),
+                    index_pin.pin.url,
+                    index_pin.pin.rev,
+                    err
+                ));
+                continue;
+            }
+            Err(err) => return Err(err),
+        };
+        if idx == 0 {
+            aliases = load_aliases_from_pin(output, &index_pin.pin);
+        }
+        packages.extend(pin_packages);
+    }
+
+    let primary = pins.first().map(|entry| &entry.pin);
+    let indexed: Vec<IndexedPin> = pins
+        .iter()
+        .enumerate()
+        .map(|(idx, pin)| IndexedPin::from_index_pin(pin, idx))
+        .collect();
+    rebuild_index_with_packages(
+        clock,
+        output_path,
+        &packages,
+        &aliases,
+        primary,
+        &indexed,
+        true,
+    )
+}
+
+pub(crate) fn rebuild_index_for_pin(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    output: &Output,
+    output_path: &Path,
+    pins: &[IndexPin],
+    name: &str,
+) -> Result<usize, CliError> {
+    let Some(idx) = find_index_pin(pins, name) else {
+        let available: Vec<&str> = pins.iter().map(index_pin_label).collect();
+        return Err(CliError::IndexPinNotFound(
+            name.to_string(),
+            available.join(", "),
+        ));
+    };
+    if !output_path.exists() {
+        return Err(CliError::MissingIndex(output_path.to_path_buf()));
+    }
+    let index_pin = &pins[idx];
+    ensure_pin_complete(&index_pin.pin)?;
+    let packages = PinLoader::new(git, clock)?.load(output, index_pin, idx)?;
+    let other_prefixes: Vec<String> = pins
+        .iter()
+        .enumerate()
+        .filter(|(other, _)| *other != idx && *other != 0)
+        .map(|(other, pin)| format!("{}.", index_pin_prefix(pin, other)))
+        .collect();
+    let prefix = format!("{}.", index_pin_prefix(index_pin, idx));
+    let mut conn = open_db(output_path)?;
+    let count = replace_packages_where(
+        &mut conn,
+        |attr| {
+            if idx == 0 {
+                !other_prefixes.iter().any(|other| attr.starts_with(other))
+            } else {
+                attr.starts_with(&prefix)
+            }
+        },
+        &packages,
+    )?;
+    if idx == 0 {
+        ingest_aliases(&mut conn, &load_aliases_from_pin(output, &index_pin.pin))?;
+    }
+    let primary = pins.first().map(|entry| &entry.pin);
+    write_index_meta(&conn, clock, count, primary, true)?;
+    let rebuilt = IndexedPin::from_index_pin(index_pin, idx);
+    let mut indexed = read_indexed_pins(&get_meta(&conn)?).unwrap_or_default();
+    indexed.retain(|entry| entry.prefix != rebuilt.prefix);
+    indexed.push(rebuilt);
+    write_indexed_pins(&conn, &indexed)?;
+    Ok(count)
+}
+
+pub(crate) fn find_index_pin(pins: &[IndexPin], name: &str) -> Option<usize> {
+    let sanitized = sanitize_pin_label(name);
+    pins.iter()
+        .position(|pin| {
+            pin.name
+                .as_deref()
+                .is_some_and(|label| label == name || label == sanitized)
+        })
+        .or_else(|| {
+            let primary = pins.first()?;
+            let matches = name == "nixpkgs" || primary.pin.name.as_deref() == Some(name);
+            matches.then_some(0)
+        })
+}
+
+fn index_pin_label(index_pin: &IndexPin) -> &str {
+    index_pin.name.as_deref().unwrap_or("nixpkgs")
+}
+
+fn index_pin_prefix(index_pin: &IndexPin, idx: usize) -> &str {
+    match &index_pin.name {
+        Some(name) => name,
+        None if idx != 0 => "pin",
+        None => "",
+    }
+}
+
+struct PinLoader<'a> {
+    versions_conn: Connection,
+    commit_dates: CommitDates<'a>,
+    indexed_at: String,
+}
+
+impl<'a> PinLoader<'a> {
+    fn new(git: &'a dyn GitProvider, clock: &dyn Clock) -> Result<PinLoader<'a>, CliError> {
+        let versions_path = versions_db_path()?;
+        if let Some(parent) = versions_path.parent() {
+            std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+        }
+        Ok(PinLoader {
+            versions_conn: init_versions_db(&versions_path)?,
+            commit_dates: CommitDates::new(git),
+            indexed_at: clock.now().to_rfc3339(),
+        })
+    }
+
+    fn load(
+        &mut self,
+        output: &Output,
+        index_pin: &IndexPin,
+        idx: usize,
+    ) -> Result<Vec<mica_index::generate::NixPackage>, CliError> {
+        let mut pin_packages = load_packages_from_pin(output, &index_pin.pin)?;
+        normalize_attr_paths(&mut pin_packages);
+        if let Some(clone) = local_clone_for_url(&index_pin.pin.url) {
+            annotate_last_updated(output, &clone, &index_pin.pin.rev, &mut pin_packages);
+        }
+        let commit_date = self
+            .commit_dates
+            .resolve(output, &self.versions_conn, &index_pin.pin);
+        let version_source = VersionSource {
+            source: pin_source_label(&index_pin.pin),
+            url: index_pin.pin.url.clone(),
+            branch: pin_branch_label(&index_pin.pin),
+            commit: index_pin.pin.rev.clone(),
+            commit_date,
+            indexed_at: self.indexed_at.clone(),
+        };
+        record_versions(&mut self.versions_conn, &version_source, &pin_packages)
+            .map_err(CliError::Index)?;
+        let prefix = index_pin_prefix(index_pin, idx);
+        if !prefix.is_empty() {
+            for pkg in &mut pin_packages {
+                pkg.attr_path = format!("{}.{}", prefix, pkg.attr_path);
+            }
+        }
+        Ok(pin_packages)
+    }
+}
+
+pub(crate) fn rebuild_index_from_pins_with_spinner(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    output: &Output,
+    output_path: &Path,
+    pins: &[IndexPin],
+) -> Result<usize, CliError> {
+    run_with_spinner(output, "building index", || {
+        rebuild_index_from_pins(git, clock, output, output_path, pins)
+    })
+}
+
+pub(crate) fn rebuild_index_from_local_repo_with_spinner(
+    output: &Output,
+    clock: &dyn Clock,
+    repo_path: &Path,
+    output_path: &Path,
+    extra_skip: &[String],
+    show_trace: bool,
+) -> Result<usize, CliError> {
+    run_with_spinner(output, "building index", || {
+        rebuild_index_from_local_repo(
+            output,
+            clock,
+            repo_path,
+            output_path,
+            extra_skip,
+            show_trace,
+        )
+    })
+}
+
+pub(crate) fn rebuild_index_with_packages(
+    clock: &dyn Clock,
+    output_path: &Path,
+    packages: &[mica_index::generate::NixPackage],
+    aliases: &[PackageAlias],
+    pin: Option<&Pin>,
+    indexed: &[IndexedPin],
+    index_has_meta: bool,
+) -> Result<usize, CliError> {
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+    }
+    let mut conn = init_db(output_path)?;
+    ingest_packages(&mut conn, packages)?;
+    ingest_aliases(&mut conn, aliases)?;
+    write_index_meta(&conn, clock, packages.len(), pin, index_has_meta)?;
+    write_indexed_pins(&conn, indexed)?;
+    Ok(packages.len())
+}
+
+fn write_index_meta(
+    conn: &Connection,
+    clock: &dyn Clock,
+    package_count: usize,
+    pin: Option<&Pin>,
+    index_has_meta: bool,
+) -> Result<(), CliError> {
+    let generated_at = clock.now().to_rfc3339();
+    set_meta(conn, "generated_at", &generated_at)?;
+    set_meta(conn, "package_count", &package_count.to_string())?;
+    set_meta(conn, "mica_version", MICA_VERSION)?;
+    set_meta(conn, "index_format", &INDEX_FORMAT.to_string())?;
+    if index_has_meta {
+        set_meta(conn, "index_meta", "true")?;
+    } else {
+        set_meta(conn, "index_meta", "false")?;
+    }
+    if let Some(pin) = pin {
+        set_meta(conn, "nixpkgs_url", &pin.url)?;
+        set_meta(conn, "nixpkgs_commit", &pin.rev)?;
+    } else {
+        set_meta(conn, "nixpkgs_url", "unknown")?;
+        set_meta(conn, "nixpkgs_commit", "unknown")?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct IndexedPin {
+    pub(crate) label: String,
+    pub(crate) prefix: String,
+    pub(crate) url: String,
+    pub(crate) rev: String,
+}
+
+impl IndexedPin {
+    fn primary(url: &str, rev: &str) -> IndexedPin {
+        IndexedPin {
+            label: "nixpkgs".to_string(),
+            prefix: String::new(),
+            url: url.to_string(),
+            rev: rev.to_string(),
+        }
+    }
+
+    fn from_index_pin(index_pin: &IndexPin, idx: usize) -> IndexedPin {
+        IndexedPin {
+            label: index_pin_label(index_pin).to_string(),
+            prefix: index_pin_prefix(index_pin, idx).to_string(),
+            url: index_pin.pin.url.clone(),
+            rev: index_pin.pin.rev.clone(),
+        }
+    }
+}
+
+fn write_indexed_pins(conn: &Connection, indexed: &[IndexedPin]) -> Result<(), CliError> {
+    let pins =
+        serde_json::to_string(indexed).map_err(|err| CliError::Index(IndexError::Json(err)))?;
+    set_meta(conn, "indexed_pins", &pins)?;
+    set_meta(
+        conn,
+        "attr_normalization",
+        &NORMALIZED_ATTR_PREFIXES.join(","),
+    )?;
+    Ok(())
+}
+
+pub(crate) fn read_indexed_pins(meta: &[(String, String)]) -> Option<Vec<IndexedPin>> {
+    let (_, value) = meta.iter().find(|(key, _)| key == "indexed_pins")?;
+    serde_json::from_str(value).ok()
+}
+
+pub(crate) fn index_coverage_issues(meta: &[(String, String)], pins: &[IndexPin]) -> Vec<String> {
+    let mut issues = Vec::new();
+    if meta
+        .iter()
+        .any(|(key, value)| key == "fallback" && value == "true")
+    {
+        return issues;
+    }
+    let normalization = NORMALIZED_ATTR_PREFIXES.join(",");
+    if let Some((_, built)) = meta
+        .iter()
+        .find(|(key, value)| key == "attr_normalization" && *value != normalization)
+    {
+        issues.push(format!(
+            "the index strips attr prefixes {:?} but this mica strips {:?}; search results may use different attr paths",
+            built, normalization
+        ));
+    }
+    let Some(indexed) = read_indexed_pins(meta) else {
+        return issues;
+    };
+    let missing: Vec<&str> = pins
+        .iter()
+        .enumerate()
+        .filter(|(idx, pin)| {
+            let prefix = index_pin_prefix(pin, *idx);
+            !indexed.iter().any(|entry| entry.prefix == prefix)
+        })
+        .map(|(_, pin)| index_pin_label(pin))
+        .collect();
+    if !missing.is_empty() {
+        issues.push(format!(
+            "the index does not cover pin(s) {}, so their packages will not show up in search (run `mica index rebuild --from-pin {}`)",
+            missing.join(", "),
+            missing[0]
+        ));
+    }
+    issues
+}
+
+pub(crate) fn warn_index_coverage(output: &Output, conn: &Connection, pins: &[IndexPin]) {
+    let meta = match get_meta(conn) {
+        Ok(meta) => meta,
+        Err(err) => {
+            output.verbose(format!("skipping index coverage check: {}", err));
+            return;
+        }
+    };
+    report_index_coverage(output, &meta, pins);
+}
+
+pub(crate) fn report_index_coverage(output: &Output, meta: &[(String, String)], pins: &[IndexPin]) {
+    for issue in index_coverage_issues(meta, pins) {
+        output.warn(format!("warning: {}", issue));
+    }
+}
+
+pub(crate) fn index_display_name_for_url(url: &str) -> String {
+    let trimmed = url.trim();
+    if trimmed.is_empty() {
+        return "jpetrucciani/nix".to_string();
+    }
+    if let Some(pos) = trimmed.find("github.com/") {
+        let mut tail = &trimmed[pos + "github.com/".len()..];
+        if let Some(idx) = tail.find('#') {
+            tail = &tail[..idx];
+        }
+        if let Some(idx) = tail.find('?') {
+            tail = &tail[..idx];
+        }
+        for marker in ["/archive/", "/tarball/", "/commit/", "/tree/", "/releases/"] {
+            if let Some(idx) = tail.find(marker) {
+                tail = &tail[..idx];
+                break;
+            }
+        }
+        let tail = tail.trim_end_matches(".git");
+        let parts: Vec<&str> = tail.split('/').filter(|part| !part.is_empty()).collect();
+        if parts.len() >= 2 {
+            return format!("{}/{}", parts[0], parts[1]);
+        }
+    }
+    trimmed.to_string()
+}
+
+pub(crate) fn pin_branch_label(pin: &Pin) -> String {
+    if pin.branch.trim().is_empty() {
+        "main".to_string()
+    } else {
+        pin.branch.clone()
+    }
+}
+
+pub(crate) fn pin_source_label(pin: &Pin) -> String {
+    let repo = index_display_name_for_url(&pin.url);
+    let branch = pin_branch_label(pin);
+    format!("{}@{}", repo, branch)
+}
+
+pub(crate) struct CommitDates<'a> {
+    git: &'a dyn GitProvider,
+    lookups_enabled: bool,
+}
+
+impl<'a> CommitDates<'a> {
+    pub(crate) fn new(git: &'a dyn GitProvider) -> CommitDates<'a> {
+        let skip = load_config_or_default()
+            .map(|config| config.index.skip_commit_dates)
+            .unwrap_or(false);
+        CommitDates {
+            git,
+            lookups_enabled: !skip,
+        }
+    }
+
+    pub(crate) fn resolve(&mut self, output: &Output, conn: &Connection, pin: &Pin) -> String {
+        if let Ok(Some(date)) = cached_commit_date(conn, &pin.url, &pin.rev) {
+            return date;
+        }
+        if self.lookups_enabled {
+            match self.git.commit_date(&pin.url, &pin.rev) {
+                Ok(date) => {
+                    if let Err(err) = cache_commit_date(conn, &pin.url, &pin.rev, &date) {
+                        output.verbose(format!("failed to cache commit date: {}", err));
+                    }
+                    return date;
+                }
+                Err(err) => {
+                    output.warn(format!(
+                        "warning: failed to fetch commit date for {}@{}: {} (using pin dates for the rest of this rebuild)",
+                        pin.url, pin.rev, err
+                    ));
+                    self.lookups_enabled = false;
+                }
+            }
+        }
+        let fallback = pin.updated.and_hms_opt(0, 0, 0).unwrap();
+        chrono::DateTime::<Utc>::from_naive_utc_and_offset(fallback, Utc).to_rfc3339()
+    }
+}
+
+pub(crate) fn meta_has_key(meta: &[(String, String)], needle: &str) -> bool {
+    meta.iter()
+        .any(|(key, value)| key == needle && value == "true")
+}
+
+pub(crate) fn index_has_descriptions(conn: &rusqlite::Connection) -> Result<bool, CliError> {
+    let count: i64 = conn
+        .query_row(
+            "SELECT COUNT(1) FROM packages WHERE description IS NOT NULL",
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|err| CliError::Index(mica_index::generate::IndexError::Db(err)))?;
+    Ok(count > 0)
+}
+
+pub(crate) fn flagged_packages(
+    conn: &rusqlite::Connection,
+    attrs: &[String],
+) -> Result<Vec<String>, CliError> {
+    let mut flagged = Vec::new();
+    for attr in attrs {
+        let Some(pkg) = get_package(conn, attr)? else {
+            continue;
+        };
+        let mut flags = Vec::new();
+        if pkg.broken {
+            flags.push("broken");
+        }
+        if pkg.insecure {
+            flags.push("insecure");
+        }
+        if !flags.is_empty() {
+            flagged.push(format!("{} ({})", attr, flags.join(", ")));
+        }
+    }
+    Ok(flagged)
+}
+
+pub(crate) fn warn_flagged_packages(output: &Output, attrs: &[String]) {
+    let Ok(index_path) = index_db_path() else {
+        return;
+    };
+    if !index_path.exists() {
+        return;
+    }
+    let flagged = match open_db(&index_path)
+        .map_err(CliError::from)
+        .and_then(|conn| flagged_packages(&conn, attrs))
+    {
+        Ok(flagged) => flagged,
+        Err(err) => {
+            output.verbose(format!("skipping broken/insecure check: {}", err));
+            return;
+        }
+    };
+    if flagged.is_empty() {
+        return;
+    }
+    output.warn(format!(
+        "warning: packages marked broken or insecure in the index: {}",
+        flagged.join(", ")
+    ));
+    output.warn("warning: nix will refuse to build them unless allowBroken or permittedInsecurePackages is set");
+}
+
+pub(crate) const DEFAULT_PRIORITY: i32 = 5;
+
+pub(crate) fn collision_winner<'a>(
+    collision: &'a BinaryCollision,
+    priorities: &BTreeMap<String, i32>,
+) -> Option<&'a str> {
+    let priority_of = |pkg: &String| priorities.get(pkg).copied().unwrap_or(DEFAULT_PRIORITY);
+    let best = collision.packages.iter().map(priority_of).min()?;
+    let mut winners = collision
+        .packages
+        .iter()
+        .filter(|pkg| priority_of(pkg) == best);
+    let winner = winners.next()?;
+    match winners.next() {
+        Some(_) => None,
+        None => Some(winner.as_str()),
+    }
+}
+
+pub(crate) fn preferred_priority(
+    package: &str,
+    collisions: &[BinaryCollision],
+    priorities: &BTreeMap<String, i32>,
+) -> i32 {
+    collisions
+        .iter()
+        .filter(|collision| collision.packages.iter().any(|pkg| pkg == package))
+        .flat_map(|collision| collision.packages.iter())
+        .filter(|pkg| pkg.as_str() != package)
+        .map(|pkg| priorities.get(pkg).copied().unwrap_or(DEFAULT_PRIORITY))
+        .min()
+        .unwrap_or(DEFAULT_PRIORITY)
+        - 1
+}
+
+pub(crate) fn project_collisions(attrs: &[String]) -> Result<Vec<BinaryCollision>, CliError> {
+    let index_path = index_db_path()?;
+    if !index_path.exists() {
+        return Ok(Vec::new());
+    }
+    let conn = open_db(&index_path)?;
+    Ok(find_binary_collisions(&conn, attrs)?)
+}
+
+pub(crate) fn warn_package_collisions(output: &Output, state: &ProjectState, attrs: &[String]) {
+    if state.build_env.ignore_collisions {
+        return;
+    }
+    let collisions = match project_collisions(attrs) {
+        Ok(collisions) => collisions,
+        Err(err) => {
+            output.verbose(format!("skipping collision check: {}", err));
+            return;
+        }
+    };
+    for collision in &collisions {
+        if collision_winner(collision, &state.packages.priorities).is_some() {
+            continue;
+        }
+        output.warn(format!(
+            "warning: {} is provided by {}; buildEnv will fail on the collision",
+            collision.binary,
+            collision.packages.join(", ")
+        ));
+        output.warn(format!(
+            "warning: pick one with `mica build-env prefer {}`",
+            collision.packages[0]
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::github::{ChannelRelease, CommitSummary, GitProvider};
+    use crate::indexops::{
+        collision_winner, find_index_pin, index_coverage_issues, parse_last_updated_log,
+        position_repo_path, preferred_priority, write_indexed_pins, CommitDates, IndexPin,
+        IndexedPin,
+    };
+    use crate::{CliError, Output};
+    use chrono::NaiveDate;
+    use mica_core::state::Pin;
+    use mica_index::generate::{get_meta, init_db, set_meta, BinaryCollision};
+    use mica_index::versions::VERSIONS_SCHEMA;
+    use rusqlite::Connection;
+    use std::cell::Cell;
+    use std::collections::BTreeMap;
+    use std::path::Path;
+
+    struct CountingGit {
+        calls: Cell<usize>,
+        fail: bool,
+    }
+
+    impl GitProvider for CountingGit {
+        fn latest_rev(&self, _url: &str, _branch: &str) -> Result<String, CliError> {
+            unreachable!()
+        }
+
+        fn commit_date(&self, _url: &str, _rev: &str) -> Result<String, CliError> {
+            self.calls.set(self.calls.get() + 1);
+            if self.fail {
+                Err(CliError::GitHubApiMissingDate)
+            } else {
+                Ok("2024-05-01T10:00:00Z".to_string())
+            }
+        }
+
+        fn channel_release(&self, _channel: &str) -> Result<ChannelRelease, CliError> {
+            unreachable!()
+        }
+
+        fn recent_commits(
+            &self,
+            _url: &str,
+            _branch: &str,
+            _limit: usize,
+        ) -> Result<Vec<CommitSummary>, CliError> {
+            unreachable!()
+        }
+    }
+
+    fn pin(rev: &str) -> Pin {
+        Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: rev.to_string(),
+            sha256: "sha".to_string(),
+            branch: "main".to_string(),
+            updated: NaiveDate::from_ymd_opt(2024, 4, 1).unwrap(),
+            channel: None,
+        }
+    }
+
+    #[test]
+    fn commit_dates_are_fetched_once_then_cached() {
+        let conn = Connection::open_in_memory().expect("open failed");
+        conn.execute_batch(VERSIONS_SCHEMA).expect("schema failed");
+        let output = Output {
+            quiet: true,
+            verbose: false,
+        };
+        let git = CountingGit {
+            calls: Cell::new(0),
+            fail: false,
+        };
+        let mut dates = CommitDates {
+            git: &git,
+            lookups_enabled: true,
+        };
+        assert_eq!(
+            dates.resolve(&output, &conn, &pin("abc")),
+            "2024-05-01T10:00:00Z"
+        );
+        let mut dates = CommitDates {
+            git: &git,
+            lookups_enabled: true,
+        };
+        assert_eq!(
+            dates.resolve(&output, &conn, &pin("abc")),
+            "2024-05-01T10:00:00Z"
+        );
+        assert_eq!(git.calls.get(), 1);
+    }
+
+    #[test]
+    fn failed_commit_date_lookup_stops_further_requests() {
+        let conn = Connection::open_in_memory().expect("open failed");
+        conn.execute_batch(VERSIONS_SCHEMA).expect("schema failed");
+        let output = Output {
+            quiet: true,
+            verbose: false,
+        };
+        let git = CountingGit {
+            calls: Cell::new(0),
+            fail: true,
+        };
+        let mut dates = CommitDates {
+            git: &git,
+            lookups_enabled: true,
+        };
+        assert_eq!(
+            dates.resolve(&output, &conn, &pin("abc")),
+            "2024-04-01T00:00:00+00:00"
+        );
+        dates.resolve(&output, &conn, &pin("def"));
+        assert_eq!(git.calls.get(), 1);
+    }
+
+    #[test]
+    fn last_updated_uses_newest_commit_per_position_file() {
+        let log = "\0abc1234 2026-03-02\npkgs/by-name/he/hello/package.nix\n\n\0def5678 2026-01-15\npkgs/by-name/he/hello/package.nix\npkgs/tools/misc/fd/default.nix\n";
+        let touched = parse_last_updated_log(log);
+        assert_eq!(
+            touched
+                .get("pkgs/by-name/he/hello/package.nix")
+                .map(String::as_str),
+            Some("2026-03-02 (abc1234)")
+        );
+        assert_eq!(
+            touched
+                .get("pkgs/tools/misc/fd/default.nix")
+                .map(String::as_str),
+            Some("2026-01-15 (def5678)")
+        );
+        assert_eq!(
+            position_repo_path("/nix/store/abc-source/pkgs/tools/misc/fd/default.nix:42"),
+            Some("pkgs/tools/misc/fd/default.nix")
+        );
+        assert_eq!(position_repo_path("lib/default.nix:3"), None);
+    }
+
+    #[test]
+    fn collisions_need_a_single_lowest_priority() {
+        let collision = BinaryCollision {
+            binary: "rg".to_string(),
+            packages: vec!["ripgrep".to_string(), "ripgrep-all".to_string()],
+        };
+        let mut priorities = BTreeMap::new();
+        assert_eq!(collision_winner(&collision, &priorities), None);
+
+        let priority =
+            preferred_priority("ripgrep-all", std::slice::from_ref(&collision), &priorities);
+        assert_eq!(priority, 4);
+        priorities.insert("ripgrep-all".to_string(), priority);
+        assert_eq!(
+            collision_winner(&collision, &priorities),
+            Some("ripgrep-all")
+        );
+
+        priorities.insert("ripgrep".to_string(), 4);
+        assert_eq!(collision_winner(&collision, &priorities), None);
+    }
+
+    #[test]
+    fn single_pin_rebuilds_resolve_labels_and_the_primary_pin() {
+        let pin = |rev: &str| Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: rev.to_string(),
+            sha256: "sha".to_string(),
+            branch: "nixpkgs-unstable".to_string(),
+            updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            channel: None,
+        };
+        let pins = vec![
+            IndexPin {
+                name: None,
+                pin: pin("a"),
+            },
+            IndexPin {
+                name: Some("my_tools".to_string()),
+                pin: pin("b"),
+            },
+            IndexPin {
+                name: Some("pin-nodejs".to_string()),
+                pin: pin("c"),
+            },
+        ];
+        assert_eq!(find_index_pin(&pins, "my-tools"), Some(1));
+        assert_eq!(find_index_pin(&pins, "pin-nodejs"), Some(2));
+        assert_eq!(find_index_pin(&pins, "nixpkgs"), Some(0));
+        assert_eq!(find_index_pin(&pins, "other"), None);
+    }
+
+    #[test]
+    fn index_meta_reports_pins_missing_from_a_shared_index() {
+        let pin = |rev: &str| Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: rev.to_string(),
+            sha256: "sha".to_string(),
+            branch: "nixpkgs-unstable".to_string(),
+            updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            channel: None,
+        };
+        let pins = vec![
+            IndexPin {
+                name: None,
+                pin: pin("a"),
+            },
+            IndexPin {
+                name: Some("unstable".to_string()),
+                pin: pin("b"),
+            },
+        ];
+        let conn = init_db(Path::new(":memory:")).unwrap();
+        write_indexed_pins(&conn, &[IndexedPin::from_index_pin(&pins[0], 0)]).unwrap();
+        let meta = get_meta(&conn).unwrap();
+        let issues = index_coverage_issues(&meta, &pins);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].contains("does not cover pin(s) unstable"));
+        assert!(issues[0].contains("--from-pin unstable"));
+
+        write_indexed_pins(
+            &conn,
+            &[
+                IndexedPin::from_index_pin(&pins[0], 0),
+                IndexedPin::from_index_pin(&pins[1], 1),
+            ],
+        )
+        .unwrap();
+        assert!(index_coverage_issues(&get_meta(&conn).unwrap(), &pins).is_empty());
+
+        set_meta(&conn, "attr_normalization", "pkgs.").unwrap();
+        let issues = index_coverage_issues(&get_meta(&conn).unwrap(), &pins);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].contains("strips attr prefixes"));
+
+        let legacy = vec![("nixpkgs_commit".to_string(), "a".to_string())];
+        assert!(index_coverage_issues(&legacy, &pins).is_empty());
+    }
+}
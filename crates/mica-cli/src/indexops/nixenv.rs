@@ -0,0 +1,488 @@
+use crate::nixops::{nix_url_rewriter, pin_tarball_url, url_rewrites};
+use crate::{escape_nix_string, CliError, Output};
+use mica_core::config::UrlRewrite;
+use mica_core::state::Pin;
+use mica_index::generate::load_packages_from_json;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+use std::process::Stdio;
+
+pub(crate) fn build_index_skip_list(extra: &[String]) -> Vec<String> {
+    let mut skip = vec![
+        "home-packages".to_string(),
+        "json-crack".to_string(),
+        "nim1".to_string(),
+        "nim-1_0".to_string(),
+        "watcher".to_string(),
+        "nixosTests".to_string(),
+        "pkgs*".to_string(),
+        "by-name".to_string(),
+        "by-name*".to_string(),
+        "lib".to_string(),
+        "lib*".to_string(),
+        "darwin".to_string(),
+        "darwin*".to_string(),
+        "pypy*Packages".to_string(),
+        "python*Packages".to_string(),
+    ];
+    for entry in extra {
+        if !skip.iter().any(|existing| existing == entry) {
+            skip.push(entry.clone());
+        }
+    }
+    skip.sort();
+    skip.dedup();
+    skip
+}
+
+pub(crate) fn glob_to_regex(pattern: &str) -> String {
+    let mut out = String::from("^");
+    for ch in pattern.chars() {
+        match ch {
+            '*' => out.push_str(".*"),
+            '.' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '^' | '$' | '|' | '\\' => {
+                out.push('\\');
+                out.push(ch);
+            }
+            _ => out.push(ch),
+        }
+    }
+    out.push('$');
+    out
+}
+
+pub(crate) fn keep_index_temp_files() -> bool {
+    match std::env::var("MICA_KEEP_INDEX_NIX") {
+        Ok(value) => matches!(
+            value.trim().to_lowercase().as_str(),
+            "1" | "true" | "yes" | "on"
+        ),
+        Err(_) => false,
+    }
+}
+
+pub(crate) fn parse_skip_list(value: &str) -> Vec<String> {
+    let mut items: Vec<String> = value
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.to_string())
+        .collect();
+    items.sort();
+    items.dedup();
+    items
+}
+
+pub(crate) fn parse_failed_attr(stderr: &str) -> Option<String> {
+    let needle = "while evaluating the attribute '";
+    for line in stderr.lines() {
+        if let Some(start) = line.find(needle) {
+            let rest = &line[start + needle.len()..];
+            if let Some(end) = rest.find('\'') {
+                let attr = rest[..end].trim();
+                if !attr.is_empty() && !attr.contains('.') {
+                    return Some(attr.to_string());
+                }
+            }
+        }
+    }
+    let by_name = "/pkgs/by-name/";
+    for line in stderr.lines() {
+        if let Some(start) = line.find(by_name) {
+            let rest = &line[start + by_name.len()..];
+            let mut parts = rest.split('/');
+            let _ = parts.next();
+            if let Some(name) = parts.next() {
+                let trimmed = name.trim();
+                if !trimmed.is_empty() {
+                    return Some(trimmed.to_string());
+                }
+            }
+        }
+    }
+    for line in stderr.lines() {
+        if let Some(start) = line.find("/pkgs/") {
+            let rest = &line[start + "/pkgs/".len()..];
+            let path = rest.split(':').next().unwrap_or(rest);
+            let path = path.trim_end_matches(')');
+            if let Some(file) = path.split('/').next_back() {
+                if file == "default.nix" || file == "package.nix" {
+                    if let Some(parent) = path.split('/').rev().nth(1) {
+                        if !parent.is_empty() {
+                            return Some(parent.to_string());
+                        }
+                    }
+                } else if let Some(stem) = file.strip_suffix(".nix") {
+                    if !stem.is_empty() {
+                        return Some(stem.to_string());
+                    }
+                }
+            }
+        }
+    }
+    let missing = "error: attribute '";
+    for line in stderr.lines() {
+        if let Some(start) = line.find(missing) {
+            let rest = &line[start + missing.len()..];
+            if let Some(end) = rest.find('\'') {
+                let attr = rest[..end].trim();
+                if !attr.is_empty() && !attr.contains('.') {
+                    return Some(attr.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+pub(crate) fn nix_env_show_trace() -> bool {
+    match std::env::var("MICA_NIX_SHOW_TRACE") {
+        Ok(value) => matches!(
+            value.trim().to_lowercase().as_str(),
+            "1" | "true" | "yes" | "on"
+        ),
+        Err(_) => false,
+    }
+}
+
+pub(crate) fn index_skip_overrides(extra: &[String]) -> Vec<String> {
+    let mut skip = parse_skip_list(
+        std::env::var("MICA_NIX_SKIP_ATTRS")
+            .unwrap_or_default()
+            .as_str(),
+    );
+    for entry in extra {
+        if !skip.iter().any(|existing| existing == entry) {
+            skip.push(entry.clone());
+        }
+    }
+    skip.sort();
+    skip.dedup();
+    skip
+}
+
+pub(crate) fn load_packages_from_pin(
+    output: &Output,
+    pin: &Pin,
+) -> Result<Vec<mica_index::generate::NixPackage>, CliError> {
+    let skip = index_skip_overrides(&[]);
+    let rewrites = url_rewrites()?;
+    load_packages_from_nix_expression(output, skip, nix_env_show_trace(), |all_skip| {
+        nix_env_expression(pin, all_skip, &rewrites)
+    })
+}
+
+pub(crate) fn load_packages_from_local_repo(
+    output: &Output,
+    repo_path: &Path,
+    extra_skip: &[String],
+    show_trace: bool,
+) -> Result<Vec<mica_index::generate::NixPackage>, CliError> {
+    let repo_path = std::fs::canonicalize(repo_path).map_err(CliError::ReadNix)?;
+    let skip = index_skip_overrides(extra_skip);
+    let rewrites = url_rewrites()?;
+    load_packages_from_nix_expression(
+        output,
+        skip,
+        show_trace || nix_env_show_trace(),
+        |all_skip| nix_env_expression_from_local_repo(&repo_path, all_skip, &rewrites),
+    )
+}
+
+pub(crate) fn load_packages_from_nix_expression(
+    output: &Output,
+    mut skip: Vec<String>,
+    mut use_show_trace: bool,
+    expression_builder: impl Fn(&[String]) -> String,
+) -> Result<Vec<mica_index::generate::NixPackage>, CliError> {
+    let expr_path = temp_index_nix_path();
+    let json_path = temp_index_json_path();
+    let mut attempts = 0usize;
+    let max_attempts = 12usize;
+    loop {
+        attempts += 1;
+        let skipped_label = if skip.is_empty() {
+            "none".to_string()
+        } else {
+            skip.join(",")
+        };
+        output.status(format!(
+            "index attempt {}/{} (skipped: {}, show-trace: {})",
+            attempts, max_attempts, skipped_label, use_show_trace
+        ));
+        let all_skip = build_index_skip_list(&skip);
+        let all_skip_label = if all_skip.is_empty() {
+            "none".to_string()
+        } else {
+            all_skip.join(",")
+        };
+        output.verbose(format!("index skip list: {}", all_skip_label));
+        std::fs::write(&expr_path, expression_builder(&all_skip)).map_err(CliError::WriteNix)?;
+
+        let file = std::fs::File::create(&json_path).map_err(CliError::WriteNix)?;
+        let mut args = vec![
+            "-f",
+            expr_path.to_str().unwrap_or_default(),
+            "-qaP",
+            "--json",
+            "--meta",
+        ];
+        if use_show_trace {
+            args.push("--show-trace");
+        }
+        let mut command = ProcessCommand::new("nix-env");
+        command
+            .args(args)
+            .stdout(Stdio::from(file))
+            .stderr(Stdio::piped());
+        let child = command.spawn().map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixEnv
+            } else {
+                CliError::NixEnvIo(err)
+            }
+        })?;
+
+        let command_output = child.wait_with_output().map_err(CliError::NixEnvIo)?;
+        if command_output.status.success() {
+            let packages = load_packages_from_json(&json_path)?;
+            if !keep_index_temp_files() {
+                let _ = std::fs::remove_file(&expr_path);
+                let _ = std::fs::remove_file(&json_path);
+            }
+            return Ok(packages);
+        }
+
+        let stderr = String::from_utf8_lossy(&command_output.stderr);
+        if attempts < max_attempts {
+            if let Some(attr) = parse_failed_attr(&stderr) {
+                if !skip.iter().any(|entry| entry == &attr) {
+                    skip.push(attr.clone());
+                    output.status(format!("index retry: skipping attr '{}'", attr));
+                    continue;
+                }
+            } else if !use_show_trace {
+                use_show_trace = true;
+                output.status("index retry: enabling --show-trace");
+                continue;
+            }
+        }
+
+        let mut message = format!("status={}, stderr={}", command_output.status, stderr.trim());
+        if keep_index_temp_files() {
+            message.push_str(&format!(
+                ", expr={}, json={}",
+                expr_path.display(),
+                json_path.display()
+            ));
+        }
+        if !skip.is_empty() {
+            message.push_str(&format!(", skipped={}", skip.join(",")));
+        }
+        if !keep_index_temp_files() {
+            let _ = std::fs::remove_file(&expr_path);
+            let _ = std::fs::remove_file(&json_path);
+        }
+        return Err(CliError::NixEnvFailed(message));
+    }
+}
+
+pub(crate) fn temp_index_json_path() -> PathBuf {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let suffix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut path = std::env::temp_dir();
+    path.push(format!("mica-index-{}.json", suffix));
+    path
+}
+
+pub(crate) fn temp_index_nix_path() -> PathBuf {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let suffix = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let mut path = std::env::temp_dir();
+    path.push(format!("mica-index-{}.nix", suffix));
+    path
+}
+
+pub(crate) fn nix_string_list(items: &[String]) -> String {
+    if items.is_empty() {
+        return "[ ]".to_string();
+    }
+    let mut out = String::from("[");
+    for item in items {
+        out.push(' ');
+        out.push('"');
+        out.push_str(&escape_nix_string(item));
+        out.push('"');
+    }
+    out.push_str(" ]");
+    out
+}
+
+pub(crate) fn nix_env_expression(pin: &Pin, skip: &[String], rewrites: &[UrlRewrite]) -> String {
+    let url = pin_tarball_url(pin, rewrites);
+    let skip_regex: Vec<String> = skip.iter().map(|entry| glob_to_regex(entry)).collect();
+    let skip_list = nix_string_list(&skip_regex);
+    format!(
+        r#"let
+  src = builtins.fetchTarball {{
+    url = "{url}";
+    sha256 = "{sha256}";
+  }};
+  rewriteUrl = {rewrite_url};
+  lockPath = src + "/flake.lock";
+  lock = if builtins.pathExists lockPath
+    then builtins.fromJSON (builtins.readFile lockPath)
+    else null;
+  nixpkgsLocked = if lock != null
+    && lock ? nodes
+    && lock.nodes ? nixpkgs
+    && lock.nodes.nixpkgs ? locked
+    then lock.nodes.nixpkgs.locked
+    else null;
+  nixpkgsSrc = if nixpkgsLocked != null
+    && nixpkgsLocked ? owner
+    && nixpkgsLocked ? repo
+    && nixpkgsLocked ? rev
+    && nixpkgsLocked ? narHash
+    then builtins.fetchTarball {{
+      url = rewriteUrl "https://github.com/${{nixpkgsLocked.owner}}/${{nixpkgsLocked.repo}}/archive/${{nixpkgsLocked.rev}}.tar.gz";
+      sha256 = nixpkgsLocked.narHash;
+    }}
+    else src;
+  baseAttempt =
+    let imported = import src;
+    in builtins.tryEval (
+      if builtins.isFunction imported
+      then imported {{ }}
+      else imported
+    );
+  baseFallback =
+    let imported = import nixpkgsSrc;
+    in if builtins.isFunction imported
+      then imported {{ }}
+      else imported;
+  base = if baseAttempt.success then baseAttempt.value else baseFallback;
+  isAttrSet = v: builtins.typeOf v == "set";
+  isDerivation = v: isAttrSet v && v ? type && v.type == "derivation";
+  pkgs = if base != null && isAttrSet base && base ? pkgs
+    then base.pkgs
+    else if base != null && isAttrSet base
+    then base
+    else baseFallback;
+  sanitize = attrs:
+    if attrs == null || !isAttrSet attrs
+      then {{ }}
+      else
+        let namesAttempt = builtins.tryEval (builtins.attrNames attrs);
+            skip = {skip_list};
+            matchesSkip = name:
+              builtins.any (pattern: builtins.match pattern name != null) skip;
+            names = if namesAttempt.success
+              then builtins.filter (name: !(matchesSkip name)) namesAttempt.value
+              else [];
+        in builtins.foldl' (acc: name:
+             let attempt = builtins.tryEval attrs.${{name}};
+             in if !attempt.success then acc
+                else if isDerivation attempt.value
+                  then acc // {{ ${{name}} = attempt.value; }}
+                else if isAttrSet attempt.value
+                  then acc // {{ ${{name}} = sanitize attempt.value; }}
+                else acc
+           ) {{ }} names;
+in sanitize pkgs
+"#,
+        url = url,
+        sha256 = pin.sha256,
+        skip_list = skip_list,
+        rewrite_url = nix_url_rewriter(rewrites)
+    )
+}
+
+pub(crate) fn nix_env_expression_from_local_repo(
+    repo_path: &Path,
+    skip: &[String],
+    rewrites: &[UrlRewrite],
+) -> String {
+    let repo_path = escape_nix_string(repo_path.to_string_lossy().as_ref());
+    let skip_regex: Vec<String> = skip.iter().map(|entry| glob_to_regex(entry)).collect();
+    let skip_list = nix_string_list(&skip_regex);
+    format!(
+        r#"let
+  src = builtins.toPath "{repo_path}";
+  rewriteUrl = {rewrite_url};
+  lockPath = src + "/flake.lock";
+  lock = if builtins.pathExists lockPath
+    then builtins.fromJSON (builtins.readFile lockPath)
+    else null;
+  nixpkgsLocked = if lock != null
+    && lock ? nodes
+    && lock.nodes ? nixpkgs
+    && lock.nodes.nixpkgs ? locked
+    then lock.nodes.nixpkgs.locked
+    else null;
+  nixpkgsSrc = if nixpkgsLocked != null
+    && nixpkgsLocked ? owner
+    && nixpkgsLocked ? repo
+    && nixpkgsLocked ? rev
+    && nixpkgsLocked ? narHash
+    then builtins.fetchTarball {{
+      url = rewriteUrl "https://github.com/${{nixpkgsLocked.owner}}/${{nixpkgsLocked.repo}}/archive/${{nixpkgsLocked.rev}}.tar.gz";
+      sha256 = nixpkgsLocked.narHash;
+    }}
+    else src;
+  baseAttempt =
+    let imported = import src;
+    in builtins.tryEval (
+      if builtins.isFunction imported
+      then imported {{ }}
+      else imported
+    );
+  baseFallback =
+    let imported = import nixpkgsSrc;
+    in if builtins.isFunction imported
+      then imported {{ }}
+      else imported;
+  base = if baseAttempt.success then baseAttempt.value else baseFallback;
+  isAttrSet = v: builtins.typeOf v == "set";
+  isDerivation = v: isAttrSet v && v ? type && v.type == "derivation";
+  pkgs = if base != null && isAttrSet base && base ? pkgs
+    then base.pkgs
+    else if base != null && isAttrSet base
+    then base
+    else baseFallback;
+  sanitize = attrs:
+    if attrs == null || !isAttrSet attrs
+      then {{ }}
+      else
+        let namesAttempt = builtins.tryEval (builtins.attrNames attrs);
+            skip = {skip_list};
+            matchesSkip = name:
+              builtins.any (pattern: builtins.match pattern name != null) skip;
+            names = if namesAttempt.success
+              then builtins.filter (name: !(matchesSkip name)) namesAttempt.value
+              else [];
+        in builtins.foldl' (acc: name:
+             let attempt = builtins.tryEval attrs.${{name}};
+             in if !attempt.success then acc
+                else if isDerivation attempt.value
+                  then acc // {{ ${{name}} = attempt.value; }}
+                else if isAttrSet attempt.value
+                  then acc // {{ ${{name}} = sanitize attempt.value; }}
+                else acc
+           ) {{ }} names;
+in sanitize pkgs
+"#,
+        repo_path = repo_path,
+        skip_list = skip_list,
+        rewrite_url = nix_url_rewriter(rewrites)
+    )
+}
@@ -0,0 +1,253 @@
+use crate::clock::Clock;
+use crate::indexops::IndexPin;
+use crate::multiuser::{dir_writable, shared_index_dir};
+use crate::{cache_dir, CliError, Output};
+use chrono::{DateTime, Utc};
+use mica_core::config::Config;
+use reqwest::blocking::Client;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub(crate) fn resolve_remote_index_urls(remote_url: &str, commit: Option<&str>) -> Vec<String> {
+    let trimmed = remote_url.trim();
+    if trimmed.is_empty() {
+        return Vec::new();
+    }
+    if trimmed.ends_with(".db") {
+        return vec![trimmed.to_string()];
+    }
+    let base = trimmed.trim_end_matches('/');
+    let mut urls = Vec::new();
+    if let Some(commit) = commit.map(str::trim).filter(|value| !value.is_empty()) {
+        urls.push(format!("{}/{}.db", base, commit));
+    }
+    urls
+}
+
+pub(crate) fn fetch_remote_index_url(url: &str, output_path: &Path) -> Result<(), CliError> {
+    fetch_remote_index_url_with_progress(url, output_path, &mut |_, _| true)
+}
+
+pub(crate) fn fetch_remote_index_url_with_progress(
+    url: &str,
+    output_path: &Path,
+    progress: &mut dyn FnMut(u64, Option<u64>) -> bool,
+) -> Result<(), CliError> {
+    let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+    let mut response = client.get(url).send()?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.text().unwrap_or_default();
+        return Err(CliError::RemoteIndexFailed(status, body));
+    }
+    let total = response.content_length();
+    if let Some(parent) = output_path.parent() {
+        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+    }
+    let tmp_path = output_path.with_extension("tmp");
+    let mut file = std::fs::File::create(&tmp_path).map_err(CliError::WriteNix)?;
+    let mut buffer = [0u8; 64 * 1024];
+    let mut done = 0u64;
+    loop {
+        let read = response.read(&mut buffer).map_err(CliError::WriteNix)?;
+        if read == 0 {
+            break;
+        }
+        file.write_all(&buffer[..read])
+            .map_err(CliError::WriteNix)?;
+        done += read as u64;
+        if !progress(done, total) {
+            drop(file);
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(CliError::Cancelled);
+        }
+    }
+    drop(file);
+    std::fs::rename(&tmp_path, output_path).map_err(CliError::WriteNix)?;
+    Ok(())
+}
+
+pub(crate) fn fetch_remote_index_with_progress(
+    remote_url: &str,
+    output_path: &Path,
+    commit: Option<&str>,
+    progress: &mut dyn FnMut(u64, Option<u64>) -> bool,
+) -> Result<bool, CliError> {
+    let mut last_error: Option<CliError> = None;
+    for url in resolve_remote_index_urls(remote_url, commit) {
+        match fetch_remote_index_url_with_progress(&url, output_path, progress) {
+            Ok(()) => return Ok(true),
+            Err(CliError::RemoteIndexFailed(status, _))
+                if status == reqwest::StatusCode::NOT_FOUND => {}
+            Err(CliError::Cancelled) => return Err(CliError::Cancelled),
+            Err(err) => last_error = Some(err),
+        }
+    }
+    match last_error {
+        Some(err) => Err(err),
+        None => Ok(false),
+    }
+}
+
+pub(crate) fn try_fetch_remote_index(
+    output: &Output,
+    remote_url: &str,
+    output_path: &Path,
+    commit: Option<&str>,
+) -> Result<bool, CliError> {
+    let urls = resolve_remote_index_urls(remote_url, commit);
+    if urls.is_empty() {
+        return Ok(false);
+    }
+
+    let mut last_error: Option<CliError> = None;
+    for url in urls {
+        output.status(format!("fetching remote index from {}", url));
+        match fetch_remote_index_url(&url, output_path) {
+            Ok(()) => {
+                output.status("remote index fetched");
+                return Ok(true);
+            }
+            Err(CliError::RemoteIndexFailed(status, _))
+                if status == reqwest::StatusCode::NOT_FOUND =>
+            {
+                output.verbose(format!("remote index not found at {}", url));
+            }
+            Err(err) => {
+                output.verbose(format!("remote index fetch failed at {}: {}", url, err));
+                last_error = Some(err);
+            }
+        }
+    }
+
+    if let Some(err) = last_error {
+        output.warn(format!("remote index fetch failed: {}", err));
+    }
+    Ok(false)
+}
+
+pub(crate) fn primary_pin_rev(pins: &[IndexPin]) -> Option<&str> {
+    pins.first()
+        .map(|entry| entry.pin.rev.trim())
+        .filter(|value| !value.is_empty())
+}
+
+pub(crate) fn try_fetch_remote_index_for_pins(
+    output: &Output,
+    clock: &dyn Clock,
+    config: Option<&Config>,
+    index_path: &Path,
+    pins: &[IndexPin],
+) -> Result<bool, CliError> {
+    let Some(config) = config else {
+        return Ok(false);
+    };
+    let fetched = try_fetch_remote_index(
+        output,
+        &config.index.remote_url,
+        index_path,
+        primary_pin_rev(pins),
+    )?;
+    if !config.index.remote_url.trim().is_empty() {
+        record_index_check_time(output, clock);
+    }
+    Ok(fetched)
+}
+
+pub(crate) fn index_check_path() -> Result<PathBuf, CliError> {
+    Ok(cache_dir()?.join("index.last_check"))
+}
+
+pub(crate) fn read_index_check_time() -> Result<Option<DateTime<Utc>>, CliError> {
+    let path = index_check_path()?;
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(CliError::ReadNix(err)),
+    };
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return Ok(None);
+    }
+    match DateTime::parse_from_rfc3339(trimmed) {
+        Ok(dt) => Ok(Some(dt.with_timezone(&Utc))),
+        Err(_) => Ok(None),
+    }
+}
+
+pub(crate) fn write_index_check_time(now: DateTime<Utc>) -> Result<(), CliError> {
+    let path = index_check_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+    }
+    std::fs::write(path, now.to_rfc3339()).map_err(CliError::WriteNix)
+}
+
+pub(crate) fn record_index_check_time(output: &Output, clock: &dyn Clock) {
+    if let Err(err) = write_index_check_time(clock.now()) {
+        output.verbose(format!("index check timestamp write failed: {}", err));
+    }
+}
+
+pub(crate) fn should_check_remote_index(
+    clock: &dyn Clock,
+    config: &Config,
+) -> Result<bool, CliError> {
+    if config.index.remote_url.trim().is_empty() {
+        return Ok(false);
+    }
+    if config.index.update_check_interval == 0 {
+        return Ok(false);
+    }
+    if shared_index_dir(config).is_some_and(|dir| !dir_writable(&dir)) {
+        return Ok(false);
+    }
+    let now = clock.now();
+    if let Some(last) = read_index_check_time()? {
+        let elapsed = now.signed_duration_since(last);
+        let interval = chrono::Duration::hours(config.index.update_check_interval as i64);
+        if elapsed < interval {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+pub(crate) fn maybe_refresh_remote_index(
+    output: &Output,
+    clock: &dyn Clock,
+    config: &Config,
+    index_path: &Path,
+    commit: Option<&str>,
+) -> Result<bool, CliError> {
+    if !should_check_remote_index(clock, config)? {
+        return Ok(false);
+    }
+    output.status("checking remote index for updates");
+    let fetched = try_fetch_remote_index(output, &config.index.remote_url, index_path, commit)?;
+    record_index_check_time(output, clock);
+    Ok(fetched)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::indexops::remote::resolve_remote_index_urls;
+    #[test]
+    fn resolve_remote_index_urls_uses_commit_for_base_url() {
+        let urls = resolve_remote_index_urls("https://static.g7c.us/mica", Some("abcd1234"));
+        assert_eq!(
+            urls,
+            vec!["https://static.g7c.us/mica/abcd1234.db".to_string()]
+        );
+    }
+
+    #[test]
+    fn resolve_remote_index_urls_keeps_explicit_db_url() {
+        let urls = resolve_remote_index_urls("https://static.g7c.us/mica/index.db", Some("abcd"));
+        assert_eq!(
+            urls,
+            vec!["https://static.g7c.us/mica/index.db".to_string()]
+        );
+    }
+}
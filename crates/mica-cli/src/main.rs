@@ -1,43 +1,60 @@
+use crate::clock::{clock_from_env, Clock};
+use crate::commands::tui::resolve_pinned_version;
+use crate::github::{latest_rev_for_pin, GitHubProvider, GitProvider};
+use crate::indexops::compat::warn_incompatible_caches;
+use crate::multiuser::{shared_index_dir, warn_shared_home};
+use crate::nixops::generations::profile_installed_packages;
+use crate::nixops::{fetch_channel_sha256, fetch_nix_sha256, sync_and_install_profile};
+use crate::timing::timed;
 use chrono::{DateTime, Utc};
-use clap::{CommandFactory, Parser, Subcommand};
-use clap_complete::{generate, Shell};
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
-use mica_core::config::Config;
-use mica_core::nixgen::{generate_profile_nix, generate_project_nix};
+use clap::{Parser, Subcommand};
+use clap_complete::Shell;
+use mica_core::config::{Config, TimestampMode};
+use mica_core::diff::diff_hunks;
+use mica_core::migrate::migrate_project_nix;
+use mica_core::nixgen::{
+    build_env_inherit_line, generate_profile_nix, generate_project_nix, generated_at,
+    is_reserved_pin_name, project_paths_line, replace_manifest_header, same_ignoring_generated_at,
+    set_generated_at,
+};
 use mica_core::nixparse::{
-    parse_nix_file, parse_profile_nix, parse_profile_state_from_nix, parse_project_state_from_nix,
+    parse_build_env_section, parse_generator_version, parse_nix_file, parse_profile_nix,
+    parse_profile_state_from_nix, parse_project_state_from_nix,
 };
 use mica_core::preset::{
-    load_embedded_presets, load_presets_from_dir, merge_presets, merge_profile_presets, Preset,
+    load_embedded_presets, load_presets_from_dir, merge_presets, merge_profile_presets,
+    MergedResult, Preset,
 };
+use mica_core::registry::ProjectRegistry;
 use mica_core::state::{
-    GenerationEntry, GlobalProfileState, MicaMetadata, NixBlocks, Pin, PinnedPackage, PresetState,
-    ProjectState, ShellState, NIX_EXPR_PREFIX,
-};
-use mica_index::generate::{
-    get_meta, ingest_packages, init_db, list_packages, load_packages_from_json, open_db,
-    search_packages_with_mode, set_meta, SearchMode as IndexSearchMode,
+    check_version, BuildEnvState, GlobalProfileState, HostInfo, ManifestState, MicaMetadata,
+    NixBlocks, Pin, PinChannel, PinnedPackage, PresetState, ProjectState, ShellState, VersionCheck,
+    MICA_VERSION,
 };
-use mica_index::versions::{
-    init_versions_db, latest_version_for_source, list_versions, open_versions_db, record_versions,
-    version_for_commit, VersionSource,
-};
-use reqwest::blocking::Client;
-use serde::Deserialize;
+use mica_index::generate::SearchMode as IndexSearchMode;
 use std::collections::{BTreeMap, BTreeSet};
 use std::io;
 use std::io::{IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::process::Command as ProcessCommand;
-use std::process::Stdio;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
 use std::thread;
-use std::time::Duration;
-
+use std::time::{Duration, Instant};
+
+mod clock;
+mod commands;
+mod daemon;
+mod github;
+mod indexops;
+mod multiuser;
+mod nixops;
+mod timing;
 mod tui;
+use crate::commands::{CliCommand, CommandContext};
+
+const DRY_RUN_DIFF_CONTEXT: usize = 3;
 
 #[derive(Debug, Parser)]
 #[command(name = "mica", version, about = "A TUI for managing Nix environments")]
@@ -90,6 +107,11 @@ enum Command {
             help = "GitHub repo URL for nixpkgs (defaults to config or MICA_NIXPKGS_REPO)"
         )]
         repo: Option<String>,
+        #[arg(
+            long,
+            help = "Import packages already installed with nix-env (global profile only)"
+        )]
+        adopt: bool,
     },
     #[command(about = "List current state")]
     List,
@@ -99,6 +121,16 @@ enum Command {
     Add { packages: Vec<String> },
     #[command(about = "Remove packages from environment")]
     Remove { packages: Vec<String> },
+    #[command(about = "Open an ephemeral shell with packages without changing state")]
+    Try {
+        #[arg(required = true)]
+        packages: Vec<String>,
+        #[arg(
+            long,
+            help = "Run a command in the shell instead of starting it interactively"
+        )]
+        run: Option<String>,
+    },
     #[command(about = "Search packages (index required)")]
     Search {
         query: String,
@@ -144,19 +176,98 @@ enum Command {
         sha256: Option<String>,
         #[arg(long, help = "Set nixpkgs branch for the pin")]
         branch: Option<String>,
+        #[arg(
+            long,
+            value_name = "CHANNEL",
+            help = "Resolve the pin revision from a nixos channel (e.g. nixos-24.05, nixpkgs-unstable)",
+            conflicts_with_all = ["rev", "latest", "branch"]
+        )]
+        channel: Option<String>,
+    },
+    #[command(about = "Layer this environment on top of a parent default.nix")]
+    Extends {
+        #[arg(help = "Path to the parent nix file, relative to the project dir")]
+        path: Option<String>,
+        #[arg(long, help = "Stop extending a parent", conflicts_with = "path")]
+        clear: bool,
+    },
+    #[command(about = "Describe what this environment is for (defaults to show)")]
+    Describe {
+        #[arg(help = "Short description rendered in the nix file header")]
+        description: Option<String>,
+        #[arg(long, help = "Owning team or person")]
+        owner: Option<String>,
+        #[arg(
+            long,
+            help = "Remove the description and owner",
+            conflicts_with_all = ["description", "owner"]
+        )]
+        clear: bool,
+    },
+    #[command(
+        about = "Upgrade managed nix files to the current mica layout",
+        args_conflicts_with_subcommands = true
+    )]
+    Migrate {
+        #[command(subcommand)]
+        command: Option<MigrateCommand>,
+        #[arg(
+            long,
+            help = "Migrate every project mica has seen, not just the current one"
+        )]
+        all_projects: bool,
+    },
+    #[command(about = "Report stale pins and packages")]
+    Outdated {
+        #[arg(
+            long,
+            help = "Check every project mica has seen, not just the current one"
+        )]
+        all_projects: bool,
+    },
+    #[command(about = "Customize the generated buildEnv (defaults to show)")]
+    BuildEnv {
+        #[command(subcommand)]
+        command: Option<BuildEnvCommand>,
     },
     #[command(about = "Manage extra pins")]
     Pin {
         #[command(subcommand)]
         command: PinCommand,
     },
+    #[command(about = "Back up or restore the mica config directory")]
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommand,
+    },
+    #[command(about = "Dump or load project state as TOML or JSON")]
+    State {
+        #[command(subcommand)]
+        command: StateCommand,
+    },
     #[command(about = "Manage global generations")]
     Generations {
         #[command(subcommand)]
         command: GenerationsCommand,
     },
+    #[command(about = "Run or control the background daemon (defaults to run)")]
+    Daemon {
+        #[command(subcommand)]
+        command: Option<DaemonCommand>,
+    },
     #[command(about = "Output standalone nix file to stdout")]
-    Export,
+    Export {
+        #[arg(
+            long,
+            help = "Append a footer with the mica version, state hash and content hash"
+        )]
+        footer: bool,
+        #[arg(
+            long,
+            help = "Add a micaCheck attribute that verifies the footer and pin sha256 at eval time (implies --footer)"
+        )]
+        check: bool,
+    },
     #[command(about = "Manage package index")]
     Index {
         #[command(subcommand)]
@@ -169,13 +280,39 @@ enum Command {
     },
     #[command(about = "Validate current configuration")]
     Eval,
-    #[command(about = "Check for drift between state and nix file")]
-    Diff,
+    #[command(about = "Check for drift between state, nix file and installed profile")]
+    Diff {
+        #[arg(
+            long,
+            value_enum,
+            default_value = "nix",
+            help = "Layer to compare against (nix, installed, all)"
+        )]
+        target: DiffTarget,
+    },
     #[command(about = "Generate shell completion script")]
     Completion {
         #[arg(value_enum, help = "Target shell")]
         shell: Shell,
     },
+    #[command(hide = true, about = "Render a TUI screen with sample data to stdout")]
+    TuiSnapshot {
+        #[arg(value_enum, help = "Screen to render (defaults to all screens)")]
+        screen: Option<tui::snapshot::Screen>,
+        #[arg(long, help = "Terminal width (defaults to the screen's snapshot size)")]
+        width: Option<u16>,
+        #[arg(
+            long,
+            help = "Terminal height (defaults to the screen's snapshot size)"
+        )]
+        height: Option<u16>,
+    },
+    #[cfg(feature = "bench")]
+    #[command(about = "Time core flows against their performance budgets")]
+    Bench {
+        #[arg(long, default_value_t = 10, help = "Iterations per flow")]
+        iterations: usize,
+    },
 }
 
 #[derive(Debug, Clone, Copy, clap::ValueEnum)]
@@ -205,6 +342,59 @@ enum EnvCommand {
     Unset { key: String },
 }
 
+#[derive(Debug, Subcommand)]
+enum BuildEnvCommand {
+    #[command(about = "Show buildEnv settings")]
+    Show,
+    #[command(about = "Override the derivation name (defaults to the project dir name)")]
+    Name {
+        name: Option<String>,
+        #[arg(long, help = "Use the project dir name again", conflicts_with = "name")]
+        clear: bool,
+    },
+    #[command(about = "Add outputs to extraOutputsToInstall (e.g. man, dev)")]
+    AddOutput { outputs: Vec<String> },
+    #[command(about = "Remove outputs from extraOutputsToInstall")]
+    RemoveOutput { outputs: Vec<String> },
+    #[command(about = "Choose whether colliding files fail the build")]
+    Collisions {
+        #[arg(value_enum)]
+        mode: CollisionsArg,
+    },
+    #[command(about = "Give a package priority when its files collide with another package")]
+    Prefer {
+        package: String,
+        #[arg(
+            long,
+            allow_negative_numbers = true,
+            help = "Explicit priority (lower wins, nixpkgs default is 5)"
+        )]
+        priority: Option<i32>,
+        #[arg(long, help = "Drop the package priority", conflicts_with = "priority")]
+        clear: bool,
+    },
+    #[command(about = "Set meta.priority of the environment")]
+    Priority {
+        #[arg(allow_negative_numbers = true)]
+        value: Option<i32>,
+        #[arg(long, help = "Remove the priority", conflicts_with = "value")]
+        clear: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DiffTarget {
+    Nix,
+    Installed,
+    All,
+}
+
+#[derive(Debug, Clone, Copy, clap::ValueEnum)]
+enum CollisionsArg {
+    Error,
+    Ignore,
+}
+
 #[derive(Debug, Subcommand)]
 enum ShellCommand {
     #[command(about = "Set shell hook content (overwrites)")]
@@ -217,7 +407,7 @@ enum ShellCommand {
 enum PinCommand {
     #[command(about = "Add an extra pin")]
     Add {
-        #[arg(help = "Pin name (used as attribute)")]
+        #[arg(help = "Pin name (used as attribute)", value_parser = parse_pin_name)]
         name: String,
         #[arg(long, help = "GitHub repo URL for the pin")]
         url: String,
@@ -234,11 +424,23 @@ enum PinCommand {
             help = "Set nixpkgs sha256 for the pin (auto-computed when rev/latest is set)"
         )]
         sha256: Option<String>,
+        #[arg(
+            long,
+            value_name = "CHANNEL",
+            help = "Resolve the pin revision from a nixos channel (e.g. nixos-24.05, nixpkgs-unstable)",
+            conflicts_with_all = ["rev", "latest", "branch"]
+        )]
+        channel: Option<String>,
     },
     #[command(about = "Remove an extra pin")]
     Remove { name: String },
     #[command(about = "List extra pins")]
     List,
+    #[command(about = "Re-prefetch each pin's tarball and check the stored sha256")]
+    Verify {
+        #[arg(long, help = "Only check that each tarball is still downloadable")]
+        quick: bool,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -249,16 +451,85 @@ enum GenerationsCommand {
     Rollback { id: Option<u64> },
 }
 
+#[derive(Debug, Subcommand)]
+enum BackupCommand {
+    #[command(about = "Write config, profile, generations and presets to a .tar.gz archive")]
+    Create {
+        #[arg(help = "Archive path to write")]
+        archive: PathBuf,
+        #[arg(long, help = "Include the package index cache")]
+        with_index: bool,
+    },
+    #[command(about = "Restore a backup archive into the mica config directory")]
+    Restore {
+        #[arg(help = "Archive path to read")]
+        archive: PathBuf,
+        #[arg(
+            long,
+            value_enum,
+            value_delimiter = ',',
+            help = "Only restore these sections"
+        )]
+        only: Vec<commands::backup::BackupSection>,
+        #[arg(long, help = "Overwrite files that already exist")]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum StateCommand {
+    #[command(about = "Print the project state parsed from default.nix")]
+    Dump {
+        #[arg(help = "File to write, or - for stdout", default_value = "-")]
+        file: PathBuf,
+        #[arg(long, value_enum, default_value = "toml", help = "Output format")]
+        format: StateFormat,
+    },
+    #[command(about = "Regenerate default.nix from a state dump")]
+    Load {
+        #[arg(help = "File to read, or - for stdin")]
+        file: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum StateFormat {
+    Toml,
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum DaemonCommand {
+    #[command(about = "Run the daemon in the foreground")]
+    Run,
+    #[command(about = "Show daemon status")]
+    Status,
+    #[command(about = "Stop a running daemon")]
+    Stop,
+}
+
 #[derive(Debug, Subcommand)]
 enum IndexCommand {
     #[command(about = "Show index status")]
     Status,
     #[command(about = "Rebuild local index from nix-env json")]
     Rebuild {
-        #[arg(help = "Path to nix-env -qaP --json output")]
-        input: PathBuf,
+        #[arg(
+            required_unless_present = "from_pin",
+            help = "Paths to nix-env -qaP --json output, merged in order (later files win)"
+        )]
+        inputs: Vec<PathBuf>,
         #[arg(long, help = "Output path for the index db")]
         output: Option<PathBuf>,
+        #[arg(long, help = "Discard an interrupted rebuild instead of resuming it")]
+        fresh: bool,
+        #[arg(
+            long,
+            value_name = "NAME",
+            conflicts_with_all = ["inputs", "fresh"],
+            help = "Re-evaluate only this pin and merge it into the existing index"
+        )]
+        from_pin: Option<String>,
     },
     #[command(about = "Evaluate a local nix repo and rebuild index")]
     RebuildLocal {
@@ -278,12 +549,49 @@ enum IndexCommand {
     },
     #[command(about = "Fetch remote index")]
     Fetch,
+    #[command(about = "Write the bundled fallback package list from the local index")]
+    Fallback {
+        #[arg(help = "File to write, or - for stdout")]
+        file: PathBuf,
+        #[arg(
+            long,
+            help = "File with one attr per line (defaults to the attrs in the bundled list)"
+        )]
+        attrs: Option<PathBuf>,
+    },
+    #[command(about = "Export or import the version history database")]
+    Versions {
+        #[command(subcommand)]
+        command: VersionsCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum MigrateCommand {
+    #[command(about = "Check the index and versions caches, refreshing incompatible ones")]
+    Caches,
+}
+
+#[derive(Debug, Subcommand)]
+enum VersionsCommand {
+    #[command(about = "Write version history as JSONL")]
+    Export {
+        #[arg(help = "File to write, or - for stdout")]
+        file: PathBuf,
+    },
+    #[command(about = "Merge version history from a JSONL export")]
+    Import {
+        #[arg(help = "File to read, or - for stdin")]
+        file: PathBuf,
+    },
 }
 
 #[derive(Debug, thiserror::Error)]
 enum CliError {
     #[error("missing default.nix at {0}")]
     MissingDefaultNix(PathBuf),
+    #[error("this command needs a project default.nix and cannot run with --global")]
+    MissingProjectPaths,
     #[error("missing state file at {0}")]
     MissingState(PathBuf),
     #[error("state file already exists at {0}")]
@@ -302,22 +610,47 @@ enum CliError {
     Config(#[from] mica_core::config::ConfigError),
     #[error("missing preset: {0}")]
     MissingPreset(String),
+    #[error("package is not part of this environment: {0}")]
+    PackageNotSelected(String),
     #[error("failed to write nix file: {0}")]
     WriteNix(std::io::Error),
+    #[error("failed to write timing log: {0}")]
+    TimingLogIo(std::io::Error),
+    #[error("failed to write export: {0}")]
+    ExportIo(std::io::Error),
     #[error("failed to read nix file: {0}")]
     ReadNix(std::io::Error),
     #[error("nix parse error: {0}")]
     NixParse(mica_core::nixparse::ParseError),
     #[error("nix state parse error: {0}")]
     NixStateParse(mica_core::nixparse::StateParseError),
+    #[error("{0} was written by mica {1}, which is newer than this mica, refusing to modify it")]
+    ReadOnlyState(PathBuf, String),
+    #[error("timed out waiting for {0}, remove it if no other mica is running")]
+    StateLocked(PathBuf),
+    #[error("failed to lock state file {0}: {1}")]
+    StateLockIo(PathBuf, std::io::Error),
+    #[error("nix layout error: {0}")]
+    Migrate(#[from] mica_core::migrate::MigrateError),
+    #[cfg(feature = "bench")]
+    #[error("{0} benchmark(s) exceeded their budget")]
+    BenchBudgetExceeded(usize),
     #[error("index error: {0}")]
     Index(#[from] mica_index::generate::IndexError),
     #[error("missing index at {0}")]
     MissingIndex(PathBuf),
+    #[error("index at {0} does not record a nixpkgs commit, rebuild it from a pin first")]
+    IndexMissingCommit(PathBuf),
+    #[error("shared index directory {0} is not writable by this user; ask its owner to update the index, or unset index.shared_dir")]
+    SharedIndexReadOnly(PathBuf),
+    #[error("--adopt only applies to the global profile, run `mica -g init --adopt`")]
+    AdoptRequiresGlobal,
     #[error("missing remote index url in config")]
     MissingRemoteIndex,
     #[error("remote index fetch failed ({0}): {1}")]
     RemoteIndexFailed(reqwest::StatusCode, String),
+    #[error("cancelled")]
+    Cancelled,
     #[error("generation history is empty")]
     NoGenerations,
     #[error("generation {0} not found")]
@@ -326,10 +659,22 @@ enum CliError {
     GenerationSnapshotMissing(PathBuf),
     #[error("invalid pin name: {0}")]
     InvalidPinName(String),
+    #[error("pin name {0} is reserved in generated nix, try {1}")]
+    ReservedPinName(String, String),
     #[error("pin already exists: {0}")]
     PinExists(String),
     #[error("pin not found: {0}")]
     PinNotFound(String),
+    #[error("no index pin named {0} (available: {1})")]
+    IndexPinNotFound(String, String),
+    #[error("failed to read or write state dump: {0}")]
+    StateDumpIo(std::io::Error),
+    #[error("invalid state dump: {0}")]
+    InvalidStateDump(String),
+    #[error("{0} pin(s) failed verification")]
+    PinVerifyFailed(usize),
+    #[error("tarball request failed ({0})")]
+    TarballStatus(reqwest::StatusCode),
     #[error("invalid github repo url: {0}")]
     InvalidGitHubUrl(String),
     #[error("github api request failed ({0}): {1}")]
@@ -340,6 +685,12 @@ enum CliError {
     GitHubApiMissingDefaultBranch,
     #[error("github api response missing commit date")]
     GitHubApiMissingDate,
+    #[error("invalid channel name: {0}")]
+    InvalidChannel(String),
+    #[error("channel lookup failed for {0} ({1})")]
+    ChannelStatus(String, reqwest::StatusCode),
+    #[error("channel {0} did not report a git revision")]
+    ChannelMissingRev(String),
     #[error("http error: {0}")]
     Http(#[from] reqwest::Error),
     #[error("nix-prefetch-url not found in PATH, install Nix or pass --sha256")]
@@ -360,38 +711,48 @@ enum CliError {
     NixBuildFailed(String),
     #[error("failed to create temp nix file: {0}")]
     TempNixFile(std::io::Error),
+    #[error("nix-shell not found in PATH, install Nix to try packages")]
+    MissingNixShell,
+    #[error("nix-shell failed: {0}")]
+    NixShellFailed(String),
     #[error("nix-env not found in PATH, install Nix to auto-build the index")]
     MissingNixEnv,
     #[error("failed to run nix-env: {0}")]
     NixEnvIo(std::io::Error),
     #[error("nix-env failed: {0}")]
     NixEnvFailed(String),
-}
-
-#[derive(Debug, Deserialize)]
-struct GitHubCommit {
-    sha: String,
-    #[serde(default)]
-    commit: GitHubCommitInfo,
-}
-
-#[derive(Debug, Deserialize, Default)]
-struct GitHubCommitInfo {
-    #[serde(default)]
-    author: Option<GitHubCommitAuthor>,
-    #[serde(default)]
-    committer: Option<GitHubCommitAuthor>,
-}
-
-#[derive(Debug, Deserialize)]
-struct GitHubCommitAuthor {
-    date: String,
-}
-
-#[derive(Debug, Deserialize, Default)]
-struct GitHubRepoInfo {
-    #[serde(default)]
-    default_branch: String,
+    #[error("nix not found in PATH, install Nix or switch profile.backend to nix-env")]
+    MissingNix,
+    #[error("failed to run nix profile: {0}")]
+    NixProfileIo(std::io::Error),
+    #[error("nix profile failed: {0}")]
+    NixProfileFailed(String),
+    #[error("git not found in PATH")]
+    MissingGit,
+    #[error("failed to run git: {0}")]
+    GitIo(std::io::Error),
+    #[error("git failed: {0}")]
+    GitFailed(String),
+    #[error("invalid extends path: {0}")]
+    InvalidExtendsPath(String),
+    #[error("daemon already running at {0}")]
+    DaemonRunning(PathBuf),
+    #[error("daemon not running at {0}")]
+    DaemonNotRunning(PathBuf),
+    #[error("daemon socket error: {0}")]
+    DaemonIo(io::Error),
+    #[error("daemon protocol error: {0}")]
+    DaemonProtocol(String),
+    #[error("backup archive error: {0}")]
+    BackupIo(std::io::Error),
+    #[error("invalid backup archive: {0}")]
+    InvalidBackup(String),
+    #[error("refusing to restore through symlink {0}")]
+    BackupSymlink(PathBuf),
+    #[error("{0} file(s) would be overwritten, pass --force to restore over them")]
+    BackupConflict(usize),
+    #[error("registry error: {0}")]
+    Registry(#[from] mica_core::registry::RegistryError),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -496,3868 +857,157 @@ fn run() -> Result<(), CliError> {
         Some(ProjectPaths::new(cli.file.clone(), cli.dir.clone())?)
     };
 
-    match command {
-        Command::Tui => {
-            if cli.dry_run {
-                output.info("dry-run ignored for TUI");
-            }
-            run_tui(cli.global, project_paths.as_ref(), &output)
-        }
-        Command::Init { repo } => {
-            if cli.global {
-                if cli.dry_run {
-                    let state = build_initial_profile_state(repo)?;
-                    output.info(format!(
-                        "dry-run: would initialize {}",
-                        profile_state_path()?.display()
-                    ));
-                    if output.verbose {
-                        output.info(build_profile_nix(&state)?);
-                    }
-                } else {
-                    init_profile_state(repo)?;
-                    let state = load_profile_state()?;
-                    sync_and_install_profile(&output, &state)?;
-                }
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                if cli.dry_run {
-                    if paths.nix_path.exists() {
-                        return Err(CliError::StateExists(paths.nix_path.to_path_buf()));
-                    }
-                    let state = build_initial_project_state(repo)?;
-                    output.info(format!(
-                        "dry-run: would initialize {}",
-                        paths.nix_path.display()
-                    ));
-                    if output.verbose {
-                        output.info(build_project_nix(paths, &state)?);
-                    }
-                } else {
-                    init_project_state(paths, repo)?;
-                }
-            }
-            Ok(())
-        }
-        Command::Add { packages } => {
-            if cli.global {
-                let mut state = load_profile_state()?;
-                for pkg in packages {
-                    if !state.packages.added.contains(&pkg) {
-                        state.packages.added.push(pkg.clone());
-                    }
-                    state.packages.removed.retain(|item| item != &pkg);
-                }
-                update_profile_modified(&mut state);
-                apply_profile_changes(&output, cli.dry_run, &state)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                for pkg in packages {
-                    if !state.packages.added.contains(&pkg) {
-                        state.packages.added.push(pkg.clone());
-                    }
-                    state.packages.removed.retain(|item| item != &pkg);
-                }
-                update_project_modified(&mut state);
-                apply_project_changes(&output, paths, cli.dry_run, &state)?;
-            }
-            Ok(())
-        }
-        Command::Remove { packages } => {
-            if cli.global {
-                let mut state = load_profile_state()?;
-                for pkg in packages {
-                    if !state.packages.removed.contains(&pkg) {
-                        state.packages.removed.push(pkg.clone());
-                    }
-                    state.packages.added.retain(|item| item != &pkg);
-                }
-                update_profile_modified(&mut state);
-                apply_profile_changes(&output, cli.dry_run, &state)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                for pkg in packages {
-                    if !state.packages.removed.contains(&pkg) {
-                        state.packages.removed.push(pkg.clone());
-                    }
-                    state.packages.added.retain(|item| item != &pkg);
-                }
-                update_project_modified(&mut state);
-                apply_project_changes(&output, paths, cli.dry_run, &state)?;
-            }
-            Ok(())
-        }
-        Command::Search { query, mode } => {
-            let index_path = index_db_path()?;
-            if !index_path.exists() {
-                return Err(CliError::MissingIndex(index_path));
-            }
-            let conn = open_db(&index_path)?;
-            let config = load_config_or_default()?;
-            let search_mode = mode
-                .map(|mode| mode.to_search_mode())
-                .unwrap_or(config.tui.search_mode);
-            let results =
-                search_packages_with_mode(&conn, &query, 25, to_index_search_mode(&search_mode))?;
-            for pkg in results {
-                let version = pkg.version.unwrap_or_else(|| "-".to_string());
-                let description = pkg.description.unwrap_or_default();
-                output.info(format!(
-                    "{} {} {}",
-                    normalize_attr_path(&pkg.attr_path),
-                    version,
-                    description
-                ));
-            }
-            Ok(())
-        }
-        Command::Env { command } => {
-            if cli.global {
-                output.info("env is only supported in project mode for now");
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                match command {
-                    EnvCommand::Set { key, value } => {
-                        state.env.insert(key, value);
-                    }
-                    EnvCommand::Unset { key } => {
-                        state.env.remove(&key);
-                    }
-                }
-                update_project_modified(&mut state);
-                apply_project_changes(&output, paths, cli.dry_run, &state)?;
-            }
-            Ok(())
-        }
-        Command::Shell { command } => {
-            if cli.global {
-                output.info("shell hook is only supported in project mode for now");
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                match command {
-                    ShellCommand::Set { content } => {
-                        state.shell.hook = Some(content);
-                    }
-                    ShellCommand::Clear => {
-                        state.shell.hook = None;
-                    }
-                }
-                update_project_modified(&mut state);
-                apply_project_changes(&output, paths, cli.dry_run, &state)?;
-            }
-            Ok(())
-        }
-        Command::Apply { presets } => {
-            if cli.global {
-                let mut state = load_profile_state()?;
-                for preset in presets {
-                    if !state.presets.active.contains(&preset) {
-                        state.presets.active.push(preset);
-                    }
-                }
-                update_profile_modified(&mut state);
-                apply_profile_changes(&output, cli.dry_run, &state)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                for preset in presets {
-                    if !state.presets.active.contains(&preset) {
-                        state.presets.active.push(preset);
-                    }
-                }
-                update_project_modified(&mut state);
-                apply_project_changes(&output, paths, cli.dry_run, &state)?;
-            }
-            Ok(())
-        }
-        Command::Unapply { presets } => {
-            if cli.global {
-                let mut state = load_profile_state()?;
-                state
-                    .presets
-                    .active
-                    .retain(|preset| !presets.contains(preset));
-                update_profile_modified(&mut state);
-                apply_profile_changes(&output, cli.dry_run, &state)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                state
-                    .presets
-                    .active
-                    .retain(|preset| !presets.contains(preset));
-                update_project_modified(&mut state);
-                apply_project_changes(&output, paths, cli.dry_run, &state)?;
-            }
-            Ok(())
-        }
-        Command::List => {
-            if cli.global {
-                let state = load_profile_state()?;
-                print_profile_state(&output, &state);
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let state = load_project_state(paths)?;
-                print_project_state(&output, &state);
-            }
-            Ok(())
-        }
-        Command::Presets => {
-            let mut presets = load_all_presets()?;
-            presets.sort_by(|left, right| {
-                left.order
-                    .cmp(&right.order)
-                    .then_with(|| left.name.cmp(&right.name))
-            });
-
-            for preset in presets {
-                let description = preset.description.trim();
-                if description.is_empty() {
-                    output.info(format!(
-                        "{} [order:{} req:{} opt:{}] {}",
-                        preset.name,
-                        preset.order,
-                        preset.packages_required.len(),
-                        preset.packages_optional.len(),
-                        preset.source.display()
-                    ));
-                } else {
-                    output.info(format!(
-                        "{} [order:{} req:{} opt:{}] {} - {}",
-                        preset.name,
-                        preset.order,
-                        preset.packages_required.len(),
-                        preset.packages_optional.len(),
-                        preset.source.display(),
-                        description
-                    ));
-                }
-            }
-            Ok(())
-        }
-        Command::Update {
-            package,
-            url,
-            latest,
-            rev,
-            sha256,
-            branch,
-        } => {
-            if cli.global {
-                let mut state = load_profile_state()?;
-                let base_pin = match package.as_deref() {
-                    Some(name) => state
-                        .packages
-                        .pinned
-                        .get(name)
-                        .map(|pinned| &pinned.pin)
-                        .unwrap_or(&state.pin),
-                    None => &state.pin,
-                };
-                let (resolved_rev, resolved_sha256) =
-                    resolve_update_rev_and_sha(base_pin, &url, &branch, rev, sha256, latest)?;
-                update_profile_pin_stub(
-                    &mut state,
-                    package,
-                    url,
-                    resolved_rev,
-                    resolved_sha256,
-                    branch,
-                )?;
-                apply_profile_changes(&output, cli.dry_run, &state)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                let base_pin = match package.as_deref() {
-                    Some(name) => state
-                        .packages
-                        .pinned
-                        .get(name)
-                        .map(|pinned| &pinned.pin)
-                        .unwrap_or(&state.pin),
-                    None => &state.pin,
-                };
-                let (resolved_rev, resolved_sha256) =
-                    resolve_update_rev_and_sha(base_pin, &url, &branch, rev, sha256, latest)?;
-                update_project_pin_stub(
-                    &mut state,
-                    package,
-                    url,
-                    resolved_rev,
-                    resolved_sha256,
-                    branch,
-                )?;
-                apply_project_changes(&output, paths, cli.dry_run, &state)?;
-            }
-            Ok(())
-        }
-        Command::Pin { command } => {
-            if cli.global {
-                output.info("pins are only supported in project mode for now");
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                match command {
-                    PinCommand::Add {
-                        name,
-                        url,
-                        branch,
-                        tarball_name,
-                        latest,
-                        rev,
-                        sha256,
-                    } => {
-                        add_extra_pin(
-                            &mut state,
-                            AddPinRequest {
-                                name,
-                                url,
-                                branch,
-                                tarball_name,
-                                rev,
-                                sha256,
-                                latest,
-                            },
-                        )?;
-                        apply_project_changes(&output, paths, cli.dry_run, &state)?;
-                    }
-                    PinCommand::Remove { name } => {
-                        if state.pins.remove(&name).is_none() {
-                            return Err(CliError::PinNotFound(name));
-                        }
-                        update_project_modified(&mut state);
-                        apply_project_changes(&output, paths, cli.dry_run, &state)?;
-                    }
-                    PinCommand::List => {
-                        if state.pins.is_empty() {
-                            output.info("no extra pins configured");
-                        } else {
-                            for (name, pin) in &state.pins {
-                                output.info(format!("{} -> {} @ {}", name, pin.url, pin.rev));
-                            }
-                        }
-                    }
-                }
-            }
-            Ok(())
-        }
-        Command::Generations { command } => {
-            if !cli.global {
-                output.info("generations are only available in global mode");
-                return Ok(());
-            }
-            match command {
-                GenerationsCommand::List => {
-                    let state = load_profile_state()?;
-                    list_generations(&output, &state)?;
-                }
-                GenerationsCommand::Rollback { id } => {
-                    rollback_generation(&output, id, cli.dry_run)?;
-                }
-            }
-            Ok(())
-        }
-        Command::Export => {
-            if cli.global {
-                let state = load_profile_state()?;
-                let generated = build_profile_nix(&state)?;
-                let formatted = format_mica_nix(&generated);
-                io::stdout()
-                    .write_all(formatted.as_bytes())
-                    .map_err(CliError::WriteNix)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let state = load_project_state(paths)?;
-                let generated = build_project_nix(paths, &state)?;
-                let formatted = format_mica_nix(&generated);
-                io::stdout()
-                    .write_all(formatted.as_bytes())
-                    .map_err(CliError::WriteNix)?;
-            }
-            Ok(())
-        }
-        Command::Index { command } => {
-            match command {
-                IndexCommand::Status => {
-                    let index_path = index_db_path()?;
-                    if !index_path.exists() {
-                        return Err(CliError::MissingIndex(index_path));
-                    }
-                    let conn = open_db(&index_path)?;
-                    let meta = get_meta(&conn)?;
-                    if meta.is_empty() {
-                        output.info(format!("index: {}", index_path.display()));
-                        output.info("meta: empty");
-                    } else {
-                        output.info(format!("index: {}", index_path.display()));
-                        for (key, value) in meta {
-                            output.info(format!("{}: {}", key, value));
-                        }
-                    }
-                }
-                IndexCommand::Rebuild {
-                    input,
-                    output: output_path_override,
-                } => {
-                    if cli.dry_run {
-                        output.info("dry-run: skipping index rebuild");
-                        return Ok(());
-                    }
-                    let output_path = output_path_override.unwrap_or(index_db_path()?);
-                    let pin = if cli.global {
-                        load_profile_state().ok().map(|state| state.pin)
-                    } else {
-                        project_paths
-                            .as_ref()
-                            .and_then(|paths| load_project_state(paths).ok().map(|state| state.pin))
-                    };
-                    let count =
-                        rebuild_index_from_json(&output, &input, &output_path, pin.as_ref())?;
-                    output.info(format!("indexed {} packages", count));
-                }
-                IndexCommand::RebuildLocal {
-                    repo,
-                    output: output_path_override,
-                    skip_attr,
-                    show_trace,
-                } => {
-                    if cli.dry_run {
-                        output.info("dry-run: skipping local index rebuild");
-                        return Ok(());
-                    }
-                    let output_path = output_path_override.unwrap_or(index_db_path()?);
-                    let count = rebuild_index_from_local_repo_with_spinner(
-                        &output,
-                        &repo,
-                        &output_path,
-                        &skip_attr,
-                        show_trace,
-                    )?;
-                    output.info(format!("indexed {} packages", count));
-                }
-                IndexCommand::Fetch => {
-                    if cli.dry_run {
-                        output.info("dry-run: skipping index fetch");
-                        return Ok(());
-                    }
-                    let config = load_config_or_default()?;
-                    if config.index.remote_url.trim().is_empty() {
-                        return Err(CliError::MissingRemoteIndex);
-                    }
-                    let index_path = index_db_path()?;
-                    let pins = if cli.global {
-                        load_profile_state()
-                            .ok()
-                            .map(|state| collect_index_pins_profile(&state))
-                    } else {
-                        project_paths.as_ref().and_then(|paths| {
-                            load_project_state(paths)
-                                .ok()
-                                .map(|state| collect_index_pins(&state))
-                        })
-                    };
-                    let fetched = try_fetch_remote_index(
-                        &output,
-                        &config.index.remote_url,
-                        &index_path,
-                        pins.as_ref().and_then(|entries| primary_pin_rev(entries)),
-                    )?;
-                    if !fetched {
-                        let Some(pins) = pins.as_ref() else {
-                            return Err(CliError::RemoteIndexFailed(
-                                reqwest::StatusCode::NOT_FOUND,
-                                "no remote index found and no local state available for rebuild"
-                                    .to_string(),
-                            ));
-                        };
-                        output.status("remote index unavailable, rebuilding locally");
-                        let count =
-                            rebuild_index_from_pins_with_spinner(&output, &index_path, pins)?;
-                        output.info(format!("indexed {} packages", count));
-                    }
-                    output.info(format!("index fetched to {}", index_path.display()));
-                    if let Ok(conn) = open_db(&index_path) {
-                        if let Ok(meta) = get_meta(&conn) {
-                            for (key, value) in meta {
-                                output.info(format!("{}: {}", key, value));
-                            }
-                        }
-                    }
-                }
-            }
-            Ok(())
-        }
-        Command::Sync { from_nix } => {
-            if cli.global {
-                let mut state = load_profile_state()?;
-                if from_nix {
-                    update_profile_state_from_nix(&mut state)?;
-                }
-                apply_profile_changes(&output, cli.dry_run, &state)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let mut state = load_project_state(paths)?;
-                if from_nix {
-                    update_project_state_from_nix(paths, &mut state)?;
-                }
-                apply_project_changes(&output, paths, cli.dry_run, &state)?;
-            }
-            Ok(())
-        }
-        Command::Eval => {
-            if cli.global {
-                let state = load_profile_state()?;
-                let generated = build_profile_nix(&state)?;
-                eval_nix_contents(&output, &generated)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let state = load_project_state(paths)?;
-                let generated = build_project_nix(paths, &state)?;
-                eval_nix_contents(&output, &generated)?;
-            }
-            Ok(())
-        }
-        Command::Diff => {
-            if cli.global {
-                let state = load_profile_state()?;
-                diff_profile(&output, &state)?;
-            } else {
-                let paths = project_paths.as_ref().expect("project paths missing");
-                let state = load_project_state(paths)?;
-                diff_project(&output, paths, &state)?;
-            }
-            Ok(())
-        }
-        Command::Completion { shell } => {
-            let mut cmd = Cli::command();
-            generate(shell, &mut cmd, "mica", &mut io::stdout());
-            Ok(())
-        }
-    }
+    let clock = clock_from_env();
+    let ctx = CommandContext {
+        output: &output,
+        global: cli.global,
+        dry_run: cli.dry_run,
+        project_paths: project_paths.as_ref(),
+        git: &GitHubProvider,
+        clock: clock.as_ref(),
+    };
+    let start = Instant::now();
+    warn_newer_state(&output, project_paths.as_ref());
+    warn_shared_home(&output);
+    if !matches!(
+        command,
+        Command::Migrate {
+            command: Some(MigrateCommand::Caches),
+            ..
+        }
+    ) {
+        warn_incompatible_caches(&output);
+    }
+    let result = command.run(&ctx);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    timing::report(&output, clock.as_ref(), &args, start.elapsed());
+    result
 }
 
-fn run_tui(
-    global: bool,
-    project_paths: Option<&ProjectPaths>,
-    output: &Output,
-) -> Result<(), CliError> {
-    if global {
-        run_tui_global(output)
-    } else {
-        let paths = project_paths.expect("project paths missing");
-        run_tui_project(paths, output)
+fn to_index_search_mode(mode: &mica_core::config::SearchMode) -> IndexSearchMode {
+    match mode {
+        mica_core::config::SearchMode::Name => IndexSearchMode::Name,
+        mica_core::config::SearchMode::Description => IndexSearchMode::Description,
+        mica_core::config::SearchMode::Binary => IndexSearchMode::Binary,
+        mica_core::config::SearchMode::All => IndexSearchMode::All,
     }
 }
 
-fn run_tui_project(paths: &ProjectPaths, output: &Output) -> Result<(), CliError> {
-    use crossterm::terminal::{
-        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
-    };
-    use ratatui::backend::CrosstermBackend;
-    use ratatui::Terminal;
-    use tui::app::App;
-
-    let project_path = &paths.nix_path;
-    if !project_path.exists() {
-        output.status(format!(
-            "default.nix missing at {}, initializing",
-            project_path.display()
-        ));
-        init_project_state(paths, None)?;
-    }
-    let mut state = load_project_state(paths)?;
-    let config = load_config_or_default().ok();
-    let index_path = index_db_path()?;
-    if !index_path.exists() {
-        let pins = collect_index_pins(&state);
-        let fetched = try_fetch_remote_index_for_pins(output, config.as_ref(), &index_path, &pins)?;
-        if !fetched {
-            output.status(format!(
-                "index missing at {}, building from nix-env -qaP --json",
-                index_path.display()
-            ));
-            let count = rebuild_index_from_pins_with_spinner(output, &index_path, &pins)?;
-            output.status(format!("index ready, {} packages", count));
-        }
-    }
-    if let Some(config) = &config {
-        let pins = collect_index_pins(&state);
-        let _ = maybe_refresh_remote_index(output, config, &index_path, primary_pin_rev(&pins))?;
-    }
-
-    let mut conn = open_db(&index_path)?;
-    let mut meta = get_meta(&conn).unwrap_or_default();
-    let mut has_meta = meta_has_key(&meta, "index_meta");
-    if has_meta && !index_has_descriptions(&conn)? {
-        has_meta = false;
-    }
-    if !has_meta {
-        let pins = collect_index_pins(&state);
-        let fetched = try_fetch_remote_index_for_pins(output, config.as_ref(), &index_path, &pins)?;
-        if fetched {
-            conn = open_db(&index_path)?;
-            meta = get_meta(&conn).unwrap_or_default();
-            has_meta = meta_has_key(&meta, "index_meta");
-            if has_meta && !index_has_descriptions(&conn)? {
-                has_meta = false;
-            }
-        }
-        if !has_meta {
-            output.status("index missing metadata, rebuilding from nix-env -qaP --json --meta");
-            let count = rebuild_index_from_pins_with_spinner(output, &index_path, &pins)?;
-            output.status(format!("index ready, {} packages", count));
-            conn = open_db(&index_path)?;
-            meta = get_meta(&conn).unwrap_or_default();
-        }
-    }
-    let presets = load_tui_presets()?;
-    let mut app = App::new(Vec::new(), presets);
-    app.mode = tui::app::AppMode::Project;
-    app.project_dir = Some(paths.root_dir.to_string_lossy().to_string());
-    if let Some(config) = &config {
-        apply_columns_from_config(&mut app, config);
-        apply_search_mode_from_config(&mut app, config);
-        apply_show_details_from_config(&mut app, config);
-    }
-    let pins = collect_index_pins(&state);
-    app.index_info = index_info_with_pin_fallback(index_info_from_meta(meta), &pins);
-    apply_state_to_app(&mut app, &state);
-    update_search_results(&conn, &mut app)?;
-    app.refresh_preset_filter();
-
-    enable_raw_mode().map_err(CliError::WriteNix)?;
-    let mut stdout = std::io::stdout();
-    crossterm::execute!(stdout, EnterAlternateScreen).map_err(CliError::WriteNix)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend).map_err(CliError::WriteNix)?;
-
-    let result = run_tui_loop_project(
-        &mut terminal,
-        &mut app,
-        &mut state,
-        paths,
-        &index_path,
-        &mut conn,
-        output,
-    );
-
-    disable_raw_mode().map_err(CliError::WriteNix)?;
-    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen)
-        .map_err(CliError::WriteNix)?;
-    terminal.show_cursor().map_err(CliError::WriteNix)?;
-    result
-}
-
-fn run_tui_global(output: &Output) -> Result<(), CliError> {
-    use crossterm::terminal::{
-        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
-    };
-    use ratatui::backend::CrosstermBackend;
-    use ratatui::Terminal;
-    use tui::app::App;
-
-    let profile_state = profile_state_path()?;
-    if !profile_state.exists() {
-        output.status(format!(
-            "global profile missing at {}, initializing",
-            profile_state.display()
-        ));
-        init_profile_state(None)?;
-        let state = load_profile_state()?;
-        sync_and_install_profile(output, &state)?;
-    }
-    let mut state = load_profile_state()?;
-    let profile_nix = profile_nix_path()?;
-    if !profile_nix.exists() {
-        sync_profile_nix(&state)?;
-    }
-
-    let config = load_config_or_default().ok();
-    let index_path = index_db_path()?;
-    if !index_path.exists() {
-        let pins = collect_index_pins_profile(&state);
-        let fetched = try_fetch_remote_index_for_pins(output, config.as_ref(), &index_path, &pins)?;
-        if !fetched {
-            output.status(format!(
-                "index missing at {}, building from nix-env -qaP --json",
-                index_path.display()
-            ));
-            let count = rebuild_index_from_pins_with_spinner(output, &index_path, &pins)?;
-            output.status(format!("index ready, {} packages", count));
-        }
-    }
-    if let Some(config) = &config {
-        let pins = collect_index_pins_profile(&state);
-        let _ = maybe_refresh_remote_index(output, config, &index_path, primary_pin_rev(&pins))?;
-    }
-
-    let mut conn = open_db(&index_path)?;
-    let mut meta = get_meta(&conn).unwrap_or_default();
-    let mut has_meta = meta_has_key(&meta, "index_meta");
-    if has_meta && !index_has_descriptions(&conn)? {
-        has_meta = false;
-    }
-    if !has_meta {
-        let pins = collect_index_pins_profile(&state);
-        let fetched = try_fetch_remote_index_for_pins(output, config.as_ref(), &index_path, &pins)?;
-        if fetched {
-            conn = open_db(&index_path)?;
-            meta = get_meta(&conn).unwrap_or_default();
-            has_meta = meta_has_key(&meta, "index_meta");
-            if has_meta && !index_has_descriptions(&conn)? {
-                has_meta = false;
-            }
-        }
-        if !has_meta {
-            output.status("index missing metadata, rebuilding from nix-env -qaP --json --meta");
-            let count = rebuild_index_from_pins_with_spinner(output, &index_path, &pins)?;
-            output.status(format!("index ready, {} packages", count));
-            conn = open_db(&index_path)?;
-            meta = get_meta(&conn).unwrap_or_default();
-        }
-    }
-
-    let presets = load_tui_presets()?;
-    let mut app = App::new(Vec::new(), presets);
-    app.mode = tui::app::AppMode::Global;
-    if let Some(config) = &config {
-        apply_columns_from_config(&mut app, config);
-        apply_search_mode_from_config(&mut app, config);
-        apply_show_details_from_config(&mut app, config);
-    }
-    let pins = collect_index_pins_profile(&state);
-    app.index_info = index_info_with_pin_fallback(index_info_from_meta(meta), &pins);
-    apply_profile_state_to_app(&mut app, &state);
-    update_search_results(&conn, &mut app)?;
-    app.refresh_preset_filter();
-
-    enable_raw_mode().map_err(CliError::WriteNix)?;
-    let mut stdout = std::io::stdout();
-    crossterm::execute!(stdout, EnterAlternateScreen).map_err(CliError::WriteNix)?;
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend).map_err(CliError::WriteNix)?;
-
-    let result = run_tui_loop_global(
-        &mut terminal,
-        &mut app,
-        &mut state,
-        &index_path,
-        &mut conn,
-        output,
-    );
-
-    disable_raw_mode().map_err(CliError::WriteNix)?;
-    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen)
-        .map_err(CliError::WriteNix)?;
-    terminal.show_cursor().map_err(CliError::WriteNix)?;
-    result
-}
-
-fn run_tui_loop_project(
-    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
-    app: &mut tui::app::App,
-    state: &mut ProjectState,
-    paths: &ProjectPaths,
-    index_path: &Path,
-    conn: &mut rusqlite::Connection,
-    output: &Output,
-) -> Result<(), CliError> {
-    use crossterm::event::{self, Event};
-
-    loop {
-        app.clear_expired_toast();
-        terminal
-            .draw(|frame| tui::ui::render(frame, app))
-            .map_err(CliError::WriteNix)?;
-
-        if event::poll(Duration::from_millis(200)).map_err(CliError::WriteNix)? {
-            if let Event::Key(key) = event::read().map_err(CliError::WriteNix)? {
-                if app.overlay.is_some() {
-                    if let Err(err) = handle_overlay_key(
-                        key, terminal, app, state, paths, index_path, conn, output,
-                    ) {
-                        app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-                    }
-                } else if let Err(err) =
-                    handle_main_key(key, terminal, app, state, paths, index_path, conn, output)
-                {
-                    app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-                }
-            }
-        }
-
-        if app.should_quit {
-            break;
-        }
-    }
-
-    Ok(())
-}
-
-fn run_tui_loop_global(
-    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
-    app: &mut tui::app::App,
-    state: &mut GlobalProfileState,
-    index_path: &Path,
-    conn: &mut rusqlite::Connection,
-    output: &Output,
-) -> Result<(), CliError> {
-    use crossterm::event::{self, Event};
-
-    loop {
-        app.clear_expired_toast();
-        terminal
-            .draw(|frame| tui::ui::render(frame, app))
-            .map_err(CliError::WriteNix)?;
-
-        if event::poll(Duration::from_millis(200)).map_err(CliError::WriteNix)? {
-            if let Event::Key(key) = event::read().map_err(CliError::WriteNix)? {
-                if app.overlay.is_some() {
-                    if let Err(err) = handle_overlay_key_global(key, terminal, app, conn, output) {
-                        app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-                    }
-                } else if let Err(err) =
-                    handle_main_key_global(key, terminal, app, state, index_path, conn, output)
-                {
-                    app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-                }
-            }
-        }
-
-        if app.should_quit {
-            break;
-        }
-    }
-
-    Ok(())
-}
-
-#[allow(clippy::too_many_arguments)]
-fn handle_main_key(
-    key: KeyEvent,
-    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
-    app: &mut tui::app::App,
-    state: &mut ProjectState,
-    paths: &ProjectPaths,
-    index_path: &Path,
-    conn: &mut rusqlite::Connection,
-    output: &Output,
-) -> Result<(), CliError> {
-    use tui::app::{FilterKind, Focus, Overlay};
-    use tui::input::{map_key, InputAction};
-
-    match map_key(key) {
-        InputAction::Quit => app.should_quit = true,
-        InputAction::Help => app.overlay = Some(Overlay::Help),
-        InputAction::Toggle => app.toggle_current(),
-        InputAction::ToggleFocus => app.toggle_focus(),
-        InputAction::Next => app.next(),
-        InputAction::Prev => app.prev(),
-        InputAction::Save => {
-            save_tui_selection(paths, state, app)?;
-            app.push_toast(tui::app::ToastLevel::Info, "Saved changes");
-        }
-        InputAction::OpenEnv => open_env_overlay(app),
-        InputAction::OpenShell => open_shell_overlay(app),
-        InputAction::ToggleBroken => {
-            app.filters.show_broken = !app.filters.show_broken;
-            update_search_results(conn, app)?;
-        }
-        InputAction::ToggleInsecure => {
-            app.filters.show_insecure = !app.filters.show_insecure;
-            update_search_results(conn, app)?;
-        }
-        InputAction::ToggleInstalled => {
-            app.filters.show_installed_only = !app.filters.show_installed_only;
-            update_search_results(conn, app)?;
-        }
-        InputAction::ToggleSearchMode => {
-            app.cycle_search_mode();
-            if let Err(err) = save_search_mode_to_config(&app.search_mode) {
-                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-            }
-            update_search_results(conn, app)?;
-            app.push_toast(
-                tui::app::ToastLevel::Info,
-                format!("Search mode: {}", app.search_mode_label()),
-            );
-        }
-        InputAction::ToggleDetails => {
-            app.show_details = !app.show_details;
-            if let Err(err) = save_show_details_to_config(app.show_details) {
-                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-            }
-            app.push_toast(
-                tui::app::ToastLevel::Info,
-                format!(
-                    "Details panel: {}",
-                    if app.show_details { "on" } else { "off" }
-                ),
-            );
-        }
-        InputAction::EditLicenseFilter => open_filter_overlay(app, FilterKind::License),
-        InputAction::EditPlatformFilter => open_filter_overlay(app, FilterKind::Platform),
-        InputAction::PreviewDiff => {
-            app.overlay = Some(build_diff_overlay(paths, state, app)?);
-        }
-        InputAction::ShowPackageInfo => {
-            if app.focus != Focus::Packages {
-                app.push_toast(tui::app::ToastLevel::Info, "Focus packages to view info");
-            } else if let Some(overlay) = build_package_info_overlay(app, state) {
-                app.overlay = Some(overlay);
-            } else {
-                app.push_toast(tui::app::ToastLevel::Info, "No package selected");
-            }
-        }
-        InputAction::OpenVersionPicker => {
-            if app.focus != Focus::Packages {
-                app.push_toast(
-                    tui::app::ToastLevel::Info,
-                    "Focus packages to view versions",
-                );
-            } else {
-                match build_version_picker_overlay(app) {
-                    Ok(Some(overlay)) => app.overlay = Some(overlay),
-                    Ok(None) => app.push_toast(
-                        tui::app::ToastLevel::Info,
-                        "No version history for selection",
-                    ),
-                    Err(err) => app.push_toast(tui::app::ToastLevel::Error, err.to_string()),
-                }
-            }
-        }
-        InputAction::UpdatePin => {
-            with_tui_suspended(terminal, || {
-                let rev = run_with_spinner(output, "fetching latest nixpkgs revision", || {
-                    fetch_latest_github_rev(&state.pin.url, &state.pin.branch)
-                })?;
-                let sha256 = run_with_spinner(output, "prefetching nixpkgs tarball", || {
-                    fetch_nix_sha256(&state.pin.url, &rev)
-                })?;
-                state.pin.rev = rev;
-                state.pin.sha256 = sha256;
-                state.pin.updated = Utc::now().date_naive();
-                update_project_modified(state);
-                save_project_state(paths, state)?;
-                let pins = collect_index_pins(state);
-                let config = load_config_or_default().ok();
-                let fetched =
-                    try_fetch_remote_index_for_pins(output, config.as_ref(), index_path, &pins)?;
-                if !fetched {
-                    rebuild_index_from_pins_with_spinner(output, index_path, &pins)?;
-                }
-                Ok(())
-            })?;
-            *conn = open_db(index_path)?;
-            let pins = collect_index_pins(state);
-            app.index_info = index_info_with_pin_fallback(
-                index_info_from_meta(get_meta(conn).unwrap_or_default()),
-                &pins,
-            );
-            update_search_results(conn, app)?;
-            app.push_toast(tui::app::ToastLevel::Info, "Pin updated");
-        }
-        InputAction::AddPin => {
-            app.overlay = Some(tui::app::Overlay::PinEditor(tui::app::PinEditorState::new(
-                state.pin.url.clone(),
-                state.pin.branch.clone(),
-            )));
-        }
-        InputAction::TogglePresets => {
-            app.presets_collapsed = !app.presets_collapsed;
-            if app.presets_collapsed {
-                app.focus = Focus::Packages;
-            }
-        }
-        InputAction::ToggleChanges => {
-            app.changes_collapsed = !app.changes_collapsed;
-        }
-        InputAction::OpenColumns => {
-            app.overlay = Some(tui::app::Overlay::Columns(tui::app::ColumnsEditorState {
-                cursor: 0,
-            }));
-        }
-        InputAction::RebuildIndex => {
-            with_tui_suspended(terminal, || {
-                let pins = collect_index_pins(state);
-                let config = load_config_or_default().ok();
-                let fetched =
-                    try_fetch_remote_index_for_pins(output, config.as_ref(), index_path, &pins)?;
-                if !fetched {
-                    rebuild_index_from_pins_with_spinner(output, index_path, &pins)?;
-                }
-                Ok(())
-            })?;
-            *conn = open_db(index_path)?;
-            let pins = collect_index_pins(state);
-            app.index_info = index_info_with_pin_fallback(
-                index_info_from_meta(get_meta(conn).unwrap_or_default()),
-                &pins,
-            );
-            update_search_results(conn, app)?;
-            app.push_toast(tui::app::ToastLevel::Info, "Index rebuilt");
-        }
-        InputAction::Sync => {
-            update_project_state_from_nix(paths, state)?;
-            apply_state_to_app(app, state);
-            update_search_results(conn, app)?;
-            app.refresh_preset_filter();
-            app.push_toast(tui::app::ToastLevel::Info, "Reloaded from nix");
-        }
-        InputAction::Backspace => match app.focus {
-            Focus::Packages => {
-                app.query.pop();
-                update_search_results(conn, app)?;
-            }
-            Focus::Presets => {
-                app.preset_query.pop();
-                app.refresh_preset_filter();
-            }
-            Focus::Changes => {}
-        },
-        InputAction::Clear => match app.focus {
-            Focus::Packages => {
-                app.query.clear();
-                update_search_results(conn, app)?;
-            }
-            Focus::Presets => {
-                app.preset_query.clear();
-                app.refresh_preset_filter();
-            }
-            Focus::Changes => {}
-        },
-        InputAction::Insert(ch) => match app.focus {
-            Focus::Packages => {
-                app.query.push(ch);
-                update_search_results(conn, app)?;
-            }
-            Focus::Presets => {
-                app.preset_query.push(ch);
-                app.refresh_preset_filter();
-            }
-            Focus::Changes => {}
-        },
-        InputAction::None => {}
-    }
-
-    Ok(())
-}
-
-fn handle_main_key_global(
-    key: KeyEvent,
-    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
-    app: &mut tui::app::App,
-    state: &mut GlobalProfileState,
-    index_path: &Path,
-    conn: &mut rusqlite::Connection,
-    output: &Output,
-) -> Result<(), CliError> {
-    use tui::app::{FilterKind, Focus, Overlay};
-    use tui::input::{map_key, InputAction};
-
-    match map_key(key) {
-        InputAction::Quit => app.should_quit = true,
-        InputAction::Help => app.overlay = Some(Overlay::Help),
-        InputAction::Toggle => app.toggle_current(),
-        InputAction::ToggleFocus => app.toggle_focus(),
-        InputAction::Next => app.next(),
-        InputAction::Prev => app.prev(),
-        InputAction::Save => {
-            with_tui_suspended(terminal, || save_profile_tui_selection(output, state, app))?;
-            app.push_toast(tui::app::ToastLevel::Info, "Saved and installed");
-        }
-        InputAction::OpenEnv => {
-            app.push_toast(tui::app::ToastLevel::Info, "Env is project-only");
-        }
-        InputAction::OpenShell => {
-            app.push_toast(tui::app::ToastLevel::Info, "Shell hook is project-only");
-        }
-        InputAction::ToggleBroken => {
-            app.filters.show_broken = !app.filters.show_broken;
-            update_search_results(conn, app)?;
-        }
-        InputAction::ToggleInsecure => {
-            app.filters.show_insecure = !app.filters.show_insecure;
-            update_search_results(conn, app)?;
-        }
-        InputAction::ToggleInstalled => {
-            app.filters.show_installed_only = !app.filters.show_installed_only;
-            update_search_results(conn, app)?;
-        }
-        InputAction::ToggleSearchMode => {
-            app.cycle_search_mode();
-            if let Err(err) = save_search_mode_to_config(&app.search_mode) {
-                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-            }
-            update_search_results(conn, app)?;
-            app.push_toast(
-                tui::app::ToastLevel::Info,
-                format!("Search mode: {}", app.search_mode_label()),
-            );
-        }
-        InputAction::ToggleDetails => {
-            app.show_details = !app.show_details;
-            if let Err(err) = save_show_details_to_config(app.show_details) {
-                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-            }
-            app.push_toast(
-                tui::app::ToastLevel::Info,
-                format!(
-                    "Details panel: {}",
-                    if app.show_details { "on" } else { "off" }
-                ),
-            );
-        }
-        InputAction::EditLicenseFilter => open_filter_overlay(app, FilterKind::License),
-        InputAction::EditPlatformFilter => open_filter_overlay(app, FilterKind::Platform),
-        InputAction::PreviewDiff => {
-            app.overlay = Some(build_diff_overlay_profile(state, app)?);
-        }
-        InputAction::ShowPackageInfo => {
-            if app.focus != Focus::Packages {
-                app.push_toast(tui::app::ToastLevel::Info, "Focus packages to view info");
-            } else {
-                let pins = collect_index_pins_profile(state);
-                if let Some(overlay) = build_package_info_overlay_with_pins(app, &pins) {
-                    app.overlay = Some(overlay);
-                } else {
-                    app.push_toast(tui::app::ToastLevel::Info, "No package selected");
-                }
-            }
-        }
-        InputAction::OpenVersionPicker => {
-            if app.focus != Focus::Packages {
-                app.push_toast(
-                    tui::app::ToastLevel::Info,
-                    "Focus packages to view versions",
-                );
-            } else {
-                match build_version_picker_overlay(app) {
-                    Ok(Some(overlay)) => app.overlay = Some(overlay),
-                    Ok(None) => app.push_toast(
-                        tui::app::ToastLevel::Info,
-                        "No version history for selection",
-                    ),
-                    Err(err) => app.push_toast(tui::app::ToastLevel::Error, err.to_string()),
-                }
-            }
-        }
-        InputAction::UpdatePin => {
-            with_tui_suspended(terminal, || {
-                let rev = run_with_spinner(output, "fetching latest nixpkgs revision", || {
-                    fetch_latest_github_rev(&state.pin.url, &state.pin.branch)
-                })?;
-                let sha256 = run_with_spinner(output, "prefetching nixpkgs tarball", || {
-                    fetch_nix_sha256(&state.pin.url, &rev)
-                })?;
-                state.pin.rev = rev;
-                state.pin.sha256 = sha256;
-                state.pin.updated = Utc::now().date_naive();
-                update_profile_modified(state);
-                save_profile_state(state)?;
-                sync_and_install_profile(output, state)?;
-                let pins = collect_index_pins_profile(state);
-                let config = load_config_or_default().ok();
-                let fetched =
-                    try_fetch_remote_index_for_pins(output, config.as_ref(), index_path, &pins)?;
-                if !fetched {
-                    rebuild_index_from_pins_with_spinner(output, index_path, &pins)?;
-                }
-                Ok(())
-            })?;
-            *conn = open_db(index_path)?;
-            let pins = collect_index_pins_profile(state);
-            app.index_info = index_info_with_pin_fallback(
-                index_info_from_meta(get_meta(conn).unwrap_or_default()),
-                &pins,
-            );
-            update_search_results(conn, app)?;
-            app.push_toast(tui::app::ToastLevel::Info, "Pin updated");
-        }
-        InputAction::AddPin => {
-            app.push_toast(tui::app::ToastLevel::Info, "Extra pins are project-only");
-        }
-        InputAction::TogglePresets => {
-            app.presets_collapsed = !app.presets_collapsed;
-            if app.presets_collapsed {
-                app.focus = Focus::Packages;
-            }
-        }
-        InputAction::ToggleChanges => {
-            app.changes_collapsed = !app.changes_collapsed;
-        }
-        InputAction::OpenColumns => {
-            app.overlay = Some(tui::app::Overlay::Columns(tui::app::ColumnsEditorState {
-                cursor: 0,
-            }));
-        }
-        InputAction::RebuildIndex => {
-            with_tui_suspended(terminal, || {
-                let pins = collect_index_pins_profile(state);
-                let config = load_config_or_default().ok();
-                let fetched =
-                    try_fetch_remote_index_for_pins(output, config.as_ref(), index_path, &pins)?;
-                if !fetched {
-                    rebuild_index_from_pins_with_spinner(output, index_path, &pins)?;
-                }
-                Ok(())
-            })?;
-            *conn = open_db(index_path)?;
-            let pins = collect_index_pins_profile(state);
-            app.index_info = index_info_with_pin_fallback(
-                index_info_from_meta(get_meta(conn).unwrap_or_default()),
-                &pins,
-            );
-            update_search_results(conn, app)?;
-            app.push_toast(tui::app::ToastLevel::Info, "Index rebuilt");
-        }
-        InputAction::Sync => {
-            update_profile_state_from_nix(state)?;
-            apply_profile_state_to_app(app, state);
-            update_search_results(conn, app)?;
-            app.refresh_preset_filter();
-            app.push_toast(tui::app::ToastLevel::Info, "Reloaded from nix");
-        }
-        InputAction::Backspace => match app.focus {
-            Focus::Packages => {
-                app.query.pop();
-                update_search_results(conn, app)?;
-            }
-            Focus::Presets => {
-                app.preset_query.pop();
-                app.refresh_preset_filter();
-            }
-            Focus::Changes => {}
-        },
-        InputAction::Clear => match app.focus {
-            Focus::Packages => {
-                app.query.clear();
-                update_search_results(conn, app)?;
-            }
-            Focus::Presets => {
-                app.preset_query.clear();
-                app.refresh_preset_filter();
-            }
-            Focus::Changes => {}
-        },
-        InputAction::Insert(ch) => match app.focus {
-            Focus::Packages => {
-                app.query.push(ch);
-                update_search_results(conn, app)?;
-            }
-            Focus::Presets => {
-                app.preset_query.push(ch);
-                app.refresh_preset_filter();
-            }
-            Focus::Changes => {}
-        },
-        InputAction::None => {}
-    }
-
-    Ok(())
-}
-
-#[allow(clippy::too_many_arguments)]
-fn handle_overlay_key(
-    key: KeyEvent,
-    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
-    app: &mut tui::app::App,
-    state: &mut ProjectState,
-    paths: &ProjectPaths,
-    index_path: &Path,
-    conn: &mut rusqlite::Connection,
-    output: &Output,
-) -> Result<(), CliError> {
-    use tui::app::{EnvEditMode, EnvValueMode, Overlay};
-
-    let overlay = match app.overlay.take() {
-        Some(overlay) => overlay,
-        None => return Ok(()),
-    };
-
-    match overlay {
-        Overlay::Help => {
-            let close = matches!(
-                key.code,
-                KeyCode::Esc | KeyCode::Char('?') | KeyCode::Char('q') | KeyCode::Enter
-            );
-            if !close {
-                app.overlay = Some(Overlay::Help);
-            }
-        }
-        Overlay::PackageInfo(mut state) => {
-            let mut close = false;
-            let max_scroll = state.lines.len().saturating_sub(1);
-            match key.code {
-                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Enter => close = true,
-                KeyCode::Up => state.scroll = state.scroll.saturating_sub(1),
-                KeyCode::Down => state.scroll = (state.scroll + 1).min(max_scroll),
-                KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
-                KeyCode::PageDown => state.scroll = (state.scroll + 10).min(max_scroll),
-                KeyCode::Home => state.scroll = 0,
-                KeyCode::End => state.scroll = max_scroll,
-                KeyCode::Char('i') if key.modifiers.contains(KeyModifiers::CONTROL) => close = true,
-                _ => {}
-            }
-            if !close {
-                app.overlay = Some(Overlay::PackageInfo(state));
-            }
-        }
-        Overlay::VersionPicker(mut state) => {
-            let mut close = false;
-            let max = state.entries.len().saturating_sub(1);
-            match key.code {
-                KeyCode::Esc => close = true,
-                KeyCode::Up => {
-                    if state.cursor > 0 {
-                        state.cursor -= 1;
-                    }
-                }
-                KeyCode::Down => {
-                    state.cursor = (state.cursor + 1).min(max);
-                }
-                KeyCode::Enter => {
-                    if let Some(entry) = state.entries.get(state.cursor).cloned() {
-                        let package = state.package.clone();
-                        with_tui_suspended(terminal, || {
-                            apply_version_selection(output, app, &package, entry)
-                        })?;
-                        close = true;
-                    }
-                }
-                _ => {}
-            }
-            if !close {
-                app.overlay = Some(Overlay::VersionPicker(state));
-            }
-        }
-        Overlay::PinEditor(mut editor) => {
-            let mut close = false;
-            match key.code {
-                KeyCode::Esc => close = true,
-                KeyCode::Tab | KeyCode::Down => {
-                    pin_editor_next_field(&mut editor);
-                    editor.error = None;
-                }
-                KeyCode::BackTab | KeyCode::Up => {
-                    pin_editor_prev_field(&mut editor);
-                    editor.error = None;
-                }
-                KeyCode::Char('l') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    editor.use_latest = !editor.use_latest;
-                    editor.error = None;
-                }
-                KeyCode::Enter => {
-                    if submit_pin_editor(
-                        terminal,
-                        app,
-                        &mut editor,
-                        state,
-                        paths,
-                        index_path,
-                        conn,
-                        output,
-                    ) {
-                        close = true;
-                    } else {
-                        app.overlay = Some(Overlay::PinEditor(editor));
-                        return Ok(());
-                    }
-                }
-                KeyCode::Backspace => {
-                    let (value, cursor) = pin_editor_active_input(&mut editor);
-                    if *cursor > 0 {
-                        *cursor -= 1;
-                        value.remove(*cursor);
-                    }
-                    editor.error = None;
-                }
-                KeyCode::Left => {
-                    let (_, cursor) = pin_editor_active_input(&mut editor);
-                    if *cursor > 0 {
-                        *cursor -= 1;
-                    }
-                }
-                KeyCode::Right => {
-                    let (value, cursor) = pin_editor_active_input(&mut editor);
-                    if *cursor < value.len() {
-                        *cursor += 1;
-                    }
-                }
-                KeyCode::Home => {
-                    let (_, cursor) = pin_editor_active_input(&mut editor);
-                    *cursor = 0;
-                }
-                KeyCode::End => {
-                    let (value, cursor) = pin_editor_active_input(&mut editor);
-                    *cursor = value.len();
-                }
-                KeyCode::Char(ch)
-                    if !key.modifiers.contains(KeyModifiers::CONTROL)
-                        && !key.modifiers.contains(KeyModifiers::ALT) =>
-                {
-                    let (value, cursor) = pin_editor_active_input(&mut editor);
-                    value.insert(*cursor, ch);
-                    *cursor += 1;
-                    editor.error = None;
-                }
-                _ => {}
-            }
-            if !close {
-                app.overlay = Some(Overlay::PinEditor(editor));
-            }
-        }
-        Overlay::Columns(mut state) => {
-            let mut close = false;
-            let max = tui::app::COLUMN_OPTIONS.len().saturating_sub(1);
-            match key.code {
-                KeyCode::Esc => close = true,
-                KeyCode::Up => {
-                    if state.cursor > 0 {
-                        state.cursor -= 1;
-                    }
-                }
-                KeyCode::Down => {
-                    state.cursor = (state.cursor + 1).min(max);
-                }
-                KeyCode::Enter | KeyCode::Char(' ') => {
-                    if let Some(option) = tui::app::COLUMN_OPTIONS.get(state.cursor) {
-                        toggle_column_setting(app, option.kind);
-                    }
-                }
-                _ => {}
-            }
-            if !close {
-                app.overlay = Some(Overlay::Columns(state));
-            }
-        }
-        Overlay::Filter(mut state) => match key.code {
-            KeyCode::Esc => {}
-            KeyCode::Enter => {
-                let value = state.input.trim().to_string();
-                match state.kind {
-                    tui::app::FilterKind::License => app.filters.license = value,
-                    tui::app::FilterKind::Platform => app.filters.platform = value,
-                }
-                update_search_results(conn, app)?;
-            }
-            KeyCode::Backspace => {
-                if state.cursor > 0 {
-                    state.cursor -= 1;
-                    state.input.remove(state.cursor);
-                }
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::Left => {
-                if state.cursor > 0 {
-                    state.cursor -= 1;
-                }
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::Right => {
-                if state.cursor < state.input.len() {
-                    state.cursor += 1;
-                }
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::Home => {
-                state.cursor = 0;
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::End => {
-                state.cursor = state.input.len();
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::Char(ch)
-                if !key.modifiers.contains(KeyModifiers::CONTROL)
-                    && !key.modifiers.contains(KeyModifiers::ALT) =>
-            {
-                state.input.insert(state.cursor, ch);
-                state.cursor += 1;
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            _ => {
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-        },
-        Overlay::Env(mut state) => {
-            let mut close = false;
-            if matches!(state.mode, EnvEditMode::List) {
-                match key.code {
-                    KeyCode::Esc | KeyCode::Char('q') => close = true,
-                    KeyCode::Up => {
-                        if state.cursor > 0 {
-                            state.cursor -= 1;
-                        }
-                    }
-                    KeyCode::Down => {
-                        if !state.entries.is_empty() {
-                            state.cursor = (state.cursor + 1).min(state.entries.len() - 1);
-                        }
-                    }
-                    KeyCode::Char('a') => {
-                        state.mode = EnvEditMode::Edit {
-                            original_key: None,
-                            value_mode: EnvValueMode::String,
-                        };
-                        state.input.clear();
-                        state.input_cursor = 0;
-                        state.error = None;
-                    }
-                    KeyCode::Enter => {
-                        if let Some(entry) = state.entries.get(state.cursor) {
-                            state.input =
-                                format!("{}={}", entry.key, env_value_for_editor(&entry.value));
-                            state.input_cursor = state.input.len();
-                            state.mode = EnvEditMode::Edit {
-                                original_key: Some(entry.key.clone()),
-                                value_mode: env_value_mode_from_stored(&entry.value),
-                            };
-                            state.error = None;
-                        }
-                    }
-                    KeyCode::Char('d') => {
-                        if !state.entries.is_empty() {
-                            state.entries.remove(state.cursor);
-                            if state.cursor >= state.entries.len() && state.cursor > 0 {
-                                state.cursor -= 1;
-                            }
-                        }
-                    }
-                    _ => {}
-                }
-            } else {
-                match key.code {
-                    KeyCode::Esc => {
-                        state.mode = EnvEditMode::List;
-                        state.input.clear();
-                        state.input_cursor = 0;
-                        state.error = None;
-                    }
-                    KeyCode::Enter => match apply_env_input(&mut state) {
-                        Ok(()) => {}
-                        Err(err) => state.error = Some(err),
-                    },
-                    KeyCode::Tab => {
-                        if let EnvEditMode::Edit { value_mode, .. } = &mut state.mode {
-                            *value_mode = value_mode.toggle();
-                            state.error = None;
-                        }
-                    }
-                    KeyCode::Backspace => {
-                        if state.input_cursor > 0 {
-                            state.input_cursor -= 1;
-                            state.input.remove(state.input_cursor);
-                        }
-                    }
-                    KeyCode::Left => {
-                        if state.input_cursor > 0 {
-                            state.input_cursor -= 1;
-                        }
-                    }
-                    KeyCode::Right => {
-                        if state.input_cursor < state.input.len() {
-                            state.input_cursor += 1;
-                        }
-                    }
-                    KeyCode::Home => state.input_cursor = 0,
-                    KeyCode::End => state.input_cursor = state.input.len(),
-                    KeyCode::Char(ch)
-                        if !key.modifiers.contains(KeyModifiers::CONTROL)
-                            && !key.modifiers.contains(KeyModifiers::ALT) =>
-                    {
-                        state.input.insert(state.input_cursor, ch);
-                        state.input_cursor += 1;
-                    }
-                    _ => {}
-                }
-            }
-
-            if close {
-                apply_env_overlay(app, state);
-            } else {
-                app.overlay = Some(Overlay::Env(state));
-            }
-        }
-        Overlay::Shell(mut state) => {
-            let mut close = false;
-            let mut cancel = false;
-            match key.code {
-                KeyCode::Esc => close = true,
-                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
-                    close = true;
-                    cancel = true;
-                }
-                KeyCode::Up => {
-                    if state.cursor_row > 0 {
-                        state.cursor_row -= 1;
-                        let line_len = state.lines[state.cursor_row].len();
-                        state.cursor_col = state.cursor_col.min(line_len);
-                    }
-                }
-                KeyCode::Down => {
-                    if state.cursor_row + 1 < state.lines.len() {
-                        state.cursor_row += 1;
-                        let line_len = state.lines[state.cursor_row].len();
-                        state.cursor_col = state.cursor_col.min(line_len);
-                    }
-                }
-                KeyCode::Left => {
-                    if state.cursor_col > 0 {
-                        state.cursor_col -= 1;
-                    } else if state.cursor_row > 0 {
-                        state.cursor_row -= 1;
-                        state.cursor_col = state.lines[state.cursor_row].len();
-                    }
-                }
-                KeyCode::Right => {
-                    let line_len = state.lines[state.cursor_row].len();
-                    if state.cursor_col < line_len {
-                        state.cursor_col += 1;
-                    } else if state.cursor_row + 1 < state.lines.len() {
-                        state.cursor_row += 1;
-                        state.cursor_col = 0;
-                    }
-                }
-                KeyCode::Enter => {
-                    ensure_shell_lines(&mut state);
-                    let current = state.lines.get_mut(state.cursor_row).unwrap();
-                    let remainder = current.split_off(state.cursor_col);
-                    state.cursor_row += 1;
-                    state.cursor_col = 0;
-                    state.lines.insert(state.cursor_row, remainder);
-                }
-                KeyCode::Backspace => {
-                    ensure_shell_lines(&mut state);
-                    if state.cursor_col > 0 {
-                        let current = state.lines.get_mut(state.cursor_row).unwrap();
-                        current.remove(state.cursor_col - 1);
-                        state.cursor_col -= 1;
-                    } else if state.cursor_row > 0 {
-                        let current = state.lines.remove(state.cursor_row);
-                        state.cursor_row -= 1;
-                        let prev = state.lines.get_mut(state.cursor_row).unwrap();
-                        let prev_len = prev.len();
-                        prev.push_str(&current);
-                        state.cursor_col = prev_len;
-                    }
-                }
-                KeyCode::Char(ch)
-                    if !key.modifiers.contains(KeyModifiers::CONTROL)
-                        && !key.modifiers.contains(KeyModifiers::ALT) =>
-                {
-                    ensure_shell_lines(&mut state);
-                    let current = state.lines.get_mut(state.cursor_row).unwrap();
-                    current.insert(state.cursor_col, ch);
-                    state.cursor_col += 1;
-                }
-                _ => {}
-            }
-
-            if close {
-                if cancel {
-                    apply_shell_overlay(app, &state.original);
-                } else {
-                    apply_shell_overlay(app, &state.lines);
-                }
-            } else {
-                app.overlay = Some(Overlay::Shell(state));
-            }
-        }
-        Overlay::Diff(mut state) => {
-            let current_lines = if state.show_full {
-                &state.full_lines
-            } else {
-                &state.change_lines
-            };
-            let max_scroll = current_lines.len().saturating_sub(1);
-            match key.code {
-                KeyCode::Esc | KeyCode::Char('q') => {}
-                KeyCode::Up => state.scroll = state.scroll.saturating_sub(1),
-                KeyCode::Down => state.scroll = (state.scroll + 1).min(max_scroll),
-                KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
-                KeyCode::PageDown => state.scroll = (state.scroll + 10).min(max_scroll),
-                KeyCode::Home => state.scroll = 0,
-                KeyCode::End => state.scroll = max_scroll,
-                KeyCode::Char('t') | KeyCode::Char('T') => {
-                    state.show_full = !state.show_full;
-                    let new_max = if state.show_full {
-                        state.full_lines.len().saturating_sub(1)
-                    } else {
-                        state.change_lines.len().saturating_sub(1)
-                    };
-                    if state.scroll > new_max {
-                        state.scroll = new_max;
-                    }
-                }
-                _ => {}
-            }
-            if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
-                return Ok(());
-            }
-            app.overlay = Some(Overlay::Diff(state));
-        }
-    }
-
-    Ok(())
-}
-
-fn handle_overlay_key_global(
-    key: KeyEvent,
-    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
-    app: &mut tui::app::App,
-    conn: &rusqlite::Connection,
-    output: &Output,
-) -> Result<(), CliError> {
-    use tui::app::Overlay;
-
-    let overlay = match app.overlay.take() {
-        Some(overlay) => overlay,
-        None => return Ok(()),
-    };
-
-    match overlay {
-        Overlay::Help => {
-            let close = matches!(
-                key.code,
-                KeyCode::Esc | KeyCode::Char('?') | KeyCode::Char('q') | KeyCode::Enter
-            );
-            if !close {
-                app.overlay = Some(Overlay::Help);
-            }
-        }
-        Overlay::PackageInfo(mut state) => {
-            let mut close = false;
-            let max_scroll = state.lines.len().saturating_sub(1);
-            match key.code {
-                KeyCode::Esc | KeyCode::Char('q') | KeyCode::Enter => close = true,
-                KeyCode::Up => state.scroll = state.scroll.saturating_sub(1),
-                KeyCode::Down => state.scroll = (state.scroll + 1).min(max_scroll),
-                KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
-                KeyCode::PageDown => state.scroll = (state.scroll + 10).min(max_scroll),
-                KeyCode::Home => state.scroll = 0,
-                KeyCode::End => state.scroll = max_scroll,
-                KeyCode::Char('p') if key.modifiers.contains(KeyModifiers::CONTROL) => close = true,
-                KeyCode::Char('i') if key.modifiers.contains(KeyModifiers::CONTROL) => close = true,
-                _ => {}
-            }
-            if !close {
-                app.overlay = Some(Overlay::PackageInfo(state));
-            }
-        }
-        Overlay::VersionPicker(mut state) => {
-            let mut close = false;
-            let max = state.entries.len().saturating_sub(1);
-            match key.code {
-                KeyCode::Esc => close = true,
-                KeyCode::Up => {
-                    if state.cursor > 0 {
-                        state.cursor -= 1;
-                    }
-                }
-                KeyCode::Down => {
-                    state.cursor = (state.cursor + 1).min(max);
-                }
-                KeyCode::Enter => {
-                    if let Some(entry) = state.entries.get(state.cursor).cloned() {
-                        let package = state.package.clone();
-                        with_tui_suspended(terminal, || {
-                            apply_version_selection(output, app, &package, entry)
-                        })?;
-                        close = true;
-                    }
-                }
-                _ => {}
-            }
-            if !close {
-                app.overlay = Some(Overlay::VersionPicker(state));
-            }
-        }
-        Overlay::Columns(mut state) => {
-            let mut close = false;
-            let max = tui::app::COLUMN_OPTIONS.len().saturating_sub(1);
-            match key.code {
-                KeyCode::Esc => close = true,
-                KeyCode::Up => {
-                    if state.cursor > 0 {
-                        state.cursor -= 1;
-                    }
-                }
-                KeyCode::Down => {
-                    state.cursor = (state.cursor + 1).min(max);
-                }
-                KeyCode::Enter | KeyCode::Char(' ') => {
-                    if let Some(option) = tui::app::COLUMN_OPTIONS.get(state.cursor) {
-                        toggle_column_setting(app, option.kind);
-                    }
-                }
-                _ => {}
-            }
-            if !close {
-                app.overlay = Some(Overlay::Columns(state));
-            }
-        }
-        Overlay::Filter(mut state) => match key.code {
-            KeyCode::Esc => {}
-            KeyCode::Enter => {
-                let value = state.input.trim().to_string();
-                match state.kind {
-                    tui::app::FilterKind::License => app.filters.license = value,
-                    tui::app::FilterKind::Platform => app.filters.platform = value,
-                }
-                update_search_results(conn, app)?;
-            }
-            KeyCode::Backspace => {
-                if state.cursor > 0 {
-                    state.cursor -= 1;
-                    state.input.remove(state.cursor);
-                }
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::Left => {
-                if state.cursor > 0 {
-                    state.cursor -= 1;
-                }
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::Right => {
-                if state.cursor < state.input.len() {
-                    state.cursor += 1;
-                }
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::Home => {
-                state.cursor = 0;
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::End => {
-                state.cursor = state.input.len();
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            KeyCode::Char(ch)
-                if !key.modifiers.contains(KeyModifiers::CONTROL)
-                    && !key.modifiers.contains(KeyModifiers::ALT) =>
-            {
-                state.input.insert(state.cursor, ch);
-                state.cursor += 1;
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-            _ => {
-                app.overlay = Some(Overlay::Filter(state));
-                return Ok(());
-            }
-        },
-        Overlay::Diff(mut state) => {
-            let current_lines = if state.show_full {
-                &state.full_lines
-            } else {
-                &state.change_lines
-            };
-            let max_scroll = current_lines.len().saturating_sub(1);
-            match key.code {
-                KeyCode::Esc | KeyCode::Char('q') => {}
-                KeyCode::Up => state.scroll = state.scroll.saturating_sub(1),
-                KeyCode::Down => state.scroll = (state.scroll + 1).min(max_scroll),
-                KeyCode::PageUp => state.scroll = state.scroll.saturating_sub(10),
-                KeyCode::PageDown => state.scroll = (state.scroll + 10).min(max_scroll),
-                KeyCode::Home => state.scroll = 0,
-                KeyCode::End => state.scroll = max_scroll,
-                KeyCode::Char('t') | KeyCode::Char('T') => {
-                    state.show_full = !state.show_full;
-                    let new_max = if state.show_full {
-                        state.full_lines.len().saturating_sub(1)
-                    } else {
-                        state.change_lines.len().saturating_sub(1)
-                    };
-                    if state.scroll > new_max {
-                        state.scroll = new_max;
-                    }
-                }
-                _ => {}
-            }
-            if matches!(key.code, KeyCode::Esc | KeyCode::Char('q')) {
-                return Ok(());
-            }
-            app.overlay = Some(Overlay::Diff(state));
-        }
-        Overlay::Env(_) | Overlay::Shell(_) | Overlay::PinEditor(_) => {
-            app.push_toast(tui::app::ToastLevel::Info, "Not available in global mode");
-        }
-    }
-
-    Ok(())
-}
-
-fn pin_editor_active_input(editor: &mut tui::app::PinEditorState) -> (&mut String, &mut usize) {
-    let (value, cursor) = match editor.active {
-        tui::app::PinField::Name => (&mut editor.name, &mut editor.name_cursor),
-        tui::app::PinField::Url => (&mut editor.url, &mut editor.url_cursor),
-        tui::app::PinField::Branch => (&mut editor.branch, &mut editor.branch_cursor),
-        tui::app::PinField::Rev => (&mut editor.rev, &mut editor.rev_cursor),
-        tui::app::PinField::Sha256 => (&mut editor.sha256, &mut editor.sha256_cursor),
-        tui::app::PinField::TarballName => {
-            (&mut editor.tarball_name, &mut editor.tarball_name_cursor)
-        }
-    };
-    if *cursor > value.len() {
-        *cursor = value.len();
-    }
-    (value, cursor)
-}
-
-fn pin_editor_next_field(editor: &mut tui::app::PinEditorState) {
-    let idx = tui::app::PIN_FIELDS
-        .iter()
-        .position(|field| *field == editor.active)
-        .unwrap_or(0);
-    let next = (idx + 1) % tui::app::PIN_FIELDS.len();
-    editor.active = tui::app::PIN_FIELDS[next];
-}
-
-fn pin_editor_prev_field(editor: &mut tui::app::PinEditorState) {
-    let idx = tui::app::PIN_FIELDS
-        .iter()
-        .position(|field| *field == editor.active)
-        .unwrap_or(0);
-    let prev = if idx == 0 {
-        tui::app::PIN_FIELDS.len() - 1
-    } else {
-        idx - 1
-    };
-    editor.active = tui::app::PIN_FIELDS[prev];
-}
-
-#[allow(clippy::too_many_arguments)]
-fn submit_pin_editor(
-    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
-    app: &mut tui::app::App,
-    editor: &mut tui::app::PinEditorState,
-    state: &mut ProjectState,
-    paths: &ProjectPaths,
-    index_path: &Path,
-    conn: &mut rusqlite::Connection,
-    output: &Output,
-) -> bool {
-    editor.error = None;
-
-    let name = editor.name.trim();
-    if name.is_empty() {
-        editor.error = Some("Name is required".to_string());
-        return false;
-    }
-    if !is_valid_pin_name(name) {
-        editor.error = Some("Name must be a valid identifier".to_string());
-        return false;
-    }
-    let url = editor.url.trim();
-    if url.is_empty() {
-        editor.error = Some("URL is required".to_string());
-        return false;
-    }
-    if !editor.use_latest && editor.rev.trim().is_empty() {
-        editor.error = Some("Revision is required when latest is off".to_string());
-        return false;
-    }
-
-    let name = name.to_string();
-    let url = url.to_string();
-    let branch = {
-        let value = editor.branch.trim();
-        if value.is_empty() {
-            None
-        } else {
-            Some(value.to_string())
-        }
-    };
-    let use_latest = editor.use_latest;
-    let rev = if use_latest {
-        None
-    } else {
-        Some(editor.rev.trim().to_string())
-    };
-    let sha256 = {
-        let value = editor.sha256.trim();
-        if value.is_empty() {
-            None
-        } else {
-            Some(value.to_string())
-        }
-    };
-    let tarball_name = {
-        let value = editor.tarball_name.trim();
-        if value.is_empty() {
-            None
-        } else {
-            Some(value.to_string())
-        }
-    };
-
-    if let Err(err) = with_tui_suspended(terminal, || {
-        add_extra_pin(
-            state,
-            AddPinRequest {
-                name,
-                url,
-                branch,
-                tarball_name,
-                rev,
-                sha256,
-                latest: use_latest,
-            },
-        )?;
-        save_project_state(paths, state)?;
-        let pins = collect_index_pins(state);
-        let config = load_config_or_default().ok();
-        let fetched = try_fetch_remote_index_for_pins(output, config.as_ref(), index_path, &pins)?;
-        if !fetched {
-            rebuild_index_from_pins_with_spinner(output, index_path, &pins)?;
-        }
-        Ok(())
-    }) {
-        editor.error = Some(err.to_string());
-        return false;
-    }
-
-    match open_db(index_path) {
-        Ok(new_conn) => {
-            *conn = new_conn;
-            let pins = collect_index_pins(state);
-            app.index_info = index_info_with_pin_fallback(
-                index_info_from_meta(get_meta(conn).unwrap_or_default()),
-                &pins,
-            );
-            if let Err(err) = update_search_results(conn, app) {
-                app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-            }
-        }
-        Err(err) => {
-            app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-        }
-    }
-
-    app.push_toast(tui::app::ToastLevel::Info, "Pin added");
-    true
-}
-
-fn with_tui_suspended<T>(
-    terminal: &mut ratatui::Terminal<ratatui::backend::CrosstermBackend<std::io::Stdout>>,
-    action: impl FnOnce() -> Result<T, CliError>,
-) -> Result<T, CliError> {
-    use crossterm::terminal::{
-        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
-    };
-
-    disable_raw_mode().map_err(CliError::WriteNix)?;
-    crossterm::execute!(terminal.backend_mut(), LeaveAlternateScreen)
-        .map_err(CliError::WriteNix)?;
-    terminal.show_cursor().map_err(CliError::WriteNix)?;
-
-    let result = action();
-
-    crossterm::execute!(terminal.backend_mut(), EnterAlternateScreen)
-        .map_err(CliError::WriteNix)?;
-    enable_raw_mode().map_err(CliError::WriteNix)?;
-    let _ = terminal.hide_cursor();
-    terminal.clear().map_err(CliError::WriteNix)?;
-    result
-}
-
-fn update_search_results(
-    conn: &rusqlite::Connection,
-    app: &mut tui::app::App,
-) -> Result<(), CliError> {
-    let limit = 1000usize;
-    let query = app.query.trim();
-    let packages = if query.is_empty() {
-        list_packages(conn, limit + 1)?
-    } else {
-        search_packages_with_mode(
-            conn,
-            query,
-            limit + 1,
-            to_index_search_mode(&app.search_mode),
-        )?
-    };
-
-    let total_fetched = packages.len();
-    let entries: Vec<tui::app::PackageEntry> = packages
-        .into_iter()
-        .take(limit)
-        .map(|pkg| tui::app::PackageEntry {
-            attr_path: pkg.attr_path.clone(),
-            name: normalize_attr_path(&pkg.attr_path),
-            version: pkg.version,
-            description: pkg.description,
-            homepage: pkg.homepage,
-            license: pkg.license,
-            platforms: pkg.platforms,
-            main_program: pkg.main_program,
-            position: pkg.position,
-            broken: pkg.broken,
-            insecure: pkg.insecure,
-        })
-        .filter(|pkg| {
-            app.filters.matches(pkg)
-                && (!app.filters.show_installed_only || app.is_installed(&pkg.name))
-        })
-        .collect();
-
-    let display_total = if total_fetched > limit {
-        Some(limit + 1)
-    } else {
-        Some(total_fetched)
-    };
-
-    app.packages = entries;
-    app.index_info.displayed_count = display_total;
-    app.cursor = 0;
-    if app.packages.is_empty() {
-        app.packages_state.select(None);
-    } else {
-        app.packages_state.select(Some(0));
-    }
-    Ok(())
-}
-
-fn apply_state_to_app(app: &mut tui::app::App, state: &ProjectState) {
-    app.added = state.packages.added.iter().cloned().collect();
-    app.removed = state.packages.removed.iter().cloned().collect();
-    app.active_presets = state.presets.active.iter().cloned().collect();
-    app.pinned = state.packages.pinned.clone();
-    app.env = state.env.clone();
-    app.shell_hook = state.shell.hook.clone();
-    apply_pin_map_to_app(app, &collect_index_pins(state));
-    app.rebuild_preset_packages();
-    app.commit_baseline();
-}
-
-fn apply_profile_state_to_app(app: &mut tui::app::App, state: &GlobalProfileState) {
-    app.added = state.packages.added.iter().cloned().collect();
-    app.removed = state.packages.removed.iter().cloned().collect();
-    app.active_presets = state.presets.active.iter().cloned().collect();
-    app.pinned = state.packages.pinned.clone();
-    app.env.clear();
-    app.shell_hook = None;
-    apply_pin_map_to_app(app, &collect_index_pins_profile(state));
-    app.rebuild_preset_packages();
-    app.commit_baseline();
-}
-
-fn apply_columns_from_config(app: &mut tui::app::App, config: &Config) {
-    app.columns = tui::app::ColumnSettings {
-        show_version: config.tui.columns.version,
-        show_description: config.tui.columns.description,
-        show_license: config.tui.columns.license,
-        show_platforms: config.tui.columns.platforms,
-        show_main_program: config.tui.columns.main_program,
-    };
-}
-
-fn apply_search_mode_from_config(app: &mut tui::app::App, config: &Config) {
-    app.search_mode = config.tui.search_mode.clone();
-}
-
-fn apply_show_details_from_config(app: &mut tui::app::App, config: &Config) {
-    app.show_details = config.tui.show_details;
-}
-
-fn save_columns_to_config(columns: &tui::app::ColumnSettings) -> Result<(), CliError> {
-    ensure_config_dir()?;
-    let mut config = load_config_or_default()?;
-    config.tui.columns = mica_core::config::TuiColumns {
-        version: columns.show_version,
-        description: columns.show_description,
-        license: columns.show_license,
-        platforms: columns.show_platforms,
-        main_program: columns.show_main_program,
-    };
-    config
-        .save_to_path(&config_path()?)
-        .map_err(CliError::Config)
-}
-
-fn save_search_mode_to_config(mode: &mica_core::config::SearchMode) -> Result<(), CliError> {
-    ensure_config_dir()?;
-    let mut config = load_config_or_default()?;
-    config.tui.search_mode = mode.clone();
-    config
-        .save_to_path(&config_path()?)
-        .map_err(CliError::Config)
-}
-
-fn save_show_details_to_config(show_details: bool) -> Result<(), CliError> {
-    ensure_config_dir()?;
-    let mut config = load_config_or_default()?;
-    config.tui.show_details = show_details;
-    config
-        .save_to_path(&config_path()?)
-        .map_err(CliError::Config)
-}
-
-fn to_index_search_mode(mode: &mica_core::config::SearchMode) -> IndexSearchMode {
-    match mode {
-        mica_core::config::SearchMode::Name => IndexSearchMode::Name,
-        mica_core::config::SearchMode::Description => IndexSearchMode::Description,
-        mica_core::config::SearchMode::Binary => IndexSearchMode::Binary,
-        mica_core::config::SearchMode::All => IndexSearchMode::All,
-    }
-}
-
-fn toggle_column_setting(app: &mut tui::app::App, column: tui::app::ColumnKind) {
-    app.toggle_column(column);
-    if let Err(err) = save_columns_to_config(&app.columns) {
-        app.push_toast(tui::app::ToastLevel::Error, err.to_string());
-    }
-}
-
-fn open_env_overlay(app: &mut tui::app::App) {
-    let mut entries: Vec<tui::app::EnvEntry> = app
-        .env
-        .iter()
-        .map(|(key, value)| tui::app::EnvEntry {
-            key: key.clone(),
-            value: value.clone(),
-        })
-        .collect();
-    entries.sort_by(|a, b| a.key.cmp(&b.key));
-    app.overlay = Some(tui::app::Overlay::Env(tui::app::EnvEditorState {
-        entries,
-        cursor: 0,
-        input: String::new(),
-        input_cursor: 0,
-        mode: tui::app::EnvEditMode::List,
-        error: None,
-    }));
-}
-
-fn open_shell_overlay(app: &mut tui::app::App) {
-    let lines: Vec<String> = app
-        .shell_hook
-        .as_deref()
-        .unwrap_or("")
-        .lines()
-        .map(|line| line.to_string())
-        .collect();
-    let lines = if lines.is_empty() {
-        vec![String::new()]
-    } else {
-        lines
-    };
-    app.overlay = Some(tui::app::Overlay::Shell(tui::app::ShellEditorState {
-        original: lines.clone(),
-        lines,
-        cursor_row: 0,
-        cursor_col: 0,
-    }));
-}
-
-fn open_filter_overlay(app: &mut tui::app::App, kind: tui::app::FilterKind) {
-    let input = match kind {
-        tui::app::FilterKind::License => app.filters.license.clone(),
-        tui::app::FilterKind::Platform => app.filters.platform.clone(),
-    };
-    app.overlay = Some(tui::app::Overlay::Filter(tui::app::FilterEditorState {
-        cursor: input.len(),
-        input,
-        kind,
-    }));
-}
-
-fn build_diff_overlay(
-    paths: &ProjectPaths,
-    state: &ProjectState,
-    app: &tui::app::App,
-) -> Result<tui::app::Overlay, CliError> {
-    let mut temp_state = state.clone();
-    temp_state.packages.added = app.added.iter().cloned().collect();
-    temp_state.packages.removed = app.removed.iter().cloned().collect();
-    temp_state.packages.pinned = app.pinned.clone();
-    temp_state.presets.active = app.active_presets.iter().cloned().collect();
-    temp_state.env = app.env.clone();
-    temp_state.shell.hook = app.shell_hook.clone();
-
-    let generated = format_mica_nix(&build_project_nix(paths, &temp_state)?);
-    let existing = std::fs::read_to_string(&paths.nix_path).map_err(CliError::ReadNix)?;
-    let full_diff = diff_lines(&existing, &generated);
-    let mut changes_only = diff_lines_changes_only(&existing, &generated);
-    if changes_only.is_empty() {
-        changes_only.push("No changes".to_string());
-    }
-
-    Ok(tui::app::Overlay::Diff(tui::app::DiffViewerState {
-        full_lines: full_diff,
-        change_lines: changes_only,
-        show_full: false,
-        scroll: 0,
-    }))
-}
-
-fn build_diff_overlay_profile(
-    state: &GlobalProfileState,
-    app: &tui::app::App,
-) -> Result<tui::app::Overlay, CliError> {
-    let mut temp_state = state.clone();
-    temp_state.packages.added = app.added.iter().cloned().collect();
-    temp_state.packages.removed = app.removed.iter().cloned().collect();
-    temp_state.packages.pinned = app.pinned.clone();
-    temp_state.presets.active = app.active_presets.iter().cloned().collect();
-
-    let presets = load_all_presets()?;
-    let mut preset_map = BTreeMap::new();
-    for preset in presets {
-        preset_map.insert(preset.name.clone(), preset);
-    }
-    let mut active_presets = Vec::new();
-    for name in &temp_state.presets.active {
-        match preset_map.get(name) {
-            Some(preset) => active_presets.push(preset.clone()),
-            None => return Err(CliError::MissingPreset(name.clone())),
-        }
-    }
-    let merged = merge_profile_presets(&active_presets, &temp_state);
-    let generated = generate_profile_nix(&temp_state, &merged, Utc::now());
-    let generated = format_mica_nix(&generated);
-    let existing = std::fs::read_to_string(profile_nix_path()?).map_err(CliError::ReadNix)?;
-
-    let full_diff = diff_lines(&existing, &generated);
-    let mut changes_only = diff_lines_changes_only(&existing, &generated);
-    if changes_only.is_empty() {
-        changes_only.push("No changes".to_string());
-    }
-
-    Ok(tui::app::Overlay::Diff(tui::app::DiffViewerState {
-        full_lines: full_diff,
-        change_lines: changes_only,
-        show_full: false,
-        scroll: 0,
-    }))
-}
-
-fn build_package_info_overlay(
-    app: &tui::app::App,
-    state: &ProjectState,
-) -> Option<tui::app::Overlay> {
-    let pins = collect_index_pins(state);
-    build_package_info_overlay_with_pins(app, &pins)
-}
-
-fn build_package_info_overlay_with_pins(
-    app: &tui::app::App,
-    pins: &[IndexPin],
-) -> Option<tui::app::Overlay> {
-    let pkg = app.packages.get(app.cursor)?;
-    let mut lines = Vec::new();
-    lines.push(format!("Name: {}", pkg.name));
-    lines.push(format!("Attr path: {}", pkg.attr_path));
-    lines.push(format!(
-        "Version: {}",
-        pkg.version.as_deref().unwrap_or("unknown")
-    ));
-
-    let mut pin_label = "primary".to_string();
-    let mut pin_url = pins
-        .first()
-        .map(|pin| pin.pin.url.clone())
-        .unwrap_or_else(|| "unknown".to_string());
-    let mut pin_rev = pins
-        .first()
-        .map(|pin| pin.pin.rev.clone())
-        .unwrap_or_else(|| "unknown".to_string());
-    for pin in pins {
-        let Some(label) = pin.name.as_ref() else {
-            continue;
-        };
-        if pkg.attr_path.starts_with(&format!("{}.", label)) {
-            pin_label = label.clone();
-            pin_url = pin.pin.url.clone();
-            pin_rev = pin.pin.rev.clone();
-            break;
-        }
-    }
-
-    lines.push(format!("Pin: {}", pin_label));
-    lines.push(format!("Pin URL: {}", pin_url));
-    lines.push(format!("Pin rev: {}", pin_rev));
-    lines.push(format!(
-        "Source: {}",
-        pkg.position.as_deref().unwrap_or("unknown")
-    ));
-
-    if let Some(homepage) = pkg.homepage.as_deref().filter(|s| !s.trim().is_empty()) {
-        lines.push(format!("Homepage: {}", homepage));
-    }
-    if let Some(main_program) = pkg.main_program.as_deref().filter(|s| !s.trim().is_empty()) {
-        lines.push(format!("Main program: {}", main_program));
-    }
-    if let Some(license) = pkg.license.as_deref().filter(|s| !s.trim().is_empty()) {
-        lines.push(format!("License: {}", license));
-    }
-    if let Some(platforms) = pkg.platforms.as_deref().filter(|s| !s.trim().is_empty()) {
-        lines.push(format!("Platforms: {}", platforms));
-    }
-    if pkg.broken || pkg.insecure {
-        let mut flags = Vec::new();
-        if pkg.broken {
-            flags.push("broken");
-        }
-        if pkg.insecure {
-            flags.push("insecure");
-        }
-        lines.push(format!("Flags: {}", flags.join(", ")));
-    }
-
-    if let Some(description) = pkg.description.as_deref().filter(|s| !s.trim().is_empty()) {
-        lines.push("Description:".to_string());
-        for line in description.lines() {
-            lines.push(format!("  {}", line));
-        }
-    }
-
-    Some(tui::app::Overlay::PackageInfo(tui::app::PackageInfoState {
-        lines,
-        scroll: 0,
-    }))
-}
-
-fn build_version_picker_overlay(
-    app: &tui::app::App,
-) -> Result<Option<tui::app::Overlay>, CliError> {
-    let pkg = match app.packages.get(app.cursor) {
-        Some(pkg) => pkg,
-        None => return Ok(None),
-    };
-    let base_attr = app.base_attr_for(&pkg.attr_path);
-    let versions_path = versions_db_path()?;
-    if !versions_path.exists() {
-        return Ok(None);
-    }
-    let conn = open_versions_db(&versions_path).map_err(CliError::Index)?;
-    let versions = list_versions(&conn, &base_attr, 200).map_err(CliError::Index)?;
-    if versions.is_empty() {
-        return Ok(None);
-    }
-    let entries = versions
-        .into_iter()
-        .map(|entry| tui::app::VersionPickerEntry {
-            source: entry.source,
-            version: entry.version,
-            commit: entry.commit,
-            commit_date: entry.commit_date,
-            branch: entry.branch,
-            url: entry.url,
-        })
-        .collect();
-
-    Ok(Some(tui::app::Overlay::VersionPicker(
-        tui::app::VersionPickerState {
-            entries,
-            cursor: 0,
-            package: base_attr,
-        },
-    )))
-}
-
-fn apply_version_selection(
-    output: &Output,
-    app: &mut tui::app::App,
-    package: &str,
-    entry: tui::app::VersionPickerEntry,
-) -> Result<(), CliError> {
-    let sha256 = run_with_spinner(output, "prefetching nix tarball", || {
-        fetch_nix_sha256(&entry.url, &entry.commit)
-    })?;
-    let pin = Pin {
-        name: None,
-        url: entry.url,
-        rev: entry.commit,
-        sha256,
-        branch: entry.branch,
-        updated: Utc::now().date_naive(),
-    };
-    app.pinned.insert(
-        package.to_string(),
-        PinnedPackage {
-            version: entry.version,
-            pin,
-        },
-    );
-    app.added.remove(package);
-    app.removed.remove(package);
-    app.update_dirty();
-    app.push_toast(tui::app::ToastLevel::Info, "Pinned package version");
-    Ok(())
-}
-
-fn resolve_pinned_version(package: &str, pin: &Pin) -> Result<Option<String>, CliError> {
-    let versions_path = versions_db_path()?;
-    if !versions_path.exists() {
-        return Ok(None);
-    }
-    let conn = open_versions_db(&versions_path).map_err(CliError::Index)?;
-    let source = pin_source_label(pin);
-    if let Some(entry) =
-        version_for_commit(&conn, package, &source, &pin.rev).map_err(CliError::Index)?
-    {
-        return Ok(Some(entry.version));
-    }
-    if let Some(entry) =
-        latest_version_for_source(&conn, package, &source).map_err(CliError::Index)?
-    {
-        return Ok(Some(entry.version));
-    }
-    Ok(None)
-}
-
-fn apply_env_input(state: &mut tui::app::EnvEditorState) -> Result<(), String> {
-    let input = state.input.trim();
-    if input.is_empty() {
-        return Err("entry cannot be empty".to_string());
-    }
-    let (raw_key, raw_value) = input
-        .split_once('=')
-        .ok_or_else(|| "use KEY=VALUE".to_string())?;
-    let key = raw_key.trim();
-    if key.is_empty() {
-        return Err("key cannot be empty".to_string());
-    }
-    let raw_value = raw_value.trim();
-
-    let (original, value_mode) = match &state.mode {
-        tui::app::EnvEditMode::Edit {
-            original_key,
-            value_mode,
-        } => (original_key.clone(), *value_mode),
-        _ => (None, tui::app::EnvValueMode::String),
-    };
-    let value = encode_env_editor_value(raw_value, value_mode)?;
-
-    if let Some(original_key) = &original {
-        if original_key != key && state.entries.iter().any(|entry| entry.key == key) {
-            return Err("key already exists".to_string());
-        }
-        if let Some(entry) = state
-            .entries
-            .iter_mut()
-            .find(|entry| &entry.key == original_key)
-        {
-            entry.key = key.to_string();
-            entry.value = value;
-        } else {
-            state.entries.push(tui::app::EnvEntry {
-                key: key.to_string(),
-                value,
-            });
-        }
-    } else {
-        if state.entries.iter().any(|entry| entry.key == key) {
-            return Err("key already exists".to_string());
-        }
-        state.entries.push(tui::app::EnvEntry {
-            key: key.to_string(),
-            value,
-        });
-    }
-
-    state.entries.sort_by(|a, b| a.key.cmp(&b.key));
-    state.cursor = state
-        .entries
-        .iter()
-        .position(|entry| entry.key == key)
-        .unwrap_or(0);
-    state.mode = tui::app::EnvEditMode::List;
-    state.input.clear();
-    state.input_cursor = 0;
-    state.error = None;
-    Ok(())
-}
-
-fn apply_env_overlay(app: &mut tui::app::App, state: tui::app::EnvEditorState) {
-    let mut env = BTreeMap::new();
-    for entry in state.entries {
-        let key = entry.key;
-        let value = entry.value;
-        if !key.trim().is_empty() {
-            env.insert(key, value);
-        }
-    }
-    app.env = env;
-    app.update_dirty();
-}
-
-fn env_value_mode_from_stored(value: &str) -> tui::app::EnvValueMode {
-    if value.starts_with(NIX_EXPR_PREFIX) || is_legacy_nix_expression_value(value) {
-        tui::app::EnvValueMode::NixExpression
-    } else {
-        tui::app::EnvValueMode::String
-    }
-}
-
-fn env_value_for_editor(value: &str) -> String {
-    value
-        .strip_prefix(NIX_EXPR_PREFIX)
-        .unwrap_or(value)
-        .to_string()
-}
-
-fn encode_env_editor_value(raw: &str, mode: tui::app::EnvValueMode) -> Result<String, String> {
-    match mode {
-        tui::app::EnvValueMode::String => Ok(raw.to_string()),
-        tui::app::EnvValueMode::NixExpression => {
-            if raw.trim().is_empty() {
-                return Err("expression cannot be empty".to_string());
-            }
-            Ok(format!("{}{}", NIX_EXPR_PREFIX, raw.trim()))
-        }
-    }
-}
-
-fn is_legacy_nix_expression_value(value: &str) -> bool {
-    let trimmed = value.trim();
-    (trimmed.len() >= 2
-        && trimmed.starts_with('\"')
-        && trimmed.ends_with('\"')
-        && contains_unescaped_nix_interpolation(trimmed))
-        || (trimmed.len() >= 4 && trimmed.starts_with("''") && trimmed.ends_with("''"))
-}
-
-fn contains_unescaped_nix_interpolation(value: &str) -> bool {
-    let bytes = value.as_bytes();
-    let mut idx = 0usize;
-    while idx + 1 < bytes.len() {
-        if bytes[idx] == b'$' && bytes[idx + 1] == b'{' && (idx == 0 || bytes[idx - 1] != b'\\') {
-            return true;
-        }
-        idx += 1;
-    }
-    false
-}
-
-fn apply_shell_overlay(app: &mut tui::app::App, lines: &[String]) {
-    let combined = lines.join("\n");
-    if combined.trim().is_empty() {
-        app.shell_hook = None;
-    } else {
-        app.shell_hook = Some(combined);
-    }
-    app.update_dirty();
-}
-
-fn ensure_shell_lines(state: &mut tui::app::ShellEditorState) {
-    if state.lines.is_empty() {
-        state.lines.push(String::new());
-    }
-    if state.cursor_row >= state.lines.len() {
-        state.cursor_row = state.lines.len().saturating_sub(1);
-    }
-    let line_len = state.lines[state.cursor_row].len();
-    if state.cursor_col > line_len {
-        state.cursor_col = line_len;
-    }
-}
-
-fn diff_lines(old: &str, new: &str) -> Vec<String> {
-    let old_lines: Vec<&str> = old.lines().collect();
-    let new_lines: Vec<&str> = new.lines().collect();
-    let mut dp = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
-
-    for i in (0..old_lines.len()).rev() {
-        for j in (0..new_lines.len()).rev() {
-            if old_lines[i] == new_lines[j] {
-                dp[i][j] = dp[i + 1][j + 1] + 1;
-            } else {
-                dp[i][j] = dp[i + 1][j].max(dp[i][j + 1]);
-            }
-        }
-    }
-
-    let mut out = Vec::new();
-    let mut i = 0;
-    let mut j = 0;
-    while i < old_lines.len() && j < new_lines.len() {
-        if old_lines[i] == new_lines[j] {
-            out.push(format!("  {}", old_lines[i]));
-            i += 1;
-            j += 1;
-        } else if dp[i + 1][j] >= dp[i][j + 1] {
-            out.push(format!("- {}", old_lines[i]));
-            i += 1;
-        } else {
-            out.push(format!("+ {}", new_lines[j]));
-            j += 1;
-        }
-    }
-
-    while i < old_lines.len() {
-        out.push(format!("- {}", old_lines[i]));
-        i += 1;
-    }
-    while j < new_lines.len() {
-        out.push(format!("+ {}", new_lines[j]));
-        j += 1;
-    }
-
-    out
-}
-
-fn diff_lines_changes_only(old: &str, new: &str) -> Vec<String> {
-    diff_lines(old, new)
-        .into_iter()
-        .filter(|line| line.starts_with('+') || line.starts_with('-'))
-        .collect()
-}
-
-fn index_info_from_meta(meta: Vec<(String, String)>) -> tui::app::IndexInfo {
-    let mut info = tui::app::IndexInfo::default();
-    for (key, value) in meta {
-        match key.as_str() {
-            "nixpkgs_url" => info.url = value,
-            "nixpkgs_commit" => info.rev = value,
-            "package_count" => info.count = value.parse().ok(),
-            "generated_at" => info.generated_at = Some(value),
-            _ => {}
-        }
-    }
-    info
-}
-
-fn index_info_unknown(value: &str) -> bool {
-    let trimmed = value.trim();
-    trimmed.is_empty() || trimmed.eq_ignore_ascii_case("unknown")
-}
-
-fn index_info_with_pin_fallback(
-    mut info: tui::app::IndexInfo,
-    pins: &[IndexPin],
-) -> tui::app::IndexInfo {
-    let Some(primary) = pins.first() else {
-        return info;
-    };
-    if index_info_unknown(&info.url) {
-        info.url = primary.pin.url.clone();
-    }
-    if index_info_unknown(&info.rev) {
-        info.rev = primary.pin.rev.clone();
-    }
-    info
-}
-
-fn meta_has_key(meta: &[(String, String)], needle: &str) -> bool {
-    meta.iter()
-        .any(|(key, value)| key == needle && value == "true")
-}
-
-fn index_has_descriptions(conn: &rusqlite::Connection) -> Result<bool, CliError> {
-    let count: i64 = conn
-        .query_row(
-            "SELECT COUNT(1) FROM packages WHERE description IS NOT NULL",
-            [],
-            |row| row.get(0),
-        )
-        .map_err(|err| CliError::Index(mica_index::generate::IndexError::Db(err)))?;
-    Ok(count > 0)
-}
-
-fn load_tui_presets() -> Result<Vec<tui::app::PresetEntry>, CliError> {
-    let mut presets: Vec<_> = load_all_presets()?
-        .into_iter()
-        .map(|preset| tui::app::PresetEntry {
-            name: preset.name,
-            description: preset.description,
-            order: preset.order,
-            packages_required: preset.packages_required,
-            packages_optional: preset.packages_optional,
-        })
-        .collect();
-    presets.sort_by_key(|preset| preset.order);
-    Ok(presets)
-}
-
-fn save_tui_selection(
-    paths: &ProjectPaths,
-    state: &mut ProjectState,
-    app: &mut tui::app::App,
-) -> Result<(), CliError> {
-    state.packages.added = app.added.iter().cloned().collect();
-    state.packages.removed = app.removed.iter().cloned().collect();
-    state.packages.pinned = app.pinned.clone();
-    state.presets.active = app.active_presets.iter().cloned().collect();
-    state.env = app.env.clone();
-    state.shell.hook = app.shell_hook.clone();
-    update_project_modified(state);
-    save_project_state(paths, state)?;
-    app.commit_baseline();
-    Ok(())
-}
-
-fn save_profile_tui_selection(
-    output: &Output,
-    state: &mut GlobalProfileState,
-    app: &mut tui::app::App,
-) -> Result<(), CliError> {
-    state.packages.added = app.added.iter().cloned().collect();
-    state.packages.removed = app.removed.iter().cloned().collect();
-    state.packages.pinned = app.pinned.clone();
-    state.presets.active = app.active_presets.iter().cloned().collect();
-    update_profile_modified(state);
-    save_profile_state(state)?;
-    sync_and_install_profile(output, state)?;
-    app.commit_baseline();
-    Ok(())
-}
-
-fn build_initial_project_state(repo: Option<String>) -> Result<ProjectState, CliError> {
-    let config = load_config_or_default()?;
-    let now = Utc::now();
-    let url = resolve_init_repo(repo, &config);
-    let branch = config.nixpkgs.default_branch.clone();
-    let rev = fetch_latest_github_rev(&url, &branch)?;
-    let sha256 = fetch_nix_sha256(&url, &rev)?;
-    Ok(ProjectState {
-        mica: MicaMetadata {
-            version: "0.1.0".to_string(),
-            created: now,
-            modified: now,
-        },
-        pin: Pin {
-            name: None,
-            url,
-            rev,
-            sha256,
-            branch,
-            updated: now.date_naive(),
-        },
-        pins: BTreeMap::new(),
-        presets: PresetState::default(),
-        packages: Default::default(),
-        env: BTreeMap::new(),
-        shell: ShellState::default(),
-        nix: NixBlocks::default(),
-    })
-}
-
-fn init_project_state(paths: &ProjectPaths, repo: Option<String>) -> Result<(), CliError> {
-    let path = &paths.nix_path;
-    if path.exists() {
-        return Err(CliError::StateExists(path.to_path_buf()));
-    }
-    let state = build_initial_project_state(repo)?;
-    sync_project_nix(paths, &state)?;
-    Ok(())
-}
-
-fn build_initial_profile_state(repo: Option<String>) -> Result<GlobalProfileState, CliError> {
-    let path = profile_state_path()?;
-    if path.exists() {
-        return Err(CliError::StateExists(path));
-    }
-    ensure_config_dir()?;
-    let config = load_config_or_default()?;
-    let now = Utc::now();
-    let url = resolve_init_repo(repo, &config);
-    let branch = config.nixpkgs.default_branch.clone();
-    let rev = fetch_latest_github_rev(&url, &branch)?;
-    let sha256 = fetch_nix_sha256(&url, &rev)?;
-    Ok(GlobalProfileState {
-        mica: MicaMetadata {
-            version: "0.1.0".to_string(),
-            created: now,
-            modified: now,
-        },
-        pin: Pin {
-            name: None,
-            url,
-            rev,
-            sha256,
-            branch,
-            updated: now.date_naive(),
-        },
-        presets: PresetState::default(),
-        packages: Default::default(),
-        generations: Default::default(),
-    })
-}
-
-fn init_profile_state(repo: Option<String>) -> Result<(), CliError> {
-    let state = build_initial_profile_state(repo)?;
-    let path = profile_state_path()?;
-    state.save_to_path(&path).map_err(CliError::State)
-}
-
-fn resolve_init_repo(repo: Option<String>, config: &Config) -> String {
-    if let Some(repo) = repo {
-        let trimmed = repo.trim();
-        if !trimmed.is_empty() {
-            return trimmed.to_string();
-        }
-    }
-    if let Ok(repo) = std::env::var("MICA_NIXPKGS_REPO") {
-        let trimmed = repo.trim();
-        if !trimmed.is_empty() {
-            return trimmed.to_string();
-        }
-    }
-    config.nixpkgs.default_url.clone()
-}
-
-#[derive(Debug, Clone)]
-struct IndexPin {
-    name: Option<String>,
-    pin: Pin,
-}
-
-fn collect_index_pins(state: &ProjectState) -> Vec<IndexPin> {
-    let mut pins = Vec::new();
-    pins.push(IndexPin {
-        name: None,
-        pin: state.pin.clone(),
-    });
-
-    let mut seen = BTreeSet::new();
-    seen.insert((
-        state.pin.url.clone(),
-        state.pin.rev.clone(),
-        state.pin.sha256.clone(),
-    ));
-
-    let mut used_labels = BTreeSet::new();
-    for (name, pin) in &state.pins {
-        let key = (pin.url.clone(), pin.rev.clone(), pin.sha256.clone());
-        if !seen.insert(key) {
-            continue;
-        }
-        let label = unique_pin_label(&sanitize_pin_label(name), &mut used_labels);
-        pins.push(IndexPin {
-            name: Some(label),
-            pin: pin.clone(),
-        });
-    }
-    for (pkg, pinned) in &state.packages.pinned {
-        let key = (
-            pinned.pin.url.clone(),
-            pinned.pin.rev.clone(),
-            pinned.pin.sha256.clone(),
-        );
-        if !seen.insert(key) {
-            continue;
-        }
-        let base_label = format!("pin-{}", sanitize_pin_label(pkg));
-        let label = unique_pin_label(&base_label, &mut used_labels);
-        pins.push(IndexPin {
-            name: Some(label),
-            pin: pinned.pin.clone(),
-        });
-    }
-
-    pins
-}
-
-fn collect_index_pins_profile(state: &GlobalProfileState) -> Vec<IndexPin> {
-    let mut pins = Vec::new();
-    pins.push(IndexPin {
-        name: None,
-        pin: state.pin.clone(),
-    });
-
-    let mut seen = BTreeSet::new();
-    seen.insert((
-        state.pin.url.clone(),
-        state.pin.rev.clone(),
-        state.pin.sha256.clone(),
-    ));
-
-    let mut used_labels = BTreeSet::new();
-    for (pkg, pinned) in &state.packages.pinned {
-        let key = (
-            pinned.pin.url.clone(),
-            pinned.pin.rev.clone(),
-            pinned.pin.sha256.clone(),
-        );
-        if !seen.insert(key) {
-            continue;
-        }
-        let base_label = format!("pin-{}", sanitize_pin_label(pkg));
-        let label = unique_pin_label(&base_label, &mut used_labels);
-        pins.push(IndexPin {
-            name: Some(label),
-            pin: pinned.pin.clone(),
-        });
-    }
-
-    pins
-}
-
-fn apply_pin_map_to_app(app: &mut tui::app::App, pins: &[IndexPin]) {
-    app.pin_map.clear();
-    for pin in pins {
-        if let Some(label) = pin.name.as_ref() {
-            app.pin_map.insert(label.clone(), pin.pin.clone());
-        }
-    }
-}
-
-fn sanitize_pin_label(value: &str) -> String {
-    let mut out = String::new();
-    for ch in value.chars() {
-        if ch.is_ascii_alphanumeric() {
-            out.push(ch.to_ascii_lowercase());
-        } else {
-            out.push('_');
-        }
-    }
-    if out.is_empty() {
-        "pin".to_string()
-    } else {
-        out
-    }
-}
-
-fn normalize_attr_paths(packages: &mut [mica_index::generate::NixPackage]) {
-    for pkg in packages {
-        pkg.attr_path = normalize_attr_path(&pkg.attr_path);
-    }
-}
-
-fn normalize_attr_path(value: &str) -> String {
-    value
-        .strip_prefix("nixos.")
-        .or_else(|| value.strip_prefix("pkgs."))
-        .unwrap_or(value)
-        .to_string()
-}
-
-fn packages_have_meta(packages: &[mica_index::generate::NixPackage]) -> bool {
-    packages.iter().any(|pkg| {
-        pkg.description.is_some()
-            || pkg.homepage.is_some()
-            || pkg.license.is_some()
-            || pkg.platforms.is_some()
-            || pkg.main_program.is_some()
-            || pkg.broken.unwrap_or(false)
-            || pkg.insecure.unwrap_or(false)
-    })
-}
-
-fn build_index_skip_list(extra: &[String]) -> Vec<String> {
-    let mut skip = vec![
-        "home-packages".to_string(),
-        "json-crack".to_string(),
-        "nim1".to_string(),
-        "nim-1_0".to_string(),
-        "watcher".to_string(),
-        "nixosTests".to_string(),
-        "pkgs*".to_string(),
-        "by-name".to_string(),
-        "by-name*".to_string(),
-        "lib".to_string(),
-        "lib*".to_string(),
-        "darwin".to_string(),
-        "darwin*".to_string(),
-        "pypy*Packages".to_string(),
-        "python*Packages".to_string(),
-    ];
-    for entry in extra {
-        if !skip.iter().any(|existing| existing == entry) {
-            skip.push(entry.clone());
-        }
-    }
-    skip.sort();
-    skip.dedup();
-    skip
-}
-
-fn glob_to_regex(pattern: &str) -> String {
-    let mut out = String::from("^");
-    for ch in pattern.chars() {
-        match ch {
-            '*' => out.push_str(".*"),
-            '.' | '+' | '?' | '(' | ')' | '[' | ']' | '{' | '}' | '^' | '$' | '|' | '\\' => {
-                out.push('\\');
-                out.push(ch);
-            }
-            _ => out.push(ch),
-        }
-    }
-    out.push('$');
-    out
-}
-
-fn keep_index_temp_files() -> bool {
-    match std::env::var("MICA_KEEP_INDEX_NIX") {
-        Ok(value) => matches!(
-            value.trim().to_lowercase().as_str(),
-            "1" | "true" | "yes" | "on"
-        ),
-        Err(_) => false,
-    }
-}
-
-fn parse_skip_list(value: &str) -> Vec<String> {
-    let mut items: Vec<String> = value
-        .split(',')
-        .map(|entry| entry.trim())
-        .filter(|entry| !entry.is_empty())
-        .map(|entry| entry.to_string())
-        .collect();
-    items.sort();
-    items.dedup();
-    items
-}
-
-fn parse_failed_attr(stderr: &str) -> Option<String> {
-    let needle = "while evaluating the attribute '";
-    for line in stderr.lines() {
-        if let Some(start) = line.find(needle) {
-            let rest = &line[start + needle.len()..];
-            if let Some(end) = rest.find('\'') {
-                let attr = rest[..end].trim();
-                if !attr.is_empty() && !attr.contains('.') {
-                    return Some(attr.to_string());
-                }
-            }
-        }
-    }
-    let by_name = "/pkgs/by-name/";
-    for line in stderr.lines() {
-        if let Some(start) = line.find(by_name) {
-            let rest = &line[start + by_name.len()..];
-            let mut parts = rest.split('/');
-            let _ = parts.next();
-            if let Some(name) = parts.next() {
-                let trimmed = name.trim();
-                if !trimmed.is_empty() {
-                    return Some(trimmed.to_string());
-                }
-            }
-        }
-    }
-    for line in stderr.lines() {
-        if let Some(start) = line.find("/pkgs/") {
-            let rest = &line[start + "/pkgs/".len()..];
-            let path = rest.split(':').next().unwrap_or(rest);
-            let path = path.trim_end_matches(')');
-            if let Some(file) = path.split('/').next_back() {
-                if file == "default.nix" || file == "package.nix" {
-                    if let Some(parent) = path.split('/').rev().nth(1) {
-                        if !parent.is_empty() {
-                            return Some(parent.to_string());
-                        }
-                    }
-                } else if let Some(stem) = file.strip_suffix(".nix") {
-                    if !stem.is_empty() {
-                        return Some(stem.to_string());
-                    }
-                }
-            }
-        }
-    }
-    let missing = "error: attribute '";
-    for line in stderr.lines() {
-        if let Some(start) = line.find(missing) {
-            let rest = &line[start + missing.len()..];
-            if let Some(end) = rest.find('\'') {
-                let attr = rest[..end].trim();
-                if !attr.is_empty() && !attr.contains('.') {
-                    return Some(attr.to_string());
-                }
-            }
-        }
-    }
-    None
-}
-
-fn nix_env_show_trace() -> bool {
-    match std::env::var("MICA_NIX_SHOW_TRACE") {
-        Ok(value) => matches!(
-            value.trim().to_lowercase().as_str(),
-            "1" | "true" | "yes" | "on"
-        ),
-        Err(_) => false,
-    }
-}
-
-fn unique_pin_label(base: &str, used: &mut BTreeSet<String>) -> String {
-    if used.insert(base.to_string()) {
-        return base.to_string();
-    }
-    let mut idx = 2;
-    loop {
-        let candidate = format!("{}-{}", base, idx);
-        if used.insert(candidate.clone()) {
-            return candidate;
-        }
-        idx += 1;
-    }
-}
-
-fn rebuild_index_from_json(
-    output: &Output,
-    input: &Path,
-    output_path: &Path,
-    pin: Option<&Pin>,
-) -> Result<usize, CliError> {
-    let mut packages = load_packages_from_json(input)?;
-    normalize_attr_paths(&mut packages);
-    let index_has_meta = packages_have_meta(&packages);
-    if let Some(pin) = pin {
-        let versions_path = versions_db_path()?;
-        if let Some(parent) = versions_path.parent() {
-            std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
-        }
-        let mut versions_conn = init_versions_db(&versions_path)?;
-        let indexed_at = Utc::now().to_rfc3339();
-        let source = pin_source_label(pin);
-        let commit_date = pin_commit_date(output, pin);
-        let branch = pin_branch_label(pin);
-        let version_source = VersionSource {
-            source,
-            url: pin.url.clone(),
-            branch,
-            commit: pin.rev.clone(),
-            commit_date,
-            indexed_at: indexed_at.clone(),
-        };
-        record_versions(&mut versions_conn, &version_source, &packages).map_err(CliError::Index)?;
-    }
-    rebuild_index_with_packages(output_path, &packages, pin, index_has_meta)
-}
-
-fn rebuild_index_from_local_repo(
-    output: &Output,
-    repo_path: &Path,
-    output_path: &Path,
-    extra_skip: &[String],
-    show_trace: bool,
-) -> Result<usize, CliError> {
-    let mut packages = load_packages_from_local_repo(output, repo_path, extra_skip, show_trace)?;
-    normalize_attr_paths(&mut packages);
-    let index_has_meta = packages_have_meta(&packages);
-    rebuild_index_with_packages(output_path, &packages, None, index_has_meta)
-}
-
-fn rebuild_index_from_pins(
-    output: &Output,
-    output_path: &Path,
-    pins: &[IndexPin],
-) -> Result<usize, CliError> {
-    let versions_path = versions_db_path()?;
-    if let Some(parent) = versions_path.parent() {
-        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
-    }
-    let mut versions_conn = init_versions_db(&versions_path)?;
-    let indexed_at = Utc::now().to_rfc3339();
-    let mut packages = Vec::new();
-    for (idx, index_pin) in pins.iter().enumerate() {
-        if idx == 0 {
-            ensure_pin_complete(&index_pin.pin)?;
-        } else if ensure_pin_complete(&index_pin.pin).is_err() {
-            continue;
-        }
-        let pin_label = index_pin.name.as_deref().unwrap_or("nixpkgs");
-        let mut pin_packages = match load_packages_from_pin(output, &index_pin.pin) {
-            Ok(packages) => packages,
-            Err(err) if idx > 0 => {
-                output.warn(format!(
-                    "warning: skipping supplemental pin '{}' ({}@{}): {}",
-                    pin_label, index_pin.pin.url, index_pin.pin.rev, err
-                ));
-                continue;
-            }
-            Err(err) => return Err(err),
-        };
-        normalize_attr_paths(&mut pin_packages);
-        let source = pin_source_label(&index_pin.pin);
-        let commit_date = pin_commit_date(output, &index_pin.pin);
-        let branch = pin_branch_label(&index_pin.pin);
-        let version_source = VersionSource {
-            source,
-            url: index_pin.pin.url.clone(),
+fn build_initial_project_state(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    repo: Option<String>,
+) -> Result<ProjectState, CliError> {
+    let config = load_config_or_default()?;
+    let now = clock.now();
+    let url = resolve_init_repo(repo, &config);
+    let branch = config.nixpkgs.default_branch.clone();
+    let rev = git.latest_rev(&url, &branch)?;
+    let sha256 = fetch_nix_sha256(&url, &rev)?;
+    Ok(ProjectState {
+        mica: MicaMetadata {
+            version: MICA_VERSION.to_string(),
+            created: now,
+            modified: now,
+        },
+        pin: Pin {
+            name: None,
+            url,
+            rev,
+            sha256,
             branch,
-            commit: index_pin.pin.rev.clone(),
-            commit_date,
-            indexed_at: indexed_at.clone(),
-        };
-        record_versions(&mut versions_conn, &version_source, &pin_packages)
-            .map_err(CliError::Index)?;
-        if let Some(prefix) = &index_pin.name {
-            for pkg in &mut pin_packages {
-                pkg.attr_path = format!("{}.{}", prefix, pkg.attr_path);
-            }
-        } else if idx != 0 {
-            for pkg in &mut pin_packages {
-                pkg.attr_path = format!("pin.{}", pkg.attr_path);
-            }
-        }
-        packages.extend(pin_packages);
-    }
-
-    let primary = pins.first().map(|entry| &entry.pin);
-    rebuild_index_with_packages(output_path, &packages, primary, true)
-}
-
-fn rebuild_index_from_pins_with_spinner(
-    output: &Output,
-    output_path: &Path,
-    pins: &[IndexPin],
-) -> Result<usize, CliError> {
-    run_with_spinner(output, "building index", || {
-        rebuild_index_from_pins(output, output_path, pins)
-    })
-}
-
-fn rebuild_index_from_local_repo_with_spinner(
-    output: &Output,
-    repo_path: &Path,
-    output_path: &Path,
-    extra_skip: &[String],
-    show_trace: bool,
-) -> Result<usize, CliError> {
-    run_with_spinner(output, "building index", || {
-        rebuild_index_from_local_repo(output, repo_path, output_path, extra_skip, show_trace)
+            updated: now.date_naive(),
+            channel: None,
+        },
+        pins: BTreeMap::new(),
+        presets: PresetState::default(),
+        packages: Default::default(),
+        env: BTreeMap::new(),
+        shell: ShellState::default(),
+        nix: NixBlocks::default(),
+        extends: None,
+        build_env: BuildEnvState::default(),
+        manifest: ManifestState::default(),
     })
 }
 
-fn resolve_remote_index_urls(remote_url: &str, commit: Option<&str>) -> Vec<String> {
-    let trimmed = remote_url.trim();
-    if trimmed.is_empty() {
-        return Vec::new();
-    }
-    if trimmed.ends_with(".db") {
-        return vec![trimmed.to_string()];
-    }
-    let base = trimmed.trim_end_matches('/');
-    let mut urls = Vec::new();
-    if let Some(commit) = commit.map(str::trim).filter(|value| !value.is_empty()) {
-        urls.push(format!("{}/{}.db", base, commit));
-    }
-    urls
-}
-
-fn fetch_remote_index_url(url: &str, output_path: &Path) -> Result<(), CliError> {
-    let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
-    let response = client.get(url).send()?;
-    let status = response.status();
-    if !status.is_success() {
-        let body = response.text().unwrap_or_default();
-        return Err(CliError::RemoteIndexFailed(status, body));
-    }
-    let bytes = response.bytes()?;
-    if let Some(parent) = output_path.parent() {
-        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+fn init_project_state(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    repo: Option<String>,
+) -> Result<(), CliError> {
+    let path = &paths.nix_path;
+    if path.exists() {
+        return Err(CliError::StateExists(path.to_path_buf()));
     }
-    let tmp_path = output_path.with_extension("tmp");
-    std::fs::write(&tmp_path, &bytes).map_err(CliError::WriteNix)?;
-    std::fs::rename(&tmp_path, output_path).map_err(CliError::WriteNix)?;
+    let state = build_initial_project_state(git, clock, repo)?;
+    sync_project_nix(clock, paths, &state)?;
     Ok(())
 }
 
-fn try_fetch_remote_index(
-    output: &Output,
-    remote_url: &str,
-    output_path: &Path,
-    commit: Option<&str>,
-) -> Result<bool, CliError> {
-    let urls = resolve_remote_index_urls(remote_url, commit);
-    if urls.is_empty() {
-        return Ok(false);
-    }
-
-    let mut last_error: Option<CliError> = None;
-    for url in urls {
-        output.status(format!("fetching remote index from {}", url));
-        match fetch_remote_index_url(&url, output_path) {
-            Ok(()) => {
-                output.status("remote index fetched");
-                return Ok(true);
-            }
-            Err(CliError::RemoteIndexFailed(status, _))
-                if status == reqwest::StatusCode::NOT_FOUND =>
-            {
-                output.verbose(format!("remote index not found at {}", url));
-            }
-            Err(err) => {
-                output.verbose(format!("remote index fetch failed at {}: {}", url, err));
-                last_error = Some(err);
-            }
-        }
-    }
-
-    if let Some(err) = last_error {
-        output.warn(format!("remote index fetch failed: {}", err));
-    }
-    Ok(false)
-}
-
-fn primary_pin_rev(pins: &[IndexPin]) -> Option<&str> {
-    pins.first()
-        .map(|entry| entry.pin.rev.trim())
-        .filter(|value| !value.is_empty())
-}
-
-fn try_fetch_remote_index_for_pins(
-    output: &Output,
-    config: Option<&Config>,
-    index_path: &Path,
-    pins: &[IndexPin],
-) -> Result<bool, CliError> {
-    let Some(config) = config else {
-        return Ok(false);
-    };
-    let fetched = try_fetch_remote_index(
-        output,
-        &config.index.remote_url,
-        index_path,
-        primary_pin_rev(pins),
-    )?;
-    if !config.index.remote_url.trim().is_empty() {
-        record_index_check_time(output);
-    }
-    Ok(fetched)
-}
-
-fn index_check_path() -> Result<PathBuf, CliError> {
-    Ok(cache_dir()?.join("index.last_check"))
-}
-
-fn read_index_check_time() -> Result<Option<DateTime<Utc>>, CliError> {
-    let path = index_check_path()?;
-    let content = match std::fs::read_to_string(&path) {
-        Ok(content) => content,
-        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
-        Err(err) => return Err(CliError::ReadNix(err)),
-    };
-    let trimmed = content.trim();
-    if trimmed.is_empty() {
-        return Ok(None);
-    }
-    match DateTime::parse_from_rfc3339(trimmed) {
-        Ok(dt) => Ok(Some(dt.with_timezone(&Utc))),
-        Err(_) => Ok(None),
-    }
-}
-
-fn write_index_check_time(now: DateTime<Utc>) -> Result<(), CliError> {
-    let path = index_check_path()?;
-    if let Some(parent) = path.parent() {
-        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
-    }
-    std::fs::write(path, now.to_rfc3339()).map_err(CliError::WriteNix)
-}
-
-fn record_index_check_time(output: &Output) {
-    if let Err(err) = write_index_check_time(Utc::now()) {
-        output.verbose(format!("index check timestamp write failed: {}", err));
-    }
-}
-
-fn should_check_remote_index(config: &Config) -> Result<bool, CliError> {
-    if config.index.remote_url.trim().is_empty() {
-        return Ok(false);
-    }
-    if config.index.update_check_interval == 0 {
-        return Ok(false);
-    }
-    let now = Utc::now();
-    if let Some(last) = read_index_check_time()? {
-        let elapsed = now.signed_duration_since(last);
-        let interval = chrono::Duration::hours(config.index.update_check_interval as i64);
-        if elapsed < interval {
-            return Ok(false);
-        }
-    }
-    Ok(true)
-}
-
-fn maybe_refresh_remote_index(
-    output: &Output,
-    config: &Config,
-    index_path: &Path,
-    commit: Option<&str>,
-) -> Result<bool, CliError> {
-    if !should_check_remote_index(config)? {
-        return Ok(false);
-    }
-    output.status("checking remote index for updates");
-    let fetched = try_fetch_remote_index(output, &config.index.remote_url, index_path, commit)?;
-    record_index_check_time(output);
-    Ok(fetched)
-}
-
-fn index_skip_overrides(extra: &[String]) -> Vec<String> {
-    let mut skip = parse_skip_list(
-        std::env::var("MICA_NIX_SKIP_ATTRS")
-            .unwrap_or_default()
-            .as_str(),
-    );
-    for entry in extra {
-        if !skip.iter().any(|existing| existing == entry) {
-            skip.push(entry.clone());
-        }
+fn build_initial_profile_state(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    repo: Option<String>,
+) -> Result<GlobalProfileState, CliError> {
+    let path = profile_state_path()?;
+    if path.exists() {
+        return Err(CliError::StateExists(path));
     }
-    skip.sort();
-    skip.dedup();
-    skip
-}
-
-fn load_packages_from_pin(
-    output: &Output,
-    pin: &Pin,
-) -> Result<Vec<mica_index::generate::NixPackage>, CliError> {
-    let skip = index_skip_overrides(&[]);
-    load_packages_from_nix_expression(output, skip, nix_env_show_trace(), |all_skip| {
-        nix_env_expression(pin, all_skip)
+    ensure_config_dir()?;
+    let config = load_config_or_default()?;
+    let now = clock.now();
+    let url = resolve_init_repo(repo, &config);
+    let branch = config.nixpkgs.default_branch.clone();
+    let rev = git.latest_rev(&url, &branch)?;
+    let sha256 = fetch_nix_sha256(&url, &rev)?;
+    Ok(GlobalProfileState {
+        mica: MicaMetadata {
+            version: MICA_VERSION.to_string(),
+            created: now,
+            modified: now,
+        },
+        pin: Pin {
+            name: None,
+            url,
+            rev,
+            sha256,
+            branch,
+            updated: now.date_naive(),
+            channel: None,
+        },
+        presets: PresetState::default(),
+        packages: Default::default(),
+        generations: Default::default(),
+        hosts: Vec::new(),
     })
 }
 
-fn load_packages_from_local_repo(
-    output: &Output,
-    repo_path: &Path,
-    extra_skip: &[String],
-    show_trace: bool,
-) -> Result<Vec<mica_index::generate::NixPackage>, CliError> {
-    let repo_path = std::fs::canonicalize(repo_path).map_err(CliError::ReadNix)?;
-    let skip = index_skip_overrides(extra_skip);
-    load_packages_from_nix_expression(
-        output,
-        skip,
-        show_trace || nix_env_show_trace(),
-        |all_skip| nix_env_expression_from_local_repo(&repo_path, all_skip),
-    )
+fn init_profile_state(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    repo: Option<String>,
+) -> Result<(), CliError> {
+    let state = build_initial_profile_state(git, clock, repo)?;
+    let path = profile_state_path()?;
+    state.save_to_path(&path).map_err(CliError::State)
 }
 
-fn load_packages_from_nix_expression(
-    output: &Output,
-    mut skip: Vec<String>,
-    mut use_show_trace: bool,
-    expression_builder: impl Fn(&[String]) -> String,
-) -> Result<Vec<mica_index::generate::NixPackage>, CliError> {
-    let expr_path = temp_index_nix_path();
-    let json_path = temp_index_json_path();
-    let mut attempts = 0usize;
-    let max_attempts = 12usize;
-    loop {
-        attempts += 1;
-        let skipped_label = if skip.is_empty() {
-            "none".to_string()
-        } else {
-            skip.join(",")
-        };
-        output.status(format!(
-            "index attempt {}/{} (skipped: {}, show-trace: {})",
-            attempts, max_attempts, skipped_label, use_show_trace
-        ));
-        let all_skip = build_index_skip_list(&skip);
-        let all_skip_label = if all_skip.is_empty() {
-            "none".to_string()
-        } else {
-            all_skip.join(",")
-        };
-        output.verbose(format!("index skip list: {}", all_skip_label));
-        std::fs::write(&expr_path, expression_builder(&all_skip)).map_err(CliError::WriteNix)?;
-
-        let file = std::fs::File::create(&json_path).map_err(CliError::WriteNix)?;
-        let mut args = vec![
-            "-f",
-            expr_path.to_str().unwrap_or_default(),
-            "-qaP",
-            "--json",
-            "--meta",
-        ];
-        if use_show_trace {
-            args.push("--show-trace");
-        }
-        let mut command = ProcessCommand::new("nix-env");
-        command
-            .args(args)
-            .stdout(Stdio::from(file))
-            .stderr(Stdio::piped());
-        let child = command.spawn().map_err(|err| {
-            if err.kind() == io::ErrorKind::NotFound {
-                CliError::MissingNixEnv
-            } else {
-                CliError::NixEnvIo(err)
-            }
-        })?;
-
-        let command_output = child.wait_with_output().map_err(CliError::NixEnvIo)?;
-        if command_output.status.success() {
-            let packages = load_packages_from_json(&json_path)?;
-            if !keep_index_temp_files() {
-                let _ = std::fs::remove_file(&expr_path);
-                let _ = std::fs::remove_file(&json_path);
-            }
-            return Ok(packages);
-        }
-
-        let stderr = String::from_utf8_lossy(&command_output.stderr);
-        if attempts < max_attempts {
-            if let Some(attr) = parse_failed_attr(&stderr) {
-                if !skip.iter().any(|entry| entry == &attr) {
-                    skip.push(attr.clone());
-                    output.status(format!("index retry: skipping attr '{}'", attr));
-                    continue;
-                }
-            } else if !use_show_trace {
-                use_show_trace = true;
-                output.status("index retry: enabling --show-trace");
-                continue;
-            }
-        }
-
-        let mut message = format!("status={}, stderr={}", command_output.status, stderr.trim());
-        if keep_index_temp_files() {
-            message.push_str(&format!(
-                ", expr={}, json={}",
-                expr_path.display(),
-                json_path.display()
-            ));
-        }
-        if !skip.is_empty() {
-            message.push_str(&format!(", skipped={}", skip.join(",")));
-        }
-        if !keep_index_temp_files() {
-            let _ = std::fs::remove_file(&expr_path);
-            let _ = std::fs::remove_file(&json_path);
+fn resolve_init_repo(repo: Option<String>, config: &Config) -> String {
+    if let Some(repo) = repo {
+        let trimmed = repo.trim();
+        if !trimmed.is_empty() {
+            return trimmed.to_string();
         }
-        return Err(CliError::NixEnvFailed(message));
-    }
-}
-
-fn rebuild_index_with_packages(
-    output_path: &Path,
-    packages: &[mica_index::generate::NixPackage],
-    pin: Option<&Pin>,
-    index_has_meta: bool,
-) -> Result<usize, CliError> {
-    if let Some(parent) = output_path.parent() {
-        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
-    }
-    let mut conn = init_db(output_path)?;
-    ingest_packages(&mut conn, packages)?;
-    let generated_at = Utc::now().to_rfc3339();
-    set_meta(&conn, "generated_at", &generated_at)?;
-    set_meta(&conn, "package_count", &packages.len().to_string())?;
-    set_meta(&conn, "mica_version", "0.1.0")?;
-    if index_has_meta {
-        set_meta(&conn, "index_meta", "true")?;
-    } else {
-        set_meta(&conn, "index_meta", "false")?;
-    }
-    if let Some(pin) = pin {
-        set_meta(&conn, "nixpkgs_url", &pin.url)?;
-        set_meta(&conn, "nixpkgs_commit", &pin.rev)?;
-    } else {
-        set_meta(&conn, "nixpkgs_url", "unknown")?;
-        set_meta(&conn, "nixpkgs_commit", "unknown")?;
-    }
-    Ok(packages.len())
-}
-
-fn temp_index_json_path() -> PathBuf {
-    use std::time::{SystemTime, UNIX_EPOCH};
-
-    let suffix = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_nanos();
-    let mut path = std::env::temp_dir();
-    path.push(format!("mica-index-{}.json", suffix));
-    path
-}
-
-fn temp_index_nix_path() -> PathBuf {
-    use std::time::{SystemTime, UNIX_EPOCH};
-
-    let suffix = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_nanos();
-    let mut path = std::env::temp_dir();
-    path.push(format!("mica-index-{}.nix", suffix));
-    path
-}
-
-fn nix_string_list(items: &[String]) -> String {
-    if items.is_empty() {
-        return "[ ]".to_string();
     }
-    let mut out = String::from("[");
-    for item in items {
-        out.push(' ');
-        out.push('"');
-        out.push_str(&escape_nix_string(item));
-        out.push('"');
+    if let Ok(repo) = std::env::var("MICA_NIXPKGS_REPO") {
+        let trimmed = repo.trim();
+        if !trimmed.is_empty() {
+            return trimmed.to_string();
+        }
     }
-    out.push_str(" ]");
-    out
-}
-
-fn nix_env_expression(pin: &Pin, skip: &[String]) -> String {
-    let url = format!("{}/archive/{}.tar.gz", pin.url, pin.rev);
-    let skip_regex: Vec<String> = skip.iter().map(|entry| glob_to_regex(entry)).collect();
-    let skip_list = nix_string_list(&skip_regex);
-    format!(
-        r#"let
-  src = builtins.fetchTarball {{
-    url = "{url}";
-    sha256 = "{sha256}";
-  }};
-  lockPath = src + "/flake.lock";
-  lock = if builtins.pathExists lockPath
-    then builtins.fromJSON (builtins.readFile lockPath)
-    else null;
-  nixpkgsLocked = if lock != null
-    && lock ? nodes
-    && lock.nodes ? nixpkgs
-    && lock.nodes.nixpkgs ? locked
-    then lock.nodes.nixpkgs.locked
-    else null;
-  nixpkgsSrc = if nixpkgsLocked != null
-    && nixpkgsLocked ? owner
-    && nixpkgsLocked ? repo
-    && nixpkgsLocked ? rev
-    && nixpkgsLocked ? narHash
-    then builtins.fetchTarball {{
-      url = "https://github.com/${{nixpkgsLocked.owner}}/${{nixpkgsLocked.repo}}/archive/${{nixpkgsLocked.rev}}.tar.gz";
-      sha256 = nixpkgsLocked.narHash;
-    }}
-    else src;
-  baseAttempt =
-    let imported = import src;
-    in builtins.tryEval (
-      if builtins.isFunction imported
-      then imported {{ }}
-      else imported
-    );
-  baseFallback =
-    let imported = import nixpkgsSrc;
-    in if builtins.isFunction imported
-      then imported {{ }}
-      else imported;
-  base = if baseAttempt.success then baseAttempt.value else baseFallback;
-  isAttrSet = v: builtins.typeOf v == "set";
-  isDerivation = v: isAttrSet v && v ? type && v.type == "derivation";
-  pkgs = if base != null && isAttrSet base && base ? pkgs
-    then base.pkgs
-    else if base != null && isAttrSet base
-    then base
-    else baseFallback;
-  sanitize = attrs:
-    if attrs == null || !isAttrSet attrs
-      then {{ }}
-      else
-        let namesAttempt = builtins.tryEval (builtins.attrNames attrs);
-            skip = {skip_list};
-            matchesSkip = name:
-              builtins.any (pattern: builtins.match pattern name != null) skip;
-            names = if namesAttempt.success
-              then builtins.filter (name: !(matchesSkip name)) namesAttempt.value
-              else [];
-        in builtins.foldl' (acc: name:
-             let attempt = builtins.tryEval attrs.${{name}};
-             in if !attempt.success then acc
-                else if isDerivation attempt.value
-                  then acc // {{ ${{name}} = attempt.value; }}
-                else if isAttrSet attempt.value
-                  then acc // {{ ${{name}} = sanitize attempt.value; }}
-                else acc
-           ) {{ }} names;
-in sanitize pkgs
-"#,
-        url = url,
-        sha256 = pin.sha256,
-        skip_list = skip_list
-    )
-}
-
-fn nix_env_expression_from_local_repo(repo_path: &Path, skip: &[String]) -> String {
-    let repo_path = escape_nix_string(repo_path.to_string_lossy().as_ref());
-    let skip_regex: Vec<String> = skip.iter().map(|entry| glob_to_regex(entry)).collect();
-    let skip_list = nix_string_list(&skip_regex);
-    format!(
-        r#"let
-  src = builtins.toPath "{repo_path}";
-  lockPath = src + "/flake.lock";
-  lock = if builtins.pathExists lockPath
-    then builtins.fromJSON (builtins.readFile lockPath)
-    else null;
-  nixpkgsLocked = if lock != null
-    && lock ? nodes
-    && lock.nodes ? nixpkgs
-    && lock.nodes.nixpkgs ? locked
-    then lock.nodes.nixpkgs.locked
-    else null;
-  nixpkgsSrc = if nixpkgsLocked != null
-    && nixpkgsLocked ? owner
-    && nixpkgsLocked ? repo
-    && nixpkgsLocked ? rev
-    && nixpkgsLocked ? narHash
-    then builtins.fetchTarball {{
-      url = "https://github.com/${{nixpkgsLocked.owner}}/${{nixpkgsLocked.repo}}/archive/${{nixpkgsLocked.rev}}.tar.gz";
-      sha256 = nixpkgsLocked.narHash;
-    }}
-    else src;
-  baseAttempt =
-    let imported = import src;
-    in builtins.tryEval (
-      if builtins.isFunction imported
-      then imported {{ }}
-      else imported
-    );
-  baseFallback =
-    let imported = import nixpkgsSrc;
-    in if builtins.isFunction imported
-      then imported {{ }}
-      else imported;
-  base = if baseAttempt.success then baseAttempt.value else baseFallback;
-  isAttrSet = v: builtins.typeOf v == "set";
-  isDerivation = v: isAttrSet v && v ? type && v.type == "derivation";
-  pkgs = if base != null && isAttrSet base && base ? pkgs
-    then base.pkgs
-    else if base != null && isAttrSet base
-    then base
-    else baseFallback;
-  sanitize = attrs:
-    if attrs == null || !isAttrSet attrs
-      then {{ }}
-      else
-        let namesAttempt = builtins.tryEval (builtins.attrNames attrs);
-            skip = {skip_list};
-            matchesSkip = name:
-              builtins.any (pattern: builtins.match pattern name != null) skip;
-            names = if namesAttempt.success
-              then builtins.filter (name: !(matchesSkip name)) namesAttempt.value
-              else [];
-        in builtins.foldl' (acc: name:
-             let attempt = builtins.tryEval attrs.${{name}};
-             in if !attempt.success then acc
-                else if isDerivation attempt.value
-                  then acc // {{ ${{name}} = attempt.value; }}
-                else if isAttrSet attempt.value
-                  then acc // {{ ${{name}} = sanitize attempt.value; }}
-                else acc
-           ) {{ }} names;
-in sanitize pkgs
-"#,
-        repo_path = repo_path,
-        skip_list = skip_list
-    )
+    config.nixpkgs.default_url.clone()
 }
 
 fn run_with_spinner<T>(
@@ -4397,17 +1047,21 @@ fn run_with_spinner<T>(
     result
 }
 
-fn load_project_state(paths: &ProjectPaths) -> Result<ProjectState, CliError> {
+fn load_project_state(clock: &dyn Clock, paths: &ProjectPaths) -> Result<ProjectState, CliError> {
     let path = &paths.nix_path;
     if !path.exists() {
         return Err(CliError::MissingDefaultNix(path.to_path_buf()));
     }
     let content = std::fs::read_to_string(path).map_err(CliError::ReadNix)?;
-    let parsed = parse_project_state_from_nix(&content).map_err(CliError::NixStateParse)?;
-    let now = Utc::now();
+    let parsed = timed("parse", || parse_project_state_from_nix(&content))
+        .map_err(CliError::NixStateParse)?;
+    let now = generated_at(&content).unwrap_or_else(|| clock.now());
     let mut state = ProjectState {
         mica: MicaMetadata {
-            version: "0.1.0".to_string(),
+            version: parsed
+                .version
+                .clone()
+                .unwrap_or_else(|| MICA_VERSION.to_string()),
             created: now,
             modified: now,
         },
@@ -4422,38 +1076,177 @@ fn load_project_state(paths: &ProjectPaths) -> Result<ProjectState, CliError> {
             hook: parsed.shell_hook,
         },
         nix: parsed.nix,
+        extends: parsed.extends,
+        build_env: parsed.build_env,
+        manifest: parsed.manifest,
     };
 
     state.pin.updated = now.date_naive();
+    state.packages.removed = parsed.removed;
     state.packages.pinned = parsed.pinned;
+    state.packages.priorities = parsed.priorities;
+    state.packages.overrides = parsed.overrides;
     state.packages.added = compute_added_packages(
         parsed.packages,
         &state.presets.active,
         &state.packages.pinned,
+        &state.pins,
     )?;
     Ok(state)
 }
 
-fn load_profile_state() -> Result<GlobalProfileState, CliError> {
-    let path = profile_state_path()?;
-    if !path.exists() {
-        return Err(CliError::MissingState(path));
+fn load_profile_state() -> Result<GlobalProfileState, CliError> {
+    let path = profile_state_path()?;
+    if !path.exists() {
+        return Err(CliError::MissingState(path));
+    }
+    timed("parse", || GlobalProfileState::load_from_path(&path)).map_err(CliError::State)
+}
+
+fn save_profile_state(state: &GlobalProfileState) -> Result<(), CliError> {
+    let _lock = lock_profile_state()?;
+    write_profile_state(state)
+}
+
+fn write_profile_state(state: &GlobalProfileState) -> Result<(), CliError> {
+    let path = profile_state_path()?;
+    ensure_state_writable(&state.mica, &path)?;
+    let mut state = state.clone();
+    state.mica.version = MICA_VERSION.to_string();
+    if let Ok(saved) = GlobalProfileState::load_from_path(&path) {
+        state.generations.merge(&saved.generations);
+    }
+    timed("write", || state.save_to_path(&path)).map_err(CliError::State)
+}
+
+const STATE_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+const STALE_STATE_LOCK: Duration = Duration::from_secs(60);
+
+struct StateLock {
+    path: PathBuf,
+}
+
+impl Drop for StateLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Serializes writers of `profile.toml`. The lock only covers the final write, where
+/// `write_profile_state` merges the generation history on disk; package and preset edits
+/// made from an earlier load are still last-writer-wins.
+fn lock_profile_state() -> Result<StateLock, CliError> {
+    let path = config_dir()?.join("profile.toml.lock");
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|err| CliError::StateLockIo(path.clone(), err))?;
+    }
+    let started = Instant::now();
+    loop {
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                let _ = writeln!(file, "{}", std::process::id());
+                return Ok(StateLock { path });
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                let stale = std::fs::metadata(&path)
+                    .and_then(|meta| meta.modified())
+                    .ok()
+                    .and_then(|modified| modified.elapsed().ok())
+                    .is_some_and(|age| age > STALE_STATE_LOCK);
+                if stale {
+                    let _ = std::fs::remove_file(&path);
+                    continue;
+                }
+                if started.elapsed() > STATE_LOCK_TIMEOUT {
+                    return Err(CliError::StateLocked(path));
+                }
+                thread::sleep(Duration::from_millis(50));
+            }
+            Err(err) => return Err(CliError::StateLockIo(path, err)),
+        }
+    }
+}
+
+fn ensure_state_writable(mica: &MicaMetadata, path: &Path) -> Result<(), CliError> {
+    match mica.version_check() {
+        VersionCheck::Newer => Err(CliError::ReadOnlyState(
+            path.to_path_buf(),
+            mica.version.clone(),
+        )),
+        VersionCheck::Compatible | VersionCheck::Unknown => Ok(()),
+    }
+}
+
+fn warn_newer_state(output: &Output, project_paths: Option<&ProjectPaths>) {
+    let (path, version) = match project_paths {
+        Some(paths) => {
+            let Ok(content) = std::fs::read_to_string(&paths.nix_path) else {
+                return;
+            };
+            let Some(version) = parse_generator_version(&content) else {
+                return;
+            };
+            (paths.nix_path.clone(), version)
+        }
+        None => {
+            let Ok(path) = profile_state_path() else {
+                return;
+            };
+            let Ok(state) = GlobalProfileState::load_from_path(&path) else {
+                return;
+            };
+            (path, state.mica.version)
+        }
+    };
+    match check_version(&version) {
+        VersionCheck::Newer => output.warn(format!(
+            "warning: {} was written by mica {} (this is {}), opening it read-only",
+            path.display(),
+            version,
+            MICA_VERSION
+        )),
+        VersionCheck::Unknown => output.warn(format!(
+            "warning: {} has an unrecognized mica version {:?}, skipping version checks",
+            path.display(),
+            version
+        )),
+        VersionCheck::Compatible => {}
+    }
+}
+
+fn save_project_state(
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    state: &ProjectState,
+) -> Result<(), CliError> {
+    sync_project_nix(clock, paths, state)?;
+    let _ = register_project(clock, paths);
+    Ok(())
+}
+
+fn register_project(clock: &dyn Clock, paths: &ProjectPaths) -> Result<(), CliError> {
+    let path = registry_path()?;
+    let mut registry = ProjectRegistry::load_from_path(&path)?;
+    let nix_path = paths
+        .nix_path
+        .canonicalize()
+        .unwrap_or_else(|_| paths.nix_path.clone());
+    if registry.record(&nix_path, clock.now()) {
+        ensure_config_dir()?;
+        registry.save_to_path(&path)?;
     }
-    GlobalProfileState::load_from_path(&path).map_err(CliError::State)
-}
-
-fn save_profile_state(state: &GlobalProfileState) -> Result<(), CliError> {
-    state
-        .save_to_path(&profile_state_path()?)
-        .map_err(CliError::State)
-}
-
-fn save_project_state(paths: &ProjectPaths, state: &ProjectState) -> Result<(), CliError> {
-    sync_project_nix(paths, state)
+    Ok(())
 }
 
-fn build_project_nix(paths: &ProjectPaths, state: &ProjectState) -> Result<String, CliError> {
-    ensure_pin_complete(&state.pin)?;
+fn merge_project_presets(
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    state: &ProjectState,
+) -> Result<MergedResult, CliError> {
     let presets = load_all_presets()?;
     let mut preset_map = BTreeMap::new();
     for preset in presets {
@@ -4466,19 +1259,113 @@ fn build_project_nix(paths: &ProjectPaths, state: &ProjectState) -> Result<Strin
             None => return Err(CliError::MissingPreset(name.clone())),
         }
     }
-    let merged = merge_presets(&active_presets, state);
+    let mut merged = merge_presets(&active_presets, state);
+    if let Some(parent) = &state.extends {
+        let parent_merged = merge_parent_presets(clock, paths, parent, &preset_map)?;
+        let inherited = parent_merged.all_packages.into_iter().collect();
+        strip_inherited_packages(&mut merged, &inherited);
+        merged.inherited_env = parent_merged
+            .env
+            .into_keys()
+            .filter(|key| !merged.env.contains_key(key))
+            .collect();
+    }
+    Ok(merged)
+}
+
+/// Packages the `extends` parent already provides, empty when the project has no parent.
+fn inherited_packages(
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    state: &ProjectState,
+) -> Result<BTreeSet<String>, CliError> {
+    let Some(parent) = &state.extends else {
+        return Ok(BTreeSet::new());
+    };
+    let preset_map = load_all_presets()?
+        .into_iter()
+        .map(|preset| (preset.name.clone(), preset))
+        .collect();
+    let parent_merged = merge_parent_presets(clock, paths, parent, &preset_map)?;
+    Ok(parent_merged.all_packages.into_iter().collect())
+}
+
+fn project_selected_packages(
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    state: &ProjectState,
+) -> Result<Vec<String>, CliError> {
+    let merged = merge_project_presets(clock, paths, state)?;
+    let mut packages: BTreeSet<String> = merged.all_packages.into_iter().collect();
+    packages.extend(state.packages.pinned.keys().cloned());
+    Ok(packages.into_iter().collect())
+}
+
+fn merge_parent_presets(
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    parent: &str,
+    preset_map: &BTreeMap<String, Preset>,
+) -> Result<MergedResult, CliError> {
+    let nix_path = paths.root_dir.join(parent);
+    let root_dir = nix_path
+        .parent()
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| paths.root_dir.clone());
+    let parent_paths = ProjectPaths { nix_path, root_dir };
+    let parent_state = load_project_state(clock, &parent_paths)?;
+    let active: Vec<Preset> = parent_state
+        .presets
+        .active
+        .iter()
+        .filter_map(|name| preset_map.get(name).cloned())
+        .collect();
+    Ok(merge_presets(&active, &parent_state))
+}
+
+fn strip_inherited_packages(merged: &mut MergedResult, inherited: &BTreeSet<String>) {
+    for group in &mut merged.preset_packages {
+        group.packages.retain(|pkg| !inherited.contains(pkg));
+    }
+    merged
+        .preset_packages
+        .retain(|group| !group.packages.is_empty());
+    merged.user_packages.retain(|pkg| !inherited.contains(pkg));
+    merged.all_packages.retain(|pkg| !inherited.contains(pkg));
+}
+
+fn build_project_nix(
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    state: &ProjectState,
+) -> Result<String, CliError> {
+    ensure_state_writable(&state.mica, &paths.nix_path)?;
+    ensure_pin_complete(&state.pin)?;
+    let merged = merge_project_presets(clock, paths, state)?;
     let project_name = project_dir_name(paths);
-    let generated = generate_project_nix(state, &merged, &project_name, Utc::now());
+    let generated = timed("nix generation", || {
+        generate_project_nix(state, &merged, &project_name, clock.now())
+    });
     let output = if paths.nix_path.exists() {
         let existing = std::fs::read_to_string(&paths.nix_path).map_err(CliError::ReadNix)?;
+        let existing = migrate_project_nix(&existing)?.content;
         if let Ok(parsed_existing) = parse_nix_file(&existing) {
             if let Ok(parsed_generated) = parse_nix_file(&generated) {
+                let preamble = replace_manifest_header(&parsed_existing.preamble, &state.manifest);
                 assemble_project_nix(ProjectNixParts {
-                    preamble: &parsed_existing.preamble,
+                    preamble: &preamble,
                     pin_section: &parsed_generated.pin_section,
                     pins_section: parsed_generated.pins_section.as_deref().unwrap_or(""),
+                    extends_section: parsed_generated.extends_section.as_deref().unwrap_or(""),
                     let_section: parsed_generated.let_section.as_deref().unwrap_or(""),
                     packages_section: &parsed_generated.packages_section,
+                    exclude_section: parsed_generated.exclude_section.as_deref().unwrap_or(""),
+                    overrides_section: parsed_generated.overrides_section.as_deref().unwrap_or(""),
+                    priorities_section: parsed_generated
+                        .priorities_section
+                        .as_deref()
+                        .unwrap_or(""),
+                    build_env_section: parsed_generated.build_env_section.as_deref().unwrap_or(""),
                     scripts_section: parsed_generated.scripts_section.as_deref().unwrap_or(""),
                     env_section: &parsed_generated.env_section,
                     shell_section: &parsed_generated.shell_hook_section,
@@ -4605,13 +1492,18 @@ fn trim_trailing_blank_lines(lines: &mut Vec<String>) {
     }
 }
 
-fn sync_project_nix(paths: &ProjectPaths, state: &ProjectState) -> Result<(), CliError> {
-    let output = build_project_nix(paths, state)?;
+fn sync_project_nix(
+    clock: &dyn Clock,
+    paths: &ProjectPaths,
+    state: &ProjectState,
+) -> Result<(), CliError> {
+    let output = build_project_nix(clock, paths, state)?;
     let formatted = format_mica_nix(&output);
-    std::fs::write(&paths.nix_path, formatted).map_err(CliError::WriteNix)
+    write_generated_nix(clock, &paths.nix_path, &formatted)
 }
 
-fn build_profile_nix(state: &GlobalProfileState) -> Result<String, CliError> {
+fn build_profile_nix(clock: &dyn Clock, state: &GlobalProfileState) -> Result<String, CliError> {
+    ensure_state_writable(&state.mica, &profile_state_path()?)?;
     ensure_pin_complete(&state.pin)?;
     let presets = load_all_presets()?;
     let mut preset_map = BTreeMap::new();
@@ -4625,410 +1517,160 @@ fn build_profile_nix(state: &GlobalProfileState) -> Result<String, CliError> {
             None => return Err(CliError::MissingPreset(name.clone())),
         }
     }
-    let merged = merge_profile_presets(&active_presets, state);
-    Ok(generate_profile_nix(state, &merged, Utc::now()))
+    let merged = merge_profile_presets(&active_presets, state, &current_host());
+    Ok(timed("nix generation", || {
+        generate_profile_nix(state, &merged, clock.now())
+    }))
 }
 
-fn sync_profile_nix(state: &GlobalProfileState) -> Result<(), CliError> {
-    let generated = build_profile_nix(state)?;
+fn sync_profile_nix(clock: &dyn Clock, state: &GlobalProfileState) -> Result<(), CliError> {
+    let generated = build_profile_nix(clock, state)?;
     let formatted = format_mica_nix(&generated);
-    std::fs::write(profile_nix_path()?, formatted).map_err(CliError::WriteNix)
+    write_generated_nix(clock, &profile_nix_path()?, &formatted)
+}
+
+fn write_generated_nix(clock: &dyn Clock, path: &Path, formatted: &str) -> Result<(), CliError> {
+    let Some(content) = stamp_generated_nix(clock, path, formatted)? else {
+        return Ok(());
+    };
+    timed("write", || std::fs::write(path, content)).map_err(CliError::WriteNix)
+}
+
+fn stamp_generated_nix(
+    clock: &dyn Clock,
+    path: &Path,
+    formatted: &str,
+) -> Result<Option<String>, CliError> {
+    if let Ok(existing) = std::fs::read_to_string(path) {
+        if same_ignoring_generated_at(&existing, formatted) {
+            return Ok(None);
+        }
+    }
+    Ok(Some(set_generated_at(
+        formatted,
+        generated_timestamp(clock)?,
+    )))
+}
+
+fn generated_timestamp(clock: &dyn Clock) -> Result<Option<DateTime<Utc>>, CliError> {
+    let config = load_config_or_default()?;
+    Ok(match config.mica.timestamp {
+        TimestampMode::Omit => None,
+        TimestampMode::Now => Some(clock.now()),
+    })
 }
 
 fn apply_project_changes(
     output: &Output,
+    clock: &dyn Clock,
     paths: &ProjectPaths,
     dry_run: bool,
     state: &ProjectState,
 ) -> Result<(), CliError> {
     if dry_run {
         output.info("dry-run: skipping write");
-        if paths.nix_path.exists() {
-            diff_project(output, paths, state)?;
-        } else {
-            output.info(format!("would write {}", paths.nix_path.display()));
-        }
-        Ok(())
+        let before = load_project_state(clock, paths)
+            .and_then(|existing| project_selected_packages(clock, paths, &existing))
+            .unwrap_or_default();
+        let after = project_selected_packages(clock, paths, state)?;
+        report_package_delta(output, &before, &after);
+        let generated = format_mica_nix(&build_project_nix(clock, paths, state)?);
+        report_dry_run_diff(output, clock, &paths.nix_path, &generated)
     } else {
-        save_project_state(paths, state)
+        save_project_state(clock, paths, state)
     }
 }
 
 fn apply_profile_changes(
     output: &Output,
+    clock: &dyn Clock,
     dry_run: bool,
     state: &GlobalProfileState,
 ) -> Result<(), CliError> {
     if dry_run {
         output.info("dry-run: skipping install");
-        let path = profile_nix_path()?;
-        if path.exists() {
-            diff_profile(output, state)?;
-        } else {
-            output.info(format!("would write {}", path.display()));
-        }
-        Ok(())
+        let before = load_profile_state()
+            .and_then(|existing| profile_installed_packages(&existing))
+            .unwrap_or_default();
+        let after = profile_installed_packages(state)?;
+        report_package_delta(output, &before, &after);
+        let generated = format_mica_nix(&build_profile_nix(clock, state)?);
+        report_dry_run_diff(output, clock, &profile_nix_path()?, &generated)
     } else {
         save_profile_state(state)?;
-        sync_and_install_profile(output, state)?;
+        sync_and_install_profile(output, clock, state)?;
         Ok(())
     }
 }
 
-fn generations_dir() -> Result<PathBuf, CliError> {
-    Ok(config_dir()?.join("generations"))
-}
-
-fn latest_nix_env_generation() -> Result<Option<u64>, CliError> {
-    let output = ProcessCommand::new("nix-env")
-        .arg("--list-generations")
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .map_err(|err| {
-            if err.kind() == io::ErrorKind::NotFound {
-                CliError::MissingNixEnv
-            } else {
-                CliError::NixEnvIo(err)
-            }
-        })?;
-
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(CliError::NixEnvFailed(format!(
-            "status={}, stderr={}",
-            output.status,
-            stderr.trim()
-        )));
-    }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let mut last = None;
-    for line in stdout.lines() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-        if let Some(id) = trimmed.split_whitespace().next() {
-            if let Ok(parsed) = id.parse::<u64>() {
-                last = Some(parsed);
-            }
-        }
-    }
-    Ok(last)
-}
-
-fn profile_installed_packages(state: &GlobalProfileState) -> Result<Vec<String>, CliError> {
-    let presets = load_all_presets()?;
-    let mut preset_map = BTreeMap::new();
-    for preset in presets {
-        preset_map.insert(preset.name.clone(), preset);
-    }
-    let mut active_presets = Vec::new();
-    for name in &state.presets.active {
-        match preset_map.get(name) {
-            Some(preset) => active_presets.push(preset.clone()),
-            None => return Err(CliError::MissingPreset(name.clone())),
-        }
-    }
-    let merged = merge_profile_presets(&active_presets, state);
-    let mut packages: BTreeSet<String> = merged.all_packages.into_iter().collect();
-    for pkg in state.packages.pinned.keys() {
-        packages.insert(pkg.clone());
-    }
-    Ok(packages.into_iter().collect())
-}
-
-fn snapshot_generation(state: &GlobalProfileState, id: u64) -> Result<(), CliError> {
-    let dir = generations_dir()?.join(id.to_string());
-    std::fs::create_dir_all(&dir).map_err(CliError::WriteNix)?;
-    let snapshot_path = dir.join("profile.toml");
-    state
-        .save_to_path(&snapshot_path)
-        .map_err(CliError::State)?;
-    let profile_nix = profile_nix_path()?;
-    if profile_nix.exists() {
-        let _ = std::fs::copy(&profile_nix, dir.join("profile.nix"));
-    }
-    Ok(())
-}
-
-fn record_profile_generation(output: &Output, state: &GlobalProfileState) -> Result<(), CliError> {
-    let packages = profile_installed_packages(state)?;
-    let fallback = state
-        .generations
-        .history
-        .last()
-        .map(|entry| entry.id + 1)
-        .unwrap_or(1);
-    let id = match latest_nix_env_generation() {
-        Ok(Some(id)) => id,
-        Ok(None) => fallback,
-        Err(err) => {
-            output.warn(format!(
-                "warning: failed to read nix-env generations: {}",
-                err
-            ));
-            fallback
-        }
-    };
-
-    let mut record_state = state.clone();
-    let timestamp = Utc::now();
-    let entry = GenerationEntry {
-        id,
-        timestamp,
-        packages,
-    };
-    if let Some(existing) = record_state
-        .generations
-        .history
-        .iter_mut()
-        .find(|entry| entry.id == id)
-    {
-        *existing = entry;
+fn report_package_delta(output: &Output, before: &[String], after: &[String]) {
+    let before: BTreeSet<&String> = before.iter().collect();
+    let after: BTreeSet<&String> = after.iter().collect();
+    let mut delta: Vec<String> = after
+        .difference(&before)
+        .map(|pkg| format!("+{}", pkg))
+        .collect();
+    delta.extend(before.difference(&after).map(|pkg| format!("-{}", pkg)));
+    if delta.is_empty() {
+        output.info("packages: no change");
     } else {
-        record_state.generations.history.push(entry);
-    }
-    record_state
-        .generations
-        .history
-        .sort_by_key(|entry| entry.id);
-    if record_state.generations.history.len() > 50 {
-        let keep_from = record_state.generations.history.len() - 50;
-        record_state.generations.history = record_state.generations.history.split_off(keep_from);
-    }
-    record_state.mica.modified = timestamp;
-    save_profile_state(&record_state)?;
-    snapshot_generation(&record_state, id)?;
-    Ok(())
-}
-
-fn list_generations(output: &Output, state: &GlobalProfileState) -> Result<(), CliError> {
-    if state.generations.history.is_empty() {
-        output.info("no generations recorded");
-        return Ok(());
+        output.info(format!("packages: {}", delta.join(" ")));
     }
-    for entry in &state.generations.history {
-        output.info(format!(
-            "{} {} ({} pkgs)",
-            entry.id,
-            entry.timestamp.to_rfc3339(),
-            entry.packages.len()
-        ));
-    }
-    Ok(())
 }
 
-fn rollback_generation(
+fn report_dry_run_diff(
     output: &Output,
-    target_id: Option<u64>,
-    dry_run: bool,
+    clock: &dyn Clock,
+    path: &Path,
+    generated: &str,
 ) -> Result<(), CliError> {
-    let current = load_profile_state()?;
-    if current.generations.history.is_empty() {
-        return Err(CliError::NoGenerations);
+    if !path.exists() {
+        output.info(format!("would write {}", path.display()));
+        return Ok(());
     }
-    let target = match target_id {
-        Some(id) => id,
-        None => {
-            if current.generations.history.len() < 2 {
-                return Err(CliError::NoGenerations);
-            }
-            current.generations.history[current.generations.history.len() - 2].id
-        }
+    let existing = std::fs::read_to_string(path).map_err(CliError::ReadNix)?;
+    let Some(generated) = stamp_generated_nix(clock, path, generated)? else {
+        output.info(format!("no changes to {}", path.display()));
+        return Ok(());
     };
-    if !current
-        .generations
-        .history
-        .iter()
-        .any(|entry| entry.id == target)
-    {
-        return Err(CliError::GenerationNotFound(target));
-    }
-    let snapshot_path = generations_dir()?
-        .join(target.to_string())
-        .join("profile.toml");
-    if !snapshot_path.exists() {
-        return Err(CliError::GenerationSnapshotMissing(snapshot_path));
-    }
-    let snapshot = GlobalProfileState::load_from_path(&snapshot_path).map_err(CliError::State)?;
-    let mut next_state = snapshot;
-    next_state.generations = current.generations.clone();
-    next_state.mica.modified = Utc::now();
-
-    if dry_run {
-        output.info(format!("dry-run: would rollback to generation {}", target));
-        diff_profile(output, &next_state)?;
+    let hunks = diff_hunks(&existing, &generated, DRY_RUN_DIFF_CONTEXT);
+    if hunks.is_empty() {
+        output.info(format!("no changes to {}", path.display()));
         return Ok(());
     }
-
-    save_profile_state(&next_state)?;
-    sync_and_install_profile(output, &next_state)?;
-    output.info(format!("rolled back to generation {}", target));
-    Ok(())
-}
-
-fn sync_and_install_profile(output: &Output, state: &GlobalProfileState) -> Result<(), CliError> {
-    sync_profile_nix(state)?;
-    run_with_spinner(output, "installing global profile", install_profile_nix)?;
-    if let Err(err) = record_profile_generation(output, state) {
-        output.warn(format!("warning: failed to record generation: {}", err));
-    }
-    Ok(())
-}
-
-fn install_profile_nix() -> Result<(), CliError> {
-    let path = profile_nix_path()?;
-    let mut command = ProcessCommand::new("nix-env");
-    command
-        .arg("-if")
-        .arg(&path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped());
-    let output = command
-        .spawn()
-        .map_err(|err| {
-            if err.kind() == io::ErrorKind::NotFound {
-                CliError::MissingNixEnv
-            } else {
-                CliError::NixEnvIo(err)
-            }
-        })?
-        .wait_with_output()
-        .map_err(CliError::NixEnvIo)?;
-
-    if !output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let message = format!(
-            "status={}, stdout={}, stderr={}",
-            output.status,
-            stdout.trim(),
-            stderr.trim()
-        );
-        return Err(CliError::NixEnvFailed(message));
-    }
-
-    Ok(())
-}
-
-fn create_temp_nix_file(contents: &str) -> Result<PathBuf, CliError> {
-    let dir = std::env::temp_dir();
-    let pid = std::process::id();
-    for attempt in 0..20u32 {
-        let path = dir.join(format!("mica-eval-{}-{}.nix", pid, attempt));
-        match std::fs::OpenOptions::new()
-            .write(true)
-            .create_new(true)
-            .open(&path)
-        {
-            Ok(mut file) => {
-                file.write_all(contents.as_bytes())
-                    .map_err(CliError::TempNixFile)?;
-                return Ok(path);
-            }
-            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
-            Err(err) => return Err(CliError::TempNixFile(err)),
-        }
-    }
-    Err(CliError::TempNixFile(io::Error::new(
-        io::ErrorKind::AlreadyExists,
-        "failed to create temp nix file",
-    )))
-}
-
-fn eval_nix_file(path: &Path) -> Result<(), CliError> {
-    let parse_output = ProcessCommand::new("nix-instantiate")
-        .args(["--parse"])
-        .arg(path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .map_err(|err| {
-            if err.kind() == io::ErrorKind::NotFound {
-                CliError::MissingNixInstantiate
-            } else {
-                CliError::NixInstantiateFailed(err.to_string())
-            }
-        })?;
-    if !parse_output.status.success() {
-        let stdout = String::from_utf8_lossy(&parse_output.stdout);
-        let stderr = String::from_utf8_lossy(&parse_output.stderr);
-        return Err(CliError::NixInstantiateFailed(format!(
-            "status={}, stdout={}, stderr={}",
-            parse_output.status,
-            stdout.trim(),
-            stderr.trim()
-        )));
-    }
-
-    let build_output = ProcessCommand::new("nix-build")
-        .args(["--dry-run"])
-        .arg(path)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .output()
-        .map_err(|err| {
-            if err.kind() == io::ErrorKind::NotFound {
-                CliError::MissingNixBuild
-            } else {
-                CliError::NixBuildFailed(err.to_string())
-            }
-        })?;
-    if !build_output.status.success() {
-        let stdout = String::from_utf8_lossy(&build_output.stdout);
-        let stderr = String::from_utf8_lossy(&build_output.stderr);
-        return Err(CliError::NixBuildFailed(format!(
-            "status={}, stdout={}, stderr={}",
-            build_output.status,
-            stdout.trim(),
-            stderr.trim()
-        )));
+    output.info(format!("--- {}", path.display()));
+    output.info(format!("+++ {} (dry-run)", path.display()));
+    for line in hunks {
+        output.info(line);
     }
-
     Ok(())
 }
 
-fn eval_nix_contents(output: &Output, contents: &str) -> Result<(), CliError> {
-    let path = create_temp_nix_file(contents)?;
-    let result = eval_nix_file(&path);
-    let _ = std::fs::remove_file(&path);
-    if result.is_ok() {
-        output.info("validation ok");
-    }
-    result
-}
-
 fn diff_project(
     output: &Output,
+    clock: &dyn Clock,
     paths: &ProjectPaths,
     state: &ProjectState,
 ) -> Result<(), CliError> {
     ensure_pin_complete(&state.pin)?;
-    let presets = load_all_presets()?;
-    let mut preset_map = BTreeMap::new();
-    for preset in presets {
-        preset_map.insert(preset.name.clone(), preset);
-    }
-    let mut active_presets = Vec::new();
-    for name in &state.presets.active {
-        match preset_map.get(name) {
-            Some(preset) => active_presets.push(preset.clone()),
-            None => return Err(CliError::MissingPreset(name.clone())),
-        }
-    }
-    let merged = merge_presets(&active_presets, state);
+    let merged = merge_project_presets(clock, paths, state)?;
     let project_name = project_dir_name(paths);
-    let generated = generate_project_nix(state, &merged, &project_name, Utc::now());
+    let generated = timed("nix generation", || {
+        generate_project_nix(state, &merged, &project_name, clock.now())
+    });
     let existing = std::fs::read_to_string(&paths.nix_path).map_err(CliError::ReadNix)?;
     let parsed_generated = parse_nix_file(&generated).map_err(CliError::NixParse)?;
     let parsed_existing = parse_nix_file(&existing).map_err(CliError::NixParse)?;
 
     let pin_changed = parsed_generated.pin_section != parsed_existing.pin_section;
+    let extends_changed = parsed_generated.extends_section != parsed_existing.extends_section;
     let let_changed = parsed_generated.let_section != parsed_existing.let_section;
-    let packages_changed = parsed_generated.packages_section != parsed_existing.packages_section;
+    let packages_changed = parsed_generated.packages_section != parsed_existing.packages_section
+        || parsed_generated.exclude_section != parsed_existing.exclude_section
+        || parsed_generated.overrides_section != parsed_existing.overrides_section
+        || parsed_generated.priorities_section != parsed_existing.priorities_section;
     let env_changed = parsed_generated.env_section != parsed_existing.env_section;
+    let build_env_changed = parsed_generated.build_env_section != parsed_existing.build_env_section;
     let shell_changed = parsed_generated.shell_hook_section != parsed_existing.shell_hook_section;
     let override_changed = parsed_generated.override_section != parsed_existing.override_section;
     let override_shellhook_changed =
@@ -5037,9 +1679,11 @@ fn diff_project(
         parsed_generated.override_merge_section != parsed_existing.override_merge_section;
 
     if !(pin_changed
+        || extends_changed
         || let_changed
         || packages_changed
         || env_changed
+        || build_env_changed
         || shell_changed
         || override_changed
         || override_shellhook_changed
@@ -5052,6 +1696,10 @@ fn diff_project(
             "  pin: {}",
             if pin_changed { "changed" } else { "ok" }
         ));
+        output.info(format!(
+            "  extends: {}",
+            if extends_changed { "changed" } else { "ok" }
+        ));
         output.info(format!(
             "  let: {}",
             if let_changed { "changed" } else { "ok" }
@@ -5064,6 +1712,10 @@ fn diff_project(
             "  env: {}",
             if env_changed { "changed" } else { "ok" }
         ));
+        output.info(format!(
+            "  buildEnv: {}",
+            if build_env_changed { "changed" } else { "ok" }
+        ));
         output.info(format!(
             "  shellHook: {}",
             if shell_changed { "changed" } else { "ok" }
@@ -5092,7 +1744,32 @@ fn diff_project(
     Ok(())
 }
 
-fn diff_profile(output: &Output, state: &GlobalProfileState) -> Result<(), CliError> {
+fn diff_profile(
+    output: &Output,
+    clock: &dyn Clock,
+    state: &GlobalProfileState,
+) -> Result<(), CliError> {
+    let (pins_changed, paths_changed) = profile_nix_drift(clock, state)?;
+    if !(pins_changed || paths_changed) {
+        output.info("no drift detected");
+    } else {
+        output.info("drift detected:");
+        output.info(format!(
+            "  pins: {}",
+            if pins_changed { "changed" } else { "ok" }
+        ));
+        output.info(format!(
+            "  paths: {}",
+            if paths_changed { "changed" } else { "ok" }
+        ));
+    }
+    Ok(())
+}
+
+fn profile_nix_drift(
+    clock: &dyn Clock,
+    state: &GlobalProfileState,
+) -> Result<(bool, bool), CliError> {
     ensure_pin_complete(&state.pin)?;
     let presets = load_all_presets()?;
     let mut preset_map = BTreeMap::new();
@@ -5106,82 +1783,90 @@ fn diff_profile(output: &Output, state: &GlobalProfileState) -> Result<(), CliEr
             None => return Err(CliError::MissingPreset(name.clone())),
         }
     }
-    let merged = merge_profile_presets(&active_presets, state);
-    let generated = generate_profile_nix(state, &merged, Utc::now());
+    let merged = merge_profile_presets(&active_presets, state, &current_host());
+    let generated = timed("nix generation", || {
+        generate_profile_nix(state, &merged, clock.now())
+    });
     let existing = std::fs::read_to_string(profile_nix_path()?).map_err(CliError::ReadNix)?;
     let parsed_generated = parse_profile_nix(&generated).map_err(CliError::NixParse)?;
     let parsed_existing = parse_profile_nix(&existing).map_err(CliError::NixParse)?;
 
     let pins_changed = parsed_generated.pins_section != parsed_existing.pins_section;
     let paths_changed = parsed_generated.paths_section != parsed_existing.paths_section;
-
-    if !(pins_changed || paths_changed) {
-        output.info("no drift detected");
-    } else {
-        output.info("drift detected:");
-        output.info(format!(
-            "  pins: {}",
-            if pins_changed { "changed" } else { "ok" }
-        ));
-        output.info(format!(
-            "  paths: {}",
-            if paths_changed { "changed" } else { "ok" }
-        ));
-    }
-    Ok(())
+    Ok((pins_changed, paths_changed))
 }
 
 fn update_project_state_from_nix(
+    clock: &dyn Clock,
     paths: &ProjectPaths,
     state: &mut ProjectState,
 ) -> Result<(), CliError> {
     let content = std::fs::read_to_string(&paths.nix_path).map_err(CliError::ReadNix)?;
-    let parsed = parse_project_state_from_nix(&content).map_err(CliError::NixStateParse)?;
+    let parsed = timed("parse", || parse_project_state_from_nix(&content))
+        .map_err(CliError::NixStateParse)?;
     state.pin = parsed.pin;
     state.pins = parsed.pins;
+    state.packages.removed = parsed.removed;
     state.packages.pinned = parsed.pinned;
-    state.packages.added =
-        compute_added_packages(parsed.packages, &parsed.presets, &state.packages.pinned)?;
+    state.packages.priorities = parsed.priorities;
+    state.packages.overrides = parsed.overrides;
+    state.packages.added = compute_added_packages(
+        parsed.packages,
+        &parsed.presets,
+        &state.packages.pinned,
+        &state.pins,
+    )?;
     state.env = parsed.env;
     state.shell.hook = parsed.shell_hook;
     state.presets.active = parsed.presets;
     state.nix = parsed.nix;
-    update_project_modified(state);
+    state.extends = parsed.extends;
+    state.build_env = parsed.build_env;
+    state.manifest = parsed.manifest;
+    if let Some(version) = parsed.version {
+        state.mica.version = version;
+    }
+    update_project_modified(clock, state);
     Ok(())
 }
 
-fn update_profile_state_from_nix(state: &mut GlobalProfileState) -> Result<(), CliError> {
+fn update_profile_state_from_nix(
+    clock: &dyn Clock,
+    state: &mut GlobalProfileState,
+) -> Result<(), CliError> {
     let content = std::fs::read_to_string(profile_nix_path()?).map_err(CliError::ReadNix)?;
     let parsed = parse_profile_state_from_nix(&content).map_err(CliError::NixStateParse)?;
     state.pin = parsed.pin;
     state.packages.pinned = parsed.pinned;
     state.packages.added = parsed.packages;
-    update_profile_modified(state);
+    update_profile_modified(clock, state);
     Ok(())
 }
 
-fn update_project_modified(state: &mut ProjectState) {
-    state.mica.modified = Utc::now();
+fn update_project_modified(clock: &dyn Clock, state: &mut ProjectState) {
+    state.mica.modified = clock.now();
 }
 
-fn update_profile_modified(state: &mut GlobalProfileState) {
-    state.mica.modified = Utc::now();
+fn update_profile_modified(clock: &dyn Clock, state: &mut GlobalProfileState) {
+    state.mica.modified = clock.now();
 }
 
 fn update_pin_fields(
     pin: &mut Pin,
     url: Option<String>,
-    rev: Option<String>,
-    sha256: Option<String>,
+    resolved: ResolvedPin,
     branch: Option<String>,
 ) {
+    if url.is_some() || resolved.rev.is_some() {
+        pin.channel = resolved.channel;
+    }
     if let Some(url) = url {
         pin.url = url;
     }
-    if let Some(rev) = rev {
+    if let Some(rev) = resolved.rev {
         pin.rev = rev;
     }
-    if let Some(sha256) = sha256 {
+    if let Some(sha256) = resolved.sha256 {
         pin.sha256 = sha256;
     }
     if let Some(branch) = branch {
@@ -5189,6 +1874,13 @@ fn update_pin_fields(
     }
 }
 
+fn is_valid_extends_path(path: &str) -> bool {
+    !path.is_empty()
+        && path
+            .chars()
+            .all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '/' | '.' | '_' | '-' | '+'))
+}
+
 fn is_valid_pin_name(name: &str) -> bool {
     let mut chars = name.chars();
     let Some(first) = chars.next() else {
@@ -5197,7 +1889,45 @@ fn is_valid_pin_name(name: &str) -> bool {
     if !(first.is_ascii_alphabetic() || first == '_') {
         return false;
     }
-    chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+    chars.all(|ch| ch.is_ascii_alphanumeric() || ch == '_')
+}
+
+fn suggest_pin_name(name: &str, pins: &BTreeMap<String, Pin>) -> String {
+    let base = format!("{}_pin", name);
+    let mut candidate = base.clone();
+    let mut idx = 2;
+    while pins.contains_key(&candidate) {
+        candidate = format!("{}{}", base, idx);
+        idx += 1;
+    }
+    candidate
+}
+
+fn check_pin_name(name: &str, pins: &BTreeMap<String, Pin>) -> Result<(), CliError> {
+    if !is_valid_pin_name(name) {
+        return Err(CliError::InvalidPinName(name.to_string()));
+    }
+    if is_reserved_pin_name(name) {
+        return Err(CliError::ReservedPinName(
+            name.to_string(),
+            suggest_pin_name(name, pins),
+        ));
+    }
+    if pins.contains_key(name) {
+        return Err(CliError::PinExists(name.to_string()));
+    }
+    Ok(())
+}
+
+fn parse_pin_name(value: &str) -> Result<String, String> {
+    let name = value.trim();
+    match check_pin_name(name, &BTreeMap::new()) {
+        Ok(()) => Ok(name.to_string()),
+        Err(CliError::InvalidPinName(_)) => {
+            Err("must start with a letter or _ and contain only letters, digits and _".to_string())
+        }
+        Err(err) => Err(err.to_string()),
+    }
 }
 
 struct AddPinRequest {
@@ -5208,32 +1938,37 @@ struct AddPinRequest {
     rev: Option<String>,
     sha256: Option<String>,
     latest: bool,
+    channel: Option<String>,
 }
 
-fn add_extra_pin(state: &mut ProjectState, request: AddPinRequest) -> Result<(), CliError> {
+fn add_extra_pin(
+    git: &dyn GitProvider,
+    clock: &dyn Clock,
+    state: &mut ProjectState,
+    request: AddPinRequest,
+) -> Result<(), CliError> {
     let name = request.name.trim();
-    if !is_valid_pin_name(name) {
-        return Err(CliError::InvalidPinName(name.to_string()));
-    }
-    if state.pins.contains_key(name) {
-        return Err(CliError::PinExists(name.to_string()));
-    }
+    check_pin_name(name, &state.pins)?;
     let url = request.url.trim().to_string();
     let mut branch = request.branch.unwrap_or_else(|| state.pin.branch.clone());
     if branch.trim().is_empty() {
         branch = "main".to_string();
     }
     let use_latest = request.latest || request.rev.is_none();
-    let (resolved_rev, resolved_sha256) = resolve_update_rev_and_sha(
-        &state.pin,
-        &Some(url.clone()),
-        &Some(branch.clone()),
-        request.rev,
-        request.sha256,
-        use_latest,
-    )?;
-    let rev = resolved_rev.ok_or(CliError::IncompletePin)?;
-    let sha256 = resolved_sha256.ok_or(CliError::IncompletePin)?;
+    let resolved = match request.channel.as_deref() {
+        Some(channel) => resolve_channel_pin(git, channel, request.sha256)?,
+        None => resolve_update_rev_and_sha(
+            git,
+            &state.pin,
+            &Some(url.clone()),
+            &Some(branch.clone()),
+            request.rev,
+            request.sha256,
+            use_latest,
+        )?,
+    };
+    let rev = resolved.rev.ok_or(CliError::IncompletePin)?;
+    let sha256 = resolved.sha256.ok_or(CliError::IncompletePin)?;
     let tarball_name = request.tarball_name.and_then(|value| {
         let trimmed = value.trim();
         if trimmed.is_empty() {
@@ -5250,23 +1985,73 @@ fn add_extra_pin(state: &mut ProjectState, request: AddPinRequest) -> Result<(),
             rev,
             sha256,
             branch,
-            updated: Utc::now().date_naive(),
+            updated: clock.now().date_naive(),
+            channel: resolved.channel,
         },
     );
-    update_project_modified(state);
+    update_project_modified(clock, state);
     Ok(())
 }
 
+/// Revision, sha256 and channel source resolved for a pin update.
+struct ResolvedPin {
+    rev: Option<String>,
+    sha256: Option<String>,
+    channel: Option<PinChannel>,
+}
+
+/// Channel an update resolves from: the one passed with `--channel`, or the
+/// channel the pin already tracks when `--latest` keeps its url and branch.
+fn update_channel(
+    base_pin: &Pin,
+    channel: Option<String>,
+    url: &Option<String>,
+    branch: &Option<String>,
+    latest: bool,
+) -> Option<String> {
+    channel.or_else(|| {
+        if latest && url.is_none() && branch.is_none() {
+            base_pin
+                .channel
+                .as_ref()
+                .map(|channel| channel.name.clone())
+        } else {
+            None
+        }
+    })
+}
+
+fn resolve_channel_pin(
+    git: &dyn GitProvider,
+    channel: &str,
+    sha256: Option<String>,
+) -> Result<ResolvedPin, CliError> {
+    let release = git.channel_release(channel)?;
+    let sha256 = match sha256 {
+        Some(sha256) => sha256,
+        None => fetch_channel_sha256(&release.tarball)?,
+    };
+    Ok(ResolvedPin {
+        rev: Some(release.rev),
+        sha256: Some(sha256),
+        channel: Some(PinChannel {
+            name: channel.trim().to_string(),
+            tarball: release.tarball,
+        }),
+    })
+}
+
 fn resolve_update_rev_and_sha(
+    git: &dyn GitProvider,
     base_pin: &Pin,
     url: &Option<String>,
     branch: &Option<String>,
     rev: Option<String>,
     sha256: Option<String>,
     latest: bool,
-) -> Result<(Option<String>, Option<String>), CliError> {
+) -> Result<ResolvedPin, CliError> {
     let resolved_rev = if latest {
-        Some(latest_rev_from_github(url, branch, base_pin)?)
+        Some(latest_rev_for_pin(git, url, branch, base_pin)?)
     } else {
         rev
     };
@@ -5278,291 +2063,24 @@ fn resolve_update_rev_and_sha(
     } else {
         None
     };
-    Ok((resolved_rev, resolved_sha256))
-}
-
-fn latest_rev_from_github(
-    url: &Option<String>,
-    branch: &Option<String>,
-    base_pin: &Pin,
-) -> Result<String, CliError> {
-    let effective_url = url.clone().unwrap_or_else(|| base_pin.url.clone());
-    let mut effective_branch = branch.clone().unwrap_or_else(|| base_pin.branch.clone());
-    if effective_branch.trim().is_empty() {
-        effective_branch = "main".to_string();
-    }
-    fetch_latest_github_rev(&effective_url, &effective_branch)
-}
-
-fn fetch_latest_github_rev(url: &str, branch: &str) -> Result<String, CliError> {
-    let (owner, repo) = parse_github_repo(url)?;
-    let requested_branch = if branch.trim().is_empty() {
-        "main"
-    } else {
-        branch.trim()
-    };
-    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
-
-    match fetch_github_commit_sha(&client, &owner, &repo, requested_branch) {
-        Ok(rev) => Ok(rev),
-        Err(CliError::GitHubApiStatus(status, body))
-            if should_retry_default_branch_lookup(status, &body) =>
-        {
-            let default_branch = fetch_github_default_branch(&client, &owner, &repo)?;
-            if default_branch.trim().is_empty() || default_branch == requested_branch {
-                return Err(CliError::GitHubApiStatus(status, body));
-            }
-            fetch_github_commit_sha(&client, &owner, &repo, &default_branch)
-        }
-        Err(err) => Err(err),
-    }
-}
-
-fn fetch_github_commit_sha(
-    client: &Client,
-    owner: &str,
-    repo: &str,
-    reference: &str,
-) -> Result<String, CliError> {
-    let ref_encoded = encode_github_ref(reference);
-    let api_url = format!(
-        "https://api.github.com/repos/{}/{}/commits/{}",
-        owner, repo, ref_encoded
-    );
-    let response = client
-        .get(&api_url)
-        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
-        .header("Accept", "application/vnd.github+json")
-        .header("X-GitHub-Api-Version", "2022-11-28")
-        .send()?;
-
-    let status = response.status();
-    if !status.is_success() {
-        let body = response.text().unwrap_or_default();
-        return Err(CliError::GitHubApiStatus(status, body));
-    }
-
-    let commit: GitHubCommit = response.json()?;
-    if commit.sha.trim().is_empty() {
-        return Err(CliError::GitHubApiMissingSha);
-    }
-    Ok(commit.sha)
-}
-
-fn fetch_github_commit_date(url: &str, rev: &str) -> Result<String, CliError> {
-    let (owner, repo) = parse_github_repo(url)?;
-    let ref_encoded = encode_github_ref(rev);
-    let api_url = format!(
-        "https://api.github.com/repos/{}/{}/commits/{}",
-        owner, repo, ref_encoded
-    );
-    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
-    let response = client
-        .get(&api_url)
-        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
-        .header("Accept", "application/vnd.github+json")
-        .header("X-GitHub-Api-Version", "2022-11-28")
-        .send()?;
-
-    let status = response.status();
-    if !status.is_success() {
-        let body = response.text().unwrap_or_default();
-        return Err(CliError::GitHubApiStatus(status, body));
-    }
-
-    let commit: GitHubCommit = response.json()?;
-    if let Some(committer) = commit.commit.committer {
-        if !committer.date.trim().is_empty() {
-            return Ok(committer.date);
-        }
-    }
-    if let Some(author) = commit.commit.author {
-        if !author.date.trim().is_empty() {
-            return Ok(author.date);
-        }
-    }
-
-    Err(CliError::GitHubApiMissingDate)
-}
-
-fn fetch_github_default_branch(
-    client: &Client,
-    owner: &str,
-    repo: &str,
-) -> Result<String, CliError> {
-    let api_url = format!("https://api.github.com/repos/{}/{}", owner, repo);
-    let response = client
-        .get(&api_url)
-        .header("User-Agent", format!("mica/{}", env!("CARGO_PKG_VERSION")))
-        .header("Accept", "application/vnd.github+json")
-        .header("X-GitHub-Api-Version", "2022-11-28")
-        .send()?;
-
-    let status = response.status();
-    if !status.is_success() {
-        let body = response.text().unwrap_or_default();
-        return Err(CliError::GitHubApiStatus(status, body));
-    }
-
-    let repo_info: GitHubRepoInfo = response.json()?;
-    if repo_info.default_branch.trim().is_empty() {
-        return Err(CliError::GitHubApiMissingDefaultBranch);
-    }
-    Ok(repo_info.default_branch)
-}
-
-fn should_retry_default_branch_lookup(status: reqwest::StatusCode, body: &str) -> bool {
-    status == reqwest::StatusCode::UNPROCESSABLE_ENTITY && body.contains("No commit found for SHA")
-}
-
-fn fetch_nix_sha256(url: &str, rev: &str) -> Result<String, CliError> {
-    let tarball_url = format!("{}/archive/{}.tar.gz", url, rev);
-    prefetch_nix_sha256(&tarball_url)
-}
-
-fn prefetch_nix_sha256(url: &str) -> Result<String, CliError> {
-    let output = ProcessCommand::new("nix-prefetch-url")
-        .arg("--unpack")
-        .arg(url)
-        .output()
-        .map_err(|err| {
-            if err.kind() == io::ErrorKind::NotFound {
-                CliError::MissingNixPrefetch
-            } else {
-                CliError::NixPrefetchIo(err)
-            }
-        })?;
-
-    if !output.status.success() {
-        let stdout = String::from_utf8_lossy(&output.stdout);
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        let message = format!(
-            "status={}, stdout={}, stderr={}",
-            output.status,
-            stdout.trim(),
-            stderr.trim()
-        );
-        return Err(CliError::NixPrefetchFailed(message));
-    }
-
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
-    if let Some(hash) =
-        extract_nix_base32_hash(stdout.trim()).or_else(|| extract_nix_base32_hash(stderr.trim()))
-    {
-        return Ok(hash);
-    }
-
-    Err(CliError::NixPrefetchMissingHash)
-}
-
-fn extract_nix_base32_hash(output: &str) -> Option<String> {
-    output
-        .lines()
-        .rev()
-        .map(|line| line.trim())
-        .find(|line| is_nix_base32_hash(line))
-        .map(|line| line.to_string())
-}
-
-fn is_nix_base32_hash(value: &str) -> bool {
-    if value.len() != 52 {
-        return false;
-    }
-    value.chars().all(|ch| {
-        matches!(
-            ch,
-            '0'..='9'
-                | 'a'
-                | 'b'
-                | 'c'
-                | 'd'
-                | 'f'
-                | 'g'
-                | 'h'
-                | 'i'
-                | 'j'
-                | 'k'
-                | 'l'
-                | 'm'
-                | 'n'
-                | 'p'
-                | 'q'
-                | 'r'
-                | 's'
-                | 'v'
-                | 'w'
-                | 'x'
-                | 'y'
-                | 'z'
-        )
+    Ok(ResolvedPin {
+        rev: resolved_rev,
+        sha256: resolved_sha256,
+        channel: None,
     })
 }
 
-fn parse_github_repo(url: &str) -> Result<(String, String), CliError> {
-    let trimmed = url.trim();
-    if trimmed.is_empty() {
-        return Err(CliError::InvalidGitHubUrl(url.to_string()));
-    }
-
-    let rest = if let Some(rest) = trimmed.strip_prefix("https://github.com/") {
-        rest
-    } else if let Some(rest) = trimmed.strip_prefix("http://github.com/") {
-        rest
-    } else if let Some(rest) = trimmed.strip_prefix("git@github.com:") {
-        rest
-    } else if let Some(rest) = trimmed.strip_prefix("github.com/") {
-        rest
-    } else {
-        return Err(CliError::InvalidGitHubUrl(trimmed.to_string()));
-    };
-
-    let rest = rest.trim_end_matches('/');
-    let mut split = rest.split(['?', '#']);
-    let rest = match split.next() {
-        Some(value) => value,
-        None => rest,
-    };
-    let mut parts = rest.split('/').filter(|part| !part.is_empty());
-    let owner = parts
-        .next()
-        .ok_or_else(|| CliError::InvalidGitHubUrl(trimmed.to_string()))?;
-    let repo = parts
-        .next()
-        .ok_or_else(|| CliError::InvalidGitHubUrl(trimmed.to_string()))?;
-    if parts.next().is_some() {
-        return Err(CliError::InvalidGitHubUrl(trimmed.to_string()));
-    }
-    let repo = match repo.strip_suffix(".git") {
-        Some(stripped) => stripped,
-        None => repo,
-    };
-    if owner.is_empty() || repo.is_empty() {
-        return Err(CliError::InvalidGitHubUrl(trimmed.to_string()));
-    }
-
-    Ok((owner.to_string(), repo.to_string()))
-}
-
-fn encode_github_ref(reference: &str) -> String {
-    let mut out = String::new();
-    for byte in reference.as_bytes() {
-        match *byte {
-            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
-                out.push(*byte as char)
-            }
-            _ => out.push_str(&format!("%{:02X}", byte)),
-        }
-    }
-    out
-}
-
 struct ProjectNixParts<'a> {
     preamble: &'a str,
     pin_section: &'a str,
     pins_section: &'a str,
+    extends_section: &'a str,
     let_section: &'a str,
     packages_section: &'a str,
+    exclude_section: &'a str,
+    overrides_section: &'a str,
+    priorities_section: &'a str,
+    build_env_section: &'a str,
     scripts_section: &'a str,
     env_section: &'a str,
     shell_section: &'a str,
@@ -5588,6 +2106,10 @@ fn assemble_project_nix(parts: ProjectNixParts<'_>) -> String {
         "  name = \"{}\";\n\n",
         escape_nix_string(parts.project_name)
     ));
+    if !parts.extends_section.trim().is_empty() {
+        push_marker_block(&mut output, "  ", "mica:extends", parts.extends_section);
+        output.push('\n');
+    }
     push_marker_block(&mut output, "  ", "mica:let", parts.let_section);
     output.push('\n');
     output.push_str("  scripts = with pkgs; {\n");
@@ -5595,9 +2117,35 @@ fn assemble_project_nix(parts: ProjectNixParts<'_>) -> String {
     output.push_str("  };\n\n");
     push_marker_block(&mut output, "  ", "mica:packages", parts.packages_section);
     output.push('\n');
-    output.push_str("  paths = pkgs.lib.flatten [ tools ];\n");
+    let excluded = !parts.exclude_section.trim().is_empty();
+    if excluded {
+        push_marker_block(&mut output, "  ", "mica:exclude", parts.exclude_section);
+    }
+    let overridden = !parts.overrides_section.trim().is_empty();
+    if overridden {
+        push_marker_block(&mut output, "  ", "mica:overrides", parts.overrides_section);
+    }
+    let prioritized = !parts.priorities_section.trim().is_empty();
+    if prioritized {
+        push_marker_block(
+            &mut output,
+            "  ",
+            "mica:priorities",
+            parts.priorities_section,
+        );
+    }
+    output.push_str(&project_paths_line(excluded, prioritized, overridden));
     output.push_str("  env = pkgs.buildEnv {\n");
-    output.push_str("    inherit name paths; buildInputs = paths;\n");
+    let build_env = parse_build_env_section(parts.build_env_section);
+    output.push_str(build_env_inherit_line(build_env.name.is_some()));
+    if !build_env.is_default() {
+        push_marker_block(
+            &mut output,
+            "    ",
+            "mica:buildenv",
+            parts.build_env_section,
+        );
+    }
     push_marker_block(&mut output, "    ", "mica:env", parts.env_section);
     output.push('\n');
     push_marker_block(&mut output, "    ", "mica:shellhook", parts.shell_section);
@@ -5650,17 +2198,17 @@ fn push_marker_block(output: &mut String, indent: &str, name: &str, section: &st
 }
 
 fn update_project_pin_stub(
+    clock: &dyn Clock,
     state: &mut ProjectState,
     package: Option<String>,
     url: Option<String>,
-    rev: Option<String>,
-    sha256: Option<String>,
+    resolved: ResolvedPin,
     branch: Option<String>,
 ) -> Result<(), CliError> {
-    let now = Utc::now();
+    let now = clock.now();
     match package {
         None => {
-            update_pin_fields(&mut state.pin, url, rev, sha256, branch);
+            update_pin_fields(&mut state.pin, url, resolved, branch);
             state.pin.updated = now.date_naive();
         }
         Some(name) => {
@@ -5672,7 +2220,7 @@ fn update_project_pin_stub(
                     version: String::new(),
                     pin: state.pin.clone(),
                 });
-            update_pin_fields(&mut entry.pin, url, rev, sha256, branch);
+            update_pin_fields(&mut entry.pin, url, resolved, branch);
             entry.pin.updated = now.date_naive();
             state.packages.added.retain(|pkg| pkg != &name);
             state.packages.removed.retain(|pkg| pkg != &name);
@@ -5683,22 +2231,22 @@ fn update_project_pin_stub(
             }
         }
     }
-    update_project_modified(state);
+    update_project_modified(clock, state);
     Ok(())
 }
 
 fn update_profile_pin_stub(
+    clock: &dyn Clock,
     state: &mut GlobalProfileState,
     package: Option<String>,
     url: Option<String>,
-    rev: Option<String>,
-    sha256: Option<String>,
+    resolved: ResolvedPin,
     branch: Option<String>,
 ) -> Result<(), CliError> {
-    let now = Utc::now();
+    let now = clock.now();
     match package {
         None => {
-            update_pin_fields(&mut state.pin, url, rev, sha256, branch);
+            update_pin_fields(&mut state.pin, url, resolved, branch);
             state.pin.updated = now.date_naive();
         }
         Some(name) => {
@@ -5710,7 +2258,7 @@ fn update_profile_pin_stub(
                     version: String::new(),
                     pin: state.pin.clone(),
                 });
-            update_pin_fields(&mut entry.pin, url, rev, sha256, branch);
+            update_pin_fields(&mut entry.pin, url, resolved, branch);
             entry.pin.updated = now.date_naive();
             state.packages.added.retain(|pkg| pkg != &name);
             state.packages.removed.retain(|pkg| pkg != &name);
@@ -5721,7 +2269,7 @@ fn update_profile_pin_stub(
             }
         }
     }
-    update_profile_modified(state);
+    update_profile_modified(clock, state);
     Ok(())
 }
 
@@ -5734,62 +2282,6 @@ fn project_dir_name(paths: &ProjectPaths) -> String {
         .unwrap_or_else(|| "dev-environment".to_string())
 }
 
-fn index_display_name_for_url(url: &str) -> String {
-    let trimmed = url.trim();
-    if trimmed.is_empty() {
-        return "jpetrucciani/nix".to_string();
-    }
-    if let Some(pos) = trimmed.find("github.com/") {
-        let mut tail = &trimmed[pos + "github.com/".len()..];
-        if let Some(idx) = tail.find('#') {
-            tail = &tail[..idx];
-        }
-        if let Some(idx) = tail.find('?') {
-            tail = &tail[..idx];
-        }
-        for marker in ["/archive/", "/tarball/", "/commit/", "/tree/", "/releases/"] {
-            if let Some(idx) = tail.find(marker) {
-                tail = &tail[..idx];
-                break;
-            }
-        }
-        let tail = tail.trim_end_matches(".git");
-        let parts: Vec<&str> = tail.split('/').filter(|part| !part.is_empty()).collect();
-        if parts.len() >= 2 {
-            return format!("{}/{}", parts[0], parts[1]);
-        }
-    }
-    trimmed.to_string()
-}
-
-fn pin_branch_label(pin: &Pin) -> String {
-    if pin.branch.trim().is_empty() {
-        "main".to_string()
-    } else {
-        pin.branch.clone()
-    }
-}
-
-fn pin_source_label(pin: &Pin) -> String {
-    let repo = index_display_name_for_url(&pin.url);
-    let branch = pin_branch_label(pin);
-    format!("{}@{}", repo, branch)
-}
-
-fn pin_commit_date(output: &Output, pin: &Pin) -> String {
-    match fetch_github_commit_date(&pin.url, &pin.rev) {
-        Ok(date) => date,
-        Err(err) => {
-            output.warn(format!(
-                "warning: failed to fetch commit date for {}@{}: {}",
-                pin.url, pin.rev, err
-            ));
-            let fallback = pin.updated.and_hms_opt(0, 0, 0).unwrap();
-            chrono::DateTime::<Utc>::from_naive_utc_and_offset(fallback, Utc).to_rfc3339()
-        }
-    }
-}
-
 fn presets_path() -> PathBuf {
     Path::new("presets").to_path_buf()
 }
@@ -5817,6 +2309,7 @@ fn compute_added_packages(
     packages: Vec<String>,
     presets: &[String],
     pinned: &BTreeMap<String, PinnedPackage>,
+    pins: &BTreeMap<String, Pin>,
 ) -> Result<Vec<String>, CliError> {
     if presets.is_empty() {
         return Ok(packages
@@ -5832,9 +2325,7 @@ fn compute_added_packages(
     let mut preset_packages = std::collections::BTreeSet::new();
     for name in presets {
         if let Some(preset) = preset_map.get(name) {
-            for pkg in &preset.packages_required {
-                preset_packages.insert(pkg.clone());
-            }
+            preset_packages.extend(preset.required_packages(pins));
         }
     }
     Ok(packages
@@ -5844,21 +2335,39 @@ fn compute_added_packages(
 }
 
 fn load_all_presets() -> Result<Vec<Preset>, CliError> {
-    let config = load_config_or_default()?;
-    let mut preset_map: BTreeMap<String, Preset> = BTreeMap::new();
-    for preset in load_embedded_presets()? {
-        preset_map.insert(preset.name.clone(), preset);
-    }
-    for preset in load_presets_from_dir(&presets_path())? {
-        preset_map.insert(preset.name.clone(), preset);
-    }
-    for extra in config.presets.extra_dirs {
-        let expanded = expand_tilde(&extra)?;
-        for preset in load_presets_from_dir(&expanded)? {
+    load_presets(true)
+}
+
+/// Presets that do not depend on the working directory, used by the daemon
+/// so every client sees the same set regardless of where it was started.
+fn load_shared_presets() -> Result<Vec<Preset>, CliError> {
+    load_presets(false)
+}
+
+fn has_local_presets() -> bool {
+    presets_path().is_dir()
+}
+
+fn load_presets(include_local: bool) -> Result<Vec<Preset>, CliError> {
+    timed("preset load", || {
+        let config = load_config_or_default()?;
+        let mut preset_map: BTreeMap<String, Preset> = BTreeMap::new();
+        for preset in load_embedded_presets()? {
             preset_map.insert(preset.name.clone(), preset);
         }
-    }
-    Ok(preset_map.into_values().collect())
+        if include_local {
+            for preset in load_presets_from_dir(&presets_path())? {
+                preset_map.insert(preset.name.clone(), preset);
+            }
+        }
+        for extra in config.presets.extra_dirs {
+            let expanded = expand_tilde(&extra)?;
+            for preset in load_presets_from_dir(&expanded)? {
+                preset_map.insert(preset.name.clone(), preset);
+            }
+        }
+        Ok(preset_map.into_values().collect())
+    })
 }
 
 fn expand_tilde(path: &str) -> Result<PathBuf, CliError> {
@@ -5893,12 +2402,52 @@ fn profile_nix_path() -> Result<PathBuf, CliError> {
     Ok(config_dir()?.join("profile.nix"))
 }
 
+fn current_host() -> HostInfo {
+    let hostname = std::env::var("MICA_HOSTNAME")
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+        .or_else(|| std::fs::read_to_string("/proc/sys/kernel/hostname").ok())
+        .or_else(|| {
+            std::process::Command::new("hostname")
+                .stdin(std::process::Stdio::null())
+                .stderr(std::process::Stdio::null())
+                .output()
+                .ok()
+                .filter(|output| output.status.success())
+                .map(|output| String::from_utf8_lossy(&output.stdout).to_string())
+        })
+        .unwrap_or_default();
+    HostInfo::new(&hostname, std::env::consts::OS)
+}
+
+fn log_path() -> Result<PathBuf, CliError> {
+    Ok(cache_dir()?.join("mica.log"))
+}
+
+fn registry_path() -> Result<PathBuf, CliError> {
+    Ok(config_dir()?.join("projects.toml"))
+}
+
+fn daemon_socket_path() -> Result<PathBuf, CliError> {
+    Ok(cache_dir()?.join("daemon.sock"))
+}
+
+fn index_dir() -> Result<PathBuf, CliError> {
+    let shared = load_config_or_default()
+        .ok()
+        .and_then(|config| shared_index_dir(&config));
+    match shared {
+        Some(dir) => Ok(dir),
+        None => cache_dir(),
+    }
+}
+
 fn index_db_path() -> Result<PathBuf, CliError> {
-    Ok(cache_dir()?.join("index.db"))
+    Ok(index_dir()?.join("index.db"))
 }
 
 fn versions_db_path() -> Result<PathBuf, CliError> {
-    Ok(cache_dir()?.join("versions.db"))
+    Ok(index_dir()?.join("versions.db"))
 }
 
 fn home_dir() -> Result<PathBuf, CliError> {
@@ -5907,108 +2456,72 @@ fn home_dir() -> Result<PathBuf, CliError> {
         .map_err(|_| CliError::MissingHome)
 }
 
-fn print_project_state(output: &Output, state: &ProjectState) {
-    output.info("mode: project");
-    output.info(format!("pin: {} @ {}", state.pin.url, state.pin.rev));
-    if !state.pins.is_empty() {
-        output.info("pins:");
-        for (name, pin) in &state.pins {
-            output.info(format!("  {} -> {} ({})", name, pin.url, pin.rev));
-        }
-    }
-    output.info(format!("presets: {}", state.presets.active.join(", ")));
-    output.info(format!(
-        "packages (added): {}",
-        state.packages.added.join(", ")
-    ));
-    output.info(format!(
-        "packages (removed): {}",
-        state.packages.removed.join(", ")
-    ));
-    if !state.packages.pinned.is_empty() {
-        output.info("packages (pinned):");
-        for (name, pinned) in &state.packages.pinned {
-            output.info(format!(
-                "  {} -> {} ({})",
-                name, pinned.version, pinned.pin.rev
-            ));
-        }
-    }
-    if !state.env.is_empty() {
-        output.info("env:");
-        for (key, value) in &state.env {
-            let display = env_value_for_editor(value);
-            let suffix =
-                if env_value_mode_from_stored(value) == tui::app::EnvValueMode::NixExpression {
-                    " [expr]"
-                } else {
-                    ""
-                };
-            output.info(format!("  {}={}{}", key, display, suffix));
-        }
-    }
-    if let Some(hook) = &state.shell.hook {
-        output.info("shellHook:");
-        output.info(hook);
-    }
-}
-
-fn print_profile_state(output: &Output, state: &GlobalProfileState) {
-    output.info("mode: global");
-    output.info(format!("pin: {} @ {}", state.pin.url, state.pin.rev));
-    output.info(format!("presets: {}", state.presets.active.join(", ")));
-    output.info(format!(
-        "packages (added): {}",
-        state.packages.added.join(", ")
-    ));
-    output.info(format!(
-        "packages (removed): {}",
-        state.packages.removed.join(", ")
-    ));
-    if !state.packages.pinned.is_empty() {
-        output.info("packages (pinned):");
-        for (name, pinned) in &state.packages.pinned {
-            output.info(format!(
-                "  {} -> {} ({})",
-                name, pinned.version, pinned.pin.rev
-            ));
-        }
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use crate::{
-        encode_env_editor_value, env_value_for_editor, env_value_mode_from_stored,
-        parse_github_repo, resolve_remote_index_urls, should_retry_default_branch_lookup, Cli,
-        CliError, Command, IndexCommand,
+        check_pin_name, is_valid_extends_path, strip_inherited_packages, update_channel,
+        update_pin_fields, Cli, CliError, Command, IndexCommand, MigrateCommand, ResolvedPin,
     };
-    use chrono::NaiveDate;
+
     use clap::Parser;
     use clap_complete::Shell;
-    use mica_core::state::NIX_EXPR_PREFIX;
+
     use std::path::PathBuf;
 
     #[test]
-    fn parse_github_repo_https() {
-        let (owner, repo) =
-            parse_github_repo("https://github.com/jpetrucciani/nix").expect("parse failed");
-        assert_eq!(owner, "jpetrucciani");
-        assert_eq!(repo, "nix");
+    fn strip_inherited_packages_drops_parent_packages() {
+        let mut merged = mica_core::preset::MergedResult {
+            preset_packages: vec![mica_core::preset::PresetPackageGroup {
+                preset: "base".to_string(),
+                packages: vec!["jq".to_string()],
+            }],
+            user_packages: vec!["fd".to_string(), "ripgrep".to_string()],
+            env: Default::default(),
+            inherited_env: Vec::new(),
+            shell_hooks: Vec::new(),
+            all_packages: vec!["jq".to_string(), "fd".to_string(), "ripgrep".to_string()],
+            let_blocks: Vec::new(),
+            pin_blocks: Vec::new(),
+            packages_raw_blocks: Vec::new(),
+            scripts_blocks: Vec::new(),
+            env_raw_blocks: Vec::new(),
+            override_blocks: Vec::new(),
+            override_merge_blocks: Vec::new(),
+            override_shellhook_blocks: Vec::new(),
+        };
+        let inherited = ["jq".to_string(), "ripgrep".to_string()]
+            .into_iter()
+            .collect();
+
+        strip_inherited_packages(&mut merged, &inherited);
+
+        assert!(merged.preset_packages.is_empty());
+        assert_eq!(merged.user_packages, vec!["fd".to_string()]);
+        assert_eq!(merged.all_packages, vec!["fd".to_string()]);
     }
 
     #[test]
-    fn parse_github_repo_git_ssh() {
-        let (owner, repo) =
-            parse_github_repo("git@github.com:jpetrucciani/nix.git").expect("parse failed");
-        assert_eq!(owner, "jpetrucciani");
-        assert_eq!(repo, "nix");
+    fn extends_paths_must_be_plain_nix_paths() {
+        assert!(is_valid_extends_path("../default.nix"));
+        assert!(is_valid_extends_path("/repo/default.nix"));
+        assert!(!is_valid_extends_path("../my env/default.nix"));
+        assert!(!is_valid_extends_path(""));
     }
 
     #[test]
-    fn parse_github_repo_rejects_non_github() {
-        let result = parse_github_repo("https://example.com/jpetrucciani/nix");
-        assert!(matches!(result, Err(CliError::InvalidGitHubUrl(_))));
+    fn cli_parses_update_channel_flag() {
+        let cli = Cli::try_parse_from(["mica", "update", "--channel", "nixos-24.05"])
+            .expect("parse failed");
+        match cli.command {
+            Some(Command::Update { channel, .. }) => {
+                assert_eq!(channel.as_deref(), Some("nixos-24.05"))
+            }
+            _ => panic!("expected update command"),
+        }
+        assert!(
+            Cli::try_parse_from(["mica", "update", "--channel", "nixos-24.05", "--latest"])
+                .is_err()
+        );
     }
 
     #[test]
@@ -6053,6 +2566,48 @@ mod tests {
         }
     }
 
+    #[test]
+    fn cli_parses_index_rebuild_from_pin() {
+        let cli = Cli::try_parse_from(["mica", "index", "rebuild", "--from-pin", "unstable"])
+            .expect("parse failed");
+        match cli.command {
+            Some(Command::Index {
+                command:
+                    IndexCommand::Rebuild {
+                        inputs, from_pin, ..
+                    },
+            }) => {
+                assert!(inputs.is_empty());
+                assert_eq!(from_pin.as_deref(), Some("unstable"));
+            }
+            _ => panic!("expected index rebuild"),
+        }
+        assert!(Cli::try_parse_from(["mica", "index", "rebuild"]).is_err());
+        assert!(
+            Cli::try_parse_from(["mica", "index", "rebuild", "a.json", "--from-pin", "x"]).is_err()
+        );
+    }
+
+    #[test]
+    fn cli_parses_migrate_caches_next_to_all_projects() {
+        let cli = Cli::try_parse_from(["mica", "migrate", "caches"]).expect("parse failed");
+        assert!(matches!(
+            cli.command,
+            Some(Command::Migrate {
+                command: Some(MigrateCommand::Caches),
+                all_projects: false,
+            })
+        ));
+        let cli = Cli::try_parse_from(["mica", "migrate", "--all-projects"]).expect("parse failed");
+        assert!(matches!(
+            cli.command,
+            Some(Command::Migrate {
+                command: None,
+                all_projects: true,
+            })
+        ));
+    }
+
     #[test]
     fn cli_parses_completion_subcommand() {
         let cli = Cli::try_parse_from(["mica", "completion", "zsh"]).expect("parse failed");
@@ -6063,94 +2618,78 @@ mod tests {
     }
 
     #[test]
-    fn resolve_remote_index_urls_uses_commit_for_base_url() {
-        let urls = resolve_remote_index_urls("https://static.g7c.us/mica", Some("abcd1234"));
+    fn latest_follows_the_tracked_channel_until_a_revision_replaces_it() {
+        let mut pin = mica_core::state::Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: "abc".to_string(),
+            sha256: "sha".to_string(),
+            branch: "nixos-24.05".to_string(),
+            updated: chrono::NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            channel: Some(mica_core::state::PinChannel {
+                name: "nixos-24.05".to_string(),
+                tarball: "https://releases.nixos.org/nixos/24.05/x/nixexprs.tar.xz".to_string(),
+            }),
+        };
         assert_eq!(
-            urls,
-            vec!["https://static.g7c.us/mica/abcd1234.db".to_string()]
+            update_channel(&pin, None, &None, &None, true).as_deref(),
+            Some("nixos-24.05")
         );
-    }
-
-    #[test]
-    fn resolve_remote_index_urls_keeps_explicit_db_url() {
-        let urls = resolve_remote_index_urls("https://static.g7c.us/mica/index.db", Some("abcd"));
         assert_eq!(
-            urls,
-            vec!["https://static.g7c.us/mica/index.db".to_string()]
+            update_channel(&pin, None, &None, &Some("master".to_string()), true),
+            None
         );
-    }
-
-    #[test]
-    fn index_info_falls_back_to_primary_pin_when_meta_is_unknown() {
-        let info = crate::tui::app::IndexInfo {
-            url: "unknown".to_string(),
-            rev: "unknown".to_string(),
-            count: None,
-            generated_at: None,
-            displayed_count: None,
-        };
-        let pins = vec![crate::IndexPin {
-            name: None,
-            pin: mica_core::state::Pin {
-                name: None,
-                url: "https://github.com/jpetrucciani/nix".to_string(),
-                rev: "004391ff727d67a4f2e41590b0e8430a306d6688".to_string(),
-                sha256: "sha256-test".to_string(),
-                branch: "main".to_string(),
-                updated: NaiveDate::from_ymd_opt(2026, 2, 8).expect("valid date"),
+        assert_eq!(update_channel(&pin, None, &None, &None, false), None);
+
+        update_pin_fields(
+            &mut pin,
+            None,
+            ResolvedPin {
+                rev: Some("def".to_string()),
+                sha256: Some("sha2".to_string()),
+                channel: None,
             },
-        }];
-
-        let merged = crate::index_info_with_pin_fallback(info, &pins);
-        assert_eq!(merged.url, "https://github.com/jpetrucciani/nix");
-        assert_eq!(merged.rev, "004391ff727d67a4f2e41590b0e8430a306d6688");
+            None,
+        );
+        assert_eq!(pin.rev, "def");
+        assert_eq!(pin.channel, None);
     }
 
     #[test]
-    fn retry_default_branch_lookup_when_commit_is_missing_for_sha() {
-        let body = r#"{"message":"No commit found for SHA: main"}"#;
-        assert!(should_retry_default_branch_lookup(
-            reqwest::StatusCode::UNPROCESSABLE_ENTITY,
-            body
+    fn reserved_pin_names_are_rejected_with_a_suggestion() {
+        let mut pins = std::collections::BTreeMap::new();
+        assert!(check_pin_name("rust", &pins).is_ok());
+        assert!(matches!(
+            check_pin_name("1st", &pins),
+            Err(CliError::InvalidPinName(_))
         ));
-    }
+        match check_pin_name("env", &pins) {
+            Err(CliError::ReservedPinName(name, suggestion)) => {
+                assert_eq!(name, "env");
+                assert_eq!(suggestion, "env_pin");
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
 
-    #[test]
-    fn does_not_retry_default_branch_lookup_for_other_errors() {
-        let body = r#"{"message":"Validation Failed"}"#;
-        assert!(!should_retry_default_branch_lookup(
-            reqwest::StatusCode::UNPROCESSABLE_ENTITY,
-            body
+        let pin = mica_core::state::Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: "abc".to_string(),
+            sha256: "sha".to_string(),
+            branch: "main".to_string(),
+            updated: chrono::NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            channel: None,
+        };
+        pins.insert("pkgs_pin".to_string(), pin.clone());
+        pins.insert("rust".to_string(), pin);
+        assert!(matches!(
+            check_pin_name("pkgs", &pins),
+            Err(CliError::ReservedPinName(_, suggestion)) if suggestion == "pkgs_pin2"
         ));
-        assert!(!should_retry_default_branch_lookup(
-            reqwest::StatusCode::NOT_FOUND,
-            r#"{"message":"Not Found"}"#
+        assert!(matches!(
+            check_pin_name("rust", &pins),
+            Err(CliError::PinExists(_))
         ));
-    }
-
-    #[test]
-    fn env_expression_values_round_trip_through_editor_helpers() {
-        let stored = format!("{}${{pkgs.path}}/meme", NIX_EXPR_PREFIX);
-        assert_eq!(
-            env_value_mode_from_stored(&stored),
-            crate::tui::app::EnvValueMode::NixExpression
-        );
-        assert_eq!(env_value_for_editor(&stored), "${pkgs.path}/meme");
-    }
-
-    #[test]
-    fn encode_env_editor_value_marks_nix_expressions() {
-        let encoded = encode_env_editor_value(
-            "pkgs.path + \"/meme\"",
-            crate::tui::app::EnvValueMode::NixExpression,
-        )
-        .expect("encode should succeed");
-        assert_eq!(encoded, format!("{}pkgs.path + \"/meme\"", NIX_EXPR_PREFIX));
-    }
-
-    #[test]
-    fn encode_env_editor_value_rejects_empty_expression() {
-        let result = encode_env_editor_value("   ", crate::tui::app::EnvValueMode::NixExpression);
-        assert!(result.is_err());
+        assert!(Cli::try_parse_from(["mica", "pin", "add", "paths", "--url", "x"]).is_err());
     }
 }
@@ -0,0 +1,191 @@
+use crate::{
+    cache_dir, config_dir, expand_tilde, home_dir, index_db_path, load_config_or_default,
+    profile_nix_path, profile_state_path, versions_db_path, CliError, Output,
+};
+use mica_core::config::Config;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+#[derive(Debug, Default)]
+pub(crate) struct HomeFacts {
+    pub(crate) home: PathBuf,
+    pub(crate) home_uid: Option<u32>,
+    pub(crate) process_uid: Option<u32>,
+    pub(crate) user: Option<String>,
+    pub(crate) owners: Vec<(PathBuf, u32)>,
+    pub(crate) nix_profile: Option<PathBuf>,
+}
+
+pub(crate) fn home_issues(facts: &HomeFacts) -> Vec<String> {
+    let mut issues = Vec::new();
+    if let Some(home_uid) = facts.home_uid {
+        if let Some(process_uid) = facts.process_uid.filter(|uid| *uid != home_uid) {
+            issues.push(format!(
+                "mica is running as uid {} but HOME ({}) belongs to uid {}; files it writes there will not be writable by their owner (use `sudo -H` or run as that user)",
+                process_uid,
+                facts.home.display(),
+                home_uid
+            ));
+        }
+        let foreign: Vec<String> = facts
+            .owners
+            .iter()
+            .filter(|(_, uid)| *uid != home_uid)
+            .map(|(path, uid)| format!("{} (uid {})", path.display(), uid))
+            .collect();
+        if !foreign.is_empty() {
+            issues.push(format!(
+                "mica files not owned by the owner of HOME (uid {}): {}; installs and index updates may fail with permission errors (chown them back, or set index.shared_dir for a system-wide index)",
+                home_uid,
+                foreign.join(", ")
+            ));
+        }
+    }
+    if let (Some(target), Some(user)) = (&facts.nix_profile, &facts.user) {
+        if let Some(owner) = per_user_profile_owner(target).filter(|owner| owner != user) {
+            issues.push(format!(
+                "~/.nix-profile points at {}'s profile ({}) but mica runs as {}; nix-env will install into a different profile than the one mica tracks",
+                owner,
+                target.display(),
+                user
+            ));
+        }
+    }
+    issues
+}
+
+fn per_user_profile_owner(target: &Path) -> Option<String> {
+    let mut components = target.components();
+    while let Some(component) = components.next() {
+        if component == Component::Normal("per-user".as_ref()) {
+            return match components.next() {
+                Some(Component::Normal(name)) => Some(name.to_string_lossy().to_string()),
+                _ => None,
+            };
+        }
+    }
+    None
+}
+
+fn gather_home_facts() -> Result<HomeFacts, CliError> {
+    let home = home_dir()?;
+    let config = load_config_or_default().unwrap_or_default();
+    let mut candidates = vec![
+        config_dir()?,
+        cache_dir()?,
+        profile_state_path()?,
+        profile_nix_path()?,
+    ];
+    if shared_index_dir(&config).is_none() {
+        candidates.push(index_db_path()?);
+        candidates.push(versions_db_path()?);
+    }
+    let owners = candidates
+        .into_iter()
+        .filter_map(|path| {
+            let uid = std::fs::metadata(&path).ok()?.uid();
+            Some((path, uid))
+        })
+        .collect();
+    Ok(HomeFacts {
+        home_uid: std::fs::metadata(&home).ok().map(|meta| meta.uid()),
+        process_uid: std::fs::metadata("/proc/self").ok().map(|meta| meta.uid()),
+        user: std::env::var("USER")
+            .or_else(|_| std::env::var("LOGNAME"))
+            .ok()
+            .filter(|user| !user.is_empty()),
+        owners,
+        nix_profile: std::fs::read_link(home.join(".nix-profile")).ok(),
+        home,
+    })
+}
+
+pub(crate) fn warn_shared_home(output: &Output) {
+    match gather_home_facts() {
+        Ok(facts) => {
+            for issue in home_issues(&facts) {
+                output.warn(format!("warning: {}", issue));
+            }
+        }
+        Err(err) => output.verbose(format!("skipping multi-user check: {}", err)),
+    }
+}
+
+pub(crate) fn shared_index_dir(config: &Config) -> Option<PathBuf> {
+    let dir = config.index.shared_dir.as_deref()?.trim();
+    if dir.is_empty() {
+        return None;
+    }
+    expand_tilde(dir).ok()
+}
+
+pub(crate) fn dir_writable(dir: &Path) -> bool {
+    if std::fs::create_dir_all(dir).is_err() {
+        return false;
+    }
+    let probe = dir.join(format!(".mica-write-check-{}", std::process::id()));
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => {
+            let _ = std::fs::remove_file(&probe);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+pub(crate) fn ensure_index_writable(path: &Path) -> Result<(), CliError> {
+    let Ok(config) = load_config_or_default() else {
+        return Ok(());
+    };
+    let Some(dir) = shared_index_dir(&config) else {
+        return Ok(());
+    };
+    if path.parent() != Some(dir.as_path()) || dir_writable(&dir) {
+        return Ok(());
+    }
+    Err(CliError::SharedIndexReadOnly(dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::multiuser::{home_issues, HomeFacts};
+    use std::path::PathBuf;
+
+    #[test]
+    fn home_issues_flag_foreign_owners_and_profiles() {
+        let mut facts = HomeFacts {
+            home: PathBuf::from("/home/alice"),
+            home_uid: Some(1000),
+            process_uid: Some(1000),
+            user: Some("alice".to_string()),
+            owners: vec![
+                (PathBuf::from("/home/alice/.config/mica"), 1000),
+                (PathBuf::from("/home/alice/.config/mica/cache"), 0),
+            ],
+            nix_profile: Some(PathBuf::from(
+                "/nix/var/nix/profiles/per-user/alice/profile",
+            )),
+        };
+        let issues = home_issues(&facts);
+        assert_eq!(issues.len(), 1);
+        assert!(issues[0].contains("/home/alice/.config/mica/cache (uid 0)"));
+
+        facts.owners.clear();
+        facts.process_uid = Some(0);
+        facts.user = Some("root".to_string());
+        let issues = home_issues(&facts);
+        assert_eq!(issues.len(), 2);
+        assert!(issues[0].starts_with("mica is running as uid 0"));
+        assert!(issues[1].contains("points at alice's profile"));
+
+        facts.process_uid = None;
+        facts.nix_profile = Some(PathBuf::from(
+            "/home/alice/.local/state/nix/profiles/profile",
+        ));
+        assert!(home_issues(&facts).is_empty());
+    }
+}
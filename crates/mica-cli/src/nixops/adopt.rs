@@ -0,0 +1,223 @@
+use crate::nixops::drift::drv_pname;
+use crate::{CliError, Output};
+use mica_index::generate::find_attrs_by_pname;
+use rusqlite::Connection;
+use std::io;
+use std::process::Command as ProcessCommand;
+use std::process::Stdio;
+
+const PROFILE_ELEMENT_NAME: &str = "mica-profile";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct NixEnvElement {
+    pub(crate) name: String,
+    pub(crate) pname: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct AdoptPlan {
+    pub(crate) adopted: Vec<(String, String)>,
+    pub(crate) ambiguous: Vec<(String, Vec<String>)>,
+    pub(crate) unmatched: Vec<String>,
+}
+
+impl AdoptPlan {
+    pub(crate) fn attrs(&self) -> Vec<String> {
+        let mut attrs: Vec<String> = self.adopted.iter().map(|(_, attr)| attr.clone()).collect();
+        attrs.sort();
+        attrs.dedup();
+        attrs
+    }
+}
+
+pub(crate) fn list_nix_env_elements() -> Result<Vec<NixEnvElement>, CliError> {
+    let stdout = run_nix_env(&["-q", "--json"])?;
+    parse_nix_env_json(&stdout)
+}
+
+pub(crate) fn parse_nix_env_json(json: &str) -> Result<Vec<NixEnvElement>, CliError> {
+    let value: serde_json::Value = serde_json::from_str(json)
+        .map_err(|err| CliError::NixEnvFailed(format!("invalid nix-env output: {}", err)))?;
+    let Some(entries) = value.as_object() else {
+        return Ok(Vec::new());
+    };
+    let mut elements = Vec::new();
+    for (key, entry) in entries {
+        let name = entry
+            .get("name")
+            .and_then(|name| name.as_str())
+            .unwrap_or(key)
+            .to_string();
+        let pname = entry
+            .get("pname")
+            .and_then(|pname| pname.as_str())
+            .filter(|pname| !pname.is_empty())
+            .map(str::to_string)
+            .unwrap_or_else(|| drv_pname(&name).to_string());
+        if pname == PROFILE_ELEMENT_NAME {
+            continue;
+        }
+        elements.push(NixEnvElement { name, pname });
+    }
+    Ok(elements)
+}
+
+pub(crate) fn plan_adoption(
+    conn: &Connection,
+    elements: &[NixEnvElement],
+) -> Result<AdoptPlan, CliError> {
+    let mut plan = AdoptPlan::default();
+    for element in elements {
+        let candidates = find_attrs_by_pname(conn, &element.pname)?;
+        // Only adopt when the match is unambiguous, anything else stays in
+        // nix-env so adoption never swaps a package for a different one.
+        let chosen = candidates.iter().find(|attr| **attr == element.pname).or(
+            match candidates.as_slice() {
+                [only] => Some(only),
+                _ => None,
+            },
+        );
+        match chosen {
+            Some(attr) => plan.adopted.push((element.name.clone(), attr.clone())),
+            None if candidates.is_empty() => plan.unmatched.push(element.name.clone()),
+            None => plan.ambiguous.push((element.name.clone(), candidates)),
+        }
+    }
+    Ok(plan)
+}
+
+pub(crate) fn report_adoption(output: &Output, plan: &AdoptPlan) {
+    for (name, attr) in &plan.adopted {
+        output.info(format!("  {} -> {}", name, attr));
+    }
+    for (name, candidates) in &plan.ambiguous {
+        output.warn(format!(
+            "warning: {} matches several attrs ({}), left in nix-env, add the right one with `mica -g add`",
+            name,
+            candidates.join(", ")
+        ));
+    }
+    if !plan.unmatched.is_empty() {
+        output.warn(format!(
+            "warning: no index match for {} package(s), left in nix-env: {}",
+            plan.unmatched.len(),
+            plan.unmatched.join(", ")
+        ));
+    }
+}
+
+/// Lowers the priority of the given elements so the mica profile wins file
+/// collisions while both are installed.
+pub(crate) fn deprioritize_nix_env_elements(names: &[String]) -> Result<(), CliError> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    let mut args = vec!["--set-flag", "priority", "10"];
+    args.extend(names.iter().map(String::as_str));
+    run_nix_env(&args).map(|_| ())
+}
+
+pub(crate) fn uninstall_nix_env_elements(names: &[String]) -> Result<(), CliError> {
+    if names.is_empty() {
+        return Ok(());
+    }
+    let mut args = vec!["-e"];
+    args.extend(names.iter().map(String::as_str));
+    run_nix_env(&args).map(|_| ())
+}
+
+fn run_nix_env(args: &[&str]) -> Result<String, CliError> {
+    let output = ProcessCommand::new("nix-env")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixEnv
+            } else {
+                CliError::NixEnvIo(err)
+            }
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CliError::NixEnvFailed(format!(
+            "status={}, stderr={}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nixops::adopt::{parse_nix_env_json, plan_adoption, AdoptPlan};
+    use mica_index::generate::{ingest_packages, init_db, NixPackage};
+    use std::path::Path;
+
+    fn package(attr: &str, name: &str) -> NixPackage {
+        NixPackage {
+            attr_path: attr.to_string(),
+            name: name.to_string(),
+            version: None,
+            description: None,
+            homepage: None,
+            license: None,
+            platforms: None,
+            main_program: None,
+            position: None,
+            broken: None,
+            insecure: None,
+            maintainers: None,
+            last_updated: None,
+        }
+    }
+
+    #[test]
+    fn nix_env_packages_map_to_index_attrs() {
+        let elements = parse_nix_env_json(
+            r#"{
+                "ripgrep-14.1.1": {"name": "ripgrep-14.1.1", "pname": "ripgrep", "version": "14.1.1"},
+                "python3-3.12.2": {"name": "python3-3.12.2", "version": "3.12.2"},
+                "mica-profile": {"name": "mica-profile", "pname": "mica-profile", "version": ""},
+                "my-script": {"name": "my-script", "pname": "my-script", "version": ""},
+                "nodejs-20.11.0": {"name": "nodejs-20.11.0", "pname": "nodejs", "version": "20.11.0"},
+                "jq-1.7.1": {"name": "jq-1.7.1", "pname": "jq", "version": "1.7.1"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(elements.len(), 5);
+
+        let mut conn = init_db(Path::new(":memory:")).unwrap();
+        ingest_packages(
+            &mut conn,
+            &[
+                package("ripgrep", "ripgrep-14.1.1"),
+                package("python3", "python3-3.12.2"),
+                package("python312", "python3-3.12.2"),
+                package("nodejs_20", "nodejs-20.11.0"),
+                package("nodejs_22", "nodejs-22.1.0"),
+                package("jq", "jq-1.7.1"),
+            ],
+        )
+        .unwrap();
+        let plan = plan_adoption(&conn, &elements).unwrap();
+        assert_eq!(
+            plan,
+            AdoptPlan {
+                adopted: vec![
+                    ("jq-1.7.1".to_string(), "jq".to_string()),
+                    ("python3-3.12.2".to_string(), "python3".to_string()),
+                    ("ripgrep-14.1.1".to_string(), "ripgrep".to_string()),
+                ],
+                ambiguous: vec![(
+                    "nodejs-20.11.0".to_string(),
+                    vec!["nodejs_20".to_string(), "nodejs_22".to_string()],
+                )],
+                unmatched: vec!["my-script".to_string()],
+            }
+        );
+        assert_eq!(plan.attrs(), vec!["jq", "python3", "ripgrep"]);
+    }
+}
@@ -0,0 +1,307 @@
+use crate::nixops::generations::profile_installed_packages;
+use crate::nixops::profile::{
+    desired_profile_packages, list_profile_elements, load_managed_elements, plan_profile_changes,
+};
+use crate::{index_db_path, load_config_or_default, profile_nix_path, CliError, Output};
+use mica_core::config::ProfileBackend;
+use mica_core::state::GlobalProfileState;
+use mica_index::generate::{get_package, open_db};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::Path;
+use std::process::Command as ProcessCommand;
+use std::process::Stdio;
+
+const PROFILE_ELEMENT_NAME: &str = "mica-profile";
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct InstalledDrift {
+    pub(crate) missing: Vec<String>,
+    pub(crate) extra: Vec<String>,
+}
+
+impl InstalledDrift {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.extra.is_empty()
+    }
+}
+
+pub(crate) fn diff_installed_profile(
+    output: &Output,
+    state: &GlobalProfileState,
+) -> Result<bool, CliError> {
+    let config = load_config_or_default()?;
+    match config.profile.backend {
+        ProfileBackend::NixEnv => diff_nix_env_profile(output, state),
+        ProfileBackend::NixProfile => diff_nix_profile(output, state),
+    }
+}
+
+fn diff_nix_env_profile(output: &Output, state: &GlobalProfileState) -> Result<bool, CliError> {
+    let stdout = run_nix_env_query(&["-q", "--out-path"])?;
+    let Some(installed) = parse_nix_env_out_paths(&stdout).remove(PROFILE_ELEMENT_NAME) else {
+        output.info("profile.nix -> installed: out of sync");
+        output.info(format!(
+            "  {} is not installed, run `mica --global sync`",
+            PROFILE_ELEMENT_NAME
+        ));
+        return Ok(true);
+    };
+
+    match profile_out_path(&profile_nix_path()?) {
+        Ok(expected) if expected == installed => {
+            output.info("profile.nix -> installed: ok");
+            return Ok(false);
+        }
+        Ok(expected) => output.verbose(format!(
+            "profile.nix builds {}, installed is {}",
+            expected, installed
+        )),
+        Err(err) => output.warn(format!(
+            "warning: could not evaluate profile.nix, comparing packages only: {}",
+            err
+        )),
+    }
+
+    let references = run_nix_store_query(&["-q", "--references", &installed])?;
+    let installed_names: BTreeSet<String> = references
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(store_path_pname)
+        .collect();
+    let expected = expected_package_names(output, state)?;
+    let drift = installed_drift(&expected, &installed_names);
+    output.info("profile.nix -> installed: out of sync");
+    if drift.is_empty() {
+        output.info("  package set matches, but the installed build differs (pin or overrides)");
+    }
+    report_drift(output, &drift);
+    Ok(true)
+}
+
+fn diff_nix_profile(output: &Output, state: &GlobalProfileState) -> Result<bool, CliError> {
+    let managed = load_managed_elements()?;
+    let desired = desired_profile_packages(state)?;
+    let plan = plan_profile_changes(&desired, &managed);
+    let listed = list_profile_elements()?;
+    let vanished: Vec<String> = managed
+        .elements
+        .iter()
+        .filter(|(_, element)| !listed.contains(&element.name))
+        .map(|(attr, _)| attr.clone())
+        .collect();
+    if plan.is_empty() && vanished.is_empty() {
+        output.info("state -> nix profile: ok");
+        return Ok(false);
+    }
+    output.info("state -> nix profile: out of sync");
+    let drift = InstalledDrift {
+        missing: plan
+            .install
+            .iter()
+            .map(|(attr, _)| attr.clone())
+            .filter(|attr| !vanished.contains(attr))
+            .collect(),
+        extra: plan.remove.iter().map(|(attr, _)| attr.clone()).collect(),
+    };
+    report_drift(output, &drift);
+    if !vanished.is_empty() {
+        output.info(format!("  removed outside mica: {}", vanished.join(", ")));
+    }
+    Ok(true)
+}
+
+fn report_drift(output: &Output, drift: &InstalledDrift) {
+    if !drift.missing.is_empty() {
+        output.info(format!("  missing: {}", drift.missing.join(", ")));
+    }
+    if !drift.extra.is_empty() {
+        output.info(format!("  extra: {}", drift.extra.join(", ")));
+    }
+}
+
+fn expected_package_names(
+    output: &Output,
+    state: &GlobalProfileState,
+) -> Result<BTreeMap<String, String>, CliError> {
+    let index_path = index_db_path()?;
+    let conn = if index_path.exists() {
+        Some(open_db(&index_path)?)
+    } else {
+        output.verbose("index missing, matching installed packages by attribute name");
+        None
+    };
+    let mut expected = BTreeMap::new();
+    for attr in profile_installed_packages(state)? {
+        let name = match &conn {
+            Some(conn) => get_package(conn, &attr)?.map(|pkg| drv_pname(&pkg.name).to_string()),
+            None => None,
+        };
+        let name = name.unwrap_or_else(|| attr.rsplit('.').next().unwrap_or(&attr).to_string());
+        expected.insert(attr, name);
+    }
+    Ok(expected)
+}
+
+pub(crate) fn installed_drift(
+    expected: &BTreeMap<String, String>,
+    installed: &BTreeSet<String>,
+) -> InstalledDrift {
+    let wanted: BTreeSet<&str> = expected.values().map(String::as_str).collect();
+    InstalledDrift {
+        missing: expected
+            .iter()
+            .filter(|(_, name)| !installed.contains(*name))
+            .map(|(attr, _)| attr.clone())
+            .collect(),
+        extra: installed
+            .iter()
+            .filter(|name| !wanted.contains(name.as_str()))
+            .cloned()
+            .collect(),
+    }
+}
+
+pub(crate) fn parse_nix_env_out_paths(stdout: &str) -> BTreeMap<String, String> {
+    let mut paths = BTreeMap::new();
+    for line in stdout.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(name), Some(path)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let name = drv_pname(name).to_string();
+        paths.insert(name, path.to_string());
+    }
+    paths
+}
+
+pub(crate) fn store_path_pname(path: &str) -> String {
+    let base = path.rsplit('/').next().unwrap_or(path);
+    let name = base.split_once('-').map(|(_, rest)| rest).unwrap_or(base);
+    drv_pname(name).to_string()
+}
+
+pub(crate) fn drv_pname(name: &str) -> &str {
+    let bytes = name.as_bytes();
+    for (idx, byte) in bytes.iter().enumerate() {
+        if *byte == b'-'
+            && bytes
+                .get(idx + 1)
+                .is_some_and(|next| !next.is_ascii_alphabetic())
+        {
+            return &name[..idx];
+        }
+    }
+    name
+}
+
+fn profile_out_path(path: &Path) -> Result<String, CliError> {
+    let output = ProcessCommand::new("nix-instantiate")
+        .args([
+            "--eval",
+            "--expr",
+            "{ path }: (import path).outPath",
+            "--argstr",
+            "path",
+        ])
+        .arg(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixInstantiate
+            } else {
+                CliError::NixInstantiateFailed(err.to_string())
+            }
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CliError::NixInstantiateFailed(format!(
+            "status={}, stderr={}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .trim_matches('"')
+        .to_string())
+}
+
+fn run_nix_env_query(args: &[&str]) -> Result<String, CliError> {
+    run_query("nix-env", args)
+}
+
+fn run_nix_store_query(args: &[&str]) -> Result<String, CliError> {
+    run_query("nix-store", args)
+}
+
+fn run_query(program: &str, args: &[&str]) -> Result<String, CliError> {
+    let output = ProcessCommand::new(program)
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixEnv
+            } else {
+                CliError::NixEnvIo(err)
+            }
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CliError::NixEnvFailed(format!(
+            "{}: status={}, stderr={}",
+            program,
+            output.status,
+            stderr.trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nixops::drift::{
+        installed_drift, parse_nix_env_out_paths, store_path_pname, InstalledDrift,
+    };
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn installed_drift_reports_missing_and_extra_packages() {
+        let out_paths = parse_nix_env_out_paths(
+            "mica-profile  /nix/store/aaa-mica-profile\nhello-2.12.1  /nix/store/bbb-hello-2.12.1\n",
+        );
+        assert_eq!(
+            out_paths.get("mica-profile").map(String::as_str),
+            Some("/nix/store/aaa-mica-profile")
+        );
+        assert!(out_paths.contains_key("hello"));
+
+        assert_eq!(store_path_pname("/nix/store/abc-ripgrep-14.1.1"), "ripgrep");
+        assert_eq!(store_path_pname("/nix/store/abc-git-2.44.0-doc"), "git");
+        assert_eq!(store_path_pname("/nix/store/abc-python3-3.12.2"), "python3");
+        assert_eq!(
+            store_path_pname("/nix/store/abc-font-awesome"),
+            "font-awesome"
+        );
+
+        let expected: BTreeMap<String, String> = [
+            ("ripgrep".to_string(), "ripgrep".to_string()),
+            ("nodejs_22".to_string(), "nodejs".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        let installed: BTreeSet<String> = ["ripgrep", "jq"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(
+            installed_drift(&expected, &installed),
+            InstalledDrift {
+                missing: vec!["nodejs_22".to_string()],
+                extra: vec!["jq".to_string()],
+            }
+        );
+    }
+}
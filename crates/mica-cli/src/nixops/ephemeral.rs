@@ -0,0 +1,144 @@
+use crate::indexops::normalize_attr_path;
+use crate::nixops::pin_tarball_url;
+use crate::CliError;
+use mica_core::config::UrlRewrite;
+use mica_core::state::{Pin, PinnedPackage};
+use std::collections::BTreeMap;
+use std::io;
+use std::process::Command as ProcessCommand;
+
+pub(crate) fn resolve_try_packages<'a>(
+    packages: &[String],
+    pin: &'a Pin,
+    pins: &'a BTreeMap<String, Pin>,
+    pinned: &'a BTreeMap<String, PinnedPackage>,
+) -> Vec<(String, &'a Pin)> {
+    packages
+        .iter()
+        .map(|pkg| normalize_attr_path(pkg.trim()))
+        .filter(|attr| !attr.is_empty())
+        .map(|attr| {
+            for (name, extra) in pins {
+                if let Some(base) = attr.strip_prefix(&format!("{}.", name)) {
+                    return (base.to_string(), extra);
+                }
+            }
+            let source = pinned.get(&attr).map(|entry| &entry.pin).unwrap_or(pin);
+            (attr, source)
+        })
+        .collect()
+}
+
+pub(crate) fn ephemeral_shell_expression(
+    pin: &Pin,
+    packages: &[(String, &Pin)],
+    rewrites: &[UrlRewrite],
+) -> String {
+    let mut sources: Vec<&Pin> = vec![pin];
+    let mut items = Vec::new();
+    for (attr, source) in packages {
+        let idx = match sources.iter().position(|known| same_source(known, source)) {
+            Some(idx) => idx,
+            None => {
+                sources.push(source);
+                sources.len() - 1
+            }
+        };
+        items.push(format!("pkgs{}.{}", idx, attr));
+    }
+    let imports = sources
+        .iter()
+        .enumerate()
+        .map(|(idx, source)| {
+            format!(
+                "pkgs{} = import (fetchTarball {{ url = \"{}\"; sha256 = \"{}\"; }}) {{}};",
+                idx,
+                pin_tarball_url(source, rewrites),
+                source.sha256
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "let {} in pkgs0.mkShell {{ packages = [ {} ]; }}",
+        imports,
+        items.join(" ")
+    )
+}
+
+fn same_source(left: &Pin, right: &Pin) -> bool {
+    left.url == right.url && left.rev == right.rev && left.sha256 == right.sha256
+}
+
+pub(crate) fn run_ephemeral_shell(expression: &str, run: Option<&str>) -> Result<(), CliError> {
+    let mut command = ProcessCommand::new("nix-shell");
+    command.arg("-E").arg(expression);
+    if let Some(run) = run {
+        command.arg("--run").arg(run);
+    }
+    let status = command.status().map_err(|err| {
+        if err.kind() == io::ErrorKind::NotFound {
+            CliError::MissingNixShell
+        } else {
+            CliError::NixShellFailed(err.to_string())
+        }
+    })?;
+    if !status.success() {
+        return Err(CliError::NixShellFailed(format!("status={}", status)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nixops::ephemeral::{ephemeral_shell_expression, resolve_try_packages};
+    use chrono::NaiveDate;
+    use mica_core::state::{Pin, PinnedPackage};
+    use std::collections::BTreeMap;
+
+    fn pin(rev: &str) -> Pin {
+        Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: rev.to_string(),
+            sha256: "sha".to_string(),
+            branch: "main".to_string(),
+            updated: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            channel: None,
+        }
+    }
+
+    #[test]
+    fn ephemeral_shell_uses_pinned_sources_per_package() {
+        let base = pin("aaa");
+        let mut pinned = BTreeMap::new();
+        pinned.insert(
+            "nodejs".to_string(),
+            PinnedPackage {
+                version: "18.0.0".to_string(),
+                pin: pin("bbb"),
+            },
+        );
+        let mut pins = BTreeMap::new();
+        pins.insert("stable".to_string(), pin("ccc"));
+        let packages = [
+            "pkgs.ripgrep".to_string(),
+            "nodejs".to_string(),
+            "stable.hello".to_string(),
+            " ".to_string(),
+        ];
+        let resolved = resolve_try_packages(&packages, &base, &pins, &pinned);
+        assert_eq!(resolved.len(), 3);
+        assert_eq!(resolved[0].0, "ripgrep");
+        assert_eq!(resolved[1].1.rev, "bbb");
+        assert_eq!(resolved[2].0, "hello");
+        assert_eq!(resolved[2].1.rev, "ccc");
+
+        let expr = ephemeral_shell_expression(&base, &resolved, &[]);
+        assert!(expr.contains("pkgs0 = import (fetchTarball { url = \"https://github.com/NixOS/nixpkgs/archive/aaa.tar.gz\"; sha256 = \"sha\"; }) {};"));
+        assert!(expr.contains("archive/bbb.tar.gz"));
+        assert!(expr.ends_with(
+            "in pkgs0.mkShell { packages = [ pkgs0.ripgrep pkgs1.nodejs pkgs2.hello ]; }"
+        ));
+    }
+}
@@ -0,0 +1,353 @@
+use crate::clock::Clock;
+use crate::nixops::sync_and_install_profile;
+use crate::{
+    config_dir, current_host, diff_profile, load_all_presets, load_config_or_default,
+    load_profile_state, lock_profile_state, profile_nix_path, save_profile_state,
+    write_profile_state, CliError, Output,
+};
+use chrono::{DateTime, Utc};
+use mica_core::config::{Config, GenerationRecordMode, GenerationsSection, ProfileBackend};
+use mica_core::preset::merge_profile_presets;
+use mica_core::state::{GenerationEntry, GlobalProfileState};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::PathBuf;
+use std::process::Command as ProcessCommand;
+use std::process::Stdio;
+
+pub(crate) fn generations_dir() -> Result<PathBuf, CliError> {
+    Ok(config_dir()?.join("generations"))
+}
+
+pub(crate) fn latest_nix_env_generation() -> Result<Option<u64>, CliError> {
+    let output = ProcessCommand::new("nix-env")
+        .arg("--list-generations")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixEnv
+            } else {
+                CliError::NixEnvIo(err)
+            }
+        })?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CliError::NixEnvFailed(format!(
+            "status={}, stderr={}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut last = None;
+    for line in stdout.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if let Some(id) = trimmed.split_whitespace().next() {
+            if let Ok(parsed) = id.parse::<u64>() {
+                last = Some(parsed);
+            }
+        }
+    }
+    Ok(last)
+}
+
+pub(crate) fn profile_installed_packages(
+    state: &GlobalProfileState,
+) -> Result<Vec<String>, CliError> {
+    let presets = load_all_presets()?;
+    let mut preset_map = BTreeMap::new();
+    for preset in presets {
+        preset_map.insert(preset.name.clone(), preset);
+    }
+    let mut active_presets = Vec::new();
+    for name in &state.presets.active {
+        match preset_map.get(name) {
+            Some(preset) => active_presets.push(preset.clone()),
+            None => return Err(CliError::MissingPreset(name.clone())),
+        }
+    }
+    let merged = merge_profile_presets(&active_presets, state, &current_host());
+    let mut packages: BTreeSet<String> = merged.all_packages.into_iter().collect();
+    for pkg in state.packages.pinned.keys() {
+        packages.insert(pkg.clone());
+    }
+    Ok(packages.into_iter().collect())
+}
+
+pub(crate) fn snapshot_generation(state: &GlobalProfileState, id: u64) -> Result<(), CliError> {
+    let dir = generations_dir()?.join(id.to_string());
+    std::fs::create_dir_all(&dir).map_err(CliError::WriteNix)?;
+    let snapshot_path = dir.join("profile.toml");
+    state
+        .save_to_path(&snapshot_path)
+        .map_err(CliError::State)?;
+    let profile_nix = profile_nix_path()?;
+    if profile_nix.exists() {
+        let _ = std::fs::copy(&profile_nix, dir.join("profile.nix"));
+    }
+    Ok(())
+}
+
+pub(crate) fn record_profile_generation(
+    output: &Output,
+    clock: &dyn Clock,
+    state: &GlobalProfileState,
+) -> Result<(), CliError> {
+    let packages = profile_installed_packages(state)?;
+    let config = load_config_or_default()?;
+    let _lock = lock_profile_state()?;
+    let mut record_state = load_profile_state().unwrap_or_else(|_| state.clone());
+    record_state.generations.merge(&state.generations);
+    let timestamp = clock.now();
+    let action = generation_record_action(
+        &config.generations,
+        &record_state.generations.history,
+        &packages,
+        timestamp,
+    );
+    let id = match action {
+        GenerationRecord::Skip => {
+            output.verbose("skipping generation record (generations config)");
+            return Ok(());
+        }
+        GenerationRecord::ReplaceLast => match record_state.generations.history.last() {
+            Some(entry) => {
+                output.verbose(format!(
+                    "replacing generation {} (within generations.min_interval_minutes)",
+                    entry.id
+                ));
+                entry.id
+            }
+            None => next_generation_id(output, &config, &record_state),
+        },
+        GenerationRecord::Append => next_generation_id(output, &config, &record_state),
+    };
+
+    record_state.generations.record(GenerationEntry {
+        id,
+        timestamp,
+        packages,
+    });
+    record_state.mica.modified = timestamp;
+    write_profile_state(&record_state)?;
+    let mut snapshot = state.clone();
+    snapshot.generations = record_state.generations;
+    snapshot_generation(&snapshot, id)?;
+    Ok(())
+}
+
+fn next_generation_id(output: &Output, config: &Config, record_state: &GlobalProfileState) -> u64 {
+    let fallback = record_state
+        .generations
+        .history
+        .last()
+        .map(|entry| entry.id + 1)
+        .unwrap_or(1);
+    let nix_env_generation = match config.profile.backend {
+        ProfileBackend::NixEnv => latest_nix_env_generation(),
+        ProfileBackend::NixProfile => Ok(None),
+    };
+    match nix_env_generation {
+        Ok(Some(id)) => id,
+        Ok(None) => fallback,
+        Err(err) => {
+            output.warn(format!(
+                "warning: failed to read nix-env generations: {}",
+                err
+            ));
+            fallback
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum GenerationRecord {
+    Skip,
+    Append,
+    /// Inside `min_interval_minutes`: overwrite the newest entry with the new package set.
+    ReplaceLast,
+}
+
+pub(crate) fn generation_record_action(
+    config: &GenerationsSection,
+    history: &[GenerationEntry],
+    packages: &[String],
+    now: DateTime<Utc>,
+) -> GenerationRecord {
+    let last = history.last();
+    let unchanged = last.is_some_and(|entry| entry.packages == packages);
+    match config.record {
+        GenerationRecordMode::Off => return GenerationRecord::Skip,
+        GenerationRecordMode::Changed if unchanged => return GenerationRecord::Skip,
+        GenerationRecordMode::Changed | GenerationRecordMode::Always => {}
+    }
+    if config.min_interval_minutes > 0 {
+        if let Some(entry) = last {
+            let min_interval = chrono::Duration::minutes(config.min_interval_minutes as i64);
+            if now - entry.timestamp < min_interval {
+                return if unchanged {
+                    GenerationRecord::Skip
+                } else {
+                    GenerationRecord::ReplaceLast
+                };
+            }
+        }
+    }
+    GenerationRecord::Append
+}
+
+pub(crate) fn list_generations(
+    output: &Output,
+    state: &GlobalProfileState,
+) -> Result<(), CliError> {
+    if state.generations.history.is_empty() {
+        output.info("no generations recorded");
+        return Ok(());
+    }
+    for entry in &state.generations.history {
+        output.info(format!(
+            "{} {} ({} pkgs)",
+            entry.id,
+            entry.timestamp.to_rfc3339(),
+            entry.packages.len()
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn rollback_generation(
+    output: &Output,
+    clock: &dyn Clock,
+    target_id: Option<u64>,
+    dry_run: bool,
+) -> Result<(), CliError> {
+    let current = load_profile_state()?;
+    if current.generations.history.is_empty() {
+        return Err(CliError::NoGenerations);
+    }
+    let target = match target_id {
+        Some(id) => id,
+        None => {
+            if current.generations.history.len() < 2 {
+                return Err(CliError::NoGenerations);
+            }
+            current.generations.history[current.generations.history.len() - 2].id
+        }
+    };
+    if !current
+        .generations
+        .history
+        .iter()
+        .any(|entry| entry.id == target)
+    {
+        return Err(CliError::GenerationNotFound(target));
+    }
+    let snapshot_path = generations_dir()?
+        .join(target.to_string())
+        .join("profile.toml");
+    if !snapshot_path.exists() {
+        return Err(CliError::GenerationSnapshotMissing(snapshot_path));
+    }
+    let snapshot = GlobalProfileState::load_from_path(&snapshot_path).map_err(CliError::State)?;
+    let mut next_state = snapshot;
+    next_state.generations = current.generations.clone();
+    next_state.mica.modified = clock.now();
+
+    if dry_run {
+        output.info(format!("dry-run: would rollback to generation {}", target));
+        diff_profile(output, clock, &next_state)?;
+        return Ok(());
+    }
+
+    save_profile_state(&next_state)?;
+    sync_and_install_profile(output, clock, &next_state)?;
+    output.info(format!("rolled back to generation {}", target));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nixops::generations::{generation_record_action, GenerationRecord};
+    use chrono::{TimeZone, Utc};
+    use mica_core::config::{GenerationRecordMode, GenerationsSection};
+    use mica_core::state::GenerationEntry;
+
+    #[test]
+    fn generation_recording_respects_mode_and_interval() {
+        let last = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let history = vec![GenerationEntry {
+            id: 3,
+            timestamp: last,
+            packages: vec!["fd".to_string(), "jq".to_string()],
+        }];
+        let same = vec!["fd".to_string(), "jq".to_string()];
+        let changed = vec!["fd".to_string()];
+        let soon = last + chrono::Duration::minutes(5);
+        let later = last + chrono::Duration::minutes(30);
+
+        let always = GenerationsSection::default();
+        assert_eq!(
+            generation_record_action(&always, &history, &same, soon),
+            GenerationRecord::Append
+        );
+
+        let off = GenerationsSection {
+            record: GenerationRecordMode::Off,
+            min_interval_minutes: 0,
+        };
+        assert_eq!(
+            generation_record_action(&off, &history, &changed, later),
+            GenerationRecord::Skip
+        );
+
+        let only_changed = GenerationsSection {
+            record: GenerationRecordMode::Changed,
+            min_interval_minutes: 0,
+        };
+        assert_eq!(
+            generation_record_action(&only_changed, &history, &same, later),
+            GenerationRecord::Skip
+        );
+        assert_eq!(
+            generation_record_action(&only_changed, &history, &changed, soon),
+            GenerationRecord::Append
+        );
+        assert_eq!(
+            generation_record_action(&only_changed, &[], &same, soon),
+            GenerationRecord::Append
+        );
+
+        let throttled = GenerationsSection {
+            record: GenerationRecordMode::Always,
+            min_interval_minutes: 10,
+        };
+        assert_eq!(
+            generation_record_action(&throttled, &history, &changed, soon),
+            GenerationRecord::ReplaceLast
+        );
+        assert_eq!(
+            generation_record_action(&throttled, &history, &same, soon),
+            GenerationRecord::Skip
+        );
+        assert_eq!(
+            generation_record_action(&throttled, &history, &changed, later),
+            GenerationRecord::Append
+        );
+
+        let throttled_changed = GenerationsSection {
+            record: GenerationRecordMode::Changed,
+            min_interval_minutes: 10,
+        };
+        assert_eq!(
+            generation_record_action(&throttled_changed, &history, &changed, soon),
+            GenerationRecord::ReplaceLast
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use crate::clock::Clock;
+use crate::github::clone::{git_archive_uses_export_attributes, local_clone_for_url, run_git};
+use crate::nixops::generations::record_profile_generation;
+use crate::nixops::profile::install_with_nix_profile;
+use crate::timing::timed;
+use crate::{
+    escape_nix_string, load_config_or_default, profile_nix_path, run_with_spinner,
+    sync_profile_nix, CliError, Output,
+};
+use mica_core::config::{apply_url_rewrites, ProfileBackend, UrlRewrite};
+use mica_core::state::{archive_url, GlobalProfileState, Pin};
+use reqwest::blocking::Client;
+use std::io;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+use std::process::Stdio;
+use std::time::Duration;
+
+pub(crate) mod adopt;
+pub(crate) mod drift;
+pub(crate) mod ephemeral;
+pub(crate) mod generations;
+pub(crate) mod profile;
+pub(crate) mod storepath;
+pub(crate) mod warm;
+
+pub(crate) fn fetch_nix_sha256(url: &str, rev: &str) -> Result<String, CliError> {
+    fetch_nix_sha256_cancellable(url, rev, &|| false)
+}
+
+/// Like `fetch_nix_sha256`, but kills `nix-prefetch-url` once `cancelled`
+/// returns true.
+pub(crate) fn fetch_nix_sha256_cancellable(
+    url: &str,
+    rev: &str,
+    cancelled: &dyn Fn() -> bool,
+) -> Result<String, CliError> {
+    if let Some(clone) = local_clone_for_url(url) {
+        if let Ok(sha256) = prefetch_nix_sha256_from_clone(&clone, rev) {
+            return Ok(sha256);
+        }
+    }
+    prefetch_nix_sha256_cancellable(&nix_tarball_url(url, rev)?, cancelled)
+}
+
+/// Archive URL for a revision, after the configured `nixpkgs.url_rewrites`.
+pub(crate) fn nix_tarball_url(url: &str, rev: &str) -> Result<String, CliError> {
+    Ok(apply_url_rewrites(&url_rewrites()?, &archive_url(url, rev)))
+}
+
+/// The configured `nixpkgs.url_rewrites`. A config that fails to load is an
+/// error, so a broken config cannot quietly bypass the mirror.
+pub(crate) fn url_rewrites() -> Result<Vec<UrlRewrite>, CliError> {
+    Ok(load_config_or_default()?.nixpkgs.url_rewrites)
+}
+
+pub(crate) fn pin_tarball_url(pin: &Pin, rewrites: &[UrlRewrite]) -> String {
+    apply_url_rewrites(rewrites, &pin.tarball_url())
+}
+
+/// Nix function applying `rewrites` to URLs only known at evaluation time,
+/// such as the nixpkgs revision locked in a pinned repo's `flake.lock`.
+pub(crate) fn nix_url_rewriter(rewrites: &[UrlRewrite]) -> String {
+    let rules = rewrites
+        .iter()
+        .filter(|rule| !rule.from.is_empty())
+        .map(|rule| {
+            format!(
+                "{{ from = \"{}\"; to = \"{}\"; }}",
+                escape_nix_string(&rule.from),
+                escape_nix_string(&rule.to)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!(
+        "url: let matching = builtins.filter (rule: builtins.substring 0 (builtins.stringLength rule.from) url == rule.from) [ {} ]; \
+         in if matching == [ ] then url \
+         else let rule = builtins.head matching; \
+         in rule.to + builtins.substring (builtins.stringLength rule.from) (builtins.stringLength url) url",
+        rules
+    )
+}
+
+/// sha256 of a channel release's `nixexprs.tar.xz`, which differs from the
+/// GitHub archive of the same revision.
+pub(crate) fn fetch_channel_sha256(tarball: &str) -> Result<String, CliError> {
+    prefetch_nix_sha256(&apply_url_rewrites(&url_rewrites()?, tarball))
+}
+
+pub(crate) fn head_nix_tarball(url: &str) -> Result<Option<u64>, CliError> {
+    let client = Client::builder().timeout(Duration::from_secs(30)).build()?;
+    let response = client.head(url).send()?;
+    if !response.status().is_success() {
+        return Err(CliError::TarballStatus(response.status()));
+    }
+    Ok(response.content_length().filter(|len| *len > 0))
+}
+
+pub(crate) fn prefetch_nix_sha256_from_clone(clone: &Path, rev: &str) -> Result<String, CliError> {
+    let spec = format!("{}^{{commit}}", rev);
+    run_git(clone, &["rev-parse", "--verify", "--quiet", &spec])?;
+    if git_archive_uses_export_attributes(clone, rev)? {
+        return Err(CliError::GitFailed(format!(
+            "{} uses export attributes, git archive may not match the tarball",
+            rev
+        )));
+    }
+    let archive_path = std::env::temp_dir().join(format!(
+        "mica-archive-{}-{}.tar.gz",
+        std::process::id(),
+        rev
+    ));
+    let archive_arg = archive_path.to_string_lossy().to_string();
+    let prefix = format!("source-{}/", rev);
+    let result = run_git(
+        clone,
+        &[
+            "archive",
+            "--format=tar.gz",
+            &format!("--prefix={}", prefix),
+            "-o",
+            &archive_arg,
+            rev,
+        ],
+    )
+    .and_then(|_| prefetch_nix_sha256(&format!("file://{}", archive_arg)));
+    let _ = std::fs::remove_file(&archive_path);
+    result
+}
+
+pub(crate) fn prefetch_nix_sha256(url: &str) -> Result<String, CliError> {
+    prefetch_nix_sha256_cancellable(url, &|| false)
+}
+
+fn prefetch_nix_sha256_cancellable(
+    url: &str,
+    cancelled: &dyn Fn() -> bool,
+) -> Result<String, CliError> {
+    let mut child = ProcessCommand::new("nix-prefetch-url")
+        .arg("--unpack")
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixPrefetch
+            } else {
+                CliError::NixPrefetchIo(err)
+            }
+        })?;
+    // Drained on their own threads so a chatty prefetch cannot fill a pipe
+    // and stall while we poll for cancellation.
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(CliError::NixPrefetchIo)? {
+            break status;
+        }
+        if cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(CliError::Cancelled);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    };
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    if !status.success() {
+        let message = format!(
+            "status={}, stdout={}, stderr={}",
+            status,
+            stdout.trim(),
+            stderr.trim()
+        );
+        return Err(CliError::NixPrefetchFailed(message));
+    }
+
+    if let Some(hash) =
+        extract_nix_base32_hash(stdout.trim()).or_else(|| extract_nix_base32_hash(stderr.trim()))
+    {
+        return Ok(hash);
+    }
+
+    Err(CliError::NixPrefetchMissingHash)
+}
+
+fn read_pipe(pipe: Option<impl io::Read + Send + 'static>) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut bytes = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut bytes);
+        }
+        String::from_utf8_lossy(&bytes).to_string()
+    })
+}
+
+pub(crate) fn extract_nix_base32_hash(output: &str) -> Option<String> {
+    output
+        .lines()
+        .rev()
+        .map(|line| line.trim())
+        .find(|line| is_nix_base32_hash(line))
+        .map(|line| line.to_string())
+}
+
+pub(crate) fn is_nix_base32_hash(value: &str) -> bool {
+    if value.len() != 52 {
+        return false;
+    }
+    value.chars().all(|ch| {
+        matches!(
+            ch,
+            '0'..='9'
+                | 'a'
+                | 'b'
+                | 'c'
+                | 'd'
+                | 'f'
+                | 'g'
+                | 'h'
+                | 'i'
+                | 'j'
+                | 'k'
+                | 'l'
+                | 'm'
+                | 'n'
+                | 'p'
+                | 'q'
+                | 'r'
+                | 's'
+                | 'v'
+                | 'w'
+                | 'x'
+                | 'y'
+                | 'z'
+        )
+    })
+}
+
+pub(crate) fn create_temp_nix_file(contents: &str) -> Result<PathBuf, CliError> {
+    let dir = std::env::temp_dir();
+    let pid = std::process::id();
+    for attempt in 0..20u32 {
+        let path = dir.join(format!("mica-eval-{}-{}.nix", pid, attempt));
+        match std::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(mut file) => {
+                file.write_all(contents.as_bytes())
+                    .map_err(CliError::TempNixFile)?;
+                return Ok(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(CliError::TempNixFile(err)),
+        }
+    }
+    Err(CliError::TempNixFile(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        "failed to create temp nix file",
+    )))
+}
+
+pub(crate) fn eval_nix_file(path: &Path) -> Result<(), CliError> {
+    let parse_output = ProcessCommand::new("nix-instantiate")
+        .args(["--parse"])
+        .arg(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixInstantiate
+            } else {
+                CliError::NixInstantiateFailed(err.to_string())
+            }
+        })?;
+    if !parse_output.status.success() {
+        let stdout = String::from_utf8_lossy(&parse_output.stdout);
+        let stderr = String::from_utf8_lossy(&parse_output.stderr);
+        return Err(CliError::NixInstantiateFailed(format!(
+            "status={}, stdout={}, stderr={}",
+            parse_output.status,
+            stdout.trim(),
+            stderr.trim()
+        )));
+    }
+
+    let build_output = ProcessCommand::new("nix-build")
+        .args(["--dry-run"])
+        .arg(path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixBuild
+            } else {
+                CliError::NixBuildFailed(err.to_string())
+            }
+        })?;
+    if !build_output.status.success() {
+        let stdout = String::from_utf8_lossy(&build_output.stdout);
+        let stderr = String::from_utf8_lossy(&build_output.stderr);
+        return Err(CliError::NixBuildFailed(format!(
+            "status={}, stdout={}, stderr={}",
+            build_output.status,
+            stdout.trim(),
+            stderr.trim()
+        )));
+    }
+
+    Ok(())
+}
+
+pub(crate) fn eval_nix_contents(output: &Output, contents: &str) -> Result<(), CliError> {
+    let path = create_temp_nix_file(contents)?;
+    let result = eval_nix_file(&path);
+    let _ = std::fs::remove_file(&path);
+    if result.is_ok() {
+        output.info("validation ok");
+    }
+    result
+}
+
+pub(crate) fn sync_and_install_profile(
+    output: &Output,
+    clock: &dyn Clock,
+    state: &GlobalProfileState,
+) -> Result<(), CliError> {
+    sync_profile_nix(clock, state)?;
+    let backend = load_config_or_default()?.profile.backend;
+    timed("install", || match backend {
+        ProfileBackend::NixEnv => {
+            run_with_spinner(output, "installing global profile", install_profile_nix)
+        }
+        ProfileBackend::NixProfile => run_with_spinner(output, "updating nix profile", || {
+            install_with_nix_profile(output, state)
+        }),
+    })?;
+    if let Err(err) = record_profile_generation(output, clock, state) {
+        output.warn(format!("warning: failed to record generation: {}", err));
+    }
+    Ok(())
+}
+
+pub(crate) fn install_profile_nix() -> Result<(), CliError> {
+    let path = profile_nix_path()?;
+    let mut command = ProcessCommand::new("nix-env");
+    command
+        .arg("-if")
+        .arg(&path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let output = command
+        .spawn()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixEnv
+            } else {
+                CliError::NixEnvIo(err)
+            }
+        })?
+        .wait_with_output()
+        .map_err(CliError::NixEnvIo)?;
+
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = format!(
+            "status={}, stdout={}, stderr={}",
+            output.status,
+            stdout.trim(),
+            stderr.trim()
+        );
+        return Err(CliError::NixEnvFailed(message));
+    }
+
+    Ok(())
+}
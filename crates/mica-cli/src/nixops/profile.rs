@@ -0,0 +1,263 @@
+use crate::nixops::generations::profile_installed_packages;
+use crate::nixops::{pin_tarball_url, url_rewrites};
+use crate::{config_dir, CliError, Output};
+use mica_core::config::UrlRewrite;
+use mica_core::state::{GlobalProfileState, Pin};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::PathBuf;
+use std::process::Command as ProcessCommand;
+use std::process::Stdio;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct ManagedElements {
+    #[serde(default)]
+    pub(crate) elements: BTreeMap<String, ManagedElement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub(crate) struct ManagedElement {
+    pub(crate) name: String,
+    pub(crate) source: String,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct ProfilePlan<'a> {
+    pub(crate) remove: Vec<(String, ManagedElement)>,
+    pub(crate) install: Vec<(String, &'a Pin)>,
+}
+
+impl ProfilePlan<'_> {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.remove.is_empty() && self.install.is_empty()
+    }
+}
+
+pub(crate) fn managed_elements_path() -> Result<PathBuf, CliError> {
+    Ok(config_dir()?.join("nix-profile.json"))
+}
+
+pub(crate) fn load_managed_elements() -> Result<ManagedElements, CliError> {
+    let path = managed_elements_path()?;
+    if !path.exists() {
+        return Ok(ManagedElements::default());
+    }
+    let content = std::fs::read_to_string(&path).map_err(CliError::ReadNix)?;
+    serde_json::from_str(&content)
+        .map_err(|err| CliError::NixProfileFailed(format!("{}: {}", path.display(), err)))
+}
+
+pub(crate) fn save_managed_elements(managed: &ManagedElements) -> Result<(), CliError> {
+    let content = serde_json::to_string_pretty(managed)
+        .map_err(|err| CliError::NixProfileFailed(err.to_string()))?;
+    std::fs::write(managed_elements_path()?, content).map_err(CliError::WriteNix)
+}
+
+pub(crate) fn desired_profile_packages(
+    state: &GlobalProfileState,
+) -> Result<BTreeMap<String, &Pin>, CliError> {
+    let mut desired = BTreeMap::new();
+    for attr in profile_installed_packages(state)? {
+        let pin = state
+            .packages
+            .pinned
+            .get(&attr)
+            .map(|pinned| &pinned.pin)
+            .unwrap_or(&state.pin);
+        desired.insert(attr, pin);
+    }
+    Ok(desired)
+}
+
+pub(crate) fn pin_source_key(pin: &Pin) -> String {
+    format!("{}@{}", pin.url, pin.rev)
+}
+
+pub(crate) fn plan_profile_changes<'a>(
+    desired: &BTreeMap<String, &'a Pin>,
+    managed: &ManagedElements,
+) -> ProfilePlan<'a> {
+    let mut plan = ProfilePlan::default();
+    for (attr, element) in &managed.elements {
+        let keep = desired
+            .get(attr)
+            .is_some_and(|pin| pin_source_key(pin) == element.source);
+        if !keep {
+            plan.remove.push((attr.clone(), element.clone()));
+        }
+    }
+    for (attr, pin) in desired {
+        let installed = managed
+            .elements
+            .get(attr)
+            .is_some_and(|element| element.source == pin_source_key(pin));
+        if !installed {
+            plan.install.push((attr.clone(), *pin));
+        }
+    }
+    plan
+}
+
+pub(crate) fn package_expression(attr: &str, pin: &Pin, rewrites: &[UrlRewrite]) -> String {
+    format!(
+        "let pkgs = import (fetchTarball {{ url = \"{}\"; sha256 = \"{}\"; }}) {{}}; in pkgs.{}",
+        pin_tarball_url(pin, rewrites),
+        pin.sha256,
+        attr
+    )
+}
+
+pub(crate) fn install_with_nix_profile(
+    output: &Output,
+    state: &GlobalProfileState,
+) -> Result<(), CliError> {
+    let mut managed = load_managed_elements()?;
+    let desired = desired_profile_packages(state)?;
+    let plan = plan_profile_changes(&desired, &managed);
+    if plan.is_empty() {
+        output.verbose("nix profile already matches the global profile");
+        return Ok(());
+    }
+    let removed = plan.remove.len();
+    let installed = plan.install.len();
+    let rewrites = url_rewrites()?;
+
+    for (attr, element) in plan.remove {
+        output.verbose(format!("nix profile remove {}", element.name));
+        run_nix_profile(&["remove", &element.name])?;
+        managed.elements.remove(&attr);
+        save_managed_elements(&managed)?;
+    }
+    for (attr, pin) in plan.install {
+        output.verbose(format!("nix profile install {}", attr));
+        let before = list_profile_elements()?;
+        let expression = package_expression(&attr, pin, &rewrites);
+        run_nix_profile(&["install", "--impure", "--expr", &expression])?;
+        let after = list_profile_elements()?;
+        let name = after
+            .difference(&before)
+            .next()
+            .cloned()
+            .unwrap_or_else(|| attr.clone());
+        managed.elements.insert(
+            attr,
+            ManagedElement {
+                name,
+                source: pin_source_key(pin),
+            },
+        );
+        save_managed_elements(&managed)?;
+    }
+    output.status(format!(
+        "nix profile: {} installed, {} removed",
+        installed, removed
+    ));
+    Ok(())
+}
+
+pub(crate) fn list_profile_elements() -> Result<BTreeSet<String>, CliError> {
+    let stdout = run_nix_profile(&["list", "--json"])?;
+    parse_profile_element_names(&stdout)
+}
+
+pub(crate) fn parse_profile_element_names(json: &str) -> Result<BTreeSet<String>, CliError> {
+    let value: serde_json::Value = serde_json::from_str(json)
+        .map_err(|err| CliError::NixProfileFailed(format!("invalid profile list: {}", err)))?;
+    match value.get("elements") {
+        Some(serde_json::Value::Object(elements)) => Ok(elements.keys().cloned().collect()),
+        Some(serde_json::Value::Array(_)) => Err(CliError::NixProfileFailed(
+            "named profile elements require nix 2.20 or newer".to_string(),
+        )),
+        _ => Ok(BTreeSet::new()),
+    }
+}
+
+fn run_nix_profile(args: &[&str]) -> Result<String, CliError> {
+    let output = ProcessCommand::new("nix")
+        .args(["--extra-experimental-features", "nix-command flakes"])
+        .arg("profile")
+        .args(args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNix
+            } else {
+                CliError::NixProfileIo(err)
+            }
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CliError::NixProfileFailed(format!(
+            "status={}, stderr={}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nixops::profile::{
+        parse_profile_element_names, pin_source_key, plan_profile_changes, ManagedElement,
+        ManagedElements,
+    };
+    use chrono::NaiveDate;
+    use mica_core::state::Pin;
+    use std::collections::BTreeMap;
+
+    fn pin(rev: &str) -> Pin {
+        Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: rev.to_string(),
+            sha256: "sha".to_string(),
+            branch: "main".to_string(),
+            updated: NaiveDate::from_ymd_opt(2024, 1, 1).unwrap(),
+            channel: None,
+        }
+    }
+
+    #[test]
+    fn plan_only_touches_changed_packages() {
+        let base = pin("aaa");
+        let bumped = pin("bbb");
+        let mut managed = ManagedElements::default();
+        for attr in ["fd", "jq", "ripgrep"] {
+            managed.elements.insert(
+                attr.to_string(),
+                ManagedElement {
+                    name: attr.to_string(),
+                    source: pin_source_key(&base),
+                },
+            );
+        }
+        let desired: BTreeMap<String, &Pin> = [
+            ("fd".to_string(), &base),
+            ("jq".to_string(), &bumped),
+            ("bat".to_string(), &base),
+        ]
+        .into_iter()
+        .collect();
+
+        let plan = plan_profile_changes(&desired, &managed);
+        let removed: Vec<&str> = plan.remove.iter().map(|(attr, _)| attr.as_str()).collect();
+        let installed: Vec<&str> = plan.install.iter().map(|(attr, _)| attr.as_str()).collect();
+        assert_eq!(removed, vec!["jq", "ripgrep"]);
+        assert_eq!(installed, vec!["bat", "jq"]);
+    }
+
+    #[test]
+    fn parses_named_profile_elements() {
+        let json = r#"{"elements":{"ripgrep":{"active":true},"fd":{"active":true}},"version":3}"#;
+        let names = parse_profile_element_names(json).expect("parse failed");
+        assert_eq!(
+            names.into_iter().collect::<Vec<_>>(),
+            vec!["fd".to_string(), "ripgrep".to_string()]
+        );
+        assert!(parse_profile_element_names(r#"{"elements":[],"version":2}"#).is_err());
+    }
+}
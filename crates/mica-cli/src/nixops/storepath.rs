@@ -0,0 +1,184 @@
+use crate::nixops::{pin_tarball_url, url_rewrites};
+use crate::CliError;
+use mica_core::config::UrlRewrite;
+use mica_core::state::Pin;
+use reqwest::blocking::Client;
+use std::io;
+use std::path::Path;
+use std::process::Command as ProcessCommand;
+use std::process::Stdio;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum StorePathStatus {
+    Local,
+    Cached {
+        cache: String,
+        download_size: Option<u64>,
+    },
+    Build,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct StorePathEntry {
+    pub(crate) attr: String,
+    pub(crate) out_path: Option<String>,
+    pub(crate) status: StorePathStatus,
+}
+
+pub(crate) fn out_path_expression(attr: &str, pin: &Pin, rewrites: &[UrlRewrite]) -> String {
+    let attr_path = attr
+        .split('.')
+        .map(|part| format!("\"{}\"", part.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect::<Vec<_>>()
+        .join(".");
+    format!(
+        "(import (fetchTarball {{ url = \"{}\"; sha256 = \"{}\"; }}) {{}}).{}.outPath",
+        pin_tarball_url(pin, rewrites),
+        pin.sha256,
+        attr_path
+    )
+}
+
+pub(crate) fn evaluate_out_path(attr: &str, pin: &Pin) -> Result<String, CliError> {
+    let output = ProcessCommand::new("nix-instantiate")
+        .args(["--eval", "--expr"])
+        .arg(out_path_expression(attr, pin, &url_rewrites()?))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .output()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::MissingNixInstantiate
+            } else {
+                CliError::NixInstantiateFailed(err.to_string())
+            }
+        })?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = stderr
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("error:"))
+            .unwrap_or_else(|| stderr.trim());
+        return Err(CliError::NixInstantiateFailed(message.to_string()));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .trim_matches('"')
+        .to_string())
+}
+
+pub(crate) fn narinfo_url(cache: &str, out_path: &str) -> Option<String> {
+    let base = out_path.strip_prefix("/nix/store/")?;
+    let (hash, _) = base.split_once('-')?;
+    if hash.len() != 32 {
+        return None;
+    }
+    Some(format!("{}/{}.narinfo", cache.trim_end_matches('/'), hash))
+}
+
+pub(crate) fn narinfo_download_size(narinfo: &str) -> Option<u64> {
+    let field = |name: &str| {
+        narinfo
+            .lines()
+            .find_map(|line| line.strip_prefix(name))
+            .and_then(|value| value.trim().parse().ok())
+    };
+    field("FileSize:").or_else(|| field("NarSize:"))
+}
+
+fn query_cache(client: &Client, cache: &str, out_path: &str) -> Option<Option<u64>> {
+    let url = narinfo_url(cache, out_path)?;
+    let response = client.get(url).send().ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    Some(
+        response
+            .text()
+            .ok()
+            .and_then(|body| narinfo_download_size(&body)),
+    )
+}
+
+pub(crate) fn store_path_status(
+    client: &Client,
+    out_path: &str,
+    substituters: &[String],
+) -> StorePathStatus {
+    if Path::new(out_path).exists() {
+        return StorePathStatus::Local;
+    }
+    for cache in substituters {
+        if let Some(download_size) = query_cache(client, cache, out_path) {
+            return StorePathStatus::Cached {
+                cache: cache.clone(),
+                download_size,
+            };
+        }
+    }
+    StorePathStatus::Build
+}
+
+pub(crate) fn preview_store_paths(
+    packages: &[(String, &Pin)],
+    substituters: &[String],
+) -> Result<Vec<StorePathEntry>, CliError> {
+    let client = Client::builder().timeout(Duration::from_secs(15)).build()?;
+    let mut entries = Vec::new();
+    for (attr, pin) in packages {
+        let entry = match evaluate_out_path(attr, pin) {
+            Ok(out_path) => StorePathEntry {
+                attr: attr.clone(),
+                status: store_path_status(&client, &out_path, substituters),
+                out_path: Some(out_path),
+            },
+            Err(CliError::MissingNixInstantiate) => return Err(CliError::MissingNixInstantiate),
+            Err(err) => StorePathEntry {
+                attr: attr.clone(),
+                out_path: None,
+                status: StorePathStatus::Failed(err.to_string()),
+            },
+        };
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nixops::storepath::{narinfo_download_size, narinfo_url, out_path_expression};
+    use chrono::NaiveDate;
+    use mica_core::state::Pin;
+
+    #[test]
+    fn out_paths_map_to_narinfo_lookups() {
+        let pin = Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: "abc".to_string(),
+            sha256: "sha".to_string(),
+            branch: "nixpkgs-unstable".to_string(),
+            updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap(),
+            channel: None,
+        };
+        assert_eq!(
+            out_path_expression("python3Packages.requests", &pin, &[]),
+            "(import (fetchTarball { url = \"https://github.com/NixOS/nixpkgs/archive/abc.tar.gz\"; sha256 = \"sha\"; }) {}).\"python3Packages\".\"requests\".outPath"
+        );
+
+        let out_path = "/nix/store/0c1dgwb1ra9a6zpg9lmk0mr6bhsm81pv-ripgrep-14.1.1";
+        assert_eq!(
+            narinfo_url("https://cache.nixos.org/", out_path).as_deref(),
+            Some("https://cache.nixos.org/0c1dgwb1ra9a6zpg9lmk0mr6bhsm81pv.narinfo")
+        );
+        assert_eq!(narinfo_url("https://cache.nixos.org", "/tmp/ripgrep"), None);
+
+        let narinfo = "StorePath: /nix/store/0c1d-ripgrep\nCompression: xz\nFileSize: 1834120\nNarSize: 5529464\n";
+        assert_eq!(narinfo_download_size(narinfo), Some(1834120));
+        assert_eq!(narinfo_download_size("NarSize: 42\n"), Some(42));
+        assert_eq!(narinfo_download_size("StorePath: x\n"), None);
+    }
+}
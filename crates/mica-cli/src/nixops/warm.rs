@@ -0,0 +1,152 @@
+use crate::{cache_dir, CliError, Output};
+use mica_core::config::{HooksSection, PostUpdateHook};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command as ProcessCommand, Stdio};
+use std::time::Duration;
+
+pub(crate) fn warm_cache_command(hooks: &HooksSection, nix_path: &Path) -> Option<ProcessCommand> {
+    let copy_to = hooks
+        .copy_to
+        .as_deref()
+        .map(str::trim)
+        .filter(|value| !value.is_empty());
+    match (hooks.post_update, copy_to) {
+        (PostUpdateHook::Off, _) => None,
+        (PostUpdateHook::Copy, Some(target)) => {
+            let mut command = ProcessCommand::new("nix");
+            command
+                .args([
+                    "--extra-experimental-features",
+                    "nix-command",
+                    "copy",
+                    "--to",
+                ])
+                .arg(target)
+                .arg("--file")
+                .arg(nix_path);
+            Some(command)
+        }
+        (PostUpdateHook::Build | PostUpdateHook::Copy, _) => {
+            let mut command = ProcessCommand::new("nix-build");
+            command.arg(nix_path).arg("--no-out-link");
+            Some(command)
+        }
+    }
+}
+
+pub(crate) fn warm_cache_log_path() -> Result<PathBuf, CliError> {
+    Ok(cache_dir()?.join("warm-cache.log"))
+}
+
+fn spawn_logged(mut command: ProcessCommand) -> Result<std::process::Child, CliError> {
+    let log_path = warm_cache_log_path()?;
+    if let Some(parent) = log_path.parent() {
+        std::fs::create_dir_all(parent).map_err(CliError::WriteNix)?;
+    }
+    let log = File::create(&log_path).map_err(CliError::WriteNix)?;
+    let err_log = log.try_clone().map_err(CliError::WriteNix)?;
+    let program = command.get_program().to_string_lossy().to_string();
+    command
+        .stdin(Stdio::null())
+        .stdout(log)
+        .stderr(err_log)
+        .spawn()
+        .map_err(|err| {
+            if err.kind() == io::ErrorKind::NotFound {
+                CliError::NixBuildFailed(format!("{} not found in PATH", program))
+            } else {
+                CliError::NixBuildFailed(err.to_string())
+            }
+        })
+}
+
+pub(crate) fn spawn_warm_cache(output: &Output, hooks: &HooksSection, nix_path: &Path) {
+    let Some(command) = warm_cache_command(hooks, nix_path) else {
+        return;
+    };
+    match spawn_logged(command) {
+        Ok(_) => {
+            let log = warm_cache_log_path()
+                .map(|path| path.display().to_string())
+                .unwrap_or_default();
+            output.info(format!(
+                "warming binary cache in the background, log: {}",
+                log
+            ));
+        }
+        Err(err) => output.warn(format!("warning: cache warm skipped: {}", err)),
+    }
+}
+
+pub(crate) fn run_warm_cache(
+    command: ProcessCommand,
+    cancelled: &dyn Fn() -> bool,
+) -> Result<(), CliError> {
+    let mut child = spawn_logged(command)?;
+    loop {
+        if let Some(status) = child.try_wait().map_err(CliError::WriteNix)? {
+            if status.success() {
+                return Ok(());
+            }
+            let log = warm_cache_log_path()?;
+            return Err(CliError::NixBuildFailed(format!(
+                "status={}, see {}",
+                status,
+                log.display()
+            )));
+        }
+        if cancelled() {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(CliError::Cancelled);
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::nixops::warm::warm_cache_command;
+    use mica_core::config::{HooksSection, PostUpdateHook};
+    use std::path::Path;
+
+    fn args(hooks: &HooksSection) -> Option<Vec<String>> {
+        warm_cache_command(hooks, Path::new("/work/default.nix")).map(|command| {
+            std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect()
+        })
+    }
+
+    #[test]
+    fn warm_cache_command_follows_post_update_hook() {
+        let mut hooks = HooksSection::default();
+        assert_eq!(args(&hooks), None);
+
+        hooks.post_update = PostUpdateHook::Build;
+        assert_eq!(
+            args(&hooks).unwrap(),
+            vec!["nix-build", "/work/default.nix", "--no-out-link"]
+        );
+
+        hooks.post_update = PostUpdateHook::Copy;
+        assert_eq!(args(&hooks).unwrap()[0], "nix-build");
+        hooks.copy_to = Some("s3://nix-cache".to_string());
+        assert_eq!(
+            args(&hooks).unwrap(),
+            vec![
+                "nix",
+                "--extra-experimental-features",
+                "nix-command",
+                "copy",
+                "--to",
+                "s3://nix-cache",
+                "--file",
+                "/work/default.nix"
+            ]
+        );
+    }
+}
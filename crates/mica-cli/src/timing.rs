@@ -0,0 +1,141 @@
+use crate::clock::Clock;
+use crate::{log_path, CliError, Output};
+use chrono::{DateTime, Utc};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const LOG_MAX_BYTES: u64 = 1024 * 1024;
+
+type PhaseSummary = Vec<(&'static str, Duration, usize)>;
+
+// Totals per phase name rather than one entry per call, so long-running
+// processes like the daemon and the TUI stay bounded.
+static PHASES: Mutex<PhaseSummary> = Mutex::new(Vec::new());
+
+pub(crate) fn timed<T>(phase: &'static str, action: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = action();
+    if let Ok(mut phases) = PHASES.lock() {
+        record_phase(&mut phases, phase, start.elapsed());
+    }
+    result
+}
+
+pub(crate) fn report(output: &Output, clock: &dyn Clock, args: &[String], total: Duration) {
+    let phases = match PHASES.lock() {
+        Ok(mut phases) => std::mem::take(&mut *phases),
+        Err(_) => return,
+    };
+    if phases.is_empty() {
+        return;
+    }
+    for (phase, elapsed, count) in &phases {
+        if *count > 1 {
+            output.verbose(format!(
+                "timing: {} {} ({}x)",
+                phase,
+                format_duration(*elapsed),
+                count
+            ));
+        } else {
+            output.verbose(format!("timing: {} {}", phase, format_duration(*elapsed)));
+        }
+    }
+    output.verbose(format!("timing: total {}", format_duration(total)));
+    let line = format_log_line(clock.now(), args, total, &phases);
+    if let Err(err) = append_log_line(&line) {
+        output.verbose(format!("warning: {}", err));
+    }
+}
+
+fn record_phase(summary: &mut PhaseSummary, phase: &'static str, elapsed: Duration) {
+    match summary.iter_mut().find(|(name, _, _)| *name == phase) {
+        Some(entry) => {
+            entry.1 += elapsed;
+            entry.2 += 1;
+        }
+        None => summary.push((phase, elapsed, 1)),
+    }
+}
+
+pub(crate) fn format_duration(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+fn format_log_line(
+    now: DateTime<Utc>,
+    args: &[String],
+    total: Duration,
+    phases: &[(&'static str, Duration, usize)],
+) -> String {
+    let mut line = format!(
+        "{} cmd=\"{}\" total={}",
+        now.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        args.join(" ").replace('"', "'"),
+        format_duration(total)
+    );
+    for (phase, elapsed, _) in phases {
+        line.push_str(&format!(
+            " {}={}",
+            phase.replace(' ', "_"),
+            format_duration(*elapsed)
+        ));
+    }
+    line
+}
+
+fn append_log_line(line: &str) -> Result<(), CliError> {
+    let path = log_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(CliError::TimingLogIo)?;
+    }
+    if std::fs::metadata(&path)
+        .map(|metadata| metadata.len() > LOG_MAX_BYTES)
+        .unwrap_or(false)
+    {
+        std::fs::rename(&path, path.with_extension("log.1")).map_err(CliError::TimingLogIo)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .map_err(CliError::TimingLogIo)?;
+    writeln!(file, "{}", line).map_err(CliError::TimingLogIo)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timing::{format_log_line, record_phase};
+    use chrono::{TimeZone, Utc};
+    use std::time::Duration;
+
+    #[test]
+    fn phases_are_summed_per_name_in_first_seen_order() {
+        let mut summary = Vec::new();
+        record_phase(&mut summary, "preset load", Duration::from_millis(2));
+        record_phase(&mut summary, "nix generation", Duration::from_millis(5));
+        for _ in 0..3 {
+            record_phase(&mut summary, "preset load", Duration::from_millis(1));
+        }
+        assert_eq!(
+            summary,
+            vec![
+                ("preset load", Duration::from_millis(5), 4),
+                ("nix generation", Duration::from_millis(5), 1),
+            ]
+        );
+
+        let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        let line = format_log_line(
+            now,
+            &["add".to_string(), "ripgrep".to_string()],
+            Duration::from_millis(12),
+            &summary,
+        );
+        assert_eq!(
+            line,
+            "2024-05-01T12:00:00Z cmd=\"add ripgrep\" total=12.0ms preset_load=5.0ms nix_generation=5.0ms"
+        );
+    }
+}
@@ -1,9 +1,19 @@
-use mica_core::config::SearchMode;
-use mica_core::state::{Pin, PinnedPackage};
+use mica_core::config::{FilterChip, SearchMode};
+use mica_core::merge::{MergeChoice, MergeSection};
+use mica_core::state::{BuildEnvState, Pin, PinnedPackage, ProjectState};
+use mica_index::generate::BinaryCollision;
 use ratatui::widgets::{ListState, TableState};
-use std::collections::{BTreeMap, BTreeSet};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
 use std::time::{Duration, Instant};
 
+const TOAST_DURATION: Duration = Duration::from_secs(3);
+const MAX_PENDING_NOTIFICATIONS: usize = 4;
+const MAX_FINISHED_JOBS: usize = 8;
+
 #[derive(Debug, Clone)]
 pub struct PackageEntry {
     pub attr_path: String,
@@ -17,18 +27,32 @@ pub struct PackageEntry {
     pub position: Option<String>,
     pub broken: bool,
     pub insecure: bool,
+    pub maintainers: Option<String>,
+    pub last_updated: Option<String>,
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub struct PackageFilters {
     pub show_broken: bool,
     pub show_insecure: bool,
     pub license: String,
     pub platform: String,
     pub show_installed_only: bool,
+    pub pin: Option<String>,
 }
 
 impl PackageFilters {
+    pub fn from_chip(chip: &FilterChip) -> Self {
+        PackageFilters {
+            show_broken: chip.broken,
+            show_insecure: chip.insecure,
+            license: chip.license.clone(),
+            platform: chip.platform.clone(),
+            show_installed_only: chip.installed_only,
+            pin: chip.pin.clone(),
+        }
+    }
+
     pub fn matches(&self, pkg: &PackageEntry) -> bool {
         if !self.show_broken && pkg.broken {
             return false;
@@ -68,6 +92,7 @@ pub struct IndexInfo {
     pub count: Option<usize>,
     pub generated_at: Option<String>,
     pub displayed_count: Option<usize>,
+    pub fallback: bool,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -77,6 +102,66 @@ pub enum Focus {
     Changes,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeSection {
+    Packages,
+    Templates,
+    Pinned,
+    Env,
+    BuildEnv,
+    ShellHook,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+impl ChangeKind {
+    pub fn prefix(self) -> &'static str {
+        match self {
+            ChangeKind::Added => "+",
+            ChangeKind::Removed => "-",
+            ChangeKind::Changed => "~",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PendingChange {
+    pub section: ChangeSection,
+    pub kind: ChangeKind,
+    pub target: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeAction {
+    JumpTo,
+    Unstage,
+    PickVersion,
+    Edit,
+}
+
+impl ChangeAction {
+    pub fn label(self) -> &'static str {
+        match self {
+            ChangeAction::JumpTo => "Jump to entry",
+            ChangeAction::Unstage => "Unstage change",
+            ChangeAction::PickVersion => "Pick a version",
+            ChangeAction::Edit => "Open editor",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChangeActionsState {
+    pub change: PendingChange,
+    pub actions: Vec<ChangeAction>,
+    pub cursor: usize,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ColumnKind {
     Version,
@@ -155,6 +240,13 @@ pub struct FilterEditorState {
     pub cursor: usize,
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct GoToState {
+    pub input: String,
+    pub cursor: usize,
+    pub missing: Option<String>,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum EnvEditMode {
     List,
@@ -203,6 +295,64 @@ pub struct ShellEditorState {
     pub original: Vec<String>,
 }
 
+#[derive(Debug, Clone)]
+pub struct OverrideEditorState {
+    pub attr: String,
+    pub editor: ShellEditorState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildEnvField {
+    Name,
+    Outputs,
+    Collisions,
+    Priority,
+}
+
+impl BuildEnvField {
+    pub fn label(self) -> &'static str {
+        match self {
+            BuildEnvField::Name => "Name",
+            BuildEnvField::Outputs => "Extra outputs",
+            BuildEnvField::Collisions => "Collisions",
+            BuildEnvField::Priority => "Priority",
+        }
+    }
+
+    pub fn value(self, settings: &BuildEnvState) -> String {
+        match self {
+            BuildEnvField::Name => settings.name.clone().unwrap_or_default(),
+            BuildEnvField::Outputs => settings.extra_outputs.join(" "),
+            BuildEnvField::Collisions => if settings.ignore_collisions {
+                "ignore"
+            } else {
+                "error"
+            }
+            .to_string(),
+            BuildEnvField::Priority => settings
+                .priority
+                .map(|priority| priority.to_string())
+                .unwrap_or_default(),
+        }
+    }
+}
+
+pub const BUILD_ENV_FIELDS: [BuildEnvField; 4] = [
+    BuildEnvField::Name,
+    BuildEnvField::Outputs,
+    BuildEnvField::Collisions,
+    BuildEnvField::Priority,
+];
+
+#[derive(Debug, Clone)]
+pub struct BuildEnvEditorState {
+    pub cursor: usize,
+    pub editing: bool,
+    pub input: String,
+    pub input_cursor: usize,
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct DiffViewerState {
     pub full_lines: Vec<String>,
@@ -211,6 +361,43 @@ pub struct DiffViewerState {
     pub scroll: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct MissingPackageEntry {
+    pub attr: String,
+    pub candidates: Vec<String>,
+    pub choice: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct MissingPackagesState {
+    pub rev: String,
+    pub entries: Vec<MissingPackageEntry>,
+    pub cursor: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct StorePathsState {
+    pub rev: String,
+    pub entries: Vec<crate::nixops::storepath::StorePathEntry>,
+    pub cursor: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeEntry {
+    pub section: MergeSection,
+    pub ours_changed: bool,
+    pub theirs_changed: bool,
+    pub choice: MergeChoice,
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeState {
+    pub entries: Vec<MergeEntry>,
+    pub cursor: usize,
+    pub ours: Box<ProjectState>,
+    pub theirs: Box<ProjectState>,
+}
+
 #[derive(Debug, Clone)]
 pub struct PackageInfoState {
     pub lines: Vec<String>,
@@ -234,6 +421,21 @@ pub struct VersionPickerState {
     pub package: String,
 }
 
+#[derive(Debug, Clone)]
+pub struct CommitPickerEntry {
+    pub rev: String,
+    pub date: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CommitPickerState {
+    pub entries: Vec<CommitPickerEntry>,
+    pub cursor: usize,
+    pub branch: String,
+    pub current: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct ColumnsEditorState {
     pub cursor: usize,
@@ -309,6 +511,7 @@ impl PinEditorState {
 #[derive(Debug, Clone, Copy)]
 pub enum ToastLevel {
     Info,
+    Warning,
     Error,
 }
 
@@ -319,17 +522,248 @@ pub struct Toast {
     pub expires_at: Instant,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    IndexRefresh,
+    WarmCache,
+    Prefetch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobStatus {
+    Running,
+    Done(Option<String>),
+    Failed(String),
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobProgress {
+    pub done: u64,
+    pub total: Option<u64>,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    pub id: u64,
+    pub kind: JobKind,
+    pub label: String,
+    pub status: JobStatus,
+    pub progress: Option<JobProgress>,
+    pub started: Instant,
+    cancel: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+#[derive(Debug)]
+enum JobEvent {
+    Progress(u64, JobProgress),
+    Finished(u64, Result<Option<String>, String>),
+}
+
+#[derive(Debug, Clone)]
+pub struct JobHandle {
+    id: u64,
+    sender: Sender<JobEvent>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl JobHandle {
+    pub fn progress(&self, done: u64, total: Option<u64>) {
+        let _ = self
+            .sender
+            .send(JobEvent::Progress(self.id, JobProgress { done, total }));
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinishedJob {
+    pub id: u64,
+    pub kind: JobKind,
+    pub label: String,
+    pub status: JobStatus,
+}
+
+#[derive(Debug)]
+pub struct Jobs {
+    pub entries: Vec<Job>,
+    pub cursor: usize,
+    next_id: u64,
+    sender: Sender<JobEvent>,
+    receiver: Receiver<JobEvent>,
+}
+
+impl Default for Jobs {
+    fn default() -> Self {
+        let (sender, receiver) = channel();
+        Jobs {
+            entries: Vec::new(),
+            cursor: 0,
+            next_id: 1,
+            sender,
+            receiver,
+        }
+    }
+}
+
+impl Jobs {
+    pub fn spawn<F>(&mut self, kind: JobKind, label: impl Into<String>, work: F) -> u64
+    where
+        F: FnOnce(&JobHandle) -> Result<Option<String>, String> + Send + 'static,
+    {
+        let id = self.next_id;
+        self.next_id += 1;
+        let cancel = Arc::new(AtomicBool::new(false));
+        let handle = JobHandle {
+            id,
+            sender: self.sender.clone(),
+            cancel: cancel.clone(),
+        };
+        self.entries.push(Job {
+            id,
+            kind,
+            label: label.into(),
+            status: JobStatus::Running,
+            progress: None,
+            started: Instant::now(),
+            cancel,
+            thread: None,
+        });
+        let thread = std::thread::spawn(move || {
+            let result = work(&handle);
+            let _ = handle.sender.send(JobEvent::Finished(handle.id, result));
+        });
+        if let Some(job) = self.entries.last_mut() {
+            job.thread = Some(thread);
+        }
+        id
+    }
+
+    pub fn is_running(&self, kind: JobKind) -> bool {
+        self.entries
+            .iter()
+            .any(|job| job.kind == kind && job.status == JobStatus::Running)
+    }
+
+    pub fn is_job_running(&self, id: u64) -> bool {
+        self.entries
+            .iter()
+            .any(|job| job.id == id && job.status == JobStatus::Running)
+    }
+
+    pub fn running(&self) -> impl Iterator<Item = &Job> {
+        self.entries
+            .iter()
+            .filter(|job| job.status == JobStatus::Running)
+    }
+
+    pub fn cancel(&mut self, id: u64) -> bool {
+        match self
+            .entries
+            .iter_mut()
+            .find(|job| job.id == id && job.status == JobStatus::Running)
+        {
+            Some(job) => {
+                job.cancel.store(true, Ordering::Relaxed);
+                job.status = JobStatus::Cancelled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Cancels a running job and blocks until its worker returns, so any
+    /// process it started is gone before mica exits.
+    pub fn cancel_and_wait(&mut self, id: u64) -> bool {
+        let cancelled = self.cancel(id);
+        let thread = self
+            .entries
+            .iter_mut()
+            .find(|job| job.id == id)
+            .and_then(|job| job.thread.take());
+        if let Some(thread) = thread {
+            let _ = thread.join();
+        }
+        cancelled
+    }
+
+    pub fn poll(&mut self) -> Vec<FinishedJob> {
+        let mut finished = Vec::new();
+        while let Ok(event) = self.receiver.try_recv() {
+            match event {
+                JobEvent::Progress(id, progress) => {
+                    if let Some(job) = self.entries.iter_mut().find(|job| job.id == id) {
+                        job.progress = Some(progress);
+                    }
+                }
+                JobEvent::Finished(id, result) => {
+                    let Some(job) = self.entries.iter_mut().find(|job| job.id == id) else {
+                        continue;
+                    };
+                    if job.status != JobStatus::Running {
+                        continue;
+                    }
+                    job.status = match result {
+                        Ok(message) => JobStatus::Done(message),
+                        Err(err) => JobStatus::Failed(err),
+                    };
+                    finished.push(FinishedJob {
+                        id: job.id,
+                        kind: job.kind,
+                        label: job.label.clone(),
+                        status: job.status.clone(),
+                    });
+                }
+            }
+        }
+        self.prune();
+        finished
+    }
+
+    fn prune(&mut self) {
+        let finished = self
+            .entries
+            .iter()
+            .filter(|job| job.status != JobStatus::Running)
+            .count();
+        let mut excess = finished.saturating_sub(MAX_FINISHED_JOBS);
+        self.entries.retain(|job| {
+            if excess > 0 && job.status != JobStatus::Running {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+        if self.cursor >= self.entries.len() {
+            self.cursor = self.entries.len().saturating_sub(1);
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Overlay {
     Help,
+    Jobs,
+    CommitPicker(CommitPickerState),
     PackageInfo(PackageInfoState),
     VersionPicker(VersionPickerState),
     PinEditor(PinEditorState),
     Columns(ColumnsEditorState),
     Env(EnvEditorState),
     Shell(ShellEditorState),
+    Override(OverrideEditorState),
+    BuildEnv(BuildEnvEditorState),
     Filter(FilterEditorState),
+    GoTo(GoToState),
     Diff(DiffViewerState),
+    MissingPackages(MissingPackagesState),
+    Merge(MergeState),
+    StorePaths(StorePathsState),
+    ChangeActions(ChangeActionsState),
 }
 
 #[derive(Debug)]
@@ -346,8 +780,11 @@ pub struct App {
     pub preset_filtered: Vec<usize>,
     pub presets_collapsed: bool,
     pub changes_collapsed: bool,
+    pub changes_cursor: usize,
     pub columns: ColumnSettings,
     pub show_details: bool,
+    pub details_expanded: bool,
+    pub details_scroll: usize,
     pub pinned: BTreeMap<String, PinnedPackage>,
     pub base_pinned: BTreeMap<String, PinnedPackage>,
     pub pin_map: BTreeMap<String, Pin>,
@@ -357,18 +794,30 @@ pub struct App {
     pub preset_packages: BTreeSet<String>,
     pub env: BTreeMap<String, String>,
     pub shell_hook: Option<String>,
+    pub build_env: BuildEnvState,
+    pub priorities: BTreeMap<String, i32>,
+    pub overrides: BTreeMap<String, String>,
+    pub collisions: Vec<BinaryCollision>,
+    pub collisions_for: Vec<String>,
     pub base_added: BTreeSet<String>,
     pub base_removed: BTreeSet<String>,
     pub base_presets: BTreeSet<String>,
     pub base_env: BTreeMap<String, String>,
     pub base_shell_hook: Option<String>,
+    pub base_build_env: BuildEnvState,
+    pub base_priorities: BTreeMap<String, i32>,
+    pub base_overrides: BTreeMap<String, String>,
     pub filters: PackageFilters,
+    pub filter_chips: Vec<FilterChip>,
     pub search_mode: SearchMode,
     pub packages_state: TableState,
     pub presets_state: ListState,
     pub overlay: Option<Overlay>,
     pub index_info: IndexInfo,
     pub toast: Option<Toast>,
+    pub notifications: VecDeque<Toast>,
+    pub jobs: Jobs,
+    pub pending_pins: BTreeMap<u64, (String, VersionPickerEntry)>,
     pub dirty: bool,
     pub should_quit: bool,
 }
@@ -388,8 +837,11 @@ impl App {
             preset_filtered: Vec::new(),
             presets_collapsed: true,
             changes_collapsed: false,
+            changes_cursor: 0,
             columns: ColumnSettings::default(),
             show_details: true,
+            details_expanded: false,
+            details_scroll: 0,
             pinned: BTreeMap::new(),
             base_pinned: BTreeMap::new(),
             pin_map: BTreeMap::new(),
@@ -399,18 +851,30 @@ impl App {
             preset_packages: BTreeSet::new(),
             env: BTreeMap::new(),
             shell_hook: None,
+            build_env: BuildEnvState::default(),
+            priorities: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+            collisions: Vec::new(),
+            collisions_for: Vec::new(),
             base_added: BTreeSet::new(),
             base_removed: BTreeSet::new(),
             base_presets: BTreeSet::new(),
             base_env: BTreeMap::new(),
             base_shell_hook: None,
+            base_build_env: BuildEnvState::default(),
+            base_priorities: BTreeMap::new(),
+            base_overrides: BTreeMap::new(),
             filters: PackageFilters::default(),
+            filter_chips: Vec::new(),
             search_mode: SearchMode::All,
             packages_state: TableState::new(),
             presets_state: ListState::default(),
             overlay: None,
             index_info: IndexInfo::default(),
             toast: None,
+            notifications: VecDeque::new(),
+            jobs: Jobs::default(),
+            pending_pins: BTreeMap::new(),
             dirty: false,
             should_quit: false,
         };
@@ -422,6 +886,10 @@ impl App {
     }
 
     pub fn effective_package_count(&self) -> usize {
+        self.selected_packages().len()
+    }
+
+    pub fn selected_packages(&self) -> BTreeSet<String> {
         let mut packages = self.preset_packages.clone();
         for pkg in &self.added {
             packages.insert(pkg.clone());
@@ -432,7 +900,7 @@ impl App {
         for pkg in self.pinned.keys() {
             packages.insert(pkg.clone());
         }
-        packages.len()
+        packages
     }
 
     pub fn cycle_search_mode(&mut self) {
@@ -444,6 +912,18 @@ impl App {
         };
     }
 
+    pub fn cycle_pin_filter(&mut self) {
+        let labels: Vec<&String> = self.pin_map.keys().collect();
+        self.filters.pin = match &self.filters.pin {
+            None => labels.first().map(|label| label.to_string()),
+            Some(current) => labels
+                .iter()
+                .position(|label| *label == current)
+                .and_then(|idx| labels.get(idx + 1))
+                .map(|label| label.to_string()),
+        };
+    }
+
     pub fn search_mode_label(&self) -> &'static str {
         match self.search_mode {
             SearchMode::All => "all",
@@ -510,6 +990,7 @@ impl App {
                 }
                 self.cursor = (self.cursor + 1).min(self.packages.len() - 1);
                 self.packages_state.select(Some(self.cursor));
+                self.details_scroll = 0;
             }
             Focus::Presets => {
                 if self.preset_filtered.is_empty() {
@@ -520,7 +1001,10 @@ impl App {
                 self.preset_cursor = (self.preset_cursor + 1).min(self.preset_filtered.len() - 1);
                 self.presets_state.select(Some(self.preset_cursor));
             }
-            Focus::Changes => {}
+            Focus::Changes => {
+                let count = self.pending_changes().len();
+                self.changes_cursor = (self.changes_cursor + 1).min(count.saturating_sub(1));
+            }
         }
     }
 
@@ -537,6 +1021,7 @@ impl App {
                 }
                 self.cursor -= 1;
                 self.packages_state.select(Some(self.cursor));
+                self.details_scroll = 0;
             }
             Focus::Presets => {
                 if self.preset_filtered.is_empty() {
@@ -550,7 +1035,9 @@ impl App {
                 self.preset_cursor -= 1;
                 self.presets_state.select(Some(self.preset_cursor));
             }
-            Focus::Changes => {}
+            Focus::Changes => {
+                self.changes_cursor = self.changes_cursor.saturating_sub(1);
+            }
         }
     }
 
@@ -570,6 +1057,178 @@ impl App {
         };
     }
 
+    pub fn pending_changes(&self) -> Vec<PendingChange> {
+        let change = |section, kind, target: &String| PendingChange {
+            section,
+            kind,
+            target: target.clone(),
+        };
+        let mut changes = Vec::new();
+        for attr in self.added.difference(&self.base_added) {
+            changes.push(change(ChangeSection::Packages, ChangeKind::Added, attr));
+        }
+        let dropped = self
+            .base_added
+            .difference(&self.added)
+            .filter(|attr| !self.pinned.contains_key(*attr));
+        let excluded = self.removed.difference(&self.base_removed);
+        let mut removed: Vec<&String> = dropped.chain(excluded).collect();
+        removed.sort();
+        removed.dedup();
+        for attr in removed {
+            changes.push(change(ChangeSection::Packages, ChangeKind::Removed, attr));
+        }
+        for name in self.active_presets.difference(&self.base_presets) {
+            changes.push(change(ChangeSection::Templates, ChangeKind::Added, name));
+        }
+        for name in self.base_presets.difference(&self.active_presets) {
+            changes.push(change(ChangeSection::Templates, ChangeKind::Removed, name));
+        }
+        for (name, pinned) in &self.pinned {
+            match self.base_pinned.get(name) {
+                None => changes.push(change(ChangeSection::Pinned, ChangeKind::Added, name)),
+                Some(existing) if existing != pinned => {
+                    changes.push(change(ChangeSection::Pinned, ChangeKind::Changed, name))
+                }
+                _ => {}
+            }
+        }
+        for name in self.base_pinned.keys() {
+            if !self.pinned.contains_key(name) {
+                changes.push(change(ChangeSection::Pinned, ChangeKind::Removed, name));
+            }
+        }
+        for (key, value) in &self.env {
+            match self.base_env.get(key) {
+                None => changes.push(change(ChangeSection::Env, ChangeKind::Added, key)),
+                Some(existing) if existing != value => {
+                    changes.push(change(ChangeSection::Env, ChangeKind::Changed, key))
+                }
+                _ => {}
+            }
+        }
+        for key in self.base_env.keys() {
+            if !self.env.contains_key(key) {
+                changes.push(change(ChangeSection::Env, ChangeKind::Removed, key));
+            }
+        }
+        if self.build_env != self.base_build_env {
+            changes.push(change(
+                ChangeSection::BuildEnv,
+                ChangeKind::Changed,
+                &String::new(),
+            ));
+        }
+        if self.shell_hook != self.base_shell_hook {
+            changes.push(change(
+                ChangeSection::ShellHook,
+                ChangeKind::Changed,
+                &String::new(),
+            ));
+        }
+        changes
+    }
+
+    pub fn current_change(&self) -> Option<PendingChange> {
+        self.pending_changes().into_iter().nth(self.changes_cursor)
+    }
+
+    pub fn change_actions(&self, change: &PendingChange) -> Vec<ChangeAction> {
+        match (change.section, change.kind) {
+            (ChangeSection::Packages, ChangeKind::Added)
+            | (ChangeSection::Pinned, ChangeKind::Added | ChangeKind::Changed) => vec![
+                ChangeAction::JumpTo,
+                ChangeAction::PickVersion,
+                ChangeAction::Unstage,
+            ],
+            (ChangeSection::Packages | ChangeSection::Pinned | ChangeSection::Templates, _) => {
+                vec![ChangeAction::JumpTo, ChangeAction::Unstage]
+            }
+            (ChangeSection::Env | ChangeSection::BuildEnv | ChangeSection::ShellHook, _) => {
+                vec![ChangeAction::Edit, ChangeAction::Unstage]
+            }
+        }
+    }
+
+    pub fn affected_section(&self, change: &PendingChange) -> String {
+        if self.mode == AppMode::Global {
+            let block = match change.section {
+                ChangeSection::Pinned => "mica:pins, mica:paths",
+                _ => "mica:paths",
+            };
+            return format!("profile.nix ({})", block);
+        }
+        let block = match change.section {
+            ChangeSection::Packages
+                if change.kind == ChangeKind::Removed
+                    && self.preset_packages.contains(&change.target) =>
+            {
+                "mica:exclude"
+            }
+            ChangeSection::Packages | ChangeSection::Templates => "mica:packages",
+            ChangeSection::Pinned => "mica:pins, mica:packages",
+            ChangeSection::Env => "mica:env",
+            ChangeSection::BuildEnv => "mica:buildenv",
+            ChangeSection::ShellHook => "mica:shellhook",
+        };
+        format!("default.nix ({})", block)
+    }
+
+    pub fn unstage(&mut self, change: &PendingChange) {
+        let target = &change.target;
+        match change.section {
+            ChangeSection::Packages | ChangeSection::Pinned => {
+                restore_member(&mut self.added, &self.base_added, target);
+                restore_member(&mut self.removed, &self.base_removed, target);
+                restore_entry(&mut self.pinned, &self.base_pinned, target);
+                restore_entry(&mut self.priorities, &self.base_priorities, target);
+                restore_entry(&mut self.overrides, &self.base_overrides, target);
+            }
+            ChangeSection::Templates => {
+                restore_member(&mut self.active_presets, &self.base_presets, target);
+                self.rebuild_preset_packages();
+            }
+            ChangeSection::Env => restore_entry(&mut self.env, &self.base_env, target),
+            ChangeSection::BuildEnv => self.build_env = self.base_build_env.clone(),
+            ChangeSection::ShellHook => self.shell_hook = self.base_shell_hook.clone(),
+        }
+        self.update_dirty();
+        let count = self.pending_changes().len();
+        self.changes_cursor = self.changes_cursor.min(count.saturating_sub(1));
+    }
+
+    pub fn jump_to_preset(&mut self, name: &str) {
+        self.preset_query.clear();
+        self.refresh_preset_filter();
+        let Some(position) = self
+            .preset_filtered
+            .iter()
+            .position(|index| self.presets.get(*index).is_some_and(|p| p.name == name))
+        else {
+            return;
+        };
+        self.presets_collapsed = false;
+        self.focus = Focus::Presets;
+        self.preset_cursor = position;
+        self.presets_state.select(Some(position));
+    }
+
+    pub fn active_filter_chip(&self) -> Option<usize> {
+        self.filter_chips
+            .iter()
+            .position(|chip| PackageFilters::from_chip(chip) == self.filters)
+    }
+
+    pub fn select_filter_chip(&mut self, index: usize) -> Option<String> {
+        let chip = self.filter_chips.get(index)?;
+        if self.active_filter_chip() == Some(index) {
+            self.filters = PackageFilters::default();
+        } else {
+            self.filters = PackageFilters::from_chip(chip);
+        }
+        Some(chip.name.clone())
+    }
+
     pub fn rebuild_preset_packages(&mut self) {
         self.preset_packages.clear();
         for preset in &self.presets {
@@ -581,6 +1240,39 @@ impl App {
         }
     }
 
+    pub fn presets_providing(&self, attr: &str) -> Vec<String> {
+        self.presets
+            .iter()
+            .filter(|preset| self.active_presets.contains(&preset.name))
+            .filter(|preset| preset.packages_required.iter().any(|pkg| pkg == attr))
+            .map(|preset| preset.name.clone())
+            .collect()
+    }
+
+    pub fn add_current_package(&mut self) {
+        let Some(entry) = self.packages.get(self.cursor) else {
+            return;
+        };
+        let attr = entry.attr_path.clone();
+        self.add_attr(&attr);
+    }
+
+    pub fn add_attr(&mut self, attr: &str) {
+        if self.pin_for_attr(attr).is_some() {
+            return;
+        }
+        let base = self.base_attr_for(attr);
+        if self.pinned.contains_key(&base) {
+            return;
+        }
+        if self.preset_packages.contains(&base) {
+            self.removed.remove(&base);
+        } else {
+            self.added.insert(base);
+        }
+        self.update_dirty();
+    }
+
     fn toggle_current_package(&mut self) {
         if let Some(entry) = self.packages.get(self.cursor) {
             if let Some((base, pin)) = self.pin_for_attr(&entry.attr_path) {
@@ -623,6 +1315,23 @@ impl App {
         }
     }
 
+    pub fn drop_package(&mut self, attr: &str) {
+        self.added.remove(attr);
+        if self.preset_packages.contains(attr) {
+            self.removed.insert(attr.to_string());
+        }
+        self.update_dirty();
+    }
+
+    pub fn replace_package(&mut self, attr: &str, replacement: &str) {
+        self.drop_package(attr);
+        self.removed.remove(replacement);
+        if !self.preset_packages.contains(replacement) {
+            self.added.insert(replacement.to_string());
+        }
+        self.update_dirty();
+    }
+
     fn toggle_current_preset(&mut self) {
         if let Some(index) = self.preset_filtered.get(self.preset_cursor).copied() {
             if let Some(entry) = self.presets.get(index) {
@@ -643,6 +1352,9 @@ impl App {
         self.base_presets = self.active_presets.clone();
         self.base_env = self.env.clone();
         self.base_shell_hook = self.shell_hook.clone();
+        self.base_build_env = self.build_env.clone();
+        self.base_priorities = self.priorities.clone();
+        self.base_overrides = self.overrides.clone();
         self.base_pinned = self.pinned.clone();
         self.dirty = false;
     }
@@ -653,6 +1365,9 @@ impl App {
             || self.active_presets != self.base_presets
             || self.env != self.base_env
             || self.shell_hook != self.base_shell_hook
+            || self.build_env != self.base_build_env
+            || self.priorities != self.base_priorities
+            || self.overrides != self.base_overrides
             || self.pinned != self.base_pinned;
     }
 
@@ -660,7 +1375,31 @@ impl App {
         self.toast = Some(Toast {
             message: message.into(),
             level,
-            expires_at: Instant::now() + Duration::from_secs(3),
+            expires_at: Instant::now() + TOAST_DURATION,
+        });
+    }
+
+    pub fn notify(&mut self, level: ToastLevel, message: impl Into<String>) {
+        let message = message.into();
+        if self.toast.is_none() {
+            self.push_toast(level, message);
+            return;
+        }
+        let duplicate = self
+            .toast
+            .iter()
+            .chain(self.notifications.iter())
+            .any(|toast| toast.message == message);
+        if duplicate {
+            return;
+        }
+        if self.notifications.len() == MAX_PENDING_NOTIFICATIONS {
+            self.notifications.pop_front();
+        }
+        self.notifications.push_back(Toast {
+            message,
+            level,
+            expires_at: Instant::now(),
         });
     }
 
@@ -672,6 +1411,11 @@ impl App {
         if expired {
             self.toast = None;
         }
+        if self.toast.is_none() {
+            if let Some(next) = self.notifications.pop_front() {
+                self.push_toast(next.level, next.message);
+            }
+        }
     }
 
     pub fn refresh_preset_filter(&mut self) {
@@ -709,3 +1453,26 @@ impl App {
 fn contains_case_insensitive(haystack: &str, needle: &str) -> bool {
     haystack.to_lowercase().contains(&needle.to_lowercase())
 }
+
+fn restore_member(current: &mut BTreeSet<String>, base: &BTreeSet<String>, item: &str) {
+    if base.contains(item) {
+        current.insert(item.to_string());
+    } else {
+        current.remove(item);
+    }
+}
+
+fn restore_entry<V: Clone>(
+    current: &mut BTreeMap<String, V>,
+    base: &BTreeMap<String, V>,
+    key: &str,
+) {
+    match base.get(key) {
+        Some(value) => {
+            current.insert(key.to_string(), value.clone());
+        }
+        None => {
+            current.remove(key);
+        }
+    }
+}
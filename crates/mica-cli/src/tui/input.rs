@@ -6,6 +6,7 @@ pub enum InputAction {
     Quit,
     Save,
     Toggle,
+    AddAndPin,
     ToggleFocus,
     Next,
     Prev,
@@ -16,11 +17,19 @@ pub enum InputAction {
     OpenVersionPicker,
     OpenEnv,
     OpenShell,
+    OpenBuildEnv,
+    PreferPackage,
+    EditOverride,
+    TryPackage,
     ToggleBroken,
     ToggleInsecure,
     ToggleInstalled,
+    CyclePinFilter,
     ToggleSearchMode,
     ToggleDetails,
+    ExpandDetails,
+    ScrollDetailsUp,
+    ScrollDetailsDown,
     EditLicenseFilter,
     EditPlatformFilter,
     PreviewDiff,
@@ -30,7 +39,12 @@ pub enum InputAction {
     ToggleChanges,
     OpenColumns,
     RebuildIndex,
+    OpenJobs,
+    OpenCommitPicker,
+    GoTo,
+    PreviewStorePaths,
     Sync,
+    FilterChip(usize),
     Insert(char),
 }
 
@@ -53,29 +67,53 @@ pub fn map_key(event: KeyEvent) -> InputAction {
         }
         KeyCode::Char('E') => InputAction::OpenEnv,
         KeyCode::Char('H') => InputAction::OpenShell,
+        KeyCode::Char('N') => InputAction::OpenBuildEnv,
+        KeyCode::Char('P') => InputAction::PreferPackage,
+        KeyCode::Char('A') => InputAction::EditOverride,
         KeyCode::Char('B') => InputAction::ToggleBroken,
         KeyCode::Char('I') => InputAction::ToggleInsecure,
         KeyCode::Char('V') => InputAction::ToggleInstalled,
+        KeyCode::Char('F') => InputAction::CyclePinFilter,
         KeyCode::Char('S') => InputAction::ToggleSearchMode,
         KeyCode::Char('K') => InputAction::ToggleDetails,
+        KeyCode::Char('X') => InputAction::ExpandDetails,
+        KeyCode::PageUp => InputAction::ScrollDetailsUp,
+        KeyCode::PageDown => InputAction::ScrollDetailsDown,
         KeyCode::Char('L') => InputAction::EditLicenseFilter,
         KeyCode::Char('O') => InputAction::EditPlatformFilter,
         KeyCode::Char('D') => InputAction::PreviewDiff,
         KeyCode::Char('U') => InputAction::UpdatePin,
+        KeyCode::Char('t') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+            InputAction::TryPackage
+        }
         KeyCode::Char('n') if event.modifiers.contains(KeyModifiers::CONTROL) => {
             InputAction::AddPin
         }
+        KeyCode::Char('g') if event.modifiers.contains(KeyModifiers::CONTROL) => InputAction::GoTo,
         KeyCode::Char('T') => InputAction::TogglePresets,
         KeyCode::Char('C') => InputAction::ToggleChanges,
         KeyCode::Char('M') => InputAction::OpenColumns,
         KeyCode::Char('R') => InputAction::RebuildIndex,
+        KeyCode::Char('J') => InputAction::OpenJobs,
+        KeyCode::Char('G') => InputAction::OpenCommitPicker,
         KeyCode::Char('Y') => InputAction::Sync,
+        KeyCode::Char('W') => InputAction::PreviewStorePaths,
+        KeyCode::Enter
+            if event
+                .modifiers
+                .intersects(KeyModifiers::SHIFT | KeyModifiers::ALT) =>
+        {
+            InputAction::AddAndPin
+        }
         KeyCode::Enter => InputAction::Toggle,
         KeyCode::Char(' ') => InputAction::Toggle,
         KeyCode::Tab => InputAction::ToggleFocus,
         KeyCode::Backspace => InputAction::Backspace,
         KeyCode::Char('u') if event.modifiers.contains(KeyModifiers::CONTROL) => InputAction::Clear,
-        KeyCode::Char(ch) if event.modifiers.contains(KeyModifiers::CONTROL) => InputAction::None,
+        KeyCode::Char(_) if event.modifiers.contains(KeyModifiers::CONTROL) => InputAction::None,
+        KeyCode::Char(ch @ '0'..='9') if event.modifiers.contains(KeyModifiers::ALT) => {
+            InputAction::FilterChip(ch as usize - '0' as usize)
+        }
         KeyCode::Char(ch) => InputAction::Insert(ch),
         _ => InputAction::None,
     }
@@ -1,3 +1,4 @@
 pub mod app;
 pub mod input;
+pub mod snapshot;
 pub mod ui;
@@ -0,0 +1,271 @@
+use crate::tui::app::{
+    App, BuildEnvEditorState, DiffViewerState, FilterEditorState, FilterKind, Focus, IndexInfo,
+    MissingPackageEntry, MissingPackagesState, Overlay, PackageEntry, PresetEntry,
+};
+use crate::tui::ui::render;
+use mica_core::config::FilterChip;
+use mica_core::diff::{diff_lines, diff_lines_changes_only};
+use ratatui::backend::TestBackend;
+use ratatui::buffer::Buffer;
+use ratatui::Terminal;
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Screen {
+    Main,
+    Help,
+    Diff,
+    BuildEnv,
+    Filter,
+    Missing,
+    Chips,
+    Small,
+}
+
+pub const SCREENS: [Screen; 8] = [
+    Screen::Main,
+    Screen::Help,
+    Screen::Diff,
+    Screen::BuildEnv,
+    Screen::Filter,
+    Screen::Missing,
+    Screen::Chips,
+    Screen::Small,
+];
+
+impl Screen {
+    pub fn name(self) -> &'static str {
+        match self {
+            Screen::Main => "main",
+            Screen::Help => "help",
+            Screen::Diff => "diff",
+            Screen::BuildEnv => "build-env",
+            Screen::Filter => "filter",
+            Screen::Missing => "missing",
+            Screen::Chips => "chips",
+            Screen::Small => "small",
+        }
+    }
+
+    pub fn size(self) -> (u16, u16) {
+        match self {
+            Screen::Small => (60, 16),
+            _ => (120, 36),
+        }
+    }
+
+    pub fn app(self) -> App {
+        let mut app = sample_app();
+        app.overlay = match self {
+            Screen::Main | Screen::Chips | Screen::Small => None,
+            Screen::Help => Some(Overlay::Help),
+            Screen::Diff => Some(Overlay::Diff(sample_diff())),
+            Screen::BuildEnv => Some(Overlay::BuildEnv(BuildEnvEditorState {
+                cursor: 0,
+                editing: false,
+                input: String::new(),
+                input_cursor: 0,
+                error: None,
+            })),
+            Screen::Filter => Some(Overlay::Filter(FilterEditorState {
+                kind: FilterKind::License,
+                input: "mit".to_string(),
+                cursor: 3,
+            })),
+            Screen::Missing => Some(Overlay::MissingPackages(MissingPackagesState {
+                rev: app.index_info.rev.clone(),
+                entries: vec![
+                    MissingPackageEntry {
+                        attr: "nodejs_16".to_string(),
+                        candidates: vec!["nodejs_20".to_string(), "nodejs_22".to_string()],
+                        choice: 0,
+                    },
+                    MissingPackageEntry {
+                        attr: "exa".to_string(),
+                        candidates: Vec::new(),
+                        choice: 0,
+                    },
+                ],
+                cursor: 0,
+            })),
+        };
+        if self == Screen::Chips {
+            app.filter_chips = vec![
+                FilterChip {
+                    name: "darwin-safe".to_string(),
+                    platform: "aarch64-darwin".to_string(),
+                    ..FilterChip::default()
+                },
+                FilterChip {
+                    name: "free".to_string(),
+                    license: "mit".to_string(),
+                    ..FilterChip::default()
+                },
+            ];
+            app.select_filter_chip(0);
+        }
+        app
+    }
+}
+
+pub fn render_screen(screen: Screen, width: u16, height: u16) -> std::io::Result<String> {
+    let mut app = screen.app();
+    render_to_string(&mut app, width, height)
+}
+
+pub fn render_to_string(app: &mut App, width: u16, height: u16) -> std::io::Result<String> {
+    let mut terminal = Terminal::new(TestBackend::new(width, height))?;
+    let frame = terminal.draw(|frame| render(frame, app))?;
+    Ok(buffer_to_string(frame.buffer))
+}
+
+pub fn buffer_to_string(buffer: &Buffer) -> String {
+    let area = buffer.area;
+    let mut output = String::new();
+    for y in area.top()..area.bottom() {
+        let mut line = String::new();
+        for x in area.left()..area.right() {
+            line.push_str(buffer[(x, y)].symbol());
+        }
+        output.push_str(line.trim_end());
+        output.push('\n');
+    }
+    output
+}
+
+pub fn sample_app() -> App {
+    let packages = vec![
+        sample_package("bat", "0.24.0", "Cat clone with syntax highlighting", "bat"),
+        sample_package("fd", "10.2.0", "Simple, fast alternative to find", "fd"),
+        sample_package(
+            "jq",
+            "1.7.1",
+            "Lightweight command-line JSON processor",
+            "jq",
+        ),
+        sample_package("neovim", "0.10.2", "Vim text editor fork", "nvim"),
+        sample_package("ripgrep", "14.1.1", "Recursive line-oriented search", "rg"),
+        PackageEntry {
+            broken: true,
+            ..sample_package("oldtool", "0.1.0", "Unmaintained example tool", "oldtool")
+        },
+    ];
+    let presets = vec![
+        PresetEntry {
+            name: "rust".to_string(),
+            description: "Rust toolchain and cargo helpers".to_string(),
+            order: 10,
+            packages_required: vec!["cargo".to_string(), "rustc".to_string()],
+            packages_optional: vec!["rust-analyzer".to_string()],
+        },
+        PresetEntry {
+            name: "python".to_string(),
+            description: "Python interpreter and tooling".to_string(),
+            order: 20,
+            packages_required: vec!["python3".to_string()],
+            packages_optional: Vec::new(),
+        },
+        PresetEntry {
+            name: "search".to_string(),
+            description: "Fast file and text search".to_string(),
+            order: 30,
+            packages_required: vec!["fd".to_string()],
+            packages_optional: Vec::new(),
+        },
+    ];
+    let mut app = App::new(packages, presets);
+    app.project_dir = Some("/work/example".to_string());
+    app.index_info = IndexInfo {
+        url: "https://github.com/NixOS/nixpkgs".to_string(),
+        rev: "0123456789abcdef0123456789abcdef01234567".to_string(),
+        count: Some(6),
+        generated_at: Some("2026-01-01".to_string()),
+        displayed_count: Some(6),
+        fallback: false,
+    };
+    app.added = BTreeSet::from(["ripgrep".to_string()]);
+    app.active_presets = BTreeSet::from(["rust".to_string(), "search".to_string()]);
+    app.rebuild_preset_packages();
+    app.env.insert("RUST_LOG".to_string(), "info".to_string());
+    app.shell_hook = Some("echo ready".to_string());
+    app.commit_baseline();
+    app.added.insert("jq".to_string());
+    app.update_dirty();
+    app.focus = Focus::Packages;
+    app
+}
+
+fn sample_package(attr: &str, version: &str, description: &str, program: &str) -> PackageEntry {
+    PackageEntry {
+        attr_path: attr.to_string(),
+        name: format!("{}-{}", attr, version),
+        version: Some(version.to_string()),
+        description: Some(description.to_string()),
+        homepage: Some(format!("https://example.org/{}", attr)),
+        license: Some("MIT".to_string()),
+        platforms: Some("x86_64-linux, aarch64-darwin".to_string()),
+        main_program: Some(program.to_string()),
+        position: None,
+        broken: false,
+        insecure: false,
+        maintainers: None,
+        last_updated: None,
+    }
+}
+
+fn sample_diff() -> DiffViewerState {
+    let old = "paths = [\n  pkgs.ripgrep\n];\n";
+    let new = "paths = [\n  pkgs.jq\n  pkgs.ripgrep\n];\n";
+    DiffViewerState {
+        full_lines: diff_lines(old, new),
+        change_lines: diff_lines_changes_only(old, new),
+        show_full: false,
+        scroll: 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tui::snapshot::{render_screen, SCREENS};
+    use std::path::PathBuf;
+
+    fn snapshot_path(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("src/tui/snapshots")
+            .join(format!("{}.txt", name))
+    }
+
+    #[test]
+    fn screens_match_snapshots() {
+        let update = std::env::var_os("MICA_UPDATE_SNAPSHOTS").is_some();
+        let mut mismatched = Vec::new();
+        for screen in SCREENS {
+            let (width, height) = screen.size();
+            let rendered = render_screen(screen, width, height).expect("render failed");
+            let path = snapshot_path(screen.name());
+            if update {
+                std::fs::write(&path, &rendered).expect("write snapshot");
+                continue;
+            }
+            let expected = std::fs::read_to_string(&path).unwrap_or_default();
+            if expected != rendered {
+                eprintln!("snapshot {} differs:\n{}", screen.name(), rendered);
+                mismatched.push(screen.name());
+            }
+        }
+        assert!(
+            mismatched.is_empty(),
+            "snapshots differ: {} (rerun with MICA_UPDATE_SNAPSHOTS=1 to accept)",
+            mismatched.join(", ")
+        );
+    }
+
+    #[test]
+    fn screens_render_in_tiny_terminals() {
+        for screen in SCREENS {
+            for (width, height) in [(1, 1), (20, 6), (40, 10)] {
+                render_screen(screen, width, height).expect("render failed");
+            }
+        }
+    }
+}
@@ -1,6 +1,7 @@
+use crate::indexops::collision_winner;
 use crate::tui::app::{
-    App, EnvEditMode, EnvValueMode, FilterKind, Focus, Overlay, PackageEntry, PinField,
-    PresetEntry, Toast, ToastLevel,
+    App, EnvEditMode, EnvValueMode, FilterKind, Focus, JobStatus, Jobs, Overlay, PackageEntry,
+    PackageFilters, PinField, PresetEntry, Toast, ToastLevel,
 };
 use mica_core::state::NIX_EXPR_PREFIX;
 use ratatui::layout::{Alignment, Constraint, Direction, Layout, Rect};
@@ -59,7 +60,11 @@ fn render_header(frame: &mut Frame, app: &App, area: Rect) {
     } else {
         Style::default().fg(Color::Green)
     };
-    let line_two_left = format!("{} @ {}", index_name, rev);
+    let line_two_left = if app.index_info.fallback {
+        format!("{} @ {} (bundled fallback)", index_name, rev)
+    } else {
+        format!("{} @ {}", index_name, rev)
+    };
     let line_two = header_line_with_right_span(
         &line_two_left,
         Span::styled(dirty.to_string(), dirty_style),
@@ -99,10 +104,28 @@ fn render_status_bar(frame: &mut Frame, app: &App, area: Rect) {
         .clone()
         .unwrap_or_else(|| "unknown".to_string());
     let installed = app.effective_package_count();
-    let status = format!(
+    let mut status = format!(
         "mode: {} | focus: {} | index {} | {} pkgs | installed {} | pulled {}",
         mode, focus, rev, count, installed, generated
     );
+    let running: Vec<_> = app.jobs.running().collect();
+    if let Some(job) = running.first() {
+        let progress = job
+            .progress
+            .and_then(|progress| progress_percent(progress.done, progress.total))
+            .map(|percent| format!(" {}%", percent))
+            .unwrap_or_default();
+        if running.len() == 1 {
+            status.push_str(&format!(" | {}{}", job.label, progress));
+        } else {
+            status.push_str(&format!(
+                " | {} jobs ({}{})",
+                running.len(),
+                job.label,
+                progress
+            ));
+        }
+    }
 
     let bar = Paragraph::new(status)
         .style(Style::default().bg(Color::DarkGray).fg(Color::White))
@@ -145,9 +168,19 @@ fn render_body(frame: &mut Frame, app: &mut App, area: Rect) {
 }
 
 fn render_package_column(frame: &mut Frame, app: &mut App, area: Rect) {
-    let mut constraints = vec![Constraint::Length(3), Constraint::Min(0)];
+    let chip_rows = u16::from(!app.filter_chips.is_empty());
+    let mut constraints = vec![
+        Constraint::Length(3),
+        Constraint::Length(chip_rows),
+        Constraint::Min(0),
+    ];
     if app.show_details {
-        constraints.push(Constraint::Length(7));
+        if app.details_expanded {
+            constraints[2] = Constraint::Min(6);
+            constraints.push(Constraint::Percentage(60));
+        } else {
+            constraints.push(Constraint::Length(7));
+        }
     }
     let layout = Layout::default()
         .direction(Direction::Vertical)
@@ -155,10 +188,37 @@ fn render_package_column(frame: &mut Frame, app: &mut App, area: Rect) {
         .split(area);
 
     render_package_search(frame, app, layout[0]);
-    render_package_table(frame, app, layout[1]);
+    if chip_rows > 0 {
+        render_filter_chips(frame, app, layout[1]);
+    }
+    render_package_table(frame, app, layout[2]);
     if app.show_details {
-        render_package_details(frame, app, layout[2]);
+        render_package_details(frame, app, layout[3]);
+    }
+}
+
+fn render_filter_chips(frame: &mut Frame, app: &App, area: Rect) {
+    let active = app.active_filter_chip();
+    let mut spans = vec![Span::styled("Alt+", Style::default().fg(Color::DarkGray))];
+    for (idx, chip) in app.filter_chips.iter().enumerate().take(9) {
+        let style = if active == Some(idx) {
+            Style::default()
+                .fg(Color::Black)
+                .bg(Color::Cyan)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default().fg(Color::Cyan)
+        };
+        spans.push(Span::raw(" "));
+        spans.push(Span::styled(format!("[{}] {}", idx + 1, chip.name), style));
+    }
+    if app.filters != PackageFilters::default() {
+        spans.push(Span::styled(
+            "  [0] clear",
+            Style::default().fg(Color::DarkGray),
+        ));
     }
+    frame.render_widget(Paragraph::new(Line::from(spans)), area);
 }
 
 fn render_package_search(frame: &mut Frame, app: &App, area: Rect) {
@@ -169,6 +229,9 @@ fn render_package_search(frame: &mut Frame, app: &App, area: Rect) {
     if !app.filters.platform.is_empty() {
         filters.push(format!("platform={}", app.filters.platform));
     }
+    if let Some(pin) = &app.filters.pin {
+        filters.push(format!("pin={}", pin));
+    }
     let filter_summary = if filters.is_empty() {
         String::new()
     } else {
@@ -309,43 +372,133 @@ fn render_package_table(frame: &mut Frame, app: &mut App, area: Rect) {
     frame.render_stateful_widget(table, area, &mut app.packages_state);
 }
 
-fn render_package_details(frame: &mut Frame, app: &App, area: Rect) {
-    let mut lines = Vec::new();
+fn render_package_details(frame: &mut Frame, app: &mut App, area: Rect) {
+    let mut heading = None;
+    let mut fields = Vec::new();
     if let Some(pkg) = app.current_package() {
         let title = pkg.name.clone();
         let version = pkg.version.clone().unwrap_or_else(|| "unknown".to_string());
-        lines.push(Line::from(Span::styled(
-            format!("{} ({})", title, version),
-            Style::default().add_modifier(Modifier::BOLD),
-        )));
+        heading = Some(format!("{} ({})", title, version));
         if let Some(description) = pkg.description.as_ref() {
             if !description.trim().is_empty() {
-                lines.push(Line::from(description.clone()));
+                fields.push(description.clone());
             }
         }
-        lines.push(Line::from(format!("attr: {}", pkg.attr_path)));
-        lines.push(Line::from(format!(
+        fields.push(format!("attr: {}", pkg.attr_path));
+        let templates = app.presets_providing(&app.base_attr_for(&pkg.attr_path));
+        if !templates.is_empty() {
+            fields.push(format!("template: {}", templates.join(", ")));
+        }
+        if let Some((_, entry)) = app
+            .pending_pins
+            .values()
+            .find(|(attr, _)| *attr == pkg.attr_path)
+        {
+            fields.push(format!("pinning: {} (prefetching)", entry.version));
+        }
+        fields.push(format!(
             "main: {}",
             pkg.main_program.as_deref().unwrap_or("-")
-        )));
-        lines.push(Line::from(format!(
+        ));
+        fields.push(format!(
             "license: {}",
             pkg.license.as_deref().unwrap_or("-")
-        )));
-        lines.push(Line::from(format!(
+        ));
+        fields.push(format!(
             "platforms: {}",
             pkg.platforms.as_deref().unwrap_or("-")
-        )));
+        ));
     } else {
-        lines.push(Line::from("No package selected"));
+        fields.push("No package selected".to_string());
+    }
+
+    let width = area.width.saturating_sub(2) as usize;
+    let height = area.height.saturating_sub(2) as usize;
+    let heading_lines = heading
+        .as_deref()
+        .map(|heading| wrap_details_text(heading, width))
+        .unwrap_or_default();
+    let mut wrapped: Vec<Line> = heading_lines
+        .into_iter()
+        .map(|line| {
+            Line::from(Span::styled(
+                line,
+                Style::default().add_modifier(Modifier::BOLD),
+            ))
+        })
+        .collect();
+    for field in &fields {
+        wrapped.extend(wrap_details_text(field, width).into_iter().map(Line::from));
+    }
+
+    let total = wrapped.len();
+    app.details_scroll = app.details_scroll.min(total.saturating_sub(height));
+    let scroll = app.details_scroll;
+    let overflow = total > scroll + height;
+    let visible = if overflow {
+        height.saturating_sub(1)
+    } else {
+        height
+    };
+    let mut lines: Vec<Line> = wrapped.into_iter().skip(scroll).take(visible).collect();
+    if overflow && height > 0 {
+        let hint = if app.details_expanded {
+            format!("more… {} lines (PgDn scroll)", total - scroll - visible)
+        } else {
+            format!(
+                "more… {} lines (X expand, PgDn scroll)",
+                total - scroll - visible
+            )
+        };
+        lines.push(Line::from(Span::styled(
+            hint,
+            Style::default().fg(Color::DarkGray),
+        )));
     }
 
+    let title = if scroll > 0 {
+        format!("Details (+{} above)", scroll)
+    } else {
+        "Details".to_string()
+    };
     let details = Paragraph::new(Text::from(lines))
-        .block(Block::default().title("Details").borders(Borders::ALL))
-        .wrap(Wrap { trim: true });
+        .block(Block::default().title(title).borders(Borders::ALL));
     frame.render_widget(details, area);
 }
 
+fn wrap_details_text(text: &str, width: usize) -> Vec<String> {
+    if width == 0 {
+        return Vec::new();
+    }
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let mut current = String::new();
+        let mut current_len = 0usize;
+        for word in paragraph.split_whitespace() {
+            let mut word: Vec<char> = word.chars().collect();
+            if current_len > 0 && current_len + 1 + word.len() > width {
+                lines.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            while word.len() > width {
+                let rest = word.split_off(width);
+                lines.push(word.into_iter().collect());
+                word = rest;
+            }
+            if current_len > 0 {
+                current.push(' ');
+                current_len += 1;
+            }
+            current_len += word.len();
+            current.extend(word);
+        }
+        if !current.is_empty() {
+            lines.push(current);
+        }
+    }
+    lines
+}
+
 fn render_preset_search(frame: &mut Frame, app: &App, area: Rect) {
     let title = "[T]emplates search";
     let border_style = focus_border_style(app, Focus::Presets);
@@ -433,9 +586,19 @@ fn render_preset_details(frame: &mut Frame, app: &App, area: Rect) {
 }
 
 fn render_changes_panel(frame: &mut Frame, app: &App, area: Rect) {
-    let lines = build_changes_lines(app, 3);
+    let max_items = if app.focus == Focus::Changes {
+        usize::MAX
+    } else {
+        3
+    };
+    let (lines, cursor_line) = build_changes_lines(app, max_items);
+    let visible = usize::from(area.height.saturating_sub(2));
+    let scroll = cursor_line
+        .map(|line| (line + 1).saturating_sub(visible))
+        .unwrap_or(0);
     let border_style = focus_border_style(app, Focus::Changes);
     let changes = Paragraph::new(Text::from(lines))
+        .scroll((u16::try_from(scroll).unwrap_or(u16::MAX), 0))
         .block(
             Block::default()
                 .title("[C]hanges")
@@ -472,14 +635,32 @@ fn render_templates_banner(frame: &mut Frame, app: &App, area: Rect) {
 fn render_overlay(frame: &mut Frame, app: &App, overlay: &Overlay) {
     match overlay {
         Overlay::Help => render_help_overlay(frame),
+        Overlay::Jobs => render_jobs_overlay(frame, &app.jobs),
+        Overlay::CommitPicker(state) => render_commit_picker_overlay(frame, state),
         Overlay::PackageInfo(state) => render_package_info_overlay(frame, state),
         Overlay::VersionPicker(state) => render_version_picker_overlay(frame, state),
         Overlay::PinEditor(state) => render_pin_editor_overlay(frame, state),
         Overlay::Columns(state) => render_columns_overlay(frame, app, state),
         Overlay::Filter(state) => render_filter_overlay(frame, state),
+        Overlay::GoTo(state) => render_goto_overlay(frame, app, state),
         Overlay::Env(state) => render_env_overlay(frame, state),
-        Overlay::Shell(state) => render_shell_overlay(frame, state),
+        Overlay::Shell(state) => {
+            render_text_editor(frame, state, "Shell hook (Esc to close, Ctrl+C cancel)")
+        }
+        Overlay::Override(state) => render_text_editor(
+            frame,
+            &state.editor,
+            &format!(
+                "{}.overrideAttrs (Esc to apply, Ctrl+C cancel, empty clears)",
+                state.attr
+            ),
+        ),
+        Overlay::BuildEnv(state) => render_build_env_overlay(frame, app, state),
         Overlay::Diff(state) => render_diff_overlay(frame, app, state),
+        Overlay::MissingPackages(state) => render_missing_packages_overlay(frame, state),
+        Overlay::Merge(state) => render_merge_overlay(frame, state),
+        Overlay::StorePaths(state) => render_store_paths_overlay(frame, state),
+        Overlay::ChangeActions(state) => render_change_actions_overlay(frame, app, state),
     }
 }
 
@@ -516,7 +697,11 @@ fn render_help_overlay(frame: &mut Frame) {
         ]),
         Row::new(vec![
             Span::styled("Enter/Space", key_style),
-            Span::raw("toggle"),
+            Span::raw("toggle (on a change: actions)"),
+        ]),
+        Row::new(vec![
+            Span::styled("Shift+Enter", key_style),
+            Span::raw("add and pick a version (also Alt+Enter)"),
         ]),
         Row::new(vec![
             Span::styled("Type", key_style),
@@ -534,6 +719,10 @@ fn render_help_overlay(frame: &mut Frame) {
             Span::styled("Ctrl+U", key_style),
             Span::raw("clear search"),
         ]),
+        Row::new(vec![
+            Span::styled("Ctrl+G", key_style),
+            Span::raw("go to exact attr path"),
+        ]),
         Row::new(vec![Span::styled("S", key_style), Span::raw("search mode")]),
         Row::new(vec![
             Span::styled("Esc/?", key_style),
@@ -551,6 +740,18 @@ fn render_help_overlay(frame: &mut Frame) {
             Span::styled("Ctrl+V", key_style),
             Span::raw("version picker"),
         ]),
+        Row::new(vec![
+            Span::styled("Ctrl+T", key_style),
+            Span::raw("try package in ephemeral shell"),
+        ]),
+        Row::new(vec![
+            Span::styled("A", key_style),
+            Span::raw("edit overrideAttrs for selected package"),
+        ]),
+        Row::new(vec![
+            Span::styled("W", key_style),
+            Span::raw("store path preview (selected + unsaved)"),
+        ]),
         Row::new(vec![
             Span::styled("Ctrl+N", key_style),
             Span::raw("add pin"),
@@ -564,6 +765,10 @@ fn render_help_overlay(frame: &mut Frame) {
             Span::raw("toggle diff view (diff)"),
         ]),
         Row::new(vec![Span::styled("U", key_style), Span::raw("update pin")]),
+        Row::new(vec![
+            Span::styled("G", key_style),
+            Span::raw("pin to a recent commit"),
+        ]),
         Row::new(vec![Span::styled("M", key_style), Span::raw("columns")]),
         Row::new(vec![
             Span::styled("R", key_style),
@@ -573,6 +778,10 @@ fn render_help_overlay(frame: &mut Frame) {
             Span::styled("Y", key_style),
             Span::raw("reload from nix"),
         ]),
+        Row::new(vec![
+            Span::styled("J", key_style),
+            Span::raw("background jobs (c to cancel)"),
+        ]),
         Row::new(vec!["", ""]),
         Row::new(vec!["Filters", ""]).style(header_style),
         Row::new(vec![
@@ -595,6 +804,14 @@ fn render_help_overlay(frame: &mut Frame) {
             Span::styled("O", key_style),
             Span::raw("platform filter"),
         ]),
+        Row::new(vec![
+            Span::styled("F", key_style),
+            Span::raw("cycle pin filter"),
+        ]),
+        Row::new(vec![
+            Span::styled("Alt+1-9", key_style),
+            Span::raw("filter chip (Alt+0 clears)"),
+        ]),
         Row::new(vec!["", ""]),
         Row::new(vec!["Panels", ""]).style(header_style),
         Row::new(vec![
@@ -609,6 +826,14 @@ fn render_help_overlay(frame: &mut Frame) {
             Span::styled("K", key_style),
             Span::raw("toggle details"),
         ]),
+        Row::new(vec![
+            Span::styled("X", key_style),
+            Span::raw("expand details"),
+        ]),
+        Row::new(vec![
+            Span::styled("PgUp/PgDn", key_style),
+            Span::raw("scroll details"),
+        ]),
         Row::new(vec![Span::styled("E", key_style), Span::raw("edit env")]),
         Row::new(vec![
             Span::styled("Tab", key_style),
@@ -618,6 +843,14 @@ fn render_help_overlay(frame: &mut Frame) {
             Span::styled("H", key_style),
             Span::raw("edit shell hook"),
         ]),
+        Row::new(vec![
+            Span::styled("P", key_style),
+            Span::raw("prefer package on file collisions (again to clear)"),
+        ]),
+        Row::new(vec![
+            Span::styled("N", key_style),
+            Span::raw("buildEnv name/outputs/collisions"),
+        ]),
     ];
 
     let table = Table::new(rows, [Constraint::Length(16), Constraint::Min(0)])
@@ -646,6 +879,35 @@ fn render_filter_overlay(frame: &mut Frame, state: &crate::tui::app::FilterEdito
     frame.render_widget(filter, area);
 }
 
+fn render_goto_overlay(frame: &mut Frame, app: &App, state: &crate::tui::app::GoToState) {
+    let area = centered_rect(60, 20, frame.area());
+    frame.render_widget(Clear, area);
+
+    let mut lines = match &state.missing {
+        Some(attr) => vec![
+            Line::from(Span::styled(
+                format!(
+                    "{} is not in the index at {}",
+                    attr,
+                    short_rev(&app.index_info.rev)
+                ),
+                Style::default().fg(Color::Yellow),
+            )),
+            Line::from("Enter to add it anyway, Esc to cancel"),
+        ],
+        None => vec![Line::from(
+            "Type a full attr path, Enter to jump, Esc to cancel",
+        )],
+    };
+    lines.push(Line::from(""));
+    lines.push(render_input_with_cursor(&state.input, state.cursor));
+
+    let prompt = Paragraph::new(Text::from(lines))
+        .block(Block::default().title("Go to attr").borders(Borders::ALL))
+        .wrap(Wrap { trim: true });
+    frame.render_widget(prompt, area);
+}
+
 fn render_columns_overlay(
     frame: &mut Frame,
     app: &App,
@@ -762,6 +1024,59 @@ fn render_version_picker_overlay(frame: &mut Frame, state: &crate::tui::app::Ver
     frame.render_stateful_widget(table, area, &mut list_state);
 }
 
+fn render_commit_picker_overlay(frame: &mut Frame, state: &crate::tui::app::CommitPickerState) {
+    let area = centered_rect(80, 80, frame.area());
+    frame.render_widget(Clear, area);
+
+    let mut table_state = TableState::default();
+    if !state.entries.is_empty() {
+        table_state.select(Some(state.cursor));
+    }
+
+    let rows: Vec<Row> = state
+        .entries
+        .iter()
+        .map(|entry| {
+            let marker = if entry.rev == state.current { "*" } else { "" };
+            Row::new(vec![
+                Cell::from(marker),
+                Cell::from(entry.date.chars().take(10).collect::<String>()),
+                Cell::from(short_rev(&entry.rev)),
+                Cell::from(entry.message.clone()),
+            ])
+        })
+        .collect();
+
+    let header = Row::new(vec!["", "Date", "Commit", "Message"])
+        .style(Style::default().add_modifier(Modifier::BOLD));
+
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(1),
+            Constraint::Length(10),
+            Constraint::Length(8),
+            Constraint::Min(0),
+        ],
+    )
+    .header(header)
+    .block(
+        Block::default()
+            .title(format!(
+                "Recent commits on {} (Enter to pin, Esc to close)",
+                state.branch
+            ))
+            .borders(Borders::ALL),
+    )
+    .row_highlight_style(
+        Style::default()
+            .bg(Color::DarkGray)
+            .add_modifier(Modifier::BOLD),
+    );
+
+    frame.render_stateful_widget(table, area, &mut table_state);
+}
+
 fn render_pin_editor_overlay(frame: &mut Frame, state: &crate::tui::app::PinEditorState) {
     let area = centered_rect(80, 70, frame.area());
     frame.render_widget(Clear, area);
@@ -919,7 +1234,7 @@ fn env_value_for_display(value: &str) -> String {
         .to_string()
 }
 
-fn render_shell_overlay(frame: &mut Frame, state: &crate::tui::app::ShellEditorState) {
+fn render_text_editor(frame: &mut Frame, state: &crate::tui::app::ShellEditorState, title: &str) {
     let area = centered_rect(80, 70, frame.area());
     frame.render_widget(Clear, area);
 
@@ -940,13 +1255,427 @@ fn render_shell_overlay(frame: &mut Frame, state: &crate::tui::app::ShellEditorS
     let shell = Paragraph::new(text)
         .block(
             Block::default()
-                .title("Shell hook (Esc to close, Ctrl+C cancel)")
+                .title(title.to_string())
                 .borders(Borders::ALL),
         )
         .wrap(Wrap { trim: false });
     frame.render_widget(shell, area);
 }
 
+fn render_build_env_overlay(
+    frame: &mut Frame,
+    app: &App,
+    state: &crate::tui::app::BuildEnvEditorState,
+) {
+    let area = centered_rect(60, 40, frame.area());
+    frame.render_widget(Clear, area);
+
+    let mut lines = vec![Line::from(
+        "Enter edit (Space toggles collisions), Esc close. Empty name uses the project dir.",
+    )];
+    lines.push(Line::from(""));
+    for (idx, field) in crate::tui::app::BUILD_ENV_FIELDS.iter().enumerate() {
+        let selected = idx == state.cursor;
+        let label = format!("{:<14}", field.label());
+        let label_style = if selected {
+            Style::default()
+                .bg(Color::DarkGray)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let mut spans = vec![Span::styled(label, label_style), Span::raw(" ")];
+        if selected && state.editing {
+            spans.extend(render_input_with_cursor(&state.input, state.input_cursor).spans);
+        } else {
+            let value = field.value(&app.build_env);
+            if value.is_empty() {
+                spans.push(Span::styled(
+                    "(default)",
+                    Style::default().fg(Color::DarkGray),
+                ));
+            } else {
+                spans.push(Span::raw(value));
+            }
+        }
+        lines.push(Line::from(spans));
+    }
+    if let Some(error) = &state.error {
+        lines.push(Line::from(""));
+        lines.push(Line::from(Span::styled(
+            error.clone(),
+            Style::default().fg(Color::Red),
+        )));
+    }
+
+    let editor = Paragraph::new(Text::from(lines))
+        .block(Block::default().title("buildEnv").borders(Borders::ALL))
+        .wrap(Wrap { trim: false });
+    frame.render_widget(editor, area);
+}
+
+fn render_missing_packages_overlay(
+    frame: &mut Frame,
+    state: &crate::tui::app::MissingPackagesState,
+) {
+    let area = centered_rect(70, 50, frame.area());
+    frame.render_widget(Clear, area);
+
+    let mut lines = vec![
+        Line::from(format!(
+            "Not found at {}. Left/Right pick a replacement, Enter replace, d drop, Esc keep.",
+            short_rev(&state.rev)
+        )),
+        Line::from(""),
+    ];
+    let width = state
+        .entries
+        .iter()
+        .map(|entry| entry.attr.len())
+        .max()
+        .unwrap_or(0);
+    for (idx, entry) in state.entries.iter().enumerate() {
+        let label_style = if idx == state.cursor {
+            Style::default()
+                .bg(Color::DarkGray)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let mut spans = vec![
+            Span::styled(
+                format!("{:<width$}", entry.attr, width = width),
+                label_style,
+            ),
+            Span::raw("  "),
+        ];
+        if entry.candidates.is_empty() {
+            spans.push(Span::styled(
+                "no candidates",
+                Style::default().fg(Color::DarkGray),
+            ));
+        }
+        for (choice, candidate) in entry.candidates.iter().enumerate() {
+            if choice == entry.choice {
+                spans.push(Span::styled(
+                    format!("[{}]", candidate),
+                    Style::default().fg(Color::Green),
+                ));
+            } else {
+                spans.push(Span::raw(format!(" {} ", candidate)));
+            }
+            spans.push(Span::raw(" "));
+        }
+        lines.push(Line::from(spans));
+    }
+
+    let overlay = Paragraph::new(Text::from(lines))
+        .block(
+            Block::default()
+                .title("Missing after pin update")
+                .borders(Borders::ALL),
+        )
+        .wrap(Wrap { trim: false });
+    frame.render_widget(overlay, area);
+}
+
+fn render_change_actions_overlay(
+    frame: &mut Frame,
+    app: &App,
+    state: &crate::tui::app::ChangeActionsState,
+) {
+    let area = centered_rect(50, 30, frame.area());
+    frame.render_widget(Clear, area);
+
+    let mut lines = vec![
+        Line::from(vec![
+            Span::styled(
+                format!("{} ", state.change.kind.prefix()),
+                Style::default().add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(change_label(app, &state.change)),
+        ]),
+        Line::from(Span::styled(
+            format!("Affects {}", app.affected_section(&state.change)),
+            Style::default().fg(Color::DarkGray),
+        )),
+        Line::from(""),
+    ];
+    for (idx, action) in state.actions.iter().enumerate() {
+        let style = if idx == state.cursor {
+            Style::default()
+                .bg(Color::DarkGray)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        lines.push(Line::from(Span::styled(action.label(), style)));
+    }
+
+    let overlay = Paragraph::new(Text::from(lines))
+        .block(
+            Block::default()
+                .title("Change (Enter run, Esc close)")
+                .borders(Borders::ALL),
+        )
+        .wrap(Wrap { trim: false });
+    frame.render_widget(overlay, area);
+}
+
+fn render_store_paths_overlay(frame: &mut Frame, state: &crate::tui::app::StorePathsState) {
+    use crate::nixops::storepath::StorePathStatus;
+
+    let area = centered_rect(80, 50, frame.area());
+    frame.render_widget(Clear, area);
+
+    let width = state
+        .entries
+        .iter()
+        .map(|entry| entry.attr.len())
+        .max()
+        .unwrap_or(0);
+    let mut download = 0u64;
+    let (mut local, mut cached, mut build) = (0, 0, 0);
+    let mut lines = vec![
+        Line::from(format!(
+            "Out paths at {}. Up/Down select, Esc close.",
+            short_rev(&state.rev)
+        )),
+        Line::from(""),
+    ];
+    for (idx, entry) in state.entries.iter().enumerate() {
+        let label_style = if idx == state.cursor {
+            Style::default()
+                .bg(Color::DarkGray)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let status = match &entry.status {
+            StorePathStatus::Local => {
+                local += 1;
+                Span::styled("in local store", Style::default().fg(Color::Green))
+            }
+            StorePathStatus::Cached {
+                cache,
+                download_size,
+            } => {
+                cached += 1;
+                download += download_size.unwrap_or(0);
+                let size = download_size
+                    .map(|size| format!(" ({})", format_size(size)))
+                    .unwrap_or_default();
+                Span::styled(
+                    format!("download from {}{}", cache, size),
+                    Style::default().fg(Color::Yellow),
+                )
+            }
+            StorePathStatus::Build => {
+                build += 1;
+                Span::styled(
+                    "not cached, builds locally",
+                    Style::default().fg(Color::Red),
+                )
+            }
+            StorePathStatus::Failed(err) => Span::styled(
+                format!("eval failed: {}", err),
+                Style::default().fg(Color::Red),
+            ),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(
+                format!("{:<width$}", entry.attr, width = width),
+                label_style,
+            ),
+            Span::raw("  "),
+            status,
+        ]));
+        if idx == state.cursor {
+            if let Some(out_path) = &entry.out_path {
+                lines.push(Line::from(Span::styled(
+                    format!("  {}", out_path),
+                    Style::default().fg(Color::DarkGray),
+                )));
+            }
+        }
+    }
+    lines.push(Line::from(""));
+    lines.push(Line::from(format!(
+        "{} in store, {} to download ({}), {} to build",
+        local,
+        cached,
+        format_size(download),
+        build
+    )));
+
+    let overlay = Paragraph::new(Text::from(lines))
+        .block(
+            Block::default()
+                .title("Store path preview")
+                .borders(Borders::ALL),
+        )
+        .wrap(Wrap { trim: false });
+    frame.render_widget(overlay, area);
+}
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+fn render_merge_overlay(frame: &mut Frame, state: &crate::tui::app::MergeState) {
+    use mica_core::merge::MergeChoice;
+
+    let area = centered_rect(70, 50, frame.area());
+    frame.render_widget(Clear, area);
+
+    let mut lines = vec![
+        Line::from("default.nix changed on disk since it was loaded."),
+        Line::from("Up/Down select, o ours, t theirs, b both, Enter save, Esc cancel."),
+        Line::from(""),
+    ];
+    let width = state
+        .entries
+        .iter()
+        .map(|entry| entry.section.name().len())
+        .max()
+        .unwrap_or(0);
+    for (idx, entry) in state.entries.iter().enumerate() {
+        let label_style = if idx == state.cursor {
+            Style::default()
+                .bg(Color::DarkGray)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let changed = match (entry.ours_changed, entry.theirs_changed) {
+            (true, true) => "conflict",
+            (true, false) => "changed here",
+            _ => "changed in default.nix",
+        };
+        let mut spans = vec![
+            Span::styled(
+                format!("{:<width$}", entry.section.name(), width = width),
+                label_style,
+            ),
+            Span::raw("  "),
+            Span::styled(
+                format!("{:<22}", changed),
+                if entry.ours_changed && entry.theirs_changed {
+                    Style::default().fg(Color::Yellow)
+                } else {
+                    Style::default().fg(Color::DarkGray)
+                },
+            ),
+        ];
+        for (choice, label) in [
+            (MergeChoice::Ours, "ours"),
+            (MergeChoice::Theirs, "theirs"),
+            (MergeChoice::Both, "both"),
+        ] {
+            if choice == entry.choice {
+                spans.push(Span::styled(
+                    format!("[{}]", label),
+                    Style::default().fg(Color::Green),
+                ));
+            } else {
+                spans.push(Span::raw(format!(" {} ", label)));
+            }
+            spans.push(Span::raw(" "));
+        }
+        lines.push(Line::from(spans));
+    }
+
+    let overlay = Paragraph::new(Text::from(lines))
+        .block(
+            Block::default()
+                .title("Merge changes")
+                .borders(Borders::ALL),
+        )
+        .wrap(Wrap { trim: false });
+    frame.render_widget(overlay, area);
+}
+
+fn render_jobs_overlay(frame: &mut Frame, jobs: &Jobs) {
+    let area = centered_rect(60, 40, frame.area());
+    frame.render_widget(Clear, area);
+
+    let mut lines = vec![
+        Line::from("Up/Down select, c cancel, Esc close."),
+        Line::from(""),
+    ];
+    if jobs.entries.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "no background jobs",
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+    let width = jobs
+        .entries
+        .iter()
+        .map(|job| job.label.len())
+        .max()
+        .unwrap_or(0);
+    for (idx, job) in jobs.entries.iter().enumerate() {
+        let label_style = if idx == jobs.cursor {
+            Style::default()
+                .bg(Color::DarkGray)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
+        let (status, status_style) = match &job.status {
+            JobStatus::Running => {
+                let elapsed = job.started.elapsed().as_secs();
+                let detail = match job
+                    .progress
+                    .and_then(|progress| progress_percent(progress.done, progress.total))
+                {
+                    Some(percent) => format!("running {}% ({}s)", percent, elapsed),
+                    None => format!("running ({}s)", elapsed),
+                };
+                (detail, Style::default().fg(Color::Yellow))
+            }
+            JobStatus::Done(message) => (
+                message.clone().unwrap_or_else(|| "done".to_string()),
+                Style::default().fg(Color::Green),
+            ),
+            JobStatus::Failed(err) => (format!("failed: {}", err), Style::default().fg(Color::Red)),
+            JobStatus::Cancelled => (
+                "cancelled".to_string(),
+                Style::default().fg(Color::DarkGray),
+            ),
+        };
+        lines.push(Line::from(vec![
+            Span::styled(format!("{:<width$}", job.label, width = width), label_style),
+            Span::raw("  "),
+            Span::styled(status, status_style),
+        ]));
+    }
+
+    let overlay = Paragraph::new(Text::from(lines))
+        .block(Block::default().title("Jobs").borders(Borders::ALL))
+        .wrap(Wrap { trim: false });
+    frame.render_widget(overlay, area);
+}
+
+fn progress_percent(done: u64, total: Option<u64>) -> Option<u64> {
+    match total {
+        Some(total) if total > 0 => Some(done.min(total) * 100 / total),
+        _ => None,
+    }
+}
+
 fn render_diff_overlay(frame: &mut Frame, _app: &App, state: &crate::tui::app::DiffViewerState) {
     let area = centered_rect(90, 80, frame.area());
     frame.render_widget(Clear, area);
@@ -1005,6 +1734,10 @@ fn render_toast(frame: &mut Frame, toast: &Toast) {
             Style::default().fg(Color::Cyan),
             Style::default().fg(Color::White),
         ),
+        ToastLevel::Warning => (
+            Style::default().fg(Color::Yellow),
+            Style::default().fg(Color::Yellow),
+        ),
         ToastLevel::Error => (
             Style::default().fg(Color::Red),
             Style::default().fg(Color::Red),
@@ -1047,7 +1780,7 @@ fn package_row(app: &App, pkg: &PackageEntry) -> Row<'static> {
     } else if is_added {
         "[+]"
     } else if is_preset {
-        "[x]"
+        "[T]"
     } else {
         "[ ]"
     };
@@ -1132,114 +1865,203 @@ fn preset_item(app: &App, preset: &PresetEntry) -> ListItem<'static> {
     ListItem::new(Line::from(spans))
 }
 
-fn build_changes_lines(app: &App, max_items: usize) -> Vec<Line<'static>> {
-    let mut lines = Vec::new();
-
-    let added: Vec<_> = app.added.difference(&app.base_added).cloned().collect();
-    let removed: Vec<_> = app.removed.difference(&app.base_removed).cloned().collect();
-    let presets_on: Vec<_> = app
-        .active_presets
-        .difference(&app.base_presets)
-        .cloned()
-        .collect();
-    let presets_off: Vec<_> = app
-        .base_presets
-        .difference(&app.active_presets)
-        .cloned()
-        .collect();
-
-    let mut pinned_added = Vec::new();
-    let mut pinned_removed = Vec::new();
-    let mut pinned_changed = Vec::new();
-    for (name, pinned) in &app.pinned {
-        match app.base_pinned.get(name) {
-            None => pinned_added.push(format!("{} ({})", name, pinned.version)),
-            Some(existing) if existing != pinned => {
-                pinned_changed.push(format!("{} ({})", name, pinned.version))
-            }
-            _ => {}
-        }
-    }
-    for name in app.base_pinned.keys() {
-        if !app.pinned.contains_key(name) {
-            pinned_removed.push(name.clone());
-        }
-    }
+fn build_changes_lines(app: &App, max_items: usize) -> (Vec<Line<'static>>, Option<usize>) {
+    use crate::tui::app::{ChangeKind, ChangeSection};
 
-    let mut env_added = Vec::new();
-    let mut env_removed = Vec::new();
-    let mut env_changed = Vec::new();
-    for (key, value) in &app.env {
-        let display = env_value_for_display(value);
-        let suffix = if env_value_is_nix_expression(value) {
-            " [expr]"
-        } else {
-            ""
-        };
-        match app.base_env.get(key) {
-            None => env_added.push(format!("{}={}{}", key, display, suffix)),
-            Some(existing) if existing != value => {
-                env_changed.push(format!("{}={}{}", key, display, suffix))
-            }
-            _ => {}
-        }
-    }
-    for key in app.base_env.keys() {
-        if !app.env.contains_key(key) {
-            env_removed.push(key.clone());
-        }
-    }
+    let mut lines = Vec::new();
+    let mut cursor_line = None;
+    let cursor = (app.focus == Focus::Changes).then_some(app.changes_cursor);
+    let changes = app.pending_changes();
+    let mut push_group = |lines: &mut Vec<Line<'static>>, section, kind, color| {
+        let items: Vec<(usize, String)> = changes
+            .iter()
+            .enumerate()
+            .filter(|(_, change)| change.section == section && change.kind == kind)
+            .map(|(idx, change)| (idx, change_label(app, change)))
+            .collect();
+        push_change_lines(
+            lines,
+            kind.prefix(),
+            &items,
+            max_items,
+            color,
+            cursor,
+            &mut cursor_line,
+        );
+    };
 
     lines.push(Line::from(Span::styled(
         "Packages",
         Style::default().add_modifier(Modifier::BOLD),
     )));
-    push_change_lines(&mut lines, "+", &added, max_items, Color::Green);
-    push_change_lines(&mut lines, "-", &removed, max_items, Color::Red);
+    push_group(
+        &mut lines,
+        ChangeSection::Packages,
+        ChangeKind::Added,
+        Color::Green,
+    );
+    push_group(
+        &mut lines,
+        ChangeSection::Packages,
+        ChangeKind::Removed,
+        Color::Red,
+    );
 
     lines.push(Line::from(Span::styled(
         "Templates",
         Style::default().add_modifier(Modifier::BOLD),
     )));
-    push_change_lines(&mut lines, "+", &presets_on, max_items, Color::Green);
-    push_change_lines(&mut lines, "-", &presets_off, max_items, Color::Red);
+    push_group(
+        &mut lines,
+        ChangeSection::Templates,
+        ChangeKind::Added,
+        Color::Green,
+    );
+    push_group(
+        &mut lines,
+        ChangeSection::Templates,
+        ChangeKind::Removed,
+        Color::Red,
+    );
 
     lines.push(Line::from(Span::styled(
         "Pinned",
         Style::default().add_modifier(Modifier::BOLD),
     )));
-    push_change_lines(&mut lines, "+", &pinned_added, max_items, Color::Green);
-    push_change_lines(&mut lines, "-", &pinned_removed, max_items, Color::Red);
-    push_change_lines(&mut lines, "~", &pinned_changed, max_items, Color::Yellow);
+    push_group(
+        &mut lines,
+        ChangeSection::Pinned,
+        ChangeKind::Added,
+        Color::Green,
+    );
+    push_group(
+        &mut lines,
+        ChangeSection::Pinned,
+        ChangeKind::Removed,
+        Color::Red,
+    );
+    push_group(
+        &mut lines,
+        ChangeSection::Pinned,
+        ChangeKind::Changed,
+        Color::Yellow,
+    );
 
     lines.push(Line::from(Span::styled(
         "Env",
         Style::default().add_modifier(Modifier::BOLD),
     )));
-    push_change_lines(&mut lines, "+", &env_added, max_items, Color::Green);
-    push_change_lines(&mut lines, "-", &env_removed, max_items, Color::Red);
-    push_change_lines(&mut lines, "~", &env_changed, max_items, Color::Yellow);
+    push_group(
+        &mut lines,
+        ChangeSection::Env,
+        ChangeKind::Added,
+        Color::Green,
+    );
+    push_group(
+        &mut lines,
+        ChangeSection::Env,
+        ChangeKind::Removed,
+        Color::Red,
+    );
+    push_group(
+        &mut lines,
+        ChangeSection::Env,
+        ChangeKind::Changed,
+        Color::Yellow,
+    );
 
-    let shell_changed = app.shell_hook != app.base_shell_hook;
     lines.push(Line::from(Span::styled(
-        "Shell hook",
+        "Collisions",
         Style::default().add_modifier(Modifier::BOLD),
     )));
-    lines.push(Line::from(if shell_changed {
-        Span::styled("modified", Style::default().fg(Color::Yellow))
-    } else {
-        Span::raw("unchanged")
-    }));
+    if app.collisions.is_empty() {
+        lines.push(Line::from(Span::styled(
+            "none",
+            Style::default().fg(Color::DarkGray),
+        )));
+    }
+    for collision in &app.collisions {
+        let packages = collision.packages.join(", ");
+        let line = if app.build_env.ignore_collisions {
+            Span::styled(
+                format!("{}: {} (ignored)", collision.binary, packages),
+                Style::default().fg(Color::DarkGray),
+            )
+        } else {
+            match collision_winner(collision, &app.priorities) {
+                Some(winner) => Span::styled(
+                    format!("{}: {} wins", collision.binary, winner),
+                    Style::default().fg(Color::Green),
+                ),
+                None => Span::styled(
+                    format!("! {}: {} (P to prefer)", collision.binary, packages),
+                    Style::default().fg(Color::Red),
+                ),
+            }
+        };
+        lines.push(Line::from(line));
+    }
 
-    lines
+    for (title, section) in [
+        ("buildEnv", ChangeSection::BuildEnv),
+        ("Shell hook", ChangeSection::ShellHook),
+    ] {
+        lines.push(Line::from(Span::styled(
+            title,
+            Style::default().add_modifier(Modifier::BOLD),
+        )));
+        match changes.iter().position(|change| change.section == section) {
+            Some(idx) => {
+                let mut style = Style::default().fg(Color::Yellow);
+                if cursor == Some(idx) {
+                    style = style.bg(Color::DarkGray).add_modifier(Modifier::BOLD);
+                    cursor_line = Some(lines.len());
+                }
+                lines.push(Line::from(Span::styled("modified", style)));
+            }
+            None => lines.push(Line::from(Span::raw("unchanged"))),
+        }
+    }
+
+    (lines, cursor_line)
+}
+
+fn change_label(app: &App, change: &crate::tui::app::PendingChange) -> String {
+    use crate::tui::app::{ChangeKind, ChangeSection};
+
+    match (change.section, change.kind) {
+        (ChangeSection::Pinned, ChangeKind::Added | ChangeKind::Changed) => {
+            match app.pinned.get(&change.target) {
+                Some(pinned) => format!("{} ({})", change.target, pinned.version),
+                None => change.target.clone(),
+            }
+        }
+        (ChangeSection::Env, ChangeKind::Added | ChangeKind::Changed) => {
+            let value = app.env.get(&change.target).cloned().unwrap_or_default();
+            let suffix = if env_value_is_nix_expression(&value) {
+                " [expr]"
+            } else {
+                ""
+            };
+            format!(
+                "{}={}{}",
+                change.target,
+                env_value_for_display(&value),
+                suffix
+            )
+        }
+        _ => change.target.clone(),
+    }
 }
 
 fn push_change_lines(
     lines: &mut Vec<Line>,
     prefix: &str,
-    items: &[String],
+    items: &[(usize, String)],
     max_items: usize,
     color: Color,
+    cursor: Option<usize>,
+    cursor_line: &mut Option<usize>,
 ) {
     if items.is_empty() {
         lines.push(Line::from(Span::styled(
@@ -1249,10 +2071,18 @@ fn push_change_lines(
         return;
     }
 
-    for item in items.iter().take(max_items) {
+    for (idx, item) in items.iter().take(max_items) {
+        let style = if cursor == Some(*idx) {
+            *cursor_line = Some(lines.len());
+            Style::default()
+                .bg(Color::DarkGray)
+                .add_modifier(Modifier::BOLD)
+        } else {
+            Style::default()
+        };
         lines.push(Line::from(vec![
             Span::styled(format!("{} ", prefix), Style::default().fg(color)),
-            Span::raw(item.clone()),
+            Span::styled(item.clone(), style),
         ]));
     }
 
@@ -198,6 +198,8 @@ fn write_index_fixture(home: &TempHome) {
         position: Some("pkgs/tools/text/ripgrep/default.nix".to_string()),
         broken: Some(false),
         insecure: Some(false),
+        maintainers: None,
+        last_updated: None,
     }];
     ingest_packages(&mut conn, &packages).expect("failed to ingest fixture package");
     set_meta(&conn, "index_meta", "true").expect("failed to set index_meta");
@@ -272,3 +274,510 @@ fn default_invocation_launches_tui() {
         String::from_utf8_lossy(&output.stderr)
     );
 }
+
+#[test]
+fn dry_run_add_prints_package_delta_and_file_diff() {
+    let home = TempHome::new("dry-run-add");
+    let project_dir = home.path.join("project");
+    fs::create_dir_all(&project_dir).expect("failed to create project directory");
+    write_default_nix_fixture(&project_dir);
+    let before = fs::read_to_string(project_dir.join("default.nix")).expect("read default.nix");
+
+    let output = mica_cmd_in(&home, &project_dir)
+        .args(["--dry-run", "add", "jq"])
+        .output()
+        .expect("failed to run mica add");
+    assert!(
+        output.status.success(),
+        "dry-run add failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("packages: +jq"),
+        "expected package delta, got:\n{}",
+        stdout
+    );
+    assert!(
+        stdout.lines().any(|line| line.starts_with("@@ -"))
+            && stdout.lines().any(|line| line.trim_end() == "+     jq"),
+        "expected a unified diff adding jq, got:\n{}",
+        stdout
+    );
+    assert_eq!(
+        fs::read_to_string(project_dir.join("default.nix")).expect("read default.nix"),
+        before
+    );
+}
+
+#[test]
+fn no_op_sync_leaves_default_nix_untouched() {
+    let home = TempHome::new("no-op-sync");
+    let project_dir = home.path.join("project");
+    fs::create_dir_all(&project_dir).expect("failed to create project directory");
+    write_default_nix_fixture(&project_dir);
+
+    let sync = |epoch: &str| {
+        let output = mica_cmd_in(&home, &project_dir)
+            .arg("sync")
+            .env("SOURCE_DATE_EPOCH", epoch)
+            .output()
+            .expect("failed to run mica sync");
+        assert!(
+            output.status.success(),
+            "sync failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        fs::read_to_string(project_dir.join("default.nix")).expect("read default.nix")
+    };
+    let first = sync("0");
+    assert!(
+        first.contains("# Last generated: 1970-01-01T00:00:00+00:00\n"),
+        "expected the SOURCE_DATE_EPOCH timestamp, got:\n{}",
+        first
+    );
+    assert_eq!(sync("86400"), first);
+}
+
+#[test]
+fn state_dump_then_load_regenerates_default_nix() {
+    let home = TempHome::new("state-dump");
+    let project_dir = home.path.join("project");
+    fs::create_dir_all(&project_dir).expect("failed to create project directory");
+    write_default_nix_fixture(&project_dir);
+    let run = |args: &[&str]| {
+        let output = mica_cmd_in(&home, &project_dir)
+            .args(args)
+            .env("SOURCE_DATE_EPOCH", "0")
+            .output()
+            .expect("failed to run mica");
+        assert!(
+            output.status.success(),
+            "{:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    run(&["sync"]);
+
+    let dump = run(&["state", "dump"]);
+    assert!(
+        dump.contains("created = \"1970-01-01T00:00:00Z\""),
+        "expected timestamps from the generated header, got:\n{}",
+        dump
+    );
+    assert_eq!(run(&["state", "dump"]), dump);
+    let json = run(&["state", "dump", "--format", "json"]);
+    assert!(json.trim_start().starts_with('{'));
+
+    let edited = dump.replace("added = []", "added = [\"jq\"]");
+    assert_ne!(edited, dump);
+    let dump_path = home.path.join("state.toml");
+    fs::write(&dump_path, edited).expect("failed to write state dump");
+    run(&["state", "load", dump_path.to_str().expect("utf-8 path")]);
+    let default_nix =
+        fs::read_to_string(project_dir.join("default.nix")).expect("read default.nix");
+    assert!(
+        default_nix.lines().any(|line| line.trim() == "jq"),
+        "expected jq in default.nix, got:\n{}",
+        default_nix
+    );
+
+    let json_path = home.path.join("state.json");
+    fs::write(&json_path, json).expect("failed to write json dump");
+    run(&["state", "load", json_path.to_str().expect("utf-8 path")]);
+    assert_eq!(run(&["state", "dump"]), dump);
+}
+
+#[test]
+fn export_footer_records_state_hash_and_check_attribute() {
+    let home = TempHome::new("export-footer");
+    let project_dir = home.path.join("project");
+    fs::create_dir_all(&project_dir).expect("failed to create project directory");
+    write_default_nix_fixture(&project_dir);
+    let export = |args: &[&str], epoch: &str| {
+        let output = mica_cmd_in(&home, &project_dir)
+            .arg("export")
+            .args(args)
+            .env("SOURCE_DATE_EPOCH", epoch)
+            .output()
+            .expect("failed to run mica export");
+        assert!(
+            output.status.success(),
+            "export failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+    let state_hash = |exported: &str| {
+        exported
+            .lines()
+            .find_map(|line| line.strip_prefix("# state-sha256: "))
+            .map(str::to_string)
+    };
+
+    assert!(!export(&[], "0").contains("# mica:export:begin"));
+    let footer = export(&["--footer"], "0");
+    assert!(footer.ends_with("# mica:export:end\n"), "got:\n{}", footer);
+    assert!(footer.contains("# mica-version: "));
+    assert!(state_hash(&footer).is_some());
+    assert_eq!(
+        state_hash(&export(&["--footer"], "86400")),
+        state_hash(&footer)
+    );
+
+    let checked = export(&["--check"], "0");
+    assert!(checked.starts_with("{ ... }@args:\n"), "got:\n{}", checked);
+    assert!(checked.contains("micaCheck"));
+    assert_eq!(state_hash(&checked), state_hash(&footer));
+}
+
+#[test]
+fn global_export_check_only_calls_function_bodies() {
+    let home = TempHome::new("export-global");
+    let config_dir = home.path.join(".config").join("mica");
+    fs::create_dir_all(&config_dir).expect("create config dir");
+    fs::write(
+        config_dir.join("profile.toml"),
+        r#"[mica]
+version = "0.1.0"
+created = "2026-01-01T00:00:00Z"
+modified = "2026-01-01T00:00:00Z"
+
+[pin]
+url = "https://github.com/NixOS/nixpkgs"
+rev = "abc"
+sha256 = "0000000000000000000000000000000000000000000000000000"
+branch = "nixpkgs-unstable"
+updated = "2026-01-01"
+
+[packages]
+added = ["jq"]
+"#,
+    )
+    .expect("write profile");
+    let output = mica_cmd(&home)
+        .args(["--global", "export", "--check"])
+        .output()
+        .expect("failed to run mica export");
+    assert!(
+        output.status.success(),
+        "export failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let checked = String::from_utf8_lossy(&output.stdout);
+    assert!(checked.starts_with("{ ... }@args:\n"), "got:\n{}", checked);
+    assert!(checked.contains("if builtins.isFunction body then body args else body"));
+    assert!(!checked.contains(") args;"));
+}
+
+#[test]
+fn index_rebuild_merges_inputs_and_resumes_after_a_failure() {
+    let home = TempHome::new("index-chunks");
+    let first = home.path.join("part-1.json");
+    let second = home.path.join("part-2.json");
+    let db = home.path.join("index.db");
+    fs::write(
+        &first,
+        r#"{"ripgrep": {"name": "ripgrep-14.1.1", "version": "14.1.1"}, "jq": {"name": "jq-1.7", "version": "1.7"}}"#,
+    )
+    .expect("write first chunk");
+    fs::write(&second, "{ not json").expect("write second chunk");
+
+    let rebuild = || {
+        mica_cmd(&home)
+            .args(["index", "rebuild"])
+            .arg(&first)
+            .arg(&second)
+            .arg("--output")
+            .arg(&db)
+            .output()
+            .expect("failed to run mica index rebuild")
+    };
+    let failed = rebuild();
+    assert!(!failed.status.success(), "expected the bad chunk to fail");
+    assert!(!db.exists());
+    assert!(home.path.join("index.db.partial").exists());
+
+    fs::write(
+        &second,
+        r#"{"jq": {"name": "jq-1.7.1", "version": "1.7.1"}, "fd": {"name": "fd-10.2.0", "version": "10.2.0"}}"#,
+    )
+    .expect("fix second chunk");
+    let output = rebuild();
+    assert!(
+        output.status.success(),
+        "rebuild failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("resuming rebuild: 1 of 2 input(s) already ingested"),
+        "expected a resumed rebuild, got:\n{}",
+        stdout
+    );
+    assert!(stdout.contains("indexed 3 packages"), "got:\n{}", stdout);
+    assert!(db.exists());
+    assert!(!home.path.join("index.db.partial").exists());
+}
+
+#[test]
+fn shared_index_dir_moves_the_index_out_of_home() {
+    let home = TempHome::new("shared-index");
+    let shared = home.path.join("shared");
+    fs::create_dir_all(&shared).expect("create shared dir");
+    let config_dir = home.path.join(".config").join("mica");
+    fs::create_dir_all(&config_dir).expect("create config dir");
+    fs::write(
+        config_dir.join("config.toml"),
+        format!(
+            "[index]\nremote_url = \"\"\nupdate_check_interval = 0\nshared_dir = \"{}\"\n",
+            shared.display()
+        ),
+    )
+    .expect("write config");
+    let conn = init_db(&shared.join("index.db")).expect("init shared index");
+    set_meta(&conn, "package_count", "0").expect("set meta");
+    drop(conn);
+
+    let output = mica_cmd(&home)
+        .args(["index", "status"])
+        .output()
+        .expect("failed to run index status");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "status failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        stdout.contains(&format!("index: {}", shared.join("index.db").display())),
+        "got:\n{}",
+        stdout
+    );
+    assert!(!config_dir.join("cache").join("index.db").exists());
+}
+
+#[test]
+fn migrate_caches_moves_unreadable_caches_aside() {
+    let home = TempHome::new("migrate-caches");
+    let cache_dir = home.path.join(".config").join("mica").join("cache");
+    fs::create_dir_all(&cache_dir).expect("create cache dir");
+    let index = cache_dir.join("index.db");
+    fs::write(&index, "this is not a sqlite database, only filler text").expect("write index");
+
+    let status = mica_cmd_in(&home, &home.path)
+        .args(["index", "status"])
+        .output()
+        .expect("failed to run index status");
+    let stderr = String::from_utf8_lossy(&status.stderr);
+    assert!(
+        stderr.contains("run `mica migrate caches` to refresh them"),
+        "got:\n{}",
+        stderr
+    );
+
+    let migrate = mica_cmd_in(&home, &home.path)
+        .args(["migrate", "caches"])
+        .output()
+        .expect("failed to run migrate caches");
+    let stdout = String::from_utf8_lossy(&migrate.stdout);
+    assert!(
+        migrate.status.success(),
+        "migrate failed: {}",
+        String::from_utf8_lossy(&migrate.stderr)
+    );
+    assert!(stdout.contains("index.db.bak"), "got:\n{}", stdout);
+    assert!(
+        stdout.contains("versions.db does not exist"),
+        "got:\n{}",
+        stdout
+    );
+    assert!(!index.exists());
+    assert!(cache_dir.join("index.db.bak").exists());
+}
+
+#[test]
+fn index_versions_import_then_export() {
+    let home = TempHome::new("versions-jsonl");
+    let input = home.path.join("versions.jsonl");
+    fs::write(
+        &input,
+        concat!(
+            r#"{"type":"commit","source":"nixpkgs","commit":"abc","branch":"nixpkgs-unstable","commit_date":"2026-01-01T00:00:00Z","indexed_at":"2026-01-02T00:00:00Z","package_count":1,"url":"https://github.com/NixOS/nixpkgs"}"#,
+            "\n",
+            r#"{"type":"version","attr":"ripgrep","version":"14.1.1","source":"nixpkgs","commit":"abc","commit_date":"2026-01-01T00:00:00Z","branch":"nixpkgs-unstable"}"#,
+            "\n",
+        ),
+    )
+    .expect("write versions export");
+
+    let import = mica_cmd(&home)
+        .args(["index", "versions", "import"])
+        .arg(&input)
+        .output()
+        .expect("failed to run versions import");
+    assert!(
+        import.status.success(),
+        "import failed: {}",
+        String::from_utf8_lossy(&import.stderr)
+    );
+    assert!(String::from_utf8_lossy(&import.stdout)
+        .contains("imported 1 commit(s), 1 version(s), 0 commit date(s); 0 already present"));
+
+    let export = mica_cmd(&home)
+        .args(["index", "versions", "export", "-"])
+        .output()
+        .expect("failed to run versions export");
+    assert!(export.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&export.stdout),
+        fs::read_to_string(&input).expect("read versions export")
+    );
+}
+
+#[test]
+fn backup_restores_selected_sections() {
+    let home = TempHome::new("backup");
+    let config_dir = home.path.join(".config").join("mica");
+    fs::create_dir_all(config_dir.join("generations").join("1")).expect("create generations");
+    fs::write(config_dir.join("config.toml"), "").expect("write config");
+    fs::write(config_dir.join("profile.toml"), "# profile\n").expect("write profile");
+    fs::write(
+        config_dir.join("generations").join("1").join("profile.nix"),
+        "# generation 1\n",
+    )
+    .expect("write generation");
+    let archive = home.path.join("mica-backup.tar.gz");
+
+    let output = mica_cmd(&home)
+        .args(["backup", "create"])
+        .arg(&archive)
+        .output()
+        .expect("failed to run mica backup create");
+    assert!(
+        output.status.success(),
+        "backup create failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let output = mica_cmd(&home)
+        .args(["backup", "restore"])
+        .arg(&archive)
+        .output()
+        .expect("failed to run mica backup restore");
+    assert!(
+        !output.status.success(),
+        "restore over existing files should require --force"
+    );
+
+    fs::remove_dir_all(&config_dir).expect("remove config dir");
+    let output = mica_cmd(&home)
+        .args(["backup", "restore", "--only", "profile,generations"])
+        .arg(&archive)
+        .output()
+        .expect("failed to run mica backup restore");
+    assert!(
+        output.status.success(),
+        "backup restore failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(
+        fs::read_to_string(config_dir.join("profile.toml")).expect("read profile"),
+        "# profile\n"
+    );
+    assert!(config_dir
+        .join("generations")
+        .join("1")
+        .join("profile.nix")
+        .exists());
+    assert!(!config_dir.join("config.toml").exists());
+}
+
+fn write_backup_archive(path: &Path, manifest: &str, files: &[(&str, &str)]) {
+    let file = fs::File::create(path).expect("create archive");
+    let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+        file,
+        flate2::Compression::default(),
+    ));
+    let mut entries = vec![("mica-backup.toml", manifest)];
+    entries.extend_from_slice(files);
+    for (name, content) in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(&mut header, name, content.as_bytes())
+            .expect("append archive entry");
+    }
+    builder
+        .into_inner()
+        .and_then(|encoder| encoder.finish())
+        .expect("finish archive");
+}
+
+#[test]
+fn backup_restore_ignores_manifest_preset_dirs_and_symlinks() {
+    let home = TempHome::new("backup-hostile");
+    let config_dir = home.path.join(".config").join("mica");
+    let evil = home.path.join("evil");
+    let mine = home.path.join("my-presets");
+    let outside = home.path.join("outside");
+    fs::create_dir_all(&config_dir).expect("create config dir");
+    fs::create_dir_all(&mine).expect("create preset dir");
+    fs::create_dir_all(&outside).expect("create outside dir");
+    fs::write(
+        config_dir.join("config.toml"),
+        format!("[presets]\nextra_dirs = [\"{}\"]\n", mine.display()),
+    )
+    .expect("write config");
+    let manifest = format!(
+        "mica_version = \"0.1.0\"\ncreated = \"2026-01-01T00:00:00Z\"\nsections = [\"presets\"]\n\n[preset_dirs]\n\"presets/0\" = \"{}\"\n\"presets/1\" = \"{}\"\n",
+        evil.display(),
+        evil.display()
+    );
+    let archive = home.path.join("hostile.tar.gz");
+    write_backup_archive(
+        &archive,
+        &manifest,
+        &[
+            ("presets/0/ok.toml", "# ok\n"),
+            ("presets/1/pwn.toml", "# pwn\n"),
+        ],
+    );
+
+    let output = mica_cmd(&home)
+        .args(["backup", "restore"])
+        .arg(&archive)
+        .output()
+        .expect("failed to run mica backup restore");
+    assert!(
+        output.status.success(),
+        "backup restore failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(
+        !evil.exists(),
+        "manifest preset_dirs must not be restored to"
+    );
+    assert_eq!(
+        fs::read_to_string(mine.join("ok.toml")).expect("read restored preset"),
+        "# ok\n"
+    );
+
+    std::os::unix::fs::symlink(&outside, mine.join("linked")).expect("create symlink");
+    let archive = home.path.join("symlink.tar.gz");
+    write_backup_archive(&archive, &manifest, &[("presets/0/linked/x.toml", "# x\n")]);
+    let output = mica_cmd(&home)
+        .args(["backup", "restore", "--force"])
+        .arg(&archive)
+        .output()
+        .expect("failed to run mica backup restore");
+    assert!(
+        !output.status.success(),
+        "restore through a symlink should fail"
+    );
+    assert!(!outside.join("x.toml").exists());
+}
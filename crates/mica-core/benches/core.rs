@@ -0,0 +1,49 @@
+use chrono::Utc;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use mica_core::diff::diff_lines;
+use mica_core::fixtures::{
+    synthetic_merged, synthetic_nix_edit, synthetic_project_nix, synthetic_project_state,
+};
+use mica_core::nixgen::generate_project_nix;
+use mica_core::nixparse::parse_project_state_from_nix;
+
+fn generate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate_project_nix");
+    for packages in [200, 2_000] {
+        let state = synthetic_project_state(packages);
+        let merged = synthetic_merged(&state);
+        group.bench_with_input(BenchmarkId::from_parameter(packages), &packages, |b, _| {
+            b.iter(|| generate_project_nix(black_box(&state), &merged, "bench", Utc::now()))
+        });
+    }
+    group.finish();
+}
+
+fn parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_project_state");
+    for packages in [200, 2_000] {
+        let content = synthetic_project_nix(packages);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(packages),
+            &content,
+            |b, content| {
+                b.iter(|| parse_project_state_from_nix(black_box(content)).expect("parse failed"))
+            },
+        );
+    }
+    group.finish();
+}
+
+fn diff(c: &mut Criterion) {
+    let old = synthetic_project_nix(5_000);
+    let new = synthetic_nix_edit(&old);
+    let mut group = c.benchmark_group("diff");
+    group.sample_size(10);
+    group.bench_function("5k_lines", |b| {
+        b.iter(|| diff_lines(black_box(&old), black_box(&new)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, generate, parse, diff);
+criterion_main!(benches);
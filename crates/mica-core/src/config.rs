@@ -1,4 +1,5 @@
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::path::Path;
 
 #[derive(Debug, thiserror::Error)]
@@ -25,6 +26,12 @@ pub struct Config {
     pub presets: PresetSection,
     #[serde(default)]
     pub tui: TuiSection,
+    #[serde(default)]
+    pub generations: GenerationsSection,
+    #[serde(default)]
+    pub profile: ProfileSection,
+    #[serde(default)]
+    pub hooks: HooksSection,
 }
 
 impl Config {
@@ -44,20 +51,68 @@ impl Config {
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct MicaSection {
     pub version: String,
+    #[serde(default)]
+    pub timestamp: TimestampMode,
 }
 
 impl Default for MicaSection {
     fn default() -> Self {
         MicaSection {
             version: "0.1.0".to_string(),
+            timestamp: TimestampMode::default(),
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TimestampMode {
+    #[default]
+    Now,
+    Omit,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct NixpkgsSection {
     pub default_url: String,
     pub default_branch: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub local_clones: BTreeMap<String, String>,
+    #[serde(default)]
+    pub mirror_clones: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub url_rewrites: Vec<UrlRewrite>,
+    #[serde(default = "default_substituters")]
+    pub substituters: Vec<String>,
+}
+
+fn default_substituters() -> Vec<String> {
+    vec!["https://cache.nixos.org".to_string()]
+}
+
+impl NixpkgsSection {
+    pub fn rewrite_url(&self, url: &str) -> String {
+        apply_url_rewrites(&self.url_rewrites, url)
+    }
+}
+
+/// Replaces the prefix of the first matching rule; rules with an empty `from` are ignored.
+pub fn apply_url_rewrites(rules: &[UrlRewrite], url: &str) -> String {
+    for rule in rules {
+        if rule.from.is_empty() {
+            continue;
+        }
+        if let Some(rest) = url.strip_prefix(&rule.from) {
+            return format!("{}{}", rule.to, rest);
+        }
+    }
+    url.to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UrlRewrite {
+    pub from: String,
+    pub to: String,
 }
 
 impl Default for NixpkgsSection {
@@ -65,6 +120,10 @@ impl Default for NixpkgsSection {
         NixpkgsSection {
             default_url: "https://github.com/jpetrucciani/nix".to_string(),
             default_branch: "main".to_string(),
+            local_clones: BTreeMap::new(),
+            mirror_clones: false,
+            url_rewrites: Vec::new(),
+            substituters: default_substituters(),
         }
     }
 }
@@ -73,6 +132,10 @@ impl Default for NixpkgsSection {
 pub struct IndexSection {
     pub remote_url: String,
     pub update_check_interval: u64,
+    #[serde(default)]
+    pub skip_commit_dates: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shared_dir: Option<String>,
 }
 
 impl Default for IndexSection {
@@ -80,6 +143,8 @@ impl Default for IndexSection {
         IndexSection {
             remote_url: "https://static.g7c.us/mica".to_string(),
             update_check_interval: 24,
+            skip_commit_dates: false,
+            shared_dir: None,
         }
     }
 }
@@ -90,12 +155,62 @@ pub struct PresetSection {
     pub extra_dirs: Vec<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct ProfileSection {
+    #[serde(default)]
+    pub backend: ProfileBackend,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum ProfileBackend {
+    #[default]
+    NixEnv,
+    NixProfile,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct GenerationsSection {
+    #[serde(default)]
+    pub record: GenerationRecordMode,
+    #[serde(default)]
+    pub min_interval_minutes: u64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum GenerationRecordMode {
+    #[default]
+    Always,
+    Changed,
+    Off,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct HooksSection {
+    #[serde(default)]
+    pub post_update: PostUpdateHook,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub copy_to: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PostUpdateHook {
+    #[default]
+    Off,
+    Build,
+    Copy,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TuiSection {
     pub show_details: bool,
     pub search_mode: SearchMode,
     #[serde(default)]
     pub columns: TuiColumns,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub filters: Vec<FilterChip>,
 }
 
 impl Default for TuiSection {
@@ -104,10 +219,28 @@ impl Default for TuiSection {
             show_details: true,
             search_mode: SearchMode::All,
             columns: TuiColumns::default(),
+            filters: Vec::new(),
         }
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct FilterChip {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub license: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub platform: String,
+    #[serde(default)]
+    pub broken: bool,
+    #[serde(default)]
+    pub insecure: bool,
+    #[serde(default)]
+    pub installed_only: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct TuiColumns {
     pub version: bool,
@@ -141,13 +274,29 @@ pub enum SearchMode {
 
 #[cfg(test)]
 mod tests {
-    use crate::config::{Config, SearchMode};
+    use crate::config::{Config, FilterChip, PostUpdateHook, SearchMode, UrlRewrite};
 
     #[test]
     fn config_round_trip() {
         let mut config = Config::default();
         config.tui.search_mode = SearchMode::Binary;
         config.presets.extra_dirs = vec!["~/my-presets".to_string()];
+        config.nixpkgs.local_clones.insert(
+            "https://github.com/NixOS/nixpkgs".to_string(),
+            "~/src/nixpkgs".to_string(),
+        );
+        config.nixpkgs.mirror_clones = true;
+        config.hooks.post_update = PostUpdateHook::Copy;
+        config.hooks.copy_to = Some("s3://nix-cache".to_string());
+        config.nixpkgs.url_rewrites.push(UrlRewrite {
+            from: "https://github.com/".to_string(),
+            to: "https://mirror.example.com/github/".to_string(),
+        });
+        config.tui.filters.push(FilterChip {
+            name: "darwin-safe".to_string(),
+            platform: "aarch64-darwin".to_string(),
+            ..FilterChip::default()
+        });
 
         let toml = toml::to_string(&config).expect("serialize failed");
         let decoded: Config = toml::from_str(&toml).expect("deserialize failed");
@@ -159,4 +308,36 @@ mod tests {
         let config = Config::default();
         assert_eq!(config.index.remote_url, "https://static.g7c.us/mica");
     }
+
+    #[test]
+    fn rewrite_url_applies_first_matching_prefix() {
+        let mut config = Config::default();
+        config.nixpkgs.url_rewrites = vec![
+            UrlRewrite {
+                from: "https://github.com/NixOS/".to_string(),
+                to: "https://proxy.internal/nixos/".to_string(),
+            },
+            UrlRewrite {
+                from: "https://github.com/".to_string(),
+                to: "https://proxy.internal/github/".to_string(),
+            },
+        ];
+
+        assert_eq!(
+            config
+                .nixpkgs
+                .rewrite_url("https://github.com/NixOS/nixpkgs/archive/abc.tar.gz"),
+            "https://proxy.internal/nixos/nixpkgs/archive/abc.tar.gz"
+        );
+        assert_eq!(
+            config
+                .nixpkgs
+                .rewrite_url("https://github.com/jpetrucciani/nix/archive/abc.tar.gz"),
+            "https://proxy.internal/github/jpetrucciani/nix/archive/abc.tar.gz"
+        );
+        assert_eq!(
+            config.nixpkgs.rewrite_url("https://example.com/x.tar.gz"),
+            "https://example.com/x.tar.gz"
+        );
+    }
 }
@@ -0,0 +1,129 @@
+pub fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let mut dp = vec![vec![0usize; new_lines.len() + 1]; old_lines.len() + 1];
+
+    for i in (0..old_lines.len()).rev() {
+        for j in (0..new_lines.len()).rev() {
+            if old_lines[i] == new_lines[j] {
+                dp[i][j] = dp[i + 1][j + 1] + 1;
+            } else {
+                dp[i][j] = dp[i + 1][j].max(dp[i][j + 1]);
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut i = 0;
+    let mut j = 0;
+    while i < old_lines.len() && j < new_lines.len() {
+        if old_lines[i] == new_lines[j] {
+            out.push(format!("  {}", old_lines[i]));
+            i += 1;
+            j += 1;
+        } else if dp[i + 1][j] >= dp[i][j + 1] {
+            out.push(format!("- {}", old_lines[i]));
+            i += 1;
+        } else {
+            out.push(format!("+ {}", new_lines[j]));
+            j += 1;
+        }
+    }
+
+    while i < old_lines.len() {
+        out.push(format!("- {}", old_lines[i]));
+        i += 1;
+    }
+    while j < new_lines.len() {
+        out.push(format!("+ {}", new_lines[j]));
+        j += 1;
+    }
+
+    out
+}
+
+pub fn diff_lines_changes_only(old: &str, new: &str) -> Vec<String> {
+    diff_lines(old, new)
+        .into_iter()
+        .filter(|line| line.starts_with('+') || line.starts_with('-'))
+        .collect()
+}
+
+pub fn diff_hunks(old: &str, new: &str, context: usize) -> Vec<String> {
+    let lines = diff_lines(old, new);
+    let changed: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| !line.starts_with("  "))
+        .map(|(idx, _)| idx)
+        .collect();
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for idx in changed {
+        let start = idx.saturating_sub(context);
+        let end = (idx + context + 1).min(lines.len());
+        match ranges.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => ranges.push((start, end)),
+        }
+    }
+
+    let mut out = Vec::new();
+    let mut old_line = 1;
+    let mut new_line = 1;
+    let mut cursor = 0;
+    for (start, end) in ranges {
+        for line in &lines[cursor..start] {
+            if !line.starts_with('+') {
+                old_line += 1;
+            }
+            if !line.starts_with('-') {
+                new_line += 1;
+            }
+        }
+        let hunk = &lines[start..end];
+        let old_count = hunk.iter().filter(|line| !line.starts_with('+')).count();
+        let new_count = hunk.iter().filter(|line| !line.starts_with('-')).count();
+        out.push(format!(
+            "@@ -{},{} +{},{} @@",
+            old_line, old_count, new_line, new_count
+        ));
+        out.extend(hunk.iter().cloned());
+        old_line += old_count;
+        new_line += new_count;
+        cursor = end;
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diff::{diff_hunks, diff_lines, diff_lines_changes_only};
+
+    #[test]
+    fn diff_marks_added_and_removed_lines() {
+        let old = "a\nb\nc\n";
+        let new = "a\nc\nd\n";
+        assert_eq!(diff_lines(old, new), vec!["  a", "- b", "  c", "+ d"]);
+        assert_eq!(diff_lines_changes_only(old, new), vec!["- b", "+ d"]);
+    }
+
+    #[test]
+    fn diff_hunks_keep_context_around_changes() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\nten\n";
+        assert_eq!(
+            diff_hunks(old, new, 1),
+            vec![
+                "@@ -2,3 +2,3 @@",
+                "  2",
+                "- 3",
+                "+ three",
+                "  4",
+                "@@ -9,1 +9,2 @@",
+                "  9",
+                "+ ten",
+            ]
+        );
+        assert!(diff_hunks(old, old, 3).is_empty());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::nixgen::escape_nix_string;
+use crate::state::{Pin, StateError, MICA_VERSION};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+pub const EXPORT_FOOTER_BEGIN: &str = "# mica:export:begin";
+pub const EXPORT_FOOTER_END: &str = "# mica:export:end";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportFooter {
+    pub state_sha256: String,
+    pub pin: Pin,
+}
+
+impl ExportFooter {
+    pub fn new<T: Serialize>(state: &T, pin: &Pin) -> Result<Self, StateError> {
+        Ok(ExportFooter {
+            state_sha256: state_sha256(state)?,
+            pin: pin.clone(),
+        })
+    }
+}
+
+pub fn state_sha256<T: Serialize>(state: &T) -> Result<String, StateError> {
+    let mut value = toml::Value::try_from(state).map_err(StateError::Serialize)?;
+    if let Some(table) = value.as_table_mut() {
+        table.remove("mica");
+        // Project files do not record when a pin was updated; loading fills
+        // it in from the generation time, so it is metadata like `mica`.
+        if let Some(pin) = table.get_mut("pin").and_then(toml::Value::as_table_mut) {
+            pin.remove("updated");
+        }
+        if let Some(pins) = table.get_mut("pins").and_then(toml::Value::as_table_mut) {
+            for (_, pin) in pins.iter_mut() {
+                if let Some(pin) = pin.as_table_mut() {
+                    pin.remove("updated");
+                }
+            }
+        }
+    }
+    let content = toml::to_string(&value).map_err(StateError::Serialize)?;
+    Ok(sha256_hex(&content))
+}
+
+pub fn sha256_hex(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+pub fn render_export(nix: &str, footer: &ExportFooter, check: bool) -> String {
+    let body = if check {
+        wrap_with_check(nix, &footer.pin)
+    } else {
+        nix.trim_end().to_string()
+    };
+    let mut output = body.clone();
+    output.push('\n');
+    output.push_str(EXPORT_FOOTER_BEGIN);
+    output.push('\n');
+    output.push_str(&format!("# mica-version: {}\n", MICA_VERSION));
+    output.push_str(&format!("# state-sha256: {}\n", footer.state_sha256));
+    output.push_str(&format!("# pin-sha256: {}\n", footer.pin.sha256));
+    output.push_str(&format!("# content-sha256: {}\n", sha256_hex(&body)));
+    output.push_str(EXPORT_FOOTER_END);
+    output.push('\n');
+    output
+}
+
+fn wrap_with_check(nix: &str, pin: &Pin) -> String {
+    let mut output = String::new();
+    // Project files are functions of their pins, the global profile is a
+    // plain expression, so only call the body when it takes arguments.
+    output.push_str("{ ... }@args:\nlet\n  body = (\n");
+    output.push_str(nix.trim_end());
+    output.push_str("\n  );\n");
+    output.push_str("  result = if builtins.isFunction body then body args else body;\n");
+    output.push_str("  source = builtins.readFile __curPos.file;\n");
+    output.push_str(&format!(
+        "  parts = builtins.split \"\\n{}\\n\" source;\n",
+        EXPORT_FOOTER_BEGIN
+    ));
+    output.push_str("  content = builtins.head parts;\n");
+    output.push_str(
+        "  footer = if builtins.length parts < 3 then \"\" else builtins.elemAt parts 2;\n",
+    );
+    output.push_str("  recorded = builtins.match \".*# content-sha256: ([0-9a-f]+).*\"\n");
+    output.push_str("    (builtins.replaceStrings [ \"\\n\" ] [ \" \" ] footer);\n");
+    output.push_str("in\nresult // {\n  micaCheck =\n");
+    output.push_str("    if recorded == null || builtins.hashString \"sha256\" content != builtins.head recorded then\n");
+    output.push_str("      throw \"mica export: file changed since it was exported\"\n");
+    output.push_str("    else\n      builtins.pathExists (builtins.fetchTarball {\n");
+    output.push_str(&format!(
+        "        url = \"{}\";\n",
+        escape_nix_string(&pin.tarball_url())
+    ));
+    output.push_str(&format!(
+        "        sha256 = \"{}\";\n",
+        escape_nix_string(&pin.sha256)
+    ));
+    output.push_str("      });\n}");
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::{render_export, sha256_hex, state_sha256, ExportFooter};
+    use crate::state::test_state;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn export_footer_records_hashes_and_check_wraps_expression() {
+        let mut state = test_state();
+        let hash = state_sha256(&state).unwrap();
+        assert_eq!(
+            sha256_hex("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        state.mica.modified = Utc.with_ymd_and_hms(2026, 2, 1, 0, 0, 0).unwrap();
+        state.pin.updated = state.mica.modified.date_naive();
+        assert_eq!(state_sha256(&state).unwrap(), hash);
+        state.packages.added.push("jq".to_string());
+        assert_ne!(state_sha256(&state).unwrap(), hash);
+
+        let footer = ExportFooter::new(&state, &state.pin).unwrap();
+        let nix = "{ pkgs ? import <nixpkgs> {} }:\npkgs.mkShell {}\n";
+        let plain = render_export(nix, &footer, false);
+        let (body, rest) = plain.split_once("\n# mica:export:begin\n").unwrap();
+        assert_eq!(body, nix.trim_end());
+        assert!(rest.contains(&format!("# state-sha256: {}\n", footer.state_sha256)));
+        assert!(rest.contains(&format!("# pin-sha256: {}\n", state.pin.sha256)));
+        assert!(rest.contains(&format!("# content-sha256: {}\n", sha256_hex(body))));
+        assert!(rest.ends_with("# mica:export:end\n"));
+
+        let checked = render_export(nix, &footer, true);
+        let (body, rest) = checked.split_once("\n# mica:export:begin\n").unwrap();
+        assert!(body.starts_with("{ ... }@args:\n"));
+        assert!(body.contains("micaCheck"));
+        assert!(
+            body.contains("url = \"https://github.com/NixOS/nixpkgs/archive/deadbeef.tar.gz\";")
+        );
+        assert!(rest.contains(&format!("# content-sha256: {}\n", sha256_hex(body))));
+        assert!(body.contains("result = if builtins.isFunction body then body args else body;"));
+    }
+
+    #[test]
+    fn check_wraps_global_profile_expression() {
+        let state = test_state();
+        let footer = ExportFooter::new(&state, &state.pin).unwrap();
+        let profile = "let\n  pkgs = import <nixpkgs> {};\nin\npkgs.buildEnv { name = \"mica-profile\"; paths = [ ]; }\n";
+        let checked = render_export(profile, &footer, true);
+        let (body, _) = checked.split_once("\n# mica:export:begin\n").unwrap();
+        assert!(body.starts_with("{ ... }@args:\nlet\n  body = (\nlet\n  pkgs"));
+        assert!(!body.contains(") args;"));
+        assert!(body.contains("if builtins.isFunction body then body args else body"));
+    }
+}
@@ -0,0 +1,85 @@
+use crate::nixgen::generate_project_nix;
+use crate::preset::{merge_presets, MergedResult};
+use crate::state::{
+    BuildEnvState, ManifestState, MicaMetadata, PackagesState, Pin, PresetState, ProjectState,
+    ShellState,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use std::collections::BTreeMap;
+
+fn fixed_timestamp() -> DateTime<Utc> {
+    DateTime::from_timestamp(1_767_225_600, 0).unwrap_or_default()
+}
+
+pub fn synthetic_project_state(packages: usize) -> ProjectState {
+    let mut env = BTreeMap::new();
+    for idx in 0..packages / 50 {
+        env.insert(format!("MICA_VAR_{}", idx), format!("value-{}", idx));
+    }
+    ProjectState {
+        mica: MicaMetadata {
+            version: "0.1.0".to_string(),
+            created: fixed_timestamp(),
+            modified: fixed_timestamp(),
+        },
+        pin: Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: "0123456789abcdef0123456789abcdef01234567".to_string(),
+            sha256: "0000000000000000000000000000000000000000000000000000".to_string(),
+            branch: "nixpkgs-unstable".to_string(),
+            updated: NaiveDate::from_ymd_opt(2026, 1, 1).unwrap_or_default(),
+            channel: None,
+        },
+        pins: BTreeMap::new(),
+        presets: PresetState::default(),
+        packages: PackagesState {
+            added: (0..packages).map(synthetic_attr).collect(),
+            removed: Vec::new(),
+            pinned: BTreeMap::new(),
+            priorities: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+        },
+        env,
+        shell: ShellState {
+            hook: Some("echo \"{{project_name}} ready\"".to_string()),
+        },
+        nix: Default::default(),
+        extends: None,
+        build_env: BuildEnvState::default(),
+        manifest: ManifestState::default(),
+    }
+}
+
+pub fn synthetic_attr(idx: usize) -> String {
+    format!("pkg{:05}", idx)
+}
+
+pub fn synthetic_merged(state: &ProjectState) -> MergedResult {
+    merge_presets(&[], state)
+}
+
+pub fn synthetic_project_nix(packages: usize) -> String {
+    let state = synthetic_project_state(packages);
+    generate_project_nix(
+        &state,
+        &synthetic_merged(&state),
+        "bench",
+        fixed_timestamp(),
+    )
+}
+
+pub fn synthetic_nix_edit(content: &str) -> String {
+    let mut output = String::new();
+    for (idx, line) in content.lines().enumerate() {
+        if idx % 97 == 0 {
+            continue;
+        }
+        output.push_str(line);
+        output.push('\n');
+        if idx % 89 == 0 {
+            output.push_str(&format!("    extra{:05}\n", idx));
+        }
+    }
+    output
+}
@@ -1,7 +1,14 @@
 //! Core library for Mica.
 
 pub mod config;
+pub mod diff;
+pub mod export;
+#[cfg(feature = "bench")]
+pub mod fixtures;
+pub mod merge;
+pub mod migrate;
 pub mod nixgen;
 pub mod nixparse;
 pub mod preset;
+pub mod registry;
 pub mod state;
@@ -0,0 +1,445 @@
+use crate::state::{NixBlocks, Pin, ProjectState};
+use std::collections::BTreeSet;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MergeSection {
+    Pin,
+    Pins,
+    Packages,
+    Presets,
+    Env,
+    ShellHook,
+    BuildEnv,
+    Extends,
+    Manifest,
+    NixBlocks,
+}
+
+pub const MERGE_SECTIONS: [MergeSection; 10] = [
+    MergeSection::Pin,
+    MergeSection::Pins,
+    MergeSection::Packages,
+    MergeSection::Presets,
+    MergeSection::Env,
+    MergeSection::ShellHook,
+    MergeSection::BuildEnv,
+    MergeSection::Extends,
+    MergeSection::Manifest,
+    MergeSection::NixBlocks,
+];
+
+impl MergeSection {
+    pub fn name(self) -> &'static str {
+        match self {
+            MergeSection::Pin => "pin",
+            MergeSection::Pins => "pins",
+            MergeSection::Packages => "packages",
+            MergeSection::Presets => "presets",
+            MergeSection::Env => "env",
+            MergeSection::ShellHook => "shell hook",
+            MergeSection::BuildEnv => "buildEnv",
+            MergeSection::Extends => "extends",
+            MergeSection::Manifest => "manifest",
+            MergeSection::NixBlocks => "raw nix",
+        }
+    }
+
+    fn same(self, left: &ProjectState, right: &ProjectState) -> bool {
+        match self {
+            MergeSection::Pin => same_pin(&left.pin, &right.pin),
+            MergeSection::Pins => {
+                left.pins.len() == right.pins.len()
+                    && left.pins.iter().all(|(name, pin)| {
+                        right
+                            .pins
+                            .get(name)
+                            .is_some_and(|other| same_pin(pin, other))
+                    })
+            }
+            MergeSection::Packages => {
+                same_set(&left.packages.added, &right.packages.added)
+                    && same_set(&left.packages.removed, &right.packages.removed)
+                    && left.packages.pinned == right.packages.pinned
+                    && left.packages.priorities == right.packages.priorities
+                    && left.packages.overrides == right.packages.overrides
+            }
+            MergeSection::Presets => same_set(&left.presets.active, &right.presets.active),
+            MergeSection::Env => left.env == right.env,
+            MergeSection::ShellHook => left.shell.hook == right.shell.hook,
+            MergeSection::BuildEnv => left.build_env == right.build_env,
+            MergeSection::Extends => left.extends == right.extends,
+            MergeSection::Manifest => left.manifest == right.manifest,
+            MergeSection::NixBlocks => left.nix == right.nix,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergeChoice {
+    Ours,
+    Theirs,
+    Both,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionMerge {
+    pub section: MergeSection,
+    pub ours_changed: bool,
+    pub theirs_changed: bool,
+}
+
+impl SectionMerge {
+    pub fn is_conflict(&self) -> bool {
+        self.ours_changed && self.theirs_changed
+    }
+
+    pub fn default_choice(&self) -> MergeChoice {
+        if self.ours_changed {
+            MergeChoice::Ours
+        } else {
+            MergeChoice::Theirs
+        }
+    }
+}
+
+pub fn plan_merge(
+    base: &ProjectState,
+    ours: &ProjectState,
+    theirs: &ProjectState,
+) -> Vec<SectionMerge> {
+    MERGE_SECTIONS
+        .into_iter()
+        .filter(|section| !section.same(ours, theirs))
+        .map(|section| SectionMerge {
+            section,
+            ours_changed: !section.same(base, ours),
+            theirs_changed: !section.same(base, theirs),
+        })
+        .collect()
+}
+
+pub fn merge_section(
+    merged: &mut ProjectState,
+    ours: &ProjectState,
+    section: MergeSection,
+    choice: MergeChoice,
+) {
+    match (section, choice) {
+        (_, MergeChoice::Theirs) => {}
+        // A single pin or parent path cannot be combined, so both keeps ours.
+        (MergeSection::Pin, _) => merged.pin = ours.pin.clone(),
+        (MergeSection::Extends, _) => merged.extends = ours.extends.clone(),
+        (MergeSection::Pins, MergeChoice::Ours) => merged.pins = ours.pins.clone(),
+        (MergeSection::Pins, MergeChoice::Both) => {
+            for (name, pin) in &ours.pins {
+                merged.pins.insert(name.clone(), pin.clone());
+            }
+        }
+        (MergeSection::Manifest, MergeChoice::Ours) => merged.manifest = ours.manifest.clone(),
+        (MergeSection::NixBlocks, MergeChoice::Ours) => merged.nix = ours.nix.clone(),
+        (MergeSection::NixBlocks, MergeChoice::Both) => {
+            merge_nix_blocks(&mut merged.nix, &ours.nix)
+        }
+        (MergeSection::Manifest, MergeChoice::Both) => {
+            let manifest = &mut merged.manifest;
+            manifest.description = ours
+                .manifest
+                .description
+                .clone()
+                .or(manifest.description.take());
+            manifest.owner = ours.manifest.owner.clone().or(manifest.owner.take());
+        }
+        (MergeSection::Packages, MergeChoice::Ours) => merged.packages = ours.packages.clone(),
+        (MergeSection::Presets, MergeChoice::Ours) => merged.presets = ours.presets.clone(),
+        (MergeSection::Env, MergeChoice::Ours) => merged.env = ours.env.clone(),
+        (MergeSection::ShellHook, MergeChoice::Ours) => merged.shell.hook = ours.shell.hook.clone(),
+        (MergeSection::BuildEnv, MergeChoice::Ours) => merged.build_env = ours.build_env.clone(),
+        (MergeSection::Packages, MergeChoice::Both) => {
+            let packages = &mut merged.packages;
+            union_into(&mut packages.added, &ours.packages.added);
+            union_into(&mut packages.removed, &ours.packages.removed);
+            let added = packages.added.clone();
+            packages.removed.retain(|attr| !added.contains(attr));
+            for (attr, pinned) in &ours.packages.pinned {
+                packages.pinned.insert(attr.clone(), pinned.clone());
+            }
+            for (attr, priority) in &ours.packages.priorities {
+                packages.priorities.insert(attr.clone(), *priority);
+            }
+            for (attr, snippet) in &ours.packages.overrides {
+                packages.overrides.insert(attr.clone(), snippet.clone());
+            }
+        }
+        (MergeSection::Presets, MergeChoice::Both) => {
+            union_into(&mut merged.presets.active, &ours.presets.active);
+        }
+        (MergeSection::Env, MergeChoice::Both) => {
+            for (key, value) in &ours.env {
+                merged.env.insert(key.clone(), value.clone());
+            }
+        }
+        (MergeSection::BuildEnv, MergeChoice::Both) => {
+            let build_env = &mut merged.build_env;
+            union_into(&mut build_env.extra_outputs, &ours.build_env.extra_outputs);
+            build_env.name = ours.build_env.name.clone().or(build_env.name.take());
+            build_env.priority = ours.build_env.priority.or(build_env.priority);
+            build_env.ignore_collisions |= ours.build_env.ignore_collisions;
+        }
+        (MergeSection::ShellHook, MergeChoice::Both) => {
+            merged.shell.hook = match (merged.shell.hook.take(), &ours.shell.hook) {
+                (Some(theirs), Some(ours)) if theirs.trim() != ours.trim() => {
+                    Some(format!("{}\n{}", theirs.trim_end(), ours.trim_start()))
+                }
+                (theirs, ours) => theirs.or_else(|| ours.clone()),
+            };
+        }
+    }
+}
+
+/// Builds the merged state from `theirs`, applying each section choice and
+/// keeping any of our extra pins that the merged packages still reference.
+pub fn merge_states(
+    ours: &ProjectState,
+    theirs: &ProjectState,
+    choices: impl IntoIterator<Item = (MergeSection, MergeChoice)>,
+) -> ProjectState {
+    let mut merged = theirs.clone();
+    for (section, choice) in choices {
+        merge_section(&mut merged, ours, section, choice);
+    }
+    let referenced: Vec<String> = merged
+        .packages
+        .added
+        .iter()
+        .filter_map(|attr| attr.split_once('.').map(|(pin, _)| pin.to_string()))
+        .filter(|pin| !merged.pins.contains_key(pin))
+        .collect();
+    for name in referenced {
+        if let Some(pin) = ours.pins.get(&name) {
+            merged.pins.insert(name, pin.clone());
+        }
+    }
+    merged.mica = ours.mica.clone();
+    merged
+}
+
+/// Takes each raw block from ours unless only theirs has it. Raw nix cannot be
+/// combined line by line, so a block set on both sides keeps ours.
+fn merge_nix_blocks(merged: &mut NixBlocks, ours: &NixBlocks) {
+    let blocks = [
+        (&mut merged.let_block, &ours.let_block),
+        (&mut merged.pins, &ours.pins),
+        (&mut merged.packages_raw, &ours.packages_raw),
+        (&mut merged.scripts, &ours.scripts),
+        (&mut merged.env_raw, &ours.env_raw),
+        (&mut merged.override_attrs, &ours.override_attrs),
+        (&mut merged.override_merge, &ours.override_merge),
+        (&mut merged.override_shell_hook, &ours.override_shell_hook),
+    ];
+    for (merged, ours) in blocks {
+        if ours.is_some() {
+            *merged = ours.clone();
+        }
+    }
+}
+
+/// Compares pins without `updated`, which is derived from the file's
+/// generation time rather than edited.
+fn same_pin(left: &Pin, right: &Pin) -> bool {
+    left.name == right.name
+        && left.url == right.url
+        && left.rev == right.rev
+        && left.sha256 == right.sha256
+        && left.branch == right.branch
+}
+
+fn same_set(left: &[String], right: &[String]) -> bool {
+    let left: BTreeSet<&String> = left.iter().collect();
+    let right: BTreeSet<&String> = right.iter().collect();
+    left == right
+}
+
+fn union_into(target: &mut Vec<String>, extra: &[String]) {
+    for item in extra {
+        if !target.contains(item) {
+            target.push(item.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::merge::{
+        merge_section, merge_states, plan_merge, MergeChoice, MergeSection, SectionMerge,
+    };
+    use crate::state::{test_state, ProjectState};
+    use chrono::NaiveDate;
+
+    fn base() -> ProjectState {
+        let mut state = test_state();
+        state.packages.added = vec!["jq".to_string()];
+        state.env.insert("A".to_string(), "1".to_string());
+        state.shell.hook = Some("echo hi".to_string());
+        state
+    }
+
+    #[test]
+    fn sections_changed_on_both_sides_are_conflicts() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.packages.added.push("ripgrep".to_string());
+        ours.env.insert("B".to_string(), "ours".to_string());
+        let mut theirs = base.clone();
+        theirs.packages.added.push("fd".to_string());
+        theirs.packages.removed.push("ripgrep".to_string());
+        theirs.shell.hook = Some("echo theirs".to_string());
+
+        let plan = plan_merge(&base, &ours, &theirs);
+        assert_eq!(
+            plan,
+            vec![
+                SectionMerge {
+                    section: MergeSection::Packages,
+                    ours_changed: true,
+                    theirs_changed: true,
+                },
+                SectionMerge {
+                    section: MergeSection::Env,
+                    ours_changed: true,
+                    theirs_changed: false,
+                },
+                SectionMerge {
+                    section: MergeSection::ShellHook,
+                    ours_changed: false,
+                    theirs_changed: true,
+                },
+            ]
+        );
+        assert!(plan[0].is_conflict());
+        assert_eq!(plan[1].default_choice(), MergeChoice::Ours);
+        assert_eq!(plan[2].default_choice(), MergeChoice::Theirs);
+
+        let mut merged = theirs.clone();
+        for entry in &plan {
+            let choice = if entry.is_conflict() {
+                MergeChoice::Both
+            } else {
+                entry.default_choice()
+            };
+            merge_section(&mut merged, &ours, entry.section, choice);
+        }
+        assert_eq!(merged.packages.added, vec!["jq", "fd", "ripgrep"]);
+        assert!(merged.packages.removed.is_empty());
+        assert_eq!(merged.env.get("B").map(String::as_str), Some("ours"));
+        assert_eq!(merged.shell.hook.as_deref(), Some("echo theirs"));
+
+        merged.shell.hook = Some("echo theirs".to_string());
+        merge_section(
+            &mut merged,
+            &ours,
+            MergeSection::ShellHook,
+            MergeChoice::Both,
+        );
+        assert_eq!(merged.shell.hook.as_deref(), Some("echo theirs\necho hi"));
+    }
+
+    #[test]
+    fn pin_only_changes_on_disk_are_reported() {
+        let base = base();
+        let ours = base.clone();
+        let mut theirs = base.clone();
+        theirs.pin.rev = "def".to_string();
+        theirs.extends = Some("../parent".to_string());
+        theirs.manifest.owner = Some("team".to_string());
+        theirs.pins.insert("old".to_string(), base.pin.clone());
+
+        let plan = plan_merge(&base, &ours, &theirs);
+        let sections: Vec<MergeSection> = plan.iter().map(|entry| entry.section).collect();
+        assert_eq!(
+            sections,
+            vec![
+                MergeSection::Pin,
+                MergeSection::Pins,
+                MergeSection::Extends,
+                MergeSection::Manifest,
+            ]
+        );
+        assert!(plan
+            .iter()
+            .all(|entry| entry.theirs_changed && !entry.ours_changed));
+
+        let mut dated = base.clone();
+        dated.pin.updated = NaiveDate::from_ymd_opt(2026, 2, 1).unwrap();
+        assert!(plan_merge(&base, &ours, &dated).is_empty());
+    }
+
+    #[test]
+    fn merged_state_keeps_our_pin_edits_and_referenced_pins() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.pin.rev = "ours".to_string();
+        let mut old = base.pin.clone();
+        old.rev = "old".to_string();
+        ours.pins.insert("old".to_string(), old);
+        ours.packages.added.push("old.hello".to_string());
+        let mut theirs = base.clone();
+        theirs.packages.added.push("fd".to_string());
+        theirs.pins.insert("other".to_string(), base.pin.clone());
+
+        let plan = plan_merge(&base, &ours, &theirs);
+        let choices: Vec<(MergeSection, MergeChoice)> = plan
+            .iter()
+            .map(|entry| {
+                let choice = if entry.section == MergeSection::Packages {
+                    MergeChoice::Both
+                } else if entry.section == MergeSection::Pins {
+                    MergeChoice::Theirs
+                } else {
+                    entry.default_choice()
+                };
+                (entry.section, choice)
+            })
+            .collect();
+        let merged = merge_states(&ours, &theirs, choices);
+        assert_eq!(merged.pin.rev, "ours");
+        assert_eq!(merged.packages.added, vec!["jq", "fd", "old.hello"]);
+        assert_eq!(
+            merged.pins.keys().map(String::as_str).collect::<Vec<_>>(),
+            vec!["old", "other"]
+        );
+    }
+
+    #[test]
+    fn raw_nix_edits_are_offered_for_merge() {
+        let base = base();
+        let mut ours = base.clone();
+        ours.nix.let_block = Some("ours = 1;".to_string());
+        let mut theirs = base.clone();
+        theirs.nix.scripts = Some("hello = pkgs.hello;".to_string());
+        theirs.nix.let_block = Some("theirs = 1;".to_string());
+
+        let plan = plan_merge(&base, &ours, &theirs);
+        assert_eq!(
+            plan,
+            vec![SectionMerge {
+                section: MergeSection::NixBlocks,
+                ours_changed: true,
+                theirs_changed: true,
+            }]
+        );
+
+        let merged = merge_states(
+            &ours,
+            &theirs,
+            [(MergeSection::NixBlocks, MergeChoice::Both)],
+        );
+        assert_eq!(merged.nix.let_block.as_deref(), Some("ours = 1;"));
+        assert_eq!(merged.nix.scripts.as_deref(), Some("hello = pkgs.hello;"));
+        let merged = merge_states(
+            &ours,
+            &theirs,
+            [(MergeSection::NixBlocks, MergeChoice::Theirs)],
+        );
+        assert_eq!(merged.nix, theirs.nix);
+    }
+}
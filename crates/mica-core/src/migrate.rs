@@ -0,0 +1,108 @@
+use crate::nixgen::{layout_line, NIX_LAYOUT_VERSION};
+use crate::nixparse::parse_layout_version;
+
+#[derive(Debug, thiserror::Error)]
+pub enum MigrateError {
+    #[error(
+        "file uses nix layout {found} but this mica only understands up to {supported}, upgrade mica first"
+    )]
+    NewerLayout { found: u32, supported: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayoutMigration {
+    pub from: u32,
+    pub to: u32,
+    pub content: String,
+}
+
+impl LayoutMigration {
+    pub fn changed(&self) -> bool {
+        self.from != self.to
+    }
+}
+
+type MigrationStep = fn(&str) -> String;
+
+// MIGRATIONS[n] upgrades a file from layout n + 1 to layout n + 2.
+const MIGRATIONS: [MigrationStep; 1] = [migrate_v1_to_v2];
+
+pub fn migrate_project_nix(content: &str) -> Result<LayoutMigration, MigrateError> {
+    let from = parse_layout_version(content);
+    if from > NIX_LAYOUT_VERSION {
+        return Err(MigrateError::NewerLayout {
+            found: from,
+            supported: NIX_LAYOUT_VERSION,
+        });
+    }
+    let mut migrated = content.to_string();
+    for step in MIGRATIONS.iter().skip(from.saturating_sub(1) as usize) {
+        migrated = step(&migrated);
+    }
+    Ok(LayoutMigration {
+        from,
+        to: NIX_LAYOUT_VERSION,
+        content: migrated,
+    })
+}
+
+fn migrate_v1_to_v2(content: &str) -> String {
+    set_layout_version(content, 2)
+}
+
+fn set_layout_version(content: &str, version: u32) -> String {
+    let lines: Vec<&str> = content
+        .split_inclusive('\n')
+        .filter(|line| !line.starts_with("# mica:layout "))
+        .collect();
+    let insert_at = lines
+        .iter()
+        .position(|line| !line.starts_with('#') || line.contains("mica:manifest:begin"))
+        .unwrap_or(lines.len());
+    let mut output = String::with_capacity(content.len() + 20);
+    for line in &lines[..insert_at] {
+        output.push_str(line);
+    }
+    output.push_str(&layout_line(version));
+    for line in &lines[insert_at..] {
+        output.push_str(line);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::migrate::{migrate_project_nix, MigrateError};
+    use crate::nixgen::NIX_LAYOUT_VERSION;
+    use crate::nixparse::{parse_layout_version, parse_nix_file};
+
+    const LEGACY: &str = "# Managed by Mica v0.1.0\n# Do not edit sections between mica: markers\n# Manual additions outside markers will be preserved\n# Last generated: 2026-01-01T00:00:00+00:00\n# mica:manifest:begin\n# owner: infra\n# mica:manifest:end\n\n{ pkgs ? import (fetchTarball {\n    # mica:pin:begin\n    url = \"https://github.com/NixOS/nixpkgs/archive/abc.tar.gz\";\n    sha256 = \"0000000000000000000000000000000000000000000000000000\";\n    # mica:pin:end\n  }) {}\n}:\n\nlet\n  # mica:packages:begin\n  tools = with pkgs; [\n    ripgrep\n  ];\n  # mica:packages:end\n  env = pkgs.buildEnv {\n    # mica:env:begin\n    # mica:env:end\n    # mica:shellhook:begin\n    shellHook = ''\n    '';\n    # mica:shellhook:end\n  };\nin\nenv\n";
+
+    #[test]
+    fn legacy_files_gain_a_layout_marker() {
+        assert_eq!(parse_layout_version(LEGACY), 1);
+        let migration = migrate_project_nix(LEGACY).unwrap();
+        assert!(migration.changed());
+        assert_eq!(parse_layout_version(&migration.content), NIX_LAYOUT_VERSION);
+        assert!(migration.content.contains(
+            "# Last generated: 2026-01-01T00:00:00+00:00\n# mica:layout 2\n# mica:manifest:begin\n"
+        ));
+        let before = parse_nix_file(LEGACY).unwrap();
+        let after = parse_nix_file(&migration.content).unwrap();
+        assert_eq!(before.packages_section, after.packages_section);
+        assert_eq!(before.postamble, after.postamble);
+
+        let again = migrate_project_nix(&migration.content).unwrap();
+        assert!(!again.changed());
+        assert_eq!(again.content, migration.content);
+    }
+
+    #[test]
+    fn newer_layouts_are_rejected() {
+        let newer = LEGACY.replacen("# Last generated", "# mica:layout 99\n# Last generated", 1);
+        assert!(matches!(
+            migrate_project_nix(&newer),
+            Err(MigrateError::NewerLayout { found: 99, .. })
+        ));
+    }
+}
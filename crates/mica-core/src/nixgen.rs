@@ -1,8 +1,48 @@
 use crate::preset::{MergedProfileResult, MergedResult};
-use crate::state::{GlobalProfileState, PinnedPackage, ProjectState, NIX_EXPR_PREFIX};
+use crate::state::{
+    BuildEnvState, GlobalProfileState, ManifestState, Pin, PinnedPackage, ProjectState,
+    MICA_VERSION, NIX_EXPR_PREFIX,
+};
 use chrono::{DateTime, Utc};
 use std::collections::{BTreeMap, HashSet};
 
+pub const NIX_LAYOUT_VERSION: u32 = 2;
+
+const GENERATED_AT_PREFIX: &str = "# Last generated:";
+
+pub const RESERVED_PIN_NAMES: &[&str] = &[
+    "assert",
+    "builtins",
+    "else",
+    "env",
+    "excluded",
+    "false",
+    "fetchTarball",
+    "if",
+    "import",
+    "in",
+    "inherit",
+    "let",
+    "name",
+    "null",
+    "or",
+    "parent",
+    "paths",
+    "pkgs",
+    "prioritized",
+    "rec",
+    "scripts",
+    "shellHook",
+    "then",
+    "tools",
+    "true",
+    "with",
+];
+
+pub fn is_reserved_pin_name(name: &str) -> bool {
+    RESERVED_PIN_NAMES.contains(&name)
+}
+
 pub fn generate_project_nix(
     state: &ProjectState,
     merged: &MergedResult,
@@ -10,24 +50,21 @@ pub fn generate_project_nix(
     generated_at: DateTime<Utc>,
 ) -> String {
     let mut output = String::new();
-    output.push_str("# Managed by Mica v0.1.0\n");
+    output.push_str(&format!("# Managed by Mica v{}\n", MICA_VERSION));
     output.push_str("# Do not edit sections between mica: markers\n");
     output.push_str("# Manual additions outside markers will be preserved\n");
     output.push_str(&format!(
-        "# Last generated: {}\n\n",
+        "{} {}\n",
+        GENERATED_AT_PREFIX,
         generated_at.to_rfc3339()
     ));
+    output.push_str(&layout_line(NIX_LAYOUT_VERSION));
+    output.push_str(&render_manifest_header(&state.manifest));
+    output.push('\n');
 
     output.push_str("{ pkgs ? import (fetchTarball {\n");
     output.push_str("    # mica:pin:begin\n");
-    if let Some(name) = &state.pin.name {
-        output.push_str(&format!("    name = \"{}\";\n", escape_nix_string(name)));
-    }
-    output.push_str(&format!(
-        "    url = \"{}/archive/{}.tar.gz\";\n",
-        state.pin.url, state.pin.rev
-    ));
-    output.push_str(&format!("    sha256 = \"{}\";\n", state.pin.sha256));
+    push_fetch_attrs(&mut output, "    ", &state.pin);
     output.push_str("    # mica:pin:end\n");
     output.push_str("  }) {}\n");
     output.push_str("  # mica:pins:begin\n");
@@ -36,17 +73,7 @@ pub fn generate_project_nix(
     for (name, pin) in &state.pins {
         let name = sanitize_nix_identifier(name);
         output.push_str(&format!("  , {} ? import (fetchTarball {{\n", name));
-        if let Some(fetch_name) = &pin.name {
-            output.push_str(&format!(
-                "      name = \"{}\";\n",
-                escape_nix_string(fetch_name)
-            ));
-        }
-        output.push_str(&format!(
-            "      url = \"{}/archive/{}.tar.gz\";\n",
-            pin.url, pin.rev
-        ));
-        output.push_str(&format!("      sha256 = \"{}\";\n", pin.sha256));
+        push_fetch_attrs(&mut output, "      ", pin);
         output.push_str("    }) {}\n");
     }
     for (attr, pinned) in &state.packages.pinned {
@@ -58,14 +85,7 @@ pub fn generate_project_nix(
             "  , pkgs-{} ? import (fetchTarball {{\n",
             var_name
         ));
-        if let Some(name) = &pinned.pin.name {
-            output.push_str(&format!("      name = \"{}\";\n", escape_nix_string(name)));
-        }
-        output.push_str(&format!(
-            "      url = \"{}/archive/{}.tar.gz\";\n",
-            pinned.pin.url, pinned.pin.rev
-        ));
-        output.push_str(&format!("      sha256 = \"{}\";\n", pinned.pin.sha256));
+        push_fetch_attrs(&mut output, "      ", &pinned.pin);
         output.push_str("    }) {}\n");
     }
     let mut filtered_pin_blocks = Vec::new();
@@ -86,6 +106,11 @@ pub fn generate_project_nix(
         "  name = \"{}\";\n\n",
         escape_nix_string(project_name)
     ));
+    if let Some(parent) = &state.extends {
+        output.push_str("  # mica:extends:begin\n");
+        output.push_str(&format!("  {}\n", parent_import_line(parent)));
+        output.push_str("  # mica:extends:end\n\n");
+    }
     output.push_str("  # mica:let:begin\n");
     write_blocks(&mut output, "  ", &merged.let_blocks);
     output.push_str("  # mica:let:end\n\n");
@@ -125,28 +150,150 @@ pub fn generate_project_nix(
     output.push_str("    # mica:packages-raw:begin\n");
     write_blocks(&mut output, "    ", &merged.packages_raw_blocks);
     output.push_str("    # mica:packages-raw:end\n");
-    output.push_str("  ] ++ (pkgs.lib.attrsets.attrValues scripts);\n");
+    if state.extends.is_some() {
+        output.push_str(&format!(
+            "  ] ++ (pkgs.lib.attrsets.attrValues scripts) ++ {};\n",
+            PARENT_PATHS_EXPR
+        ));
+    } else {
+        output.push_str("  ] ++ (pkgs.lib.attrsets.attrValues scripts);\n");
+    }
     output.push_str("  # mica:packages:end\n\n");
-    output.push_str("  paths = pkgs.lib.flatten [ tools ];\n");
+    if !state.packages.removed.is_empty() {
+        output.push_str("  # mica:exclude:begin\n");
+        output.push_str(
+            "  excluded = map (attr: pkgs.lib.attrByPath (pkgs.lib.splitString \".\" attr) null pkgs) [\n",
+        );
+        for pkg in &state.packages.removed {
+            output.push_str(&format!("    \"{}\"\n", escape_nix_string(pkg)));
+        }
+        output.push_str("  ];\n");
+        output.push_str("  # mica:exclude:end\n");
+    }
+    let package_expr = |attr: &str| match state.packages.pinned.get(attr) {
+        Some(_) => format!(
+            "pkgs-{}.{}",
+            pinned_var_names
+                .get(attr)
+                .cloned()
+                .unwrap_or_else(|| sanitize_var_name(attr)),
+            attr
+        ),
+        None => format!("pkgs.{}", attr),
+    };
+    let overrides: Vec<(&String, &String)> = state
+        .packages
+        .overrides
+        .iter()
+        .filter(|(attr, snippet)| {
+            !state.packages.removed.contains(attr) && !snippet.trim().is_empty()
+        })
+        .collect();
+    if !overrides.is_empty() {
+        output.push_str("  # mica:overrides:begin\n");
+        output.push_str("  overridden = {\n");
+        for (attr, snippet) in overrides.iter().copied() {
+            output.push_str(&format!(
+                "    \"{}\" = {}.overrideAttrs (\n",
+                escape_nix_string(attr),
+                package_expr(attr)
+            ));
+            for line in snippet.trim().lines() {
+                if line.trim().is_empty() {
+                    output.push('\n');
+                } else {
+                    output.push_str(&format!("      {}\n", line.trim_end()));
+                }
+            }
+            output.push_str("    );\n");
+        }
+        output.push_str("  };\n");
+        output.push_str("  replaced = [\n");
+        for (attr, _) in overrides.iter().copied() {
+            output.push_str(&format!("    {}\n", package_expr(attr)));
+        }
+        output.push_str("  ];\n");
+        output.push_str("  # mica:overrides:end\n");
+    }
+    let priorities: Vec<(&String, &i32)> = state
+        .packages
+        .priorities
+        .iter()
+        .filter(|(attr, _)| !state.packages.removed.contains(attr))
+        .collect();
+    if !priorities.is_empty() {
+        output.push_str("  # mica:priorities:begin\n");
+        output.push_str("  prioritized = [\n");
+        for (attr, priority) in priorities.iter().copied() {
+            let source = if overrides.iter().any(|(overridden, _)| *overridden == attr) {
+                format!("overridden.\"{}\"", escape_nix_string(attr))
+            } else {
+                package_expr(attr)
+            };
+            output.push_str(&format!(
+                "    (pkgs.lib.meta.setPrio {} {})\n",
+                priority, source
+            ));
+        }
+        output.push_str("  ];\n");
+        output.push_str("  # mica:priorities:end\n");
+    }
+    output.push_str(&project_paths_line(
+        !state.packages.removed.is_empty(),
+        !priorities.is_empty(),
+        !overrides.is_empty(),
+    ));
     output.push_str("  env = pkgs.buildEnv {\n");
-    output.push_str("    inherit name paths; buildInputs = paths;\n");
+    output.push_str(build_env_inherit_line(state.build_env.name.is_some()));
+    if !state.build_env.is_default() {
+        output.push_str("    # mica:buildenv:begin\n");
+        output.push_str(&render_build_env_settings(&state.build_env));
+        output.push_str("    # mica:buildenv:end\n");
+    }
     output.push_str("    # mica:env:begin\n");
+    let vars = TemplateVars {
+        project_name,
+        pin_rev: &state.pin.rev,
+    };
     for (key, value) in &merged.env {
-        output.push_str(&format!("    {} = {};\n", key, render_nix_env_value(value)));
+        if has_template_vars(value) {
+            output.push_str(&format!("    {}\n", template_marker(value)));
+        }
+        output.push_str(&format!(
+            "    {} = {};\n",
+            key,
+            render_templated_env_value(value, &vars)
+        ));
+    }
+    if state.extends.is_some() && !merged.inherited_env.is_empty() {
+        output.push_str(&format!(
+            "    inherit (parent) {};\n",
+            merged.inherited_env.join(" ")
+        ));
     }
     output.push_str("    # mica:env-raw:begin\n");
     write_blocks(&mut output, "    ", &merged.env_raw_blocks);
     output.push_str("    # mica:env-raw:end\n");
     output.push_str("    # mica:env:end\n\n");
     output.push_str("    # mica:shellhook:begin\n");
-    if !merged.shell_hooks.is_empty() {
+    if !merged.shell_hooks.is_empty() || state.extends.is_some() {
         output.push_str("    shellHook = ''\n");
+        if state.extends.is_some() {
+            output.push_str(PARENT_SHELL_HOOK_EXPR);
+            output.push('\n');
+        }
         for (idx, hook) in merged.shell_hooks.iter().enumerate() {
             if idx > 0 {
                 output.push('\n');
             }
-            output.push_str(hook);
-            if !hook.ends_with('\n') {
+            for line in hook.lines() {
+                if has_template_vars(line) {
+                    output.push_str(&template_marker(line));
+                    output.push('\n');
+                }
+                output.push_str(
+                    &expand_template_vars(line, &vars).replace(ROOT_DIR_TEMPLATE, ROOT_DIR_EXPR),
+                );
                 output.push('\n');
             }
         }
@@ -179,7 +326,229 @@ pub fn generate_project_nix(
     output
 }
 
-fn escape_nix_string(value: &str) -> String {
+pub fn layout_line(version: u32) -> String {
+    format!("# mica:layout {}\n", version)
+}
+
+pub fn render_manifest_header(manifest: &ManifestState) -> String {
+    if manifest.is_default() {
+        return String::new();
+    }
+    let mut output = String::from("# mica:manifest:begin\n");
+    if let Some(description) = &manifest.description {
+        output.push_str(&format!("# description: {}\n", single_line(description)));
+    }
+    if let Some(owner) = &manifest.owner {
+        output.push_str(&format!("# owner: {}\n", single_line(owner)));
+    }
+    output.push_str("# mica:manifest:end\n");
+    output
+}
+
+pub fn replace_manifest_header(preamble: &str, manifest: &ManifestState) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    let mut in_manifest = false;
+    for line in preamble.lines() {
+        if line.contains("mica:manifest:begin") {
+            in_manifest = true;
+            continue;
+        }
+        if in_manifest {
+            in_manifest = !line.contains("mica:manifest:end");
+            continue;
+        }
+        lines.push(line);
+    }
+    let header_end = lines
+        .iter()
+        .position(|line| !line.starts_with('#'))
+        .unwrap_or(lines.len());
+    let mut output = String::new();
+    for line in &lines[..header_end] {
+        output.push_str(line);
+        output.push('\n');
+    }
+    output.push_str(&render_manifest_header(manifest));
+    for line in &lines[header_end..] {
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+fn single_line(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+pub fn project_paths_line(excluded: bool, prioritized: bool, overridden: bool) -> String {
+    let tools = match (excluded, overridden) {
+        (true, true) => {
+            "pkgs.lib.subtractLists (excluded ++ replaced) (pkgs.lib.flatten [ tools ])"
+        }
+        (true, false) => "pkgs.lib.subtractLists excluded (pkgs.lib.flatten [ tools ])",
+        (false, true) => "pkgs.lib.subtractLists replaced (pkgs.lib.flatten [ tools ])",
+        (false, false) => "pkgs.lib.flatten [ tools ]",
+    };
+    let mut prefix = String::new();
+    if prioritized {
+        prefix.push_str("prioritized ++ ");
+    }
+    if overridden {
+        prefix.push_str("pkgs.lib.attrValues overridden ++ ");
+    }
+    format!("  paths = {}{};\n", prefix, tools)
+}
+
+pub fn build_env_inherit_line(overrides_name: bool) -> &'static str {
+    if overrides_name {
+        "    inherit paths; buildInputs = paths;\n"
+    } else {
+        "    inherit name paths; buildInputs = paths;\n"
+    }
+}
+
+fn render_build_env_settings(settings: &BuildEnvState) -> String {
+    let mut output = String::new();
+    if let Some(name) = &settings.name {
+        output.push_str(&format!("    name = \"{}\";\n", escape_nix_string(name)));
+    }
+    if !settings.extra_outputs.is_empty() {
+        let outputs = settings
+            .extra_outputs
+            .iter()
+            .map(|value| format!("\"{}\"", escape_nix_string(value)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        output.push_str(&format!("    extraOutputsToInstall = [ {} ];\n", outputs));
+    }
+    if settings.ignore_collisions {
+        output.push_str("    ignoreCollisions = true;\n");
+    }
+    if let Some(priority) = settings.priority {
+        output.push_str(&format!("    meta.priority = {};\n", priority));
+    }
+    output
+}
+
+pub const PARENT_SHELL_HOOK_EXPR: &str = "${parent.shellHook or \"\"}";
+const PARENT_PATHS_EXPR: &str = "(parent.buildInputs or [ ])";
+
+fn parent_import_line(path: &str) -> String {
+    format!(
+        "parent = import {} {{ inherit pkgs; }};",
+        nix_path_literal(path)
+    )
+}
+
+fn nix_path_literal(path: &str) -> String {
+    let trimmed = path.trim();
+    if trimmed.starts_with('/') || trimmed.starts_with("./") || trimmed.starts_with("../") {
+        trimmed.to_string()
+    } else {
+        format!("./{}", trimmed)
+    }
+}
+
+const ROOT_DIR_TEMPLATE: &str = "{{root_dir}}";
+const ROOT_DIR_EXPR: &str = "${toString ./.}";
+
+struct TemplateVars<'a> {
+    project_name: &'a str,
+    pin_rev: &'a str,
+}
+
+/// Comment recording a channel pin's name, git revision and repository, which
+/// its `nixexprs.tar.xz` URL does not carry.
+const CHANNEL_MARKER: &str = "# mica:channel ";
+
+fn push_fetch_attrs(output: &mut String, indent: &str, pin: &Pin) {
+    if let Some(name) = &pin.name {
+        output.push_str(&format!(
+            "{}name = \"{}\";\n",
+            indent,
+            escape_nix_string(name)
+        ));
+    }
+    if let Some(channel) = &pin.channel {
+        output.push_str(&format!(
+            "{}{}{} {} {}\n",
+            indent, CHANNEL_MARKER, channel.name, pin.rev, pin.url
+        ));
+    }
+    output.push_str(&format!("{}url = \"{}\";\n", indent, pin.tarball_url()));
+    output.push_str(&format!("{}sha256 = \"{}\";\n", indent, pin.sha256));
+}
+
+/// Parses a channel marker into `(channel, rev, repository url)`.
+pub(crate) fn parse_channel_marker(line: &str) -> Option<(String, String, String)> {
+    let rest = line.trim_start().strip_prefix(CHANNEL_MARKER)?;
+    let mut parts = rest.split_whitespace();
+    let channel = parts.next()?.to_string();
+    let rev = parts.next()?.to_string();
+    let url = parts.next()?.to_string();
+    Some((channel, rev, url))
+}
+
+/// Comment written before an expanded line so the parser can recover the
+/// unexpanded template, keeping placeholders live across regeneration.
+const TEMPLATE_MARKER: &str = "# mica:template ";
+
+fn has_template_vars(value: &str) -> bool {
+    ["{{project_name}}", "{{pin_rev}}", ROOT_DIR_TEMPLATE]
+        .iter()
+        .any(|var| value.contains(var))
+}
+
+fn template_marker(raw: &str) -> String {
+    format!(
+        "{}{}",
+        TEMPLATE_MARKER,
+        raw.replace('\\', "\\\\").replace('\n', "\\n")
+    )
+}
+
+pub(crate) fn parse_template_marker(line: &str) -> Option<String> {
+    let encoded = line.trim_start().strip_prefix(TEMPLATE_MARKER)?;
+    let mut raw = String::new();
+    let mut chars = encoded.chars();
+    while let Some(ch) = chars.next() {
+        match (ch, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                raw.push('\n');
+                chars.next();
+            }
+            ('\\', Some('\\')) => {
+                raw.push('\\');
+                chars.next();
+            }
+            _ => raw.push(ch),
+        }
+    }
+    Some(raw)
+}
+
+fn expand_template_vars(value: &str, vars: &TemplateVars<'_>) -> String {
+    value
+        .replace("{{project_name}}", vars.project_name)
+        .replace("{{pin_rev}}", vars.pin_rev)
+}
+
+fn render_templated_env_value(value: &str, vars: &TemplateVars<'_>) -> String {
+    let expanded = expand_template_vars(value, vars);
+    if !expanded.contains(ROOT_DIR_TEMPLATE)
+        || expanded.starts_with(NIX_EXPR_PREFIX)
+        || is_nix_expression_literal(&expanded)
+    {
+        return render_nix_env_value(&expanded.replace(ROOT_DIR_TEMPLATE, ROOT_DIR_EXPR));
+    }
+    let parts: Vec<String> = expanded
+        .split(ROOT_DIR_TEMPLATE)
+        .map(escape_nix_string)
+        .collect();
+    format!("\"{}\"", parts.join(ROOT_DIR_EXPR))
+}
+
+pub(crate) fn escape_nix_string(value: &str) -> String {
     let mut out = value.replace('\\', "\\\\").replace('\"', "\\\"");
     if out.contains("${") {
         out = out.replace("${", "\\${");
@@ -220,11 +589,12 @@ pub fn generate_profile_nix(
     generated_at: DateTime<Utc>,
 ) -> String {
     let mut output = String::new();
-    output.push_str("# Managed by Mica v0.1.0\n");
+    output.push_str(&format!("# Managed by Mica v{}\n", MICA_VERSION));
     output
         .push_str("# Global user profile - install with: nix-env -if ~/.config/mica/profile.nix\n");
     output.push_str(&format!(
-        "# Last generated: {}\n\n",
+        "{} {}\n\n",
+        GENERATED_AT_PREFIX,
         generated_at.to_rfc3339()
     ));
 
@@ -232,14 +602,7 @@ pub fn generate_profile_nix(
     output.push_str("  # mica:pins:begin\n");
     output.push_str("  # Primary nixpkgs\n");
     output.push_str("  pkgs = import (fetchTarball {\n");
-    if let Some(name) = &state.pin.name {
-        output.push_str(&format!("    name = \"{}\";\n", escape_nix_string(name)));
-    }
-    output.push_str(&format!(
-        "    url = \"{}/archive/{}.tar.gz\";\n",
-        state.pin.url, state.pin.rev
-    ));
-    output.push_str(&format!("    sha256 = \"{}\";\n", state.pin.sha256));
+    push_fetch_attrs(&mut output, "    ", &state.pin);
     output.push_str("  }) {};\n");
     let pinned_var_names = build_pinned_var_names(&state.packages.pinned);
     for (attr, pinned) in &state.packages.pinned {
@@ -249,14 +612,7 @@ pub fn generate_profile_nix(
             .unwrap_or_else(|| sanitize_var_name(attr));
         output.push_str(&format!("\n  # Pin for {}\n", attr));
         output.push_str(&format!("  pkgs-{} = import (fetchTarball {{\n", var_name));
-        if let Some(name) = &pinned.pin.name {
-            output.push_str(&format!("    name = \"{}\";\n", escape_nix_string(name)));
-        }
-        output.push_str(&format!(
-            "    url = \"{}/archive/{}.tar.gz\";\n",
-            pinned.pin.url, pinned.pin.rev
-        ));
-        output.push_str(&format!("    sha256 = \"{}\";\n", pinned.pin.sha256));
+        push_fetch_attrs(&mut output, "    ", &pinned.pin);
         output.push_str("  }) {};\n");
     }
     output.push_str("  # mica:pins:end\n\n");
@@ -278,6 +634,12 @@ pub fn generate_profile_nix(
             output.push_str(&format!("    pkgs.{}\n", pkg));
         }
     }
+    for group in &merged.host_packages {
+        output.push_str(&format!("\n    # Host: {}\n", group.host));
+        for pkg in &group.packages {
+            output.push_str(&format!("    pkgs.{}\n", pkg));
+        }
+    }
     for (attr, pinned) in &state.packages.pinned {
         let var_name = pinned_var_names
             .get(attr)
@@ -297,6 +659,41 @@ pub fn generate_profile_nix(
     output
 }
 
+pub fn set_generated_at(content: &str, generated_at: Option<DateTime<Utc>>) -> String {
+    let stamp = generated_at.map(|at| format!("{} {}\n", GENERATED_AT_PREFIX, at.to_rfc3339()));
+    let mut output = String::with_capacity(content.len());
+    let mut in_header = true;
+    for line in content.split_inclusive('\n') {
+        if in_header {
+            let trimmed = line.trim_end();
+            if trimmed.starts_with(GENERATED_AT_PREFIX) {
+                output.push_str(stamp.as_deref().unwrap_or(""));
+                in_header = false;
+                continue;
+            }
+            if !trimmed.starts_with('#') || trimmed.starts_with("# mica:") {
+                output.push_str(stamp.as_deref().unwrap_or(""));
+                in_header = false;
+            }
+        }
+        output.push_str(line);
+    }
+    output
+}
+
+pub fn generated_at(content: &str) -> Option<DateTime<Utc>> {
+    content
+        .lines()
+        .take_while(|line| line.starts_with('#') && !line.starts_with("# mica:"))
+        .find_map(|line| line.strip_prefix(GENERATED_AT_PREFIX))
+        .and_then(|stamp| DateTime::parse_from_rfc3339(stamp.trim()).ok())
+        .map(|stamp| stamp.with_timezone(&Utc))
+}
+
+pub fn same_ignoring_generated_at(left: &str, right: &str) -> bool {
+    set_generated_at(left, None) == set_generated_at(right, None)
+}
+
 fn sanitize_var_name(name: &str) -> String {
     sanitize_nix_identifier(name)
 }
@@ -369,11 +766,19 @@ fn write_blocks(output: &mut String, indent: &str, blocks: &[String]) {
 
 #[cfg(test)]
 mod tests {
-    use crate::nixgen::{generate_profile_nix, generate_project_nix};
-    use crate::preset::{MergedProfileResult, MergedResult};
+    use crate::nixgen::{
+        generate_profile_nix, generate_project_nix, generated_at, replace_manifest_header,
+        same_ignoring_generated_at, set_generated_at,
+    };
+    use crate::nixparse::{
+        parse_nix_file, parse_profile_state_from_nix, parse_project_state_from_nix,
+    };
+    use crate::preset::{merge_profile_presets, MergedProfileResult, MergedResult};
+    use crate::state::test_state;
     use crate::state::{
-        GenerationsState, GlobalProfileState, MicaMetadata, PackagesState, Pin, PinnedPackage,
-        PresetState, ProjectState, ShellState, NIX_EXPR_PREFIX,
+        BuildEnvState, GenerationsState, GlobalProfileState, HostInfo, HostPackages, ManifestState,
+        MicaMetadata, PackagesState, Pin, PinChannel, PinnedPackage, PresetState, ProjectState,
+        NIX_EXPR_PREFIX,
     };
     use chrono::{DateTime, NaiveDate, Utc};
     use std::collections::BTreeMap;
@@ -396,6 +801,7 @@ mod tests {
             sha256: "0123456789abcdef0123456789abcdef0123456789abcdef0123".to_string(),
             branch: "main".to_string(),
             updated: date(),
+            channel: None,
         }
     }
 
@@ -424,6 +830,7 @@ mod tests {
             preset_packages: Vec::new(),
             user_packages: Vec::new(),
             env: BTreeMap::new(),
+            inherited_env: Vec::new(),
             shell_hooks: Vec::new(),
             all_packages: Vec::new(),
             let_blocks: Vec::new(),
@@ -440,22 +847,14 @@ mod tests {
     #[test]
     fn project_generation_uses_unique_vars_for_colliding_pinned_attrs() {
         let state = ProjectState {
-            mica: MicaMetadata {
-                version: "0.1.0".to_string(),
-                created: timestamp(),
-                modified: timestamp(),
-            },
-            pin: base_pin(),
-            pins: BTreeMap::new(),
-            presets: PresetState::default(),
             packages: PackagesState {
                 added: Vec::new(),
                 removed: Vec::new(),
                 pinned: pinned_packages(),
+                priorities: BTreeMap::new(),
+                overrides: BTreeMap::new(),
             },
-            env: BTreeMap::new(),
-            shell: ShellState::default(),
-            nix: Default::default(),
+            ..test_state()
         };
 
         let output = generate_project_nix(
@@ -485,12 +884,16 @@ mod tests {
                 added: Vec::new(),
                 removed: Vec::new(),
                 pinned: pinned_packages(),
+                priorities: BTreeMap::new(),
+                overrides: BTreeMap::new(),
             },
             generations: GenerationsState::default(),
+            hosts: Vec::new(),
         };
         let merged = MergedProfileResult {
             preset_packages: Vec::new(),
             user_packages: Vec::new(),
+            host_packages: Vec::new(),
             all_packages: Vec::new(),
         };
 
@@ -503,22 +906,108 @@ mod tests {
     }
 
     #[test]
-    fn project_generation_escapes_plain_env_values() {
-        let state = ProjectState {
+    fn profile_generation_includes_only_current_host_packages() {
+        let state = GlobalProfileState {
             mica: MicaMetadata {
                 version: "0.1.0".to_string(),
                 created: timestamp(),
                 modified: timestamp(),
             },
             pin: base_pin(),
-            pins: BTreeMap::new(),
             presets: PresetState::default(),
-            packages: PackagesState::default(),
-            env: BTreeMap::new(),
-            shell: ShellState::default(),
-            nix: Default::default(),
+            packages: PackagesState {
+                added: vec!["jq".to_string()],
+                removed: Vec::new(),
+                pinned: BTreeMap::new(),
+                priorities: BTreeMap::new(),
+                overrides: BTreeMap::new(),
+            },
+            generations: GenerationsState::default(),
+            hosts: vec![
+                HostPackages {
+                    hostname: Some("workstation".to_string()),
+                    os: None,
+                    packages: vec!["steam".to_string(), "jq".to_string()],
+                },
+                HostPackages {
+                    hostname: None,
+                    os: Some("macos".to_string()),
+                    packages: vec!["coreutils".to_string()],
+                },
+            ],
+        };
+        let merged = merge_profile_presets(&[], &state, &HostInfo::new("workstation", "linux"));
+        assert_eq!(merged.all_packages, vec!["jq", "steam"]);
+
+        let output = generate_profile_nix(&state, &merged, timestamp());
+        assert!(output.contains("    # Host: hostname=workstation\n    pkgs.steam\n"));
+        assert!(!output.contains("coreutils"));
+
+        let parsed = parse_profile_state_from_nix(&output).expect("profile parse failed");
+        assert_eq!(parsed.packages, vec!["jq"]);
+    }
+
+    #[test]
+    fn generated_at_line_can_be_replaced_or_omitted() {
+        let output = "# Managed by Mica v0.1.0\n# Manual additions outside markers will be preserved\n# Last generated: 2026-02-06T00:00:00+00:00\n# mica:layout 2\n\n{ pkgs ? import <nixpkgs> {} }:\npkgs.hello\n";
+        let omitted = set_generated_at(output, None);
+        assert!(!omitted.contains("# Last generated:"));
+        assert!(omitted
+            .contains("# Manual additions outside markers will be preserved\n# mica:layout 2\n"));
+
+        let later = DateTime::parse_from_rfc3339("2026-03-01T12:00:00Z")
+            .expect("timestamp parse failed")
+            .with_timezone(&Utc);
+        let restamped = set_generated_at(&omitted, Some(later));
+        assert_eq!(generated_at(&restamped), Some(later));
+        assert_eq!(generated_at(&omitted), None);
+        assert_eq!(set_generated_at(output, None), omitted);
+        assert!(restamped
+            .contains("preserved\n# Last generated: 2026-03-01T12:00:00+00:00\n# mica:layout 2\n"));
+        assert_eq!(set_generated_at(output, Some(later)), restamped);
+        assert!(same_ignoring_generated_at(output, &restamped));
+        assert!(!same_ignoring_generated_at(
+            output,
+            &output.replace("hello", "cowsay")
+        ));
+    }
+
+    #[test]
+    fn project_generation_round_trips_manifest_header() {
+        let state = ProjectState {
+            manifest: ManifestState {
+                description: Some("Billing service dev shell".to_string()),
+                owner: Some("payments-team".to_string()),
+            },
+            ..test_state()
         };
 
+        let output = generate_project_nix(&state, &empty_merged_result(), "billing", timestamp());
+        assert!(output.contains(
+            "# Last generated: 2026-02-06T00:00:00+00:00\n# mica:layout 2\n# mica:manifest:begin\n# description: Billing service dev shell\n# owner: payments-team\n# mica:manifest:end\n\n{ pkgs"
+        ));
+        let parsed = parse_project_state_from_nix(&output).expect("parse failed");
+        assert_eq!(parsed.manifest, state.manifest);
+
+        let preamble = parse_nix_file(&output).expect("parse failed").preamble;
+        let updated = replace_manifest_header(
+            &preamble,
+            &ManifestState {
+                description: None,
+                owner: Some("platform".to_string()),
+            },
+        );
+        assert!(updated.contains("# mica:manifest:begin\n# owner: platform\n# mica:manifest:end\n"));
+        assert!(!updated.contains("Billing"));
+        let cleared = replace_manifest_header(&preamble, &ManifestState::default());
+        assert!(!cleared.contains("mica:manifest"));
+        assert!(cleared.ends_with("\n\n{ pkgs ? import (fetchTarball {\n"));
+    }
+
+    #[test]
+    fn project_generation_escapes_plain_env_values() {
+        let state = test_state();
+
         let mut merged = empty_merged_result();
         merged
             .env
@@ -531,20 +1020,7 @@ mod tests {
 
     #[test]
     fn project_generation_preserves_nix_expression_env_values() {
-        let state = ProjectState {
-            mica: MicaMetadata {
-                version: "0.1.0".to_string(),
-                created: timestamp(),
-                modified: timestamp(),
-            },
-            pin: base_pin(),
-            pins: BTreeMap::new(),
-            presets: PresetState::default(),
-            packages: PackagesState::default(),
-            env: BTreeMap::new(),
-            shell: ShellState::default(),
-            nix: Default::default(),
-        };
+        let state = test_state();
 
         let mut merged = empty_merged_result();
         merged
@@ -558,20 +1034,7 @@ mod tests {
 
     #[test]
     fn project_generation_renders_prefixed_nix_expression_values_raw() {
-        let state = ProjectState {
-            mica: MicaMetadata {
-                version: "0.1.0".to_string(),
-                created: timestamp(),
-                modified: timestamp(),
-            },
-            pin: base_pin(),
-            pins: BTreeMap::new(),
-            presets: PresetState::default(),
-            packages: PackagesState::default(),
-            env: BTreeMap::new(),
-            shell: ShellState::default(),
-            nix: Default::default(),
-        };
+        let state = test_state();
 
         let mut merged = empty_merged_result();
         merged.env.insert(
@@ -586,20 +1049,7 @@ mod tests {
 
     #[test]
     fn project_generation_wraps_prefixed_interpolation_fragment_as_nix_string() {
-        let state = ProjectState {
-            mica: MicaMetadata {
-                version: "0.1.0".to_string(),
-                created: timestamp(),
-                modified: timestamp(),
-            },
-            pin: base_pin(),
-            pins: BTreeMap::new(),
-            presets: PresetState::default(),
-            packages: PackagesState::default(),
-            env: BTreeMap::new(),
-            shell: ShellState::default(),
-            nix: Default::default(),
-        };
+        let state = test_state();
 
         let mut merged = empty_merged_result();
         merged.env.insert(
@@ -611,4 +1061,262 @@ mod tests {
 
         assert!(output.contains("MICA_TEST = \"${pkgs.path}/meme\";"));
     }
+
+    #[test]
+    fn project_generation_expands_template_vars_in_env_and_hooks() {
+        let state = test_state();
+
+        let mut merged = empty_merged_result();
+        merged.env.insert(
+            "DATA_DIR".to_string(),
+            "{{root_dir}}/.data/{{project_name}}".to_string(),
+        );
+        merged
+            .env
+            .insert("PIN".to_string(), "rev-{{pin_rev}}".to_string());
+        merged
+            .shell_hooks
+            .push("echo \"{{project_name}} in {{root_dir}}\"".to_string());
+
+        let output = generate_project_nix(&state, &merged, "env-test", timestamp());
+
+        assert!(output.contains("DATA_DIR = \"${toString ./.}/.data/env-test\";"));
+        assert!(output.contains("PIN = \"rev-deadbeef\";"));
+        assert!(output.contains("echo \"env-test in ${toString ./.}\""));
+    }
+
+    #[test]
+    fn channel_pins_fetch_nixexprs_and_survive_parse() {
+        let tarball =
+            "https://releases.nixos.org/nixos/24.05/nixos-24.05.7376.b134951a4c9f/nixexprs.tar.xz";
+        let channel_pin = Pin {
+            rev: "b134951a4c9f3c995fd7be05f3243f8ecd65d798".to_string(),
+            branch: String::new(),
+            updated: NaiveDate::from_ymd_opt(1970, 1, 1).unwrap(),
+            channel: Some(PinChannel {
+                name: "nixos-24.05".to_string(),
+                tarball: tarball.to_string(),
+            }),
+            ..base_pin()
+        };
+        let state = ProjectState {
+            pin: channel_pin.clone(),
+            pins: BTreeMap::from([("stable".to_string(), channel_pin.clone())]),
+            ..test_state()
+        };
+
+        let output = generate_project_nix(&state, &empty_merged_result(), "env-test", timestamp());
+        assert!(output.contains(&format!("url = \"{}\";", tarball)));
+        assert!(!output.contains("/archive/"));
+        let parsed = parse_project_state_from_nix(&output).expect("parse generated nix");
+        assert_eq!(parsed.pin, channel_pin);
+        assert_eq!(parsed.pins.get("stable"), Some(&channel_pin));
+    }
+
+    #[test]
+    fn template_vars_survive_parse_and_regeneration() {
+        let mut state = test_state();
+        let mut merged = empty_merged_result();
+        merged
+            .env
+            .insert("PIN".to_string(), "rev-{{pin_rev}}".to_string());
+        merged
+            .env
+            .insert("DATA_DIR".to_string(), "{{root_dir}}/.data".to_string());
+        merged
+            .shell_hooks
+            .push("echo {{project_name}}\necho done\n".to_string());
+
+        let output = generate_project_nix(&state, &merged, "env-test", timestamp());
+        let parsed = parse_project_state_from_nix(&output).expect("parse generated nix");
+        assert_eq!(
+            parsed.env.get("PIN").map(String::as_str),
+            Some("rev-{{pin_rev}}")
+        );
+        assert_eq!(
+            parsed.env.get("DATA_DIR").map(String::as_str),
+            Some("{{root_dir}}/.data")
+        );
+        assert_eq!(
+            parsed.shell_hook.as_deref(),
+            Some("echo {{project_name}}\necho done\n")
+        );
+
+        state.pin.rev = "cafebabe".to_string();
+        let mut merged = empty_merged_result();
+        merged.env = parsed.env;
+        merged.shell_hooks = parsed.shell_hook.into_iter().collect();
+        let output = generate_project_nix(&state, &merged, "env-test", timestamp());
+        assert!(output.contains("PIN = \"rev-cafebabe\";"));
+        assert!(output.contains("# mica:template rev-{{pin_rev}}"));
+        assert!(output.contains("echo env-test\n"));
+    }
+
+    #[test]
+    fn project_generation_imports_parent_when_extending() {
+        let state = ProjectState {
+            extends: Some("../default.nix".to_string()),
+            ..test_state()
+        };
+
+        let output = generate_project_nix(&state, &empty_merged_result(), "child", timestamp());
+
+        assert!(output.contains("  parent = import ../default.nix { inherit pkgs; };"));
+        assert!(output.contains(
+            "  ] ++ (pkgs.lib.attrsets.attrValues scripts) ++ (parent.buildInputs or [ ]);"
+        ));
+        assert!(output.contains("${parent.shellHook or \"\"}"));
+    }
+
+    #[test]
+    fn project_generation_inherits_parent_env_inside_env_markers() {
+        let mut env = BTreeMap::new();
+        env.insert("LOCAL".to_string(), "1".to_string());
+        let state = ProjectState {
+            env: env.clone(),
+            extends: Some("../default.nix".to_string()),
+            ..test_state()
+        };
+        let merged = MergedResult {
+            env,
+            inherited_env: vec!["DATABASE_URL".to_string(), "RUST_LOG".to_string()],
+            ..empty_merged_result()
+        };
+
+        let output = generate_project_nix(&state, &merged, "child", timestamp());
+        let sections = parse_nix_file(&output).expect("parse");
+        let parsed = parse_project_state_from_nix(&output).expect("parse generated nix");
+
+        assert!(output.contains("    inherit (parent) DATABASE_URL RUST_LOG;\n"));
+        assert!(sections
+            .env_section
+            .contains("inherit (parent) DATABASE_URL RUST_LOG;"));
+        assert_eq!(parsed.env, state.env);
+    }
+
+    #[test]
+    fn project_generation_renders_build_env_settings() {
+        let mut state = test_state();
+
+        let output = generate_project_nix(&state, &empty_merged_result(), "app", timestamp());
+        assert!(output.contains("    inherit name paths; buildInputs = paths;\n"));
+        assert!(!output.contains("mica:buildenv"));
+
+        state.build_env = BuildEnvState {
+            name: Some("app-dev".to_string()),
+            extra_outputs: vec!["man".to_string(), "dev".to_string()],
+            ignore_collisions: true,
+            priority: Some(4),
+        };
+        let output = generate_project_nix(&state, &empty_merged_result(), "app", timestamp());
+        assert!(output.contains("  name = \"app\";\n"));
+        assert!(output.contains("    inherit paths; buildInputs = paths;\n"));
+        assert!(output.contains("    name = \"app-dev\";\n"));
+        assert!(output.contains("    extraOutputsToInstall = [ \"man\" \"dev\" ];\n"));
+        assert!(output.contains("    ignoreCollisions = true;\n"));
+        assert!(output.contains("    meta.priority = 4;\n"));
+
+        let parsed = parse_project_state_from_nix(&output).expect("parse failed");
+        assert_eq!(parsed.build_env, state.build_env);
+    }
+
+    #[test]
+    fn project_generation_prepends_prioritized_packages() {
+        let mut packages = PackagesState {
+            added: vec!["ripgrep".to_string(), "ripgrep-all".to_string()],
+            removed: vec!["fd".to_string()],
+            pinned: BTreeMap::new(),
+            priorities: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+        };
+        packages.priorities.insert("ripgrep".to_string(), 4);
+        packages.priorities.insert("fd".to_string(), 1);
+        let state = ProjectState {
+            packages,
+            ..test_state()
+        };
+
+        let output = generate_project_nix(&state, &empty_merged_result(), "app", timestamp());
+        assert!(output.contains("    (pkgs.lib.meta.setPrio 4 pkgs.ripgrep)\n"));
+        assert!(!output.contains("setPrio 1"));
+        assert!(output.contains(
+            "  paths = prioritized ++ pkgs.lib.subtractLists excluded (pkgs.lib.flatten [ tools ]);\n"
+        ));
+
+        let parsed = parse_project_state_from_nix(&output).expect("parse failed");
+        assert_eq!(
+            parsed.priorities.into_iter().collect::<Vec<_>>(),
+            vec![("ripgrep".to_string(), 4)]
+        );
+    }
+
+    #[test]
+    fn project_generation_replaces_overridden_packages() {
+        let mut packages = PackagesState {
+            added: vec!["ripgrep".to_string(), "python3Packages.black".to_string()],
+            removed: vec!["fd".to_string()],
+            pinned: BTreeMap::new(),
+            priorities: BTreeMap::new(),
+            overrides: BTreeMap::new(),
+        };
+        packages.overrides.insert(
+            "ripgrep".to_string(),
+            "prev: {\n  patches = (prev.patches or [ ]) ++ [ ./rg.patch ];\n\n  doCheck = false;\n}"
+                .to_string(),
+        );
+        packages.overrides.insert(
+            "python3Packages.black".to_string(),
+            "{ version = \"24.1.0\"; }".to_string(),
+        );
+        packages
+            .overrides
+            .insert("fd".to_string(), "{ doCheck = false; }".to_string());
+        packages.priorities.insert("ripgrep".to_string(), 4);
+        let state = ProjectState {
+            packages,
+            ..test_state()
+        };
+
+        let output = generate_project_nix(&state, &empty_merged_result(), "app", timestamp());
+        assert!(output.contains(
+            "    \"ripgrep\" = pkgs.ripgrep.overrideAttrs (\n      prev: {\n        patches = (prev.patches or [ ]) ++ [ ./rg.patch ];\n\n        doCheck = false;\n      }\n    );\n"
+        ));
+        assert!(output
+            .contains("  replaced = [\n    pkgs.python3Packages.black\n    pkgs.ripgrep\n  ];\n"));
+        assert!(!output.contains("pkgs.fd.overrideAttrs"));
+        assert!(output.contains("    (pkgs.lib.meta.setPrio 4 overridden.\"ripgrep\")\n"));
+        assert!(output.contains(
+            "  paths = prioritized ++ pkgs.lib.attrValues overridden ++ pkgs.lib.subtractLists (excluded ++ replaced) (pkgs.lib.flatten [ tools ]);\n"
+        ));
+
+        let parsed = parse_project_state_from_nix(&output).expect("parse failed");
+        let mut expected = state.packages.overrides.clone();
+        expected.remove("fd");
+        assert_eq!(parsed.overrides, expected);
+        assert_eq!(parsed.priorities.get("ripgrep"), Some(&4));
+    }
+
+    #[test]
+    fn project_generation_subtracts_removed_packages_from_paths() {
+        let state = ProjectState {
+            packages: PackagesState {
+                added: Vec::new(),
+                removed: vec![
+                    "cargo-edit".to_string(),
+                    "python3Packages.black".to_string(),
+                ],
+                pinned: BTreeMap::new(),
+                priorities: BTreeMap::new(),
+                overrides: BTreeMap::new(),
+            },
+            ..test_state()
+        };
+
+        let output = generate_project_nix(&state, &empty_merged_result(), "exclude", timestamp());
+
+        assert!(output.contains("  # mica:exclude:begin\n"));
+        assert!(output.contains("    \"cargo-edit\"\n    \"python3Packages.black\"\n  ];\n"));
+        assert!(output
+            .contains("  paths = pkgs.lib.subtractLists excluded (pkgs.lib.flatten [ tools ]);\n"));
+    }
 }
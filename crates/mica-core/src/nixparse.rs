@@ -3,7 +3,12 @@ use std::fmt;
 
 use chrono::NaiveDate;
 
-use crate::state::{NixBlocks, Pin, PinnedPackage, NIX_EXPR_PREFIX};
+use crate::nixgen::{parse_channel_marker, parse_template_marker, PARENT_SHELL_HOOK_EXPR};
+use crate::state::{
+    BuildEnvState, ManifestState, NixBlocks, Pin, PinChannel, PinnedPackage, NIX_EXPR_PREFIX,
+};
+
+pub const LEGACY_LAYOUT_VERSION: u32 = 1;
 
 #[derive(Debug)]
 pub enum ParseError {
@@ -24,11 +29,17 @@ impl fmt::Display for ParseError {
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ParsedNix {
+    pub manifest_section: Option<String>,
     pub pin_section: String,
     pub pins_section: Option<String>,
     pub let_section: Option<String>,
+    pub extends_section: Option<String>,
     pub packages_section: String,
     pub packages_raw_section: Option<String>,
+    pub exclude_section: Option<String>,
+    pub overrides_section: Option<String>,
+    pub priorities_section: Option<String>,
+    pub build_env_section: Option<String>,
     pub scripts_section: Option<String>,
     pub env_section: String,
     pub env_raw_section: Option<String>,
@@ -54,10 +65,14 @@ pub fn parse_nix_file(content: &str) -> Result<ParsedNix, ParseError> {
     }
 
     let preamble = extract_before_marker(content, "mica:pin:begin")?;
+    let manifest_section =
+        extract_between_markers_optional(content, "mica:manifest:begin", "mica:manifest:end")?;
     let pin_section = extract_between_markers(content, "mica:pin:begin", "mica:pin:end")?;
     let pins_section =
         extract_between_markers_optional(content, "mica:pins:begin", "mica:pins:end")?;
     let let_section = extract_between_markers_optional(content, "mica:let:begin", "mica:let:end")?;
+    let extends_section =
+        extract_between_markers_optional(content, "mica:extends:begin", "mica:extends:end")?;
     let packages_section =
         extract_between_markers(content, "mica:packages:begin", "mica:packages:end")?;
     let packages_raw_section = extract_between_markers_optional(
@@ -65,6 +80,14 @@ pub fn parse_nix_file(content: &str) -> Result<ParsedNix, ParseError> {
         "mica:packages-raw:begin",
         "mica:packages-raw:end",
     )?;
+    let exclude_section =
+        extract_between_markers_optional(content, "mica:exclude:begin", "mica:exclude:end")?;
+    let overrides_section =
+        extract_between_markers_optional(content, "mica:overrides:begin", "mica:overrides:end")?;
+    let priorities_section =
+        extract_between_markers_optional(content, "mica:priorities:begin", "mica:priorities:end")?;
+    let build_env_section =
+        extract_between_markers_optional(content, "mica:buildenv:begin", "mica:buildenv:end")?;
     let scripts_section =
         extract_between_markers_optional(content, "mica:scripts:begin", "mica:scripts:end")?;
     let env_section = extract_between_markers(content, "mica:env:begin", "mica:env:end")?;
@@ -87,11 +110,17 @@ pub fn parse_nix_file(content: &str) -> Result<ParsedNix, ParseError> {
     let postamble = extract_postamble(content)?;
 
     Ok(ParsedNix {
+        manifest_section,
         pin_section,
         pins_section,
         let_section,
+        extends_section,
         packages_section,
         packages_raw_section,
+        exclude_section,
+        overrides_section,
+        priorities_section,
+        build_env_section,
         scripts_section,
         env_section,
         env_raw_section,
@@ -104,6 +133,24 @@ pub fn parse_nix_file(content: &str) -> Result<ParsedNix, ParseError> {
     })
 }
 
+pub fn parse_generator_version(content: &str) -> Option<String> {
+    content
+        .lines()
+        .next()
+        .and_then(|line| line.strip_prefix("# Managed by Mica v"))
+        .map(|version| version.trim().to_string())
+        .filter(|version| !version.is_empty())
+}
+
+pub fn parse_layout_version(content: &str) -> u32 {
+    content
+        .lines()
+        .take_while(|line| line.starts_with('#'))
+        .find_map(|line| line.strip_prefix("# mica:layout "))
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(LEGACY_LAYOUT_VERSION)
+}
+
 pub fn parse_profile_nix(content: &str) -> Result<ParsedProfileNix, ParseError> {
     if !content.starts_with("# Managed by Mica") {
         return Err(ParseError::NotMicaManaged);
@@ -139,11 +186,18 @@ pub struct ParsedProjectState {
     pub pin: Pin,
     pub pins: BTreeMap<String, Pin>,
     pub packages: Vec<String>,
+    pub removed: Vec<String>,
     pub pinned: BTreeMap<String, PinnedPackage>,
+    pub priorities: BTreeMap<String, i32>,
+    pub overrides: BTreeMap<String, String>,
     pub env: BTreeMap<String, String>,
     pub shell_hook: Option<String>,
     pub presets: Vec<String>,
     pub nix: NixBlocks,
+    pub extends: Option<String>,
+    pub build_env: BuildEnvState,
+    pub manifest: ManifestState,
+    pub version: Option<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -164,11 +218,43 @@ pub fn parse_project_state_from_nix(content: &str) -> Result<ParsedProjectState,
     }
     let env = parse_env_section(&parsed.env_section);
     let shell_hook = parse_shell_hook(&parsed.shell_hook_section);
+    let extends = parsed
+        .extends_section
+        .as_deref()
+        .and_then(parse_extends_section);
+    let removed = parsed
+        .exclude_section
+        .as_deref()
+        .map(parse_exclude_section)
+        .unwrap_or_default();
+    let priorities = parsed
+        .priorities_section
+        .as_deref()
+        .map(parse_priorities_section)
+        .unwrap_or_default();
+    let overrides = parsed
+        .overrides_section
+        .as_deref()
+        .map(parse_overrides_section)
+        .unwrap_or_default();
+    let build_env = parsed
+        .build_env_section
+        .as_deref()
+        .map(parse_build_env_section)
+        .unwrap_or_default();
+    let manifest = parsed
+        .manifest_section
+        .as_deref()
+        .map(parse_manifest_section)
+        .unwrap_or_default();
     Ok(ParsedProjectState {
         pin,
         pins,
         packages,
+        removed,
         pinned,
+        priorities,
+        overrides,
         env,
         shell_hook,
         presets,
@@ -182,9 +268,133 @@ pub fn parse_project_state_from_nix(content: &str) -> Result<ParsedProjectState,
             override_merge: normalize_optional_block(parsed.override_merge_section),
             override_shell_hook: parse_override_shellhook(parsed.override_shellhook_section),
         },
+        extends,
+        build_env,
+        manifest,
+        version: parse_generator_version(content),
     })
 }
 
+pub fn parse_manifest_section(section: &str) -> ManifestState {
+    let mut manifest = ManifestState::default();
+    for line in section.lines() {
+        let trimmed = line.trim().trim_start_matches('#').trim();
+        let Some((key, value)) = trimmed.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        match key.trim() {
+            "description" => manifest.description = Some(value.to_string()),
+            "owner" => manifest.owner = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    manifest
+}
+
+fn parse_priorities_section(section: &str) -> BTreeMap<String, i32> {
+    let mut priorities = BTreeMap::new();
+    for line in section.lines() {
+        let trimmed = line.trim().trim_start_matches('(').trim_end_matches(')');
+        let mut parts = trimmed.split_whitespace();
+        if parts.next() != Some("pkgs.lib.meta.setPrio") {
+            continue;
+        }
+        let (Some(priority), Some(source)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        let (Ok(priority), Some((_, attr))) = (priority.parse(), source.split_once('.')) else {
+            continue;
+        };
+        priorities.insert(attr.trim_matches('"').to_string(), priority);
+    }
+    priorities
+}
+
+fn parse_overrides_section(section: &str) -> BTreeMap<String, String> {
+    let mut overrides = BTreeMap::new();
+    let mut current: Option<(String, Vec<&str>)> = None;
+    for line in section.lines() {
+        if let Some((attr, lines)) = current.as_mut() {
+            if line.trim_end() == "    );" {
+                overrides.insert(std::mem::take(attr), lines.join("\n"));
+                current = None;
+            } else {
+                lines.push(line.strip_prefix("      ").unwrap_or(line.trim_start()));
+            }
+            continue;
+        }
+        let trimmed = line.trim();
+        let Some(rest) = trimmed.strip_prefix('"') else {
+            continue;
+        };
+        let Some((attr, expr)) = rest.split_once("\" = ") else {
+            continue;
+        };
+        if expr.ends_with(".overrideAttrs (") {
+            current = Some((attr.to_string(), Vec::new()));
+        }
+    }
+    overrides
+}
+
+pub fn parse_build_env_section(section: &str) -> BuildEnvState {
+    let mut settings = BuildEnvState::default();
+    for line in section.lines() {
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        let Some((key, value)) = trimmed.split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_end_matches(';').trim();
+        match key.trim() {
+            "name" => settings.name = Some(trim_quotes(value)),
+            "extraOutputsToInstall" => {
+                settings.extra_outputs = value
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split_whitespace()
+                    .map(trim_quotes)
+                    .filter(|output| !output.is_empty())
+                    .collect();
+            }
+            "ignoreCollisions" => settings.ignore_collisions = value == "true",
+            "meta.priority" => settings.priority = value.parse().ok(),
+            _ => {}
+        }
+    }
+    settings
+}
+
+fn parse_exclude_section(section: &str) -> Vec<String> {
+    section
+        .lines()
+        .map(str::trim)
+        .filter(|line| line.starts_with('"'))
+        .map(|line| trim_quotes(line.trim_end_matches(';')))
+        .filter(|value| !value.is_empty())
+        .collect()
+}
+
+fn parse_extends_section(section: &str) -> Option<String> {
+    for line in section.lines() {
+        let trimmed = line.trim();
+        let Some((_, rest)) = trimmed.split_once("import ") else {
+            continue;
+        };
+        let path = rest.split_whitespace().next()?;
+        if !path.is_empty() {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
 pub fn parse_profile_state_from_nix(content: &str) -> Result<ParsedProfileState, StateParseError> {
     let parsed = parse_profile_nix(content)?;
     let pin = parse_pin_section(&parsed.pins_section)?;
@@ -201,14 +411,16 @@ fn parse_pin_section(section: &str) -> Result<Pin, StateParseError> {
     let name = find_attr_value(section, "name").filter(|value| !value.trim().is_empty());
     let url = find_attr_value(section, "url").ok_or(StateParseError::MissingPinUrl)?;
     let sha256 = find_attr_value(section, "sha256").ok_or(StateParseError::MissingPinSha)?;
-    let rev = extract_rev_from_url(&url).ok_or(StateParseError::MissingPinRev)?;
+    let marker = section.lines().find_map(parse_channel_marker);
+    let (url, rev, channel) = pin_source(&url, marker);
     Ok(Pin {
         name,
-        url: trim_archive_url(&url),
-        rev,
+        url,
+        rev: rev.ok_or(StateParseError::MissingPinRev)?,
         sha256,
         branch: String::new(),
         updated: NaiveDate::from_ymd_opt(1970, 1, 1).unwrap(),
+        channel,
     })
 }
 
@@ -219,6 +431,7 @@ fn parse_pin_args(section: Option<&str>) -> (BTreeMap<String, Pin>, Option<Strin
     let mut current_name: Option<String> = None;
     let mut current_url: Option<String> = None;
     let mut current_sha: Option<String> = None;
+    let mut current_channel = None;
 
     let Some(section) = section else {
         return (pins, None);
@@ -235,6 +448,7 @@ fn parse_pin_args(section: Option<&str>) -> (BTreeMap<String, Pin>, Option<Strin
                     current_name = None;
                     current_url = None;
                     current_sha = None;
+                    current_channel = None;
                     continue;
                 }
             }
@@ -245,6 +459,9 @@ fn parse_pin_args(section: Option<&str>) -> (BTreeMap<String, Pin>, Option<Strin
         if let Some((_, lines)) = current.as_mut() {
             lines.push(line.to_string());
         }
+        if let Some(marker) = parse_channel_marker(trimmed) {
+            current_channel = Some(marker);
+        }
         if let Some(rest) = trimmed.strip_prefix("url =") {
             current_url = Some(trim_quotes(rest.trim_end_matches(';').trim()));
         }
@@ -258,16 +475,17 @@ fn parse_pin_args(section: Option<&str>) -> (BTreeMap<String, Pin>, Option<Strin
         if trimmed.contains("})") {
             if let Some((name, lines)) = current.take() {
                 if let (Some(url), Some(sha256)) = (current_url.take(), current_sha.take()) {
-                    if let Some(rev) = extract_rev_from_url(&url) {
+                    if let (url, Some(rev), channel) = pin_source(&url, current_channel.take()) {
                         pins.insert(
                             name,
                             Pin {
                                 name: current_name.take().filter(|value| !value.trim().is_empty()),
-                                url: trim_archive_url(&url),
+                                url,
                                 rev,
                                 sha256,
                                 branch: String::new(),
                                 updated: NaiveDate::from_ymd_opt(1970, 1, 1).unwrap(),
+                                channel,
                             },
                         );
                         continue;
@@ -296,6 +514,7 @@ fn parse_profile_pins(section: &str) -> BTreeMap<String, Pin> {
     let mut current_name: Option<String> = None;
     let mut current_url: Option<String> = None;
     let mut current_sha: Option<String> = None;
+    let mut current_channel = None;
 
     for line in section.lines() {
         let trimmed = line.trim();
@@ -306,11 +525,15 @@ fn parse_profile_pins(section: &str) -> BTreeMap<String, Pin> {
                     current_name = None;
                     current_url = None;
                     current_sha = None;
+                    current_channel = None;
                 }
             }
             continue;
         }
 
+        if let Some(marker) = parse_channel_marker(trimmed) {
+            current_channel = Some(marker);
+        }
         if let Some(rest) = trimmed.strip_prefix("name =") {
             current_name = Some(trim_quotes(rest.trim_end_matches(';').trim()));
         }
@@ -325,14 +548,15 @@ fn parse_profile_pins(section: &str) -> BTreeMap<String, Pin> {
             if let (Some(name), Some(url), Some(sha256)) =
                 (current.take(), current_url.take(), current_sha.take())
             {
-                let rev = extract_rev_from_url(&url).unwrap_or_default();
+                let (url, rev, channel) = pin_source(&url, current_channel.take());
                 let pin = Pin {
                     name: current_name.take(),
-                    url: trim_archive_url(&url),
-                    rev,
+                    url,
+                    rev: rev.unwrap_or_default(),
                     sha256,
                     branch: String::new(),
                     updated: NaiveDate::from_ymd_opt(1970, 1, 1).unwrap(),
+                    channel,
                 };
                 pins.insert(name, pin);
             }
@@ -366,6 +590,25 @@ fn extract_rev_from_url(url: &str) -> Option<String> {
     Some(archive.trim_end_matches(".tar.gz").to_string())
 }
 
+/// Repository url, revision and channel of a fetched tarball. Channel pins
+/// fetch `nixexprs.tar.xz`, so those come from the marker instead of the url.
+fn pin_source(
+    url: &str,
+    marker: Option<(String, String, String)>,
+) -> (String, Option<String>, Option<PinChannel>) {
+    match marker {
+        Some((name, rev, repo)) => (
+            repo,
+            Some(rev),
+            Some(PinChannel {
+                name,
+                tarball: url.to_string(),
+            }),
+        ),
+        None => (trim_archive_url(url), extract_rev_from_url(url), None),
+    }
+}
+
 fn trim_archive_url(url: &str) -> String {
     if let Some((base, _)) = url.split_once("/archive/") {
         return base.to_string();
@@ -459,9 +702,14 @@ fn parse_profile_paths(
 ) -> (Vec<String>, BTreeMap<String, PinnedPackage>) {
     let mut packages = Vec::new();
     let mut pinned = BTreeMap::new();
+    let mut in_host_group = false;
     for line in section.lines() {
         let trimmed = line.trim();
-        if trimmed.is_empty() || trimmed.starts_with('#') {
+        if trimmed.starts_with('#') {
+            in_host_group = trimmed.starts_with("# Host:");
+            continue;
+        }
+        if trimmed.is_empty() {
             continue;
         }
         if trimmed.contains("paths =") || trimmed == "[" || trimmed == "];" {
@@ -489,7 +737,9 @@ fn parse_profile_paths(
             }
         }
         if let Some(attr) = item.strip_prefix("pkgs.") {
-            packages.push(attr.to_string());
+            if !in_host_group {
+                packages.push(attr.to_string());
+            }
         }
     }
     (packages, pinned)
@@ -498,6 +748,7 @@ fn parse_profile_paths(
 fn parse_env_section(section: &str) -> BTreeMap<String, String> {
     let mut env = BTreeMap::new();
     let mut in_raw_block = false;
+    let mut template = None;
     for line in section.lines() {
         let trimmed = line.trim();
         if trimmed.contains("mica:env-raw:begin") {
@@ -511,13 +762,18 @@ fn parse_env_section(section: &str) -> BTreeMap<String, String> {
         if in_raw_block {
             continue;
         }
+        if let Some(raw) = parse_template_marker(trimmed) {
+            template = Some(raw);
+            continue;
+        }
         if trimmed.is_empty() || trimmed.starts_with('#') {
             continue;
         }
         if let Some((key, value)) = trimmed.split_once('=') {
             let key = key.trim();
             let value = value.trim().trim_end_matches(';').trim();
-            env.insert(key.to_string(), parse_env_value(value));
+            let value = template.take().unwrap_or_else(|| parse_env_value(value));
+            env.insert(key.to_string(), value);
         }
     }
     env
@@ -571,6 +827,7 @@ fn parse_shell_hook(section: &str) -> Option<String> {
     let mut lines = section.lines();
     let mut in_hook = false;
     let mut buffer = String::new();
+    let mut template = None;
     for line in lines.by_ref() {
         if line.contains("shellHook = ''") {
             in_hook = true;
@@ -580,7 +837,14 @@ fn parse_shell_hook(section: &str) -> Option<String> {
             if line.contains("'';") {
                 break;
             }
-            buffer.push_str(line);
+            if line.trim() == PARENT_SHELL_HOOK_EXPR {
+                continue;
+            }
+            if let Some(raw) = parse_template_marker(line) {
+                template = Some(raw);
+                continue;
+            }
+            buffer.push_str(template.take().as_deref().unwrap_or(line));
             buffer.push('\n');
         }
     }
@@ -766,9 +1030,71 @@ fn normalize_optional_block(block: Option<String>) -> Option<String> {
 
 #[cfg(test)]
 mod tests {
-    use crate::nixparse::parse_env_section;
+    use crate::nixparse::{parse_env_section, parse_exclude_section, parse_project_state_from_nix};
     use crate::state::NIX_EXPR_PREFIX;
 
+    #[test]
+    fn parse_exclude_section_reads_quoted_attrs() {
+        let removed = parse_exclude_section(
+            r#"
+  excluded = map (attr: pkgs.lib.attrByPath (pkgs.lib.splitString "." attr) null pkgs) [
+    "hello"
+    "python3Packages.black"
+  ];
+"#,
+        );
+        assert_eq!(
+            removed,
+            vec!["hello".to_string(), "python3Packages.black".to_string()]
+        );
+    }
+
+    #[test]
+    fn parse_project_state_reads_extends_and_skips_parent_hook() {
+        let content = r#"# Managed by Mica v0.1.0
+{ pkgs ? import (fetchTarball {
+    # mica:pin:begin
+    url = "https://github.com/NixOS/nixpkgs/archive/deadbeef.tar.gz";
+    sha256 = "0123456789abcdef0123456789abcdef0123456789abcdef0123";
+    # mica:pin:end
+  }) {}
+}:
+
+let
+  name = "child";
+
+  # mica:extends:begin
+  parent = import ../default.nix { inherit pkgs; };
+  # mica:extends:end
+
+  # mica:packages:begin
+  tools = with pkgs; [
+    # User additions
+    jq
+  ] ++ (pkgs.lib.attrsets.attrValues scripts) ++ (parent.buildInputs or [ ]);
+  # mica:packages:end
+
+  paths = pkgs.lib.flatten [ tools ];
+  env = pkgs.buildEnv {
+    inherit name paths; buildInputs = paths;
+    # mica:env:begin
+    # mica:env:end
+
+    # mica:shellhook:begin
+    shellHook = ''
+${parent.shellHook or ""}
+    '';
+    # mica:shellhook:end
+  };
+in
+env
+"#;
+        let parsed = parse_project_state_from_nix(content).expect("parse failed");
+        assert_eq!(parsed.extends.as_deref(), Some("../default.nix"));
+        assert_eq!(parsed.packages, vec!["jq".to_string()]);
+        assert_eq!(parsed.shell_hook, None);
+    }
+
     #[test]
     fn parse_env_section_keeps_interpolated_nix_string_expressions() {
         let env = parse_env_section(
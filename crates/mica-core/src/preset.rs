@@ -1,4 +1,4 @@
-use crate::state::{NixBlocks, ProjectState, ShellState};
+use crate::state::{NixBlocks, Pin, ProjectState, ShellState};
 use indexmap::IndexSet;
 use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashSet};
@@ -40,6 +40,8 @@ pub struct PresetPackages {
     pub required: Vec<String>,
     #[serde(default)]
     pub optional: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub pins: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -49,6 +51,7 @@ pub struct Preset {
     pub order: i32,
     pub packages_required: Vec<String>,
     pub packages_optional: Vec<String>,
+    pub package_pins: BTreeMap<String, String>,
     pub env: BTreeMap<String, String>,
     pub shell: ShellState,
     pub nix: NixBlocks,
@@ -71,12 +74,27 @@ impl Preset {
             order: file.preset.order,
             packages_required: file.packages.required,
             packages_optional: file.packages.optional,
+            package_pins: file.packages.pins,
             env: file.env,
             shell: file.shell,
             nix: file.nix,
             source,
         }
     }
+
+    pub fn resolve_package(&self, pkg: &str, pins: &BTreeMap<String, Pin>) -> String {
+        match self.package_pins.get(pkg) {
+            Some(pin) if pins.contains_key(pin) => format!("{}.{}", pin, pkg),
+            _ => pkg.to_string(),
+        }
+    }
+
+    pub fn required_packages(&self, pins: &BTreeMap<String, Pin>) -> Vec<String> {
+        self.packages_required
+            .iter()
+            .map(|pkg| self.resolve_package(pkg, pins))
+            .collect()
+    }
 }
 
 pub fn load_embedded_presets() -> Result<Vec<Preset>, PresetError> {
@@ -123,6 +141,8 @@ pub struct MergedResult {
     pub preset_packages: Vec<PresetPackageGroup>,
     pub user_packages: Vec<String>,
     pub env: BTreeMap<String, String>,
+    /// Env keys taken from the `extends` parent that the project does not set itself.
+    pub inherited_env: Vec<String>,
     pub shell_hooks: Vec<String>,
     pub all_packages: Vec<String>,
     pub let_blocks: Vec<String>,
@@ -146,7 +166,7 @@ fn push_block(target: &mut Vec<String>, block: &Option<String>) {
 
 pub fn merge_presets(presets: &[Preset], state: &ProjectState) -> MergedResult {
     let mut ordered = presets.to_vec();
-    ordered.sort_by_key(|preset| preset.order);
+    ordered.sort_by(|a, b| a.order.cmp(&b.order).then_with(|| a.name.cmp(&b.name)));
 
     let removed: HashSet<&String> = state.packages.removed.iter().collect();
     let mut seen = IndexSet::new();
@@ -158,12 +178,12 @@ pub fn merge_presets(presets: &[Preset], state: &ProjectState) -> MergedResult {
             packages: Vec::new(),
         };
 
-        for pkg in &preset.packages_required {
-            if removed.contains(pkg) {
+        for pkg in preset.required_packages(&state.pins) {
+            if removed.contains(&pkg) {
                 continue;
             }
             if seen.insert(pkg.clone()) {
-                group.packages.push(pkg.clone());
+                group.packages.push(pkg);
             }
         }
 
@@ -242,6 +262,7 @@ pub fn merge_presets(presets: &[Preset], state: &ProjectState) -> MergedResult {
         preset_packages,
         user_packages,
         env,
+        inherited_env: Vec::new(),
         shell_hooks,
         all_packages,
         let_blocks,
@@ -259,15 +280,23 @@ pub fn merge_presets(presets: &[Preset], state: &ProjectState) -> MergedResult {
 pub struct MergedProfileResult {
     pub preset_packages: Vec<PresetPackageGroup>,
     pub user_packages: Vec<String>,
+    pub host_packages: Vec<HostPackageGroup>,
     pub all_packages: Vec<String>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostPackageGroup {
+    pub host: String,
+    pub packages: Vec<String>,
+}
+
 pub fn merge_profile_presets(
     presets: &[Preset],
     state: &crate::state::GlobalProfileState,
+    host: &crate::state::HostInfo,
 ) -> MergedProfileResult {
     let mut ordered = presets.to_vec();
-    ordered.sort_by_key(|preset| preset.order);
+    ordered.sort_by(|a, b| a.order.cmp(&b.order).then_with(|| a.name.cmp(&b.name)));
 
     let removed: HashSet<&String> = state.packages.removed.iter().collect();
     let mut seen = IndexSet::new();
@@ -303,57 +332,42 @@ pub fn merge_profile_presets(
         }
     }
 
+    let mut host_packages = Vec::new();
+    for entry in state.host_packages(host) {
+        let mut group = HostPackageGroup {
+            host: entry.label(),
+            packages: Vec::new(),
+        };
+        for pkg in &entry.packages {
+            if removed.contains(pkg) {
+                continue;
+            }
+            if seen.insert(pkg.clone()) {
+                group.packages.push(pkg.clone());
+            }
+        }
+        if !group.packages.is_empty() {
+            host_packages.push(group);
+        }
+    }
+
     let all_packages = seen.into_iter().collect();
 
     MergedProfileResult {
         preset_packages,
         user_packages,
+        host_packages,
         all_packages,
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::preset::{merge_presets, Preset};
-    use crate::state::{MicaMetadata, NixBlocks, Pin, PresetState, ProjectState, ShellState};
-    use chrono::{DateTime, NaiveDate, Utc};
+    use crate::preset::{merge_presets, Preset, PresetFile};
+    use crate::state::{test_state, NixBlocks, ShellState};
     use std::collections::BTreeMap;
     use std::path::PathBuf;
 
-    fn timestamp() -> DateTime<Utc> {
-        DateTime::parse_from_rfc3339("2025-02-04T12:00:00Z")
-            .expect("timestamp parse failed")
-            .with_timezone(&Utc)
-    }
-
-    fn date() -> NaiveDate {
-        NaiveDate::from_ymd_opt(2025, 2, 4).expect("date parse failed")
-    }
-
-    fn base_state() -> ProjectState {
-        ProjectState {
-            mica: MicaMetadata {
-                version: "0.1.0".to_string(),
-                created: timestamp(),
-                modified: timestamp(),
-            },
-            pin: Pin {
-                name: None,
-                url: "https://github.com/jkachmar/nixpkgs".to_string(),
-                rev: "a1b2c3".to_string(),
-                sha256: "sha256-AAAA".to_string(),
-                branch: "main".to_string(),
-                updated: date(),
-            },
-            pins: BTreeMap::new(),
-            presets: PresetState { active: vec![] },
-            packages: Default::default(),
-            env: BTreeMap::new(),
-            shell: ShellState::default(),
-            nix: NixBlocks::default(),
-        }
-    }
-
     #[test]
     fn merge_presets_respects_order_and_removals() {
         let preset_a = Preset {
@@ -362,6 +376,7 @@ mod tests {
             order: 10,
             packages_required: vec!["foo".to_string(), "bar".to_string()],
             packages_optional: Vec::new(),
+            package_pins: BTreeMap::new(),
             env: BTreeMap::from([("A".to_string(), "1".to_string())]),
             shell: ShellState {
                 hook: Some("echo a".to_string()),
@@ -375,6 +390,7 @@ mod tests {
             order: 5,
             packages_required: vec!["bar".to_string(), "baz".to_string()],
             packages_optional: Vec::new(),
+            package_pins: BTreeMap::new(),
             env: BTreeMap::from([("A".to_string(), "2".to_string())]),
             shell: ShellState {
                 hook: Some("echo b".to_string()),
@@ -383,7 +399,7 @@ mod tests {
             source: PathBuf::from("b.toml"),
         };
 
-        let mut state = base_state();
+        let mut state = test_state();
         state.packages.added = vec!["extra".to_string()];
         state.packages.removed = vec!["bar".to_string()];
 
@@ -397,4 +413,26 @@ mod tests {
         assert_eq!(merged.shell_hooks.len(), 2);
         assert_eq!(merged.preset_packages.len(), 2);
     }
+
+    #[test]
+    fn preset_pin_qualifiers_resolve_against_project_pins() {
+        let file: PresetFile = toml::from_str(
+            "[preset]\nname = \"node\"\n\n[packages]\nrequired = [\"nodejs\", \"yarn\", \"jq\"]\npins = { nodejs = \"stable\", yarn = \"missing\" }\n",
+        )
+        .unwrap();
+        let preset = Preset::from_file(file, PathBuf::from("node.toml"));
+
+        let mut state = test_state();
+        assert_eq!(
+            merge_presets(std::slice::from_ref(&preset), &state).all_packages,
+            vec!["nodejs", "yarn", "jq"]
+        );
+
+        state.pins.insert("stable".to_string(), state.pin.clone());
+        state.packages.removed = vec!["jq".to_string()];
+        assert_eq!(
+            merge_presets(std::slice::from_ref(&preset), &state).all_packages,
+            vec!["stable.nodejs", "yarn"]
+        );
+    }
 }
@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RegistryError {
+    #[error("failed to read project registry: {0}")]
+    Read(std::io::Error),
+    #[error("failed to write project registry: {0}")]
+    Write(std::io::Error),
+    #[error("failed to parse project registry: {0}")]
+    Parse(toml::de::Error),
+    #[error("failed to serialize project registry: {0}")]
+    Serialize(toml::ser::Error),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct ProjectRegistry {
+    #[serde(default)]
+    pub projects: BTreeMap<PathBuf, RegisteredProject>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RegisteredProject {
+    pub last_seen: DateTime<Utc>,
+}
+
+impl ProjectRegistry {
+    pub fn load_from_path(path: &Path) -> Result<ProjectRegistry, RegistryError> {
+        if !path.exists() {
+            return Ok(ProjectRegistry::default());
+        }
+        let content = std::fs::read_to_string(path).map_err(RegistryError::Read)?;
+        toml::from_str(&content).map_err(RegistryError::Parse)
+    }
+
+    pub fn save_to_path(&self, path: &Path) -> Result<(), RegistryError> {
+        let content = toml::to_string_pretty(self).map_err(RegistryError::Serialize)?;
+        std::fs::write(path, content).map_err(RegistryError::Write)
+    }
+
+    pub fn record(&mut self, nix_path: &Path, now: DateTime<Utc>) -> bool {
+        match self.projects.get_mut(nix_path) {
+            Some(entry) if entry.last_seen.date_naive() == now.date_naive() => false,
+            Some(entry) => {
+                entry.last_seen = now;
+                true
+            }
+            None => {
+                self.projects
+                    .insert(nix_path.to_path_buf(), RegisteredProject { last_seen: now });
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::registry::ProjectRegistry;
+    use chrono::{TimeZone, Utc};
+    use std::path::Path;
+
+    #[test]
+    fn record_only_reports_changes_once_per_day() {
+        let mut registry = ProjectRegistry::default();
+        let path = Path::new("/work/app/default.nix");
+        let morning = Utc.with_ymd_and_hms(2024, 5, 1, 8, 0, 0).unwrap();
+        let evening = Utc.with_ymd_and_hms(2024, 5, 1, 20, 0, 0).unwrap();
+        let next_day = Utc.with_ymd_and_hms(2024, 5, 2, 8, 0, 0).unwrap();
+
+        assert!(registry.record(path, morning));
+        assert!(!registry.record(path, evening));
+        assert!(registry.record(path, next_day));
+        assert_eq!(registry.projects[path].last_seen, next_day);
+
+        let toml = toml::to_string(&registry).expect("serialize failed");
+        let decoded: ProjectRegistry = toml::from_str(&toml).expect("deserialize failed");
+        assert_eq!(decoded, registry);
+    }
+}
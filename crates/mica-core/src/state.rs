@@ -4,6 +4,8 @@ use std::collections::BTreeMap;
 use std::path::Path;
 
 pub const NIX_EXPR_PREFIX: &str = "__mica_nix_expr__:";
+pub const MICA_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const MAX_GENERATIONS: usize = 50;
 
 #[derive(Debug, thiserror::Error)]
 pub enum StateError {
@@ -24,6 +26,45 @@ pub struct MicaMetadata {
     pub modified: DateTime<Utc>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionCheck {
+    Compatible,
+    Newer,
+    Unknown,
+}
+
+impl MicaMetadata {
+    pub fn version_check(&self) -> VersionCheck {
+        check_version(&self.version)
+    }
+}
+
+pub fn check_version(version: &str) -> VersionCheck {
+    match (parse_version(version), parse_version(MICA_VERSION)) {
+        (Some(written), Some(current)) if written > current => VersionCheck::Newer,
+        (Some(_), Some(_)) => VersionCheck::Compatible,
+        _ => VersionCheck::Unknown,
+    }
+}
+
+fn parse_version(version: &str) -> Option<(u64, u64, u64)> {
+    let core = version.trim().trim_start_matches('v');
+    let core = core.split(['-', '+']).next().unwrap_or(core);
+    let mut parts = core.split('.').map(|part| part.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().unwrap_or(Some(0))?;
+    let patch = parts.next().unwrap_or(Some(0))?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some((major, minor, patch))
+}
+
+/// GitHub-style archive tarball URL for a revision of a repository.
+pub fn archive_url(url: &str, rev: &str) -> String {
+    format!("{}/archive/{}.tar.gz", url, rev)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Pin {
     #[serde(default, skip_serializing_if = "Option::is_none")]
@@ -33,6 +74,26 @@ pub struct Pin {
     pub sha256: String,
     pub branch: String,
     pub updated: NaiveDate,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub channel: Option<PinChannel>,
+}
+
+/// A pin resolved from a nixos channel release rather than a branch.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PinChannel {
+    pub name: String,
+    /// The release's `nixexprs.tar.xz`; the pin's sha256 is computed from it.
+    pub tarball: String,
+}
+
+impl Pin {
+    /// URL that `fetchTarball` downloads for this pin.
+    pub fn tarball_url(&self) -> String {
+        match &self.channel {
+            Some(channel) => channel.tarball.clone(),
+            None => archive_url(&self.url, &self.rev),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
@@ -49,6 +110,10 @@ pub struct PackagesState {
     pub removed: Vec<String>,
     #[serde(default)]
     pub pinned: BTreeMap<String, PinnedPackage>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub priorities: BTreeMap<String, i32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub overrides: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -63,6 +128,38 @@ pub struct ShellState {
     pub hook: Option<String>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct BuildEnvState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_outputs: Vec<String>,
+    #[serde(default)]
+    pub ignore_collisions: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+}
+
+impl BuildEnvState {
+    pub fn is_default(&self) -> bool {
+        self == &BuildEnvState::default()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct ManifestState {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+}
+
+impl ManifestState {
+    pub fn is_default(&self) -> bool {
+        self == &ManifestState::default()
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
 pub struct NixBlocks {
     #[serde(default, rename = "let")]
@@ -99,6 +196,12 @@ pub struct ProjectState {
     pub shell: ShellState,
     #[serde(default)]
     pub nix: NixBlocks,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    #[serde(default, skip_serializing_if = "BuildEnvState::is_default")]
+    pub build_env: BuildEnvState,
+    #[serde(default, skip_serializing_if = "ManifestState::is_default")]
+    pub manifest: ManifestState,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -111,6 +214,69 @@ pub struct GlobalProfileState {
     pub packages: PackagesState,
     #[serde(default)]
     pub generations: GenerationsState,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<HostPackages>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
+pub struct HostPackages {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub os: Option<String>,
+    #[serde(default)]
+    pub packages: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostInfo {
+    pub hostname: String,
+    pub os: String,
+}
+
+impl HostInfo {
+    pub fn new(hostname: &str, os: &str) -> HostInfo {
+        HostInfo {
+            hostname: hostname.trim().to_ascii_lowercase(),
+            os: normalize_os(os),
+        }
+    }
+}
+
+impl HostPackages {
+    pub fn matches(&self, host: &HostInfo) -> bool {
+        let hostname_matches = self.hostname.as_deref().is_none_or(|hostname| {
+            let hostname = hostname.trim().to_ascii_lowercase();
+            host.hostname == hostname || host.hostname.split('.').next() == Some(hostname.as_str())
+        });
+        let os_matches = self
+            .os
+            .as_deref()
+            .is_none_or(|os| normalize_os(os) == host.os);
+        hostname_matches && os_matches
+    }
+
+    pub fn label(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(hostname) = &self.hostname {
+            parts.push(format!("hostname={}", hostname));
+        }
+        if let Some(os) = &self.os {
+            parts.push(format!("os={}", os));
+        }
+        if parts.is_empty() {
+            "any".to_string()
+        } else {
+            parts.join(" ")
+        }
+    }
+}
+
+fn normalize_os(os: &str) -> String {
+    match os.trim().to_ascii_lowercase().as_str() {
+        "darwin" | "macos" | "osx" => "macos".to_string(),
+        other => other.to_string(),
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Default)]
@@ -140,7 +306,45 @@ impl ProjectState {
     }
 }
 
+impl GenerationsState {
+    pub fn record(&mut self, entry: GenerationEntry) {
+        self.history.retain(|existing| existing.id != entry.id);
+        self.history.push(entry);
+        self.normalize();
+    }
+
+    pub fn merge(&mut self, other: &GenerationsState) {
+        for entry in &other.history {
+            match self
+                .history
+                .iter_mut()
+                .find(|existing| existing.id == entry.id)
+            {
+                Some(existing) if existing.timestamp < entry.timestamp => *existing = entry.clone(),
+                Some(_) => {}
+                None => self.history.push(entry.clone()),
+            }
+        }
+        self.normalize();
+    }
+
+    fn normalize(&mut self) {
+        self.history.sort_by_key(|entry| entry.id);
+        if self.history.len() > MAX_GENERATIONS {
+            let keep_from = self.history.len() - MAX_GENERATIONS;
+            self.history = self.history.split_off(keep_from);
+        }
+    }
+}
+
 impl GlobalProfileState {
+    pub fn host_packages(&self, host: &HostInfo) -> Vec<&HostPackages> {
+        self.hosts
+            .iter()
+            .filter(|entry| entry.matches(host))
+            .collect()
+    }
+
     pub fn load_from_path(path: &Path) -> Result<GlobalProfileState, StateError> {
         let content = std::fs::read_to_string(path).map_err(StateError::Read)?;
         let state = toml::from_str(&content).map_err(StateError::Parse)?;
@@ -154,11 +358,46 @@ impl GlobalProfileState {
     }
 }
 
+/// Minimal project state for tests; override fields with `..test_state()`.
+#[cfg(test)]
+pub(crate) fn test_state() -> ProjectState {
+    let timestamp = DateTime::parse_from_rfc3339("2026-02-06T00:00:00Z")
+        .expect("timestamp parse failed")
+        .with_timezone(&Utc);
+    ProjectState {
+        mica: MicaMetadata {
+            version: "0.1.0".to_string(),
+            created: timestamp,
+            modified: timestamp,
+        },
+        pin: Pin {
+            name: None,
+            url: "https://github.com/NixOS/nixpkgs".to_string(),
+            rev: "deadbeef".to_string(),
+            sha256: "0123456789abcdef0123456789abcdef0123456789abcdef0123".to_string(),
+            branch: "main".to_string(),
+            updated: NaiveDate::from_ymd_opt(2026, 2, 6).expect("date parse failed"),
+            channel: None,
+        },
+        pins: BTreeMap::new(),
+        presets: PresetState::default(),
+        packages: PackagesState::default(),
+        env: BTreeMap::new(),
+        shell: ShellState::default(),
+        nix: NixBlocks::default(),
+        extends: None,
+        build_env: BuildEnvState::default(),
+        manifest: ManifestState::default(),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use crate::state::{
-        GenerationEntry, GenerationsState, GlobalProfileState, MicaMetadata, NixBlocks,
-        PackagesState, Pin, PinnedPackage, PresetState, ProjectState, ShellState,
+        check_version, BuildEnvState, GenerationEntry, GenerationsState, GlobalProfileState,
+        HostInfo, HostPackages, ManifestState, MicaMetadata, NixBlocks, PackagesState, Pin,
+        PinnedPackage, PresetState, ProjectState, ShellState, VersionCheck, MAX_GENERATIONS,
+        MICA_VERSION,
     };
     use chrono::{DateTime, NaiveDate, Utc};
     use std::collections::BTreeMap;
@@ -187,6 +426,7 @@ mod tests {
                     sha256: "sha256-TEST".to_string(),
                     branch: "nixos-23.11".to_string(),
                     updated: date(),
+                    channel: None,
                 },
             },
         );
@@ -204,6 +444,7 @@ mod tests {
                 sha256: "sha256-AAAA".to_string(),
                 branch: "main".to_string(),
                 updated: date(),
+                channel: None,
             },
             pins: BTreeMap::from([(
                 "rust".to_string(),
@@ -214,6 +455,7 @@ mod tests {
                     sha256: "sha256-RUST".to_string(),
                     branch: "master".to_string(),
                     updated: date(),
+                    channel: None,
                 },
             )]),
             presets: PresetState {
@@ -223,6 +465,8 @@ mod tests {
                 added: vec!["jq".to_string()],
                 removed: vec!["cargo-edit".to_string()],
                 pinned,
+                priorities: BTreeMap::new(),
+                overrides: BTreeMap::new(),
             },
             env: BTreeMap::from([("EDITOR".to_string(), "nvim".to_string())]),
             shell: ShellState {
@@ -240,6 +484,9 @@ mod tests {
                 override_merge: Some("// uvEnv.uvEnvVars".to_string()),
                 override_shell_hook: Some("${uvEnv.shellHook or \"\"}".to_string()),
             },
+            extends: Some("../default.nix".to_string()),
+            build_env: BuildEnvState::default(),
+            manifest: ManifestState::default(),
         };
 
         let toml = toml::to_string(&state).expect("serialize failed");
@@ -262,6 +509,7 @@ mod tests {
                 sha256: "sha256-AAAA".to_string(),
                 branch: "main".to_string(),
                 updated: date(),
+                channel: None,
             },
             presets: PresetState {
                 active: vec!["devops".to_string()],
@@ -274,10 +522,85 @@ mod tests {
                     packages: vec!["ripgrep".to_string()],
                 }],
             },
+            hosts: vec![HostPackages {
+                hostname: Some("workstation".to_string()),
+                os: None,
+                packages: vec!["steam".to_string()],
+            }],
         };
 
         let toml = toml::to_string(&state).expect("serialize failed");
         let decoded: GlobalProfileState = toml::from_str(&toml).expect("deserialize failed");
         assert_eq!(state, decoded);
     }
+
+    #[test]
+    fn host_packages_match_hostname_and_os() {
+        let workstation = HostPackages {
+            hostname: Some("Workstation".to_string()),
+            os: None,
+            packages: vec!["steam".to_string()],
+        };
+        let mac = HostPackages {
+            hostname: None,
+            os: Some("darwin".to_string()),
+            packages: vec!["coreutils".to_string()],
+        };
+        let laptop_linux = HostPackages {
+            hostname: Some("laptop".to_string()),
+            os: Some("linux".to_string()),
+            packages: vec!["powertop".to_string()],
+        };
+
+        let host = HostInfo::new("workstation.lan", "linux");
+        assert!(workstation.matches(&host));
+        assert!(!mac.matches(&host));
+        assert!(!laptop_linux.matches(&host));
+
+        let host = HostInfo::new("laptop", "macos");
+        assert!(!workstation.matches(&host));
+        assert!(mac.matches(&host));
+        assert!(!laptop_linux.matches(&host));
+
+        assert_eq!(laptop_linux.label(), "hostname=laptop os=linux");
+    }
+
+    #[test]
+    fn newer_state_versions_are_detected() {
+        assert_eq!(check_version(MICA_VERSION), VersionCheck::Compatible);
+        assert_eq!(check_version("0.0.9"), VersionCheck::Compatible);
+        assert_eq!(check_version("99.0.0"), VersionCheck::Newer);
+        assert_eq!(check_version("v99.1"), VersionCheck::Newer);
+        assert_eq!(check_version("99.0.0-beta.1"), VersionCheck::Newer);
+        assert_eq!(check_version("banana"), VersionCheck::Unknown);
+        assert_eq!(check_version(""), VersionCheck::Unknown);
+    }
+
+    #[test]
+    fn generation_histories_merge_by_id() {
+        let entry = |id: u64, minutes: i64, package: &str| GenerationEntry {
+            id,
+            timestamp: timestamp() + chrono::Duration::minutes(minutes),
+            packages: vec![package.to_string()],
+        };
+        let mut saved = GenerationsState {
+            history: vec![entry(1, 0, "jq"), entry(3, 2, "fd")],
+        };
+        let stale = GenerationsState {
+            history: vec![entry(1, 0, "jq"), entry(2, 1, "bat"), entry(3, 0, "old")],
+        };
+        saved.merge(&stale);
+        assert_eq!(
+            saved.history,
+            vec![entry(1, 0, "jq"), entry(2, 1, "bat"), entry(3, 2, "fd")]
+        );
+
+        saved.record(entry(2, 5, "rg"));
+        assert_eq!(saved.history[1], entry(2, 5, "rg"));
+        for id in 10..70 {
+            saved.record(entry(id, 10, "jq"));
+        }
+        assert_eq!(saved.history.len(), MAX_GENERATIONS);
+        assert_eq!(saved.history[0].id, 20);
+    }
 }
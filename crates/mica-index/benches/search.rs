@@ -0,0 +1,26 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use mica_index::fixtures::synthetic_index;
+use mica_index::generate::{search_packages_with_mode, SearchMode};
+
+fn search(c: &mut Criterion) {
+    let conn = synthetic_index(20_000).expect("failed to build index");
+    let mut group = c.benchmark_group("search_20k");
+    let cases = [
+        ("all", "editor", SearchMode::All),
+        ("name", "rust", SearchMode::Name),
+        ("description", "network", SearchMode::Description),
+        ("binary", "shell-git", SearchMode::Binary),
+        ("maintainer", "maintainer42", SearchMode::Maintainer),
+    ];
+    for (label, query, mode) in cases {
+        group.bench_function(label, |b| {
+            b.iter(|| {
+                search_packages_with_mode(&conn, black_box(query), 50, mode).expect("search failed")
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use crate::generate::{ingest_packages, init_db, IndexError, NixPackage};
+use rusqlite::Connection;
+use std::path::Path;
+
+const WORDS: &[&str] = &[
+    "terminal", "editor", "compiler", "network", "archive", "image", "audio", "database", "shell",
+    "git", "python", "rust", "server", "client", "monitor", "font",
+];
+
+pub fn synthetic_packages(count: usize) -> Vec<NixPackage> {
+    (0..count)
+        .map(|idx| {
+            let word = WORDS[idx % WORDS.len()];
+            let other = WORDS[(idx / WORDS.len()) % WORDS.len()];
+            let name = format!("{}-{}{}", word, other, idx);
+            NixPackage {
+                attr_path: name.clone(),
+                name: format!("{}-1.{}.0", name, idx % 10),
+                version: Some(format!("1.{}.0", idx % 10)),
+                description: Some(format!("A {} tool for {} workflows", word, other)),
+                homepage: Some(format!("https://example.org/{}", name)),
+                license: Some(serde_json::json!({ "spdxId": "MIT" })),
+                platforms: None,
+                main_program: Some(name.clone()),
+                position: None,
+                broken: Some(false),
+                insecure: Some(false),
+                maintainers: Some(format!("maintainer{}", idx % 100)),
+                last_updated: None,
+            }
+        })
+        .collect()
+}
+
+pub fn synthetic_index(count: usize) -> Result<Connection, IndexError> {
+    let mut conn = init_db(Path::new(":memory:"))?;
+    ingest_packages(&mut conn, &synthetic_packages(count))?;
+    Ok(conn)
+}
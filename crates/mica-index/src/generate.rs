@@ -1,7 +1,7 @@
 use crate::schema::SCHEMA;
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 use std::path::Path;
 
 #[derive(Debug, thiserror::Error)]
@@ -12,6 +12,10 @@ pub enum IndexError {
     Read(std::io::Error),
     #[error("failed to parse json: {0}")]
     Json(serde_json::Error),
+    #[error("failed to write output: {0}")]
+    Write(std::io::Error),
+    #[error("invalid record on line {0}: {1}")]
+    InvalidRecord(usize, serde_json::Error),
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +31,10 @@ pub struct NixPackage {
     pub position: Option<String>,
     pub broken: Option<bool>,
     pub insecure: Option<bool>,
+    #[serde(default)]
+    pub maintainers: Option<String>,
+    #[serde(default)]
+    pub last_updated: Option<String>,
 }
 
 pub fn init_db(path: &Path) -> Result<Connection, IndexError> {
@@ -53,6 +61,12 @@ fn ensure_packages_columns(conn: &Connection) -> Result<(), IndexError> {
     if !columns.contains("position") {
         conn.execute("ALTER TABLE packages ADD COLUMN position TEXT", [])?;
     }
+    if !columns.contains("maintainers") {
+        conn.execute("ALTER TABLE packages ADD COLUMN maintainers TEXT", [])?;
+    }
+    if !columns.contains("last_updated") {
+        conn.execute("ALTER TABLE packages ADD COLUMN last_updated TEXT", [])?;
+    }
     Ok(())
 }
 
@@ -64,42 +78,144 @@ pub fn ingest_packages(conn: &mut Connection, packages: &[NixPackage]) -> Result
         "INSERT INTO packages_fts(packages_fts) VALUES('delete-all')",
         [],
     )?;
-    {
-        let mut stmt = tx.prepare(
-            "INSERT OR REPLACE INTO packages (attr_path, name, version, description, homepage, license, platforms, main_program, position, broken, insecure) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
-        )?;
-        let mut bin_stmt =
-            tx.prepare("INSERT INTO package_binaries (package_id, binary_name) VALUES (?1, ?2)")?;
-        for pkg in packages {
-            let license_json = pkg.license.as_ref().map(|v| v.to_string());
-            let platforms_json = pkg.platforms.as_ref().map(|v| v.to_string());
-            stmt.execute(params![
-                pkg.attr_path,
-                pkg.name,
-                pkg.version,
-                pkg.description,
-                pkg.homepage,
-                license_json,
-                platforms_json,
-                pkg.main_program,
-                pkg.position,
-                pkg.broken.unwrap_or(false) as i32,
-                pkg.insecure.unwrap_or(false) as i32,
-            ])?;
-            let pkg_id = tx.last_insert_rowid();
-            if let Some(main_program) = pkg
-                .main_program
-                .as_deref()
-                .filter(|value| !value.trim().is_empty())
-            {
-                bin_stmt.execute(params![pkg_id, main_program])?;
-            }
-        }
+    insert_packages(&tx, packages, false)?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn replace_packages_where(
+    conn: &mut Connection,
+    in_scope: impl Fn(&str) -> bool,
+    packages: &[NixPackage],
+) -> Result<usize, IndexError> {
+    let tx = conn.transaction()?;
+    let existing: Vec<String> = {
+        let mut stmt = tx.prepare("SELECT attr_path FROM packages")?;
+        let rows = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        rows.collect::<Result<_, _>>()?
+    };
+    for attr_path in existing.iter().filter(|attr| in_scope(attr)) {
+        remove_package(&tx, attr_path)?;
     }
+    insert_packages(&tx, packages, true)?;
+    let count: i64 = tx.query_row("SELECT COUNT(*) FROM packages", [], |row| row.get(0))?;
+    tx.commit()?;
+    Ok(count as usize)
+}
+
+pub fn ingest_package_chunk(
+    conn: &mut Connection,
+    chunk: &str,
+    fingerprint: &str,
+    packages: &[NixPackage],
+) -> Result<(), IndexError> {
+    let tx = conn.transaction()?;
+    tx.execute_batch(INGEST_CHUNKS_SCHEMA)?;
+    insert_packages(&tx, packages, true)?;
+    tx.execute(
+        "INSERT OR REPLACE INTO ingest_chunks (chunk, fingerprint, package_count) VALUES (?1, ?2, ?3)",
+        params![chunk, fingerprint, packages.len() as i64],
+    )?;
     tx.commit()?;
     Ok(())
 }
 
+pub fn ingested_chunks(conn: &Connection) -> Result<BTreeMap<String, String>, IndexError> {
+    conn.execute_batch(INGEST_CHUNKS_SCHEMA)?;
+    let mut stmt = conn.prepare("SELECT chunk, fingerprint FROM ingest_chunks")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    let mut chunks = BTreeMap::new();
+    for row in rows {
+        let (chunk, fingerprint) = row?;
+        chunks.insert(chunk, fingerprint);
+    }
+    Ok(chunks)
+}
+
+pub fn finish_chunked_ingest(conn: &Connection) -> Result<usize, IndexError> {
+    conn.execute("DROP TABLE IF EXISTS ingest_chunks", [])?;
+    let count: i64 = conn.query_row("SELECT COUNT(*) FROM packages", [], |row| row.get(0))?;
+    Ok(count as usize)
+}
+
+const INGEST_CHUNKS_SCHEMA: &str = "CREATE TABLE IF NOT EXISTS ingest_chunks (
+    chunk TEXT PRIMARY KEY,
+    fingerprint TEXT NOT NULL,
+    package_count INTEGER NOT NULL
+);";
+
+fn remove_package(tx: &Transaction, attr_path: &str) -> Result<(), IndexError> {
+    let existing = tx
+        .prepare_cached(
+            "SELECT id, attr_path, name, description FROM packages WHERE attr_path = ?1",
+        )?
+        .query_row(params![attr_path], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Option<String>>(3)?,
+            ))
+        })
+        .optional()?;
+    let Some((id, attr_path, name, description)) = existing else {
+        return Ok(());
+    };
+    tx.execute(
+        "INSERT INTO packages_fts(packages_fts, rowid, attr_path, name, description) VALUES('delete', ?1, ?2, ?3, ?4)",
+        params![id, attr_path, name, description],
+    )?;
+    tx.execute(
+        "DELETE FROM package_binaries WHERE package_id = ?1",
+        params![id],
+    )?;
+    tx.execute("DELETE FROM packages WHERE id = ?1", params![id])?;
+    Ok(())
+}
+
+fn insert_packages(
+    tx: &Transaction,
+    packages: &[NixPackage],
+    replace_existing: bool,
+) -> Result<(), IndexError> {
+    let mut stmt = tx.prepare(
+        "INSERT OR REPLACE INTO packages (attr_path, name, version, description, homepage, license, platforms, main_program, position, broken, insecure, maintainers, last_updated) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+    )?;
+    let mut bin_stmt =
+        tx.prepare("INSERT INTO package_binaries (package_id, binary_name) VALUES (?1, ?2)")?;
+    for pkg in packages {
+        if replace_existing {
+            remove_package(tx, &pkg.attr_path)?;
+        }
+        let license_json = pkg.license.as_ref().map(|v| v.to_string());
+        let platforms_json = pkg.platforms.as_ref().map(|v| v.to_string());
+        stmt.execute(params![
+            pkg.attr_path,
+            pkg.name,
+            pkg.version,
+            pkg.description,
+            pkg.homepage,
+            license_json,
+            platforms_json,
+            pkg.main_program,
+            pkg.position,
+            pkg.broken.unwrap_or(false) as i32,
+            pkg.insecure.unwrap_or(false) as i32,
+            pkg.maintainers,
+            pkg.last_updated,
+        ])?;
+        let pkg_id = tx.last_insert_rowid();
+        if let Some(main_program) = pkg
+            .main_program
+            .as_deref()
+            .filter(|value| !value.trim().is_empty())
+        {
+            bin_stmt.execute(params![pkg_id, main_program])?;
+        }
+    }
+    Ok(())
+}
+
 pub fn set_meta(conn: &Connection, key: &str, value: &str) -> Result<(), IndexError> {
     conn.execute(
         "INSERT OR REPLACE INTO meta (key, value) VALUES (?1, ?2)",
@@ -181,6 +297,14 @@ pub fn load_packages_from_json(path: &Path) -> Result<Vec<NixPackage>, IndexErro
             .get("insecure")
             .and_then(|v| v.as_bool())
             .or_else(|| meta.and_then(|m| m.get("insecure").and_then(|v| v.as_bool())));
+        let maintainers = entry
+            .get("maintainers")
+            .or_else(|| meta.and_then(|m| m.get("maintainers")))
+            .and_then(maintainer_handles);
+        let last_updated = entry
+            .get("lastUpdated")
+            .and_then(|v| v.as_str())
+            .map(|s| s.to_string());
 
         packages.push(NixPackage {
             attr_path,
@@ -194,12 +318,43 @@ pub fn load_packages_from_json(path: &Path) -> Result<Vec<NixPackage>, IndexErro
             position,
             broken,
             insecure,
+            maintainers,
+            last_updated,
         });
     }
 
     Ok(packages)
 }
 
+fn maintainer_handles(value: &serde_json::Value) -> Option<String> {
+    let entries = match value {
+        serde_json::Value::Array(items) => items.iter().collect::<Vec<_>>(),
+        other => vec![other],
+    };
+    let mut handles = Vec::new();
+    for entry in entries {
+        let handle = match entry {
+            serde_json::Value::String(value) => Some(value.as_str()),
+            serde_json::Value::Object(map) => map
+                .get("github")
+                .and_then(|v| v.as_str())
+                .or_else(|| map.get("name").and_then(|v| v.as_str()))
+                .or_else(|| map.get("email").and_then(|v| v.as_str())),
+            _ => None,
+        };
+        if let Some(handle) = handle.map(str::trim).filter(|h| !h.is_empty()) {
+            if !handles.iter().any(|existing| existing == handle) {
+                handles.push(handle.to_string());
+            }
+        }
+    }
+    if handles.is_empty() {
+        None
+    } else {
+        Some(handles.join(", "))
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct PackageInfo {
     pub attr_path: String,
@@ -213,6 +368,8 @@ pub struct PackageInfo {
     pub position: Option<String>,
     pub broken: bool,
     pub insecure: bool,
+    pub maintainers: Option<String>,
+    pub last_updated: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -220,6 +377,7 @@ pub enum SearchMode {
     Name,
     Description,
     Binary,
+    Maintainer,
     All,
 }
 
@@ -228,6 +386,7 @@ struct ParsedSearch {
     query: String,
     mode: SearchMode,
     exact: bool,
+    pin: Option<String>,
 }
 
 pub fn search_packages(
@@ -245,45 +404,56 @@ pub fn search_packages_with_mode(
     mode: SearchMode,
 ) -> Result<Vec<PackageInfo>, IndexError> {
     let parsed = parse_search_shortcuts(query, mode);
+    let pin = parsed.pin.as_deref();
     if parsed.query.is_empty() {
-        return Ok(Vec::new());
+        return match pin {
+            Some(pin) => list_packages_in_scope(conn, limit, Some(pin)),
+            None => Ok(Vec::new()),
+        };
     }
 
     match (parsed.mode, parsed.exact) {
-        (SearchMode::Name, false) => search_packages_fts(conn, &parsed.query, limit, Some("name")),
+        (SearchMode::Name, false) => {
+            search_packages_fts(conn, &parsed.query, limit, Some("name"), pin)
+        }
         (SearchMode::Description, false) => {
-            search_packages_fts(conn, &parsed.query, limit, Some("description"))
+            search_packages_fts(conn, &parsed.query, limit, Some("description"), pin)
         }
-        (SearchMode::Binary, false) => search_packages_by_binary(conn, &parsed.query, limit),
-        (SearchMode::Name, true) => search_packages_by_name_exact(conn, &parsed.query, limit),
+        (SearchMode::Binary, false) => search_packages_by_binary(conn, &parsed.query, limit, pin),
+        (SearchMode::Name, true) => search_packages_by_name_exact(conn, &parsed.query, limit, pin),
         (SearchMode::Description, true) => {
-            search_packages_by_description_exact(conn, &parsed.query, limit)
+            search_packages_by_description_exact(conn, &parsed.query, limit, pin)
+        }
+        (SearchMode::Binary, true) => {
+            search_packages_by_binary_exact(conn, &parsed.query, limit, pin)
+        }
+        (SearchMode::Maintainer, exact) => {
+            search_packages_by_maintainer(conn, &parsed.query, limit, exact, pin)
         }
-        (SearchMode::Binary, true) => search_packages_by_binary_exact(conn, &parsed.query, limit),
         (SearchMode::All, false) => {
-            let mut results = search_packages_fts(conn, &parsed.query, limit, None)?;
+            let mut results = search_packages_fts(conn, &parsed.query, limit, None, pin)?;
             if results.len() < limit {
                 append_unique_by_attr(
                     &mut results,
-                    search_packages_by_binary(conn, &parsed.query, limit)?,
+                    search_packages_by_binary(conn, &parsed.query, limit, pin)?,
                     limit,
                 );
             }
             Ok(results)
         }
         (SearchMode::All, true) => {
-            let mut results = search_packages_by_name_exact(conn, &parsed.query, limit)?;
+            let mut results = search_packages_by_name_exact(conn, &parsed.query, limit, pin)?;
             if results.len() < limit {
                 append_unique_by_attr(
                     &mut results,
-                    search_packages_by_description_exact(conn, &parsed.query, limit)?,
+                    search_packages_by_description_exact(conn, &parsed.query, limit, pin)?,
                     limit,
                 );
             }
             if results.len() < limit {
                 append_unique_by_attr(
                     &mut results,
-                    search_packages_by_binary_exact(conn, &parsed.query, limit)?,
+                    search_packages_by_binary_exact(conn, &parsed.query, limit, pin)?,
                     limit,
                 );
             }
@@ -295,6 +465,7 @@ pub fn search_packages_with_mode(
 fn parse_search_shortcuts(query: &str, default_mode: SearchMode) -> ParsedSearch {
     let mut mode = default_mode;
     let mut exact = false;
+    let mut pin = None;
     let mut remaining = query.trim();
 
     loop {
@@ -306,6 +477,14 @@ fn parse_search_shortcuts(query: &str, default_mode: SearchMode) -> ParsedSearch
                 continue;
             }
         }
+        if let Some(rest) = strip_prefix_ignore_ascii_case(trimmed, "pin:") {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if end > 0 {
+                pin = Some(rest[..end].to_string());
+            }
+            remaining = &rest[end..];
+            continue;
+        }
         if let Some((shortcut_mode, rest)) = parse_search_mode_shortcut(trimmed) {
             mode = shortcut_mode;
             remaining = rest;
@@ -319,6 +498,7 @@ fn parse_search_shortcuts(query: &str, default_mode: SearchMode) -> ParsedSearch
         query: remaining.trim().to_string(),
         mode,
         exact,
+        pin,
     }
 }
 
@@ -334,6 +514,8 @@ fn parse_search_mode_shortcut(value: &str) -> Option<(SearchMode, &str)> {
         ("attr:", SearchMode::Name),
         ("desc:", SearchMode::Description),
         ("description:", SearchMode::Description),
+        ("maint:", SearchMode::Maintainer),
+        ("maintainer:", SearchMode::Maintainer),
         ("all:", SearchMode::All),
     ];
 
@@ -373,16 +555,17 @@ fn search_packages_by_binary(
     conn: &Connection,
     query: &str,
     limit: usize,
+    pin: Option<&str>,
 ) -> Result<Vec<PackageInfo>, IndexError> {
     let mut stmt = conn.prepare(
-        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure \
+        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure, p.maintainers, p.last_updated \
          FROM packages p \
          JOIN package_binaries b ON p.id = b.package_id \
-         WHERE b.binary_name LIKE ?1 || '%' \
+         WHERE b.binary_name LIKE ?1 || '%' AND (?3 IS NULL OR substr(p.attr_path, 1, length(?3) + 1) = ?3 || '.') \
          ORDER BY b.binary_name \
          LIMIT ?2",
     )?;
-    let rows = stmt.query_map(params![query, limit as i64], |row| {
+    let rows = stmt.query_map(params![query, limit as i64, pin], |row| {
         Ok(PackageInfo {
             attr_path: row.get(0)?,
             name: row.get(1)?,
@@ -395,6 +578,8 @@ fn search_packages_by_binary(
             position: row.get(8)?,
             broken: row.get::<_, i32>(9)? != 0,
             insecure: row.get::<_, i32>(10)? != 0,
+            maintainers: row.get(11)?,
+            last_updated: row.get(12)?,
         })
     })?;
     let mut results = Vec::new();
@@ -408,15 +593,17 @@ fn search_packages_by_binary_exact(
     conn: &Connection,
     query: &str,
     limit: usize,
+    pin: Option<&str>,
 ) -> Result<Vec<PackageInfo>, IndexError> {
     let mut stmt = conn.prepare(
-        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure \
+        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure, p.maintainers, p.last_updated \
          FROM packages p \
          WHERE EXISTS (SELECT 1 FROM package_binaries b WHERE b.package_id = p.id AND LOWER(b.binary_name) = LOWER(?1)) \
+         AND (?3 IS NULL OR substr(p.attr_path, 1, length(?3) + 1) = ?3 || '.') \
          ORDER BY p.name \
          LIMIT ?2",
     )?;
-    let rows = stmt.query_map(params![query, limit as i64], |row| {
+    let rows = stmt.query_map(params![query, limit as i64, pin], |row| {
         Ok(PackageInfo {
             attr_path: row.get(0)?,
             name: row.get(1)?,
@@ -429,6 +616,8 @@ fn search_packages_by_binary_exact(
             position: row.get(8)?,
             broken: row.get::<_, i32>(9)? != 0,
             insecure: row.get::<_, i32>(10)? != 0,
+            maintainers: row.get(11)?,
+            last_updated: row.get(12)?,
         })
     })?;
     let mut results = Vec::new();
@@ -442,19 +631,22 @@ fn search_packages_by_name_exact(
     conn: &Connection,
     query: &str,
     limit: usize,
+    pin: Option<&str>,
 ) -> Result<Vec<PackageInfo>, IndexError> {
     let mut stmt = conn.prepare(
-        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure \
+        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure, p.maintainers, p.last_updated \
          FROM packages p \
-         WHERE LOWER(p.attr_path) = LOWER(?1) OR LOWER(p.name) = LOWER(?1) \
+         WHERE (LOWER(p.attr_path) = LOWER(?1) OR LOWER(p.name) = LOWER(?1) \
+           OR LOWER(p.attr_path) = LOWER(?3 || '.' || ?1)) \
+         AND (?3 IS NULL OR substr(p.attr_path, 1, length(?3) + 1) = ?3 || '.') \
          ORDER BY CASE \
-           WHEN LOWER(p.attr_path) = LOWER(?1) THEN 0 \
+           WHEN LOWER(p.attr_path) = LOWER(?1) OR LOWER(p.attr_path) = LOWER(?3 || '.' || ?1) THEN 0 \
            WHEN LOWER(p.name) = LOWER(?1) THEN 1 \
            ELSE 2 \
          END, p.name \
          LIMIT ?2",
     )?;
-    let rows = stmt.query_map(params![query, limit as i64], |row| {
+    let rows = stmt.query_map(params![query, limit as i64, pin], |row| {
         Ok(PackageInfo {
             attr_path: row.get(0)?,
             name: row.get(1)?,
@@ -467,6 +659,8 @@ fn search_packages_by_name_exact(
             position: row.get(8)?,
             broken: row.get::<_, i32>(9)? != 0,
             insecure: row.get::<_, i32>(10)? != 0,
+            maintainers: row.get(11)?,
+            last_updated: row.get(12)?,
         })
     })?;
     let mut results = Vec::new();
@@ -480,15 +674,17 @@ fn search_packages_by_description_exact(
     conn: &Connection,
     query: &str,
     limit: usize,
+    pin: Option<&str>,
 ) -> Result<Vec<PackageInfo>, IndexError> {
     let mut stmt = conn.prepare(
-        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure \
+        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure, p.maintainers, p.last_updated \
          FROM packages p \
          WHERE p.description IS NOT NULL AND LOWER(p.description) = LOWER(?1) \
+         AND (?3 IS NULL OR substr(p.attr_path, 1, length(?3) + 1) = ?3 || '.') \
          ORDER BY p.name \
          LIMIT ?2",
     )?;
-    let rows = stmt.query_map(params![query, limit as i64], |row| {
+    let rows = stmt.query_map(params![query, limit as i64, pin], |row| {
         Ok(PackageInfo {
             attr_path: row.get(0)?,
             name: row.get(1)?,
@@ -501,6 +697,53 @@ fn search_packages_by_description_exact(
             position: row.get(8)?,
             broken: row.get::<_, i32>(9)? != 0,
             insecure: row.get::<_, i32>(10)? != 0,
+            maintainers: row.get(11)?,
+            last_updated: row.get(12)?,
+        })
+    })?;
+    let mut results = Vec::new();
+    for row in rows {
+        results.push(row?);
+    }
+    Ok(results)
+}
+
+fn search_packages_by_maintainer(
+    conn: &Connection,
+    query: &str,
+    limit: usize,
+    exact: bool,
+    pin: Option<&str>,
+) -> Result<Vec<PackageInfo>, IndexError> {
+    let condition = if exact {
+        "(', ' || LOWER(p.maintainers) || ',') LIKE '%, ' || LOWER(?1) || ',%'"
+    } else {
+        "LOWER(p.maintainers) LIKE '%' || LOWER(?1) || '%'"
+    };
+    let mut stmt = conn.prepare(&format!(
+        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure, p.maintainers, p.last_updated \
+         FROM packages p \
+         WHERE p.maintainers IS NOT NULL AND {} \
+         AND (?3 IS NULL OR substr(p.attr_path, 1, length(?3) + 1) = ?3 || '.') \
+         ORDER BY p.name \
+         LIMIT ?2",
+        condition
+    ))?;
+    let rows = stmt.query_map(params![query, limit as i64, pin], |row| {
+        Ok(PackageInfo {
+            attr_path: row.get(0)?,
+            name: row.get(1)?,
+            version: row.get(2)?,
+            description: row.get(3)?,
+            homepage: row.get(4)?,
+            license: row.get(5)?,
+            platforms: row.get(6)?,
+            main_program: row.get(7)?,
+            position: row.get(8)?,
+            broken: row.get::<_, i32>(9)? != 0,
+            insecure: row.get::<_, i32>(10)? != 0,
+            maintainers: row.get(11)?,
+            last_updated: row.get(12)?,
         })
     })?;
     let mut results = Vec::new();
@@ -515,17 +758,19 @@ fn search_packages_fts(
     query: &str,
     limit: usize,
     column: Option<&str>,
+    pin: Option<&str>,
 ) -> Result<Vec<PackageInfo>, IndexError> {
     let fts_query = build_fts_query(query, column);
     let mut stmt = conn.prepare(
-        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure \
+        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure, p.maintainers, p.last_updated \
          FROM packages p \
          JOIN packages_fts fts ON p.id = fts.rowid \
          WHERE packages_fts MATCH ?1 \
+         AND (?3 IS NULL OR substr(p.attr_path, 1, length(?3) + 1) = ?3 || '.') \
          ORDER BY rank \
          LIMIT ?2",
     )?;
-    let rows = stmt.query_map([fts_query, limit.to_string()], |row| {
+    let rows = stmt.query_map(params![fts_query, limit as i64, pin], |row| {
         Ok(PackageInfo {
             attr_path: row.get(0)?,
             name: row.get(1)?,
@@ -538,6 +783,8 @@ fn search_packages_fts(
             position: row.get(8)?,
             broken: row.get::<_, i32>(9)? != 0,
             insecure: row.get::<_, i32>(10)? != 0,
+            maintainers: row.get(11)?,
+            last_updated: row.get(12)?,
         })
     })?;
     let mut results = Vec::new();
@@ -570,11 +817,21 @@ fn build_fts_query(query: &str, column: Option<&str>) -> String {
 }
 
 pub fn list_packages(conn: &Connection, limit: usize) -> Result<Vec<PackageInfo>, IndexError> {
+    list_packages_in_scope(conn, limit, None)
+}
+
+fn list_packages_in_scope(
+    conn: &Connection,
+    limit: usize,
+    pin: Option<&str>,
+) -> Result<Vec<PackageInfo>, IndexError> {
     let mut stmt = conn.prepare(
-        "SELECT attr_path, name, version, description, homepage, license, platforms, main_program, position, broken, insecure \
-         FROM packages ORDER BY name LIMIT ?1",
+        "SELECT p.attr_path, p.name, p.version, p.description, p.homepage, p.license, p.platforms, p.main_program, p.position, p.broken, p.insecure, p.maintainers, p.last_updated \
+         FROM packages p \
+         WHERE (?2 IS NULL OR substr(p.attr_path, 1, length(?2) + 1) = ?2 || '.') \
+         ORDER BY p.name LIMIT ?1",
     )?;
-    let rows = stmt.query_map([limit.to_string()], |row| {
+    let rows = stmt.query_map(params![limit as i64, pin], |row| {
         Ok(PackageInfo {
             attr_path: row.get(0)?,
             name: row.get(1)?,
@@ -587,6 +844,8 @@ pub fn list_packages(conn: &Connection, limit: usize) -> Result<Vec<PackageInfo>
             position: row.get(8)?,
             broken: row.get::<_, i32>(9)? != 0,
             insecure: row.get::<_, i32>(10)? != 0,
+            maintainers: row.get(11)?,
+            last_updated: row.get(12)?,
         })
     })?;
     let mut results = Vec::new();
@@ -596,11 +855,141 @@ pub fn list_packages(conn: &Connection, limit: usize) -> Result<Vec<PackageInfo>
     Ok(results)
 }
 
+pub fn get_package(conn: &Connection, attr_path: &str) -> Result<Option<PackageInfo>, IndexError> {
+    let mut stmt = conn.prepare(
+        "SELECT attr_path, name, version, description, homepage, license, platforms, main_program, position, broken, insecure, maintainers, last_updated \
+         FROM packages WHERE attr_path = ?1",
+    )?;
+    let mut rows = stmt.query_map([attr_path], |row| {
+        Ok(PackageInfo {
+            attr_path: row.get(0)?,
+            name: row.get(1)?,
+            version: row.get(2)?,
+            description: row.get(3)?,
+            homepage: row.get(4)?,
+            license: row.get(5)?,
+            platforms: row.get(6)?,
+            main_program: row.get(7)?,
+            position: row.get(8)?,
+            broken: row.get::<_, i32>(9)? != 0,
+            insecure: row.get::<_, i32>(10)? != 0,
+            maintainers: row.get(11)?,
+            last_updated: row.get(12)?,
+        })
+    })?;
+    match rows.next() {
+        Some(row) => Ok(Some(row?)),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BinaryCollision {
+    pub binary: String,
+    pub packages: Vec<String>,
+}
+
+pub fn find_binary_collisions(
+    conn: &Connection,
+    attr_paths: &[String],
+) -> Result<Vec<BinaryCollision>, IndexError> {
+    let mut stmt = conn.prepare(
+        "SELECT b.binary_name FROM package_binaries b JOIN packages p ON p.id = b.package_id \
+         WHERE p.attr_path = ?1",
+    )?;
+    let mut providers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for attr_path in attr_paths {
+        let rows = stmt.query_map([attr_path], |row| row.get::<_, String>(0))?;
+        for binary in rows {
+            let entry = providers.entry(binary?).or_default();
+            if !entry.contains(attr_path) {
+                entry.push(attr_path.clone());
+            }
+        }
+    }
+    Ok(providers
+        .into_iter()
+        .filter(|(_, packages)| packages.len() > 1)
+        .map(|(binary, mut packages)| {
+            packages.sort();
+            BinaryCollision { binary, packages }
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageAlias {
+    pub alias: String,
+    pub target: Option<String>,
+    pub message: Option<String>,
+}
+
+pub fn ingest_aliases(conn: &mut Connection, aliases: &[PackageAlias]) -> Result<(), IndexError> {
+    let tx = conn.transaction()?;
+    tx.execute("DELETE FROM package_aliases", [])?;
+    {
+        let mut stmt = tx.prepare(
+            "INSERT OR REPLACE INTO package_aliases (alias, target, message) VALUES (?1, ?2, ?3)",
+        )?;
+        for alias in aliases {
+            stmt.execute(params![alias.alias, alias.target, alias.message])?;
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+pub fn find_aliases(
+    conn: &Connection,
+    attr_paths: &[String],
+) -> Result<Vec<PackageAlias>, IndexError> {
+    let mut stmt =
+        conn.prepare("SELECT alias, target, message FROM package_aliases WHERE alias = ?1")?;
+    let mut aliases = Vec::new();
+    for attr_path in attr_paths {
+        let rows = stmt.query_map([attr_path], |row| {
+            Ok(PackageAlias {
+                alias: row.get(0)?,
+                target: row.get(1)?,
+                message: row.get(2)?,
+            })
+        })?;
+        for alias in rows {
+            aliases.push(alias?);
+        }
+    }
+    Ok(aliases)
+}
+
+pub fn find_attrs_by_pname(conn: &Connection, pname: &str) -> Result<Vec<String>, IndexError> {
+    let mut stmt = conn.prepare(
+        "SELECT attr_path, name FROM packages \
+         WHERE name = ?1 OR substr(name, 1, length(?1) + 1) = ?1 || '-' \
+         ORDER BY attr_path != ?1, length(attr_path), attr_path",
+    )?;
+    let rows = stmt.query_map([pname], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+    let mut attrs = Vec::new();
+    for row in rows {
+        let (attr, name) = row?;
+        let version_follows = name[pname.len()..]
+            .strip_prefix('-')
+            .is_none_or(|rest| !rest.starts_with(|ch: char| ch.is_ascii_alphabetic()));
+        if version_follows {
+            attrs.push(attr);
+        }
+    }
+    Ok(attrs)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::generate::{
-        ingest_packages, init_db, list_packages, search_packages, search_packages_with_mode,
-        NixPackage, SearchMode,
+        find_aliases, find_attrs_by_pname, find_binary_collisions, finish_chunked_ingest,
+        get_package, ingest_aliases, ingest_package_chunk, ingest_packages, ingested_chunks,
+        init_db, list_packages, replace_packages_where, search_packages, search_packages_with_mode,
+        BinaryCollision, NixPackage, PackageAlias, SearchMode,
     };
     use std::path::PathBuf;
     use std::sync::atomic::{AtomicU64, Ordering};
@@ -635,6 +1024,8 @@ mod tests {
             position: None,
             broken: Some(false),
             insecure: Some(false),
+            maintainers: None,
+            last_updated: None,
         }
     }
 
@@ -650,6 +1041,52 @@ mod tests {
         }
     }
 
+    #[test]
+    fn replace_packages_where_only_touches_the_scoped_rows() {
+        let path = temp_db_path();
+        let mut conn = init_db(&path).expect("db init failed");
+        ingest_packages(
+            &mut conn,
+            &[
+                pkg("ripgrep", "ripgrep", "rg"),
+                pkg("unstable.ripgrep", "ripgrep", "rg"),
+                pkg("unstable.fd", "fd", "fd"),
+            ],
+        )
+        .expect("ingest failed");
+
+        let count = replace_packages_where(
+            &mut conn,
+            |attr| attr.starts_with("unstable."),
+            &[pkg_with_description(
+                "unstable.jq",
+                "jq",
+                "jq",
+                "json processor",
+            )],
+        )
+        .expect("replace failed");
+        assert_eq!(count, 2);
+        let mut listed: Vec<String> = list_packages(&conn, 10)
+            .expect("list failed")
+            .into_iter()
+            .map(|pkg| pkg.attr_path)
+            .collect();
+        listed.sort();
+        assert_eq!(listed, vec!["ripgrep", "unstable.jq"]);
+        assert!(search_packages(&conn, "fd", 10)
+            .expect("search failed")
+            .is_empty());
+        assert_eq!(
+            find_binary_collisions(&conn, &["ripgrep".to_string(), "unstable.jq".to_string()])
+                .expect("collisions failed"),
+            Vec::<BinaryCollision>::new()
+        );
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
     #[test]
     fn ingest_packages_replaces_removed_rows() {
         let path = temp_db_path();
@@ -676,6 +1113,73 @@ mod tests {
         let _ = std::fs::remove_file(path);
     }
 
+    #[test]
+    fn chunked_ingest_merges_chunks_and_records_progress() {
+        let path = temp_db_path();
+        let mut conn = init_db(&path).expect("db init failed");
+
+        let first = vec![
+            pkg("alpha", "alpha-1", "alpha"),
+            pkg("beta", "beta", "beta"),
+        ];
+        ingest_package_chunk(&mut conn, "a.json", "10:1", &first).expect("first chunk failed");
+        let progress = ingested_chunks(&conn).expect("chunk lookup failed");
+        assert_eq!(progress.get("a.json").map(String::as_str), Some("10:1"));
+        assert!(!progress.contains_key("b.json"));
+
+        let second = vec![
+            pkg("alpha", "alpha-2", "alpha2"),
+            pkg("gamma", "gamma", "gamma"),
+        ];
+        ingest_package_chunk(&mut conn, "b.json", "20:1", &second).expect("second chunk failed");
+        assert_eq!(finish_chunked_ingest(&conn).expect("finish failed"), 3);
+        assert!(ingested_chunks(&conn)
+            .expect("chunk lookup failed")
+            .is_empty());
+
+        let alpha = get_package(&conn, "alpha")
+            .expect("lookup failed")
+            .expect("alpha missing");
+        assert_eq!(alpha.name, "alpha-2");
+        let hits = search_packages(&conn, "alpha", 10).expect("alpha search failed");
+        assert_eq!(hits.len(), 1);
+        let by_binary = |name: &str| {
+            search_packages_with_mode(&conn, &format!("'{}", name), 10, SearchMode::Binary)
+                .expect("binary search failed")
+        };
+        assert!(by_binary("alpha").is_empty());
+        assert_eq!(by_binary("alpha2").len(), 1);
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn binary_collisions_only_report_shared_programs() {
+        let path = temp_db_path();
+        let mut conn = init_db(&path).expect("db init failed");
+
+        let packages = vec![
+            pkg("ripgrep", "ripgrep", "rg"),
+            pkg("ripgrep-all", "ripgrep-all", "rg"),
+            pkg("fd", "fd", "fd"),
+        ];
+        ingest_packages(&mut conn, &packages).expect("ingest failed");
+
+        let selected = ["fd", "ripgrep-all", "ripgrep", "missing"].map(str::to_string);
+        let collisions = find_binary_collisions(&conn, &selected).expect("lookup failed");
+        assert_eq!(
+            collisions,
+            vec![BinaryCollision {
+                binary: "rg".to_string(),
+                packages: vec!["ripgrep".to_string(), "ripgrep-all".to_string()],
+            }]
+        );
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
     #[test]
     fn search_shortcuts_support_exact_and_mode_override() {
         let path = temp_db_path();
@@ -718,4 +1222,168 @@ mod tests {
         drop(conn);
         let _ = std::fs::remove_file(path);
     }
+
+    #[test]
+    fn get_package_returns_flags_for_exact_attr() {
+        let path = temp_db_path();
+        let mut conn = init_db(&path).expect("db init failed");
+
+        let packages = vec![
+            NixPackage {
+                broken: Some(true),
+                ..pkg("alpha", "alpha", "alpha")
+            },
+            pkg("beta", "beta", "beta"),
+        ];
+        ingest_packages(&mut conn, &packages).expect("ingest failed");
+
+        let alpha = get_package(&conn, "alpha")
+            .expect("lookup failed")
+            .expect("alpha missing");
+        assert!(alpha.broken);
+        assert!(!alpha.insecure);
+        assert!(get_package(&conn, "alph").expect("lookup failed").is_none());
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn maintainer_shortcut_filters_by_handle() {
+        let path = temp_db_path();
+        let mut conn = init_db(&path).expect("db init failed");
+
+        let packages = vec![
+            NixPackage {
+                maintainers: Some("alice, bobby".to_string()),
+                ..pkg("alpha", "alpha", "alpha")
+            },
+            NixPackage {
+                maintainers: Some("bob".to_string()),
+                ..pkg("beta", "beta", "beta")
+            },
+            pkg("gamma", "gamma", "gamma"),
+        ];
+        ingest_packages(&mut conn, &packages).expect("ingest failed");
+
+        let fuzzy = search_packages_with_mode(&conn, "maint:bob", 10, SearchMode::Name)
+            .expect("maintainer search failed");
+        let attrs: Vec<&str> = fuzzy.iter().map(|pkg| pkg.attr_path.as_str()).collect();
+        assert_eq!(attrs, vec!["alpha", "beta"]);
+
+        let exact = search_packages_with_mode(&conn, "'maintainer:bob", 10, SearchMode::All)
+            .expect("exact maintainer search failed");
+        assert_eq!(exact.len(), 1);
+        assert_eq!(exact[0].attr_path, "beta");
+        assert_eq!(exact[0].maintainers.as_deref(), Some("bob"));
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn pin_shortcut_scopes_results_to_a_namespace() {
+        let path = temp_db_path();
+        let mut conn = init_db(&path).expect("db init failed");
+
+        let packages = vec![
+            pkg("jq", "jq-1.7.1", "jq"),
+            pkg("internal.jq", "jq-1.6", "jq"),
+            pkg("internal.ripgrep", "ripgrep-13.0.0", "rg"),
+            pkg("internal_old.jq", "jq-1.5", "jq"),
+        ];
+        ingest_packages(&mut conn, &packages).expect("ingest failed");
+
+        let attrs = |query: &str, mode: SearchMode| -> Vec<String> {
+            search_packages_with_mode(&conn, query, 10, mode)
+                .expect("search failed")
+                .into_iter()
+                .map(|pkg| pkg.attr_path)
+                .collect()
+        };
+        assert_eq!(
+            attrs("pin:internal jq", SearchMode::All),
+            vec!["internal.jq"]
+        );
+        assert_eq!(
+            attrs("pin:internal 'jq", SearchMode::Name),
+            vec!["internal.jq"]
+        );
+        assert_eq!(
+            attrs("PIN:internal bin:rg", SearchMode::All),
+            vec!["internal.ripgrep"]
+        );
+        assert_eq!(
+            attrs("pin:internal", SearchMode::All),
+            vec!["internal.jq", "internal.ripgrep"]
+        );
+        assert!(attrs("pin:missing jq", SearchMode::All).is_empty());
+        assert_eq!(attrs("jq", SearchMode::Name).len(), 3);
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn aliases_are_replaced_on_ingest() {
+        let path = temp_db_path();
+        let mut conn = init_db(&path).expect("db init failed");
+
+        let exa = PackageAlias {
+            alias: "exa".to_string(),
+            target: Some("eza".to_string()),
+            message: Some("'exa' has been removed, use 'eza' instead".to_string()),
+        };
+        let nodejs = PackageAlias {
+            alias: "nodejs-16_x".to_string(),
+            target: Some("nodejs_16".to_string()),
+            message: None,
+        };
+        ingest_aliases(&mut conn, &[exa.clone(), nodejs]).expect("ingest failed");
+        ingest_aliases(&mut conn, std::slice::from_ref(&exa)).expect("reingest failed");
+
+        let attrs = vec![
+            "ripgrep".to_string(),
+            "exa".to_string(),
+            "nodejs-16_x".to_string(),
+        ];
+        let found = find_aliases(&conn, &attrs).expect("lookup failed");
+        assert_eq!(found, vec![exa]);
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn attrs_are_found_by_derivation_pname() {
+        let path = temp_db_path();
+        let mut conn = init_db(&path).expect("db init failed");
+
+        let packages = vec![
+            pkg("python312Packages.hello", "hello-2.12.1", "hello"),
+            pkg("hello", "hello-2.12.1", "hello"),
+            pkg(
+                "hello-wayland",
+                "hello-wayland-0-unstable-2024-03-04",
+                "hello-wayland",
+            ),
+            pkg("ripgrep", "ripgrep-14.1.1", "rg"),
+        ];
+        ingest_packages(&mut conn, &packages).expect("ingest failed");
+
+        assert_eq!(
+            find_attrs_by_pname(&conn, "hello").expect("lookup failed"),
+            vec!["hello", "python312Packages.hello"]
+        );
+        assert_eq!(
+            find_attrs_by_pname(&conn, "hello-wayland").expect("lookup failed"),
+            vec!["hello-wayland"]
+        );
+        assert!(find_attrs_by_pname(&conn, "missing")
+            .expect("lookup failed")
+            .is_empty());
+
+        drop(conn);
+        let _ = std::fs::remove_file(path);
+    }
 }
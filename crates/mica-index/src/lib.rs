@@ -1,5 +1,7 @@
 //! Index generation tooling for Mica.
 
+#[cfg(feature = "bench")]
+pub mod fixtures;
 pub mod generate;
 pub mod schema;
 pub mod versions;
@@ -1,3 +1,5 @@
+pub const INDEX_FORMAT: u32 = 1;
+
 pub const SCHEMA: &str = r#"
 CREATE TABLE IF NOT EXISTS packages (
     id INTEGER PRIMARY KEY,
@@ -11,7 +13,9 @@ CREATE TABLE IF NOT EXISTS packages (
     main_program TEXT,
     position TEXT,
     broken INTEGER DEFAULT 0,
-    insecure INTEGER DEFAULT 0
+    insecure INTEGER DEFAULT 0,
+    maintainers TEXT,
+    last_updated TEXT
 );
 
 CREATE VIRTUAL TABLE IF NOT EXISTS packages_fts USING fts5(
@@ -35,6 +39,12 @@ CREATE TABLE IF NOT EXISTS package_binaries (
 
 CREATE INDEX IF NOT EXISTS idx_binaries_name ON package_binaries(binary_name);
 
+CREATE TABLE IF NOT EXISTS package_aliases (
+    alias TEXT PRIMARY KEY,
+    target TEXT,
+    message TEXT
+);
+
 CREATE TABLE IF NOT EXISTS meta (
     key TEXT PRIMARY KEY,
     value TEXT NOT NULL
@@ -1,5 +1,7 @@
 use crate::generate::{IndexError, NixPackage};
 use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
 use std::path::Path;
 
 pub const VERSIONS_SCHEMA: &str = r#"
@@ -24,6 +26,13 @@ CREATE TABLE IF NOT EXISTS indexed_commits (
     url TEXT NOT NULL,
     PRIMARY KEY (source, commit_rev)
 );
+
+CREATE TABLE IF NOT EXISTS commit_dates (
+    url TEXT NOT NULL,
+    commit_rev TEXT NOT NULL,
+    commit_date TEXT NOT NULL,
+    PRIMARY KEY (url, commit_rev)
+);
 "#;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -46,6 +55,41 @@ pub struct VersionSource {
     pub indexed_at: String,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum VersionRecord {
+    Commit {
+        source: String,
+        commit: String,
+        branch: String,
+        commit_date: String,
+        indexed_at: String,
+        package_count: Option<i64>,
+        url: String,
+    },
+    Version {
+        attr: String,
+        version: String,
+        source: String,
+        commit: String,
+        commit_date: String,
+        branch: String,
+    },
+    CommitDate {
+        url: String,
+        commit: String,
+        commit_date: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VersionImport {
+    pub commits: usize,
+    pub versions: usize,
+    pub commit_dates: usize,
+    pub skipped: usize,
+}
+
 pub fn init_versions_db(path: &Path) -> Result<Connection, IndexError> {
     let conn = Connection::open(path)?;
     conn.execute_batch(VERSIONS_SCHEMA)?;
@@ -100,6 +144,46 @@ pub fn record_versions(
     Ok(())
 }
 
+pub fn set_commit_package_count(
+    conn: &Connection,
+    source: &str,
+    commit: &str,
+    package_count: usize,
+) -> Result<(), IndexError> {
+    conn.execute(
+        "UPDATE indexed_commits SET package_count = ?1 WHERE source = ?2 AND commit_rev = ?3",
+        params![package_count as i64, source, commit],
+    )?;
+    Ok(())
+}
+
+pub fn cached_commit_date(
+    conn: &Connection,
+    url: &str,
+    commit: &str,
+) -> Result<Option<String>, IndexError> {
+    let mut stmt =
+        conn.prepare("SELECT commit_date FROM commit_dates WHERE url = ?1 AND commit_rev = ?2")?;
+    let mut rows = stmt.query(params![url, commit])?;
+    match rows.next()? {
+        Some(row) => Ok(Some(row.get(0)?)),
+        None => Ok(None),
+    }
+}
+
+pub fn cache_commit_date(
+    conn: &Connection,
+    url: &str,
+    commit: &str,
+    commit_date: &str,
+) -> Result<(), IndexError> {
+    conn.execute(
+        "INSERT OR REPLACE INTO commit_dates (url, commit_rev, commit_date) VALUES (?1, ?2, ?3)",
+        params![url, commit, commit_date],
+    )?;
+    Ok(())
+}
+
 pub fn list_versions(
     conn: &Connection,
     attr_path: &str,
@@ -186,3 +270,255 @@ pub fn latest_version_for_source(
         Ok(None)
     }
 }
+
+pub fn export_versions(conn: &Connection, writer: &mut dyn Write) -> Result<usize, IndexError> {
+    let mut written = 0;
+    let mut write_record = |record: VersionRecord| -> Result<(), IndexError> {
+        let line = serde_json::to_string(&record).map_err(IndexError::Json)?;
+        writeln!(writer, "{}", line).map_err(IndexError::Write)?;
+        written += 1;
+        Ok(())
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT source, commit_rev, branch, commit_date, indexed_at, package_count, url \
+         FROM indexed_commits ORDER BY source, commit_date, commit_rev",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        write_record(VersionRecord::Commit {
+            source: row.get(0)?,
+            commit: row.get(1)?,
+            branch: row.get(2)?,
+            commit_date: row.get(3)?,
+            indexed_at: row.get(4)?,
+            package_count: row.get(5)?,
+            url: row.get(6)?,
+        })?;
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT attr_path, version, source, commit_rev, commit_date, branch \
+         FROM package_versions ORDER BY source, commit_date, commit_rev, attr_path, version",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        write_record(VersionRecord::Version {
+            attr: row.get(0)?,
+            version: row.get(1)?,
+            source: row.get(2)?,
+            commit: row.get(3)?,
+            commit_date: row.get(4)?,
+            branch: row.get(5)?,
+        })?;
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT url, commit_rev, commit_date FROM commit_dates ORDER BY url, commit_rev",
+    )?;
+    let mut rows = stmt.query([])?;
+    while let Some(row) = rows.next()? {
+        write_record(VersionRecord::CommitDate {
+            url: row.get(0)?,
+            commit: row.get(1)?,
+            commit_date: row.get(2)?,
+        })?;
+    }
+    Ok(written)
+}
+
+pub fn import_versions(
+    conn: &mut Connection,
+    reader: &mut dyn BufRead,
+    apply: bool,
+) -> Result<VersionImport, IndexError> {
+    let tx = conn.transaction()?;
+    let mut summary = VersionImport::default();
+    {
+        let mut commit_stmt = tx.prepare(
+            "INSERT OR IGNORE INTO indexed_commits (source, commit_rev, branch, commit_date, indexed_at, package_count, url) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+        )?;
+        let mut version_stmt = tx.prepare(
+            "INSERT OR IGNORE INTO package_versions (attr_path, version, source, commit_rev, commit_date, branch) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        )?;
+        let mut date_stmt = tx.prepare(
+            "INSERT OR IGNORE INTO commit_dates (url, commit_rev, commit_date) VALUES (?1, ?2, ?3)",
+        )?;
+        for (idx, line) in reader.lines().enumerate() {
+            let line = line.map_err(IndexError::Read)?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: VersionRecord = serde_json::from_str(&line)
+                .map_err(|err| IndexError::InvalidRecord(idx + 1, err))?;
+            let (inserted, counter) = match record {
+                VersionRecord::Commit {
+                    source,
+                    commit,
+                    branch,
+                    commit_date,
+                    indexed_at,
+                    package_count,
+                    url,
+                } => (
+                    commit_stmt.execute(params![
+                        source,
+                        commit,
+                        branch,
+                        commit_date,
+                        indexed_at,
+                        package_count,
+                        url
+                    ])?,
+                    &mut summary.commits,
+                ),
+                VersionRecord::Version {
+                    attr,
+                    version,
+                    source,
+                    commit,
+                    commit_date,
+                    branch,
+                } => (
+                    version_stmt.execute(params![
+                        attr,
+                        version,
+                        source,
+                        commit,
+                        commit_date,
+                        branch
+                    ])?,
+                    &mut summary.versions,
+                ),
+                VersionRecord::CommitDate {
+                    url,
+                    commit,
+                    commit_date,
+                } => (
+                    date_stmt.execute(params![url, commit, commit_date])?,
+                    &mut summary.commit_dates,
+                ),
+            };
+            if inserted > 0 {
+                *counter += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
+    }
+    if apply {
+        tx.commit()?;
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate::NixPackage;
+    use crate::versions::{
+        cache_commit_date, cached_commit_date, export_versions, import_versions, list_versions,
+        record_versions, VersionImport, VersionSource, VERSIONS_SCHEMA,
+    };
+    use rusqlite::Connection;
+
+    #[test]
+    fn commit_dates_are_cached_per_url_and_rev() {
+        let conn = Connection::open_in_memory().expect("open failed");
+        conn.execute_batch(VERSIONS_SCHEMA).expect("schema failed");
+        let url = "https://github.com/NixOS/nixpkgs";
+
+        assert_eq!(cached_commit_date(&conn, url, "abc").expect("query"), None);
+        cache_commit_date(&conn, url, "abc", "2024-05-01T00:00:00Z").expect("insert");
+        assert_eq!(
+            cached_commit_date(&conn, url, "abc").expect("query"),
+            Some("2024-05-01T00:00:00Z".to_string())
+        );
+        assert_eq!(
+            cached_commit_date(&conn, "https://github.com/other/nixpkgs", "abc").expect("query"),
+            None
+        );
+    }
+
+    fn versions_db() -> Connection {
+        let conn = Connection::open_in_memory().expect("open failed");
+        conn.execute_batch(VERSIONS_SCHEMA).expect("schema failed");
+        conn
+    }
+
+    #[test]
+    fn version_history_round_trips_through_jsonl() {
+        let mut source_db = versions_db();
+        let package = NixPackage {
+            attr_path: "ripgrep".to_string(),
+            name: "ripgrep-14.1.1".to_string(),
+            version: Some("14.1.1".to_string()),
+            description: None,
+            homepage: None,
+            license: None,
+            platforms: None,
+            main_program: None,
+            position: None,
+            broken: None,
+            insecure: None,
+            maintainers: None,
+            last_updated: None,
+        };
+        record_versions(
+            &mut source_db,
+            &VersionSource {
+                source: "nixpkgs".to_string(),
+                url: "https://github.com/NixOS/nixpkgs".to_string(),
+                branch: "nixpkgs-unstable".to_string(),
+                commit: "abc".to_string(),
+                commit_date: "2026-01-01T00:00:00Z".to_string(),
+                indexed_at: "2026-01-02T00:00:00Z".to_string(),
+            },
+            &[package],
+        )
+        .expect("record failed");
+        cache_commit_date(
+            &source_db,
+            "https://github.com/NixOS/nixpkgs",
+            "abc",
+            "2026-01-01T00:00:00Z",
+        )
+        .expect("cache failed");
+
+        let mut exported = Vec::new();
+        assert_eq!(
+            export_versions(&source_db, &mut exported).expect("export failed"),
+            3
+        );
+
+        let mut target = versions_db();
+        let dry_run = import_versions(&mut target, &mut exported.as_slice(), false)
+            .expect("dry-run import failed");
+        assert_eq!(dry_run.versions, 1);
+        assert!(list_versions(&target, "ripgrep", 10)
+            .expect("list failed")
+            .is_empty());
+
+        let imported =
+            import_versions(&mut target, &mut exported.as_slice(), true).expect("import failed");
+        assert_eq!(
+            imported,
+            VersionImport {
+                commits: 1,
+                versions: 1,
+                commit_dates: 1,
+                skipped: 0,
+            }
+        );
+        let versions = list_versions(&target, "ripgrep", 10).expect("list failed");
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].version, "14.1.1");
+
+        let again =
+            import_versions(&mut target, &mut exported.as_slice(), true).expect("reimport failed");
+        assert_eq!(again.skipped, 3);
+
+        let mut bad = "\n{\"type\": \"version\"}\n".as_bytes();
+        let err = import_versions(&mut target, &mut bad, true).expect_err("expected a bad record");
+        assert!(err.to_string().starts_with("invalid record on line 2"));
+    }
+}